@@ -11,13 +11,14 @@ use chrono::offset::Local;
 use chrono::DateTime;
 use chrono::format::{DelayedFormat, StrftimeItems};
 use polodb_core::Database;
-use polodb_core::dump::{FullDump, JournalDump, PageDump};
+use polodb_core::dump::{FullDump, HeaderDump, JournalDump, PageDump};
 
 struct FullDumpWrapper<'a> {
     dump: &'a FullDump,
     print_page_detail: bool,
 }
 struct PageDumpWrapper<'a>(&'a PageDump);
+struct HeaderDumpWrapper<'a>(&'a HeaderDump);
 
 pub(crate) fn dump(src_path: &str, page_detail: bool) {
     if !Path::exists(src_path.as_ref()) {
@@ -29,6 +30,20 @@ pub(crate) fn dump(src_path: &str, page_detail: bool) {
     println!("{}", FullDumpWrapper{ dump: &dump, print_page_detail: page_detail });
 }
 
+pub(crate) fn inspect(src_path: &str) {
+    if !Path::exists(src_path.as_ref()) {
+        println!("database not exist: {}", src_path);
+        process::exit(2);
+    }
+    match Database::inspect_header(src_path) {
+        Ok(header) => println!("{}", HeaderDumpWrapper(&header)),
+        Err(err) => {
+            eprintln!("inspect failed: {}", err);
+            process::exit(1);
+        }
+    }
+}
+
 fn format_datetime(datetime: &DateTime<Local>) -> DelayedFormat<StrftimeItems> {
     datetime.format("%Y/%m/%d %T")
 }
@@ -48,6 +63,14 @@ impl<'a> fmt::Display for FullDumpWrapper<'a> {
         write_kv!(f, "Meta Page Id", self.dump.meta_pid)?;
         write_kv!(f, "Free List Page Id" ,self.dump.free_list_pid)?;
         write_kv!(f, "Free List Size", self.dump.free_list_size)?;
+        write_kv!(f, "Compat Features", format!("0x{:016X}", self.dump.compat_features))?;
+        write_kv!(f, "Incompat Features", format!("0x{:016X}", self.dump.incompat_features))?;
+        if let Some(created_at) = &self.dump.created_at {
+            write_kv!(f, "Created At", created_at)?;
+        }
+        if let Some(checkpoint_lsn) = self.dump.checkpoint_lsn {
+            write_kv!(f, "Checkpoint LSN", checkpoint_lsn)?;
+        }
 
         if self.print_page_detail {
             for page_dump in &self.dump.pages {
@@ -63,6 +86,45 @@ impl<'a> fmt::Display for FullDumpWrapper<'a> {
 
 }
 
+impl<'a> fmt::Display for HeaderDumpWrapper<'a> {
+
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let header = self.0;
+        write_kv!(f, "Identifier", header.identifier)?;
+        write_kv!(f, "Version", header.version)?;
+        write_kv!(f, "Page Size", header.page_size)?;
+        write_kv!(f, "Page Count", header.page_count)?;
+        write_kv!(f, "Compat Features", format!("0x{:016X}", header.compat_features))?;
+        write_kv!(f, "Incompat Features", format!("0x{:016X}", header.incompat_features))?;
+        if let Some(created_at) = &header.created_at {
+            write_kv!(f, "Created At", created_at)?;
+        }
+        if let Some(checkpoint_lsn) = header.checkpoint_lsn {
+            write_kv!(f, "Checkpoint LSN", checkpoint_lsn)?;
+        }
+        if let Some(checkpoint_at) = &header.checkpoint_at {
+            write_kv!(f, "Last Checkpoint", checkpoint_at)?;
+        }
+        if let Some(journal_size) = header.journal_size {
+            write_kv!(f, "Journal Size", format!("{} (not checkpointed)", journal_size))?;
+        }
+
+        writeln!(f)?;
+        write_kv!(f, "Collections", header.collections.len())?;
+        for collection in &header.collections {
+            let count = match collection.count {
+                Some(count) => count.to_string(),
+                None => "-".to_string(),
+            };
+            writeln!(f, "  {:22}count: {:<12}indexes: {}",
+                     collection.name, count, collection.indexes.join(", "))?;
+        }
+
+        Ok(())
+    }
+
+}
+
 fn dump_journal(journal_dump: &JournalDump, f: &mut Formatter<'_>) -> fmt::Result {
     writeln!(f)?;
     write_kv!(f, "Journal Path", journal_dump.path.to_str().unwrap())?;
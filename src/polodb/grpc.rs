@@ -0,0 +1,357 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+// tonic::Status is large, but it's what the generated service returns
+#![allow(clippy::result_large_err)]
+
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::process::exit;
+use std::str::FromStr;
+use std::sync::Arc;
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{self, doc, Bson, Document};
+use polodb_core::bson::oid::ObjectId;
+use tonic::{Request, Response, Status};
+use tonic::transport::Server;
+
+mod proto {
+    tonic::include_proto!("polodb");
+}
+
+use proto::polo_db_server::{PoloDb, PoloDbServer};
+use proto::*;
+
+struct PoloDbService {
+    db: Arc<Database>,
+}
+
+fn status_of_db_err(err: DbErr) -> Status {
+    let msg = err.to_string();
+    match err {
+        DbErr::CollectionNotFound(_) | DbErr::CursorNotFound(_) => Status::not_found(msg),
+        DbErr::CollectionAlreadyExits(_) | DbErr::DataExist(_) => Status::already_exists(msg),
+        DbErr::PermissionDenied(_) => Status::permission_denied(msg),
+        DbErr::Busy | DbErr::DatabaseOccupied => Status::unavailable(msg),
+        DbErr::TooManyOpenCursors(_) | DbErr::TooManySessions(_) | DbErr::TooManyCollections(_) => {
+            Status::resource_exhausted(msg)
+        }
+        DbErr::InvalidSession(_) | DbErr::SessionOutdated | DbErr::NoTransactionStarted |
+        DbErr::StartTransactionInAnotherTransaction | DbErr::RollbackNotInTransaction => {
+            Status::failed_precondition(msg)
+        }
+        DbErr::IOErr(_) | DbErr::LockError => Status::internal(msg),
+        _ => Status::invalid_argument(msg),
+    }
+}
+
+fn decode_document(bytes: &[u8]) -> Result<Document, Status> {
+    if bytes.is_empty() {
+        return Ok(Document::new());
+    }
+    bson::from_slice(bytes).map_err(|err| Status::invalid_argument(format!("invalid bson: {}", err)))
+}
+
+fn encode_document(doc: &Document) -> Result<Vec<u8>, Status> {
+    bson::to_vec(doc).map_err(|err| Status::internal(err.to_string()))
+}
+
+fn parse_session_id(session_id: &str) -> Result<Option<ObjectId>, Status> {
+    if session_id.is_empty() {
+        return Ok(None);
+    }
+    ObjectId::from_str(session_id)
+        .map(Some)
+        .map_err(|err| Status::invalid_argument(format!("invalid session id: {}", err)))
+}
+
+fn options_of_session(session_id: &str) -> Result<Bson, Status> {
+    let result = match parse_session_id(session_id)? {
+        Some(sid) => Bson::Document(doc! { "sessionId": sid }),
+        None => Bson::Null,
+    };
+    Ok(result)
+}
+
+fn require_session_id(session_id: &str) -> Result<ObjectId, Status> {
+    parse_session_id(session_id)?
+        .ok_or_else(|| Status::invalid_argument("session id is required"))
+}
+
+fn as_u64(value: &Bson, key: &str) -> u64 {
+    value.as_document()
+        .and_then(|doc| doc.get(key))
+        .and_then(|count| match count {
+            Bson::Int32(v) => Some(*v as u64),
+            Bson::Int64(v) => Some(*v as u64),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
+impl PoloDbService {
+
+    /// Execute the command on the blocking thread pool,
+    /// the command goes through the middlewares of the database.
+    async fn execute(&self, command: Document) -> Result<Bson, Status> {
+        let db = self.db.clone();
+        let result = tokio::task::spawn_blocking(move || db.handle_request_doc(Bson::Document(command)))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        match result {
+            Ok(result) => Ok(result.value),
+            Err(err) => Err(status_of_db_err(err)),
+        }
+    }
+
+}
+
+#[tonic::async_trait]
+impl PoloDb for PoloDbService {
+
+    async fn find(&self, request: Request<FindRequest>) -> Result<Response<DocumentsReply>, Status> {
+        let req = request.into_inner();
+        let filter = decode_document(&req.filter)?;
+        let value = self.execute(doc! {
+            "command": "Find",
+            "ns": req.ns,
+            "multi": req.multi,
+            "filter": filter,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        let mut documents = vec![];
+        if let Bson::Array(arr) = value {
+            for item in arr {
+                if let Bson::Document(doc) = item {
+                    documents.push(encode_document(&doc)?);
+                }
+            }
+        }
+
+        Ok(Response::new(DocumentsReply { documents }))
+    }
+
+    async fn insert(&self, request: Request<InsertRequest>) -> Result<Response<InsertReply>, Status> {
+        let req = request.into_inner();
+        let mut documents = bson::Array::new();
+        for bytes in &req.documents {
+            documents.push(Bson::Document(decode_document(bytes)?));
+        }
+        let value = self.execute(doc! {
+            "command": "Insert",
+            "ns": req.ns,
+            "documents": documents,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        let inserted_ids = value.as_document()
+            .and_then(|doc| doc.get_document("insertedIds").ok())
+            .cloned()
+            .unwrap_or_default();
+
+        Ok(Response::new(InsertReply {
+            inserted_ids: encode_document(&inserted_ids)?,
+        }))
+    }
+
+    async fn update(&self, request: Request<UpdateRequest>) -> Result<Response<UpdateReply>, Status> {
+        let req = request.into_inner();
+        let value = self.execute(doc! {
+            "command": "Update",
+            "ns": req.ns,
+            "filter": decode_document(&req.filter)?,
+            "update": decode_document(&req.update)?,
+            "multi": req.multi,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(UpdateReply {
+            modified_count: as_u64(&value, "modifiedCount"),
+        }))
+    }
+
+    async fn delete(&self, request: Request<DeleteRequest>) -> Result<Response<DeleteReply>, Status> {
+        let req = request.into_inner();
+        let value = self.execute(doc! {
+            "command": "Delete",
+            "ns": req.ns,
+            "filter": decode_document(&req.filter)?,
+            "multi": req.multi,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(DeleteReply {
+            deleted_count: as_u64(&value, "deletedCount"),
+        }))
+    }
+
+    async fn aggregate(&self, request: Request<AggregateRequest>) -> Result<Response<DocumentsReply>, Status> {
+        let req = request.into_inner();
+        // the sessions of the commands can't be joined by the collections
+        if parse_session_id(&req.session_id)?.is_some() {
+            return Err(Status::unimplemented("aggregate in a session is not supported"));
+        }
+        let mut pipeline = vec![];
+        for bytes in &req.pipeline {
+            pipeline.push(decode_document(bytes)?);
+        }
+
+        let db = self.db.clone();
+        let result = tokio::task::spawn_blocking(move || db.collection::<Document>(&req.ns).aggregate(pipeline))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?;
+        let mut documents = vec![];
+        for doc in result.map_err(status_of_db_err)? {
+            documents.push(encode_document(&doc)?);
+        }
+
+        Ok(Response::new(DocumentsReply { documents }))
+    }
+
+    async fn count_documents(&self, request: Request<CountDocumentsRequest>) -> Result<Response<CountDocumentsReply>, Status> {
+        let req = request.into_inner();
+        let value = self.execute(doc! {
+            "command": "CountDocuments",
+            "ns": req.ns,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        let count = match value {
+            Bson::Int64(v) => v as u64,
+            Bson::Int32(v) => v as u64,
+            _ => 0,
+        };
+
+        Ok(Response::new(CountDocumentsReply { count }))
+    }
+
+    async fn create_collection(&self, request: Request<CollectionRequest>) -> Result<Response<CreateCollectionReply>, Status> {
+        let req = request.into_inner();
+        let value = self.execute(doc! {
+            "command": "CreateCollection",
+            "ns": req.ns,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(CreateCollectionReply {
+            created: value.as_bool().unwrap_or(false),
+        }))
+    }
+
+    async fn drop_collection(&self, request: Request<CollectionRequest>) -> Result<Response<Empty>, Status> {
+        let req = request.into_inner();
+        self.execute(doc! {
+            "command": "DropCollection",
+            "ns": req.ns,
+            "options": options_of_session(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn start_session(&self, _request: Request<Empty>) -> Result<Response<SessionReply>, Status> {
+        let value = self.execute(doc! {
+            "command": "StartSession",
+        }).await?;
+
+        let session_id = match value {
+            Bson::ObjectId(oid) => oid.to_hex(),
+            _ => return Err(Status::internal("unexpected session id")),
+        };
+
+        Ok(Response::new(SessionReply { session_id }))
+    }
+
+    async fn drop_session(&self, request: Request<SessionRequest>) -> Result<Response<Empty>, Status> {
+        let req = request.into_inner();
+        self.execute(doc! {
+            "command": "DropSession",
+            "sessionId": require_session_id(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn start_transaction(&self, request: Request<StartTransactionRequest>) -> Result<Response<Empty>, Status> {
+        let req = request.into_inner();
+        let ty = match TransactionType::try_from(req.ty) {
+            Ok(TransactionType::Read) => Bson::String("Read".into()),
+            Ok(TransactionType::Write) => Bson::String("Write".into()),
+            _ => Bson::Null,
+        };
+        self.execute(doc! {
+            "command": "StartTransaction",
+            "sessionId": require_session_id(&req.session_id)?,
+            "ty": ty,
+        }).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn commit(&self, request: Request<SessionRequest>) -> Result<Response<Empty>, Status> {
+        let req = request.into_inner();
+        self.execute(doc! {
+            "command": "Commit",
+            "sessionId": require_session_id(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+    async fn rollback(&self, request: Request<SessionRequest>) -> Result<Response<Empty>, Status> {
+        let req = request.into_inner();
+        self.execute(doc! {
+            "command": "Rollback",
+            "sessionId": require_session_id(&req.session_id)?,
+        }).await?;
+
+        Ok(Response::new(Empty {}))
+    }
+
+}
+
+pub fn start_grpc_server(path: Option<&str>, addr: &str) {
+    let db = match path {
+        Some(path) => Database::open_file(path),
+        None => Database::open_memory(),
+    };
+    let db = match db {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("open db failed: {}", err);
+            exit(6);
+        }
+    };
+
+    let addr: SocketAddr = match addr.parse() {
+        Ok(addr) => addr,
+        Err(err) => {
+            eprintln!("invalid address {}: {}", addr, err);
+            exit(7);
+        }
+    };
+
+    let service = PoloDbService {
+        db: Arc::new(db),
+    };
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let result = runtime.block_on(async move {
+        eprintln!("grpc server listening on {}", addr);
+        Server::builder()
+            .add_service(PoloDbServer::new(service))
+            .serve_with_shutdown(addr, async {
+                let _ = tokio::signal::ctrl_c().await;
+                eprintln!("Received quit signal, prepare to exit");
+            })
+            .await
+    });
+
+    if let Err(err) = result {
+        eprintln!("grpc server error: {}", err);
+        exit(8);
+    }
+}
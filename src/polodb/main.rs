@@ -6,8 +6,10 @@
 mod dumper;
 mod ipc;
 mod server;
+#[cfg(feature = "grpc")]
+mod grpc;
 
-use crate::dumper::dump;
+use crate::dumper::{dump, inspect};
 use polodb_core::Database;
 use clap::{App, Arg};
 use error_chain::error_chain;
@@ -43,6 +45,13 @@ fn main() {
                     .required(true)
             )
             .arg(Arg::with_name("detail").required(false)))
+        .subcommand(App::new("inspect")
+            .about("print the header and the collections of the database without opening it")
+            .arg(
+                Arg::with_name("path")
+                    .index(1)
+                    .required(true)
+            ))
         .subcommand(App::new("serve")
             .about("attach the database, start the tcp server")
             .arg(
@@ -67,6 +76,30 @@ fn main() {
                     .short("l")
             )
         )
+        .subcommand(App::new("grpc")
+            .about("attach the database, start the gRPC server")
+            .arg(
+                Arg::with_name("listen")
+                    .long("listen")
+                    .value_name("ADDR")
+                    .help("the address to listen on")
+                    .default_value("127.0.0.1:50051")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .value_name("PATH")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("log")
+                    .help("print log")
+                    .long("log")
+                    .short("l")
+            )
+        )
         .subcommand(App::new("migrate")
             .about("migrate the older database to the newer format")
             .arg(
@@ -107,6 +140,17 @@ fn main() {
         return;
     }
 
+    if let Some(sub) = matches.subcommand_matches("grpc") {
+        Database::set_log(sub.is_present("log"));
+
+        #[cfg(feature = "grpc")]
+        grpc::start_grpc_server(sub.value_of("path"), sub.value_of("listen").unwrap());
+
+        #[cfg(not(feature = "grpc"))]
+        eprintln!("gRPC is not enabled, rebuild with `--features grpc`");
+        return;
+    }
+
     if let Some(sub) = matches.subcommand_matches("dump") {
         let path = sub.value_of("path").expect("no input path");
         let detail = sub.is_present("detail");
@@ -114,5 +158,11 @@ fn main() {
         return;
     }
 
+    if let Some(sub) = matches.subcommand_matches("inspect") {
+        let path = sub.value_of("path").expect("no input path");
+        inspect(path);
+        return;
+    }
+
     println!("{}", matches.usage());
 }
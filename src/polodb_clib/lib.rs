@@ -255,5 +255,36 @@ fn error_code_of_db_err(err: &DbErr) -> i32 {
         DbErr::NoTransactionStarted => 54,
         DbErr::InvalidSession(_) => 55,
         DbErr::SessionOutdated => 56,
+        DbErr::PermissionDenied(_) => 57,
+        DbErr::IllegalAttachAlias(_) => 58,
+        DbErr::AttachAliasAlreadyExists(_) => 59,
+        DbErr::AttachAliasNotFound(_) => 60,
+        DbErr::CompressionError(_) => 61,
+        DbErr::UnknownAggregationOperation(_) => 62,
+        DbErr::ConversionFailure(_) => 63,
+        DbErr::CollectionArchived(_) => 64,
+        DbErr::UnknownQueryOperator(_) => 65,
+        DbErr::UnsupportedFeatures(_) => 66,
+        DbErr::HeaderMetadataFull => 67,
+        DbErr::NotSupportedByBackend(_) => 69,
+        DbErr::TooManyOpenCursors(_) => 70,
+        DbErr::TooManySessions(_) => 71,
+        DbErr::TooManyCollections(_) => 72,
+        DbErr::CursorNotFound(_) => 73,
+        DbErr::TaskPanicked => 74,
+        DbErr::IndexNotFound(_) => 75,
+        DbErr::DuplicateKey(_) => 76,
+        DbErr::IndexedDbError(_) => 77,
+        DbErr::EncryptionError(_) => 78,
+        DbErr::PinnedPagesExceeded(_, _) => 79,
+        DbErr::DeserializeDocument(_) => 80,
+        DbErr::PageIdOutOfRange(_, _) => 81,
+        DbErr::DatabaseSizeLimitExceeded(_) => 82,
+        DbErr::AppendOnlyCollection(_) => 83,
+        DbErr::AppendOutOfOrder(_) => 84,
+        DbErr::MigrationOutOfOrder(_, _) => 85,
+        DbErr::ForeignKeyViolation(_) => 86,
+        DbErr::OpfsError(_) => 87,
+        DbErr::PageSizeMismatch(_, _) => 88,
     }
 }
@@ -0,0 +1,164 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use bson::Bson;
+use super::expression::compare_values;
+
+/// The accumulators shared by `$group` and `$setWindowFields`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Accumulator {
+    Sum,
+    Avg,
+    Min,
+    Max,
+    Count,
+    First,
+    Last,
+    Push,
+}
+
+impl Accumulator {
+
+    pub(crate) fn from_name(name: &str) -> Option<Accumulator> {
+        let result = match name {
+            "$sum" => Accumulator::Sum,
+            "$avg" => Accumulator::Avg,
+            "$min" => Accumulator::Min,
+            "$max" => Accumulator::Max,
+            "$count" => Accumulator::Count,
+            "$first" => Accumulator::First,
+            "$last" => Accumulator::Last,
+            "$push" => Accumulator::Push,
+            _ => return None,
+        };
+        Some(result)
+    }
+
+    /// Accumulate the evaluated values of the expression.
+    pub(crate) fn accumulate<'a, I>(&self, values: I) -> Bson
+    where
+        I: IntoIterator<Item = &'a Bson>,
+    {
+        let mut values = values.into_iter();
+        match self {
+            Accumulator::Sum => sum(values),
+            Accumulator::Avg => {
+                let mut total = 0.0;
+                let mut count = 0;
+                for value in values {
+                    if let Some(f) = as_f64(value) {
+                        total += f;
+                        count += 1;
+                    }
+                }
+                if count == 0 {
+                    Bson::Null
+                } else {
+                    Bson::Double(total / count as f64)
+                }
+            }
+            Accumulator::Min => extreme(values, Ordering::Less),
+            Accumulator::Max => extreme(values, Ordering::Greater),
+            Accumulator::Count => Bson::Int64(values.count() as i64),
+            Accumulator::First => values.next().cloned().unwrap_or(Bson::Null),
+            Accumulator::Last => values.last().cloned().unwrap_or(Bson::Null),
+            Accumulator::Push => Bson::Array(values.cloned().collect()),
+        }
+    }
+
+}
+
+pub(crate) fn as_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Int32(i) => Some(*i as f64),
+        Bson::Int64(i) => Some(*i as f64),
+        Bson::Double(f) => Some(*f),
+        _ => None,
+    }
+}
+
+// The sum of integers keeps the type if it doesn't overflow,
+// the values are not numbers are ignored.
+fn sum<'a, I>(values: I) -> Bson
+where
+    I: Iterator<Item = &'a Bson>,
+{
+    let mut int_sum: i64 = 0;
+    let mut float_sum = 0.0;
+    let mut all_int32 = true;
+    let mut is_float = false;
+    let mut overflowed = false;
+
+    for value in values {
+        match value {
+            Bson::Int32(i) => {
+                match int_sum.checked_add(*i as i64) {
+                    Some(s) => int_sum = s,
+                    None => overflowed = true,
+                }
+                float_sum += *i as f64;
+            }
+            Bson::Int64(i) => {
+                all_int32 = false;
+                match int_sum.checked_add(*i) {
+                    Some(s) => int_sum = s,
+                    None => overflowed = true,
+                }
+                float_sum += *i as f64;
+            }
+            Bson::Double(f) => {
+                is_float = true;
+                float_sum += *f;
+            }
+            _ => (),
+        }
+    }
+
+    if is_float || overflowed {
+        Bson::Double(float_sum)
+    } else if all_int32 && int_sum >= i32::MIN as i64 && int_sum <= i32::MAX as i64 {
+        Bson::Int32(int_sum as i32)
+    } else {
+        Bson::Int64(int_sum)
+    }
+}
+
+// Null and missing values are ignored.
+fn extreme<'a, I>(values: I, expected: Ordering) -> Bson
+where
+    I: Iterator<Item = &'a Bson>,
+{
+    let mut result: Option<&Bson> = None;
+    for value in values {
+        if matches!(value, Bson::Null | Bson::Undefined) {
+            continue;
+        }
+        result = match result {
+            Some(current) if compare_values(value, current) != Some(expected) => Some(current),
+            _ => Some(value),
+        };
+    }
+    result.cloned().unwrap_or(Bson::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+    use super::Accumulator;
+
+    #[test]
+    fn test_accumulate() {
+        let values = vec![Bson::Int32(3), Bson::Int32(5), Bson::Null, Bson::Int32(1)];
+        assert_eq!(Accumulator::Sum.accumulate(&values), Bson::Int32(9));
+        assert_eq!(Accumulator::Avg.accumulate(&values), Bson::Double(3.0));
+        assert_eq!(Accumulator::Min.accumulate(&values), Bson::Int32(1));
+        assert_eq!(Accumulator::Max.accumulate(&values), Bson::Int32(5));
+
+        let values = vec![Bson::Int32(i32::MAX), Bson::Int32(1)];
+        assert_eq!(Accumulator::Sum.accumulate(&values), Bson::Int64(i32::MAX as i64 + 1));
+    }
+
+}
@@ -0,0 +1,252 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! The type conversion operators of the expressions.
+use bson::{Bson, Document, DateTime};
+use bson::oid::ObjectId;
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::expression::{evaluate, Variables};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TargetType {
+    Double,
+    String,
+    ObjectId,
+    Bool,
+    Date,
+    Int,
+    Long,
+}
+
+impl TargetType {
+
+    fn parse(value: &Bson) -> DbResult<TargetType> {
+        let result = match value {
+            Bson::String(name) => match name.as_str() {
+                "double" => TargetType::Double,
+                "string" => TargetType::String,
+                "objectId" => TargetType::ObjectId,
+                "bool" => TargetType::Bool,
+                "date" => TargetType::Date,
+                "int" => TargetType::Int,
+                "long" => TargetType::Long,
+                _ => return Err(DbErr::ValidationError(format!("$convert: unsupported type '{}'", name))),
+            },
+            Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => {
+                let code = match value {
+                    Bson::Int32(i) => *i as i64,
+                    Bson::Int64(i) => *i,
+                    Bson::Double(f) => *f as i64,
+                    _ => unreachable!(),
+                };
+                match code {
+                    1 => TargetType::Double,
+                    2 => TargetType::String,
+                    7 => TargetType::ObjectId,
+                    8 => TargetType::Bool,
+                    9 => TargetType::Date,
+                    16 => TargetType::Int,
+                    18 => TargetType::Long,
+                    _ => return Err(DbErr::ValidationError(format!("$convert: unsupported type code {}", code))),
+                }
+            }
+            t => return Err(mk_field_name_type_unexpected("to".into(), "String".into(), format!("{}", t))),
+        };
+        Ok(result)
+    }
+
+    fn of_operator(op: &str) -> Option<TargetType> {
+        let result = match op {
+            "$toDouble" => TargetType::Double,
+            "$toString" => TargetType::String,
+            "$toObjectId" => TargetType::ObjectId,
+            "$toBool" => TargetType::Bool,
+            "$toDate" => TargetType::Date,
+            "$toInt" => TargetType::Int,
+            "$toLong" => TargetType::Long,
+            _ => return None,
+        };
+        Some(result)
+    }
+
+}
+
+fn conversion_failure(value: &Bson, target: TargetType) -> DbErr {
+    DbErr::ConversionFailure(format!("can't convert {} to {:?}", value, target))
+}
+
+fn float_to_int(f: f64, min: f64, max: f64) -> Option<i64> {
+    if f.is_finite() && f >= min && f <= max {
+        Some(f.trunc() as i64)
+    } else {
+        None
+    }
+}
+
+fn convert_value(value: &Bson, target: TargetType) -> DbResult<Bson> {
+    let failure = || conversion_failure(value, target);
+    let result = match target {
+        TargetType::Double => match value {
+            Bson::Double(_) => value.clone(),
+            Bson::Int32(i) => Bson::Double(*i as f64),
+            Bson::Int64(i) => Bson::Double(*i as f64),
+            Bson::Boolean(b) => Bson::Double(if *b { 1.0 } else { 0.0 }),
+            Bson::DateTime(dt) => Bson::Double(dt.timestamp_millis() as f64),
+            Bson::String(s) => Bson::Double(s.parse::<f64>().map_err(|_| failure())?),
+            _ => return Err(failure()),
+        },
+
+        TargetType::Int => {
+            let i = match value {
+                Bson::Int32(i) => *i as i64,
+                Bson::Int64(i) => *i,
+                Bson::Double(f) => float_to_int(*f, i32::MIN as f64, i32::MAX as f64).ok_or_else(failure)?,
+                Bson::Boolean(b) => *b as i64,
+                Bson::String(s) => s.parse::<i32>().map_err(|_| failure())? as i64,
+                _ => return Err(failure()),
+            };
+            if i < i32::MIN as i64 || i > i32::MAX as i64 {
+                return Err(failure());
+            }
+            Bson::Int32(i as i32)
+        }
+
+        TargetType::Long => match value {
+            Bson::Int32(i) => Bson::Int64(*i as i64),
+            Bson::Int64(_) => value.clone(),
+            Bson::Double(f) => Bson::Int64(float_to_int(*f, i64::MIN as f64, i64::MAX as f64).ok_or_else(failure)?),
+            Bson::Boolean(b) => Bson::Int64(*b as i64),
+            Bson::DateTime(dt) => Bson::Int64(dt.timestamp_millis()),
+            Bson::String(s) => Bson::Int64(s.parse::<i64>().map_err(|_| failure())?),
+            _ => return Err(failure()),
+        },
+
+        TargetType::String => match value {
+            Bson::String(_) => value.clone(),
+            Bson::Int32(i) => Bson::String(i.to_string()),
+            Bson::Int64(i) => Bson::String(i.to_string()),
+            Bson::Double(f) => Bson::String(f.to_string()),
+            Bson::Boolean(b) => Bson::String(b.to_string()),
+            Bson::ObjectId(oid) => Bson::String(oid.to_hex()),
+            Bson::DateTime(dt) => Bson::String(dt.try_to_rfc3339_string().map_err(|_| failure())?),
+            _ => return Err(failure()),
+        },
+
+        TargetType::Bool => match value {
+            Bson::Boolean(_) => value.clone(),
+            Bson::Int32(i) => Bson::Boolean(*i != 0),
+            Bson::Int64(i) => Bson::Boolean(*i != 0),
+            Bson::Double(f) => Bson::Boolean(*f != 0.0),
+            _ => Bson::Boolean(true),
+        },
+
+        TargetType::Date => match value {
+            Bson::DateTime(_) => value.clone(),
+            Bson::Int64(i) => Bson::DateTime(DateTime::from_millis(*i)),
+            Bson::Double(f) => {
+                let millis = float_to_int(*f, i64::MIN as f64, i64::MAX as f64).ok_or_else(failure)?;
+                Bson::DateTime(DateTime::from_millis(millis))
+            }
+            Bson::String(s) => Bson::DateTime(DateTime::parse_rfc3339_str(s).map_err(|_| failure())?),
+            Bson::ObjectId(oid) => Bson::DateTime(oid.timestamp()),
+            _ => return Err(failure()),
+        },
+
+        TargetType::ObjectId => match value {
+            Bson::ObjectId(_) => value.clone(),
+            Bson::String(s) => Bson::ObjectId(ObjectId::parse_str(s).map_err(|_| failure())?),
+            _ => return Err(failure()),
+        },
+    };
+    Ok(result)
+}
+
+/// `$convert` with the `onError` and `onNull` fallbacks.
+pub(super) fn convert(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = match value {
+        Bson::Document(spec) => spec,
+        t => return Err(mk_field_name_type_unexpected("$convert".into(), "Document".into(), format!("{}", t))),
+    };
+    let input = match spec.get("input") {
+        Some(input) => evaluate(input, doc, vars)?,
+        None => return Err(DbErr::ValidationError("$convert requires 'input'".into())),
+    };
+    let target = match spec.get("to") {
+        Some(to) => TargetType::parse(&evaluate(to, doc, vars)?)?,
+        None => return Err(DbErr::ValidationError("$convert requires 'to'".into())),
+    };
+
+    if matches!(input, Bson::Null | Bson::Undefined) {
+        return match spec.get("onNull") {
+            Some(on_null) => evaluate(on_null, doc, vars),
+            None => Ok(Bson::Null),
+        };
+    }
+
+    match convert_value(&input, target) {
+        Ok(result) => Ok(result),
+        Err(DbErr::ConversionFailure(_)) if spec.contains_key("onError") => {
+            evaluate(spec.get("onError").unwrap(), doc, vars)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// `$toInt`, `$toString` and the other shorthands of `$convert`.
+pub(super) fn convert_to(op: &str, value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let target = match TargetType::of_operator(op) {
+        Some(target) => target,
+        None => return Err(DbErr::UnknownAggregationOperation(op.into())),
+    };
+    let arg = match value {
+        Bson::Array(arr) if arr.len() == 1 => &arr[0],
+        _ => value,
+    };
+    let input = evaluate(arg, doc, vars)?;
+    if matches!(input, Bson::Null | Bson::Undefined) {
+        return Ok(Bson::Null);
+    }
+    convert_value(&input, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, DateTime, doc};
+    use crate::DbErr;
+    use crate::aggregation::expression::{evaluate, Variables};
+
+    fn eval(expr: Bson) -> Result<Bson, DbErr> {
+        let doc = doc! { "qty": "42", "price": 9.99, "ts": 1_000_000_i64, "bad": "n/a" };
+        evaluate(&expr, &doc, &Variables::new())
+    }
+
+    #[test]
+    fn test_convert() {
+        assert_eq!(eval(Bson::Document(doc! { "$toInt": "$qty" })).unwrap(), Bson::Int32(42));
+        assert_eq!(eval(Bson::Document(doc! { "$toInt": "$price" })).unwrap(), Bson::Int32(9));
+        assert_eq!(eval(Bson::Document(doc! { "$toString": 12 })).unwrap(), Bson::String("12".into()));
+        assert_eq!(
+            eval(Bson::Document(doc! { "$toDate": "$ts" })).unwrap(),
+            Bson::DateTime(DateTime::from_millis(1_000_000)),
+        );
+        assert_eq!(eval(Bson::Document(doc! { "$toInt": "$missing" })).unwrap(), Bson::Null);
+        assert!(matches!(
+            eval(Bson::Document(doc! { "$toInt": "$bad" })),
+            Err(DbErr::ConversionFailure(_)),
+        ));
+
+        let converted = eval(Bson::Document(doc! {
+            "$convert": { "input": "$bad", "to": "int", "onError": -1, "onNull": 0 },
+        })).unwrap();
+        assert_eq!(converted, Bson::Int32(-1));
+
+        let converted = eval(Bson::Document(doc! {
+            "$convert": { "input": "$missing", "to": "int", "onError": -1, "onNull": 0 },
+        })).unwrap();
+        assert_eq!(converted, Bson::Int32(0));
+    }
+
+}
@@ -0,0 +1,86 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::Bson;
+use crate::DbResult;
+
+/// An accumulator implemented in Rust, registered by
+/// [`Database::register_accumulator`] and used in `$group` as
+/// `{ "$accumulator": { "name": <name>, "input": <expression> } }`.
+///
+/// The state of a group is a [`Bson`] value, so the partial states
+/// accumulated from the separated batches of documents can be merged.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use polodb_core::{CustomAccumulator, Database, DbResult};
+/// use polodb_core::bson::{Bson, Document, doc};
+///
+/// // The count of the distinct values.
+/// struct Distinct;
+///
+/// impl CustomAccumulator for Distinct {
+///     fn init(&self) -> Bson {
+///         Bson::Array(vec![])
+///     }
+///
+///     fn accumulate(&self, state: Bson, value: &Bson) -> DbResult<Bson> {
+///         let mut values = state.as_array().unwrap().clone();
+///         if !values.contains(value) {
+///             values.push(value.clone());
+///         }
+///         Ok(Bson::Array(values))
+///     }
+///
+///     fn merge(&self, state: Bson, other: Bson) -> DbResult<Bson> {
+///         let mut result = state;
+///         for value in other.as_array().unwrap() {
+///             result = self.accumulate(result, value)?;
+///         }
+///         Ok(result)
+///     }
+///
+///     fn finalize(&self, state: Bson) -> DbResult<Bson> {
+///         Ok(Bson::Int64(state.as_array().unwrap().len() as i64))
+///     }
+/// }
+///
+/// let db = Database::open_memory().unwrap();
+/// db.register_accumulator("distinct", Arc::new(Distinct));
+///
+/// let visits = db.collection::<Document>("visits");
+/// visits.insert_many(vec![
+///     doc! { "page": "home", "user": "a" },
+///     doc! { "page": "home", "user": "b" },
+///     doc! { "page": "home", "user": "a" },
+/// ]).unwrap();
+///
+/// let result = visits.aggregate(vec![
+///     doc! {
+///         "$group": {
+///             "_id": "$page",
+///             "users": { "$accumulator": { "name": "distinct", "input": "$user" } },
+///         },
+///     },
+/// ]).unwrap();
+/// assert_eq!(result[0].get_i64("users").unwrap(), 2);
+/// ```
+///
+/// [`Database::register_accumulator`]: crate::Database::register_accumulator
+pub trait CustomAccumulator: Send + Sync {
+
+    /// The initial state of a group.
+    fn init(&self) -> Bson;
+
+    /// Add the evaluated input of a document to the state.
+    fn accumulate(&self, state: Bson, value: &Bson) -> DbResult<Bson>;
+
+    /// Combine two partial states of the same group.
+    fn merge(&self, state: Bson, other: Bson) -> DbResult<Bson>;
+
+    /// Convert the state to the output value.
+    fn finalize(&self, state: Bson) -> DbResult<Bson>;
+
+}
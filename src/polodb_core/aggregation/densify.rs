@@ -0,0 +1,266 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document, DateTime};
+use hashbrown::{HashMap, HashSet};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{AggregationContext, Stage};
+use super::accumulator::as_f64;
+use super::expression::{resolve_path, Variables};
+use super::hash_join::{join_key, JoinKey};
+
+#[derive(Debug, Clone, Copy)]
+enum DensifyBounds {
+    /// From the minimum to the maximum of all the documents.
+    Full,
+    /// From the minimum to the maximum of every partition.
+    Partition,
+    /// The lower bound is inclusive, and the upper bound is exclusive.
+    Range(f64, f64),
+}
+
+/// `$densify` inserts the documents for the missing values
+/// of a numeric or date field in a step-aligned range.
+pub(super) struct DensifyStage {
+    field:            String,
+    partition_fields: Vec<String>,
+    step:             f64,
+    // the step is in milliseconds if the field is a date
+    is_date:          bool,
+    bounds:           DensifyBounds,
+}
+
+fn unit_millis(unit: &str) -> DbResult<f64> {
+    let result = match unit {
+        "millisecond" => 1.0,
+        "second" => 1000.0,
+        "minute" => 60.0 * 1000.0,
+        "hour" => 60.0 * 60.0 * 1000.0,
+        "day" => 24.0 * 60.0 * 60.0 * 1000.0,
+        "week" => 7.0 * 24.0 * 60.0 * 60.0 * 1000.0,
+        _ => return Err(DbErr::ValidationError(format!("$densify: unsupported unit '{}'", unit))),
+    };
+    Ok(result)
+}
+
+fn bound_value(value: &Bson, is_date: bool) -> DbResult<f64> {
+    match value {
+        Bson::DateTime(dt) if is_date => Ok(dt.timestamp_millis() as f64),
+        _ if !is_date => as_f64(value).ok_or_else(|| {
+            mk_field_name_type_unexpected("bounds".into(), "Number".into(), format!("{}", value))
+        }),
+        _ => Err(mk_field_name_type_unexpected("bounds".into(), "DateTime".into(), format!("{}", value))),
+    }
+}
+
+impl DensifyStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<DensifyStage> {
+        let field = match spec.get("field") {
+            Some(Bson::String(field)) => field.clone(),
+            Some(t) => return Err(mk_field_name_type_unexpected("field".into(), "String".into(), format!("{}", t))),
+            None => return Err(DbErr::ValidationError("$densify requires 'field'".into())),
+        };
+
+        let mut partition_fields = vec![];
+        if let Some(value) = spec.get("partitionByFields") {
+            for item in crate::try_unwrap_array!("partitionByFields", value) {
+                match item {
+                    Bson::String(name) => partition_fields.push(name.clone()),
+                    t => return Err(mk_field_name_type_unexpected("partitionByFields".into(), "String".into(), format!("{}", t))),
+                }
+            }
+        }
+
+        let range = match spec.get("range") {
+            Some(value) => crate::try_unwrap_document!("range", value),
+            None => return Err(DbErr::ValidationError("$densify requires 'range'".into())),
+        };
+        let step = range.get("step")
+            .and_then(as_f64)
+            .filter(|step| *step > 0.0)
+            .ok_or_else(|| DbErr::ValidationError("$densify requires a positive 'step'".into()))?;
+        let (step, is_date) = match range.get("unit") {
+            Some(Bson::String(unit)) => (step * unit_millis(unit)?, true),
+            Some(t) => return Err(mk_field_name_type_unexpected("unit".into(), "String".into(), format!("{}", t))),
+            None => (step, false),
+        };
+
+        let bounds = match range.get("bounds") {
+            Some(Bson::String(s)) if s == "full" => DensifyBounds::Full,
+            Some(Bson::String(s)) if s == "partition" => DensifyBounds::Partition,
+            Some(Bson::Array(arr)) if arr.len() == 2 => {
+                DensifyBounds::Range(bound_value(&arr[0], is_date)?, bound_value(&arr[1], is_date)?)
+            }
+            _ => {
+                return Err(DbErr::ValidationError(
+                    "$densify: 'bounds' should be 'full', 'partition' or [lower, upper]".into()
+                ));
+            }
+        };
+
+        Ok(DensifyStage {
+            field,
+            partition_fields,
+            step,
+            is_date,
+            bounds,
+        })
+    }
+
+    fn value_of(&self, doc: &Document) -> DbResult<Option<f64>> {
+        let value = match resolve_path(doc, &self.field) {
+            Some(Bson::Null) | None => return Ok(None),
+            Some(value) => value,
+        };
+        let result = match &value {
+            Bson::DateTime(dt) if self.is_date => dt.timestamp_millis() as f64,
+            _ if !self.is_date => match as_f64(&value) {
+                Some(f) => f,
+                None => return Err(mk_field_name_type_unexpected(self.field.clone(), "Number".into(), format!("{}", value))),
+            },
+            _ => return Err(mk_field_name_type_unexpected(self.field.clone(), "DateTime".into(), format!("{}", value))),
+        };
+        Ok(Some(result))
+    }
+
+    fn to_bson(&self, value: f64) -> Bson {
+        if self.is_date {
+            Bson::DateTime(DateTime::from_millis(value as i64))
+        } else if value.fract() == 0.0 && value.abs() < i64::MAX as f64 {
+            Bson::Int64(value as i64)
+        } else {
+            Bson::Double(value)
+        }
+    }
+
+    // The document of a generated value, with the partition fields.
+    fn make_document(&self, partition_doc: &Document, value: f64) -> Document {
+        let mut doc = partition_doc.clone();
+        insert_path(&mut doc, &self.field, self.to_bson(value));
+        doc
+    }
+
+}
+
+// Insert the value by a dotted path, the sub-documents are created if absent.
+fn insert_path(doc: &mut Document, path: &str, value: Bson) {
+    match path.split_once('.') {
+        None => {
+            doc.insert(path.to_string(), value);
+        }
+        Some((head, rest)) => {
+            if !matches!(doc.get(head), Some(Bson::Document(_))) {
+                doc.insert(head.to_string(), Document::new());
+            }
+            if let Some(Bson::Document(sub_doc)) = doc.get_mut(head) {
+                insert_path(sub_doc, rest, value);
+            }
+        }
+    }
+}
+
+struct DensifyPartition {
+    fields:  Document,
+    docs:    Vec<(f64, Document)>,
+}
+
+impl Stage for DensifyStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, _vars: &Variables) -> DbResult<Vec<Document>> {
+        // the documents without the field are passed through
+        let mut result = vec![];
+        let mut partitions: Vec<DensifyPartition> = vec![];
+        let mut index_of: HashMap<Vec<JoinKey>, usize> = HashMap::new();
+        for doc in input {
+            let value = match self.value_of(&doc)? {
+                Some(value) => value,
+                None => {
+                    result.push(doc);
+                    continue;
+                }
+            };
+
+            let mut fields = Document::new();
+            let mut key = Vec::with_capacity(self.partition_fields.len());
+            for name in &self.partition_fields {
+                let field_value = resolve_path(&doc, name).unwrap_or(Bson::Null);
+                key.push(join_key(&field_value));
+                insert_path(&mut fields, name, field_value);
+            }
+            let index = *index_of.entry(key).or_insert_with(|| {
+                partitions.push(DensifyPartition {
+                    fields,
+                    docs: vec![],
+                });
+                partitions.len() - 1
+            });
+            partitions[index].docs.push((value, doc));
+        }
+
+        let all_values = || partitions.iter().flat_map(|p| p.docs.iter().map(|(value, _)| *value));
+        let full_range = match (
+            all_values().reduce(f64::min),
+            all_values().reduce(f64::max),
+        ) {
+            (Some(min), Some(max)) => Some((min, max)),
+            _ => None,
+        };
+
+        for mut partition in partitions {
+            partition.docs.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let (lower, upper, inclusive) = match self.bounds {
+                DensifyBounds::Full => {
+                    let (min, max) = full_range.unwrap();
+                    (min, max, true)
+                }
+                DensifyBounds::Partition => {
+                    let min = partition.docs.first().unwrap().0;
+                    let max = partition.docs.last().unwrap().0;
+                    (min, max, true)
+                }
+                DensifyBounds::Range(lower, upper) => (lower, upper, false),
+            };
+
+            let existing: HashSet<u64> = partition.docs.iter().map(|(value, _)| value.to_bits()).collect();
+            let mut generated = vec![];
+            let mut step_index: u64 = 0;
+            loop {
+                let value = lower + self.step * step_index as f64;
+                if value > upper || (!inclusive && value >= upper) {
+                    break;
+                }
+                if !existing.contains(&value.to_bits()) {
+                    generated.push((value, self.make_document(&partition.fields, value)));
+                }
+                step_index += 1;
+            }
+
+            let mut docs = partition.docs;
+            docs.extend(generated);
+            docs.sort_by(|a, b| a.0.total_cmp(&b.0));
+            result.extend(docs.into_iter().map(|(_, doc)| doc));
+        }
+
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, doc};
+    use super::insert_path;
+
+    #[test]
+    fn test_insert_path() {
+        let mut doc = doc! { "a": { "b": 1 } };
+        insert_path(&mut doc, "a.c", Bson::Int32(2));
+        insert_path(&mut doc, "x.y", Bson::Int32(3));
+        assert_eq!(doc, doc! { "a": { "b": 1, "c": 2 }, "x": { "y": 3 } });
+    }
+
+}
@@ -0,0 +1,358 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use std::sync::Arc;
+use bson::{Bson, Document};
+use hashbrown::HashMap;
+use crate::{DbErr, DbResult, OperatorMode};
+use crate::error::mk_field_name_type_unexpected;
+use super::{convert, operators};
+
+/// A function registered by [`Database::register_function`], called
+/// by `{ "$fn": { "name": <name>, "args": [<expression>, ...] } }`.
+///
+/// [`Database::register_function`]: crate::Database::register_function
+pub type ExprFunction = Arc<dyn Fn(&[Bson]) -> DbResult<Bson> + Send + Sync>;
+
+pub(crate) type Functions = HashMap<String, ExprFunction>;
+
+/// The variables can be referenced by `$$name` in the expressions,
+/// with the functions can be called by `$fn`.
+#[derive(Clone, Default)]
+pub(crate) struct Variables {
+    values:        HashMap<String, Bson>,
+    functions:     Arc<Functions>,
+    operator_mode: OperatorMode,
+}
+
+impl Variables {
+
+    pub(crate) fn new() -> Variables {
+        Variables::default()
+    }
+
+    pub(crate) fn with_functions(functions: Arc<Functions>) -> Variables {
+        Variables {
+            values: HashMap::new(),
+            functions,
+            operator_mode: OperatorMode::default(),
+        }
+    }
+
+    /// The operator mode of the queries matched with the variables.
+    pub(crate) fn set_operator_mode(&mut self, mode: OperatorMode) {
+        self.operator_mode = mode;
+    }
+
+    pub(crate) fn operator_mode(&self) -> OperatorMode {
+        self.operator_mode
+    }
+
+    pub(crate) fn insert(&mut self, name: String, value: Bson) {
+        self.values.insert(name, value);
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&Bson> {
+        self.values.get(name)
+    }
+
+    fn function(&self, name: &str) -> Option<&ExprFunction> {
+        self.functions.get(name)
+    }
+
+}
+
+/// Get the value of a dotted path, such as `address.city`.
+///
+/// If an array of documents is met, the path is resolved
+/// on every element, and an array is returned.
+pub(crate) fn resolve_path(doc: &Document, path: &str) -> Option<Bson> {
+    let (head, rest) = match path.split_once('.') {
+        Some((head, rest)) => (head, Some(rest)),
+        None => (path, None),
+    };
+    let value = doc.get(head)?;
+    match rest {
+        None => Some(value.clone()),
+        Some(rest) => resolve_value_path(value, rest),
+    }
+}
+
+fn resolve_value_path(value: &Bson, path: &str) -> Option<Bson> {
+    match value {
+        Bson::Document(sub_doc) => resolve_path(sub_doc, path),
+        Bson::Array(arr) => {
+            let result: Vec<Bson> = arr
+                .iter()
+                .filter_map(|item| match item {
+                    Bson::Document(sub_doc) => resolve_path(sub_doc, path),
+                    _ => None,
+                })
+                .collect();
+            Some(Bson::Array(result))
+        }
+        _ => None,
+    }
+}
+
+/// Compare two values, return `None` if they are not comparable.
+pub(crate) fn compare_values(a: &Bson, b: &Bson) -> Option<Ordering> {
+    if a == b {
+        return Some(Ordering::Equal);
+    }
+    crate::bson_utils::value_cmp(a, b).ok()
+}
+
+pub(crate) fn values_equal(a: &Bson, b: &Bson) -> bool {
+    matches!(compare_values(a, b), Some(Ordering::Equal))
+}
+
+pub(crate) fn is_truthy(value: &Bson) -> bool {
+    match value {
+        Bson::Null | Bson::Undefined => false,
+        Bson::Boolean(b) => *b,
+        Bson::Int32(i) => *i != 0,
+        Bson::Int64(i) => *i != 0,
+        Bson::Double(f) => *f != 0.0,
+        _ => true,
+    }
+}
+
+fn operands<'a>(op: &str, value: &'a Bson) -> DbResult<&'a [Bson]> {
+    match value {
+        Bson::Array(arr) => Ok(arr.as_slice()),
+        t => Err(mk_field_name_type_unexpected(op.into(), "Array".into(), format!("{}", t))),
+    }
+}
+
+fn binary_operands<'a>(op: &str, value: &'a Bson) -> DbResult<(&'a Bson, &'a Bson)> {
+    let arr = operands(op, value)?;
+    if arr.len() != 2 {
+        return Err(DbErr::ValidationError(format!("{} takes exactly 2 arguments", op)));
+    }
+    Ok((&arr[0], &arr[1]))
+}
+
+/// Evaluate an aggregation expression on the document.
+///
+/// - `"$field.path"` is the value of the field
+/// - `"$$name"` is the value of the variable, `$$ROOT` and `$$CURRENT` are the document itself
+/// - `{ "$op": args }` is an operator
+/// - other documents and arrays are evaluated recursively
+pub(crate) fn evaluate(expr: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    match expr {
+        Bson::String(s) if s.starts_with("$$") => evaluate_variable(&s[2..], doc, vars),
+        Bson::String(s) if s.starts_with('$') => {
+            Ok(resolve_path(doc, &s[1..]).unwrap_or(Bson::Null))
+        }
+        Bson::Array(arr) => {
+            let mut result = bson::Array::with_capacity(arr.len());
+            for item in arr {
+                result.push(evaluate(item, doc, vars)?);
+            }
+            Ok(Bson::Array(result))
+        }
+        Bson::Document(expr_doc) => {
+            if expr_doc.len() == 1 {
+                let (key, value) = expr_doc.iter().next().unwrap();
+                if key.starts_with('$') {
+                    return evaluate_operator(key, value, doc, vars);
+                }
+            }
+            let mut result = Document::new();
+            for (key, value) in expr_doc.iter() {
+                result.insert(key.clone(), evaluate(value, doc, vars)?);
+            }
+            Ok(Bson::Document(result))
+        }
+        _ => Ok(expr.clone()),
+    }
+}
+
+fn evaluate_variable(name: &str, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let (var_name, path) = match name.split_once('.') {
+        Some((var_name, path)) => (var_name, Some(path)),
+        None => (name, None),
+    };
+
+    let value = match var_name {
+        "ROOT" | "CURRENT" => Bson::Document(doc.clone()),
+        _ => match vars.get(var_name) {
+            Some(value) => value.clone(),
+            None => return Err(DbErr::ValidationError(format!("use of undefined variable: {}", var_name))),
+        }
+    };
+
+    match path {
+        Some(path) => Ok(resolve_value_path(&value, path).unwrap_or(Bson::Null)),
+        None => Ok(value),
+    }
+}
+
+fn evaluate_operator(op: &str, value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let result = match op {
+        "$literal" => value.clone(),
+
+        "$eq" | "$ne" | "$gt" | "$gte" | "$lt" | "$lte" | "$cmp" => {
+            let (a, b) = binary_operands(op, value)?;
+            let a = evaluate(a, doc, vars)?;
+            let b = evaluate(b, doc, vars)?;
+            let ord = compare_values(&a, &b);
+            match op {
+                "$eq" => Bson::Boolean(ord == Some(Ordering::Equal)),
+                "$ne" => Bson::Boolean(ord != Some(Ordering::Equal)),
+                "$gt" => Bson::Boolean(ord == Some(Ordering::Greater)),
+                "$gte" => Bson::Boolean(matches!(ord, Some(Ordering::Greater | Ordering::Equal))),
+                "$lt" => Bson::Boolean(ord == Some(Ordering::Less)),
+                "$lte" => Bson::Boolean(matches!(ord, Some(Ordering::Less | Ordering::Equal))),
+                _ => Bson::Int32(ord.map(|ord| ord as i32).unwrap_or(0)),
+            }
+        }
+
+        "$and" => {
+            for item in operands(op, value)? {
+                if !is_truthy(&evaluate(item, doc, vars)?) {
+                    return Ok(Bson::Boolean(false));
+                }
+            }
+            Bson::Boolean(true)
+        }
+
+        "$or" => {
+            for item in operands(op, value)? {
+                if is_truthy(&evaluate(item, doc, vars)?) {
+                    return Ok(Bson::Boolean(true));
+                }
+            }
+            Bson::Boolean(false)
+        }
+
+        "$not" => {
+            let arg = match value {
+                Bson::Array(arr) if arr.len() == 1 => &arr[0],
+                _ => value,
+            };
+            Bson::Boolean(!is_truthy(&evaluate(arg, doc, vars)?))
+        }
+
+        "$ifNull" => {
+            for item in operands(op, value)? {
+                let result = evaluate(item, doc, vars)?;
+                if !matches!(result, Bson::Null | Bson::Undefined) {
+                    return Ok(result);
+                }
+            }
+            Bson::Null
+        }
+
+        "$cond" => {
+            let (cond, then, otherwise) = match value {
+                Bson::Array(arr) if arr.len() == 3 => (&arr[0], &arr[1], &arr[2]),
+                Bson::Document(cond_doc) => {
+                    match (cond_doc.get("if"), cond_doc.get("then"), cond_doc.get("else")) {
+                        (Some(cond), Some(then), Some(otherwise)) => (cond, then, otherwise),
+                        _ => return Err(DbErr::ValidationError("$cond requires 'if', 'then' and 'else'".into())),
+                    }
+                }
+                t => return Err(mk_field_name_type_unexpected(op.into(), "Array".into(), format!("{}", t))),
+            };
+            if is_truthy(&evaluate(cond, doc, vars)?) {
+                evaluate(then, doc, vars)?
+            } else {
+                evaluate(otherwise, doc, vars)?
+            }
+        }
+
+        "$add" => operators::add(value, doc, vars)?,
+        "$subtract" => operators::subtract(value, doc, vars)?,
+        "$multiply" => operators::multiply(value, doc, vars)?,
+        "$divide" => operators::divide(value, doc, vars)?,
+        "$mod" => operators::modulo(value, doc, vars)?,
+        "$abs" => operators::abs(value, doc, vars)?,
+        "$round" | "$floor" | "$ceil" => operators::round(op, value, doc, vars)?,
+
+        "$arrayElemAt" => operators::array_elem_at(value, doc, vars)?,
+        "$filter" => operators::filter(value, doc, vars)?,
+        "$map" => operators::map(value, doc, vars)?,
+        "$reduce" => operators::reduce(value, doc, vars)?,
+        "$size" => operators::size(value, doc, vars)?,
+        "$slice" => operators::slice(value, doc, vars)?,
+        "$in" => operators::in_array(value, doc, vars)?,
+
+        "$toLower" | "$toUpper" => operators::change_case(op, value, doc, vars)?,
+        "$concat" => operators::concat(value, doc, vars)?,
+        "$trim" => operators::trim(value, doc, vars)?,
+
+        "$convert" => convert::convert(value, doc, vars)?,
+        "$toDouble" | "$toString" | "$toObjectId" | "$toBool" | "$toDate" | "$toInt" | "$toLong" => {
+            convert::convert_to(op, value, doc, vars)?
+        }
+
+        "$fn" => call_function(value, doc, vars)?,
+
+        _ => return Err(DbErr::UnknownAggregationOperation(op.into())),
+    };
+    Ok(result)
+}
+
+fn call_function(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = match value {
+        Bson::Document(spec) => spec,
+        t => return Err(mk_field_name_type_unexpected("$fn".into(), "Document".into(), format!("{}", t))),
+    };
+    let name = match spec.get("name") {
+        Some(Bson::String(name)) => name,
+        Some(t) => return Err(mk_field_name_type_unexpected("name".into(), "String".into(), format!("{}", t))),
+        None => return Err(DbErr::ValidationError("$fn requires 'name'".into())),
+    };
+    let function = match vars.function(name) {
+        Some(function) => function.clone(),
+        None => return Err(DbErr::UnknownAggregationOperation(format!("$fn: {}", name))),
+    };
+
+    let mut args = vec![];
+    match spec.get("args") {
+        None => (),
+        Some(Bson::Array(arr)) => {
+            for arg in arr {
+                args.push(evaluate(arg, doc, vars)?);
+            }
+        }
+        Some(t) => return Err(mk_field_name_type_unexpected("args".into(), "Array".into(), format!("{}", t))),
+    }
+    function(&args)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, doc};
+    use super::{evaluate, Variables};
+
+    #[test]
+    fn test_evaluate() {
+        let doc = doc! {
+            "qty": 5,
+            "item": { "name": "apple" },
+        };
+        let mut vars = Variables::new();
+        vars.insert("min".into(), Bson::Int32(3));
+
+        let result = evaluate(&Bson::Document(doc! {
+            "$and": [
+                { "$gte": ["$qty", "$$min"] },
+                { "$eq": ["$item.name", "apple"] },
+            ],
+        }), &doc, &vars).unwrap();
+        assert_eq!(result, Bson::Boolean(true));
+
+        let result = evaluate(&Bson::Document(doc! {
+            "name": "$item.name",
+            "missing": { "$ifNull": ["$none", "default"] },
+        }), &doc, &vars).unwrap();
+        assert_eq!(result, Bson::Document(doc! { "name": "apple", "missing": "default" }));
+    }
+
+}
@@ -0,0 +1,212 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document, DateTime};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{partition, AggregationContext, Stage};
+use super::accumulator::as_f64;
+use super::expression::{evaluate, resolve_path, Variables};
+use super::sort::SortSpec;
+
+enum FillMethod {
+    /// Fill with the evaluated expression.
+    Value(Bson),
+    /// Last observation carried forward.
+    Locf,
+    /// Linear interpolation by the `sortBy` field.
+    Linear,
+}
+
+struct FillField {
+    name:   String,
+    method: FillMethod,
+}
+
+/// `$fill` fills the null and missing fields of the documents.
+pub(super) struct FillStage {
+    partition_by: Option<Bson>,
+    sort_by:      Option<SortSpec>,
+    output:       Vec<FillField>,
+}
+
+fn is_missing(value: &Option<Bson>) -> bool {
+    matches!(value, None | Some(Bson::Null) | Some(Bson::Undefined))
+}
+
+fn numeric_of(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::DateTime(dt) => Some(dt.timestamp_millis() as f64),
+        _ => as_f64(value),
+    }
+}
+
+impl FillStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<FillStage> {
+        let partition_by = match (spec.get("partitionBy"), spec.get("partitionByFields")) {
+            (Some(_), Some(_)) => {
+                return Err(DbErr::ValidationError(
+                    "$fill accepts only one of 'partitionBy' and 'partitionByFields'".into()
+                ));
+            }
+            (Some(expr), None) => Some(expr.clone()),
+            (None, Some(value)) => {
+                let mut fields = Document::new();
+                for item in crate::try_unwrap_array!("partitionByFields", value) {
+                    match item {
+                        Bson::String(name) => {
+                            fields.insert(name.clone(), format!("${}", name));
+                        }
+                        t => return Err(mk_field_name_type_unexpected("partitionByFields".into(), "String".into(), format!("{}", t))),
+                    }
+                }
+                Some(Bson::Document(fields))
+            }
+            (None, None) => None,
+        };
+
+        let sort_by = match spec.get("sortBy") {
+            Some(value) => {
+                let sort_doc = crate::try_unwrap_document!("sortBy", value);
+                Some(SortSpec::parse("sortBy", sort_doc)?)
+            }
+            None => None,
+        };
+
+        let output_doc = match spec.get("output") {
+            Some(value) => crate::try_unwrap_document!("output", value),
+            None => return Err(DbErr::ValidationError("$fill requires 'output'".into())),
+        };
+        let mut output = Vec::with_capacity(output_doc.len());
+        for (name, value) in output_doc.iter() {
+            let field_spec = crate::try_unwrap_document!("output", value);
+            let method = match (field_spec.get("value"), field_spec.get("method")) {
+                (Some(expr), None) => FillMethod::Value(expr.clone()),
+                (None, Some(Bson::String(method))) => {
+                    let method = match method.as_str() {
+                        "locf" => FillMethod::Locf,
+                        "linear" => FillMethod::Linear,
+                        _ => return Err(DbErr::ValidationError(format!("$fill: unknown method '{}'", method))),
+                    };
+                    if sort_by.is_none() {
+                        return Err(DbErr::ValidationError(
+                            format!("$fill: the method of '{}' requires 'sortBy'", name)
+                        ));
+                    }
+                    method
+                }
+                _ => {
+                    return Err(DbErr::ValidationError(
+                        format!("$fill: '{}' requires exactly one of 'value' and 'method'", name)
+                    ));
+                }
+            };
+            if matches!(method, FillMethod::Linear)
+                && sort_by.as_ref().and_then(|s| s.single_field()).is_none() {
+                return Err(DbErr::ValidationError(
+                    "$fill: linear interpolation requires exactly one 'sortBy' field".into()
+                ));
+            }
+            output.push(FillField {
+                name: name.clone(),
+                method,
+            });
+        }
+
+        Ok(FillStage {
+            partition_by,
+            sort_by,
+            output,
+        })
+    }
+
+    fn fill_linear(&self, field: &str, docs: &mut [Document]) -> DbResult<()> {
+        let (sort_field, _) = self.sort_by.as_ref().unwrap().single_field().unwrap();
+        let mut positions = Vec::with_capacity(docs.len());
+        for doc in docs.iter() {
+            let value = resolve_path(doc, sort_field).unwrap_or(Bson::Null);
+            match numeric_of(&value) {
+                Some(f) => positions.push(f),
+                None => return Err(mk_field_name_type_unexpected(sort_field.into(), "Number".into(), format!("{}", value))),
+            }
+        }
+
+        // the index and the value of the last known point
+        let values: Vec<Option<Bson>> = docs.iter().map(|doc| doc.get(field).cloned()).collect();
+        let mut last_known: Option<(usize, Bson)> = None;
+        for (index, value) in values.into_iter().enumerate() {
+            let value = match value {
+                Some(Bson::Null) | Some(Bson::Undefined) | None => continue,
+                Some(value) => value,
+            };
+            if let Some((start, start_value)) = &last_known {
+                if index > start + 1 {
+                    let from = numeric_of(start_value);
+                    let to = numeric_of(&value);
+                    if let (Some(from), Some(to)) = (from, to) {
+                        let span = positions[index] - positions[*start];
+                        for (gap, doc) in docs.iter_mut().enumerate().take(index).skip(start + 1) {
+                            let ratio = if span == 0.0 { 0.0 } else { (positions[gap] - positions[*start]) / span };
+                            let filled = from + (to - from) * ratio;
+                            let filled = if matches!(value, Bson::DateTime(_)) {
+                                Bson::DateTime(DateTime::from_millis(filled as i64))
+                            } else {
+                                Bson::Double(filled)
+                            };
+                            doc.insert(field.to_string(), filled);
+                        }
+                    }
+                }
+            }
+            last_known = Some((index, value));
+        }
+        Ok(())
+    }
+
+}
+
+impl Stage for FillStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut result = Vec::with_capacity(input.len());
+        for mut docs in partition(self.partition_by.as_ref(), input, vars)? {
+            if let Some(sort_by) = &self.sort_by {
+                sort_by.sort(&mut docs);
+            }
+
+            for field in &self.output {
+                match &field.method {
+                    FillMethod::Value(expr) => {
+                        for doc in docs.iter_mut() {
+                            if is_missing(&doc.get(&field.name).cloned()) {
+                                let value = evaluate(expr, doc, vars)?;
+                                doc.insert(field.name.clone(), value);
+                            }
+                        }
+                    }
+                    FillMethod::Locf => {
+                        let mut last: Option<Bson> = None;
+                        for doc in docs.iter_mut() {
+                            let value = doc.get(&field.name).cloned();
+                            if is_missing(&value) {
+                                if let Some(last) = &last {
+                                    doc.insert(field.name.clone(), last.clone());
+                                }
+                            } else {
+                                last = value;
+                            }
+                        }
+                    }
+                    FillMethod::Linear => self.fill_linear(&field.name, &mut docs)?,
+                }
+            }
+
+            result.extend(docs);
+        }
+        Ok(result)
+    }
+
+}
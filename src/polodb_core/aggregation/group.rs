@@ -0,0 +1,197 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::sync::Arc;
+use bson::{Bson, Document};
+use hashbrown::HashMap;
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{AggregationContext, CustomAccumulator, Stage};
+use super::accumulator::Accumulator;
+use super::expression::{evaluate, Variables};
+use super::hash_join::{join_key, JoinKey};
+
+// The custom accumulators run on the batches of documents,
+// and the partial states of the batches are merged.
+const GROUP_BATCH_SIZE: usize = 1024;
+
+enum GroupAccumulator {
+    Builtin(Accumulator, Bson),
+    Custom {
+        name:  String,
+        input: Bson,
+    },
+}
+
+struct GroupField {
+    name:        String,
+    accumulator: GroupAccumulator,
+}
+
+/// `$group` groups the documents by the `_id` expression,
+/// and computes the accumulated fields of every group.
+pub(super) struct GroupStage {
+    id:     Bson,
+    fields: Vec<GroupField>,
+}
+
+enum FieldState {
+    Values(Vec<Bson>),
+    Custom(Bson),
+}
+
+struct Group {
+    id:     Bson,
+    states: Vec<FieldState>,
+}
+
+fn parse_custom(spec: &Bson) -> DbResult<GroupAccumulator> {
+    let spec = crate::try_unwrap_document!("$accumulator", spec);
+    let name = match spec.get("name") {
+        Some(Bson::String(name)) => name.clone(),
+        Some(t) => return Err(mk_field_name_type_unexpected("name".into(), "String".into(), format!("{}", t))),
+        None => return Err(DbErr::ValidationError("$accumulator requires 'name'".into())),
+    };
+    let input = spec.get("input").cloned().unwrap_or(Bson::Null);
+    Ok(GroupAccumulator::Custom { name, input })
+}
+
+impl GroupStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<GroupStage> {
+        let id = match spec.get("_id") {
+            Some(id) => id.clone(),
+            None => return Err(DbErr::ValidationError("$group requires '_id'".into())),
+        };
+
+        let mut fields = vec![];
+        for (name, value) in spec.iter() {
+            if name == "_id" {
+                continue;
+            }
+            let field_spec = crate::try_unwrap_document!("$group", value);
+            if field_spec.len() != 1 {
+                return Err(DbErr::ValidationError(
+                    format!("$group: the field '{}' should have exactly one accumulator", name)
+                ));
+            }
+            let (op, expr) = field_spec.iter().next().unwrap();
+            let accumulator = match op.as_str() {
+                "$accumulator" => parse_custom(expr)?,
+                _ => match Accumulator::from_name(op) {
+                    Some(accumulator) => GroupAccumulator::Builtin(accumulator, expr.clone()),
+                    None => return Err(DbErr::UnknownAggregationOperation(op.clone())),
+                },
+            };
+            fields.push(GroupField {
+                name: name.clone(),
+                accumulator,
+            });
+        }
+
+        Ok(GroupStage { id, fields })
+    }
+
+    fn new_group(&self, id: Bson, customs: &[Option<Arc<dyn CustomAccumulator>>]) -> Group {
+        let states = customs
+            .iter()
+            .map(|custom| match custom {
+                Some(custom) => FieldState::Custom(custom.init()),
+                None => FieldState::Values(vec![]),
+            })
+            .collect();
+        Group { id, states }
+    }
+
+}
+
+impl Stage for GroupStage {
+
+    fn execute(&self, ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut customs: Vec<Option<Arc<dyn CustomAccumulator>>> = Vec::with_capacity(self.fields.len());
+        for field in &self.fields {
+            let custom = match &field.accumulator {
+                GroupAccumulator::Custom { name, .. } => Some(ctx.custom_accumulator(name)?),
+                GroupAccumulator::Builtin(..) => None,
+            };
+            customs.push(custom);
+        }
+
+        let mut groups: Vec<Group> = vec![];
+        let mut index_of: HashMap<JoinKey, usize> = HashMap::new();
+
+        for batch in input.chunks(GROUP_BATCH_SIZE) {
+            // the partial states of the custom accumulators in the batch
+            let mut batch_states: HashMap<usize, Vec<Bson>> = HashMap::new();
+
+            for doc in batch {
+                let id = evaluate(&self.id, doc, vars)?;
+                let index = match index_of.get(&join_key(&id)) {
+                    Some(index) => *index,
+                    None => {
+                        index_of.insert(join_key(&id), groups.len());
+                        groups.push(self.new_group(id, &customs));
+                        groups.len() - 1
+                    }
+                };
+
+                for (field_index, field) in self.fields.iter().enumerate() {
+                    match &field.accumulator {
+                        GroupAccumulator::Builtin(_, expr) => {
+                            let value = evaluate(expr, doc, vars)?;
+                            if let FieldState::Values(values) = &mut groups[index].states[field_index] {
+                                values.push(value);
+                            }
+                        }
+                        GroupAccumulator::Custom { input, .. } => {
+                            let custom = customs[field_index].as_ref().unwrap();
+                            let value = evaluate(input, doc, vars)?;
+                            let states = batch_states.entry(index).or_insert_with(|| {
+                                customs
+                                    .iter()
+                                    .map(|custom| custom.as_ref().map(|c| c.init()).unwrap_or(Bson::Null))
+                                    .collect()
+                            });
+                            let state = std::mem::replace(&mut states[field_index], Bson::Null);
+                            states[field_index] = custom.accumulate(state, &value)?;
+                        }
+                    }
+                }
+            }
+
+            for (index, states) in batch_states {
+                for (field_index, partial) in states.into_iter().enumerate() {
+                    let custom = match &customs[field_index] {
+                        Some(custom) => custom,
+                        None => continue,
+                    };
+                    if let FieldState::Custom(state) = &mut groups[index].states[field_index] {
+                        let current = std::mem::replace(state, Bson::Null);
+                        *state = custom.merge(current, partial)?;
+                    }
+                }
+            }
+        }
+
+        let mut result = Vec::with_capacity(groups.len());
+        for group in groups {
+            let mut doc = Document::new();
+            doc.insert("_id", group.id);
+            for ((field, state), custom) in self.fields.iter().zip(group.states).zip(&customs) {
+                let value = match (&field.accumulator, state, custom) {
+                    (GroupAccumulator::Builtin(accumulator, _), FieldState::Values(values), _) => {
+                        accumulator.accumulate(&values)
+                    }
+                    (_, FieldState::Custom(state), Some(custom)) => custom.finalize(state)?,
+                    _ => unreachable!(),
+                };
+                doc.insert(field.name.clone(), value);
+            }
+            result.push(doc);
+        }
+        Ok(result)
+    }
+
+}
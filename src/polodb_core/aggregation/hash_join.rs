@@ -0,0 +1,257 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use bson::{Bson, doc};
+use bson::oid::ObjectId;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use hashbrown::HashMap;
+use crate::DbResult;
+
+const SPILL_PARTITIONS: usize = 16;
+
+// The overhead of a key in the hash table besides its bytes.
+const ENTRY_OVERHEAD: usize = size_of::<Vec<u8>>() + size_of::<Vec<usize>>() + size_of::<usize>();
+
+/// The encoded value of a join key, the values equal
+/// in the query semantics have the same key.
+pub(crate) type JoinKey = Vec<u8>;
+
+/// Encode the value as a join key, numbers equal in value have the same key.
+pub(crate) fn join_key(value: &Bson) -> JoinKey {
+    let mut key = Vec::with_capacity(16);
+    match value {
+        Bson::Int32(i) => {
+            key.push(b'i');
+            key.extend_from_slice(&(*i as i64).to_le_bytes());
+        }
+        Bson::Int64(i) => {
+            key.push(b'i');
+            key.extend_from_slice(&i.to_le_bytes());
+        }
+        Bson::Double(f) if f.fract() == 0.0 && f.abs() < i64::MAX as f64 => {
+            key.push(b'i');
+            key.extend_from_slice(&(*f as i64).to_le_bytes());
+        }
+        Bson::Double(f) => {
+            key.push(b'd');
+            key.extend_from_slice(&f.to_bits().to_le_bytes());
+        }
+        Bson::String(s) => {
+            key.push(b's');
+            key.extend_from_slice(s.as_bytes());
+        }
+        Bson::ObjectId(oid) => {
+            key.push(b'o');
+            key.extend_from_slice(&oid.bytes());
+        }
+        Bson::Null | Bson::Undefined => key.push(b'n'),
+        _ => {
+            key.push(b'x');
+            key.extend_from_slice(&bson::to_vec(&doc! { "v": value.clone() }).unwrap_or_default());
+        }
+    }
+    key
+}
+
+fn partition_of(key: &[u8]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() as usize) % SPILL_PARTITIONS
+}
+
+/// A temporary file of `(row, key)` entries, removed when dropped.
+struct SpillFile {
+    path:   PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+impl SpillFile {
+
+    fn create() -> DbResult<SpillFile> {
+        let path = std::env::temp_dir().join(format!("polodb-join-{}.spill", ObjectId::new()));
+        let file = File::create(&path)?;
+        Ok(SpillFile {
+            path,
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+
+    fn write(&mut self, row: usize, key: &[u8]) -> DbResult<()> {
+        let writer = self.writer.as_mut().unwrap();
+        writer.write_u64::<LittleEndian>(row as u64)?;
+        writer.write_u32::<LittleEndian>(key.len() as u32)?;
+        writer.write_all(key)?;
+        Ok(())
+    }
+
+    fn read_all(&mut self) -> DbResult<Vec<(usize, JoinKey)>> {
+        if let Some(mut writer) = self.writer.take() {
+            writer.flush()?;
+        }
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut result = vec![];
+        loop {
+            let row = match reader.read_u64::<LittleEndian>() {
+                Ok(row) => row as usize,
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            };
+            let len = reader.read_u32::<LittleEndian>()? as usize;
+            let mut key = vec![0; len];
+            reader.read_exact(&mut key)?;
+            result.push((row, key));
+        }
+        Ok(result)
+    }
+
+}
+
+impl Drop for SpillFile {
+
+    fn drop(&mut self) {
+        self.writer = None;
+        let _ = fs::remove_file(&self.path);
+    }
+
+}
+
+/// A build/probe hash join of the rows identified by their indexes.
+///
+/// The keys of the build side are put in a hash table, and every probe row
+/// looks up the table for the build rows with an equal key. If the table
+/// would exceed the memory limit, both sides are partitioned by the hash
+/// of the keys into temporary files, and joined partition by partition.
+pub(crate) struct HashJoin {
+    memory_limit: usize,
+}
+
+impl HashJoin {
+
+    pub(crate) fn new(memory_limit: usize) -> HashJoin {
+        HashJoin {
+            memory_limit,
+        }
+    }
+
+    /// Return the indexes of the matched build rows for every probe row, in ascending order.
+    pub(crate) fn execute(&self, build: &[Vec<JoinKey>], probe: &[Vec<JoinKey>]) -> DbResult<Vec<Vec<usize>>> {
+        let estimated_size: usize = build
+            .iter()
+            .flatten()
+            .map(|key| key.len() + ENTRY_OVERHEAD)
+            .sum();
+
+        // the keys can't be spilled without a file system
+        let mut result = if estimated_size <= self.memory_limit || !cfg!(feature = "fs") {
+            self.execute_in_memory(build, probe)
+        } else {
+            self.execute_spilled(build, probe)?
+        };
+
+        for indexes in &mut result {
+            indexes.sort_unstable();
+            indexes.dedup();
+        }
+        Ok(result)
+    }
+
+    fn execute_in_memory(&self, build: &[Vec<JoinKey>], probe: &[Vec<JoinKey>]) -> Vec<Vec<usize>> {
+        let mut table: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (row, keys) in build.iter().enumerate() {
+            for key in keys {
+                table.entry(key.as_slice()).or_default().push(row);
+            }
+        }
+
+        probe
+            .iter()
+            .map(|keys| {
+                keys
+                    .iter()
+                    .filter_map(|key| table.get(key.as_slice()))
+                    .flatten()
+                    .copied()
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn execute_spilled(&self, build: &[Vec<JoinKey>], probe: &[Vec<JoinKey>]) -> DbResult<Vec<Vec<usize>>> {
+        crate::polo_log!("hash join exceeds the memory limit, spill to {} partitions", SPILL_PARTITIONS);
+
+        let mut build_files = Vec::with_capacity(SPILL_PARTITIONS);
+        let mut probe_files = Vec::with_capacity(SPILL_PARTITIONS);
+        for _ in 0..SPILL_PARTITIONS {
+            build_files.push(SpillFile::create()?);
+            probe_files.push(SpillFile::create()?);
+        }
+
+        for (row, keys) in build.iter().enumerate() {
+            for key in keys {
+                build_files[partition_of(key)].write(row, key)?;
+            }
+        }
+        for (row, keys) in probe.iter().enumerate() {
+            for key in keys {
+                probe_files[partition_of(key)].write(row, key)?;
+            }
+        }
+
+        let mut result = vec![vec![]; probe.len()];
+        for (build_file, probe_file) in build_files.iter_mut().zip(probe_files.iter_mut()) {
+            let mut table: HashMap<JoinKey, Vec<usize>> = HashMap::new();
+            for (row, key) in build_file.read_all()? {
+                table.entry(key).or_default().push(row);
+            }
+            if table.is_empty() {
+                continue;
+            }
+            for (row, key) in probe_file.read_all()? {
+                if let Some(indexes) = table.get(&key) {
+                    result[row].extend_from_slice(indexes);
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::Bson;
+    use super::{HashJoin, join_key};
+
+    fn keys_of(values: &[i64]) -> Vec<Vec<Vec<u8>>> {
+        values.iter().map(|v| vec![join_key(&Bson::Int64(*v))]).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_hash_join_spill() {
+        let build = keys_of(&(0..1000).map(|i| i % 100).collect::<Vec<i64>>());
+        let probe = keys_of(&(0..200).collect::<Vec<i64>>());
+
+        let in_memory = HashJoin::new(usize::MAX).execute(&build, &probe).unwrap();
+        let spilled = HashJoin::new(0).execute(&build, &probe).unwrap();
+        assert_eq!(in_memory, spilled);
+        assert_eq!(spilled[7].len(), 10);
+        assert!(spilled[150].is_empty());
+    }
+
+    #[test]
+    fn test_join_key_numbers() {
+        assert_eq!(join_key(&Bson::Int32(3)), join_key(&Bson::Double(3.0)));
+        assert_ne!(join_key(&Bson::Int32(3)), join_key(&Bson::String("3".into())));
+    }
+
+}
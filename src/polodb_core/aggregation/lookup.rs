@@ -0,0 +1,295 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document, doc};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{AggregationContext, Pipeline, Stage};
+use super::expression::{evaluate, resolve_path, values_equal, Variables};
+use super::hash_join::{HashJoin, join_key};
+
+// If the count of comparisons of a nested loop join exceeds it,
+// a hash join is used instead.
+const HASH_JOIN_THRESHOLD: usize = 4096;
+
+/// How the local documents are matched with the scanned foreign documents.
+///
+/// If the foreign field is the primary key, the foreign documents
+/// are found by the index one by one instead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JoinStrategy {
+    NestedLoop,
+    HashJoin,
+}
+
+enum LocalKey {
+    /// `localField` of the equality form.
+    Field(String),
+    /// `{ $eq: ["$foreignField", "$$var"] }` in the `$match` of the pipeline form.
+    Variable(Bson),
+}
+
+struct EqualityJoin {
+    foreign_field: String,
+    local_key:     LocalKey,
+}
+
+pub(super) struct LookupStage {
+    from:      String,
+    as_field:  String,
+    equality:  Option<EqualityJoin>,
+    let_vars:  Document,
+    pipeline:  Option<Pipeline>,
+}
+
+fn get_string_field(spec: &Document, key: &str) -> DbResult<Option<String>> {
+    match spec.get(key) {
+        None => Ok(None),
+        Some(Bson::String(s)) => Ok(Some(s.clone())),
+        Some(t) => Err(mk_field_name_type_unexpected(key.into(), "String".into(), format!("{}", t))),
+    }
+}
+
+// Find `{ $match: { $expr: { $eq: ["$field", "$$var"] } } }` as the first stage.
+fn extract_equality(pipeline: &Bson) -> Option<EqualityJoin> {
+    let first = pipeline.as_array()?.first()?.as_document()?;
+    let expr = first.get_document("$match").ok()?.get_document("$expr").ok()?;
+    if expr.len() != 1 {
+        return None;
+    }
+    let operands = expr.get_array("$eq").ok()?;
+    if operands.len() != 2 {
+        return None;
+    }
+
+    let as_field = |value: &Bson| value.as_str()
+        .filter(|s| s.starts_with('$') && !s.starts_with("$$"))
+        .map(|s| s[1..].to_string());
+    let is_variable = |value: &Bson| value.as_str()
+        .map(|s| s.starts_with("$$") && !s.starts_with("$$ROOT") && !s.starts_with("$$CURRENT"))
+        .unwrap_or(false);
+
+    let (field, variable) = match (as_field(&operands[0]), as_field(&operands[1])) {
+        (Some(field), None) if is_variable(&operands[1]) => (field, operands[1].clone()),
+        (None, Some(field)) if is_variable(&operands[0]) => (field, operands[0].clone()),
+        _ => return None,
+    };
+
+    Some(EqualityJoin {
+        foreign_field: field,
+        local_key: LocalKey::Variable(variable),
+    })
+}
+
+// The values to join with, an array is matched by any of its elements.
+fn join_values(value: Option<Bson>) -> Vec<Bson> {
+    match value {
+        None => vec![Bson::Null],
+        Some(Bson::Array(arr)) => arr,
+        Some(value) => vec![value],
+    }
+}
+
+fn choose_strategy(local_count: usize, foreign_count: usize) -> JoinStrategy {
+    if local_count.saturating_mul(foreign_count) > HASH_JOIN_THRESHOLD {
+        JoinStrategy::HashJoin
+    } else {
+        JoinStrategy::NestedLoop
+    }
+}
+
+impl LookupStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<LookupStage> {
+        let from = get_string_field(spec, "from")?
+            .ok_or_else(|| DbErr::ValidationError("$lookup requires 'from'".into()))?;
+        let as_field = get_string_field(spec, "as")?
+            .ok_or_else(|| DbErr::ValidationError("$lookup requires 'as'".into()))?;
+        let local_field = get_string_field(spec, "localField")?;
+        let foreign_field = get_string_field(spec, "foreignField")?;
+
+        let let_vars = match spec.get("let") {
+            None => Document::new(),
+            Some(Bson::Document(doc)) => doc.clone(),
+            Some(t) => return Err(mk_field_name_type_unexpected("let".into(), "Document".into(), format!("{}", t))),
+        };
+
+        let pipeline = match spec.get("pipeline") {
+            Some(pipeline) => Some(Pipeline::parse_bson("pipeline", pipeline)?),
+            None => None,
+        };
+
+        let equality = match (local_field, foreign_field) {
+            (Some(local_field), Some(foreign_field)) => Some(EqualityJoin {
+                foreign_field,
+                local_key: LocalKey::Field(local_field),
+            }),
+            (None, None) => spec.get("pipeline").and_then(extract_equality),
+            _ => {
+                return Err(DbErr::ValidationError(
+                    "$lookup requires both 'localField' and 'foreignField'".into()
+                ));
+            }
+        };
+
+        if equality.is_none() && pipeline.is_none() {
+            return Err(DbErr::ValidationError(
+                "$lookup requires 'localField' and 'foreignField', or 'pipeline'".into()
+            ));
+        }
+
+        Ok(LookupStage {
+            from,
+            as_field,
+            equality,
+            let_vars,
+            pipeline,
+        })
+    }
+
+    fn variables_of(&self, doc: &Document, vars: &Variables) -> DbResult<Variables> {
+        let mut result = vars.clone();
+        for (name, expr) in self.let_vars.iter() {
+            let value = evaluate(expr, doc, vars)?;
+            result.insert(name.clone(), value);
+        }
+        Ok(result)
+    }
+
+    fn local_values(&self, equality: &EqualityJoin, doc: &Document, vars: &Variables) -> DbResult<Vec<Bson>> {
+        let value = match &equality.local_key {
+            LocalKey::Field(path) => resolve_path(doc, path),
+            LocalKey::Variable(expr) => Some(evaluate(expr, doc, vars)?),
+        };
+        Ok(join_values(value))
+    }
+
+    fn join_by_index(
+        &self,
+        ctx: &AggregationContext,
+        equality: &EqualityJoin,
+        input: &[Document],
+        doc_vars: &[Variables],
+    ) -> DbResult<Vec<Vec<Document>>> {
+        let mut result = Vec::with_capacity(input.len());
+        for (doc, vars) in input.iter().zip(doc_vars) {
+            let mut joined: Vec<Document> = vec![];
+            for value in self.local_values(equality, doc, vars)? {
+                // the primary key can't be null or a document
+                if matches!(value, Bson::Null | Bson::Document(_)) {
+                    continue;
+                }
+                for foreign_doc in ctx.find(&self.from, Some(doc! { "_id": value }))? {
+                    if !joined.iter().any(|item| item.get("_id") == foreign_doc.get("_id")) {
+                        joined.push(foreign_doc);
+                    }
+                }
+            }
+            result.push(joined);
+        }
+        Ok(result)
+    }
+
+    fn join_by_scan(
+        &self,
+        ctx: &AggregationContext,
+        equality: &EqualityJoin,
+        foreign_docs: &[Document],
+        input: &[Document],
+        doc_vars: &[Variables],
+    ) -> DbResult<Vec<Vec<Document>>> {
+        let foreign_values: Vec<Vec<Bson>> = foreign_docs
+            .iter()
+            .map(|doc| join_values(resolve_path(doc, &equality.foreign_field)))
+            .collect();
+
+        let mut local_values = Vec::with_capacity(input.len());
+        for (doc, vars) in input.iter().zip(doc_vars) {
+            local_values.push(self.local_values(equality, doc, vars)?);
+        }
+
+        let indexes: Vec<Vec<usize>> = match choose_strategy(input.len(), foreign_docs.len()) {
+            JoinStrategy::HashJoin => {
+                let build: Vec<Vec<Vec<u8>>> = foreign_values
+                    .iter()
+                    .map(|values| values.iter().map(join_key).collect())
+                    .collect();
+                let probe: Vec<Vec<Vec<u8>>> = local_values
+                    .iter()
+                    .map(|values| values.iter().map(join_key).collect())
+                    .collect();
+                HashJoin::new(ctx.join_memory_limit()?).execute(&build, &probe)?
+            }
+            JoinStrategy::NestedLoop => local_values
+                .iter()
+                .map(|locals| foreign_values
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, values)| {
+                        values.iter().any(|fv| locals.iter().any(|lv| values_equal(lv, fv)))
+                    })
+                    .map(|(index, _)| index)
+                    .collect())
+                .collect(),
+        };
+
+        let result = indexes
+            .into_iter()
+            .map(|indexes| indexes.into_iter().map(|index| foreign_docs[index].clone()).collect())
+            .collect();
+        Ok(result)
+    }
+
+}
+
+impl Stage for LookupStage {
+
+    fn execute(&self, ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut doc_vars = Vec::with_capacity(input.len());
+        for doc in &input {
+            doc_vars.push(self.variables_of(doc, vars)?);
+        }
+
+        let joined = match &self.equality {
+            Some(equality) if equality.foreign_field == "_id" => {
+                self.join_by_index(ctx, equality, &input, &doc_vars)?
+            }
+            Some(equality) => {
+                let foreign_docs = ctx.find(&self.from, None)?;
+                self.join_by_scan(ctx, equality, &foreign_docs, &input, &doc_vars)?
+            }
+            None => {
+                let foreign_docs = ctx.find(&self.from, None)?;
+                vec![foreign_docs; input.len()]
+            }
+        };
+
+        let mut result = Vec::with_capacity(input.len());
+        for ((mut doc, vars), joined) in input.into_iter().zip(doc_vars).zip(joined) {
+            let joined = match &self.pipeline {
+                Some(pipeline) => pipeline.execute(ctx, joined, &vars)?,
+                None => joined,
+            };
+            let arr: bson::Array = joined.into_iter().map(Bson::Document).collect();
+            doc.insert(self.as_field.clone(), arr);
+            result.push(doc);
+        }
+
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose_strategy, JoinStrategy};
+
+    #[test]
+    fn test_choose_strategy() {
+        assert_eq!(choose_strategy(10, 10), JoinStrategy::NestedLoop);
+        assert_eq!(choose_strategy(1000, 1000), JoinStrategy::HashJoin);
+    }
+
+}
@@ -0,0 +1,258 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use bson::{Bson, Document};
+use crate::{DbErr, DbResult, OperatorMode};
+use crate::error::mk_field_name_type_unexpected;
+use super::expression::{compare_values, evaluate, is_truthy, resolve_path, values_equal, Variables};
+
+// The operators the VM can execute in a collection scan.
+const SCAN_OPERATORS: [&str; 9] = ["$eq", "$gt", "$gte", "$lt", "$lte", "$ne", "$in", "$nin", "$size"];
+
+const LOGICAL_OPERATORS: [&str; 5] = ["$and", "$or", "$nor", "$expr", "$not"];
+
+const FIELD_OPERATORS: [&str; 11] = [
+    "$eq", "$ne", "$gt", "$gte", "$lt", "$lte", "$in", "$nin", "$exists", "$size", "$not",
+];
+
+/// Test if the key of a query is an operator not known by any
+/// of the query engines, which is handled by the [`OperatorMode`].
+pub(crate) fn is_unknown_logical_operator(key: &str) -> bool {
+    key.starts_with('$') && !LOGICAL_OPERATORS.contains(&key)
+}
+
+/// Test if the key of a condition of a field is an unknown operator.
+pub(crate) fn is_unknown_field_operator(key: &str) -> bool {
+    key.starts_with('$') && !FIELD_OPERATORS.contains(&key)
+}
+
+/// Test if the query can be compiled by the VM,
+/// so it can be pushed down to the collection scan.
+pub(crate) fn is_scan_query(query: &Document) -> bool {
+    query.iter().all(|(key, value)| match (key.as_str(), value) {
+        ("$and" | "$or", Bson::Array(arr)) => arr.iter().all(|item| match item {
+            Bson::Document(sub_query) => is_scan_query(sub_query),
+            _ => false,
+        }),
+        _ if key.starts_with('$') => false,
+        (_, Bson::Document(ops)) => {
+            !ops.is_empty() && ops.keys().all(|op| SCAN_OPERATORS.contains(&op.as_str()))
+        }
+        _ => true,
+    })
+}
+
+/// Test if the document satisfies the query, the `$expr` in the query
+/// is evaluated with the variables.
+pub(crate) fn matches(query: &Document, doc: &Document, vars: &Variables) -> DbResult<bool> {
+    for (key, value) in query.iter() {
+        let result = match key.as_str() {
+            "$and" => {
+                let mut result = true;
+                for sub_query in sub_queries(key, value)? {
+                    if !matches(sub_query, doc, vars)? {
+                        result = false;
+                        break;
+                    }
+                }
+                result
+            }
+            "$or" => {
+                let mut result = false;
+                for sub_query in sub_queries(key, value)? {
+                    if matches(sub_query, doc, vars)? {
+                        result = true;
+                        break;
+                    }
+                }
+                result
+            }
+            "$nor" => {
+                let mut result = true;
+                for sub_query in sub_queries(key, value)? {
+                    if matches(sub_query, doc, vars)? {
+                        result = false;
+                        break;
+                    }
+                }
+                result
+            }
+            "$expr" => is_truthy(&evaluate(value, doc, vars)?),
+            _ if is_unknown_logical_operator(key) => match vars.operator_mode() {
+                OperatorMode::Strict => return Err(DbErr::UnknownQueryOperator(key.clone())),
+                OperatorMode::Lax => equals(doc.get(key), value),
+            },
+            _ if key.starts_with('$') => {
+                return Err(DbErr::UnknownAggregationOperation(key.clone()));
+            }
+            _ => {
+                let field_value = resolve_path(doc, key);
+                match_field(key, field_value.as_ref(), value, vars.operator_mode())?
+            }
+        };
+        if !result {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn sub_queries<'a>(op: &str, value: &'a Bson) -> DbResult<Vec<&'a Document>> {
+    let arr = match value {
+        Bson::Array(arr) => arr,
+        t => return Err(mk_field_name_type_unexpected(op.into(), "Array".into(), format!("{}", t))),
+    };
+    let mut result = Vec::with_capacity(arr.len());
+    for item in arr {
+        match item {
+            Bson::Document(doc) => result.push(doc),
+            t => return Err(mk_field_name_type_unexpected(op.into(), "Document".into(), format!("{}", t))),
+        }
+    }
+    Ok(result)
+}
+
+fn is_operator_doc(value: &Bson) -> bool {
+    match value {
+        Bson::Document(doc) => doc.keys().next().map(|key| key.starts_with('$')).unwrap_or(false),
+        _ => false,
+    }
+}
+
+fn match_field(key: &str, field_value: Option<&Bson>, condition: &Bson, mode: OperatorMode) -> DbResult<bool> {
+    if !is_operator_doc(condition) {
+        return Ok(equals(field_value, condition));
+    }
+
+    let ops = condition.as_document().unwrap();
+    if let Some(op) = ops.keys().find(|op| is_unknown_field_operator(op)) {
+        return match mode {
+            OperatorMode::Strict => Err(DbErr::UnknownQueryOperator(op.clone())),
+            OperatorMode::Lax => Ok(equals(field_value, condition)),
+        };
+    }
+    for (op, operand) in ops.iter() {
+        let result = match op.as_str() {
+            "$eq" => equals(field_value, operand),
+            "$ne" => !equals(field_value, operand),
+            "$gt" => compare(field_value, operand, |ord| ord == Ordering::Greater),
+            "$gte" => compare(field_value, operand, |ord| ord != Ordering::Less),
+            "$lt" => compare(field_value, operand, |ord| ord == Ordering::Less),
+            "$lte" => compare(field_value, operand, |ord| ord != Ordering::Greater),
+            "$in" => {
+                let arr = crate::try_unwrap_array!("$in", operand);
+                arr.iter().any(|item| equals(field_value, item))
+            }
+            "$nin" => {
+                let arr = crate::try_unwrap_array!("$nin", operand);
+                !arr.iter().any(|item| equals(field_value, item))
+            }
+            "$exists" => field_value.is_some() == is_truthy(operand),
+            "$size" => match field_value {
+                Some(Bson::Array(arr)) => values_equal(&Bson::Int64(arr.len() as i64), operand),
+                _ => false,
+            },
+            "$not" => !match_field(key, field_value, operand, mode)?,
+            _ => return Err(DbErr::UnknownAggregationOperation(op.clone())),
+        };
+        if !result {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// An array field matches if any of the elements matches.
+fn equals(field_value: Option<&Bson>, expected: &Bson) -> bool {
+    match field_value {
+        None => matches!(expected, Bson::Null),
+        Some(value) => {
+            if values_equal(value, expected) {
+                return true;
+            }
+            match value {
+                Bson::Array(arr) => arr.iter().any(|item| values_equal(item, expected)),
+                _ => false,
+            }
+        }
+    }
+}
+
+fn compare<F>(field_value: Option<&Bson>, operand: &Bson, test: F) -> bool
+where
+    F: Fn(Ordering) -> bool,
+{
+    let is_comparable = |value: &Bson| {
+        value.element_type() == operand.element_type() || (is_number(value) && is_number(operand))
+    };
+    match field_value {
+        None => false,
+        Some(Bson::Array(arr)) => arr.iter().any(|item| {
+            is_comparable(item) && compare_values(item, operand).map(&test).unwrap_or(false)
+        }),
+        Some(value) => {
+            is_comparable(value) && compare_values(value, operand).map(&test).unwrap_or(false)
+        }
+    }
+}
+
+fn is_number(value: &Bson) -> bool {
+    matches!(value, Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use crate::{DbErr, OperatorMode};
+    use super::{is_scan_query, matches, Variables};
+
+    #[test]
+    fn test_matches() {
+        let doc = doc! {
+            "name": "Vincent",
+            "age": 30,
+            "tags": ["a", "b"],
+        };
+        let vars = Variables::new();
+
+        assert!(matches(&doc! { "name": "Vincent" }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "tags": "b" }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "age": { "$gt": 20, "$lt": 40 } }, &doc, &vars).unwrap());
+        assert!(!matches(&doc! { "age": { "$gt": "20" } }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "$or": [{ "age": 1 }, { "missing": { "$exists": false } }] }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "$expr": { "$eq": ["$age", 30] } }, &doc, &vars).unwrap());
+    }
+
+    #[test]
+    fn test_matches_unknown_operators() {
+        let doc = doc! {
+            "$tag": "a",
+            "range": { "$min": 1 },
+        };
+        let mut vars = Variables::new();
+
+        assert!(matches!(
+            matches(&doc! { "range": { "$min": 1 } }, &doc, &vars),
+            Err(DbErr::UnknownQueryOperator(op)) if op == "$min",
+        ));
+        assert!(matches(&doc! { "$tag": "a" }, &doc, &vars).is_err());
+
+        vars.set_operator_mode(OperatorMode::Lax);
+        assert!(matches(&doc! { "range": { "$min": 1 } }, &doc, &vars).unwrap());
+        assert!(!matches(&doc! { "range": { "$min": 2 } }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "$tag": "a" }, &doc, &vars).unwrap());
+        assert!(matches(&doc! { "range": { "$exists": true } }, &doc, &vars).unwrap());
+    }
+
+    #[test]
+    fn test_is_scan_query() {
+        assert!(is_scan_query(&doc! { "name": "Vincent", "age": { "$gt": 1 } }));
+        assert!(!is_scan_query(&doc! { "$expr": { "$eq": ["$a", 1] } }));
+        assert!(!is_scan_query(&doc! { "age": { "$exists": true } }));
+    }
+
+}
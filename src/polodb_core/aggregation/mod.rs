@@ -0,0 +1,344 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+mod accumulator;
+mod convert;
+mod custom;
+mod densify;
+mod expression;
+mod fill;
+mod group;
+mod matcher;
+mod hash_join;
+mod lookup;
+mod operators;
+mod project;
+mod sort;
+mod union;
+mod window;
+
+use bson::{Bson, Document};
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use hashbrown::HashMap;
+use crate::{Database, DbErr, DbResult, FindOptions, OperatorMode};
+use crate::collection_info::GeneratedField;
+use crate::error::mk_field_name_type_unexpected;
+use densify::DensifyStage;
+use expression::{evaluate, Functions, Variables};
+use fill::FillStage;
+use group::GroupStage;
+use hash_join::{join_key, JoinKey};
+use lookup::LookupStage;
+use matcher::matches;
+use project::ProjectStage;
+use sort::SortStage;
+use union::UnionWithStage;
+use window::SetWindowFieldsStage;
+
+pub use custom::CustomAccumulator;
+pub use expression::ExprFunction;
+pub(crate) use expression::resolve_path;
+pub(crate) use matcher::{is_unknown_field_operator, is_unknown_logical_operator};
+pub(crate) use sort::SortSpec;
+
+/// The environment of a running pipeline, which
+/// reads the collections of the database.
+pub(crate) struct AggregationContext<'a> {
+    db:         &'a Database,
+    session_id: Option<&'a ObjectId>,
+}
+
+impl<'a> AggregationContext<'a> {
+
+    pub(crate) fn new(db: &'a Database, session_id: Option<&'a ObjectId>) -> AggregationContext<'a> {
+        AggregationContext {
+            db,
+            session_id,
+        }
+    }
+
+    /// Read the documents of the collection satisfying the filter.
+    ///
+    /// The collections of the attached databases are not read by the session,
+    /// they are separated files without a shared snapshot.
+    pub(crate) fn find(&self, ns: &str, filter: Option<Document>) -> DbResult<Vec<Document>> {
+        let session_id = if self.db.resolve_attached(ns).is_some() {
+            None
+        } else {
+            self.session_id
+        };
+        self.db.find_many(ns, filter, FindOptions::default(), session_id)
+    }
+
+    pub(crate) fn join_memory_limit(&self) -> DbResult<usize> {
+        Ok(self.db.config()?.join_memory_limit)
+    }
+
+    /// The variables of the pipeline, with the functions registered on the database.
+    pub(crate) fn root_variables(&self) -> DbResult<Variables> {
+        let mut vars = Variables::with_functions(Arc::new(self.db.functions()));
+        vars.set_operator_mode(self.db.config()?.operator_mode);
+        Ok(vars)
+    }
+
+    pub(crate) fn custom_accumulator(&self, name: &str) -> DbResult<Arc<dyn CustomAccumulator>> {
+        self.db.custom_accumulator(name)
+            .ok_or_else(|| DbErr::UnknownAggregationOperation(format!("$accumulator: {}", name)))
+    }
+
+}
+
+pub(crate) trait Stage {
+    fn execute(&self, ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>>;
+}
+
+struct MatchStage {
+    query: Document,
+}
+
+impl Stage for MatchStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut result = Vec::with_capacity(input.len());
+        for doc in input {
+            if matches(&self.query, &doc, vars)? {
+                result.push(doc);
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+/// `$skip` skips the first documents, `$limit` keeps the first documents.
+struct SliceStage {
+    skip:  usize,
+    limit: Option<usize>,
+}
+
+impl SliceStage {
+
+    fn parse(op_name: &str, value: &Bson) -> DbResult<SliceStage> {
+        let count = match value {
+            Bson::Int32(n) if *n >= 0 => *n as usize,
+            Bson::Int64(n) if *n >= 0 => *n as usize,
+            Bson::Double(f) if *f >= 0.0 && f.fract() == 0.0 => *f as usize,
+            _ => {
+                return Err(DbErr::ValidationError(
+                    format!("{} should be a non-negative integer", op_name)
+                ));
+            }
+        };
+        let stage = if op_name == "$skip" {
+            SliceStage { skip: count, limit: None }
+        } else {
+            SliceStage { skip: 0, limit: Some(count) }
+        };
+        Ok(stage)
+    }
+
+}
+
+impl Stage for SliceStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, _vars: &Variables) -> DbResult<Vec<Document>> {
+        let docs = input.into_iter().skip(self.skip);
+        let result = match self.limit {
+            Some(limit) => docs.take(limit).collect(),
+            None => docs.collect(),
+        };
+        Ok(result)
+    }
+
+}
+
+/// Group the documents by the value of the expression, in the order of appearance.
+pub(crate) fn partition(partition_by: Option<&Bson>, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Vec<Document>>> {
+    let partition_by = match partition_by {
+        Some(partition_by) => partition_by,
+        None => return Ok(vec![input]),
+    };
+    let mut partitions: Vec<Vec<Document>> = vec![];
+    let mut index_of: HashMap<JoinKey, usize> = HashMap::new();
+    for doc in input {
+        let key = join_key(&evaluate(partition_by, &doc, vars)?);
+        let index = *index_of.entry(key).or_insert_with(|| {
+            partitions.push(vec![]);
+            partitions.len() - 1
+        });
+        partitions[index].push(doc);
+    }
+    Ok(partitions)
+}
+
+fn parse_stage(stage: &Document) -> DbResult<Box<dyn Stage>> {
+    if stage.len() != 1 {
+        return Err(DbErr::ValidationError("a pipeline stage should have exactly one field".into()));
+    }
+    let (name, value) = stage.iter().next().unwrap();
+    let result: Box<dyn Stage> = match name.as_str() {
+        "$match" => {
+            let query = crate::try_unwrap_document!("$match", value);
+            Box::new(MatchStage {
+                query: query.clone(),
+            })
+        }
+        "$group" => {
+            let spec = crate::try_unwrap_document!("$group", value);
+            Box::new(GroupStage::parse(spec)?)
+        }
+        "$project" => {
+            let spec = crate::try_unwrap_document!("$project", value);
+            Box::new(ProjectStage::parse(spec)?)
+        }
+        "$sort" => {
+            let spec = crate::try_unwrap_document!("$sort", value);
+            Box::new(SortStage::parse(spec)?)
+        }
+        "$skip" | "$limit" => Box::new(SliceStage::parse(name, value)?),
+        "$lookup" => {
+            let spec = crate::try_unwrap_document!("$lookup", value);
+            Box::new(LookupStage::parse(spec)?)
+        }
+        "$unionWith" => Box::new(UnionWithStage::parse(value)?),
+        "$setWindowFields" => {
+            let spec = crate::try_unwrap_document!("$setWindowFields", value);
+            Box::new(SetWindowFieldsStage::parse(spec)?)
+        }
+        "$densify" => {
+            let spec = crate::try_unwrap_document!("$densify", value);
+            Box::new(DensifyStage::parse(spec)?)
+        }
+        "$fill" => {
+            let spec = crate::try_unwrap_document!("$fill", value);
+            Box::new(FillStage::parse(spec)?)
+        }
+        _ => return Err(DbErr::UnknownAggregationOperation(name.clone())),
+    };
+    Ok(result)
+}
+
+/// A parsed aggregation pipeline.
+pub(crate) struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+
+    pub(crate) fn parse(pipeline: &[Document]) -> DbResult<Pipeline> {
+        let mut stages = Vec::with_capacity(pipeline.len());
+        for stage in pipeline {
+            stages.push(parse_stage(stage)?);
+        }
+        Ok(Pipeline { stages })
+    }
+
+    pub(crate) fn parse_bson(op_name: &str, pipeline: &Bson) -> DbResult<Pipeline> {
+        let arr = crate::try_unwrap_array!(op_name, pipeline);
+        let mut stages = Vec::with_capacity(arr.len());
+        for item in arr {
+            let stage = crate::try_unwrap_document!(op_name, item);
+            stages.push(parse_stage(stage)?);
+        }
+        Ok(Pipeline { stages })
+    }
+
+    pub(crate) fn execute(&self, ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut docs = input;
+        for stage in &self.stages {
+            docs = stage.execute(ctx, docs, vars)?;
+        }
+        Ok(docs)
+    }
+
+}
+
+/// Set the generated fields of the document in the order of them,
+/// so an expression can read the fields generated before it.
+pub(crate) fn set_generated_fields(doc: &mut Document, fields: &[GeneratedField]) -> DbResult<()> {
+    let vars = Variables::new();
+    for generated in fields {
+        let value = evaluate(&generated.expression, doc, &vars)?;
+        doc.insert(generated.field.clone(), value);
+    }
+    Ok(())
+}
+
+/// Test if the query has an `$expr`, which can't be
+/// executed by the VM, but by the evaluator.
+pub(crate) fn has_expr(query: &Document) -> bool {
+    query.iter().any(|(key, value)| match (key.as_str(), value) {
+        ("$expr", _) => true,
+        ("$and" | "$or" | "$nor", Bson::Array(arr)) => arr.iter().any(|item| match item {
+            Bson::Document(sub_query) => has_expr(sub_query),
+            _ => false,
+        }),
+        _ => false,
+    })
+}
+
+/// Filter the documents by the query with the registered functions.
+pub(crate) fn filter_documents(
+    query: &Document,
+    docs: Vec<Document>,
+    functions: Functions,
+    operator_mode: OperatorMode,
+) -> DbResult<Vec<Document>> {
+    let mut vars = Variables::with_functions(Arc::new(functions));
+    vars.set_operator_mode(operator_mode);
+    let mut result = Vec::with_capacity(docs.len());
+    for doc in docs {
+        if matches(query, &doc, &vars)? {
+            result.push(doc);
+        }
+    }
+    Ok(result)
+}
+
+/// A query matched against the documents one by one,
+/// with the functions registered when it's created.
+pub(crate) struct DocumentFilter {
+    query: Document,
+    vars:  Variables,
+}
+
+impl DocumentFilter {
+
+    pub(crate) fn new(query: Document, functions: Functions, operator_mode: OperatorMode) -> DocumentFilter {
+        let mut vars = Variables::with_functions(Arc::new(functions));
+        vars.set_operator_mode(operator_mode);
+        DocumentFilter {
+            query,
+            vars,
+        }
+    }
+
+    pub(crate) fn matches(&self, doc: &Document) -> DbResult<bool> {
+        matches(&self.query, doc, &self.vars)
+    }
+
+}
+
+/// Run the pipeline on the collection.
+///
+/// The leading `$match` is executed by the collection scan
+/// if the VM supports the query.
+pub(crate) fn aggregate(ctx: &AggregationContext, ns: &str, pipeline: &[Document]) -> DbResult<Vec<Document>> {
+    let mut filter = None;
+    let mut rest = pipeline;
+    if let Some(first) = pipeline.first() {
+        if let Ok(query) = first.get_document("$match") {
+            if first.len() == 1 && matcher::is_scan_query(query) {
+                filter = Some(query.clone());
+                rest = &pipeline[1..];
+            }
+        }
+    }
+
+    let pipeline = Pipeline::parse(rest)?;
+    let input = ctx.find(ns, filter)?;
+    pipeline.execute(ctx, input, &ctx.root_variables()?)
+}
@@ -0,0 +1,509 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+//! The arithmetic, string and array operators of the expressions.
+use bson::{Bson, Document, DateTime};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::expression::{evaluate, is_truthy, values_equal, Variables};
+
+fn is_nullish(value: &Bson) -> bool {
+    matches!(value, Bson::Null | Bson::Undefined)
+}
+
+fn type_error(op: &str, expected: &str, actual: &Bson) -> DbErr {
+    mk_field_name_type_unexpected(op.into(), expected.into(), format!("{}", actual))
+}
+
+fn args_of<'a>(op: &str, value: &'a Bson, min: usize, max: usize) -> DbResult<Vec<&'a Bson>> {
+    let args: Vec<&Bson> = match value {
+        Bson::Array(arr) => arr.iter().collect(),
+        _ => vec![value],
+    };
+    if args.len() < min || args.len() > max {
+        return Err(DbErr::ValidationError(
+            format!("{} takes {} to {} arguments, got {}", op, min, max, args.len())
+        ));
+    }
+    Ok(args)
+}
+
+fn evaluate_args(op: &str, value: &Bson, min: usize, max: usize, doc: &Document, vars: &Variables) -> DbResult<Vec<Bson>> {
+    let mut result = vec![];
+    for arg in args_of(op, value, min, max)? {
+        result.push(evaluate(arg, doc, vars)?);
+    }
+    Ok(result)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Number {
+    Int(i64, bool),
+    Float(f64),
+}
+
+impl Number {
+
+    fn of(op: &str, value: &Bson) -> DbResult<Number> {
+        match value {
+            Bson::Int32(i) => Ok(Number::Int(*i as i64, true)),
+            Bson::Int64(i) => Ok(Number::Int(*i, false)),
+            Bson::Double(f) => Ok(Number::Float(*f)),
+            _ => Err(type_error(op, "Number", value)),
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match self {
+            Number::Int(i, _) => *i as f64,
+            Number::Float(f) => *f,
+        }
+    }
+
+    // Integers keep the narrowest type that holds the value.
+    fn into_bson(self) -> Bson {
+        match self {
+            Number::Int(i, true) if i >= i32::MIN as i64 && i <= i32::MAX as i64 => Bson::Int32(i as i32),
+            Number::Int(i, _) => Bson::Int64(i),
+            Number::Float(f) => Bson::Double(f),
+        }
+    }
+
+    fn combine(
+        self,
+        other: Number,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Number {
+        match (self, other) {
+            (Number::Int(a, a32), Number::Int(b, b32)) => match int_op(a, b) {
+                Some(result) => Number::Int(result, a32 && b32),
+                None => Number::Float(float_op(a as f64, b as f64)),
+            },
+            _ => Number::Float(float_op(self.as_f64(), other.as_f64())),
+        }
+    }
+
+}
+
+pub(super) fn add(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$add", value, 0, usize::MAX, doc, vars)?;
+    if args.iter().any(is_nullish) {
+        return Ok(Bson::Null);
+    }
+    let mut date: Option<i64> = None;
+    let mut sum = Number::Int(0, true);
+    for arg in &args {
+        if let Bson::DateTime(dt) = arg {
+            if date.is_some() {
+                return Err(DbErr::ValidationError("$add only supports one date".into()));
+            }
+            date = Some(dt.timestamp_millis());
+            continue;
+        }
+        sum = sum.combine(Number::of("$add", arg)?, i64::checked_add, |a, b| a + b);
+    }
+    match date {
+        Some(millis) => Ok(Bson::DateTime(DateTime::from_millis(millis + sum.as_f64().round() as i64))),
+        None => Ok(sum.into_bson()),
+    }
+}
+
+pub(super) fn multiply(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$multiply", value, 0, usize::MAX, doc, vars)?;
+    if args.iter().any(is_nullish) {
+        return Ok(Bson::Null);
+    }
+    let mut product = Number::Int(1, true);
+    for arg in &args {
+        product = product.combine(Number::of("$multiply", arg)?, i64::checked_mul, |a, b| a * b);
+    }
+    Ok(product.into_bson())
+}
+
+pub(super) fn subtract(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$subtract", value, 2, 2, doc, vars)?;
+    let result = match (&args[0], &args[1]) {
+        (a, b) if is_nullish(a) || is_nullish(b) => Bson::Null,
+        (Bson::DateTime(a), Bson::DateTime(b)) => Bson::Int64(a.timestamp_millis() - b.timestamp_millis()),
+        (Bson::DateTime(a), b) => {
+            let millis = Number::of("$subtract", b)?.as_f64().round() as i64;
+            Bson::DateTime(DateTime::from_millis(a.timestamp_millis() - millis))
+        }
+        (a, b) => Number::of("$subtract", a)?
+            .combine(Number::of("$subtract", b)?, i64::checked_sub, |a, b| a - b)
+            .into_bson(),
+    };
+    Ok(result)
+}
+
+pub(super) fn divide(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$divide", value, 2, 2, doc, vars)?;
+    if is_nullish(&args[0]) || is_nullish(&args[1]) {
+        return Ok(Bson::Null);
+    }
+    let a = Number::of("$divide", &args[0])?.as_f64();
+    let b = Number::of("$divide", &args[1])?.as_f64();
+    if b == 0.0 {
+        return Err(DbErr::ValidationError("$divide by zero".into()));
+    }
+    Ok(Bson::Double(a / b))
+}
+
+pub(super) fn modulo(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$mod", value, 2, 2, doc, vars)?;
+    if is_nullish(&args[0]) || is_nullish(&args[1]) {
+        return Ok(Bson::Null);
+    }
+    let a = Number::of("$mod", &args[0])?;
+    let b = Number::of("$mod", &args[1])?;
+    if b.as_f64() == 0.0 {
+        return Err(DbErr::ValidationError("$mod by zero".into()));
+    }
+    Ok(a.combine(b, i64::checked_rem, |a, b| a % b).into_bson())
+}
+
+pub(super) fn abs(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$abs", value, 1, 1, doc, vars)?;
+    let result = match &args[0] {
+        arg if is_nullish(arg) => Bson::Null,
+        arg => match Number::of("$abs", arg)? {
+            Number::Int(i, is_int32) => match i.checked_abs() {
+                Some(i) => Number::Int(i, is_int32).into_bson(),
+                None => Bson::Double((i as f64).abs()),
+            },
+            Number::Float(f) => Bson::Double(f.abs()),
+        },
+    };
+    Ok(result)
+}
+
+// Round half to even, as the banker's rounding.
+fn round_half_even(f: f64) -> f64 {
+    let rounded = f.round();
+    if (f - f.trunc()).abs() == 0.5 {
+        2.0 * (f / 2.0).round()
+    } else {
+        rounded
+    }
+}
+
+/// `$round`, `$floor` and `$ceil`, the integers are kept if no digit is dropped.
+pub(super) fn round(op: &str, value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let max_args = if op == "$round" { 2 } else { 1 };
+    let args = evaluate_args(op, value, 1, max_args, doc, vars)?;
+    if args.iter().any(is_nullish) {
+        return Ok(Bson::Null);
+    }
+    let place = match args.get(1) {
+        Some(place) => match Number::of(op, place)? {
+            Number::Int(i, _) if (-20..=100).contains(&i) => i as i32,
+            _ => return Err(DbErr::ValidationError(format!("{}: place should be an integer in [-20, 100]", op))),
+        },
+        None => 0,
+    };
+
+    let number = Number::of(op, &args[0])?;
+    if let Number::Int(_, _) = number {
+        if place >= 0 {
+            return Ok(number.into_bson());
+        }
+    }
+
+    let round_fn: fn(f64) -> f64 = match op {
+        "$floor" => f64::floor,
+        "$ceil" => f64::ceil,
+        _ => round_half_even,
+    };
+    // divide by the power of ten for the negative place to keep the result exact
+    let rounded = if place >= 0 {
+        let factor = 10f64.powi(place);
+        round_fn(number.as_f64() * factor) / factor
+    } else {
+        let factor = 10f64.powi(-place);
+        round_fn(number.as_f64() / factor) * factor
+    };
+
+    let result = match number {
+        Number::Int(_, is_int32) => Number::Int(rounded as i64, is_int32).into_bson(),
+        Number::Float(_) => Bson::Double(rounded),
+    };
+    Ok(result)
+}
+
+fn array_arg<'a>(op: &str, value: &'a Bson) -> DbResult<Option<&'a bson::Array>> {
+    match value {
+        Bson::Array(arr) => Ok(Some(arr)),
+        _ if is_nullish(value) => Ok(None),
+        _ => Err(type_error(op, "Array", value)),
+    }
+}
+
+fn index_arg(op: &str, value: &Bson) -> DbResult<i64> {
+    match Number::of(op, value)? {
+        Number::Int(i, _) => Ok(i),
+        Number::Float(f) if f.fract() == 0.0 => Ok(f as i64),
+        Number::Float(_) => Err(type_error(op, "Integer", value)),
+    }
+}
+
+pub(super) fn array_elem_at(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$arrayElemAt", value, 2, 2, doc, vars)?;
+    let arr = match array_arg("$arrayElemAt", &args[0])? {
+        Some(arr) => arr,
+        None => return Ok(Bson::Null),
+    };
+    let index = index_arg("$arrayElemAt", &args[1])?;
+    let index = if index < 0 { arr.len() as i64 + index } else { index };
+    if index < 0 || index >= arr.len() as i64 {
+        return Ok(Bson::Null);
+    }
+    Ok(arr[index as usize].clone())
+}
+
+pub(super) fn size(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$size", value, 1, 1, doc, vars)?;
+    match &args[0] {
+        Bson::Array(arr) => Ok(Bson::Int32(arr.len() as i32)),
+        arg => Err(type_error("$size", "Array", arg)),
+    }
+}
+
+pub(super) fn slice(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$slice", value, 2, 3, doc, vars)?;
+    let arr = match array_arg("$slice", &args[0])? {
+        Some(arr) => arr,
+        None => return Ok(Bson::Null),
+    };
+    let len = arr.len() as i64;
+    let (start, count) = if args.len() == 2 {
+        let n = index_arg("$slice", &args[1])?;
+        if n >= 0 {
+            (0, n)
+        } else {
+            ((len + n).max(0), -n)
+        }
+    } else {
+        let position = index_arg("$slice", &args[1])?;
+        let n = index_arg("$slice", &args[2])?;
+        if n <= 0 {
+            return Err(DbErr::ValidationError("$slice: n should be positive".into()));
+        }
+        let start = if position < 0 { (len + position).max(0) } else { position.min(len) };
+        (start, n)
+    };
+    let end = (start + count).min(len);
+    Ok(Bson::Array(arr[start as usize..end as usize].to_vec()))
+}
+
+pub(super) fn in_array(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$in", value, 2, 2, doc, vars)?;
+    match &args[1] {
+        Bson::Array(arr) => Ok(Bson::Boolean(arr.iter().any(|item| values_equal(item, &args[0])))),
+        arg => Err(type_error("$in", "Array", arg)),
+    }
+}
+
+// the numbers are printed, a null is an empty string
+fn string_of(op: &str, value: &Bson) -> DbResult<String> {
+    match value {
+        Bson::String(s) => Ok(s.clone()),
+        Bson::Int32(i) => Ok(i.to_string()),
+        Bson::Int64(i) => Ok(i.to_string()),
+        Bson::Double(f) => Ok(f.to_string()),
+        value if is_nullish(value) => Ok(String::new()),
+        _ => Err(type_error(op, "String", value)),
+    }
+}
+
+/// `$toLower` and `$toUpper`.
+pub(super) fn change_case(op: &str, value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args(op, value, 1, 1, doc, vars)?;
+    let s = string_of(op, &args[0])?;
+    let result = if op == "$toLower" {
+        s.to_lowercase()
+    } else {
+        s.to_uppercase()
+    };
+    Ok(Bson::String(result))
+}
+
+/// `{ "$concat": [<expression>, ...] }` is null if any of the strings is null.
+pub(super) fn concat(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let args = evaluate_args("$concat", value, 0, usize::MAX, doc, vars)?;
+    let mut result = String::new();
+    for arg in &args {
+        match arg {
+            Bson::String(s) => result.push_str(s),
+            arg if is_nullish(arg) => return Ok(Bson::Null),
+            arg => return Err(type_error("$concat", "String", arg)),
+        }
+    }
+    Ok(Bson::String(result))
+}
+
+/// `{ "$trim": { "input": <expression> } }` removes the whitespaces at both ends.
+pub(super) fn trim(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = spec_of("$trim", value)?;
+    let input = evaluate(required("$trim", spec, "input")?, doc, vars)?;
+    match input {
+        Bson::String(s) => Ok(Bson::String(s.trim().to_string())),
+        input if is_nullish(&input) => Ok(Bson::Null),
+        input => Err(type_error("$trim", "String", &input)),
+    }
+}
+
+fn spec_of<'a>(op: &str, value: &'a Bson) -> DbResult<&'a Document> {
+    match value {
+        Bson::Document(spec) => Ok(spec),
+        _ => Err(type_error(op, "Document", value)),
+    }
+}
+
+fn required<'a>(op: &str, spec: &'a Document, key: &str) -> DbResult<&'a Bson> {
+    spec.get(key).ok_or_else(|| DbErr::ValidationError(format!("{} requires '{}'", op, key)))
+}
+
+fn variable_name(op: &str, spec: &Document) -> DbResult<String> {
+    match spec.get("as") {
+        Some(Bson::String(name)) => Ok(name.clone()),
+        Some(t) => Err(type_error(op, "String", t)),
+        None => Ok("this".into()),
+    }
+}
+
+pub(super) fn filter(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = spec_of("$filter", value)?;
+    let input = evaluate(required("$filter", spec, "input")?, doc, vars)?;
+    let cond = required("$filter", spec, "cond")?;
+    let name = variable_name("$filter", spec)?;
+    let limit = match spec.get("limit") {
+        Some(limit) => Some(index_arg("$filter", &evaluate(limit, doc, vars)?)?.max(0) as usize),
+        None => None,
+    };
+    let arr = match array_arg("$filter", &input)? {
+        Some(arr) => arr,
+        None => return Ok(Bson::Null),
+    };
+
+    let mut item_vars = vars.clone();
+    let mut result = bson::Array::new();
+    for item in arr {
+        if limit.map(|limit| result.len() >= limit).unwrap_or(false) {
+            break;
+        }
+        item_vars.insert(name.clone(), item.clone());
+        if is_truthy(&evaluate(cond, doc, &item_vars)?) {
+            result.push(item.clone());
+        }
+    }
+    Ok(Bson::Array(result))
+}
+
+pub(super) fn map(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = spec_of("$map", value)?;
+    let input = evaluate(required("$map", spec, "input")?, doc, vars)?;
+    let expr = required("$map", spec, "in")?;
+    let name = variable_name("$map", spec)?;
+    let arr = match array_arg("$map", &input)? {
+        Some(arr) => arr,
+        None => return Ok(Bson::Null),
+    };
+
+    let mut item_vars = vars.clone();
+    let mut result = bson::Array::with_capacity(arr.len());
+    for item in arr {
+        item_vars.insert(name.clone(), item.clone());
+        result.push(evaluate(expr, doc, &item_vars)?);
+    }
+    Ok(Bson::Array(result))
+}
+
+pub(super) fn reduce(value: &Bson, doc: &Document, vars: &Variables) -> DbResult<Bson> {
+    let spec = spec_of("$reduce", value)?;
+    let input = evaluate(required("$reduce", spec, "input")?, doc, vars)?;
+    let initial_value = evaluate(required("$reduce", spec, "initialValue")?, doc, vars)?;
+    let expr = required("$reduce", spec, "in")?;
+    let arr = match array_arg("$reduce", &input)? {
+        Some(arr) => arr,
+        None => return Ok(Bson::Null),
+    };
+
+    let mut item_vars = vars.clone();
+    let mut accumulated = initial_value;
+    for item in arr {
+        item_vars.insert("value".into(), accumulated);
+        item_vars.insert("this".into(), item.clone());
+        accumulated = evaluate(expr, doc, &item_vars)?;
+    }
+    Ok(accumulated)
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, doc};
+    use crate::aggregation::expression::{evaluate, Variables};
+
+    fn eval(expr: Bson) -> Bson {
+        let doc = doc! { "a": 7, "b": 2, "f": 2.5, "arr": [1, 2, 3, 4] };
+        evaluate(&expr, &doc, &Variables::new()).unwrap()
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(eval(Bson::Document(doc! { "$add": ["$a", "$b", 1] })), Bson::Int32(10));
+        assert_eq!(eval(Bson::Document(doc! { "$subtract": ["$a", "$f"] })), Bson::Double(4.5));
+        assert_eq!(eval(Bson::Document(doc! { "$divide": ["$a", "$b"] })), Bson::Double(3.5));
+        assert_eq!(eval(Bson::Document(doc! { "$mod": ["$a", "$b"] })), Bson::Int32(1));
+        assert_eq!(eval(Bson::Document(doc! { "$abs": -3 })), Bson::Int32(3));
+        assert_eq!(eval(Bson::Document(doc! { "$round": ["$f", 0] })), Bson::Double(2.0));
+        assert_eq!(eval(Bson::Document(doc! { "$round": [1234, -2] })), Bson::Int32(1200));
+        assert_eq!(eval(Bson::Document(doc! { "$floor": "$f" })), Bson::Double(2.0));
+        assert_eq!(eval(Bson::Document(doc! { "$ceil": "$f" })), Bson::Double(3.0));
+        assert_eq!(eval(Bson::Document(doc! { "$add": ["$a", "$missing"] })), Bson::Null);
+    }
+
+    #[test]
+    fn test_array_operators() {
+        assert_eq!(eval(Bson::Document(doc! { "$arrayElemAt": ["$arr", -1] })), Bson::Int32(4));
+        assert_eq!(eval(Bson::Document(doc! { "$size": "$arr" })), Bson::Int32(4));
+        assert_eq!(eval(Bson::Document(doc! { "$slice": ["$arr", 1, 2] })), Bson::Array(vec![Bson::Int32(2), Bson::Int32(3)]));
+        assert_eq!(eval(Bson::Document(doc! { "$slice": ["$arr", -1] })), Bson::Array(vec![Bson::Int32(4)]));
+        assert_eq!(eval(Bson::Document(doc! { "$in": [3, "$arr"] })), Bson::Boolean(true));
+
+        let filtered = eval(Bson::Document(doc! {
+            "$filter": { "input": "$arr", "as": "n", "cond": { "$gt": ["$$n", 2] } },
+        }));
+        assert_eq!(filtered, Bson::Array(vec![Bson::Int32(3), Bson::Int32(4)]));
+
+        let mapped = eval(Bson::Document(doc! {
+            "$map": { "input": "$arr", "in": { "$multiply": ["$$this", 10] } },
+        }));
+        assert_eq!(mapped, Bson::Array(vec![Bson::Int32(10), Bson::Int32(20), Bson::Int32(30), Bson::Int32(40)]));
+
+        let reduced = eval(Bson::Document(doc! {
+            "$reduce": { "input": "$arr", "initialValue": 0, "in": { "$add": ["$$value", "$$this"] } },
+        }));
+        assert_eq!(reduced, Bson::Int32(10));
+    }
+
+    #[test]
+    fn test_string_operators() {
+        let doc = doc! { "email": "  Alice@Example.COM ", "first": "Alice", "last": "Liddell" };
+        let eval = |expr: Bson| evaluate(&expr, &doc, &Variables::new()).unwrap();
+        assert_eq!(
+            eval(Bson::Document(doc! { "$toLower": { "$trim": { "input": "$email" } } })),
+            Bson::String("alice@example.com".into()),
+        );
+        assert_eq!(eval(Bson::Document(doc! { "$toUpper": "$first" })), Bson::String("ALICE".into()));
+        assert_eq!(eval(Bson::Document(doc! { "$toLower": "$missing" })), Bson::String("".into()));
+        assert_eq!(
+            eval(Bson::Document(doc! { "$concat": ["$first", " ", "$last"] })),
+            Bson::String("Alice Liddell".into()),
+        );
+        assert_eq!(eval(Bson::Document(doc! { "$concat": ["$first", "$missing"] })), Bson::Null);
+    }
+
+}
@@ -0,0 +1,79 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document};
+use crate::{DbErr, DbResult};
+use crate::projection::Projection;
+use super::{AggregationContext, Stage};
+use super::expression::{evaluate, Variables};
+
+/// `$project` includes or excludes the fields as the projection of a find,
+/// and sets the computed fields, such as `{ "total": { "$multiply": ["$price", "$quantity"] } }`.
+///
+/// The computed fields are included, so they can't be mixed with the excluded fields
+/// other than `_id`. The expressions are evaluated on the input document.
+pub(super) struct ProjectStage {
+    projection: Projection,
+    computed:   Vec<(String, Bson)>,
+}
+
+// the flags of inclusion and exclusion, and the slices are not computed
+fn is_computed(value: &Bson) -> bool {
+    match value {
+        Bson::Boolean(_) | Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) => false,
+        Bson::Document(doc) => !doc.contains_key("$slice"),
+        _ => true,
+    }
+}
+
+impl ProjectStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<ProjectStage> {
+        if spec.is_empty() {
+            return Err(DbErr::ValidationError("$project requires at least one field".into()));
+        }
+        let mut fields = Document::new();
+        let mut computed = vec![];
+        for (key, value) in spec.iter() {
+            if is_computed(value) {
+                computed.push((key.clone(), value.clone()));
+            } else {
+                fields.insert(key.clone(), value.clone());
+            }
+        }
+        // the computed fields are included by the projection,
+        // and replaced by the values of the expressions
+        for (key, _) in &computed {
+            fields.insert(key.clone(), 1);
+        }
+        let projection = Projection::parse(&fields, None)?;
+        Ok(ProjectStage {
+            projection,
+            computed,
+        })
+    }
+
+}
+
+impl Stage for ProjectStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut result = Vec::with_capacity(input.len());
+        for doc in input {
+            let mut values = Vec::with_capacity(self.computed.len());
+            for (key, expr) in &self.computed {
+                values.push((key, evaluate(expr, &doc, vars)?));
+            }
+            let mut projected = self.projection.apply(doc);
+            for (key, value) in values {
+                projected.insert(key.clone(), value);
+            }
+            result.push(projected);
+        }
+        Ok(result)
+    }
+
+}
+
@@ -0,0 +1,98 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use bson::{Bson, Document};
+use crate::{DbErr, DbResult};
+use super::{AggregationContext, Stage};
+use super::expression::{compare_values, resolve_path, Variables};
+
+/// The sort order of the documents, such as `{ "score": -1, "name": 1 }`.
+#[derive(Debug, Clone)]
+pub(crate) struct SortSpec {
+    fields: Vec<(String, bool)>,
+}
+
+impl SortSpec {
+
+    pub(crate) fn parse(op_name: &str, spec: &Document) -> DbResult<SortSpec> {
+        if spec.is_empty() {
+            return Err(DbErr::ValidationError(format!("{} requires at least one field", op_name)));
+        }
+        let mut fields = Vec::with_capacity(spec.len());
+        for (key, value) in spec.iter() {
+            let ascending = match value {
+                Bson::Int32(1) | Bson::Int64(1) => true,
+                Bson::Int32(-1) | Bson::Int64(-1) => false,
+                Bson::Double(f) if *f == 1.0 => true,
+                Bson::Double(f) if *f == -1.0 => false,
+                _ => {
+                    return Err(DbErr::ValidationError(
+                        format!("{}: the order of '{}' should be 1 or -1", op_name, key)
+                    ));
+                }
+            };
+            fields.push((key.clone(), ascending));
+        }
+        Ok(SortSpec { fields })
+    }
+
+    /// The only field of the sort order, if there's one.
+    pub(crate) fn single_field(&self) -> Option<(&str, bool)> {
+        match self.fields.as_slice() {
+            [(field, ascending)] => Some((field.as_str(), *ascending)),
+            _ => None,
+        }
+    }
+
+    /// The fields of the sort order.
+    pub(crate) fn fields(&self) -> impl Iterator<Item = &str> {
+        self.fields.iter().map(|(field, _)| field.as_str())
+    }
+
+    pub(crate) fn compare(&self, a: &Document, b: &Document) -> Ordering {
+        for (field, ascending) in &self.fields {
+            let a_value = resolve_path(a, field).unwrap_or(Bson::Null);
+            let b_value = resolve_path(b, field).unwrap_or(Bson::Null);
+            let ord = compare_values(&a_value, &b_value).unwrap_or(Ordering::Equal);
+            let ord = if *ascending { ord } else { ord.reverse() };
+            if ord != Ordering::Equal {
+                return ord;
+            }
+        }
+        Ordering::Equal
+    }
+
+    /// Sort the documents, the order of equal documents is kept.
+    pub(crate) fn sort(&self, docs: &mut [Document]) {
+        docs.sort_by(|a, b| self.compare(a, b));
+    }
+
+}
+
+/// `$sort` sorts the documents, the order of equal documents is kept.
+pub(super) struct SortStage {
+    spec: SortSpec,
+}
+
+impl SortStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<SortStage> {
+        Ok(SortStage {
+            spec: SortSpec::parse("$sort", spec)?,
+        })
+    }
+
+}
+
+impl Stage for SortStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, _vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut docs = input;
+        self.spec.sort(&mut docs);
+        Ok(docs)
+    }
+
+}
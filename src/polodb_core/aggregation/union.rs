@@ -0,0 +1,62 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{AggregationContext, Pipeline, Stage};
+use super::expression::Variables;
+
+/// `$unionWith` appends the documents of another collection to the stream,
+/// which are processed by the sub-pipeline first if there's one.
+///
+/// ```json
+/// { "$unionWith": { "coll": "orders_archive", "pipeline": [{ "$match": { "year": 2022 } }] } }
+/// ```
+pub(super) struct UnionWithStage {
+    coll:     String,
+    pipeline: Option<Pipeline>,
+}
+
+impl UnionWithStage {
+
+    pub(super) fn parse(spec: &Bson) -> DbResult<UnionWithStage> {
+        let spec = match spec {
+            Bson::String(coll) => {
+                return Ok(UnionWithStage {
+                    coll: coll.clone(),
+                    pipeline: None,
+                });
+            }
+            Bson::Document(spec) => spec,
+            t => return Err(mk_field_name_type_unexpected("$unionWith".into(), "String".into(), format!("{}", t))),
+        };
+        let coll = match spec.get("coll") {
+            Some(Bson::String(coll)) => coll.clone(),
+            Some(t) => return Err(mk_field_name_type_unexpected("coll".into(), "String".into(), format!("{}", t))),
+            None => return Err(DbErr::ValidationError("$unionWith requires 'coll'".into())),
+        };
+        let pipeline = match spec.get("pipeline") {
+            Some(pipeline) => Some(Pipeline::parse_bson("pipeline", pipeline)?),
+            None => None,
+        };
+        Ok(UnionWithStage { coll, pipeline })
+    }
+
+}
+
+impl Stage for UnionWithStage {
+
+    fn execute(&self, ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut docs = ctx.find(&self.coll, None)?;
+        if let Some(pipeline) = &self.pipeline {
+            docs = pipeline.execute(ctx, docs, vars)?;
+        }
+        let mut result = input;
+        result.extend(docs);
+        Ok(result)
+    }
+
+}
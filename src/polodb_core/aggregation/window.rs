@@ -0,0 +1,348 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use bson::{Bson, Document};
+use crate::{DbErr, DbResult};
+use crate::error::mk_field_name_type_unexpected;
+use super::{partition, AggregationContext, Stage};
+use super::accumulator::{as_f64, Accumulator};
+use super::expression::{evaluate, resolve_path, Variables};
+use super::sort::SortSpec;
+
+#[derive(Debug, Clone, Copy)]
+enum Bound {
+    Unbounded,
+    Current,
+    Offset(f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum WindowUnit {
+    /// The positions relative to the current document.
+    Documents,
+    /// The values of the `sortBy` field relative to the current document.
+    Range,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    unit:  WindowUnit,
+    lower: Bound,
+    upper: Bound,
+}
+
+enum WindowFunction {
+    Rank,
+    DenseRank,
+    DocumentNumber,
+    Accumulate {
+        accumulator: Accumulator,
+        expr:        Bson,
+        window:      Option<Window>,
+    },
+}
+
+struct OutputField {
+    name:     String,
+    function: WindowFunction,
+}
+
+/// `$setWindowFields` computes the fields of every document
+/// from the documents around it in the same partition.
+pub(super) struct SetWindowFieldsStage {
+    partition_by: Option<Bson>,
+    sort_by:      Option<SortSpec>,
+    output:       Vec<OutputField>,
+}
+
+fn parse_bound(value: &Bson, unit: WindowUnit) -> DbResult<Bound> {
+    let bound = match value {
+        Bson::String(s) if s == "unbounded" => Bound::Unbounded,
+        Bson::String(s) if s == "current" => Bound::Current,
+        Bson::Int32(_) | Bson::Int64(_) => Bound::Offset(as_f64(value).unwrap()),
+        Bson::Double(f) if unit == WindowUnit::Range => Bound::Offset(*f),
+        _ => {
+            return Err(DbErr::ValidationError(
+                format!("invalid window bound: {}", value)
+            ));
+        }
+    };
+    Ok(bound)
+}
+
+fn parse_window(spec: &Document) -> DbResult<Window> {
+    let (unit, bounds) = match (spec.get("documents"), spec.get("range")) {
+        (Some(bounds), None) => (WindowUnit::Documents, bounds),
+        (None, Some(bounds)) => (WindowUnit::Range, bounds),
+        _ => {
+            return Err(DbErr::ValidationError(
+                "window requires exactly one of 'documents' and 'range'".into()
+            ));
+        }
+    };
+    let bounds = crate::try_unwrap_array!("window", bounds);
+    if bounds.len() != 2 {
+        return Err(DbErr::ValidationError("window bounds should be [lower, upper]".into()));
+    }
+    Ok(Window {
+        unit,
+        lower: parse_bound(&bounds[0], unit)?,
+        upper: parse_bound(&bounds[1], unit)?,
+    })
+}
+
+fn parse_function(name: &str, spec: &Document) -> DbResult<WindowFunction> {
+    let mut window = None;
+    let mut function = None;
+    for (key, value) in spec.iter() {
+        if key == "window" {
+            let window_spec = crate::try_unwrap_document!("window", value);
+            window = Some(parse_window(window_spec)?);
+            continue;
+        }
+        if function.is_some() {
+            return Err(DbErr::ValidationError(
+                format!("output field '{}' should have exactly one window function", name)
+            ));
+        }
+        function = Some(match key.as_str() {
+            "$rank" => WindowFunction::Rank,
+            "$denseRank" => WindowFunction::DenseRank,
+            "$documentNumber" => WindowFunction::DocumentNumber,
+            _ => match Accumulator::from_name(key) {
+                Some(accumulator) => WindowFunction::Accumulate {
+                    accumulator,
+                    expr: value.clone(),
+                    window: None,
+                },
+                None => return Err(DbErr::UnknownAggregationOperation(key.clone())),
+            },
+        });
+    }
+
+    match function {
+        Some(WindowFunction::Accumulate { accumulator, expr, .. }) => Ok(WindowFunction::Accumulate {
+            accumulator,
+            expr,
+            window,
+        }),
+        Some(_) if window.is_some() => Err(DbErr::ValidationError(
+            format!("output field '{}': rank functions don't accept a window", name)
+        )),
+        Some(function) => Ok(function),
+        None => Err(DbErr::ValidationError(
+            format!("output field '{}' requires a window function", name)
+        )),
+    }
+}
+
+impl SetWindowFieldsStage {
+
+    pub(super) fn parse(spec: &Document) -> DbResult<SetWindowFieldsStage> {
+        let partition_by = spec.get("partitionBy").cloned();
+        let sort_by = match spec.get("sortBy") {
+            Some(value) => {
+                let sort_doc = crate::try_unwrap_document!("sortBy", value);
+                Some(SortSpec::parse("sortBy", sort_doc)?)
+            }
+            None => None,
+        };
+
+        let output_doc = match spec.get("output") {
+            Some(value) => crate::try_unwrap_document!("output", value),
+            None => return Err(DbErr::ValidationError("$setWindowFields requires 'output'".into())),
+        };
+        let mut output = Vec::with_capacity(output_doc.len());
+        for (name, value) in output_doc.iter() {
+            let function_spec = crate::try_unwrap_document!("output", value);
+            let function = parse_function(name, function_spec)?;
+            match &function {
+                WindowFunction::Rank | WindowFunction::DenseRank | WindowFunction::DocumentNumber
+                    if sort_by.is_none() => {
+                    return Err(DbErr::ValidationError(
+                        format!("output field '{}': rank functions require 'sortBy'", name)
+                    ));
+                }
+                WindowFunction::Accumulate { window: Some(window), .. }
+                    if window.unit == WindowUnit::Range
+                        && sort_by.as_ref().and_then(|s| s.single_field()).is_none() => {
+                    return Err(DbErr::ValidationError(
+                        format!("output field '{}': range window requires exactly one 'sortBy' field", name)
+                    ));
+                }
+                _ => (),
+            }
+            output.push(OutputField {
+                name: name.clone(),
+                function,
+            });
+        }
+
+        Ok(SetWindowFieldsStage {
+            partition_by,
+            sort_by,
+            output,
+        })
+    }
+
+    fn compute(&self, function: &WindowFunction, docs: &[Document], vars: &Variables) -> DbResult<Vec<Bson>> {
+        let mut result = Vec::with_capacity(docs.len());
+        match function {
+            WindowFunction::DocumentNumber => {
+                for index in 0..docs.len() {
+                    result.push(Bson::Int64(index as i64 + 1));
+                }
+            }
+            WindowFunction::Rank | WindowFunction::DenseRank => {
+                let sort_by = self.sort_by.as_ref().unwrap();
+                let mut rank = 0;
+                let mut dense_rank = 0;
+                for (index, doc) in docs.iter().enumerate() {
+                    if index == 0 || sort_by.compare(&docs[index - 1], doc) != Ordering::Equal {
+                        rank = index + 1;
+                        dense_rank += 1;
+                    }
+                    let value = if matches!(function, WindowFunction::Rank) { rank } else { dense_rank };
+                    result.push(Bson::Int64(value as i64));
+                }
+            }
+            WindowFunction::Accumulate { accumulator, expr, window } => {
+                let mut values = Vec::with_capacity(docs.len());
+                for doc in docs {
+                    values.push(evaluate(expr, doc, vars)?);
+                }
+                let range_sort = match window {
+                    Some(window) if window.unit == WindowUnit::Range => {
+                        let (field, ascending) = self.sort_by.as_ref().unwrap().single_field().unwrap();
+                        Some((range_keys(docs, field)?, ascending))
+                    }
+                    _ => None,
+                };
+                for index in 0..docs.len() {
+                    let value = match window {
+                        None => accumulator.accumulate(&values),
+                        Some(window) if window.unit == WindowUnit::Documents => {
+                            let (start, end) = document_range(window, index, docs.len());
+                            accumulator.accumulate(&values[start..end])
+                        }
+                        Some(window) => {
+                            let (keys, ascending) = range_sort.as_ref().unwrap();
+                            let current = keys[index];
+                            let in_window = |key: f64| {
+                                let offset = if *ascending { key - current } else { current - key };
+                                within(window.lower, offset, true) && within(window.upper, offset, false)
+                            };
+                            accumulator.accumulate(
+                                values.iter().zip(keys).filter(|(_, key)| in_window(**key)).map(|(value, _)| value)
+                            )
+                        }
+                    };
+                    result.push(value);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+// The half-open range of the positions in the window.
+fn document_range(window: &Window, index: usize, len: usize) -> (usize, usize) {
+    let position = |bound: Bound, unbounded: i64| -> i64 {
+        match bound {
+            Bound::Unbounded => unbounded,
+            Bound::Current => index as i64,
+            Bound::Offset(offset) => index as i64 + offset as i64,
+        }
+    };
+    let start = position(window.lower, 0).max(0);
+    let end = (position(window.upper, len as i64 - 1) + 1).min(len as i64);
+    if start >= end {
+        (0, 0)
+    } else {
+        (start as usize, end as usize)
+    }
+}
+
+fn range_keys(docs: &[Document], field: &str) -> DbResult<Vec<f64>> {
+    let mut result = Vec::with_capacity(docs.len());
+    for doc in docs {
+        let value = resolve_path(doc, field).unwrap_or(Bson::Null);
+        let key = match &value {
+            Bson::DateTime(dt) => dt.timestamp_millis() as f64,
+            _ => match as_f64(&value) {
+                Some(f) => f,
+                None => return Err(mk_field_name_type_unexpected(field.into(), "Number".into(), format!("{}", value))),
+            },
+        };
+        result.push(key);
+    }
+    Ok(result)
+}
+
+fn within(bound: Bound, offset: f64, is_lower: bool) -> bool {
+    let limit = match bound {
+        Bound::Unbounded => return true,
+        Bound::Current => 0.0,
+        Bound::Offset(limit) => limit,
+    };
+    if is_lower {
+        offset >= limit
+    } else {
+        offset <= limit
+    }
+}
+
+impl Stage for SetWindowFieldsStage {
+
+    fn execute(&self, _ctx: &AggregationContext, input: Vec<Document>, vars: &Variables) -> DbResult<Vec<Document>> {
+        let mut result = Vec::with_capacity(input.len());
+        for mut docs in partition(self.partition_by.as_ref(), input, vars)? {
+            if let Some(sort_by) = &self.sort_by {
+                sort_by.sort(&mut docs);
+            }
+
+            let mut columns = Vec::with_capacity(self.output.len());
+            for field in &self.output {
+                columns.push(self.compute(&field.function, &docs, vars)?);
+            }
+
+            for (index, mut doc) in docs.into_iter().enumerate() {
+                for (field, column) in self.output.iter().zip(&columns) {
+                    doc.insert(field.name.clone(), column[index].clone());
+                }
+                result.push(doc);
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{document_range, Bound, Window, WindowUnit};
+
+    #[test]
+    fn test_document_range() {
+        let window = Window {
+            unit: WindowUnit::Documents,
+            lower: Bound::Offset(-1.0),
+            upper: Bound::Offset(1.0),
+        };
+        assert_eq!(document_range(&window, 0, 5), (0, 2));
+        assert_eq!(document_range(&window, 2, 5), (1, 4));
+        assert_eq!(document_range(&window, 4, 5), (3, 5));
+
+        let cumulative = Window {
+            unit: WindowUnit::Documents,
+            lower: Bound::Unbounded,
+            upper: Bound::Current,
+        };
+        assert_eq!(document_range(&cumulative, 3, 5), (0, 4));
+    }
+
+}
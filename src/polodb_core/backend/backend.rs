@@ -3,11 +3,16 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::path::Path;
 use std::sync::Arc;
 use bson::oid::ObjectId;
-use crate::DbResult;
+use crate::{DbErr, DbResult};
 use crate::page::RawPage;
 use crate::transaction::TransactionType;
+use crate::dump::JournalDump;
+use crate::backend::object_store::PrefetchPlan;
+#[cfg(feature = "debug")]
+use crate::dump::JournalFrameDump;
 
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct AutoStartResult {
@@ -19,12 +24,62 @@ pub(crate) trait Backend {
     fn write_page(&mut self, page: &RawPage, session_id: Option<&ObjectId>) -> DbResult<()>;
     fn commit(&mut self) -> DbResult<()>;
     fn db_size(&self) -> u64;
+    /// Grow the database to `size` bytes in the transaction, a smaller size is ignored.
     fn set_db_size(&mut self, size: u64) -> DbResult<()>;
+    /// Shrink the database to `size` bytes in the transaction, the pages beyond it
+    /// are dropped when the transaction is committed. It's ignored by the backends
+    /// which can't shrink.
+    fn shrink_db_size(&mut self, _size: u64) -> DbResult<()> {
+        Ok(())
+    }
     fn transaction_type(&self) -> Option<TransactionType>;
     fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()>;
     fn rollback(&mut self) -> DbResult<()>;
     fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()>;
 
+    /// Flush the pending pages to the main storage, nothing to do
+    /// for the backends without a journal.
+    fn checkpoint(&mut self) -> DbResult<()> {
+        Ok(())
+    }
+
+    /// Change the count of pages which makes the commit checkpoint, see
+    /// [`Config::journal_full_size`]. Nothing to do for the backends without a journal.
+    ///
+    /// [`Config::journal_full_size`]: crate::Config::journal_full_size
+    fn set_journal_full_size(&mut self, _size: u64) {}
+
+    /// Keep the pages in the page cache, replacing the pages pinned before.
+    /// Nothing to do for the backends without a page cache.
+    fn pin_pages(&mut self, _pages: &[u32]) -> DbResult<()> {
+        Ok(())
+    }
+
+    /// The pages to fetch ahead into the page cache, at most `max_pages`,
+    /// only the object store backend fetches the pages remotely.
+    fn prefetch_plan(&self, _max_pages: usize) -> Option<PrefetchPlan> {
+        None
+    }
+
+    /// Keep the pages fetched by the plan, unless they are changed since.
+    fn put_prefetched(&mut self, _plan: &PrefetchPlan, _pages: Vec<Arc<RawPage>>) {}
+
+    /// Save the committed pages as a database file, only the memory backend supports it.
+    fn persist_to(&self, _path: &Path) -> DbResult<()> {
+        Err(DbErr::NotSupportedByBackend("persist_to".to_string()))
+    }
+
+    /// The journal of the database, only the file backend has a journal.
+    fn dump_journal(&mut self) -> DbResult<Box<JournalDump>> {
+        Err(DbErr::NotSupportedByBackend("dump_journal".to_string()))
+    }
+
+    /// The frames in the journal, only the file backend has a journal.
+    #[cfg(feature = "debug")]
+    fn journal_frames(&mut self) -> DbResult<Vec<JournalFrameDump>> {
+        Err(DbErr::NotSupportedByBackend("journal_frames".to_string()))
+    }
+
     fn new_session(&mut self, id: &ObjectId) -> DbResult<()>;
     fn remove_session(&mut self, id: &ObjectId) -> DbResult<()>;
 }
@@ -0,0 +1,81 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::{Config, DbErr, DbResult};
+use crate::page::RawPage;
+use crate::vfs::Vfs;
+use super::cipher::PageCipher;
+use super::db_file::DbFile;
+use super::os_vfs::OsVfs;
+
+/// A new database file the pages of a snapshot are copied into.
+///
+/// The pages are written the same as the file backend with the config,
+/// so the backup is encrypted and compressed as the database file.
+pub(crate) struct BackupFile {
+    vfs:       Arc<dyn Vfs>,
+    path:      PathBuf,
+    file:      DbFile,
+    page_size: u64,
+}
+
+impl BackupFile {
+
+    /// Create the file of `page_count` pages, fail if it exists.
+    pub(crate) fn create(path: &Path, page_size: NonZeroU32, page_count: u32, config: &Config) -> DbResult<BackupFile> {
+        let vfs: Arc<dyn Vfs> = match &config.vfs {
+            Some(vfs) => vfs.clone(),
+            None => Arc::new(OsVfs),
+        };
+        if vfs.exists(path) {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("the backup file \"{}\" exists", path.display()),
+            );
+            return Err(DbErr::IOErr(Box::new(err)));
+        }
+
+        let cipher = config.encryption_key.as_ref().map(PageCipher::new).transpose()?;
+        let file = DbFile::open(vfs.as_ref(), path, page_size, false, cipher, config.compression)?;
+        let backup = BackupFile {
+            vfs,
+            path: path.to_path_buf(),
+            file,
+            page_size: page_size.get() as u64,
+        };
+        if let Err(err) = backup.file.set_len((page_count as u64) * backup.page_size) {
+            backup.abort();
+            return Err(err.into());
+        }
+        Ok(backup)
+    }
+
+    /// Write the pages sorted by the ids.
+    pub(crate) fn write_pages(&mut self, pages: Vec<Arc<RawPage>>) -> DbResult<()> {
+        let pages: Vec<(u64, RawPage)> = pages
+            .into_iter()
+            .map(|page| ((page.page_id as u64) * self.page_size, page.as_ref().clone()))
+            .collect();
+        self.file.write_pages(&pages)
+    }
+
+    pub(crate) fn finish(mut self) -> DbResult<()> {
+        self.file.flush()?;
+        self.file.sync()?;
+        self.file.unlock()
+    }
+
+    /// Remove the file not finished.
+    pub(crate) fn abort(self) {
+        let _ = self.file.unlock();
+        let BackupFile { vfs, path, file, .. } = self;
+        drop(file);
+        let _ = vfs.remove(&path);
+    }
+
+}
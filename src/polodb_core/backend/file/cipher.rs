@@ -0,0 +1,92 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, Payload};
+use getrandom::getrandom;
+use crate::{DbErr, DbResult};
+
+const NONCE_SIZE: usize = 12;
+const TAG_SIZE: usize = 16;
+
+/// The bytes added to every page encrypted, the nonce and the tag.
+pub(super) const CIPHER_OVERHEAD: u64 = (NONCE_SIZE + TAG_SIZE) as u64;
+
+/// Encrypt the pages by AES-256-GCM, the encrypted page is
+/// `nonce(12 bytes) | ciphertext | tag(16 bytes)`.
+///
+/// The nonce is random for every write. The page id is authenticated
+/// with the page, so a page can't be moved to another place of the file.
+#[derive(Clone)]
+pub(super) struct PageCipher {
+    cipher: Aes256Gcm,
+}
+
+impl PageCipher {
+
+    pub(super) fn new(key: &[u8; 32]) -> DbResult<PageCipher> {
+        Ok(PageCipher {
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key)),
+        })
+    }
+
+    pub(super) fn encrypt(&self, page_id: u32, plaintext: &[u8]) -> DbResult<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        getrandom(&mut nonce).map_err(|err| DbErr::EncryptionError(err.to_string()))?;
+        let payload = Payload {
+            msg: plaintext,
+            aad: &page_id.to_be_bytes(),
+        };
+        let ciphertext = self.cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| DbErr::EncryptionError(format!("failed to encrypt the page {}", page_id)))?;
+
+        let mut result = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
+        result.extend_from_slice(&nonce);
+        result.extend_from_slice(&ciphertext);
+        Ok(result)
+    }
+
+    pub(super) fn decrypt(&self, page_id: u32, bytes: &[u8]) -> DbResult<Vec<u8>> {
+        let failed = || DbErr::EncryptionError(format!("failed to decrypt the page {}, the key may be wrong", page_id));
+        if bytes.len() < CIPHER_OVERHEAD as usize {
+            return Err(failed());
+        }
+        let payload = Payload {
+            msg: &bytes[NONCE_SIZE..],
+            aad: &page_id.to_be_bytes(),
+        };
+        self.cipher
+            .decrypt(Nonce::from_slice(&bytes[0..NONCE_SIZE]), payload)
+            .map_err(|_| failed())
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PageCipher, CIPHER_OVERHEAD};
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let cipher = PageCipher::new(&[7; 32]).unwrap();
+        let page = vec![1u8; 4096];
+        let encrypted = cipher.encrypt(3, &page).unwrap();
+        assert_eq!(encrypted.len() as u64, 4096 + CIPHER_OVERHEAD);
+        assert_ne!(cipher.encrypt(3, &page).unwrap(), encrypted);
+        assert_eq!(cipher.decrypt(3, &encrypted).unwrap(), page);
+
+        // moved to another page
+        assert!(cipher.decrypt(4, &encrypted).is_err());
+
+        let other = PageCipher::new(&[8; 32]).unwrap();
+        assert!(other.decrypt(3, &encrypted).is_err());
+
+        let mut tampered = encrypted;
+        tampered[100] ^= 1;
+        assert!(cipher.decrypt(3, &tampered).is_err());
+    }
+
+}
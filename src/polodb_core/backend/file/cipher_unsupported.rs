@@ -0,0 +1,29 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use crate::{DbErr, DbResult};
+
+pub(super) const CIPHER_OVERHEAD: u64 = 0;
+
+/// The encryption is not built without the `encryption` feature,
+/// the value can't be created.
+#[derive(Clone)]
+pub(super) enum PageCipher {}
+
+impl PageCipher {
+
+    pub(super) fn new(_key: &[u8; 32]) -> DbResult<PageCipher> {
+        Err(DbErr::EncryptionError("the encryption needs the encryption feature".to_string()))
+    }
+
+    pub(super) fn encrypt(&self, _page_id: u32, _plaintext: &[u8]) -> DbResult<Vec<u8>> {
+        match *self {}
+    }
+
+    pub(super) fn decrypt(&self, _page_id: u32, _bytes: &[u8]) -> DbResult<Vec<u8>> {
+        match *self {}
+    }
+
+}
@@ -0,0 +1,390 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::alloc::{self, Layout};
+use std::borrow::Cow;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::num::NonZeroU32;
+use std::path::Path;
+use crate::{Compression, DbErr, DbResult};
+use crate::compression::decompress_page;
+use crate::page::RawPage;
+use crate::vfs::{Vfs, VfsFile, VfsLock};
+use super::uring::Uring;
+use super::cipher::{PageCipher, CIPHER_OVERHEAD};
+
+/// The alignment of the buffers, the offsets and the lengths of the direct I/O.
+/// It covers the logical block size of the disks.
+const DIRECT_IO_ALIGN: usize = 4096;
+
+/// The holes after the compressed pages are aligned to the sectors. A hole in
+/// a block of the file system zeroes it, the whole blocks in the hole are released.
+const HOLE_ALIGN: u64 = 512;
+
+/// A zeroed buffer of a page aligned to [`DIRECT_IO_ALIGN`].
+struct AlignedBuffer {
+    ptr:    *mut u8,
+    layout: Layout,
+}
+
+impl AlignedBuffer {
+
+    fn new(size: usize) -> AlignedBuffer {
+        let layout = Layout::from_size_align(size, DIRECT_IO_ALIGN).unwrap();
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuffer { ptr, layout }
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+
+}
+
+// the buffer is owned exclusively, the same as a `Vec<u8>`
+unsafe impl Send for AlignedBuffer {}
+
+impl Drop for AlignedBuffer {
+
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+
+}
+
+/// The main database file, which is read and written by whole pages.
+///
+/// With the direct I/O, the pages bypass the cache of the OS, so they are not
+/// cached twice with the page cache of PoloDB. The pages are copied through
+/// an aligned buffer because `RawPage` is not aligned.
+///
+/// With the encryption, a page is stored in a slot of the page size and
+/// [`CIPHER_OVERHEAD`] bytes. The offsets and the lengths of the methods are
+/// the ones of the pages not encrypted, they are mapped to the slots.
+/// A slot of all zeros is a page never written, which is read as zeros.
+///
+/// With the compression, a page except the header page is written compressed
+/// at the beginning of its slot if it shrinks, and a hole is punched for the sectors
+/// after it. The compressed pages are decompressed when they are read.
+pub(crate) struct DbFile {
+    file:      Box<dyn VfsFile>,
+    buffer:    Option<AlignedBuffer>,
+    uring:     Option<Uring>,
+    page_size: u64,
+    cipher:    Option<PageCipher>,
+    compression: Compression,
+}
+
+impl DbFile {
+
+    /// Open the file and lock it exclusively.
+    ///
+    /// The direct I/O is ignored if the page size is not aligned,
+    /// or the file is not a file of the OS. The compression is ignored with the cipher.
+    pub(super) fn open(
+        vfs: &dyn Vfs,
+        path: &Path,
+        page_size: NonZeroU32,
+        direct_io: bool,
+        cipher: Option<PageCipher>,
+        compression: Compression,
+    ) -> DbResult<DbFile> {
+        let compression = if cipher.is_some() { Compression::None } else { compression };
+        // the slots of the encrypted pages and the compressed pages are not aligned
+        let direct_io = direct_io
+            && cipher.is_none()
+            && compression.is_none()
+            && (page_size.get() as usize) % DIRECT_IO_ALIGN == 0;
+        let file = vfs.open(path, direct_io)?;
+
+        match file.lock(VfsLock::Exclusive) {
+            Err(DbErr::Busy) => {
+                return Err(DbErr::DatabaseOccupied);
+            }
+            Err(err) => {
+                return Err(err);
+            },
+            _ => (),
+        };
+
+        let buffer = if direct_io && file.as_os_file().is_some() {
+            Some(AlignedBuffer::new(page_size.get() as usize))
+        } else {
+            None
+        };
+
+        Ok(DbFile {
+            file,
+            buffer,
+            uring: None,
+            page_size: page_size.get() as u64,
+            cipher,
+            compression,
+        })
+    }
+
+    /// Read and write the pages by io_uring, it's not used with the direct I/O,
+    /// or if the file is not a file of the OS.
+    pub(super) fn set_uring(&mut self, uring: Option<Uring>) {
+        if self.buffer.is_none() && self.file.as_os_file().is_some() {
+            self.uring = uring;
+        }
+    }
+
+    fn slot_size(&self) -> u64 {
+        match &self.cipher {
+            Some(_) => self.page_size + CIPHER_OVERHEAD,
+            None => self.page_size,
+        }
+    }
+
+    /// The offset in the file of the page at `offset`.
+    fn file_offset(&self, offset: u64) -> u64 {
+        offset / self.page_size * self.slot_size()
+    }
+
+    /// The size of the pages stored, a slot not complete is not counted.
+    pub(super) fn len(&self) -> std::io::Result<u64> {
+        let size = self.file.size()?;
+        match &self.cipher {
+            Some(_) => Ok(size / self.slot_size() * self.page_size),
+            None => Ok(size),
+        }
+    }
+
+    /// Whether the file is made of whole pages, or whole slots if it's encrypted.
+    pub(super) fn is_aligned(&self) -> std::io::Result<bool> {
+        Ok(self.file.size()? % self.slot_size() == 0)
+    }
+
+    pub(super) fn preallocate(&self, size: u64) -> std::io::Result<()> {
+        self.file.preallocate(self.file_offset(size))
+    }
+
+    pub(super) fn set_len(&self, size: u64) -> std::io::Result<()> {
+        self.file.set_len(self.file_offset(size))
+    }
+
+    pub(super) fn read_page(&mut self, page: &mut RawPage, offset: u64) -> DbResult<()> {
+        self.read_slot(page, offset)?;
+        // the header page is never compressed
+        if offset != 0 {
+            decompress_page(&mut page.data)?;
+        }
+        Ok(())
+    }
+
+    fn read_slot(&mut self, page: &mut RawPage, offset: u64) -> DbResult<()> {
+        if let Some(cipher) = &self.cipher {
+            let mut slot = vec![0u8; (self.page_size + CIPHER_OVERHEAD) as usize];
+            let file_offset = offset / self.page_size * (slot.len() as u64);
+            match (&mut self.uring, self.file.as_os_file()) {
+                (Some(uring), Some(os_file)) => uring.read_exact_at(os_file, &mut slot, file_offset)?,
+                _ => {
+                    self.file.seek(SeekFrom::Start(file_offset))?;
+                    self.file.read_exact(&mut slot)?;
+                }
+            }
+            if slot.iter().all(|byte| *byte == 0) {
+                page.data.iter_mut().for_each(|byte| *byte = 0);
+            } else {
+                page.data.copy_from_slice(&cipher.decrypt(page.page_id, &slot)?);
+            }
+            return Ok(());
+        }
+        match &mut self.buffer {
+            Some(buffer) => {
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.read_exact(buffer.as_mut_slice())?;
+                page.data.copy_from_slice(buffer.as_slice());
+                Ok(())
+            }
+            None => {
+                if let (Some(uring), Some(os_file)) = (&mut self.uring, self.file.as_os_file()) {
+                    uring.read_exact_at(os_file, &mut page.data, offset)?;
+                    return Ok(());
+                }
+                page.read_from_file(&mut self.file, offset)?;
+                Ok(())
+            }
+        }
+    }
+
+    pub(super) fn write_page(&mut self, page: &RawPage, offset: u64) -> DbResult<()> {
+        if let Some(cipher) = &self.cipher {
+            let slot = cipher.encrypt(page.page_id, &page.data)?;
+            let file_offset = self.file_offset(offset);
+            self.file.seek(SeekFrom::Start(file_offset))?;
+            self.file.write_all(&slot)?;
+            return Ok(());
+        }
+        if let Some(compressed) = self.compress_page(page, offset)? {
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.write_all(&compressed)?;
+            self.punch_after(offset, compressed.len() as u64)?;
+            return Ok(());
+        }
+        match &mut self.buffer {
+            Some(buffer) => {
+                buffer.as_mut_slice().copy_from_slice(&page.data);
+                self.file.seek(SeekFrom::Start(offset))?;
+                self.file.write_all(buffer.as_slice())?;
+            }
+            None => page.sync_to_file(&mut self.file, offset)?,
+        }
+        Ok(())
+    }
+
+    /// Write the pages sorted by the offsets. The adjacent pages are combined
+    /// into one sequential write, and the writes are submitted at once with io_uring.
+    pub(super) fn write_pages(&mut self, pages: &[(u64, RawPage)]) -> DbResult<()> {
+        debug_assert!(pages.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        let slots: Vec<(u64, Vec<u8>)> = match &self.cipher {
+            Some(cipher) => pages.iter()
+                .map(|(offset, page)| Ok((self.file_offset(*offset), cipher.encrypt(page.page_id, &page.data)?)))
+                .collect::<DbResult<_>>()?,
+            None => vec![],
+        };
+        let compressed: Vec<Option<Vec<u8>>> = pages.iter()
+            .map(|(offset, page)| self.compress_page(page, *offset))
+            .collect::<DbResult<_>>()?;
+        let items: Vec<(u64, &[u8])> = match &self.cipher {
+            Some(_) => slots.iter().map(|(offset, slot)| (*offset, slot.as_slice())).collect(),
+            None => pages.iter().zip(compressed.iter())
+                .map(|((offset, page), compressed)| match compressed {
+                    Some(compressed) => (*offset, compressed.as_slice()),
+                    None => (*offset, page.data.as_slice()),
+                })
+                .collect(),
+        };
+        let runs = combine_adjacent(&items);
+
+        if let (Some(uring), Some(os_file)) = (&mut self.uring, self.file.as_os_file()) {
+            let writes: Vec<(u64, &[u8])> = runs.iter()
+                .map(|(offset, data)| (*offset, data.as_ref()))
+                .collect();
+            uring.write_all_at(os_file, &writes)?;
+        } else {
+            for (offset, data) in &runs {
+                self.write_at(data, *offset)?;
+            }
+        }
+
+        for ((offset, _), compressed) in pages.iter().zip(compressed.iter()) {
+            if let Some(compressed) = compressed {
+                self.punch_after(*offset, compressed.len() as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The compressed content of the page if it's compressed.
+    fn compress_page(&self, page: &RawPage, offset: u64) -> DbResult<Option<Vec<u8>>> {
+        if offset == 0 {
+            return Ok(None);
+        }
+        self.compression.compress_page(&page.data)
+    }
+
+    /// Punch a hole for the blocks of the page at `offset` after the compressed
+    /// content of `len` bytes, the content before the hole is kept.
+    fn punch_after(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        let start = offset + (len + HOLE_ALIGN - 1) / HOLE_ALIGN * HOLE_ALIGN;
+        let end = offset + self.page_size;
+        if start < end {
+            self.file.punch_hole(start, end - start)?;
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        match &mut self.buffer {
+            Some(buffer) if buffer.as_slice().len() == data.len() => {
+                buffer.as_mut_slice().copy_from_slice(data);
+                self.file.write_all(buffer.as_slice())
+            }
+            // a run of pages is copied to an aligned buffer of its size
+            Some(_) => {
+                let mut buffer = AlignedBuffer::new(data.len());
+                buffer.as_mut_slice().copy_from_slice(data);
+                self.file.write_all(buffer.as_slice())
+            }
+            None => self.file.write_all(data),
+        }
+    }
+
+    pub(super) fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+
+    pub(super) fn sync(&self) -> std::io::Result<()> {
+        self.file.sync()
+    }
+
+    pub(super) fn punch_hole(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        self.file.punch_hole(self.file_offset(offset), self.file_offset(len))
+    }
+
+    pub(super) fn unlock(&self) -> DbResult<()> {
+        self.file.lock(VfsLock::Unlock)
+    }
+
+}
+
+/// Combine the adjacent pages into the runs, a run of one page is not copied.
+fn combine_adjacent<'a>(pages: &[(u64, &'a [u8])]) -> Vec<(u64, Cow<'a, [u8]>)> {
+    let mut result: Vec<(u64, Cow<[u8]>)> = vec![];
+    for (offset, data) in pages {
+        if let Some((run_offset, run)) = result.last_mut() {
+            if *run_offset + run.len() as u64 == *offset {
+                run.to_mut().extend_from_slice(data);
+                continue;
+            }
+        }
+        result.push((*offset, Cow::Borrowed(*data)));
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use crate::page::RawPage;
+    use super::combine_adjacent;
+
+    #[test]
+    fn test_combine_adjacent_pages() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let pages: Vec<(u64, RawPage)> = [0u32, 1, 2, 5, 7, 8].iter()
+            .map(|page_id| {
+                let mut page = RawPage::new(*page_id, page_size);
+                page.data[0] = *page_id as u8;
+                ((*page_id as u64) * 4096, page)
+            })
+            .collect();
+
+        let items: Vec<(u64, &[u8])> = pages.iter()
+            .map(|(offset, page)| (*offset, page.data.as_slice()))
+            .collect();
+        let runs = combine_adjacent(&items);
+        let runs: Vec<(u64, usize, u8)> = runs.iter()
+            .map(|(offset, data)| (*offset, data.len(), data[data.len() - 4096]))
+            .collect();
+        assert_eq!(runs, vec![
+            (0, 3 * 4096, 2),
+            (5 * 4096, 4096, 5),
+            (7 * 4096, 2 * 4096, 8),
+        ]);
+    }
+
+}
@@ -3,28 +3,40 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use std::fs::File;
 use std::num::{NonZeroU32, NonZeroU64};
 use std::cell::RefCell;
-use std::io::{SeekFrom, Seek, Read};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use bson::oid::ObjectId;
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 use super::journal_manager::JournalManager;
 use super::transaction_state::TransactionState;
-use super::pagecache::PageCache;
+use crate::backend::PageCache;
+use super::recovery::RecoveryReport;
+use super::db_file::DbFile;
+use super::uring::Uring;
+use super::cipher::PageCipher;
+use super::os_vfs::OsVfs;
 use crate::backend::Backend;
 use crate::{DbResult, DbErr, Config, Metrics};
-use crate::page::RawPage;
-use crate::page::header_page_wrapper::{HeaderPageWrapper, DATABASE_VERSION};
+use crate::vfs::Vfs;
+use crate::page::{RawPage, FreeListDataWrapper};
+use crate::page::header_page_wrapper::{
+    HeaderPageWrapper, DATABASE_VERSION, FEATURE_INCOMPAT_PAGE_COMPRESSION, HEADER_FREE_LIST_MAX_SIZE,
+};
 use crate::transaction::TransactionType;
 use crate::error::VersionMismatchError;
+use crate::dump::JournalDump;
+#[cfg(feature = "debug")]
+use crate::dump::JournalFrameDump;
 
 pub(crate) struct FileBackend {
-    file:            RefCell<File>,
+    vfs:             Arc<dyn Vfs>,
+    file:            RefCell<DbFile>,
     page_size:       NonZeroU32,
     journal_manager: JournalManager,
+    // copied from the config, it can be changed after the database is opened
+    journal_full_size: u64,
     config:          Arc<Config>,
     page_cache:      PageCache,
     state_map:       HashMap<ObjectId, TransactionState>,
@@ -35,61 +47,9 @@ struct InitDbResult {
     db_file_size: u64,
 }
 
-#[cfg(target_os = "windows")]
-mod winerror {
-    pub const ERROR_SHARING_VIOLATION: i32 = 32;
-}
-
-#[cfg(target_os = "windows")]
-fn open_file_native(path: &Path) -> DbResult<File> {
-    use std::os::windows::prelude::OpenOptionsExt;
-
-    let file_result = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .read(true)
-        .share_mode(0)
-        .open(path);
-
-    match file_result {
-        Ok(file) => Ok(file),
-        Err(err) => {
-            let os_error = err.raw_os_error();
-            if let Some(error_code) = os_error {
-                if error_code == winerror::ERROR_SHARING_VIOLATION {
-                    return Err(DbErr::DatabaseOccupied);
-                }
-            }
-            Err(err.into())
-        }
-    }
-}
-
-#[cfg(not(target_os = "windows"))]
-fn open_file_native(path: &Path) -> DbResult<File> {
-    use super::file_lock::exclusive_lock_file;
-    let file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .read(true)
-        .open(path)?;
-
-    match exclusive_lock_file(&file) {
-        Err(DbErr::Busy) => {
-            return Err(DbErr::DatabaseOccupied);
-        }
-        Err(err) => {
-            return Err(err);
-        },
-        _ => (),
-    };
-
-    Ok(file)
-}
-
 impl FileBackend {
 
-    fn mk_journal_path(db_path: &Path) -> PathBuf {
+    pub(crate) fn mk_journal_path(db_path: &Path) -> PathBuf {
         let mut buf = db_path.to_path_buf();
         let filename = buf.file_name().unwrap().to_str().unwrap();
         let new_filename = String::from(filename) + ".journal";
@@ -103,7 +63,26 @@ impl FileBackend {
         config: Arc<Config>,
         metrics: Metrics,
     ) -> DbResult<FileBackend> {
-        let mut file = open_file_native(path)?;
+        let page_cache = PageCache::new(config.page_cache_size.get(), page_size, config.page_cache_policy);
+        FileBackend::open_with_page_cache(path, page_size, config, metrics, page_cache)
+    }
+
+    /// Open the file with a page cache which may be shared with other databases.
+    pub(crate) fn open_with_page_cache(
+        path: &Path,
+        page_size: NonZeroU32,
+        config: Arc<Config>,
+        metrics: Metrics,
+        page_cache: PageCache,
+    ) -> DbResult<FileBackend> {
+        let vfs: Arc<dyn Vfs> = match &config.vfs {
+            Some(vfs) => vfs.clone(),
+            None => Arc::new(OsVfs),
+        };
+        let cipher = config.encryption_key.as_ref().map(PageCipher::new).transpose()?;
+        let mut file = DbFile::open(
+            vfs.as_ref(), path, page_size, config.direct_io, cipher.clone(), config.compression,
+        )?;
 
         let init_result = FileBackend::init_db(
             &mut file,
@@ -113,40 +92,75 @@ impl FileBackend {
         )?;
 
         let journal_file_path: PathBuf = FileBackend::mk_journal_path(path);
-        let journal_manager = JournalManager::open(
-            &journal_file_path, page_size, init_result.db_file_size
+        let mut journal_manager = JournalManager::open(
+            vfs.as_ref(), &journal_file_path, page_size, init_result.db_file_size, cipher,
         )?;
 
-        let page_cache = PageCache::new_default(page_size);
+        if config.io_uring {
+            file.set_uring(Uring::new());
+            journal_manager.set_uring(Uring::new());
+        }
+
+        // the committed transactions left by the last process are merged at once,
+        // so the state of the database file doesn't depend on when it was closed
+        if journal_manager.len() > 0 {
+            journal_manager.checkpoint_journal(&mut file)?;
+        }
 
-        Ok(FileBackend {
+        let mut backend = FileBackend {
+            vfs,
             file: RefCell::new(file),
             page_size,
             journal_manager,
+            journal_full_size: config.journal_full_size,
             config,
             page_cache,
             state_map: HashMap::new(),
             metrics,
-        })
+        };
+        if !backend.config.compression.is_none() && backend.config.encryption_key.is_none() {
+            backend.enable_page_compression()?;
+        }
+
+        Ok(backend)
     }
 
-    fn force_write_first_block(file: &mut File, page_size: NonZeroU32) -> std::io::Result<Arc<RawPage>> {
+    /// Mark the compressed pages in the header before any of them is written,
+    /// so the versions which can't read them refuse to open the file.
+    /// The pages written before are kept as they are until they are written again.
+    fn enable_page_compression(&mut self) -> DbResult<()> {
+        let header_page = self.read_page_main(0)?;
+        let mut wrapper = HeaderPageWrapper::from_raw_page(header_page.as_ref().clone());
+        let features = wrapper.get_incompat_features();
+        if features & FEATURE_INCOMPAT_PAGE_COMPRESSION != 0 {
+            return Ok(());
+        }
+        wrapper.set_incompat_features(features | FEATURE_INCOMPAT_PAGE_COMPRESSION);
+
+        self.start_transaction(TransactionType::Write)?;
+        if let Err(err) = self.write_page(&wrapper.0, None) {
+            self.rollback()?;
+            return Err(err);
+        }
+        self.commit()
+    }
+
+    fn force_write_first_block(file: &mut DbFile, page_size: NonZeroU32) -> DbResult<Arc<RawPage>> {
         let wrapper = HeaderPageWrapper::init(0, page_size);
-        wrapper.0.sync_to_file(file, 0)?;
+        file.write_page(&wrapper.0, 0)?;
         Ok(Arc::new(wrapper.0))
     }
 
-    fn init_db(file: &mut File, page_size: NonZeroU32, init_block_count: NonZeroU64, check_db_version: bool) -> DbResult<InitDbResult> {
-        let meta = file.metadata()?;
-        let file_len = meta.len();
+    fn init_db(file: &mut DbFile, page_size: NonZeroU32, init_block_count: NonZeroU64, check_db_version: bool) -> DbResult<InitDbResult> {
+        let file_len = file.len()?;
         if file_len == 0 {
             let expected_file_size: u64 = (page_size.get() as u64) * init_block_count.get();
-            file.set_len(expected_file_size)?;
+            file.preallocate(expected_file_size)?;
             FileBackend::force_write_first_block(file, page_size)?;
             Ok(InitDbResult { db_file_size: expected_file_size })
-        } else if file_len % page_size.get() as u64 == 0 {
+        } else if file.is_aligned()? {
             if check_db_version {
-                FileBackend::check_db_version(file)?;
+                FileBackend::check_db_version(file, page_size)?;
             }
             Ok(InitDbResult { db_file_size: file_len })
         } else {
@@ -154,10 +168,12 @@ impl FileBackend {
         }
     }
 
-    fn check_db_version(file: &mut File) -> DbResult<()> {
+    fn check_db_version(file: &mut DbFile, page_size: NonZeroU32) -> DbResult<()> {
+        // the whole page is read for the direct I/O
+        let mut first_page = RawPage::new(0, page_size);
+        file.read_page(&mut first_page, 0)?;
         let mut version = [0u8; 4];
-        file.seek(SeekFrom::Start(32))?;
-        file.read_exact(&mut version)?;
+        version.copy_from_slice(&first_page.data[32..36]);
 
         if version != DATABASE_VERSION {
             let err = VersionMismatchError {
@@ -167,12 +183,102 @@ impl FileBackend {
             return Err(DbErr::VersionMismatch(Box::new(err)))
         }
 
+        let mut header = HeaderPageWrapper::from_raw_page(first_page);
+        let actual_page_size = header.get_page_size();
+        if actual_page_size != page_size.get() {
+            return Err(DbErr::PageSizeMismatch(page_size.get(), actual_page_size));
+        }
+
+        header.check_features()
+    }
+
+    /// Merge the journal into the main file, the header page in the cache
+    /// is refreshed because the checkpoint LSN is written into it.
+    fn checkpoint_journal(&mut self) -> DbResult<()> {
+        {
+            let mut main_db = self.file.borrow_mut();
+            self.journal_manager.checkpoint_journal(&mut main_db)?;
+        }
+        crate::polo_log!("checkpoint journal finished");
+
+        let header_page = self.read_page_from_main_file(0)?;
+        self.page_cache.insert_to_cache(&header_page);
+
+        // the free list in the main file is up to date after the checkpoint
+        if let Some(min_pages) = self.config.hole_punch_min_pages {
+            if let Err(err) = self.punch_free_pages(&header_page, min_pages) {
+                crate::polo_log!("punch the holes failed: {}", err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Punch holes for the runs of the free pages, so the file system releases the space.
+    /// The pages of the free list itself are kept.
+    fn punch_free_pages(&self, header_page: &RawPage, min_pages: NonZeroU32) -> DbResult<()> {
+        let page_size = self.page_size.get() as u64;
+        let page_count = self.file.borrow().len()? / page_size;
+        let header = HeaderPageWrapper::from_raw_page(header_page.clone());
+
+        let free_list_size = header.get_free_list_size().min(HEADER_FREE_LIST_MAX_SIZE as u32);
+        let mut free_pages: Vec<u32> = (0..free_list_size)
+            .map(|index| header.get_free_list_content(index))
+            .collect();
+
+        let mut free_list_pages = HashSet::new();
+        let mut free_list_pid = header.get_free_list_page_id();
+        while free_list_pid != 0 && (free_list_pid as u64) < page_count && free_list_pages.insert(free_list_pid) {
+            let page = self.read_page_from_main_file(free_list_pid)?;
+            let wrapper = FreeListDataWrapper::from_raw(page.as_ref().clone());
+            free_pages.extend(wrapper.page_ids());
+            free_list_pid = wrapper.next_pid();
+        }
+
+        free_pages.retain(|pid| {
+            *pid != 0 && (*pid as u64) < page_count && !free_list_pages.contains(pid)
+        });
+        free_pages.sort_unstable();
+        free_pages.dedup();
+
+        let main_file = self.file.borrow();
+        let mut punched = 0;
+        for (first_pid, count) in free_page_runs(&free_pages) {
+            if count < min_pages.get() {
+                continue;
+            }
+            main_file.punch_hole((first_pid as u64) * page_size, (count as u64) * page_size)?;
+            punched += count;
+        }
+
+        crate::polo_log!("punch holes for {} free pages", punched);
+
         Ok(())
     }
 
+    #[inline]
+    pub(crate) fn recovery_report(&self) -> &RecoveryReport {
+        self.journal_manager.recovery_report()
+    }
+
     #[inline]
     fn is_journal_full(&self) -> bool {
-        (self.journal_manager.len() as u64) >= self.config.journal_full_size
+        (self.journal_manager.len() as u64) >= self.journal_full_size
+    }
+
+    /// Report the lag of the checkpoint, and delay the commit
+    /// if the journal is throttled.
+    fn throttle_journal(&self) {
+        let journal_len = self.journal_manager.len() as u64;
+        let lag = journal_len.saturating_sub(self.journal_full_size);
+        self.metrics.set_journal_lag(lag as usize);
+
+        if let Some(throttle) = &self.config.journal_throttle {
+            let delay = throttle.delay(journal_len, self.journal_full_size);
+            if !delay.is_zero() {
+                crate::polo_log!("journal lag {} pages, delay the commit {:?}", lag, delay);
+                std::thread::sleep(delay);
+            }
+        }
     }
 
     /// 1. Read the page from the journal
@@ -205,14 +311,29 @@ impl FileBackend {
 
         crate::polo_log!("read page from main file, id: {}", page_id);
 
-        if main_file.seek(SeekFrom::End(0))? >= offset + (self.page_size.get() as u64) {
-            result.read_from_file(&mut main_file, offset)?;
+        if main_file.len()? >= offset + (self.page_size.get() as u64) {
+            main_file.read_page(&mut result, offset)?;
         }
 
         Ok(Arc::new(result))
     }
 }
 
+/// The runs of the adjacent page ids, the ids are sorted.
+fn free_page_runs(pages: &[u32]) -> Vec<(u32, u32)> {
+    let mut result: Vec<(u32, u32)> = vec![];
+    for pid in pages {
+        if let Some((first_pid, count)) = result.last_mut() {
+            if *first_pid + *count == *pid {
+                *count += 1;
+                continue;
+            }
+        }
+        result.push((*pid, 1));
+    }
+    result
+}
+
 impl Backend for FileBackend {
 
     fn read_page(&self, page_id: u32, session_id: Option<&ObjectId>) -> DbResult<Arc<RawPage>> {
@@ -234,7 +355,13 @@ impl Backend for FileBackend {
         if session_id.is_some() {
             unreachable!()
         }
-        self.journal_manager.append_raw_page(page)?;
+        // the last content of the page is in the cache usually
+        let previous = if self.config.delta_journal {
+            self.page_cache.get_from_cache(page.page_id)
+        } else {
+            None
+        };
+        self.journal_manager.append_raw_page(page, previous.as_deref())?;
 
         self.page_cache.insert_to_cache(page);
 
@@ -245,12 +372,11 @@ impl Backend for FileBackend {
     /// 2. If the journal is full, and there is not session is opened,
     ///    merge the journal to the main database.
     fn commit(&mut self) -> DbResult<()> {
-        let mut main_db = self.file.borrow_mut();
         self.journal_manager.commit()?;
         if self.is_journal_full() && self.state_map.is_empty() {
-            self.journal_manager.checkpoint_journal(&mut main_db)?;
-            crate::polo_log!("checkpoint journal finished");
+            self.checkpoint_journal()?;
         }
+        self.throttle_journal();
         Ok(())
     }
 
@@ -262,6 +388,33 @@ impl Backend for FileBackend {
         self.journal_manager.expand_db_size(size)
     }
 
+    fn shrink_db_size(&mut self, size: u64) -> DbResult<()> {
+        self.journal_manager.shrink_db_size(size)
+    }
+
+    fn pin_pages(&mut self, pages: &[u32]) -> DbResult<()> {
+        self.page_cache.pin(pages)?;
+        // the pages in the journal are cached after the checkpoint
+        for page_id in pages {
+            self.read_page_main(*page_id)?;
+        }
+        Ok(())
+    }
+
+    fn set_journal_full_size(&mut self, size: u64) {
+        self.journal_full_size = size;
+    }
+
+    fn dump_journal(&mut self) -> DbResult<Box<JournalDump>> {
+        let dump = self.journal_manager.dump()?;
+        Ok(Box::new(dump))
+    }
+
+    #[cfg(feature = "debug")]
+    fn journal_frames(&mut self) -> DbResult<Vec<JournalFrameDump>> {
+        self.journal_manager.dump_frames()
+    }
+
     fn transaction_type(&self) -> Option<TransactionType> {
         self.journal_manager.transaction_type()
     }
@@ -272,7 +425,7 @@ impl Backend for FileBackend {
 
     fn rollback(&mut self) -> DbResult<()> {
         self.journal_manager.rollback()?;
-        self.page_cache = PageCache::new_default(self.page_size);
+        self.page_cache.invalidate();
         Ok(())
     }
 
@@ -280,6 +433,20 @@ impl Backend for FileBackend {
         self.journal_manager.start_transaction(ty)
     }
 
+    /// Merge the journal into the main file if no transaction
+    /// or session is reading it.
+    fn checkpoint(&mut self) -> DbResult<()> {
+        if self.journal_manager.transaction_type().is_some() || !self.state_map.is_empty() {
+            return Err(DbErr::Busy);
+        }
+        if self.journal_manager.len() == 0 {
+            return Ok(());
+        }
+        self.checkpoint_journal()?;
+        self.metrics.set_journal_lag(0);
+        Ok(())
+    }
+
     fn new_session(&mut self, id: &ObjectId) -> DbResult<()> {
         let state = self.journal_manager.new_state(TransactionType::Read);
         self.state_map.insert(id.clone(), state);
@@ -299,12 +466,11 @@ impl Drop for FileBackend {
         self.state_map.clear();
 
         let mut main_db = self.file.borrow_mut();
-        #[cfg(not(target_os = "windows"))]
-        let _ = super::file_lock::unlock_file(&main_db);
+        let _ = main_db.unlock();
         let result = self.journal_manager.checkpoint_journal(&mut main_db);
         if result.is_ok() {
             let path = self.journal_manager.path();
-            let _ = std::fs::remove_file(path);
+            let _ = self.vfs.remove(path);
         }
     }
 
@@ -9,51 +9,57 @@ use crate::{DbErr, DbResult};
 #[cfg(target_os = "windows")]
 use std::os::windows::io::AsRawHandle;
 
+// The locks of Windows are mandatory, a locked range can't be read by other handles.
+// So the lock is placed on a byte far beyond the data, which works as the advisory
+// lock of the whole file by flock on Unix.
 #[cfg(target_os = "windows")]
-pub(crate) fn exclusive_lock_file(file: &File) -> DbResult<()> {
-    use winapi::um::fileapi::LockFileEx;
-    use winapi::um::minwinbase::OVERLAPPED;
-    use winapi::um::minwinbase::{LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY};
+const LOCK_OFFSET: u64 = 1 << 62;
+
+#[cfg(target_os = "windows")]
+fn lock_byte_overlapped() -> winapi::um::minwinbase::OVERLAPPED {
+    let mut overlapped: winapi::um::minwinbase::OVERLAPPED = unsafe { std::mem::zeroed() };
+    unsafe {
+        let offset = overlapped.u.s_mut();
+        offset.Offset = LOCK_OFFSET as u32;
+        offset.OffsetHigh = (LOCK_OFFSET >> 32) as u32;
+    }
+    overlapped
+}
+
+#[cfg(target_os = "windows")]
+fn unlock_byte(file: &File) -> bool {
+    use winapi::um::fileapi::UnlockFileEx;
     use winapi::ctypes;
 
     let handle = file.as_raw_handle();
-
-    let bl = unsafe {
-        let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
-        libc::memset(overlapped.cast::<libc::c_void>(), 0, std::mem::size_of::<OVERLAPPED>());
-        let result: i32 = LockFileEx(
-            handle.cast::<ctypes::c_void>(),
-            LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY,
-            0, 0, 0, overlapped);
-        libc::free(overlapped.cast::<libc::c_void>());
-        result
+    let mut overlapped = lock_byte_overlapped();
+    let result: i32 = unsafe {
+        UnlockFileEx(handle.cast::<ctypes::c_void>(), 0, 1, 0, &mut overlapped)
     };
-
-    if bl == 0 {
-        return Err(DbErr::Busy);
-    }
-
-    Ok(())
+    result != 0
 }
 
+/// Lock the byte with the `flags` of `LockFileEx`. The lock held by the handle
+/// is released first, so a shared lock can be converted to an exclusive one
+/// and the other way round, the same as flock.
 #[cfg(target_os = "windows")]
-pub(crate) fn shared_lock_file(file: &File) -> DbResult<()> {
+fn lock_byte(file: &File, flags: u32) -> DbResult<()> {
     use winapi::um::fileapi::LockFileEx;
-    use winapi::um::minwinbase::OVERLAPPED;
     use winapi::um::minwinbase::LOCKFILE_FAIL_IMMEDIATELY;
     use winapi::ctypes;
 
-    let handle = file.as_raw_handle();
+    let _ = unlock_byte(file);
 
-    let bl = unsafe {
-        let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
-        libc::memset(overlapped.cast::<libc::c_void>(), 0, std::mem::size_of::<OVERLAPPED>());
-        let result: i32 = LockFileEx(handle.cast::<ctypes::c_void>(), LOCKFILE_FAIL_IMMEDIATELY, 0, 0, 0, overlapped);
-        libc::free(overlapped.cast::<libc::c_void>());
-        result
+    let handle = file.as_raw_handle();
+    let mut overlapped = lock_byte_overlapped();
+    let result: i32 = unsafe {
+        LockFileEx(
+            handle.cast::<ctypes::c_void>(),
+            flags | LOCKFILE_FAIL_IMMEDIATELY,
+            0, 1, 0, &mut overlapped)
     };
 
-    if bl == 0 {
+    if result == 0 {
         return Err(DbErr::Busy);
     }
 
@@ -61,25 +67,21 @@ pub(crate) fn shared_lock_file(file: &File) -> DbResult<()> {
 }
 
 #[cfg(target_os = "windows")]
-pub(crate) fn unlock_file(file: &File) -> DbResult<()> {
-    use winapi::um::fileapi::UnlockFileEx;
-    use winapi::um::minwinbase::OVERLAPPED;
-    use winapi::ctypes;
-
-    let handle = file.as_raw_handle();
+pub(crate) fn exclusive_lock_file(file: &File) -> DbResult<()> {
+    use winapi::um::minwinbase::LOCKFILE_EXCLUSIVE_LOCK;
+    lock_byte(file, LOCKFILE_EXCLUSIVE_LOCK)
+}
 
-    let bl = unsafe {
-        let overlapped: *mut OVERLAPPED = libc::malloc(std::mem::size_of::<OVERLAPPED>()).cast::<OVERLAPPED>();
-        libc::memset(overlapped.cast::<libc::c_void>(), 0, std::mem::size_of::<OVERLAPPED>());
-        let result: i32 = UnlockFileEx(handle.cast::<ctypes::c_void>(), 0, 0, 0, overlapped);
-        libc::free(overlapped.cast::<libc::c_void>());
-        result
-    };
+#[cfg(target_os = "windows")]
+pub(crate) fn shared_lock_file(file: &File) -> DbResult<()> {
+    lock_byte(file, 0)
+}
 
-    if bl == 0 {
+#[cfg(target_os = "windows")]
+pub(crate) fn unlock_file(file: &File) -> DbResult<()> {
+    if !unlock_byte(file) {
         return Err(DbErr::Busy);
     }
-
     Ok(())
 }
 
@@ -11,6 +11,10 @@ pub(super) struct FrameHeader {
     // page_id * offset represents the real offset from the beginning
     pub(super) page_id:       u32,  // offset 0
 
+    // 0 for the frame of a whole page,
+    // otherwise the size of the delta against the last frame of the page
+    pub(super) delta_size:    u32,  // offset 4
+
     // usually 0
     // if this frame is the final commit of a transaction
     // this field represents the read db_size
@@ -30,6 +34,10 @@ impl FrameHeader {
 
         let page_id = u32::from_be_bytes(buffer);
 
+        let mut buffer: [u8; 4] = [0; 4];
+        buffer.copy_from_slice(&bytes[4..8]);
+        let delta_size = u32::from_be_bytes(buffer);
+
         let mut buffer: [u8; 8] = [0; 8];
         buffer.copy_from_slice(&bytes[8..16]);
         let db_size = u64::from_be_bytes(buffer);
@@ -44,6 +52,7 @@ impl FrameHeader {
 
         FrameHeader {
             page_id,
+            delta_size,
             db_size,
             salt1, salt2
         }
@@ -54,6 +63,9 @@ impl FrameHeader {
         let page_id_be = self.page_id.to_be_bytes();
         buffer[0..4].copy_from_slice(&page_id_be);
 
+        let delta_size_be = self.delta_size.to_be_bytes();
+        buffer[4..8].copy_from_slice(&delta_size_be);
+
         let db_size_be = self.db_size.to_be_bytes();
         buffer[8..16].copy_from_slice(&db_size_be);
 
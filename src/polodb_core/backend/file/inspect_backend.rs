@@ -0,0 +1,130 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use crate::backend::Backend;
+use crate::compression::decompress_page;
+use crate::page::RawPage;
+use crate::transaction::TransactionType;
+use crate::{DbErr, DbResult};
+
+// the offset of the page size in the header page
+const PAGE_SIZE_OFFSET: usize = 44;
+
+/// Read the pages of the main file without locking it and without the journal,
+/// so a database opened by another process can be inspected. Nothing can be written.
+pub(crate) struct InspectBackend {
+    file:        RefCell<File>,
+    page_size:   NonZeroU32,
+    db_size:     u64,
+    transaction: Option<TransactionType>,
+}
+
+impl InspectBackend {
+
+    pub(crate) fn open(path: &Path) -> DbResult<InspectBackend> {
+        let mut file = File::open(path)?;
+        let db_size = file.metadata()?.len();
+
+        let mut head = [0u8; PAGE_SIZE_OFFSET + 4];
+        if db_size < head.len() as u64 {
+            return Err(DbErr::NotAValidDatabase);
+        }
+        file.read_exact(&mut head)?;
+        let mut page_size_bytes = [0u8; 4];
+        page_size_bytes.copy_from_slice(&head[PAGE_SIZE_OFFSET..]);
+        let page_size = NonZeroU32::new(u32::from_be_bytes(page_size_bytes))
+            .filter(|page_size| db_size % (page_size.get() as u64) == 0)
+            .ok_or(DbErr::NotAValidDatabase)?;
+
+        Ok(InspectBackend {
+            file: RefCell::new(file),
+            page_size,
+            db_size,
+            transaction: None,
+        })
+    }
+
+    #[inline]
+    pub(crate) fn page_size(&self) -> NonZeroU32 {
+        self.page_size
+    }
+
+}
+
+impl Backend for InspectBackend {
+
+    fn read_page(&self, page_id: u32, _session_id: Option<&ObjectId>) -> DbResult<Arc<RawPage>> {
+        let offset = (page_id as u64) * (self.page_size.get() as u64);
+        let mut page = RawPage::new(page_id, self.page_size);
+        if offset >= self.db_size {
+            return Ok(Arc::new(page));
+        }
+        let mut file = self.file.borrow_mut();
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut page.data)?;
+        // the header page is never compressed
+        if page_id != 0 {
+            decompress_page(&mut page.data)?;
+        }
+        Ok(Arc::new(page))
+    }
+
+    fn write_page(&mut self, _page: &RawPage, _session_id: Option<&ObjectId>) -> DbResult<()> {
+        Err(DbErr::NotSupportedByBackend("write_page".to_string()))
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        self.transaction = None;
+        Ok(())
+    }
+
+    fn db_size(&self) -> u64 {
+        self.db_size
+    }
+
+    fn set_db_size(&mut self, _size: u64) -> DbResult<()> {
+        Err(DbErr::NotSupportedByBackend("set_db_size".to_string()))
+    }
+
+    fn transaction_type(&self) -> Option<TransactionType> {
+        self.transaction
+    }
+
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        Err(DbErr::NotSupportedByBackend("write transaction".to_string()))
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        self.transaction = None;
+        Ok(())
+    }
+
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        if ty == TransactionType::Write {
+            return Err(DbErr::NotSupportedByBackend("write transaction".to_string()));
+        }
+        if self.transaction.is_some() {
+            return Err(DbErr::Busy);
+        }
+        self.transaction = Some(ty);
+        Ok(())
+    }
+
+    fn new_session(&mut self, _id: &ObjectId) -> DbResult<()> {
+        Ok(())
+    }
+
+    fn remove_session(&mut self, _id: &ObjectId) -> DbResult<()> {
+        Ok(())
+    }
+
+}
@@ -3,28 +3,38 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::collections::BTreeMap;
 use std::io::{Seek, Write, SeekFrom, Read};
 use std::cell::{Cell, RefCell};
 use std::num::NonZeroU32;
 use std::sync::Arc;
+use hashbrown::HashMap;
 use getrandom::getrandom;
 use crc64fast::Digest;
 use crate::data_structures::trans_map::TransMap;
 use super::transaction_state::TransactionState;
 use super::frame_header::FrameHeader;
+use super::recovery::{RecoveryReport, RecoveryState};
 use crate::transaction::TransactionType;
 use crate::page::RawPage;
+use crate::page::header_page_wrapper::HeaderPageWrapper;
 use crate::DbResult;
 use crate::error::DbErr;
-use super::file_lock::*;
+use crate::vfs::{Vfs, VfsFile, VfsLock};
+use super::db_file::DbFile;
+use super::page_delta;
+use super::uring::Uring;
+use super::cipher::{PageCipher, CIPHER_OVERHEAD};
 use crate::dump::{JournalDump, JournalFrameDump};
 
 static HEADER_DESP: &str       = "PoloDB Journal v0.3";
 const JOURNAL_DATA_BEGIN: u64 = 64;
 const FRAME_HEADER_SIZE: u64  = 40;
+const CHECKPOINT_BATCH_SIZE: usize = 64;
+// a whole page is written after so many deltas,
+// a page is read by applying them on the last image
+const MAX_DELTA_DEPTH: u32 = 8;
 
 // name:       32 bytes
 // version:    4bytes(offset 32)
@@ -33,9 +43,15 @@ const FRAME_HEADER_SIZE: u64  = 40;
 // salt_2:     4bytes(offset 44)
 // checksum before 48:   8bytes(offset 48)
 // data begin: 64 bytes
+//
+// A frame is a whole page, or the delta against the last frame of the page:
+// | frame header | base frame position(8 bytes) | page delta |
+//
+// With the encryption, the page and the delta are encrypted,
+// the base frame position is not, so the frames are recovered without the key.
 pub(super) struct JournalManager {
     file_path:         PathBuf,
-    journal_file:      RefCell<File>,
+    journal_file:      RefCell<Box<dyn VfsFile>>,
     version:           [u8; 4],
     page_size:         NonZeroU32,
     salt1:             u32,
@@ -50,6 +66,24 @@ pub(super) struct JournalManager {
 
     // count of all frames
     count:             u32,
+
+    // the end of the committed frames
+    end:               u64,
+    last_frame:        Option<u64>,
+
+    // file_position => depth of the delta frames
+    delta_depth:       HashMap<u64, u32>,
+
+    // what is done with the journal left by the last process
+    recovery:          RecoveryReport,
+
+    // with io_uring, the frames of the transaction are kept here
+    // and submitted together on commit
+    uring:             Option<Uring>,
+    // file_position => frame, ordered by the position
+    pending_frames:    Vec<(u64, Vec<u8>)>,
+
+    cipher:            Option<PageCipher>,
 }
 
 fn generate_a_salt() -> u32 {
@@ -66,6 +100,15 @@ fn generate_a_nonzero_salt() -> NonZeroU32 {
     NonZeroU32::new(salt).unwrap()
 }
 
+fn read_base_offset(payload: &[u8]) -> std::io::Result<u64> {
+    if payload.len() < 8 {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "the delta frame is broken"));
+    }
+    let mut buffer: [u8; 8] = [0; 8];
+    buffer.copy_from_slice(&payload[0..8]);
+    Ok(u64::from_be_bytes(buffer))
+}
+
 fn crc64(bytes: &[u8]) -> u64 {
     let mut c = Digest::new();
     c.write(bytes);
@@ -74,19 +117,22 @@ fn crc64(bytes: &[u8]) -> u64 {
 
 impl JournalManager {
 
-    pub(super) fn open(path: &Path, page_size: NonZeroU32, db_file_size: u64) -> DbResult<JournalManager> {
-        let journal_file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(path)?;
-        let meta = journal_file.metadata()?;
+    pub(super) fn open(
+        vfs: &dyn Vfs,
+        path: &Path,
+        page_size: NonZeroU32,
+        db_file_size: u64,
+        cipher: Option<PageCipher>,
+    ) -> DbResult<JournalManager> {
+        let journal_existed = vfs.exists(path);
+        let journal_file = vfs.open(path, false)?;
+        let journal_len = journal_file.size()?;
 
         let file_path: PathBuf = path.to_path_buf();
         let mut result = JournalManager {
             file_path,
             journal_file: RefCell::new(journal_file),
-            version: [0, 0, 1, 0],
+            version: [0, 0, 2, 0],
             page_size,
             db_file_size,
             salt1: generate_a_salt(),
@@ -95,10 +141,25 @@ impl JournalManager {
 
             offset_map: TransMap::new(),
             count: 0,
+            end: JOURNAL_DATA_BEGIN,
+            last_frame: None,
+            delta_depth: HashMap::new(),
+            recovery: RecoveryReport::new(journal_existed),
+            uring: None,
+            pending_frames: Vec::new(),
+            cipher,
         };
 
-        if meta.len() == 0 {  // init the file
+        if journal_len == 0 {  // init the file
             result.init_header_to_file()?;
+        } else if journal_len <= JOURNAL_DATA_BEGIN {
+            // There is no frame, the header may be broken if the process died
+            // while rewriting it after a checkpoint, nothing is lost to discard it.
+            if let Err(err) = result.read_and_check_from_file() {
+                crate::polo_log!("discard the journal with a broken header: {}", err);
+                result.init_header_to_file()?;
+                result.recovery.state = RecoveryState::DiscardedBrokenHeader;
+            }
         } else {
             result.read_and_check_from_file()?;
         }
@@ -107,7 +168,11 @@ impl JournalManager {
             let mut journal_file = result.journal_file.borrow_mut();
             journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN))?;
         }
-        result.load_all_pages(meta.len())?;
+        result.load_all_pages(journal_len)?;
+
+        result.recovery.replayed_frames = result.count;
+        result.recovery.settle();
+        crate::polo_log!("recover the journal: {:?}", result.recovery);
 
         Ok(result)
     }
@@ -198,19 +263,30 @@ impl JournalManager {
     }
 
     fn new_write_state(&mut self) {
-        let offset_map = self.offset_map.clone();
-        let new_state = TransactionState::new(
-            TransactionType::Write,
-            offset_map,
-            self.count,
-            self.db_file_size
-        );
+        let new_state = self.new_state(TransactionType::Write);
         self.transaction_state = Some(new_state);
     }
 
+    /// The size of the payload of a whole page.
+    #[inline]
+    fn page_payload_size(&self) -> u64 {
+        match &self.cipher {
+            Some(_) => (self.page_size.get() as u64) + CIPHER_OVERHEAD,
+            None => self.page_size.get() as u64,
+        }
+    }
+
     #[inline]
     fn full_frame_size(&self) -> u64 {
-        (self.page_size.get() as u64) + FRAME_HEADER_SIZE
+        self.page_payload_size() + FRAME_HEADER_SIZE
+    }
+
+    #[inline]
+    fn payload_size(&self, frame_header: &FrameHeader) -> u64 {
+        match frame_header.delta_size {
+            0 => self.page_payload_size(),
+            delta_size => delta_size as u64,
+        }
     }
 
     fn load_all_pages(&mut self, file_size: u64) -> DbResult<()> {
@@ -218,26 +294,39 @@ impl JournalManager {
             let mut journal_file = self.journal_file.borrow_mut();
             journal_file.seek(SeekFrom::Current(0))?
         };
-        let frame_size = self.full_frame_size();
 
-        while current_pos + frame_size <= file_size {
+        while current_pos + FRAME_HEADER_SIZE <= file_size {
             if self.transaction_state.is_none() {
                 self.new_write_state();
             }
 
-            let mut buffer = vec![];
-            buffer.resize(frame_size as usize, 0);
-
+            let mut header = [0u8; FRAME_HEADER_SIZE as usize];
             {
                 let mut journal_file = self.journal_file.borrow_mut();
-                journal_file.read_exact(&mut buffer)?;
+                journal_file.read_exact(&mut header)?;
             }
 
+            let payload_size = self.payload_size(&FrameHeader::from_bytes(&header[0..24]));
+            let frame_end = current_pos + FRAME_HEADER_SIZE + payload_size;
             let is_commit = Cell::new(false);
-            match self.check_and_load_frame(current_pos, &buffer, &is_commit) {
+            let load_result = if frame_end > file_size {
+                Err(DbErr::ChecksumMismatch)
+            } else {
+                let mut payload = vec![0; payload_size as usize];
+                {
+                    let mut journal_file = self.journal_file.borrow_mut();
+                    journal_file.read_exact(&mut payload)?;
+                }
+                self.check_and_load_frame(current_pos, &header, &payload, &is_commit)
+            };
+
+            match load_result {
                 Ok(()) => (),
                 Err(DbErr::SaltMismatch) |
                 Err(DbErr::ChecksumMismatch) => {
+                    // the size of the broken frames is unknown, count them as the whole pages
+                    let broken_frames = (file_size - current_pos) / self.full_frame_size();
+                    self.recovery.discarded_frames += broken_frames.max(1) as u32;
                     let mut journal_file = self.journal_file.borrow_mut();
                     journal_file.set_len(current_pos)?;  // trim the tail
                     journal_file.seek(SeekFrom::End(0))?;  // recover position
@@ -248,10 +337,9 @@ impl JournalManager {
 
             let state = self.transaction_state.as_mut().unwrap();
             state.frame_count += 1;
-            current_pos = {
-                let mut journal_file = self.journal_file.borrow_mut();
-                journal_file.seek(SeekFrom::Current(0))?
-            };
+            state.end = frame_end;
+            state.last_frame = Some(current_pos);
+            current_pos = frame_end;
 
             if is_commit.get() {
                 self.merge_transaction_state();
@@ -259,7 +347,8 @@ impl JournalManager {
         }
 
         // remain transaction, abandon
-        if self.transaction_state.is_some() {
+        if let Some(state) = &self.transaction_state {
+            self.recovery.discarded_frames += state.frame_count - self.count;
             self.recover_file_and_state()?;
         }
 
@@ -268,35 +357,34 @@ impl JournalManager {
 
     fn recover_file_and_state(&mut self) -> DbResult<()> {
         self.transaction_state = None;
-        let frame_size = FRAME_HEADER_SIZE + (self.page_size.get() as u64);
-        let expected_journal_file_size = JOURNAL_DATA_BEGIN + frame_size * (self.count as u64);
+        self.pending_frames.clear();
         let mut journal_file = self.journal_file.borrow_mut();
-        journal_file.set_len(expected_journal_file_size)?;
+        journal_file.set_len(self.end)?;
         journal_file.seek(SeekFrom::End(0))?;
         Ok(())
     }
 
-    fn check_and_load_frame(&mut self, current_pos: u64, bytes: &[u8], is_commit: &Cell<bool>) -> DbResult<()> {
-        let frame_header = FrameHeader::from_bytes(&bytes[0..24]);
+    fn check_and_load_frame(&mut self, current_pos: u64, header: &[u8], payload: &[u8], is_commit: &Cell<bool>) -> DbResult<()> {
+        let frame_header = FrameHeader::from_bytes(&header[0..24]);
         let checksum1 = {
             let mut buffer: [u8; 8] = [0; 8];
-            buffer.copy_from_slice(&bytes[24..32]);
+            buffer.copy_from_slice(&header[24..32]);
             u64::from_be_bytes(buffer)
         };
 
         let checksum2 = {
             let mut buffer: [u8; 8] = [0; 8];
-            buffer.copy_from_slice(&bytes[32..40]);
+            buffer.copy_from_slice(&header[32..40]);
             u64::from_be_bytes(buffer)
         };
 
-        let actual_header_checksum = crc64(&bytes[0..24]);
+        let actual_header_checksum = crc64(&header[0..24]);
 
         if actual_header_checksum != checksum1 {
             return Err(DbErr::ChecksumMismatch);
         }
 
-        let actual_page_checksum = crc64(&bytes[(FRAME_HEADER_SIZE as usize)..]);
+        let actual_page_checksum = crc64(payload);
 
         if actual_page_checksum != checksum2 {
             return Err(DbErr::ChecksumMismatch);
@@ -306,6 +394,14 @@ impl JournalManager {
             return Err(DbErr::SaltMismatch);
         }
 
+        if frame_header.delta_size != 0 {
+            let base_offset = read_base_offset(payload)?;
+            let depth = self.delta_depth.get(&base_offset).cloned().unwrap_or(0) + 1;
+            self.delta_depth.insert(current_pos, depth);
+        } else {
+            self.delta_depth.remove(&current_pos);
+        }
+
         // load frame
         let transaction = self.transaction_state.as_mut().unwrap();
         transaction.offset_map.insert(frame_header.page_id, current_pos);
@@ -322,13 +418,26 @@ impl JournalManager {
         let state = self.transaction_state.take().unwrap();
         self.db_file_size = state.db_file_size;
         self.count = state.frame_count;
+        self.end = state.end;
+        self.last_frame = state.last_frame;
         self.offset_map = state.offset_map.commit();
         (state.ty, state.frame_count)
     }
 
     pub(super) fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
         if let Some(state) = &mut self.transaction_state {
-            state.db_file_size += size;
+            if size > state.db_file_size {
+                state.db_file_size = size;
+            }
+            return Ok(());
+        }
+        Err(DbErr::CannotWriteDbWithoutTransaction)
+    }
+
+    /// The main file is truncated to the size by the checkpoint after the commit.
+    pub(super) fn shrink_db_size(&mut self, size: u64) -> DbResult<()> {
+        if let Some(state) = &mut self.transaction_state {
+            state.db_file_size = size;
             return Ok(());
         }
         Err(DbErr::CannotWriteDbWithoutTransaction)
@@ -343,8 +452,8 @@ impl JournalManager {
 
     fn update_last_frame(&mut self) -> DbResult<()> {
         let mut journal_file = self.journal_file.borrow_mut();
-        let full_frame_size = self.full_frame_size();
-        let begin_loc = journal_file.seek(SeekFrom::End((full_frame_size as i64) * -1))?;
+        let begin_loc = self.last_frame.unwrap();
+        journal_file.seek(SeekFrom::Start(begin_loc))?;
         let mut data: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
         journal_file.read_exact(&mut data)?;
         let mut frame_header = FrameHeader::from_bytes(&data);
@@ -367,11 +476,39 @@ impl JournalManager {
         Ok(())
     }
 
+    /// Mark the last pending frame as the commit, and write all the frames
+    /// by one submission of io_uring. The frames may reach the disk in any order,
+    /// a frame lost before the commit frame is detected by the checksum
+    /// on recovery, and the whole transaction is discarded.
+    fn submit_pending_frames(&mut self, db_file_size: u64) -> DbResult<()> {
+        {
+            let (_, last_frame) = self.pending_frames.last_mut().unwrap();
+            let mut frame_header = FrameHeader::from_bytes(&last_frame[0..24]);
+            frame_header.db_size = db_file_size;
+
+            let mut header24: [u8; 24] = [0; 24];
+            frame_header.to_bytes(&mut header24);
+            last_frame[0..24].copy_from_slice(&header24);
+            last_frame[24..32].copy_from_slice(&crc64(&header24).to_be_bytes());
+        }
+
+        let writes: Vec<(u64, &[u8])> = self.pending_frames.iter()
+            .map(|(pos, frame)| (*pos, frame.as_slice()))
+            .collect();
+        let journal_file = self.journal_file.borrow();
+        let os_file = journal_file.as_os_file().unwrap();
+        self.uring.as_mut().unwrap().write_all_at(os_file, &writes)?;
+        drop(journal_file);
+
+        self.pending_frames.clear();
+        Ok(())
+    }
+
     // frame_header: 24 bytes
     // checksum1:    8 bytes(offset 24)  header24 checksum
     // checksum2:    8 bytes(offset 32)  page checksum
     // data_begin:   page size(offset 40)
-    fn append_frame_header(journal_file: &mut File, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
+    fn append_frame_header<W: Write>(journal_file: &mut W, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
         let mut header24: [u8; 24] = [0; 24];
         frame_header.to_bytes(&mut header24);
 
@@ -387,49 +524,117 @@ impl JournalManager {
         Ok(())
     }
 
-    pub(super) fn append_raw_page(&mut self, raw_page: &RawPage) -> DbResult<()> {
+    /// Encode the page as the delta against `previous`, which is the content
+    /// of the last frame of the page. Return the payload and the depth of the delta.
+    fn encode_delta(&self, state: &TransactionState, raw_page: &RawPage, previous: Option<&RawPage>) -> Option<(Vec<u8>, u32)> {
+        let previous = previous?;
+        if self.page_size.get() > (u16::MAX as u32) + 1 {
+            return None;
+        }
+        let base_offset = *state.offset_map.get(&raw_page.page_id)?;
+        let depth = self.delta_depth.get(&base_offset).cloned().unwrap_or(0) + 1;
+        if depth > MAX_DELTA_DEPTH {
+            return None;
+        }
+
+        // the whole page is written if the delta saves less than a half
+        let limit = (self.page_size.get() as usize) / 2 - 8;
+        let delta = page_delta::encode(&previous.data, &raw_page.data, limit)?;
+
+        let mut payload = Vec::with_capacity(8 + delta.len());
+        payload.extend_from_slice(&base_offset.to_be_bytes());
+        payload.extend_from_slice(&delta);
+        Some((payload, depth))
+    }
+
+    /// Append the page as a frame. If `previous` is given, the delta against it
+    /// may be written instead of the whole page.
+    pub(super) fn append_raw_page(&mut self, raw_page: &RawPage, previous: Option<&RawPage>) -> DbResult<()> {
         let state = match &self.transaction_state {
             Some(state) if state.ty == TransactionType::Write => state,
             _ => return Err(DbErr::CannotWriteDbWithoutTransaction),
         };
 
-        let mut journal_file = self.journal_file.borrow_mut();
-        let start_pos: u64 = JOURNAL_DATA_BEGIN + (state.frame_count as u64) * (self.page_size.get() as u64 + FRAME_HEADER_SIZE);
-        journal_file.seek(SeekFrom::Start(start_pos))?;
+        let start_pos: u64 = state.end;
+        let delta = self.encode_delta(state, raw_page, previous);
+        let encrypted = match (&self.cipher, &delta) {
+            (Some(cipher), Some((payload, _))) => {
+                let mut encrypted = payload[0..8].to_vec();
+                encrypted.extend_from_slice(&cipher.encrypt(raw_page.page_id, &payload[8..])?);
+                Some(encrypted)
+            }
+            (Some(cipher), None) => Some(cipher.encrypt(raw_page.page_id, &raw_page.data)?),
+            (None, _) => None,
+        };
+        let payload = match (&encrypted, &delta) {
+            (Some(encrypted), _) => encrypted.as_slice(),
+            (None, Some((payload, _))) => payload.as_slice(),
+            (None, None) => raw_page.data.as_slice(),
+        };
+        let delta_size = match &delta {
+            Some(_) => payload.len() as u32,
+            None => 0,
+        };
 
         let frame_header = FrameHeader {
             page_id: raw_page.page_id,
+            delta_size,
             db_size: 0,
             salt1: self.salt1,
             salt2: self.salt2,
         };
 
         // calculate checksum of page data
-        let checksum2 = crc64(&raw_page.data);
+        let checksum2 = crc64(payload);
 
-        JournalManager::append_frame_header(&mut journal_file, &frame_header, checksum2)?;
+        if self.uring.is_some() {
+            let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE as usize + payload.len());
+            JournalManager::append_frame_header(&mut frame, &frame_header, checksum2)?;
+            frame.extend_from_slice(payload);
+            self.pending_frames.push((start_pos, frame));
+        } else {
+            let mut journal_file = self.journal_file.borrow_mut();
+            journal_file.seek(SeekFrom::Start(start_pos))?;
+
+            JournalManager::append_frame_header(&mut *journal_file, &frame_header, checksum2)?;
 
-        journal_file.write_all(&raw_page.data)?;
+            journal_file.write_all(payload)?;
+        }
+
+        let frame_end = start_pos + FRAME_HEADER_SIZE + payload.len() as u64;
+        match delta {
+            Some((_, depth)) => {
+                self.delta_depth.insert(start_pos, depth);
+            }
+            None => {
+                self.delta_depth.remove(&start_pos);
+            }
+        }
 
         let state = self.transaction_state.as_mut().unwrap();
         state.offset_map.insert(raw_page.page_id, start_pos);
         state.frame_count += 1;
+        state.end = frame_end;
+        state.last_frame = Some(start_pos);
 
         let expected_db_size = (raw_page.page_id as u64 + 1) * (self.page_size.get() as u64);
         if expected_db_size > state.db_file_size {
             state.db_file_size = expected_db_size;
         }
 
-        crate::polo_log!("append page to journal, page_id: {}, start_pos:\t\t0x{:0>8X}", raw_page.page_id, start_pos);
+        crate::polo_log!(
+            "append page to journal, page_id: {}, delta_size: {}, start_pos:\t\t0x{:0>8X}",
+            raw_page.page_id, delta_size, start_pos
+        );
 
         Ok(())
     }
 
-    pub(crate) fn read_page_main(&self, page_id: u32) -> std::io::Result<Option<Arc<RawPage>>> {
+    pub(crate) fn read_page_main(&self, page_id: u32) -> DbResult<Option<Arc<RawPage>>> {
         self.read_page(page_id, self.transaction_state.as_ref())
     }
 
-    pub(crate) fn read_page(&self, page_id: u32, state: Option<&TransactionState>) -> std::io::Result<Option<Arc<RawPage>>> {
+    pub(crate) fn read_page(&self, page_id: u32, state: Option<&TransactionState>) -> DbResult<Option<Arc<RawPage>>> {
         let offset = match state {
 
             // currently in transaction state
@@ -458,38 +663,128 @@ impl JournalManager {
 
         };
 
-        let data_offset = offset + FRAME_HEADER_SIZE;
-
-        let mut journal_file = self.journal_file.borrow_mut();
-        let mut result = RawPage::new(page_id, self.page_size);
-        result.read_from_file(&mut journal_file, data_offset)?;
+        let result = self.read_frame_page(page_id, offset)?;
 
         crate::polo_log!("read page from journal, page_id: {}, data_offset:\t\t0x{:0>8X}", page_id, offset);
 
         Ok(Some(Arc::new(result)))
     }
 
-    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut File) -> DbResult<()> {
+    /// Read the payload of the frame at `offset`, the whole page or the delta.
+    fn read_frame_payload(&self, page_id: u32, offset: u64) -> DbResult<Vec<u8>> {
+        let is_delta = self.delta_depth.contains_key(&offset);
+        let payload = if let Ok(index) = self.pending_frames.binary_search_by_key(&offset, |(pos, _)| *pos) {
+            let frame = &self.pending_frames[index].1;
+            frame[(FRAME_HEADER_SIZE as usize)..].to_vec()
+        } else {
+            let mut journal_file = self.journal_file.borrow_mut();
+            let payload_size = if is_delta {
+                let mut header = [0u8; 24];
+                journal_file.seek(SeekFrom::Start(offset))?;
+                journal_file.read_exact(&mut header)?;
+                self.payload_size(&FrameHeader::from_bytes(&header))
+            } else {
+                self.page_payload_size()
+            };
+
+            let mut payload = vec![0; payload_size as usize];
+            journal_file.seek(SeekFrom::Start(offset + FRAME_HEADER_SIZE))?;
+            journal_file.read_exact(&mut payload)?;
+            payload
+        };
+
+        match &self.cipher {
+            Some(cipher) if is_delta => {
+                let base_offset = read_base_offset(&payload)?;
+                let mut result = base_offset.to_be_bytes().to_vec();
+                result.extend_from_slice(&cipher.decrypt(page_id, &payload[8..])?);
+                Ok(result)
+            }
+            Some(cipher) => cipher.decrypt(page_id, &payload),
+            None => Ok(payload),
+        }
+    }
+
+    /// Read the page of the frame at `offset`, the deltas are applied on the last whole page.
+    fn read_frame_page(&self, page_id: u32, offset: u64) -> DbResult<RawPage> {
+        let mut deltas = vec![];
+        let mut offset = offset;
+        while self.delta_depth.contains_key(&offset) {
+            let payload = self.read_frame_payload(page_id, offset)?;
+            offset = read_base_offset(&payload)?;
+            deltas.push(payload);
+        }
+
+        let mut result = RawPage::new(page_id, self.page_size);
+        result.data.copy_from_slice(&self.read_frame_payload(page_id, offset)?);
+        for delta in deltas.iter().rev() {
+            page_delta::apply(&mut result.data, &delta[8..])?;
+        }
+
+        Ok(result)
+    }
+
+    /// Write the pages of the journal into the main file, then truncate the journal.
+    ///
+    /// A power failure while the pages are written may leave a torn page in the main file.
+    /// The journal keeps the full image of every page it has written, a delta frame is
+    /// applied on a frame of the journal instead of the main file. So the journal is synced
+    /// before the main file is touched, and it's truncated only after the main file is synced.
+    /// The torn pages are rewritten from the journal when the database is opened again.
+    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut DbFile) -> DbResult<()> {
         debug_assert!(self.transaction_state.is_none());
 
-        db_file.set_len(self.db_file_size)?;
+        if self.count > 0 {
+            self.journal_file.borrow().sync()?;
+        }
+
+        if db_file.len()? < self.db_file_size {
+            db_file.preallocate(self.db_file_size)?;
+        } else {
+            db_file.set_len(self.db_file_size)?;
+        }
+
+        if self.count > 0 {
+            // the LSN is counted by the file, the header page in the journal may be outdated
+            let mut header_page = RawPage::new(0, self.page_size);
+            db_file.read_page(&mut header_page, 0)?;
+            let checkpoint_lsn = HeaderPageWrapper::from_raw_page(header_page.clone())
+                .get_checkpoint_lsn()
+                .unwrap_or(0) + (self.count as u64);
 
-        {
             let mut offset_map = BTreeMap::new();
             self.offset_map.traverse(&mut offset_map);
 
-            let mut journal_file = self.journal_file.borrow_mut();
-            for (page_id, offset) in offset_map {
-                let data_offset = offset + FRAME_HEADER_SIZE;
+            if let Some(offset) = offset_map.remove(&0) {
+                header_page = self.read_frame_page(0, offset)?;
+            }
+            let mut header_wrapper = HeaderPageWrapper::from_raw_page(header_page);
+            if let Err(err) = header_wrapper.set_checkpoint_lsn(checkpoint_lsn) {
+                crate::polo_log!("write the checkpoint LSN failed: {}", err);
+            }
+            if let Err(err) = header_wrapper.set_checkpoint_at(bson::DateTime::now().timestamp_millis()) {
+                crate::polo_log!("write the checkpoint time failed: {}", err);
+            }
 
-                let mut result = RawPage::new(page_id, self.page_size);
-                result.read_from_file(&mut journal_file, data_offset)?;
+            // the pages are written in batches sorted by the offsets, the adjacent pages
+            // of a batch are combined into one sequential write, which is cheaper than
+            // the random writes on the HDDs and the SD cards
+            let mut batch = Vec::with_capacity(CHECKPOINT_BATCH_SIZE);
+            batch.push((0, header_wrapper.0));
+            for (page_id, offset) in offset_map {
+                let result = self.read_frame_page(page_id, offset)?;
 
-                result.sync_to_file(db_file, (page_id as u64) * (self.page_size.get() as u64))?;
+                batch.push(((page_id as u64) * (self.page_size.get() as u64), result));
+                if batch.len() >= CHECKPOINT_BATCH_SIZE {
+                    db_file.write_pages(&batch)?;
+                    batch.clear();
+                }
             }
+            db_file.write_pages(&batch)?;
         }
 
         db_file.flush()?;  // only checkpoint flush the file
+        db_file.sync()?;
 
         self.checkpoint_finished()
     }
@@ -510,6 +805,9 @@ impl JournalManager {
 
         // clear all data
         self.count = 0;
+        self.end = JOURNAL_DATA_BEGIN;
+        self.last_frame = None;
+        self.delta_depth.clear();
 
         self.offset_map = TransMap::new();
 
@@ -523,7 +821,9 @@ impl JournalManager {
             ty,
             self.offset_map.clone(),
             self.count,
-            self.db_file_size
+            self.db_file_size,
+            self.end,
+            self.last_frame,
         )
     }
 
@@ -532,24 +832,21 @@ impl JournalManager {
             return Err(DbErr::StartTransactionInAnotherTransaction);
         }
 
-        let mut journal_file = self.journal_file.borrow_mut();
+        let journal_file = self.journal_file.borrow_mut();
         match ty {
             TransactionType::Read => {
-                shared_lock_file(&mut journal_file)?;
+                journal_file.lock(VfsLock::Shared)?;
             }
 
             TransactionType::Write => {
-                exclusive_lock_file(&mut journal_file)?;
+                journal_file.lock(VfsLock::Exclusive)?;
             }
 
         }
 
-        let new_state = TransactionState::new(
-            ty,
-            self.offset_map.clone(),
-            self.count,
-            self.db_file_size
-        );
+        drop(journal_file);
+
+        let new_state = self.new_state(ty);
         self.transaction_state = Some(new_state);
 
         Ok(())
@@ -560,13 +857,21 @@ impl JournalManager {
             return Err(DbErr::CannotWriteDbWithoutTransaction);
         }
 
-        let (transaction_ty, frame_count) = self.merge_transaction_state();
-        if transaction_ty == TransactionType::Write && frame_count > 0 {
-            self.update_last_frame()?;
+        if !self.pending_frames.is_empty() {
+            // the state is merged after the frames are written,
+            // so the transaction can still be rolled back if the submission fails
+            let db_file_size = self.transaction_state.as_ref().unwrap().db_file_size;
+            self.submit_pending_frames(db_file_size)?;
+            self.merge_transaction_state();
+        } else {
+            let (transaction_ty, frame_count) = self.merge_transaction_state();
+            if transaction_ty == TransactionType::Write && frame_count > 0 {
+                self.update_last_frame()?;
+            }
         }
         {
-            let mut journal_file = self.journal_file.borrow_mut();
-            unlock_file(&mut journal_file)?;
+            let journal_file = self.journal_file.borrow();
+            journal_file.lock(VfsLock::Unlock)?;
         }
 
         Ok(())
@@ -579,8 +884,8 @@ impl JournalManager {
 
         self.recover_file_and_state()?;
         {
-            let mut journal_file = self.journal_file.borrow_mut();
-            unlock_file(&mut journal_file)?;
+            let journal_file = self.journal_file.borrow();
+            journal_file.lock(VfsLock::Unlock)?;
         }
 
         Ok(())
@@ -589,8 +894,8 @@ impl JournalManager {
     pub(crate) fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
         debug_assert!(self.transaction_state.is_some(), "can not upgrade transaction because there is no transaction");
 
-        let mut journal_file = self.journal_file.borrow_mut();
-        exclusive_lock_file(&mut journal_file)?;
+        let journal_file = self.journal_file.borrow_mut();
+        journal_file.lock(VfsLock::Exclusive)?;
 
         if let Some(state) = &mut self.transaction_state {
             state.set_type(TransactionType::Write);
@@ -598,11 +903,23 @@ impl JournalManager {
         Ok(())
     }
 
+    /// Append the frames by io_uring, experimental.
+    pub(super) fn set_uring(&mut self, uring: Option<Uring>) {
+        if self.journal_file.borrow().as_os_file().is_some() {
+            self.uring = uring;
+        }
+    }
+
     #[inline]
     pub(crate) fn path(&self) -> &Path {
         self.file_path.as_path()
     }
 
+    #[inline]
+    pub(crate) fn recovery_report(&self) -> &RecoveryReport {
+        &self.recovery
+    }
+
     #[inline]
     pub(crate) fn len(&self) -> u32 {
         self.count
@@ -612,11 +929,13 @@ impl JournalManager {
         self.transaction_state.as_ref().map(|state| state.ty)
     }
 
-    #[allow(dead_code)]
     pub(crate) fn dump(&mut self) -> DbResult<JournalDump> {
         let file_meta = {
             let journal_file = self.journal_file.borrow();
-            journal_file.metadata()?
+            let os_file = journal_file.as_os_file().ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::Other, "the journal is not a file of the OS")
+            })?;
+            os_file.metadata()?
         };
         let frames = self.dump_frames()?;
         let dump = JournalDump {
@@ -632,18 +951,19 @@ impl JournalManager {
         let mut result = vec![];
         let mut journal_file = self.journal_file.borrow_mut();
 
+        let mut frame_header_offset = JOURNAL_DATA_BEGIN;
         for index in 0..self.count {
-            let frame_header_offset: u64 =
-                JOURNAL_DATA_BEGIN + (self.page_size.get() as u64 + FRAME_HEADER_SIZE) * (index as u64);
-
             let mut header_buffer: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
             journal_file.seek(SeekFrom::Start(frame_header_offset))?;
             journal_file.read_exact(&mut header_buffer)?;
 
             let header = FrameHeader::from_bytes(&header_buffer);
+            frame_header_offset += FRAME_HEADER_SIZE + self.payload_size(&header);
 
             result.push(JournalFrameDump {
                 frame_id: index,
+                page_id: header.page_id,
+                delta_size: header.delta_size,
                 db_size: header.db_size,
                 salt1: header.salt1,
                 salt2: header.salt2,
@@ -662,6 +982,7 @@ mod tests {
     use crate::page::RawPage;
     use crate::TransactionType;
     use crate::backend::file::journal_manager::JournalManager;
+    use crate::backend::file::OsVfs;
     use std::env;
 
     static TEST_PAGE_LEN: u32 = 100;
@@ -698,7 +1019,7 @@ mod tests {
     fn test_journal() {
         let journal_path = prepare_journal_path("test-journal");
         let mut journal_manager = JournalManager::open(
-            journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+            &OsVfs, journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096, None
         ).unwrap();
 
         journal_manager.start_transaction(TransactionType::Write).unwrap();
@@ -710,7 +1031,7 @@ mod tests {
         }
 
         for item in &ten_pages {
-            journal_manager.append_raw_page(item).unwrap();
+            journal_manager.append_raw_page(item, None).unwrap();
         }
 
         for i in 0..TEST_PAGE_LEN {
@@ -732,7 +1053,7 @@ mod tests {
         let mem_count;
         {
             let mut journal_manager = JournalManager::open(
-                journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+                &OsVfs, journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096, None
             ).unwrap();
 
             journal_manager.start_transaction(TransactionType::Write).unwrap();
@@ -744,7 +1065,7 @@ mod tests {
             }
 
             for item in &ten_pages {
-                journal_manager.append_raw_page(item).unwrap();
+                journal_manager.append_raw_page(item, None).unwrap();
             }
 
             journal_manager.commit().unwrap();
@@ -752,9 +1073,49 @@ mod tests {
         }
 
         let journal_manager = JournalManager::open(
-            journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+            &OsVfs, journal_path.as_ref(), NonZeroU32::new(4096).unwrap(), 4096, None
         ).unwrap();
         assert_eq!(mem_count, journal_manager.count);
     }
 
+    #[test]
+    fn test_delta_frames() {
+        let journal_path = prepare_journal_path("test-journal-delta");
+        let page_size = NonZeroU32::new(4096).unwrap();
+
+        let mut pages = vec![make_raw_page(1)];
+        {
+            let mut journal_manager = JournalManager::open(
+                &OsVfs, journal_path.as_ref(), page_size, 4096, None
+            ).unwrap();
+
+            journal_manager.start_transaction(TransactionType::Write).unwrap();
+            journal_manager.append_raw_page(&pages[0], None).unwrap();
+            journal_manager.commit().unwrap();
+
+            // more deltas than the max depth, a whole page is written among them
+            for i in 0..20 {
+                let mut page = pages.last().unwrap().clone();
+                page.data[i * 10] = page.data[i * 10].wrapping_add(1);
+
+                journal_manager.start_transaction(TransactionType::Write).unwrap();
+                journal_manager.append_raw_page(&page, pages.last()).unwrap();
+                journal_manager.commit().unwrap();
+
+                let result = journal_manager.read_page_main(1).unwrap().unwrap();
+                assert_eq!(result.data, page.data);
+                pages.push(page);
+            }
+            assert!(!journal_manager.delta_depth.is_empty());
+            assert!(journal_manager.end < 64 + 10 * (4096 + 40));
+        }
+
+        let journal_manager = JournalManager::open(
+            &OsVfs, journal_path.as_ref(), page_size, 4096, None
+        ).unwrap();
+        assert_eq!(journal_manager.count, 21);
+        let result = journal_manager.read_page_main(1).unwrap().unwrap();
+        assert_eq!(result.data, pages.last().unwrap().data);
+    }
+
 }
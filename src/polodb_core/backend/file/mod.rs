@@ -3,11 +3,33 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+mod backup;
+mod db_file;
+mod page_delta;
 mod frame_header;
 mod transaction_state;
 mod journal_manager;
 mod file_backend;
 mod file_lock;
-mod pagecache;
+mod inspect_backend;
+mod os_vfs;
+mod preallocate;
+mod recovery;
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
+#[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+mod uring_unsupported;
+#[cfg(not(all(feature = "io_uring", target_os = "linux")))]
+use uring_unsupported as uring;
+#[cfg(feature = "encryption")]
+mod cipher;
+#[cfg(not(feature = "encryption"))]
+mod cipher_unsupported;
+#[cfg(not(feature = "encryption"))]
+use cipher_unsupported as cipher;
 
+pub(crate) use backup::BackupFile;
 pub(crate) use file_backend::FileBackend;
+pub(crate) use inspect_backend::InspectBackend;
+pub use os_vfs::OsVfs;
+pub use recovery::{RecoveryReport, RecoveryState};
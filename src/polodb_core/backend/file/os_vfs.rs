@@ -0,0 +1,179 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::DbResult;
+use crate::vfs::{Vfs, VfsFile, VfsLock};
+use super::file_lock::{exclusive_lock_file, shared_lock_file, unlock_file};
+use super::preallocate::{preallocate, punch_hole};
+
+/// The file system of the OS, used if [`Config::vfs`] is not set.
+///
+/// [`Config::vfs`]: crate::Config::vfs
+#[derive(Debug, Default, Clone, Copy)]
+pub struct OsVfs;
+
+impl Vfs for OsVfs {
+
+    /// The direct I/O is ignored if the platform or the file system doesn't support it.
+    fn open(&self, path: &Path, direct_io: bool) -> DbResult<Box<dyn VfsFile>> {
+        let file = match open_file_shared(path, direct_io) {
+            Ok(file) => {
+                if direct_io && !disable_os_cache(&file) {
+                    crate::polo_log!("direct I/O is not supported on this platform");
+                }
+                file
+            }
+            Err(err) if direct_io => {
+                crate::polo_log!("direct I/O is not supported: {}", err);
+                open_file_shared(path, false)?
+            }
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Box::new(OsFile(file)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn remove(&self, path: &Path) -> DbResult<()> {
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+}
+
+struct OsFile(File);
+
+impl Read for OsFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+
+}
+
+impl Write for OsFile {
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+
+}
+
+impl Seek for OsFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+
+}
+
+impl VfsFile for OsFile {
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.0.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.0.set_len(len)
+    }
+
+    fn preallocate(&self, len: u64) -> std::io::Result<()> {
+        preallocate(&self.0, len)
+    }
+
+    fn punch_hole(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        punch_hole(&self.0, offset, len)
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.0.sync_data()
+    }
+
+    fn lock(&self, lock: VfsLock) -> DbResult<()> {
+        match lock {
+            VfsLock::Shared => shared_lock_file(&self.0),
+            VfsLock::Exclusive => exclusive_lock_file(&self.0),
+            VfsLock::Unlock => unlock_file(&self.0),
+        }
+    }
+
+    fn as_os_file(&self) -> Option<&File> {
+        Some(&self.0)
+    }
+
+}
+
+// The file can be opened by other processes on both platforms,
+// the access is decided by the locks in `file_lock`.
+#[cfg(target_os = "windows")]
+fn open_file_shared(path: &Path, direct_io: bool) -> std::io::Result<File> {
+    use std::os::windows::prelude::OpenOptionsExt;
+    use winapi::um::winbase::FILE_FLAG_NO_BUFFERING;
+    use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE};
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .read(true)
+        .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE)
+        .custom_flags(if direct_io { FILE_FLAG_NO_BUFFERING } else { 0 })
+        .open(path)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn open_file_shared(path: &Path, direct_io: bool) -> std::io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .read(true)
+        .custom_flags(if direct_io { libc::O_DIRECT } else { 0 })
+        .open(path)
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "linux", target_os = "android")))]
+fn open_file_shared(path: &Path, _direct_io: bool) -> std::io::Result<File> {
+    std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .read(true)
+        .open(path)
+}
+
+/// Turn off the cache of the file opened, return `false` if it's not supported.
+/// The flags given on open are enough on Linux and Windows.
+#[cfg(any(target_os = "windows", target_os = "linux", target_os = "android"))]
+fn disable_os_cache(_file: &File) -> bool {
+    true
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn disable_os_cache(file: &File) -> bool {
+    use std::os::unix::prelude::*;
+
+    let result = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_NOCACHE, 1) };
+    result != -1
+}
+
+#[cfg(not(any(
+    target_os = "windows",
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+)))]
+fn disable_os_cache(_file: &File) -> bool {
+    false
+}
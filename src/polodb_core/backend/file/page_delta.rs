@@ -0,0 +1,110 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// The changed ranges of a page against its last image in the journal:
+// | 2b     | 2b     | len bytes |
+// | offset | len    | content   | ...
+const RECORD_HEADER_SIZE: usize = 4;
+
+// the ranges closer than it are merged, a new record costs more
+const MERGE_GAP: usize = RECORD_HEADER_SIZE * 2;
+
+/// Encode the ranges of `page` different from `base`, return `None` if
+/// the delta is larger than `limit` bytes, the whole page is written then.
+/// The offsets are 16 bits, the pages must be 64 KiB at most.
+pub(super) fn encode(base: &[u8], page: &[u8], limit: usize) -> Option<Vec<u8>> {
+    debug_assert_eq!(base.len(), page.len());
+    debug_assert!(page.len() <= (u16::MAX as usize) + 1);
+
+    let mut result = vec![];
+    let mut index = 0;
+    while index < page.len() {
+        if base[index] == page[index] {
+            index += 1;
+            continue;
+        }
+
+        let begin = index;
+        let mut end = index + 1;
+        let mut probe = end;
+        while probe < page.len() && probe - end < MERGE_GAP {
+            if base[probe] != page[probe] {
+                end = probe + 1;
+            }
+            probe += 1;
+        }
+
+        if result.len() + RECORD_HEADER_SIZE + (end - begin) > limit {
+            return None;
+        }
+        result.extend_from_slice(&(begin as u16).to_be_bytes());
+        result.extend_from_slice(&((end - begin) as u16).to_be_bytes());
+        result.extend_from_slice(&page[begin..end]);
+
+        index = end;
+    }
+
+    Some(result)
+}
+
+/// Apply the delta encoded by [`encode`] to the page.
+pub(super) fn apply(page: &mut [u8], mut delta: &[u8]) -> std::io::Result<()> {
+    while !delta.is_empty() {
+        if delta.len() < RECORD_HEADER_SIZE {
+            return Err(broken_delta());
+        }
+        let offset = u16::from_be_bytes([delta[0], delta[1]]) as usize;
+        let len = u16::from_be_bytes([delta[2], delta[3]]) as usize;
+        let content = &delta[RECORD_HEADER_SIZE..];
+        if content.len() < len || offset + len > page.len() {
+            return Err(broken_delta());
+        }
+        page[offset..(offset + len)].copy_from_slice(&content[..len]);
+        delta = &content[len..];
+    }
+    Ok(())
+}
+
+fn broken_delta() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, "the delta of the page is broken")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, encode};
+
+    #[test]
+    fn test_delta_round_trip() {
+        let base: Vec<u8> = (0..4096).map(|i| (i % 251) as u8).collect();
+        let mut page = base.clone();
+        page[0] = 0xFF;
+        page[100] = 0xFF;
+        page[103] = 0xFF;  // merged with the last one
+        page[2000..2010].copy_from_slice(&[1; 10]);
+        page[4095] = 0xFF;
+
+        let delta = encode(&base, &page, 4096).unwrap();
+        assert!(delta.len() < 64);
+
+        let mut result = base.clone();
+        apply(&mut result, &delta).unwrap();
+        assert_eq!(result, page);
+
+        // nothing changed
+        assert_eq!(encode(&base, &base, 4096).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_delta_limit() {
+        let base = vec![0u8; 4096];
+        let page = vec![1u8; 4096];
+        assert!(encode(&base, &page, 2048).is_none());
+
+        let mut broken = base.clone();
+        assert!(apply(&mut broken, &[0x0F, 0xFF, 0x00, 0x10, 0x01]).is_err());
+    }
+
+}
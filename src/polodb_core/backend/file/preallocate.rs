@@ -0,0 +1,117 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::fs::File;
+
+/// Grow the file to `len` bytes and reserve the blocks on the disk,
+/// so the file is less fragmented and a full disk fails here instead of
+/// at the writes of the pages. It falls back to `set_len` if the file system
+/// doesn't support it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::unix::prelude::*;
+
+    let fd = file.as_raw_fd();
+    let result = unsafe {
+        libc::fallocate(fd, 0, 0, len as libc::off_t)
+    };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => file.set_len(len),
+        _ => Err(err),
+    }
+}
+
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::unix::prelude::*;
+
+    let current_len = file.metadata()?.len();
+    if len > current_len {
+        let fd = file.as_raw_fd();
+        let mut store = libc::fstore_t {
+            fst_flags: libc::F_ALLOCATECONTIG | libc::F_ALLOCATEALL,
+            fst_posmode: libc::F_PEOFPOSMODE,
+            fst_offset: 0,
+            fst_length: (len - current_len) as libc::off_t,
+            fst_bytesalloc: 0,
+        };
+        let result = unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store as *mut libc::fstore_t) };
+        if result == -1 {
+            // no contiguous space, take any space
+            store.fst_flags = libc::F_ALLOCATEALL;
+            // the failure is fine, the space is allocated by the writes
+            let _ = unsafe { libc::fcntl(fd, libc::F_PREALLOCATE, &mut store as *mut libc::fstore_t) };
+        }
+    }
+
+    // F_PREALLOCATE doesn't change the size of the file
+    file.set_len(len)
+}
+
+#[cfg(target_os = "windows")]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::fileapi::SetFileValidData;
+    use winapi::ctypes;
+
+    file.set_len(len)?;
+
+    // Skip zeroing the new space, which needs the SE_MANAGE_VOLUME_NAME privilege
+    // and fails without it. The pages beyond the written ones are never read
+    // before they are written.
+    let _ = unsafe {
+        SetFileValidData(file.as_raw_handle().cast::<ctypes::c_void>(), len as i64)
+    };
+
+    Ok(())
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "windows",
+)))]
+pub(crate) fn preallocate(file: &File, len: u64) -> std::io::Result<()> {
+    file.set_len(len)
+}
+
+/// Release the blocks of the range on the disk, the size of the file is not changed
+/// and the range reads as zeros after it. It does nothing if the file system
+/// doesn't support it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn punch_hole(file: &File, offset: u64, len: u64) -> std::io::Result<()> {
+    use std::os::unix::prelude::*;
+
+    let fd = file.as_raw_fd();
+    let result = unsafe {
+        libc::fallocate(
+            fd,
+            libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+            offset as libc::off_t,
+            len as libc::off_t,
+        )
+    };
+    if result == 0 {
+        return Ok(());
+    }
+
+    let err = std::io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EOPNOTSUPP) | Some(libc::ENOSYS) => Ok(()),
+        _ => Err(err),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn punch_hole(_file: &File, _offset: u64, _len: u64) -> std::io::Result<()> {
+    Ok(())
+}
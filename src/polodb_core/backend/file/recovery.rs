@@ -0,0 +1,60 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+/// What is done with the journal left by the last process when the database is opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryState {
+    /// The database was closed cleanly, no journal is left.
+    Clean,
+
+    /// An empty journal is left, the process died after the checkpoint
+    /// and before the journal was deleted.
+    EmptyJournal,
+
+    /// The committed transactions in the journal are checkpointed into the database file.
+    Replayed,
+
+    /// The journal has only the frames of an uncommitted transaction or a broken tail,
+    /// they are discarded.
+    DiscardedUncommitted,
+
+    /// The journal has no frame and a broken header, the process died while
+    /// the header was rewritten after a checkpoint. The journal is discarded.
+    DiscardedBrokenHeader,
+}
+
+/// The report of the recovery, returned by [`Database::open_file_with_report`].
+///
+/// [`Database::open_file_with_report`]: crate::Database::open_file_with_report
+#[derive(Debug, Clone)]
+pub struct RecoveryReport {
+    pub state:            RecoveryState,
+    /// The frames of the committed transactions replayed.
+    pub replayed_frames:  u32,
+    /// The frames of the uncommitted transaction and the broken tail.
+    pub discarded_frames: u32,
+}
+
+impl RecoveryReport {
+
+    pub(super) fn new(journal_existed: bool) -> RecoveryReport {
+        RecoveryReport {
+            state: if journal_existed { RecoveryState::EmptyJournal } else { RecoveryState::Clean },
+            replayed_frames: 0,
+            discarded_frames: 0,
+        }
+    }
+
+    /// Decide the state by the frames found in the journal.
+    pub(super) fn settle(&mut self) {
+        if self.replayed_frames > 0 {
+            self.state = RecoveryState::Replayed;
+        } else if self.discarded_frames > 0 {
+            self.state = RecoveryState::DiscardedUncommitted;
+        }
+    }
+
+}
@@ -11,16 +11,28 @@ pub(super) struct TransactionState {
     pub(super) offset_map: TransMapDraft<u32, u64>,
     pub(super) frame_count: u32,
     pub(super) db_file_size: u64,
+    // the position to append the next frame
+    pub(super) end: u64,
+    pub(super) last_frame: Option<u64>,
 }
 
 impl TransactionState {
 
-    pub(super) fn new(ty: TransactionType, offset_map: TransMap<u32, u64>, frame_count: u32, db_file_size: u64) -> TransactionState {
+    pub(super) fn new(
+        ty: TransactionType,
+        offset_map: TransMap<u32, u64>,
+        frame_count: u32,
+        db_file_size: u64,
+        end: u64,
+        last_frame: Option<u64>,
+    ) -> TransactionState {
         TransactionState {
             ty,
             offset_map: TransMapDraft::new(offset_map),
             frame_count,
             db_file_size,
+            end,
+            last_frame,
         }
     }
 
@@ -0,0 +1,108 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::fs::File;
+use std::io::{Error, ErrorKind};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use io_uring::{opcode, types, IoUring};
+
+/// The entries of the submission queue, a batch larger than it
+/// is submitted in several rounds.
+const URING_ENTRIES: u32 = 64;
+
+/// Submit the reads and writes of the file backend with io_uring, experimental.
+/// A batch of writes is submitted by one system call.
+pub(super) struct Uring {
+    ring: IoUring,
+}
+
+impl Uring {
+
+    /// Return `None` if the kernel doesn't support io_uring.
+    pub(super) fn new() -> Option<Uring> {
+        match IoUring::new(URING_ENTRIES) {
+            Ok(ring) => Some(Uring { ring }),
+            Err(err) => {
+                crate::polo_log!("io_uring is not supported: {}", err);
+                None
+            }
+        }
+    }
+
+    pub(super) fn read_exact_at(&mut self, file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+        let entry = opcode::Read::new(types::Fd(file.as_raw_fd()), buf.as_mut_ptr(), buf.len() as u32)
+            .offset(offset)
+            .build();
+        let result = self.submit_one(&entry)?;
+        if result < buf.len() {
+            // the rest is read by the system call
+            file.read_exact_at(&mut buf[result..], offset + result as u64)?;
+        }
+        Ok(())
+    }
+
+    /// Write all the buffers to their offsets, the writes are not ordered.
+    pub(super) fn write_all_at(&mut self, file: &File, writes: &[(u64, &[u8])]) -> std::io::Result<()> {
+        let fd = types::Fd(file.as_raw_fd());
+        for chunk in writes.chunks(URING_ENTRIES as usize) {
+            {
+                let mut submission = self.ring.submission();
+                for (index, (offset, buf)) in chunk.iter().enumerate() {
+                    let entry = opcode::Write::new(fd, buf.as_ptr(), buf.len() as u32)
+                        .offset(*offset)
+                        .build()
+                        .user_data(index as u64);
+                    // the buffers live until the completions are reaped below
+                    unsafe { submission.push(&entry) }.map_err(|_| submission_full())?;
+                }
+            }
+            self.ring.submit_and_wait(chunk.len())?;
+
+            // all the completions of the batch are reaped, or the ones left
+            // would be taken as the completions of the next batch
+            let mut first_error = None;
+            let mut short_writes = vec![];
+            for completion in self.ring.completion() {
+                let result = completion.result();
+                if result < 0 {
+                    first_error.get_or_insert_with(|| Error::from_raw_os_error(-result));
+                    continue;
+                }
+                let index = completion.user_data() as usize;
+                if (result as usize) < chunk[index].1.len() {
+                    short_writes.push((index, result as usize));
+                }
+            }
+            if let Some(err) = first_error {
+                return Err(err);
+            }
+
+            for (index, written) in short_writes {
+                let (offset, buf) = chunk[index];
+                file.write_all_at(&buf[written..], offset + written as u64)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn submit_one(&mut self, entry: &io_uring::squeue::Entry) -> std::io::Result<usize> {
+        // the buffer of the entry lives until the completion is reaped
+        unsafe { self.ring.submission().push(entry) }.map_err(|_| submission_full())?;
+        self.ring.submit_and_wait(1)?;
+        let completion = self.ring.completion().next()
+            .ok_or_else(|| Error::new(ErrorKind::Other, "io_uring completion is lost"))?;
+        let result = completion.result();
+        if result < 0 {
+            return Err(Error::from_raw_os_error(-result));
+        }
+        Ok(result as usize)
+    }
+
+}
+
+fn submission_full() -> Error {
+    Error::new(ErrorKind::Other, "io_uring submission queue is full")
+}
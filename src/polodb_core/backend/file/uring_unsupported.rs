@@ -0,0 +1,27 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::fs::File;
+
+/// io_uring is not built without the `io_uring` feature on Linux,
+/// the value can't be created.
+pub(super) enum Uring {}
+
+impl Uring {
+
+    pub(super) fn new() -> Option<Uring> {
+        crate::polo_log!("io_uring needs the io_uring feature on Linux");
+        None
+    }
+
+    pub(super) fn read_exact_at(&mut self, _file: &File, _buf: &mut [u8], _offset: u64) -> std::io::Result<()> {
+        match *self {}
+    }
+
+    pub(super) fn write_all_at(&mut self, _file: &File, _writes: &[(u64, &[u8])]) -> std::io::Result<()> {
+        match *self {}
+    }
+
+}
@@ -0,0 +1,122 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The binary frames of the pages stored in IndexedDB.
+//!
+//! A frame is a header of 28 bytes in little-endian followed by a lz4 block:
+//!
+//! | offset | size | field                                       |
+//! |--------|------|---------------------------------------------|
+//! | 0      | 4    | magic `PLFR`                                |
+//! | 4      | 2    | version, 1                                  |
+//! | 6      | 2    | reserved                                    |
+//! | 8      | 4    | page size                                   |
+//! | 12     | 4    | count of the pages                          |
+//! | 16     | 8    | size of the database after the commit       |
+//! | 24     | 4    | size of the block decompressed              |
+//!
+//! The block decompressed is the pages, every page is the id of 4 bytes
+//! followed by the data of the page size.
+use std::convert::TryInto;
+use std::sync::Arc;
+use crate::{DbErr, DbResult};
+use crate::page::RawPage;
+
+const FRAME_MAGIC: &[u8; 4] = b"PLFR";
+const FRAME_VERSION: u16 = 1;
+const HEADER_SIZE: usize = 28;
+
+pub(super) struct Frame {
+    pub(super) db_size: u64,
+    pub(super) pages:   Vec<(u32, Vec<u8>)>,
+}
+
+pub(super) fn encode_frame(db_size: u64, page_size: u32, pages: &[(u32, Arc<RawPage>)]) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(pages.len() * (4 + page_size as usize));
+    for (page_id, page) in pages {
+        raw.extend_from_slice(&page_id.to_le_bytes());
+        raw.extend_from_slice(&page.data);
+    }
+    let block = lz4_flex::compress(&raw);
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + block.len());
+    bytes.extend_from_slice(FRAME_MAGIC);
+    bytes.extend_from_slice(&FRAME_VERSION.to_le_bytes());
+    bytes.extend_from_slice(&0u16.to_le_bytes());
+    bytes.extend_from_slice(&page_size.to_le_bytes());
+    bytes.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&db_size.to_le_bytes());
+    bytes.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&block);
+    bytes
+}
+
+pub(super) fn decode_frame(bytes: &[u8]) -> DbResult<Frame> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != FRAME_MAGIC[..] {
+        return Err(DbErr::IndexedDbError("not a frame of PoloDB".to_string()));
+    }
+    let read_u32 = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FRAME_VERSION {
+        return Err(DbErr::IndexedDbError(format!("unknown version of the frame: {}", version)));
+    }
+    let page_size = read_u32(8) as usize;
+    let page_count = read_u32(12) as usize;
+    let db_size = u64::from_le_bytes(bytes[16..24].try_into().unwrap());
+    let raw_size = read_u32(24) as usize;
+    if raw_size != page_count * (4 + page_size) {
+        return Err(DbErr::IndexedDbError("the size of the frame mismatches".to_string()));
+    }
+
+    let raw = lz4_flex::decompress(&bytes[HEADER_SIZE..], raw_size)
+        .map_err(|err| DbErr::CompressionError(err.to_string()))?;
+    if raw.len() != raw_size {
+        return Err(DbErr::IndexedDbError("the size of the frame mismatches".to_string()));
+    }
+    let pages = raw
+        .chunks(4 + page_size)
+        .map(|chunk| {
+            let page_id = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+            (page_id, chunk[4..].to_vec())
+        })
+        .collect();
+
+    Ok(Frame {
+        db_size,
+        pages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use crate::page::RawPage;
+    use super::{decode_frame, encode_frame};
+
+    #[test]
+    fn test_encode_decode() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let pages: Vec<_> = [3u32, 7].iter().map(|page_id| {
+            let mut page = RawPage::new(*page_id, page_size);
+            page.data[0] = *page_id as u8;
+            (*page_id, Arc::new(page))
+        }).collect();
+
+        let bytes = encode_frame(8 * 4096, 4096, &pages);
+        assert!(bytes.len() < 4096);
+
+        let frame = decode_frame(&bytes).unwrap();
+        assert_eq!(frame.db_size, 8 * 4096);
+        assert_eq!(frame.pages.len(), 2);
+        assert_eq!(frame.pages[1].0, 7);
+        assert_eq!(frame.pages[1].1, pages[1].1.data);
+
+        assert!(decode_frame(&bytes[0..20]).is_err());
+        assert!(decode_frame(b"not a frame at all, not at all").is_err());
+    }
+
+}
@@ -3,30 +3,436 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet};
 use std::num::{NonZeroU32, NonZeroU64};
+use std::rc::Rc;
 use std::sync::Arc;
+use std::time::Duration;
 use bson::oid::ObjectId;
+use js_sys::{Array, ArrayBuffer, Object, Reflect, Uint8Array};
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen::closure::Closure;
+use web_sys::{IdbCursorWithValue, IdbDatabase, IdbRequest, IdbTransaction, IdbTransactionMode};
 use crate::backend::Backend;
-use crate::backend::memory::MemoryBackend;
-use crate::{DbResult, TransactionType};
+use crate::backend::memory::{DbSnapshot, DbSnapshotDraft, MemoryBackend};
+use crate::{DbErr, DbResult, IndexedDbCompaction, TransactionType};
 use crate::page::RawPage;
 use crate::IndexedDbContext;
+use super::frame;
+
+// 256 KiB of the pages of 4 KiB, far below the limits of the structured clone
+const FRAME_MAX_PAGES: usize = 64;
+
+const LAST_COMMIT_KEY: &str = "lastCommit";
+
+/// The pages committed and not written to IndexedDB yet.
+///
+/// The commits within the flush interval are coalesced, the pages of them
+/// are written by one readwrite transaction of IndexedDB, so a burst of small
+/// writes doesn't issue a transaction for each commit. The pages are split
+/// into the frames of [`FRAME_MAX_PAGES`] pages at most, keyed by `[sid, seq]`,
+/// where `sid` is unique for every opening of the database and `seq` is increasing.
+/// The key of the last frame is the sequence number of the last commit written,
+/// it's put in the meta store by the same transaction.
+///
+/// When the frames appended reach the thresholds of [`IndexedDbCompaction`],
+/// all the pages are pending as a snapshot, which replaces the frames in the store.
+struct IndexedDbBackendInner {
+    ctx:             IndexedDbContext,
+    page_size:       NonZeroU32,
+    flush_interval:  Duration,
+    flush_scheduled: bool,
+    pending:         BTreeMap<u32, Arc<RawPage>>,
+    db_size:         u64,
+    sid:             String,
+    seq:             u32,
+    has_meta_store:  bool,
+    compaction:      Option<IndexedDbCompaction>,
+    // the frames appended since the last compaction
+    log_frames:      u32,
+    log_bytes:       u64,
+    // the pending pages are a snapshot of all the pages
+    compacting:      bool,
+}
+
+impl IndexedDbBackendInner {
+
+    /// The frames appended since the last compaction reach the thresholds.
+    fn should_compact(&self) -> bool {
+        match &self.compaction {
+            Some(compaction) => {
+                !self.compacting
+                    && (self.log_frames >= compaction.max_frames || self.log_bytes >= compaction.max_bytes)
+            }
+            None => false,
+        }
+    }
+
+    /// Put the pending pages as the binary frames in one transaction,
+    /// so they are written all or nothing.
+    fn flush(&mut self) -> DbResult<()> {
+        self.flush_scheduled = false;
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let pages: Vec<(u32, Arc<RawPage>)> = std::mem::take(&mut self.pending).into_iter().collect();
+        let compacting = std::mem::take(&mut self.compacting);
+
+        let store_names = Array::of1(&JsValue::from_str(IndexedDbContext::FRAME_STORE));
+        if self.has_meta_store {
+            store_names.push(&JsValue::from_str(IndexedDbContext::META_STORE));
+        }
+        let transaction = self.ctx.idb
+            .transaction_with_str_sequence_and_mode(&store_names, IdbTransactionMode::Readwrite)
+            .map_err(js_err)?;
+
+        // the requests issued are committed with the transaction,
+        // so the frames can't be cleared without the snapshot written
+        let result = self.write_frames(&transaction, &pages, compacting);
+        if result.is_err() {
+            let _ = transaction.abort();
+        }
+        result
+    }
+
+    fn write_frames(&mut self, transaction: &IdbTransaction, pages: &[(u32, Arc<RawPage>)], compacting: bool) -> DbResult<()> {
+        let store = transaction.object_store(IndexedDbContext::FRAME_STORE).map_err(js_err)?;
+
+        // the requests of a transaction are executed in order,
+        // the frames of the snapshot are put after the clear
+        if compacting {
+            store.clear().map_err(js_err)?;
+        }
+
+        for chunk in pages.chunks(FRAME_MAX_PAGES) {
+            let bytes = frame::encode_frame(self.db_size, self.page_size.get(), chunk);
+            let key = Array::of2(&JsValue::from_str(&self.sid), &JsValue::from(self.seq));
+            store.put_with_key(&Uint8Array::from(bytes.as_slice()).buffer(), &key).map_err(js_err)?;
+            self.seq += 1;
+            if !compacting {
+                self.log_frames += 1;
+                self.log_bytes += bytes.len() as u64;
+            }
+        }
+        if compacting {
+            crate::polo_log!("compact the frames of IndexedDB into {} pages", pages.len());
+            self.log_frames = 0;
+            self.log_bytes = 0;
+        }
+
+        if self.has_meta_store {
+            let last_commit = Object::new();
+            Reflect::set(&last_commit, &"sid".into(), &JsValue::from_str(&self.sid)).map_err(js_err)?;
+            Reflect::set(&last_commit, &"seq".into(), &JsValue::from(self.seq - 1)).map_err(js_err)?;
+            let meta = transaction.object_store(IndexedDbContext::META_STORE).map_err(js_err)?;
+            meta.put_with_key(&last_commit, &JsValue::from_str(LAST_COMMIT_KEY)).map_err(js_err)?;
+        }
+
+        Ok(())
+    }
+
+    fn flush_and_log(&mut self) {
+        if let Err(err) = self.flush() {
+            crate::polo_log!("write the frame to IndexedDB failed: {}", err);
+        }
+    }
+
+}
+
+fn js_err(err: JsValue) -> DbErr {
+    DbErr::IndexedDbError(format!("{:?}", err))
+}
+
+/// The progress of reading the frames on opening, reported to
+/// the `on_progress` of [`IndexedDbContext`].
+struct LoadProgress {
+    callback:      js_sys::Function,
+    frames_total:  Cell<u32>,
+    frames_loaded: Cell<u32>,
+    bytes_loaded:  Cell<f64>,
+}
+
+impl LoadProgress {
+
+    fn frame_loaded(&self, bytes: f64) {
+        self.frames_loaded.set(self.frames_loaded.get() + 1);
+        self.bytes_loaded.set(self.bytes_loaded.get() + bytes);
+        self.report(false);
+    }
+
+    fn report(&self, done: bool) {
+        let event = Object::new();
+        let fields = [
+            ("framesLoaded", JsValue::from(self.frames_loaded.get())),
+            // the frames may be written after counted
+            ("framesTotal", JsValue::from(self.frames_total.get().max(self.frames_loaded.get()))),
+            ("bytesLoaded", JsValue::from(self.bytes_loaded.get())),
+            ("done", JsValue::from(done)),
+        ];
+        for (key, value) in fields.iter() {
+            let _ = Reflect::set(&event, &JsValue::from_str(key), value);
+        }
+        if let Err(err) = self.callback.call1(&JsValue::UNDEFINED, &event) {
+            crate::polo_log!("the progress callback of IndexedDB failed: {:?}", err);
+        }
+    }
+
+}
+
+/// The frames read on opening, which are counted by the compaction.
+#[derive(Default)]
+struct LoadedFrames {
+    frames: u32,
+    bytes:  u64,
+}
+
+type LoadedCallback = Box<dyn FnOnce(DbResult<(MemoryBackend, LoadedFrames)>)>;
+
+/// Replay the frames into the memory on opening. The cursor reads the frames
+/// in the order of the keys `[sid, seq]`, which is the order of the commits:
+/// the `sid`s are the `ObjectId`s of the openings, and the `seq`s are increasing
+/// in an opening. The pages of the later frames replace the ones of the earlier frames.
+struct FrameLoader {
+    page_size:        NonZeroU32,
+    init_block_count: NonZeroU64,
+    progress:         Option<Rc<LoadProgress>>,
+    // `None` before the first frame, the database is new if there is no frame
+    draft:            Option<DbSnapshotDraft>,
+    frames:           LoadedFrames,
+    loaded:           Option<LoadedCallback>,
+}
+
+impl FrameLoader {
+
+    fn apply_frame(&mut self, bytes: &[u8]) -> DbResult<()> {
+        let frame = frame::decode_frame(bytes)?;
+        let page_size = self.page_size;
+        let draft = self.draft.get_or_insert_with(|| DbSnapshotDraft::new(DbSnapshot::new(page_size, 0)));
+        for (page_id, data) in frame.pages {
+            if data.len() != page_size.get() as usize {
+                return Err(DbErr::IndexedDbError("the size of the page of the frame mismatches".to_string()));
+            }
+            let mut page = RawPage::new(page_id, page_size);
+            page.data.copy_from_slice(&data);
+            draft.write_page(&page);
+        }
+        draft.set_db_file_size(frame.db_size);
+        self.frames.frames += 1;
+        self.frames.bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Call the `loaded` callback with the pages replayed, or the error
+    /// stopping the cursor. It's called only once.
+    fn finish(&mut self, result: DbResult<()>) {
+        if let Some(progress) = &self.progress {
+            progress.report(true);
+        }
+        let loaded = match self.loaded.take() {
+            Some(loaded) => loaded,
+            None => return,
+        };
+        let result = result.map(|()| {
+            let mem = match self.draft.take() {
+                // the pages beyond the size are left by the commits shrinking the database
+                Some(draft) => MemoryBackend::from_snapshot(self.page_size, draft.commit().truncate()),
+                None => MemoryBackend::new(self.page_size, self.init_block_count),
+            };
+            (mem, std::mem::take(&mut self.frames))
+        });
+        loaded(result);
+    }
+
+}
+
+/// Read the frames by a cursor and replay them, the frames of the objects
+/// `{ dbSize, pages: [{ pageId, data }] }` written by the older versions
+/// are rewritten to the binary frames on the way. The transaction is created
+/// before the ones of the commits, so it's done before them.
+fn load_frames(idb: &IdbDatabase, loader: FrameLoader) -> DbResult<()> {
+    let transaction = idb
+        .transaction_with_str_and_mode(IndexedDbContext::FRAME_STORE, IdbTransactionMode::Readwrite)
+        .map_err(js_err)?;
+    let store = transaction.object_store(IndexedDbContext::FRAME_STORE).map_err(js_err)?;
+
+    // the requests of a transaction succeed in order, so the total is counted
+    // before the first frame is read
+    if let Some(progress) = &loader.progress {
+        let count_request = store.count().map_err(js_err)?;
+        let result_request = count_request.clone();
+        let count_progress = progress.clone();
+        let onsuccess = Closure::once_into_js(move |_event: JsValue| {
+            if let Some(total) = result_request.result().ok().and_then(|result| result.as_f64()) {
+                count_progress.frames_total.set(total as u32);
+            }
+        });
+        count_request.set_onsuccess(Some(onsuccess.unchecked_ref()));
+    }
+
+    let loader = Rc::new(RefCell::new(loader));
+    let request = store.open_cursor().map_err(js_err)?;
+    {
+        let cursor_request = request.clone();
+        let loader = loader.clone();
+        let onsuccess = Closure::<dyn FnMut(JsValue)>::new(move |_event: JsValue| {
+            let mut loader = loader.borrow_mut();
+            match load_next_frame(&cursor_request, &mut loader) {
+                Ok(true) => (),
+                Ok(false) => loader.finish(Ok(())),
+                Err(err) => loader.finish(Err(err)),
+            }
+        });
+        request.set_onsuccess(Some(onsuccess.as_ref().unchecked_ref()));
+        onsuccess.forget();
+    }
+    {
+        let error_request = request.clone();
+        let onerror = Closure::once_into_js(move |_event: JsValue| {
+            let cause = Reflect::get(error_request.as_ref(), &"error".into()).unwrap_or(JsValue::NULL);
+            let err = DbErr::IndexedDbError(format!("read the frames failed: {:?}", cause));
+            loader.borrow_mut().finish(Err(err));
+        });
+        request.set_onerror(Some(onerror.unchecked_ref()));
+    }
+    Ok(())
+}
+
+/// Replay the frame of the cursor, return `false` at the end of the cursor.
+fn load_next_frame(request: &IdbRequest, loader: &mut FrameLoader) -> DbResult<bool> {
+    let result = request.result().map_err(js_err)?;
+    if result.is_null() || result.is_undefined() {
+        return Ok(false);
+    }
+    let cursor: IdbCursorWithValue = result.dyn_into().map_err(js_err)?;
+    let value = cursor.value().map_err(js_err)?;
+    let bytes = match value.dyn_ref::<ArrayBuffer>() {
+        Some(buffer) => Uint8Array::new(buffer).to_vec(),
+        None => {
+            let bytes = object_frame_to_binary(&value, loader.page_size)?;
+            cursor.update(&Uint8Array::from(bytes.as_slice()).buffer()).map_err(js_err)?;
+            bytes
+        }
+    };
+    loader.apply_frame(&bytes)?;
+    if let Some(progress) = &loader.progress {
+        progress.frame_loaded(bytes.len() as f64);
+    }
+    cursor.continue_().map_err(js_err)?;
+    Ok(true)
+}
+
+fn object_frame_to_binary(value: &JsValue, page_size: NonZeroU32) -> DbResult<Vec<u8>> {
+    let db_size = Reflect::get(value, &"dbSize".into()).map_err(js_err)?
+        .as_f64()
+        .unwrap_or(0.0) as u64;
+    let page_arr: Array = Reflect::get(value, &"pages".into()).map_err(js_err)?
+        .dyn_into()
+        .map_err(js_err)?;
+    let mut pages = Vec::with_capacity(page_arr.length() as usize);
+    for item in page_arr.iter() {
+        let page_id = Reflect::get(&item, &"pageId".into()).map_err(js_err)?
+            .as_f64()
+            .ok_or_else(|| DbErr::IndexedDbError("the page id of the frame is missing".to_string()))? as u32;
+        let data: Uint8Array = Reflect::get(&item, &"data".into()).map_err(js_err)?
+            .dyn_into()
+            .map_err(js_err)?;
+        if data.length() != page_size.get() {
+            return Err(DbErr::IndexedDbError("the size of the page of the frame mismatches".to_string()));
+        }
+        let mut page = RawPage::new(page_id, page_size);
+        data.copy_to(&mut page.data);
+        pages.push((page_id, Arc::new(page)));
+    }
+    Ok(frame::encode_frame(db_size, page_size.get(), &pages))
+}
+
+/// Flush the pending pages after the interval, at once if there is no
+/// `window` to set a timer, such as in a web worker.
+fn schedule_flush(inner: &Rc<RefCell<IndexedDbBackendInner>>) {
+    let mut this = inner.borrow_mut();
+    if this.flush_scheduled {
+        return;
+    }
+    let window = match web_sys::window() {
+        Some(window) if !this.flush_interval.is_zero() => window,
+        _ => return this.flush_and_log(),
+    };
+    let timer_inner = inner.clone();
+    let callback = Closure::once_into_js(move || {
+        timer_inner.borrow_mut().flush_and_log();
+    });
+    let timeout = this.flush_interval.as_millis().min(i32::MAX as u128) as i32;
+    match window.set_timeout_with_callback_and_timeout_and_arguments_0(callback.unchecked_ref(), timeout) {
+        Ok(_) => this.flush_scheduled = true,
+        Err(_) => this.flush_and_log(),
+    }
+}
 
-#[allow(dead_code)]
 pub(crate) struct IndexedDbBackend {
-    ctx: IndexedDbContext,
-    mem: MemoryBackend,
+    mem:   MemoryBackend,
+    inner: Rc<RefCell<IndexedDbBackendInner>>,
+    // the pages written by the transaction
+    dirty: BTreeSet<u32>,
 }
 
+// the browsers run the wasm in one thread
 unsafe impl Send for IndexedDbBackend {}
 
 impl IndexedDbBackend {
 
-    pub fn open(ctx: IndexedDbContext, page_size: NonZeroU32, init_block_count: NonZeroU64) -> IndexedDbBackend {
-        IndexedDbBackend {
-            ctx,
-            mem: MemoryBackend::new(page_size, init_block_count),
-        }
+    /// Replay the frames of IndexedDB into the memory, then call `loaded`
+    /// with the backend. The errors before the frames are read are returned.
+    pub fn open(
+        ctx: IndexedDbContext,
+        page_size: NonZeroU32,
+        init_block_count: NonZeroU64,
+        flush_interval: Duration,
+        compaction: Option<IndexedDbCompaction>,
+        loaded: impl FnOnce(DbResult<IndexedDbBackend>) + 'static,
+    ) -> DbResult<()> {
+        let idb = ctx.idb.clone();
+        let progress = ctx.on_progress.clone().map(|callback| Rc::new(LoadProgress {
+            callback,
+            frames_total: Cell::new(0),
+            frames_loaded: Cell::new(0),
+            bytes_loaded: Cell::new(0.0),
+        }));
+        let on_loaded = move |mem: DbResult<(MemoryBackend, LoadedFrames)>| {
+            let result = mem.map(|(mem, frames)| {
+                let has_meta_store = ctx.idb.object_store_names().contains(IndexedDbContext::META_STORE);
+                let inner = IndexedDbBackendInner {
+                    ctx,
+                    page_size,
+                    flush_interval,
+                    flush_scheduled: false,
+                    pending: BTreeMap::new(),
+                    db_size: mem.db_size(),
+                    sid: ObjectId::new().to_hex(),
+                    seq: 0,
+                    has_meta_store,
+                    compaction,
+                    // the frames loaded are compacted at the first commit if they're too many
+                    log_frames: frames.frames,
+                    log_bytes: frames.bytes,
+                    compacting: false,
+                };
+                IndexedDbBackend {
+                    mem,
+                    inner: Rc::new(RefCell::new(inner)),
+                    dirty: BTreeSet::new(),
+                }
+            });
+            loaded(result);
+        };
+        load_frames(&idb, FrameLoader {
+            page_size,
+            init_block_count,
+            progress,
+            draft: None,
+            frames: LoadedFrames::default(),
+            loaded: Some(Box::new(on_loaded)),
+        })
     }
 
 }
@@ -37,11 +443,32 @@ impl Backend for IndexedDbBackend {
     }
 
     fn write_page(&mut self, page: &RawPage, session_id: Option<&ObjectId>) -> DbResult<()> {
-        self.mem.write_page(page, session_id)
+        self.mem.write_page(page, session_id)?;
+        self.dirty.insert(page.page_id);
+        Ok(())
     }
 
     fn commit(&mut self) -> DbResult<()> {
         self.mem.commit()?;
+        let dirty = std::mem::take(&mut self.dirty);
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        {
+            let mut inner = self.inner.borrow_mut();
+            if inner.should_compact() {
+                // the snapshot includes the pages of this commit
+                inner.pending = self.mem.pages();
+                inner.compacting = true;
+            } else {
+                for page_id in dirty {
+                    let page = self.mem.read_page(page_id, None)?;
+                    inner.pending.insert(page_id, page);
+                }
+            }
+            inner.db_size = self.mem.db_size();
+        }
+        schedule_flush(&self.inner);
         Ok(())
     }
 
@@ -62,6 +489,7 @@ impl Backend for IndexedDbBackend {
     }
 
     fn rollback(&mut self) -> DbResult<()> {
+        self.dirty.clear();
         self.mem.rollback()
     }
 
@@ -77,3 +505,12 @@ impl Backend for IndexedDbBackend {
         self.mem.remove_session(id)
     }
 }
+
+impl Drop for IndexedDbBackend {
+
+    // the pending pages are written at once, the timer set finds nothing to write
+    fn drop(&mut self) {
+        self.inner.borrow_mut().flush_and_log();
+    }
+
+}
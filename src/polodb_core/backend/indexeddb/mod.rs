@@ -3,6 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+mod frame;
 mod indexeddb_backend;
 
 pub(crate) use indexeddb_backend::IndexedDbBackend;
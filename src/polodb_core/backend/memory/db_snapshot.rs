@@ -3,6 +3,7 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::collections::BTreeMap;
 use std::num::NonZeroU32;
 use std::sync::Arc;
 use crate::data_structures::trans_map::{TransMap, TransMapDraft};
@@ -35,6 +36,30 @@ impl DbSnapshot {
     pub fn db_file_size(&self) -> u64 {
         self.db_file_size
     }
+
+    /// Drop the pages beyond the size of the database, which are left
+    /// by a transaction shrinking it.
+    pub fn truncate(&self) -> DbSnapshot {
+        let page_count = self.db_file_size / (self.page_size.get() as u64);
+        let mut draft = TransMapDraft::new(TransMap::new());
+        for (page_id, page) in self.pages() {
+            if (page_id as u64) < page_count {
+                draft.insert(page_id, page);
+            }
+        }
+        DbSnapshot {
+            page_map: draft.commit(),
+            page_size: self.page_size,
+            db_file_size: self.db_file_size,
+        }
+    }
+
+    /// All the pages of the snapshot ordered by the id.
+    pub fn pages(&self) -> BTreeMap<u32, Arc<RawPage>> {
+        let mut pages = BTreeMap::new();
+        self.page_map.traverse(&mut pages);
+        pages
+    }
 }
 
 pub(crate) struct DbSnapshotDraft {
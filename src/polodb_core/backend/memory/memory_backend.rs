@@ -3,7 +3,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::io::{Seek, SeekFrom, Write};
 use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use bson::oid::ObjectId;
 use hashbrown::HashMap;
@@ -16,6 +18,7 @@ use crate::page::header_page_wrapper::HeaderPageWrapper;
 struct Transaction {
     ty: TransactionType,
     draft: DbSnapshotDraft,
+    shrunk: bool,
 }
 
 impl Transaction {
@@ -25,6 +28,7 @@ impl Transaction {
         Transaction {
             ty,
             draft,
+            shrunk: false,
         }
     }
 
@@ -62,9 +66,78 @@ impl MemoryBackend {
         }
     }
 
+    /// Restore the pages kept by the backend persisting the memory, such as IndexedDB.
+    #[cfg(feature = "indexeddb")]
+    pub(crate) fn from_snapshot(page_size: NonZeroU32, snapshot: DbSnapshot) -> MemoryBackend {
+        MemoryBackend {
+            page_size,
+            snapshot,
+            transaction: None,
+            state_map: HashMap::new(),
+        }
+    }
+
+    /// The pages committed within the size of the database, the null pages are not included.
+    #[cfg(feature = "indexeddb")]
+    pub(crate) fn pages(&self) -> std::collections::BTreeMap<u32, Arc<RawPage>> {
+        self.snapshot.truncate().pages()
+    }
+
+    /// Load all the pages of a database file into memory. The file is opened
+    /// by the file backend at first, which merges the journal left by the last process,
+    /// and fails if the file is opened by another process.
+    #[cfg(feature = "fs")]
+    pub(crate) fn load_from(path: &Path, page_size: NonZeroU32) -> DbResult<MemoryBackend> {
+        if !path.exists() {
+            let err = std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("the database file \"{}\" is not found", path.display()),
+            );
+            return Err(DbErr::IOErr(Box::new(err)));
+        }
+        drop(crate::backend::file::FileBackend::open(
+            path, page_size, Arc::new(crate::Config::default()), crate::Metrics::new(),
+        )?);
+
+        let bytes = std::fs::read(path)?;
+        if bytes.len() % (page_size.get() as usize) != 0 {
+            return Err(DbErr::NotAValidDatabase);
+        }
+
+        let mut draft = DbSnapshotDraft::new(DbSnapshot::new(page_size, bytes.len() as u64));
+        for (page_id, content) in bytes.chunks(page_size.get() as usize).enumerate() {
+            // the missing pages are read as null pages
+            if content.iter().all(|byte| *byte == 0) {
+                continue;
+            }
+            let mut page = RawPage::new(page_id as u32, page_size);
+            page.data.copy_from_slice(content);
+            if page_id != 0 {
+                crate::compression::decompress_page(&mut page.data)?;
+            }
+            draft.write_page(&page);
+        }
+
+        Ok(MemoryBackend {
+            page_size,
+            snapshot: draft.commit(),
+            transaction: None,
+            state_map: HashMap::new(),
+        })
+    }
+
+    fn journal_path(path: &Path) -> PathBuf {
+        let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+        file_name.push(".journal");
+        path.with_file_name(file_name)
+    }
+
     fn merge_transaction(&mut self) {
         let state = self.transaction.take().unwrap();
         self.snapshot = state.draft.commit();
+        if state.shrunk {
+            self.snapshot = self.snapshot.truncate();
+        }
     }
 
     fn recover_file_and_state(&mut self) {
@@ -160,8 +233,18 @@ impl Backend for MemoryBackend {
     }
 
     fn set_db_size(&mut self, size: u64) -> DbResult<()> {
+        if let Some(transaction) = &mut self.transaction {
+            if size > transaction.draft.db_file_size() {
+                transaction.draft.set_db_file_size(size);
+            }
+        }
+        Ok(())
+    }
+
+    fn shrink_db_size(&mut self, size: u64) -> DbResult<()> {
         if let Some(transaction) = &mut self.transaction {
             transaction.draft.set_db_file_size(size);
+            transaction.shrunk = true;
         }
         Ok(())
     }
@@ -197,6 +280,34 @@ impl Backend for MemoryBackend {
         Ok(())
     }
 
+    /// Write the committed pages to a temporary file, then replace the file at `path`.
+    /// The journal of the old file is removed, it must not be replayed on the new one.
+    fn persist_to(&self, path: &Path) -> DbResult<()> {
+        let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        {
+            let mut file = std::fs::File::create(&temp_path)?;
+            file.set_len(self.snapshot.db_file_size())?;
+            for (page_id, page) in self.snapshot.pages() {
+                file.seek(SeekFrom::Start((page_id as u64) * (self.page_size.get() as u64)))?;
+                file.write_all(&page.data)?;
+            }
+            file.sync_all()?;
+        }
+
+        let journal_path = MemoryBackend::journal_path(path);
+        if journal_path.exists() {
+            std::fs::remove_file(&journal_path)?;
+        }
+        std::fs::rename(&temp_path, path)?;
+
+        crate::polo_log!("persist the memory database to {}", path.display());
+
+        Ok(())
+    }
+
     fn new_session(&mut self, id: &ObjectId) -> DbResult<()> {
         let transaction = Transaction::new(
             TransactionType::Read,
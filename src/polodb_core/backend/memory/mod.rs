@@ -7,3 +7,4 @@ mod memory_backend;
 mod db_snapshot;
 
 pub(crate) use memory_backend::MemoryBackend;
+pub(crate) use db_snapshot::{DbSnapshot, DbSnapshotDraft};
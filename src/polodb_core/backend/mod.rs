@@ -5,13 +5,20 @@
  */
 
 mod backend;
+mod pagecache;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "fs")]
 pub(crate) mod file;
 
 pub(crate) mod memory;
 
-#[cfg(target_arch = "wasm32")]
+pub(crate) mod object_store;
+
+#[cfg(feature = "indexeddb")]
 pub(crate) mod indexeddb;
 
+#[cfg(feature = "opfs")]
+pub(crate) mod opfs;
+
 pub(crate) use backend::{Backend, AutoStartResult};
+pub(crate) use pagecache::PageCache;
@@ -0,0 +1,128 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::num::NonZeroU32;
+use bson::{doc, Document};
+use bson::spec::BinarySubtype;
+use hashbrown::HashMap;
+use crate::{DbErr, DbResult};
+
+static MANIFEST_DESP: &str = "PoloDB Object Store v0.1";
+
+/// The state of the database uploaded to the object store.
+///
+/// The pages uploaded by a checkpoint are new objects keyed by the generation
+/// of the checkpoint, the objects are never overwritten. The manifest is
+/// replaced at last, so a reader with the old manifest reads a consistent version.
+#[derive(Clone)]
+pub(super) struct Manifest {
+    pub(super) page_size:  NonZeroU32,
+    pub(super) db_size:    u64,
+    pub(super) generation: u32,
+    // page_id => the generation the page is uploaded
+    pub(super) pages:      HashMap<u32, u32>,
+}
+
+impl Manifest {
+
+    pub(super) fn new(page_size: NonZeroU32, db_size: u64) -> Manifest {
+        Manifest {
+            page_size,
+            db_size,
+            generation: 0,
+            pages: HashMap::new(),
+        }
+    }
+
+    pub(super) fn manifest_key(prefix: &str) -> String {
+        format!("{}/manifest", prefix)
+    }
+
+    pub(super) fn page_key(prefix: &str, page_id: u32, generation: u32) -> String {
+        format!("{}/pages/{}-{}", prefix, page_id, generation)
+    }
+
+    pub(super) fn to_bytes(&self) -> DbResult<Vec<u8>> {
+        let mut pages = Vec::with_capacity(self.pages.len() * 8);
+        let mut page_ids: Vec<&u32> = self.pages.keys().collect();
+        page_ids.sort();
+        for page_id in page_ids {
+            pages.extend_from_slice(&page_id.to_be_bytes());
+            pages.extend_from_slice(&self.pages[page_id].to_be_bytes());
+        }
+
+        let doc = doc! {
+            "desp": MANIFEST_DESP,
+            "pageSize": self.page_size.get() as i64,
+            "dbSize": self.db_size as i64,
+            "generation": self.generation as i64,
+            "pages": bson::Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: pages,
+            },
+        };
+        Ok(bson::to_vec(&doc)?)
+    }
+
+    pub(super) fn from_bytes(bytes: &[u8]) -> DbResult<Manifest> {
+        let doc: Document = bson::from_slice(bytes)?;
+        if doc.get_str("desp").ok() != Some(MANIFEST_DESP) {
+            return Err(DbErr::NotAValidDatabase);
+        }
+        let page_size = NonZeroU32::new(get_u64(&doc, "pageSize")? as u32)
+            .ok_or(DbErr::NotAValidDatabase)?;
+        let db_size = get_u64(&doc, "dbSize")?;
+        let generation = get_u64(&doc, "generation")? as u32;
+
+        let bytes = doc.get_binary_generic("pages").map_err(|_| DbErr::NotAValidDatabase)?;
+        if bytes.len() % 8 != 0 {
+            return Err(DbErr::NotAValidDatabase);
+        }
+        let mut pages = HashMap::with_capacity(bytes.len() / 8);
+        for entry in bytes.chunks(8) {
+            let mut page_id = [0u8; 4];
+            page_id.copy_from_slice(&entry[0..4]);
+            let mut page_generation = [0u8; 4];
+            page_generation.copy_from_slice(&entry[4..8]);
+            pages.insert(u32::from_be_bytes(page_id), u32::from_be_bytes(page_generation));
+        }
+
+        Ok(Manifest {
+            page_size,
+            db_size,
+            generation,
+            pages,
+        })
+    }
+
+}
+
+fn get_u64(doc: &Document, key: &str) -> DbResult<u64> {
+    doc.get_i64(key)
+        .map(|value| value as u64)
+        .map_err(|_| DbErr::NotAValidDatabase)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use super::Manifest;
+
+    #[test]
+    fn test_manifest_bytes() {
+        let mut manifest = Manifest::new(NonZeroU32::new(4096).unwrap(), 16 * 4096);
+        manifest.generation = 3;
+        manifest.pages.insert(0, 3);
+        manifest.pages.insert(7, 1);
+
+        let bytes = manifest.to_bytes().unwrap();
+        let decoded = Manifest::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.page_size.get(), 4096);
+        assert_eq!(decoded.db_size, 16 * 4096);
+        assert_eq!(decoded.generation, 3);
+        assert_eq!(decoded.pages, manifest.pages);
+    }
+
+}
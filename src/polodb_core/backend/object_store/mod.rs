@@ -0,0 +1,30 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+mod manifest;
+mod object_store_backend;
+
+pub(crate) use object_store_backend::{ObjectStoreBackend, PrefetchPlan};
+
+use crate::DbResult;
+
+/// An object storage such as S3 or GCS, implemented by the application
+/// with its client, see [`Database::open_object_store`].
+///
+/// The methods are blocking, an async client can be driven by the `block_on` of its runtime.
+/// A page missing the page cache is fetched with the lock of the database held,
+/// [`Database::prefetch`] fetches the pages ahead without holding it, and
+/// [`AsyncDatabase::prefetch`] does it on the threads of the pool.
+///
+/// [`Database::open_object_store`]: crate::Database::open_object_store
+/// [`Database::prefetch`]: crate::Database::prefetch
+/// [`AsyncDatabase::prefetch`]: crate::AsyncDatabase::prefetch
+pub trait ObjectStore: Send + Sync {
+    /// Return `None` if the object doesn't exist.
+    fn get(&self, key: &str) -> DbResult<Option<Vec<u8>>>;
+
+    /// Create or replace the object.
+    fn put(&self, key: &str, data: &[u8]) -> DbResult<()>;
+}
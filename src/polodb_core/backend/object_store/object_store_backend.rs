@@ -0,0 +1,402 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::io;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use hashbrown::HashMap;
+use super::ObjectStore;
+use super::manifest::Manifest;
+use crate::backend::Backend;
+use crate::backend::PageCache;
+use crate::backend::memory::{DbSnapshot, DbSnapshotDraft};
+use crate::{Config, DbErr, DbResult, Metrics, TransactionType};
+use crate::page::RawPage;
+use crate::page::header_page_wrapper::HeaderPageWrapper;
+
+struct Transaction {
+    ty:    TransactionType,
+    draft: DbSnapshotDraft,
+}
+
+/// What a session reads, the pages not uploaded and the manifest
+/// when the session is started.
+struct SessionState {
+    snapshot: DbSnapshot,
+    manifest: Arc<Manifest>,
+}
+
+/// The pages to fetch ahead, taken with the lock of the database held,
+/// and fetched without it by [`Database::prefetch`].
+///
+/// [`Database::prefetch`]: crate::Database::prefetch
+pub(crate) struct PrefetchPlan {
+    store:     Arc<dyn ObjectStore>,
+    prefix:    String,
+    page_size: NonZeroU32,
+    manifest:  Arc<Manifest>,
+    page_ids:  Vec<u32>,
+}
+
+impl PrefetchPlan {
+
+    pub(crate) fn fetch(&self) -> DbResult<Vec<Arc<RawPage>>> {
+        self.page_ids
+            .iter()
+            .map(|page_id| fetch_page(self.store.as_ref(), &self.prefix, self.page_size, *page_id, &self.manifest))
+            .collect()
+    }
+
+}
+
+fn fetch_page(
+    store: &dyn ObjectStore,
+    prefix: &str,
+    page_size: NonZeroU32,
+    page_id: u32,
+    manifest: &Manifest,
+) -> DbResult<Arc<RawPage>> {
+    let mut result = RawPage::new(page_id, page_size);
+    let generation = match manifest.pages.get(&page_id) {
+        Some(generation) => *generation,
+        // never written
+        None => return Ok(Arc::new(result)),
+    };
+
+    let key = Manifest::page_key(prefix, page_id, generation);
+    crate::polo_log!("fetch page from object store, key: {}", key);
+
+    let bytes = store.get(&key)?.ok_or_else(|| {
+        let err = io::Error::new(io::ErrorKind::NotFound, format!("object \"{}\" is not found", key));
+        DbErr::IOErr(Box::new(err))
+    })?;
+    if bytes.len() != result.data.len() {
+        return Err(DbErr::NotAValidDatabase);
+    }
+    result.data.copy_from_slice(&bytes);
+
+    Ok(Arc::new(result))
+}
+
+/// The pages are fetched from the object store lazily and kept in the page cache.
+/// The committed pages are kept in memory until a checkpoint uploads them,
+/// which happens when they reach `journal_full_size` and no session is reading,
+/// or when the database is checkpointed or closed.
+pub(crate) struct ObjectStoreBackend {
+    store:       Arc<dyn ObjectStore>,
+    prefix:      String,
+    page_size:   NonZeroU32,
+    manifest:    Arc<Manifest>,
+    // the pages committed but not uploaded
+    snapshot:    DbSnapshot,
+    transaction: Option<Transaction>,
+    state_map:   HashMap<ObjectId, SessionState>,
+    // copied from the config, it can be changed after the database is opened
+    journal_full_size: u64,
+    page_cache:  PageCache,
+    metrics:     Metrics,
+}
+
+impl ObjectStoreBackend {
+
+    pub(crate) fn open(
+        store: Arc<dyn ObjectStore>,
+        prefix: &str,
+        page_size: NonZeroU32,
+        config: Arc<Config>,
+        metrics: Metrics,
+    ) -> DbResult<ObjectStoreBackend> {
+        let manifest_bytes = store.get(&Manifest::manifest_key(prefix))?;
+        let is_new = manifest_bytes.is_none();
+        let manifest = match manifest_bytes {
+            Some(bytes) => {
+                let manifest = Manifest::from_bytes(&bytes)?;
+                if manifest.page_size != page_size {
+                    return Err(DbErr::NotAValidDatabase);
+                }
+                manifest
+            }
+            None => {
+                let db_size = config.init_block_count.get() * (page_size.get() as u64);
+                Manifest::new(page_size, db_size)
+            }
+        };
+
+        let mut snapshot = DbSnapshot::new(page_size, manifest.db_size);
+        if is_new {
+            let wrapper = HeaderPageWrapper::init(0, page_size);
+            let mut draft = DbSnapshotDraft::new(snapshot);
+            draft.write_page(&wrapper.0);
+            snapshot = draft.commit();
+        }
+
+        let page_cache = PageCache::new(config.page_cache_size.get(), page_size, config.page_cache_policy);
+        let mut backend = ObjectStoreBackend {
+            store,
+            prefix: prefix.to_string(),
+            page_size,
+            manifest: Arc::new(manifest),
+            snapshot,
+            transaction: None,
+            state_map: HashMap::new(),
+            journal_full_size: config.journal_full_size,
+            page_cache,
+            metrics,
+        };
+
+        // the other processes can open the database after the manifest is uploaded
+        if is_new {
+            backend.upload()?;
+        }
+
+        Ok(backend)
+    }
+
+    /// Upload the committed pages as a new generation, then replace the manifest.
+    /// The objects of the old generations are kept for the readers of the old manifest.
+    fn upload(&mut self) -> DbResult<()> {
+        let pages = self.snapshot.pages();
+        if pages.is_empty() && self.snapshot.db_file_size() == self.manifest.db_size {
+            return Ok(());
+        }
+
+        let mut manifest = self.manifest.as_ref().clone();
+        manifest.generation += 1;
+        manifest.db_size = self.snapshot.db_file_size();
+        for (page_id, page) in &pages {
+            let key = Manifest::page_key(&self.prefix, *page_id, manifest.generation);
+            self.store.put(&key, &page.data)?;
+            manifest.pages.insert(*page_id, manifest.generation);
+        }
+        self.store.put(&Manifest::manifest_key(&self.prefix), &manifest.to_bytes()?)?;
+
+        crate::polo_log!("upload {} pages, generation: {}", pages.len(), manifest.generation);
+
+        self.manifest = Arc::new(manifest);
+        self.snapshot = DbSnapshot::new(self.page_size, self.manifest.db_size);
+        Ok(())
+    }
+
+    fn fetch_page(&self, page_id: u32, manifest: &Manifest) -> DbResult<Arc<RawPage>> {
+        fetch_page(self.store.as_ref(), &self.prefix, self.page_size, page_id, manifest)
+    }
+
+    // the page is read without the object store
+    fn is_local(&self, page_id: u32) -> bool {
+        let in_transaction = self.transaction
+            .as_ref()
+            .map(|transaction| transaction.draft.read_page(page_id).is_some())
+            .unwrap_or(false);
+        in_transaction
+            || self.snapshot.read_page(page_id).is_some()
+            || self.page_cache.get_from_cache(page_id).is_some()
+    }
+
+    fn read_page_main(&self, page_id: u32) -> DbResult<Arc<RawPage>> {
+        self.metrics.fetch_page();
+
+        if let Some(transaction) = &self.transaction {
+            if let Some(page) = transaction.draft.read_page(page_id) {
+                return Ok(page);
+            }
+        }
+
+        if let Some(page) = self.snapshot.read_page(page_id) {
+            return Ok(page);
+        }
+
+        if let Some(page) = self.page_cache.get_from_cache(page_id) {
+            self.metrics.page_hit_cache();
+            return Ok(page);
+        }
+
+        let result = self.fetch_page(page_id, &self.manifest)?;
+        self.page_cache.insert_to_cache(&result);
+
+        Ok(result)
+    }
+
+    fn pending_page_count(&self) -> u64 {
+        self.snapshot.pages().len() as u64
+    }
+
+}
+
+impl Backend for ObjectStoreBackend {
+
+    fn read_page(&self, page_id: u32, session_id: Option<&ObjectId>) -> DbResult<Arc<RawPage>> {
+        match session_id {
+            Some(session_id) => {
+                let state = self.state_map
+                    .get(session_id)
+                    .ok_or(DbErr::InvalidSession(Box::new(session_id.clone())))?;
+                if let Some(page) = state.snapshot.read_page(page_id) {
+                    return Ok(page);
+                }
+                self.fetch_page(page_id, &state.manifest)
+            }
+            None => self.read_page_main(page_id),
+        }
+    }
+
+    fn write_page(&mut self, page: &RawPage, session_id: Option<&ObjectId>) -> DbResult<()> {
+        if session_id.is_some() {
+            unreachable!()
+        }
+
+        let transaction = match &mut self.transaction {
+            Some(transaction) if transaction.ty == TransactionType::Write => transaction,
+            _ => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
+        transaction.draft.write_page(page);
+
+        let expected_db_size = (page.page_id as u64 + 1) * (self.page_size.get() as u64);
+        if expected_db_size > transaction.draft.db_file_size() {
+            transaction.draft.set_db_file_size(expected_db_size);
+        }
+
+        self.page_cache.insert_to_cache(page);
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        let transaction = self.transaction.take().ok_or(DbErr::CannotWriteDbWithoutTransaction)?;
+        self.snapshot = transaction.draft.commit();
+
+        if self.state_map.is_empty() && self.pending_page_count() >= self.journal_full_size {
+            self.upload()?;
+        }
+
+        Ok(())
+    }
+
+    fn db_size(&self) -> u64 {
+        match &self.transaction {
+            Some(transaction) => transaction.draft.db_file_size(),
+            None => self.snapshot.db_file_size(),
+        }
+    }
+
+    fn set_db_size(&mut self, size: u64) -> DbResult<()> {
+        match &mut self.transaction {
+            Some(transaction) => {
+                if size > transaction.draft.db_file_size() {
+                    transaction.draft.set_db_file_size(size);
+                }
+                Ok(())
+            }
+            None => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
+    }
+
+    fn prefetch_plan(&self, max_pages: usize) -> Option<PrefetchPlan> {
+        let mut page_ids: Vec<u32> = self.manifest.pages
+            .keys()
+            .copied()
+            .filter(|page_id| !self.is_local(*page_id))
+            .collect();
+        page_ids.sort_unstable();
+        page_ids.truncate(max_pages);
+        Some(PrefetchPlan {
+            store: self.store.clone(),
+            prefix: self.prefix.clone(),
+            page_size: self.page_size,
+            manifest: self.manifest.clone(),
+            page_ids,
+        })
+    }
+
+    fn put_prefetched(&mut self, plan: &PrefetchPlan, pages: Vec<Arc<RawPage>>) {
+        // the pages are stale if a checkpoint has uploaded a new generation since the plan
+        if !Arc::ptr_eq(&self.manifest, &plan.manifest) {
+            return;
+        }
+        for page in pages {
+            // the pages written since the plan are newer
+            if !self.is_local(page.page_id) {
+                self.page_cache.insert_to_cache(&page);
+            }
+        }
+    }
+
+    fn set_journal_full_size(&mut self, size: u64) {
+        self.journal_full_size = size;
+    }
+
+    fn pin_pages(&mut self, pages: &[u32]) -> DbResult<()> {
+        self.page_cache.pin(pages)?;
+        for page_id in pages {
+            self.read_page_main(*page_id)?;
+        }
+        Ok(())
+    }
+
+    fn transaction_type(&self) -> Option<TransactionType> {
+        self.transaction.as_ref().map(|transaction| transaction.ty)
+    }
+
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.ty = TransactionType::Write;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        if self.transaction.take().is_none() {
+            return Err(DbErr::RollbackNotInTransaction);
+        }
+        self.page_cache.invalidate();
+        Ok(())
+    }
+
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        if self.transaction.is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+        self.transaction = Some(Transaction {
+            ty,
+            draft: DbSnapshotDraft::new(self.snapshot.clone()),
+        });
+        Ok(())
+    }
+
+    /// Upload the committed pages to the object store.
+    fn checkpoint(&mut self) -> DbResult<()> {
+        if self.transaction.is_some() || !self.state_map.is_empty() {
+            return Err(DbErr::Busy);
+        }
+        self.upload()
+    }
+
+    fn new_session(&mut self, id: &ObjectId) -> DbResult<()> {
+        let state = SessionState {
+            snapshot: self.snapshot.clone(),
+            manifest: self.manifest.clone(),
+        };
+        self.state_map.insert(id.clone(), state);
+        Ok(())
+    }
+
+    fn remove_session(&mut self, id: &ObjectId) -> DbResult<()> {
+        self.state_map.remove(id);
+        Ok(())
+    }
+
+}
+
+impl Drop for ObjectStoreBackend {
+
+    fn drop(&mut self) {
+        self.state_map.clear();
+        self.transaction = None;
+        if let Err(err) = self.upload() {
+            crate::polo_log!("upload the pages on close failed: {}", err);
+        }
+    }
+
+}
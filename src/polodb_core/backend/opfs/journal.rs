@@ -0,0 +1,137 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The frames of the commits appended to the journal file of OPFS.
+//!
+//! A frame is a header of 24 bytes in little-endian followed by the pages:
+//!
+//! | offset | size | field                                             |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | 4    | magic `PLOJ`                                      |
+//! | 4      | 4    | count of the pages                                |
+//! | 8      | 8    | size of the database after the commit             |
+//! | 16     | 8    | crc64 of the first 16 bytes and the pages         |
+//!
+//! Every page is the id of 4 bytes followed by the data of the page size.
+//! A frame torn by a crash fails the checksum, it's dropped with the frames after it.
+use std::convert::TryInto;
+use std::sync::Arc;
+use crc64fast::Digest;
+use crate::page::RawPage;
+
+const FRAME_MAGIC: &[u8; 4] = b"PLOJ";
+const HEADER_SIZE: usize = 24;
+
+pub(super) struct Frame {
+    pub(super) db_size: u64,
+    pub(super) pages:   Vec<(u32, Vec<u8>)>,
+}
+
+pub(super) fn encode_frame(db_size: u64, pages: &[(u32, Arc<RawPage>)]) -> Vec<u8> {
+    let page_size = pages.first().map(|(_, page)| page.data.len()).unwrap_or(0);
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + pages.len() * (4 + page_size));
+    bytes.extend_from_slice(FRAME_MAGIC);
+    bytes.extend_from_slice(&(pages.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&db_size.to_le_bytes());
+    bytes.extend_from_slice(&0u64.to_le_bytes());
+    for (page_id, page) in pages {
+        bytes.extend_from_slice(&page_id.to_le_bytes());
+        bytes.extend_from_slice(&page.data);
+    }
+    let checksum = frame_checksum(&bytes);
+    bytes[16..24].copy_from_slice(&checksum.to_le_bytes());
+    bytes
+}
+
+/// Decode the frames of the journal until the first one broken or torn.
+/// Return the frames with the length of the bytes they take.
+pub(super) fn decode_frames(bytes: &[u8], page_size: usize) -> (Vec<Frame>, usize) {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while let Some(len) = frame_len(&bytes[offset..], page_size) {
+        let frame_bytes = &bytes[offset..offset + len];
+        let checksum = u64::from_le_bytes(frame_bytes[16..24].try_into().unwrap());
+        if frame_checksum(frame_bytes) != checksum {
+            break;
+        }
+        let pages = frame_bytes[HEADER_SIZE..]
+            .chunks(4 + page_size)
+            .map(|chunk| {
+                let page_id = u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+                (page_id, chunk[4..].to_vec())
+            })
+            .collect();
+        frames.push(Frame {
+            db_size: u64::from_le_bytes(frame_bytes[8..16].try_into().unwrap()),
+            pages,
+        });
+        offset += len;
+    }
+    (frames, offset)
+}
+
+// the length of the frame at the start of the bytes, `None` if it's not complete
+fn frame_len(bytes: &[u8], page_size: usize) -> Option<usize> {
+    if bytes.len() < HEADER_SIZE || bytes[0..4] != FRAME_MAGIC[..] {
+        return None;
+    }
+    let page_count = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as usize;
+    let len = HEADER_SIZE + page_count * (4 + page_size);
+    if bytes.len() < len {
+        return None;
+    }
+    Some(len)
+}
+
+// the checksum field is skipped
+fn frame_checksum(frame_bytes: &[u8]) -> u64 {
+    let mut c = Digest::new();
+    c.write(&frame_bytes[0..16]);
+    c.write(&frame_bytes[HEADER_SIZE..]);
+    c.sum64()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use std::sync::Arc;
+    use crate::page::RawPage;
+    use super::{decode_frames, encode_frame, HEADER_SIZE};
+
+    #[test]
+    fn test_encode_decode() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let pages: Vec<_> = [3u32, 7].iter().map(|page_id| {
+            let mut page = RawPage::new(*page_id, page_size);
+            page.data[0] = *page_id as u8;
+            (*page_id, Arc::new(page))
+        }).collect();
+
+        let mut journal = encode_frame(8 * 4096, &pages);
+        let first_len = journal.len();
+        journal.extend_from_slice(&encode_frame(16 * 4096, &pages[1..]));
+
+        let (frames, len) = decode_frames(&journal, 4096);
+        assert_eq!(len, journal.len());
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].db_size, 8 * 4096);
+        assert_eq!(frames[0].pages[1].0, 7);
+        assert_eq!(frames[0].pages[1].1, pages[1].1.data);
+        assert_eq!(frames[1].db_size, 16 * 4096);
+
+        // the torn frame is dropped
+        let (frames, len) = decode_frames(&journal[0..journal.len() - 1], 4096);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(len, first_len);
+
+        // so is the broken one with the frames after it
+        journal[HEADER_SIZE + 4] ^= 1;
+        let (frames, len) = decode_frames(&journal, 4096);
+        assert!(frames.is_empty());
+        assert_eq!(len, 0);
+    }
+
+}
@@ -0,0 +1,379 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::collections::BTreeSet;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use bson::oid::ObjectId;
+use hashbrown::HashMap;
+use wasm_bindgen::JsValue;
+use web_sys::{FileSystemReadWriteOptions, FileSystemSyncAccessHandle};
+use super::journal;
+use crate::backend::Backend;
+use crate::backend::PageCache;
+use crate::backend::memory::{DbSnapshot, DbSnapshotDraft};
+use crate::{Config, DbErr, DbResult, Metrics, OpfsContext, TransactionType};
+use crate::page::RawPage;
+use crate::page::header_page_wrapper::HeaderPageWrapper;
+
+struct Transaction {
+    ty:    TransactionType,
+    draft: DbSnapshotDraft,
+    // the pages written by the transaction
+    dirty: BTreeSet<u32>,
+}
+
+/// The database file and the journal in the Origin Private File System,
+/// accessed by the sync access handles of a Web Worker.
+///
+/// A commit appends the pages written as a frame to the journal, and flushes it.
+/// The committed pages are kept in memory until a checkpoint writes them to
+/// the database file, which happens when they reach `journal_full_size` and
+/// no session is reading, or when the database is checkpointed or closed.
+/// The journal is truncated after the database file is flushed.
+/// The frames left by a crash are replayed on opening.
+pub(crate) struct OpfsBackend {
+    db_file:      FileSystemSyncAccessHandle,
+    journal_file: FileSystemSyncAccessHandle,
+    page_size:    NonZeroU32,
+    // the pages committed but not written to the database file
+    snapshot:     DbSnapshot,
+    // the size of the frames appended to the journal
+    journal_size: u64,
+    transaction:  Option<Transaction>,
+    state_map:    HashMap<ObjectId, DbSnapshot>,
+    // copied from the config, it can be changed after the database is opened
+    journal_full_size: u64,
+    page_cache:   PageCache,
+    metrics:      Metrics,
+}
+
+// the handles are only available in a dedicated worker, which runs the wasm in one thread
+unsafe impl Send for OpfsBackend {}
+
+fn js_err(err: JsValue) -> DbErr {
+    DbErr::OpfsError(format!("{:?}", err))
+}
+
+fn file_size(file: &FileSystemSyncAccessHandle) -> DbResult<u64> {
+    let size = file.get_size().map_err(js_err)?;
+    Ok(size as u64)
+}
+
+/// Read the bytes at the offset, the bytes beyond the end of the file are left as they are.
+fn read_at(file: &FileSystemSyncAccessHandle, buf: &mut [u8], offset: u64) -> DbResult<()> {
+    let options = FileSystemReadWriteOptions::new();
+    options.set_at(offset as f64);
+    file.read_with_u8_array_and_options(buf, &options).map_err(js_err)?;
+    Ok(())
+}
+
+fn write_at(file: &FileSystemSyncAccessHandle, buf: &[u8], offset: u64) -> DbResult<()> {
+    let options = FileSystemReadWriteOptions::new();
+    options.set_at(offset as f64);
+    let written = file.write_with_u8_array_and_options(buf, &options).map_err(js_err)? as usize;
+    if written != buf.len() {
+        return Err(DbErr::OpfsError(format!("{} of {} bytes are written", written, buf.len())));
+    }
+    Ok(())
+}
+
+impl OpfsBackend {
+
+    pub(crate) fn open(
+        ctx: OpfsContext,
+        page_size: NonZeroU32,
+        config: Arc<Config>,
+        metrics: Metrics,
+    ) -> DbResult<OpfsBackend> {
+        let OpfsContext { db_file, journal_file } = ctx;
+        let db_file_size = file_size(&db_file)?;
+        if db_file_size % (page_size.get() as u64) != 0 {
+            return Err(DbErr::NotAValidDatabase);
+        }
+
+        let mut journal_bytes = vec![0u8; file_size(&journal_file)? as usize];
+        read_at(&journal_file, &mut journal_bytes, 0)?;
+        let (frames, frames_len) = journal::decode_frames(&journal_bytes, page_size.get() as usize);
+        let is_new = db_file_size == 0 && frames.is_empty();
+
+        let snapshot = if is_new {
+            let db_size = config.init_block_count.get() * (page_size.get() as u64);
+            let wrapper = HeaderPageWrapper::init(0, page_size);
+            let mut draft = DbSnapshotDraft::new(DbSnapshot::new(page_size, db_size));
+            draft.write_page(&wrapper.0);
+            draft.commit()
+        } else {
+            let mut draft = DbSnapshotDraft::new(DbSnapshot::new(page_size, db_file_size));
+            for frame in &frames {
+                for (page_id, data) in &frame.pages {
+                    let mut page = RawPage::new(*page_id, page_size);
+                    page.data.copy_from_slice(data);
+                    draft.write_page(&page);
+                }
+                draft.set_db_file_size(frame.db_size);
+            }
+            draft.commit()
+        };
+        if !frames.is_empty() {
+            crate::polo_log!("replay {} frames of the journal of OPFS", frames.len());
+        }
+
+        let page_cache = PageCache::new(config.page_cache_size.get(), page_size, config.page_cache_policy);
+        let mut backend = OpfsBackend {
+            db_file,
+            journal_file,
+            page_size,
+            snapshot,
+            journal_size: frames_len as u64,
+            transaction: None,
+            state_map: HashMap::new(),
+            journal_full_size: config.journal_full_size,
+            page_cache,
+            metrics,
+        };
+
+        // the header of a new database and the replayed pages are written at once,
+        // the torn frame left at the end of the journal is dropped with them
+        if is_new || !frames.is_empty() || frames_len < journal_bytes.len() {
+            backend.write_to_db_file()?;
+        }
+
+        Ok(backend)
+    }
+
+    /// Write the committed pages to the database file, then truncate the journal.
+    fn write_to_db_file(&mut self) -> DbResult<()> {
+        let pages = self.snapshot.pages();
+        let db_size = self.snapshot.db_file_size();
+        for (page_id, page) in &pages {
+            write_at(&self.db_file, &page.data, (*page_id as u64) * (self.page_size.get() as u64))?;
+        }
+        if file_size(&self.db_file)? != db_size {
+            self.db_file.truncate_with_f64(db_size as f64).map_err(js_err)?;
+        }
+        self.db_file.flush().map_err(js_err)?;
+
+        self.journal_file.truncate_with_f64(0.0).map_err(js_err)?;
+        self.journal_file.flush().map_err(js_err)?;
+
+        if !pages.is_empty() {
+            crate::polo_log!("checkpoint {} pages to the database file of OPFS", pages.len());
+        }
+
+        self.journal_size = 0;
+        self.snapshot = DbSnapshot::new(self.page_size, db_size);
+        Ok(())
+    }
+
+    /// Append the frame and flush the journal, the frame is cut off if it fails,
+    /// so the commit failed is not replayed.
+    fn append_frame(&mut self, bytes: &[u8]) -> DbResult<()> {
+        let result = write_at(&self.journal_file, bytes, self.journal_size)
+            .and_then(|()| self.journal_file.flush().map_err(js_err));
+        if let Err(err) = result {
+            let _ = self.journal_file.truncate_with_f64(self.journal_size as f64);
+            return Err(err);
+        }
+        self.journal_size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn read_page_from_file(&self, page_id: u32) -> DbResult<Arc<RawPage>> {
+        let mut result = RawPage::new(page_id, self.page_size);
+        // the pages beyond the file are null pages
+        read_at(&self.db_file, &mut result.data, (page_id as u64) * (self.page_size.get() as u64))?;
+        Ok(Arc::new(result))
+    }
+
+    fn read_page_main(&self, page_id: u32) -> DbResult<Arc<RawPage>> {
+        self.metrics.fetch_page();
+
+        if let Some(transaction) = &self.transaction {
+            if let Some(page) = transaction.draft.read_page(page_id) {
+                return Ok(page);
+            }
+        }
+
+        if let Some(page) = self.snapshot.read_page(page_id) {
+            return Ok(page);
+        }
+
+        if let Some(page) = self.page_cache.get_from_cache(page_id) {
+            self.metrics.page_hit_cache();
+            return Ok(page);
+        }
+
+        let result = self.read_page_from_file(page_id)?;
+        self.page_cache.insert_to_cache(&result);
+
+        Ok(result)
+    }
+
+    fn pending_page_count(&self) -> u64 {
+        self.snapshot.pages().len() as u64
+    }
+
+}
+
+impl Backend for OpfsBackend {
+
+    fn read_page(&self, page_id: u32, session_id: Option<&ObjectId>) -> DbResult<Arc<RawPage>> {
+        match session_id {
+            Some(session_id) => {
+                let snapshot = self.state_map
+                    .get(session_id)
+                    .ok_or(DbErr::InvalidSession(Box::new(session_id.clone())))?;
+                if let Some(page) = snapshot.read_page(page_id) {
+                    return Ok(page);
+                }
+                // the database file is not written while a session is reading
+                self.read_page_from_file(page_id)
+            }
+            None => self.read_page_main(page_id),
+        }
+    }
+
+    fn write_page(&mut self, page: &RawPage, session_id: Option<&ObjectId>) -> DbResult<()> {
+        if session_id.is_some() {
+            unreachable!()
+        }
+
+        let transaction = match &mut self.transaction {
+            Some(transaction) if transaction.ty == TransactionType::Write => transaction,
+            _ => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
+        transaction.draft.write_page(page);
+        transaction.dirty.insert(page.page_id);
+
+        let expected_db_size = (page.page_id as u64 + 1) * (self.page_size.get() as u64);
+        if expected_db_size > transaction.draft.db_file_size() {
+            transaction.draft.set_db_file_size(expected_db_size);
+        }
+
+        self.page_cache.insert_to_cache(page);
+
+        Ok(())
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        let transaction = self.transaction.take().ok_or(DbErr::CannotWriteDbWithoutTransaction)?;
+        let db_size = transaction.draft.db_file_size();
+        if !transaction.dirty.is_empty() || db_size != self.snapshot.db_file_size() {
+            let mut pages = Vec::with_capacity(transaction.dirty.len());
+            for page_id in &transaction.dirty {
+                pages.push((*page_id, transaction.draft.read_page(*page_id).unwrap()));
+            }
+            if let Err(err) = self.append_frame(&journal::encode_frame(db_size, &pages)) {
+                // the pages of the transaction are put in the cache by the writes
+                self.page_cache.invalidate();
+                return Err(err);
+            }
+        }
+        self.snapshot = transaction.draft.commit();
+
+        if self.state_map.is_empty() && self.pending_page_count() >= self.journal_full_size {
+            self.write_to_db_file()?;
+        }
+
+        Ok(())
+    }
+
+    fn db_size(&self) -> u64 {
+        match &self.transaction {
+            Some(transaction) => transaction.draft.db_file_size(),
+            None => self.snapshot.db_file_size(),
+        }
+    }
+
+    fn set_db_size(&mut self, size: u64) -> DbResult<()> {
+        match &mut self.transaction {
+            Some(transaction) => {
+                if size > transaction.draft.db_file_size() {
+                    transaction.draft.set_db_file_size(size);
+                }
+                Ok(())
+            }
+            None => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
+    }
+
+    fn set_journal_full_size(&mut self, size: u64) {
+        self.journal_full_size = size;
+    }
+
+    fn pin_pages(&mut self, pages: &[u32]) -> DbResult<()> {
+        self.page_cache.pin(pages)?;
+        for page_id in pages {
+            self.read_page_main(*page_id)?;
+        }
+        Ok(())
+    }
+
+    fn transaction_type(&self) -> Option<TransactionType> {
+        self.transaction.as_ref().map(|transaction| transaction.ty)
+    }
+
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        if let Some(transaction) = &mut self.transaction {
+            transaction.ty = TransactionType::Write;
+        }
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        if self.transaction.take().is_none() {
+            return Err(DbErr::RollbackNotInTransaction);
+        }
+        self.page_cache.invalidate();
+        Ok(())
+    }
+
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        if self.transaction.is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+        self.transaction = Some(Transaction {
+            ty,
+            draft: DbSnapshotDraft::new(self.snapshot.clone()),
+            dirty: BTreeSet::new(),
+        });
+        Ok(())
+    }
+
+    /// Write the committed pages to the database file.
+    fn checkpoint(&mut self) -> DbResult<()> {
+        if self.transaction.is_some() || !self.state_map.is_empty() {
+            return Err(DbErr::Busy);
+        }
+        self.write_to_db_file()
+    }
+
+    fn new_session(&mut self, id: &ObjectId) -> DbResult<()> {
+        self.state_map.insert(id.clone(), self.snapshot.clone());
+        Ok(())
+    }
+
+    fn remove_session(&mut self, id: &ObjectId) -> DbResult<()> {
+        self.state_map.remove(id);
+        Ok(())
+    }
+
+}
+
+impl Drop for OpfsBackend {
+
+    // the handles lock the files, they are closed so the files can be opened again
+    fn drop(&mut self) {
+        self.state_map.clear();
+        self.transaction = None;
+        if let Err(err) = self.write_to_db_file() {
+            crate::polo_log!("checkpoint the pages of OPFS on close failed: {}", err);
+        }
+        self.db_file.close();
+        self.journal_file.close();
+    }
+
+}
@@ -0,0 +1,472 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::num::{NonZeroU32, NonZeroUsize};
+use lru::LruCache;
+use std::alloc::{alloc, dealloc, Layout};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use hashbrown::{HashMap, HashSet};
+use crate::config::PageCachePolicy;
+use crate::page::RawPage;
+use crate::{DbErr, DbResult};
+
+static OWNER_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+/// A handle of the page cache, which evicts the pages by the [`PageCachePolicy`].
+///
+/// The memory of the cache can be shared by many databases with [`PageCache::share`],
+/// the pages are keyed by the owner of the handle, so the databases
+/// sharing the memory compete for the same budget.
+///
+/// The pinned pages are never evicted, they are counted against the budget,
+/// at most half of the pages of the cache are pinned by all the handles.
+pub(crate) struct PageCache {
+    inner: Arc<Mutex<PageCacheInner>>,
+    owner: u64,
+}
+
+impl PageCache {
+    pub fn new(page_count: usize, page_size: NonZeroU32, policy: PageCachePolicy) -> PageCache {
+        let inner = PageCacheInner::new(page_count, page_size, policy);
+        PageCache {
+            inner: Arc::new(Mutex::new(inner)),
+            owner: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn new_default(page_size: NonZeroU32) -> PageCache {
+        let inner = PageCacheInner::new_default(page_size);
+        PageCache {
+            inner: Arc::new(Mutex::new(inner)),
+            owner: 0,
+        }
+    }
+
+    /// Return a new handle sharing the memory of this cache.
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    pub fn share(&self) -> PageCache {
+        PageCache {
+            inner: self.inner.clone(),
+            owner: OWNER_COUNTER.fetch_add(1, Ordering::SeqCst),
+        }
+    }
+
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    pub fn page_size(&self) -> NonZeroU32 {
+        let inner = self.inner.lock().unwrap();
+        inner.page_size
+    }
+
+    pub fn get_from_cache(&self, page_id: u32) -> Option<Arc<RawPage>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.get_from_cache(self.owner, page_id)
+    }
+
+    pub fn insert_to_cache(&self, page: &RawPage) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.insert_to_cache(self.owner, page)
+    }
+
+    /// Remove all the pages of this handle, the pinned pages are
+    /// read into the cache again when they are read.
+    pub fn invalidate(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.invalidate(self.owner)
+    }
+
+    /// Pin the pages of this handle, replacing the pages pinned before.
+    /// The pages not in the cache are pinned when they are inserted.
+    pub fn pin(&self, page_ids: &[u32]) -> DbResult<()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.pin(self.owner, page_ids)
+    }
+
+}
+
+impl Drop for PageCache {
+
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.inner) > 1 {
+            let mut inner = self.inner.lock().unwrap();
+            inner.invalidate(self.owner);
+            inner.pinned.retain(|key, _| key.0 != self.owner);
+        }
+    }
+
+}
+
+type CacheKey = (u64, u32);
+
+struct PageCacheInner {
+    page_count: usize,
+    page_size:  NonZeroU32,
+    policy:     PageCachePolicy,
+    layout:     Layout,
+    data:       *mut u8,
+    /// The pages of the LRU policy, or the hot pages of the 2Q policy.
+    hot:        LruCache<CacheKey, u32>,
+    /// The pages read once of the 2Q policy, in the order they are inserted.
+    cold:       LruCache<CacheKey, u32>,
+    /// The keys of the pages evicted from `cold` recently.
+    ghosts:     LruCache<CacheKey, ()>,
+    /// The pages never evicted, `None` if the page is not read yet.
+    pinned:     HashMap<CacheKey, Option<u32>>,
+    cold_limit: usize,
+    next_slot:  u32,
+    free_slots: Vec<u32>,
+}
+
+unsafe impl Send for PageCacheInner {}
+
+impl PageCacheInner {
+
+    fn new_default(page_size: NonZeroU32) -> PageCacheInner {
+        Self::new(1024, page_size, PageCachePolicy::default())
+    }
+
+    fn new(page_count: usize, page_size: NonZeroU32, policy: PageCachePolicy) -> PageCacheInner {
+        let cache_size = page_count * (page_size.get() as usize);
+
+        let layout = Layout::from_size_align(cache_size, 8).unwrap();
+        let data: *mut u8 = unsafe {
+            alloc(layout.clone()).cast()
+        };
+
+        // the sizes of the queues suggested by the paper of 2Q
+        let cold_limit = (page_count / 4).max(1);
+        let ghost_limit = NonZeroUsize::new((page_count / 2).max(1)).unwrap();
+
+        PageCacheInner {
+            page_count,
+            page_size,
+            policy,
+            layout,
+            data,
+            hot: LruCache::unbounded(),
+            cold: LruCache::unbounded(),
+            ghosts: LruCache::new(ghost_limit),
+            pinned: HashMap::new(),
+            cold_limit,
+            next_slot: 0,
+            free_slots: Vec::new(),
+        }
+    }
+
+    fn get_from_cache(&mut self, owner: u64, page_id: u32) -> Option<Arc<RawPage>> {
+        let key = (owner, page_id);
+        // the cold pages are not moved when they are read again,
+        // the reads of a scan are close to each other
+        let index = match self.pinned.get(&key) {
+            Some(index) => (*index)?,
+            None => match self.hot.get(&key) {
+                Some(index) => *index,
+                None => *self.cold.peek(&key)?,
+            },
+        };
+        let offset: usize = (index as usize) * (self.page_size.get() as usize);
+        let mut result = RawPage::new(page_id, self.page_size);
+        unsafe {
+            result.copy_from_ptr(self.data.add(offset as usize));
+        }
+        Some(Arc::new(result))
+    }
+
+    #[inline]
+    fn distribute_new_index(&mut self) -> u32 {
+        if let Some(index) = self.free_slots.pop() {
+            return index;
+        }
+        if (self.next_slot as usize) < self.page_count {  // is not full
+            let index = self.next_slot;
+            self.next_slot += 1;
+            return index;
+        }
+        let evict_cold = match self.policy {
+            PageCachePolicy::Lru => false,
+            PageCachePolicy::TwoQueue => self.cold.len() > self.cold_limit || self.hot.is_empty(),
+        };
+        if evict_cold {
+            let (key, index) = self.cold.pop_lru().expect("data error");
+            self.ghosts.put(key, ());
+            index
+        } else {
+            let (_, index) = self.hot.pop_lru().expect("data error");
+            index
+        }
+    }
+
+    fn insert_to_cache(&mut self, owner: u64, page: &RawPage) {
+        let key = (owner, page.page_id);
+        if let Some(pinned) = self.pinned.get(&key).copied() {
+            let index = match pinned {
+                Some(index) => index,
+                None => {
+                    let index = self.distribute_new_index();
+                    self.pinned.insert(key, Some(index));
+                    index
+                }
+            };
+            self.write_slot(index, page);
+            return;
+        }
+        let cached = match self.hot.get(&key) {
+            Some(index) => Some(*index),
+            None => self.cold.peek(&key).copied(),
+        };
+        let index = match cached {
+            Some(index) => index,  // override
+            None => {
+                let index = self.distribute_new_index();
+                let is_hot = match self.policy {
+                    PageCachePolicy::Lru => true,
+                    // read again after evicted from the cold pages
+                    PageCachePolicy::TwoQueue => self.ghosts.pop(&key).is_some(),
+                };
+                if is_hot {
+                    self.hot.put(key, index);
+                } else {
+                    self.cold.put(key, index);
+                }
+                index
+            }
+        };
+        self.write_slot(index, page);
+    }
+
+    fn write_slot(&mut self, index: u32, page: &RawPage) {
+        let offset = (index as usize) * (self.page_size.get() as usize);
+        unsafe {
+            page.copy_to_ptr(self.data.add(offset));
+        }
+    }
+
+    fn pin(&mut self, owner: u64, page_ids: &[u32]) -> DbResult<()> {
+        let keys: HashSet<CacheKey> = page_ids.iter().map(|page_id| (owner, *page_id)).collect();
+        let others = self.pinned.keys().filter(|key| key.0 != owner).count();
+        let limit = self.page_count / 2;
+        if others + keys.len() > limit {
+            return Err(DbErr::PinnedPagesExceeded(others + keys.len(), limit));
+        }
+
+        // the pages unpinned are kept as the hot pages
+        let unpinned: Vec<CacheKey> = self.pinned
+            .keys()
+            .filter(|key| key.0 == owner && !keys.contains(*key))
+            .copied()
+            .collect();
+        for key in unpinned {
+            if let Some(Some(index)) = self.pinned.remove(&key) {
+                self.hot.put(key, index);
+            }
+        }
+
+        for key in keys {
+            if self.pinned.contains_key(&key) {
+                continue;
+            }
+            let index = self.hot.pop(&key).or_else(|| self.cold.pop(&key));
+            self.ghosts.pop(&key);
+            self.pinned.insert(key, index);
+        }
+        Ok(())
+    }
+
+    fn invalidate(&mut self, owner: u64) {
+        let is_owned = |key: &CacheKey| key.0 == owner;
+        let keys: Vec<CacheKey> = self.hot
+            .iter()
+            .chain(self.cold.iter())
+            .map(|(key, _)| *key)
+            .filter(is_owned)
+            .collect();
+
+        for key in keys {
+            if let Some(index) = self.hot.pop(&key).or_else(|| self.cold.pop(&key)) {
+                self.free_slots.push(index);
+            }
+        }
+
+        let ghosts: Vec<CacheKey> = self.ghosts
+            .iter()
+            .map(|(key, _)| *key)
+            .filter(is_owned)
+            .collect();
+        for key in ghosts {
+            self.ghosts.pop(&key);
+        }
+
+        for (key, index) in self.pinned.iter_mut() {
+            if key.0 != owner {
+                continue;
+            }
+            if let Some(index) = index.take() {
+                self.free_slots.push(index);
+            }
+        }
+    }
+
+}
+
+impl Drop for PageCacheInner {
+
+    fn drop (&mut self) {
+        let layout = self.layout.clone();
+        unsafe {
+            dealloc(self.data.cast(), layout)
+        };
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use crate::backend::PageCache;
+    use crate::config::PageCachePolicy;
+    use crate::page::RawPage;
+    use crate::DbErr;
+
+    fn make_raw_page(page_id: u32) -> RawPage {
+        let mut page = RawPage::new(page_id, NonZeroU32::new(4096).unwrap());
+
+        for i in 0..4096 {
+            page.data[i] = unsafe {
+                libc::rand() as u8
+            }
+        }
+
+        page
+    }
+
+    static TEST_PAGE_LEN: u32 = 10;
+
+    #[test]
+    fn page_cache() {
+        let page_cache = PageCache::new(3, NonZeroU32::new(4096).unwrap(), PageCachePolicy::Lru);
+
+        let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
+
+        for i in 0..TEST_PAGE_LEN {
+            ten_pages.push(make_raw_page(i))
+        }
+
+        for i in 0..3 {
+            page_cache.insert_to_cache(&ten_pages[i as usize]);
+        }
+
+        for i in 0..3 {
+            let page = page_cache.get_from_cache(i).unwrap();
+
+            for (index, ch) in page.data.iter().enumerate() {
+                assert_eq!(*ch, ten_pages[i as usize].data[index])
+            }
+        }
+
+
+        for i in 3..6 {
+            page_cache.insert_to_cache(&ten_pages[i as usize]);
+        }
+
+        for i in 0..3 {
+            if let Some(_) = page_cache.get_from_cache(i) {
+                panic!("removed");
+            };
+        }
+
+        for i in 3..6 {
+            let page = page_cache.get_from_cache(i).unwrap();
+
+            for (index, ch) in page.data.iter().enumerate() {
+                assert_eq!(*ch, ten_pages[i as usize].data[index])
+            }
+        }
+    }
+
+    #[test]
+    fn shared_page_cache() {
+        let page_cache = PageCache::new(4, NonZeroU32::new(4096).unwrap(), PageCachePolicy::Lru);
+        let first = page_cache.share();
+        let second = page_cache.share();
+
+        first.insert_to_cache(&make_raw_page(1));
+        second.insert_to_cache(&make_raw_page(2));
+
+        assert!(first.get_from_cache(1).is_some());
+        assert!(first.get_from_cache(2).is_none());
+        assert!(second.get_from_cache(2).is_some());
+
+        first.invalidate();
+        assert!(first.get_from_cache(1).is_none());
+        assert!(second.get_from_cache(2).is_some());
+
+        // the slots freed by the first handle can be reused
+        for i in 10..13 {
+            first.insert_to_cache(&make_raw_page(i));
+        }
+        assert!(second.get_from_cache(2).is_some());
+    }
+
+    #[test]
+    fn two_queue_page_cache() {
+        let page_cache = PageCache::new(8, NonZeroU32::new(4096).unwrap(), PageCachePolicy::TwoQueue);
+
+        // the hot pages are read again after evicted from the cold queue
+        for i in 0..10 {
+            page_cache.insert_to_cache(&make_raw_page(i));
+        }
+        assert!(page_cache.get_from_cache(0).is_none());
+        page_cache.insert_to_cache(&make_raw_page(0));
+        page_cache.insert_to_cache(&make_raw_page(1));
+
+        // a scan reads many pages once
+        for i in 100..200 {
+            page_cache.insert_to_cache(&make_raw_page(i));
+            assert!(page_cache.get_from_cache(i).is_some());
+        }
+        assert!(page_cache.get_from_cache(0).is_some());
+        assert!(page_cache.get_from_cache(1).is_some());
+
+        let lru_cache = PageCache::new(8, NonZeroU32::new(4096).unwrap(), PageCachePolicy::Lru);
+        lru_cache.insert_to_cache(&make_raw_page(0));
+        for i in 100..200 {
+            lru_cache.insert_to_cache(&make_raw_page(i));
+        }
+        assert!(lru_cache.get_from_cache(0).is_none());
+    }
+
+    #[test]
+    fn pinned_page_cache() {
+        let page_cache = PageCache::new(8, NonZeroU32::new(4096).unwrap(), PageCachePolicy::Lru);
+        let other = page_cache.share();
+        page_cache.insert_to_cache(&make_raw_page(1));
+        page_cache.pin(&[1, 2]).unwrap();
+        assert!(matches!(other.pin(&[1, 2, 3]), Err(DbErr::PinnedPagesExceeded(5, 4))));
+
+        // the page not cached is pinned when it's inserted
+        assert!(page_cache.get_from_cache(2).is_none());
+        page_cache.insert_to_cache(&make_raw_page(2));
+        for i in 100..200 {
+            page_cache.insert_to_cache(&make_raw_page(i));
+        }
+        assert!(page_cache.get_from_cache(1).is_some());
+        assert!(page_cache.get_from_cache(2).is_some());
+
+        // the pages are unpinned by pinning the others
+        page_cache.pin(&[2]).unwrap();
+        other.pin(&[1, 2, 3]).unwrap();
+        for i in 100..200 {
+            page_cache.insert_to_cache(&make_raw_page(i));
+        }
+        assert!(page_cache.get_from_cache(1).is_none());
+        assert!(page_cache.get_from_cache(2).is_some());
+
+        // the pinned pages of a handle dropped are released
+        drop(other);
+        page_cache.pin(&[2, 3, 4, 5]).unwrap();
+    }
+
+}
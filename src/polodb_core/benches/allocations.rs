@@ -0,0 +1,71 @@
+//! Count the heap allocations per operation of the hot paths.
+//!
+//! ```sh
+//! cargo bench --package polodb_core --bench allocations
+//! ```
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicU64, Ordering};
+use polodb_core::Database;
+use polodb_core::bson::{doc, Document};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const COUNT: u64 = 1000;
+
+fn report<F: FnMut(i64)>(name: &str, mut f: F) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    for i in 0..(COUNT as i64) {
+        f(i);
+    }
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+    println!("{:<24} {:>8.1} allocations/op", name, allocations as f64 / COUNT as f64);
+}
+
+fn main() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+
+    report("insert", |i| {
+        collection.insert_one(doc! {
+            "_id": i,
+            "name": format!("name-{}", i),
+            "address": { "city": "Paris", "zip": { "code": i } },
+        }).unwrap();
+    });
+
+    report("find by dotted path", |i| {
+        let result = collection.find_one(doc! { "address.zip.code": i }).unwrap();
+        assert!(result.is_some());
+    });
+
+    report("update", |i| {
+        collection.update_one(doc! { "_id": i }, doc! { "$set": { "name": "updated" } }).unwrap();
+    });
+
+    report("delete", |i| {
+        collection.delete_one(doc! { "_id": i }).unwrap();
+    });
+}
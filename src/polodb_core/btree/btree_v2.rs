@@ -7,9 +7,9 @@ use std::cmp::Ordering;
 use std::io::{Read, Write};
 use std::num::NonZeroU32;
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
-use bson::Bson;
+use bson::{Binary, Bson};
 use bson::oid::ObjectId;
-use bson::spec::ElementType;
+use bson::spec::{BinarySubtype, ElementType};
 use crate::btree::{SearchKeyResult, vli};
 use crate::data_ticket::DataTicket;
 use crate::{DbErr, DbResult};
@@ -362,7 +362,20 @@ impl BTreePageDelegateWithKey {
     }
 
     pub fn divide_in_the_middle(&self, session: &dyn Session, right_page_pid: u32) -> DbResult<PageDivisionResult> {
-        let middle_index = self.len() / 2;
+        self.divide_at(session, right_page_pid, self.len() / 2)
+    }
+
+    /// Divide the page near the end, because the items are only appended to the right one.
+    /// The left page keeps about an eighth of the items as the headroom,
+    /// for the items of the children divided later.
+    pub fn divide_at_the_end(&self, session: &dyn Session, right_page_pid: u32) -> DbResult<PageDivisionResult> {
+        if self.len() < 3 {
+            return self.divide_in_the_middle(session, right_page_pid);
+        }
+        self.divide_at(session, right_page_pid, self.len() - 2 - self.len() / 8)
+    }
+
+    fn divide_at(&self, session: &dyn Session, right_page_pid: u32, middle_index: usize) -> DbResult<PageDivisionResult> {
         let mut middle_item = self.content[middle_index].clone();
 
         // use current page block to store left
@@ -447,11 +460,6 @@ impl BTreePageDelegateWithKey {
         &self.content[index]
     }
 
-    #[inline]
-    pub fn get_item_mut(&mut self, index: usize) -> &mut BTreeDataItemWithKey {
-        &mut self.content[index]
-    }
-
     pub fn merge_with_center(
         page_id: u32,
         parent_id: u32,
@@ -654,6 +662,12 @@ pub fn serialize_key<W: Write>(key: &Bson, writer: &mut W) -> DbResult<()> {
             Ok(())
         }
 
+        // the keys of the indexes
+        Bson::Binary(bin) => {
+            writer.write_all(&bin.bytes)?;
+            Ok(())
+        }
+
         _ => {
             let name = format!("{:?}", key);
             Err(DbErr::NotAValidKeyType(name))
@@ -678,13 +692,13 @@ pub fn deserialize_key_with_ty(key_ty: u8, buffer: &[u8]) -> DbResult<Bson> {
         }
 
         Some(ElementType::Int32) => {
-            let (int_value, _) = vli::decode_u64(buffer)?;
+            let (int_value, _) = vli::decode(buffer)?;
             Bson::Int32(int_value as i32)
         }
 
         Some(ElementType::Int64) => {
-            let (int_value, _) = vli::decode_u64(buffer)?;
-            Bson::Int64(int_value as i64)
+            let (int_value, _) = vli::decode(buffer)?;
+            Bson::Int64(int_value)
         }
 
         Some(ElementType::String) => {
@@ -692,6 +706,13 @@ pub fn deserialize_key_with_ty(key_ty: u8, buffer: &[u8]) -> DbResult<Bson> {
             Bson::String(str)
         }
 
+        Some(ElementType::Binary) => {
+            Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: buffer.to_vec(),
+            })
+        }
+
         _ => {
             let error_msg = format!("type {} is not suitable for _id", key_ty);
             return Err(DbErr::ParseError(error_msg));
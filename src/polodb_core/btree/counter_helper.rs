@@ -5,7 +5,9 @@
  */
 use crate::btree::btree_v2::{BTreePageDelegate, BTreePageDelegateWithKey};
 use crate::collection_info::CollectionSpecification;
+use crate::data_ticket::DataTicket;
 use crate::DbResult;
+use crate::page::large_data_page_wrapper::LargeDataPageWrapper;
 use crate::session::Session;
 
 pub(crate) fn count(session: &dyn Session, col_spec: &CollectionSpecification) -> DbResult<u64> {
@@ -34,3 +36,91 @@ fn count_by_btree_pid(session: &dyn Session, parent_pid: u32, pid: u32) -> DbRes
 
     Ok(result)
 }
+
+/// Count the documents and the bytes of them by scanning the btree,
+/// for the collections created before the stats were persisted.
+pub(crate) fn stats(session: &dyn Session, col_spec: &CollectionSpecification) -> DbResult<(u64, u64)> {
+    stats_by_btree_pid(session, 0, col_spec.info.root_pid)
+}
+
+fn stats_by_btree_pid(session: &dyn Session, parent_pid: u32, pid: u32) -> DbResult<(u64, u64)> {
+    let page = session.read_page(pid)?;
+    let delegate = BTreePageDelegate::from_page(page.as_ref(), parent_pid)?;
+    let btree_content = BTreePageDelegateWithKey::read_from_session(delegate, session)?;
+    if btree_content.is_empty() {
+        return Ok((0, 0));
+    }
+
+    let mut count = btree_content.len() as u64;
+    let mut data_size: u64 = 0;
+    for index in 0..btree_content.len() {
+        let bytes = session.get_data_from_storage(&btree_content.get_item(index).payload)?;
+        data_size += bytes.len() as u64;
+    }
+
+    if btree_content.is_leaf() {
+        return Ok((count, data_size));
+    }
+
+    let children_pid = btree_content.children_pid();
+    for child_pid in children_pid {
+        let (child_count, child_size) = stats_by_btree_pid(session, pid, child_pid)?;
+        count += child_count;
+        data_size += child_size;
+    }
+
+    Ok((count, data_size))
+}
+
+/// The pages of the btree and the pages of the data of its items, sorted,
+/// such as the pages to keep a collection or an index in the page cache.
+pub(crate) fn pages(session: &dyn Session, root_pid: u32) -> DbResult<Vec<u32>> {
+    let mut result = Vec::new();
+    pages_by_btree_pid(session, 0, root_pid, &mut result)?;
+    // the data pages are shared by the items
+    result.sort_unstable();
+    result.dedup();
+    Ok(result)
+}
+
+fn pages_by_btree_pid(session: &dyn Session, parent_pid: u32, pid: u32, result: &mut Vec<u32>) -> DbResult<()> {
+    result.push(pid);
+    let page = session.read_page(pid)?;
+    let delegate = BTreePageDelegate::from_page(page.as_ref(), parent_pid)?;
+    let btree_content = BTreePageDelegateWithKey::read_from_session(delegate, session)?;
+    if btree_content.is_empty() {
+        return Ok(());
+    }
+
+    for index in 0..btree_content.len() {
+        let item = btree_content.get_item(index);
+        data_pages(session, &item.payload, result)?;
+        if let Some(key_ticket) = &item.key_data_ticket {
+            data_pages(session, key_ticket, result)?;
+        }
+    }
+
+    if btree_content.is_leaf() {
+        return Ok(());
+    }
+
+    for child_pid in btree_content.children_pid() {
+        pages_by_btree_pid(session, pid, child_pid, result)?;
+    }
+
+    Ok(())
+}
+
+fn data_pages(session: &dyn Session, ticket: &DataTicket, result: &mut Vec<u32>) -> DbResult<()> {
+    if !ticket.is_large_data() {
+        result.push(ticket.pid);
+        return Ok(());
+    }
+    let mut next_pid = ticket.pid;
+    while next_pid != 0 {
+        result.push(next_pid);
+        let page = session.read_page(next_pid)?;
+        next_pid = LargeDataPageWrapper::from_raw(page.as_ref().clone()).next_pid();
+    }
+    Ok(())
+}
@@ -14,6 +14,11 @@ pub(crate) fn delete_all(session: &dyn Session, col_spec: &CollectionSpecificati
     delete_all_by_btree_pid(session, 0, col_spec.info.root_pid)
 }
 
+/// Free all the pages of the btree, such as the btree of an index.
+pub(crate) fn delete_btree(session: &dyn Session, root_pid: u32) -> DbResult<()> {
+    delete_all_by_btree_pid(session, 0, root_pid)
+}
+
 fn delete_all_by_btree_pid(session: &dyn Session, parent_id: u32, pid: u32) -> DbResult<()> {
     crate::polo_log!("delete all: parent pid: {}, pid: {}", parent_id, pid);
     let page = session.read_page(pid)?;
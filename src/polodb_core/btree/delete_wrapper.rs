@@ -11,6 +11,7 @@ use super::SearchKeyResult;
 use super::wrapper_base::BTreePageWrapperBase;
 use crate::DbResult;
 use crate::data_ticket::DataTicket;
+use crate::compression;
 use crate::session::Session;
 
 struct DeletedContent {
@@ -40,6 +41,7 @@ pub struct BTreePageDeleteWrapper<'a> {
     base:           BTreePageWrapperBase<'a>,
     dirty_set:      BTreeSet<u32>,
     cache_btree:    HashMap<u32, BTreePageDelegateWithKey>,
+    deleted_size:   u64,
 }
 
 impl<'a> BTreePageDeleteWrapper<'a>  {
@@ -50,9 +52,16 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             base,
             dirty_set: BTreeSet::new(),
             cache_btree: HashMap::new(),
+            deleted_size: 0,
         }
     }
 
+    /// The bytes of the documents erased by this wrapper.
+    #[inline]
+    pub(crate) fn deleted_size(&self) -> u64 {
+        self.deleted_size
+    }
+
     #[inline]
     fn get_btree_by_pid(&mut self, pid: u32, parent_pid: u32) -> DbResult<BTreePageDelegateWithKey> {
         self.base.get_node(pid, parent_pid)
@@ -151,7 +160,10 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
                 let current_pid = current_btree_node.page_id();
                 let subtree_pid = current_btree_node.get_right_pid(idx);
                 let next_item = self.find_min_element_in_subtree(subtree_pid, current_pid)?;
-                current_btree_node.update_content(idx, next_item.clone());
+                // the item from the leaf takes the place, but the left child is kept
+                let mut replace_item = next_item.clone();
+                replace_item.left_pid = current_btree_node.get_item(idx).left_pid;
+                current_btree_node.update_content(idx, replace_item);
                 self.write_btree(&current_btree_node)?;
 
                 let backward_opt = self.delete_item_on_subtree(
@@ -166,7 +178,9 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
                     }));
                 }
 
-                let backward_item = backward_opt.unwrap();
+                // the item moved from the leaf is kept, the one replaced by it is deleted
+                let mut backward_item = backward_opt.unwrap();
+                backward_item.deleted_content = deleted_content;
 
                 if BTreePageDeleteWrapper::remain_size_too_large(&current_btree_node, backward_item.child_remain_size) {
                     return self.re_balance_left_branch(current_btree_node, backward_item, idx + 1);
@@ -208,7 +222,7 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
                 Ok(Some(DeleteBackwardItem {
                     is_leaf: false,
                     child_remain_size: current_btree_node.remain_size(),
-                    deleted_content,
+                    deleted_content: backward_item.deleted_content,
                 }))
             },
         }
@@ -334,7 +348,7 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
         // If max_brother_size satisfies the number, shift one item the middle child.
         // Otherwise, merge the brother the the middle child
         // if !BTreePageDeleteWrapper::remain_size_too_large(current_btree_node, max_brother_size) {
-        let replace_item = if is_right { // middle <-(item)- right
+        let (replace_index, replace_item) = if is_right { // middle <-(item)- right
             let mut shift_node = right_node_opt.unwrap();
             let mut right_item = shift_node.shift_head();
             let shift_node_bytes_size = right_item.bytes_size();
@@ -354,7 +368,7 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             self.write_btree(&shift_node)?;
             self.write_btree(&subtree_node)?;
 
-            right_item
+            (node_idx, right_item)
         } else {  // left -(item)-> middle
             let mut shift_node = left_node_opt.unwrap();
             let (mut left_last_content, last_right_pid) = shift_node.shift_last();
@@ -366,7 +380,8 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
                 return Ok(false);
             }
 
-            let mut middle_item = current_btree_node.get_item(node_idx).clone();
+            // the item between the left brother and the middle child
+            let mut middle_item = current_btree_node.get_item(node_idx - 1).clone();
             left_last_content.left_pid = middle_item.left_pid;
             middle_item.left_pid = last_right_pid;
             subtree_node.insert_head(middle_item);
@@ -374,11 +389,11 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             self.write_btree(&shift_node)?;
             self.write_btree(&subtree_node)?;
 
-            left_last_content
+            (node_idx - 1, left_last_content)
         };
 
         // shift complete
-        current_btree_node.update_content(node_idx, replace_item);
+        current_btree_node.update_content(replace_index, replace_item);
 
         return Ok(true);
     }
@@ -422,7 +437,7 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             left_node.push(clone);
             left_node.merge_left_leave(&subtree_node);
 
-            current_btree_node.get_item_mut(node_idx).left_pid = left_node.page_id();
+            current_btree_node.set_right_pid(node_idx - 1, left_node.page_id());
             current_btree_node.remove_item(node_idx - 1);
 
             self.base.session.free_page(subtree_pid)?;
@@ -443,9 +458,9 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             //
             // assert_eq!(current_btree_node.indexes[node_idx + 1], right_node.pid);
 
-            current_btree_node.remove_item(node_idx);
             // set left pid for the new node
-            current_btree_node.get_item_mut(node_idx).left_pid = subtree_pid;
+            current_btree_node.set_right_pid(node_idx, subtree_pid);
+            current_btree_node.remove_item(node_idx);
 
             self.base.session.free_page(right_node.page_id())?;
 
@@ -461,9 +476,8 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
         }
         let bytes = self.base.session.free_data_ticket(&item.payload)?;
         assert!(!bytes.is_empty(), "bytes is empty");
-        let mut my_ref: &[u8] = bytes.as_ref();
-        let doc = bson::from_slice(&mut my_ref)?;
-        Ok(doc)
+        self.deleted_size += bytes.len() as u64;
+        compression::decode_doc(&bytes)
     }
 
     // #[inline]
@@ -482,7 +496,7 @@ impl<'a> BTreePageDeleteWrapper<'a>  {
             (Some(pid), None)
         } else {
             let left_pid = btree_node.get_left_pid(node_idx - 1);
-            let right_pid = btree_node.get_right_pid(node_idx + 1);
+            let right_pid = btree_node.get_right_pid(node_idx);
             (Some(left_pid), Some(right_pid))
         }
     }
@@ -0,0 +1,670 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The secondary indexes of the collections.
+//!
+//! An index is a btree of the entries `{ _id: <key>, pk: <primary key> }`.
+//! The key is the values of the fields of the index encoded in binary,
+//! followed by the primary key unless the index is unique, so the entries
+//! are ordered by the values as they are compared by the queries.
+//!
+//! A value is encoded as the tag of its type followed by the content:
+//!
+//! | type                    | tag        | content                                    |
+//! |-------------------------|------------|--------------------------------------------|
+//! | missing                 | 0x00       |                                            |
+//! | Double, Int32 and Int64 | 0x01       | the f64 of the number, then the i64 of it  |
+//! | String and Binary       | the type   | the bytes, ended by 0x00 0x00              |
+//! | ObjectId                | 0x07       | the 12 bytes                               |
+//! | Boolean                 | 0x08       | 0 or 1                                     |
+//! | DateTime                | 0x09       | the i64 of the milliseconds                |
+//! | Null and Undefined      | the type   |                                            |
+//! | MinKey                  | 0x80       |                                            |
+//! | the others              | the type   | the BSON of the value, ended by 0x00 0x00  |
+//!
+//! The numbers are big-endian and ordered bytewise, and the zeros in the bytes
+//! are escaped by 0x00 0x01. The values of the different types are compared by the
+//! codes of the types, so a range of the values of a type is scanned with the
+//! whole ranges of the other types satisfying the condition.
+use bson::{Binary, Bson, Document};
+use bson::spec::{BinarySubtype, ElementType};
+use crate::btree::{BTreePageDeleteWrapper, BTreePageInsertWrapper};
+use crate::btree::delete_all_helper;
+use crate::collection_info::{CollectionSpecification, IndexInfo};
+use crate::cursor::Cursor;
+use crate::page::RawPage;
+use crate::session::Session;
+use crate::{DbErr, DbResult, DuplicateKeyError, IndexOptions};
+
+/// The field of the entry keeping the primary key of the document.
+const ENTRY_PKEY: &str = "pk";
+
+const TAG_MISSING: u8 = 0x00;
+const TAG_NUMBER: u8 = 0x01;
+const TAG_MIN_KEY: u8 = 0x80;
+
+// the bytes of the tag and the f64 of a number, the i64 only tells
+// the integers not exactly converted to f64 apart
+const NUMBER_PREFIX_SIZE: usize = 9;
+
+const ALL_TYPES: [ElementType; 21] = [
+    ElementType::Double,
+    ElementType::String,
+    ElementType::EmbeddedDocument,
+    ElementType::Array,
+    ElementType::Binary,
+    ElementType::Undefined,
+    ElementType::ObjectId,
+    ElementType::Boolean,
+    ElementType::DateTime,
+    ElementType::Null,
+    ElementType::RegularExpression,
+    ElementType::DbPointer,
+    ElementType::JavaScriptCode,
+    ElementType::Symbol,
+    ElementType::JavaScriptCodeWithScope,
+    ElementType::Int32,
+    ElementType::Timestamp,
+    ElementType::Int64,
+    ElementType::Decimal128,
+    ElementType::MaxKey,
+    ElementType::MinKey,
+];
+
+/// The keys in `[lower, upper)`.
+type KeyRange = (Vec<u8>, Vec<u8>);
+
+fn tag_of_type(ty: ElementType) -> u8 {
+    match ty {
+        ElementType::Double | ElementType::Int32 | ElementType::Int64 => TAG_NUMBER,
+        ElementType::MinKey => TAG_MIN_KEY,
+        _ => ty as u8,
+    }
+}
+
+fn encode_f64(value: f64) -> [u8; 8] {
+    let bits = value.to_bits();
+    let bits = if bits >> 63 == 1 { !bits } else { bits | (1 << 63) };
+    bits.to_be_bytes()
+}
+
+fn encode_i64(value: i64) -> [u8; 8] {
+    ((value as u64) ^ (1 << 63)).to_be_bytes()
+}
+
+fn encode_bytes(bytes: &[u8], buffer: &mut Vec<u8>) {
+    for byte in bytes {
+        if *byte == 0 {
+            buffer.extend_from_slice(&[0, 1]);
+        } else {
+            buffer.push(*byte);
+        }
+    }
+    buffer.extend_from_slice(&[0, 0]);
+}
+
+fn encode_value(value: Option<&Bson>, buffer: &mut Vec<u8>) -> DbResult<()> {
+    let value = match value {
+        Some(value) => value,
+        None => {
+            buffer.push(TAG_MISSING);
+            return Ok(());
+        }
+    };
+    buffer.push(tag_of_type(value.element_type()));
+    match value {
+        Bson::Double(num) => {
+            buffer.extend_from_slice(&encode_f64(*num));
+            buffer.extend_from_slice(&encode_i64(*num as i64));
+        }
+        Bson::Int32(num) => {
+            buffer.extend_from_slice(&encode_f64(*num as f64));
+            buffer.extend_from_slice(&encode_i64(*num as i64));
+        }
+        Bson::Int64(num) => {
+            buffer.extend_from_slice(&encode_f64(*num as f64));
+            buffer.extend_from_slice(&encode_i64(*num));
+        }
+        Bson::String(str) => encode_bytes(str.as_bytes(), buffer),
+        // the subtype is not compared
+        Bson::Binary(bin) => encode_bytes(&bin.bytes, buffer),
+        Bson::ObjectId(oid) => buffer.extend_from_slice(&oid.bytes()),
+        Bson::Boolean(bl) => buffer.push(*bl as u8),
+        Bson::DateTime(datetime) => buffer.extend_from_slice(&encode_i64(datetime.timestamp_millis())),
+        Bson::Null | Bson::Undefined | Bson::MaxKey | Bson::MinKey => (),
+        // only kept apart, the values of these types are not ordered
+        _ => {
+            let mut wrapper = Document::new();
+            wrapper.insert("v", value.clone());
+            encode_bytes(&bson::to_vec(&wrapper)?, buffer);
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn get_path<'a>(doc: &'a Document, path: &str) -> Option<&'a Bson> {
+    let mut current = doc;
+    let mut fields = path.split('.').peekable();
+    while let Some(field) = fields.next() {
+        let value = current.get(field)?;
+        if fields.peek().is_none() {
+            return Some(value);
+        }
+        current = value.as_document()?;
+    }
+    None
+}
+
+fn entry_key(info: &IndexInfo, doc: &Document) -> DbResult<Bson> {
+    let mut key = Vec::new();
+    for field in info.key.keys() {
+        encode_value(get_path(doc, field), &mut key)?;
+    }
+    if !info.unique {
+        encode_value(doc.get("_id"), &mut key)?;
+    }
+    Ok(binary_key(key))
+}
+
+fn binary_key(bytes: Vec<u8>) -> Bson {
+    Bson::Binary(Binary {
+        subtype: BinarySubtype::Generic,
+        bytes,
+    })
+}
+
+fn duplicate_key(name: &str, info: &IndexInfo, doc: &Document) -> DbErr {
+    let mut values = Document::new();
+    for field in info.key.keys() {
+        let value = get_path(doc, field).cloned().unwrap_or(Bson::Null);
+        values.insert(field.clone(), value);
+    }
+    DbErr::DuplicateKey(Box::new(DuplicateKeyError {
+        index: name.to_string(),
+        key: values.to_string(),
+    }))
+}
+
+/// Validate the fields of an index, and return the default name of it,
+/// such as `"age_1_name_-1"` for `{ "age": 1, "name": -1 }`.
+fn default_name(keys: &Document) -> DbResult<String> {
+    if keys.is_empty() {
+        return Err(DbErr::ValidationError("an index needs at least one field".to_string()));
+    }
+    let mut parts = Vec::with_capacity(keys.len());
+    for (field, order) in keys {
+        let order = match order {
+            Bson::Int32(order @ (1 | -1)) => *order as i64,
+            Bson::Int64(order @ (1 | -1)) => *order,
+            _ => return Err(DbErr::InvalidOrderOfIndex(field.clone())),
+        };
+        parts.push(format!("{}_{}", field, order));
+    }
+    Ok(parts.join("_"))
+}
+
+fn new_root(session: &dyn Session) -> DbResult<u32> {
+    let root_pid = session.alloc_page_id()?;
+    let root_page = RawPage::new(root_pid, session.page_size());
+    session.write_page(&root_page)?;
+    Ok(root_pid)
+}
+
+/// Create the index and add the entries of the documents of the collection,
+/// return the name of the index.
+/// The outside should write the new spec to the meta.
+pub(crate) fn create_index(
+    session: &dyn Session,
+    col_spec: &mut CollectionSpecification,
+    keys: &Document,
+    options: &IndexOptions,
+) -> DbResult<String> {
+    let default_name = default_name(keys)?;
+    let name = options.name.clone().unwrap_or(default_name);
+    let unique = options.unique.unwrap_or(false);
+    if let Some(info) = col_spec.indexes.get(&name) {
+        // creating the same index again does nothing
+        if info.key == *keys && info.unique == unique {
+            return Ok(name);
+        }
+        return Err(DbErr::IndexAlreadyExists(name));
+    }
+
+    let mut info = IndexInfo {
+        key: keys.clone(),
+        unique,
+        root_pid: new_root(session)?,
+    };
+    let mut cursor = Cursor::new(col_spec.info.root_pid);
+    cursor.reset(session)?;
+    while let Some(doc) = cursor.next(session)? {
+        insert_entry(session, &name, &mut info, &doc)?;
+    }
+
+    col_spec.indexes.insert(name.clone(), info);
+    Ok(name)
+}
+
+/// Remove the index and free its pages.
+/// The outside should write the new spec to the meta.
+pub(crate) fn drop_index(session: &dyn Session, col_spec: &mut CollectionSpecification, name: &str) -> DbResult<()> {
+    let info = col_spec.indexes
+        .remove(name)
+        .ok_or_else(|| DbErr::IndexNotFound(name.to_string()))?;
+    delete_all_helper::delete_btree(session, info.root_pid)
+}
+
+/// Free the pages of all the indexes of the dropped collection.
+pub(crate) fn drop_all(session: &dyn Session, col_spec: &CollectionSpecification) -> DbResult<()> {
+    for info in col_spec.indexes.values() {
+        delete_all_helper::delete_btree(session, info.root_pid)?;
+    }
+    Ok(())
+}
+
+/// Empty the indexes of the truncated collection.
+pub(crate) fn truncate_all(session: &dyn Session, col_spec: &mut CollectionSpecification) -> DbResult<()> {
+    for info in col_spec.indexes.values_mut() {
+        delete_all_helper::delete_btree(session, info.root_pid)?;
+        info.root_pid = new_root(session)?;
+    }
+    Ok(())
+}
+
+/// Check the unique indexes before the document is inserted,
+/// so nothing is written if it fails.
+pub(crate) fn check_unique(session: &dyn Session, col_spec: &CollectionSpecification, doc: &Document) -> DbResult<()> {
+    for (name, info) in &col_spec.indexes {
+        if !info.unique {
+            continue;
+        }
+        let key = entry_key(info, doc)?;
+        let mut cursor = Cursor::new(info.root_pid);
+        if cursor.reset_by_pkey(session, &key)? {
+            return Err(duplicate_key(name, info, doc));
+        }
+    }
+    Ok(())
+}
+
+/// Check the unique indexes before the documents are updated, with the documents
+/// before and after the updates, so nothing is written if it fails.
+pub(crate) fn check_unique_updates(
+    session: &dyn Session,
+    col_spec: &CollectionSpecification,
+    updated: &[(Document, Document)],
+) -> DbResult<()> {
+    for (name, info) in &col_spec.indexes {
+        if !info.unique {
+            continue;
+        }
+        let mut removed = Vec::new();
+        let mut added: Vec<(Bson, &Document)> = Vec::new();
+        for (before, after) in updated {
+            let old_key = entry_key(info, before)?;
+            let new_key = entry_key(info, after)?;
+            if old_key != new_key {
+                removed.push(old_key);
+                added.push((new_key, after));
+            }
+        }
+        for (index, (key, doc)) in added.iter().enumerate() {
+            // the entries removed by the same updates can be taken
+            let exists = !removed.contains(key) && Cursor::new(info.root_pid).reset_by_pkey(session, key)?;
+            if exists || added[..index].iter().any(|(other, _)| other == key) {
+                return Err(duplicate_key(name, info, doc));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add the entries of the inserted document to the indexes,
+/// the outside should write the new spec to the meta.
+pub(crate) fn insert_entries(session: &dyn Session, col_spec: &mut CollectionSpecification, doc: &Document) -> DbResult<()> {
+    for (name, info) in col_spec.indexes.iter_mut() {
+        insert_entry(session, name, info, doc)?;
+    }
+    Ok(())
+}
+
+fn insert_entry(session: &dyn Session, name: &str, info: &mut IndexInfo, doc: &Document) -> DbResult<()> {
+    let mut entry = Document::new();
+    entry.insert("_id", entry_key(info, doc)?);
+    entry.insert(ENTRY_PKEY, doc.get("_id").cloned().unwrap_or(Bson::Null));
+
+    let mut insert_wrapper = BTreePageInsertWrapper::new(session, info.root_pid);
+    let insert_result = match insert_wrapper.insert_item(&entry, false) {
+        Ok(result) => result,
+        Err(DbErr::DataExist(_)) => return Err(duplicate_key(name, info, doc)),
+        Err(err) => return Err(err),
+    };
+
+    if let Some(backward_item) = insert_result.backward_item {
+        let new_root_id = session.alloc_page_id()?;
+        let raw_page = backward_item.write_to_page(session, new_root_id, info.root_pid)?;
+        session.write_page(&raw_page)?;
+        info.root_pid = new_root_id;
+    }
+
+    Ok(())
+}
+
+/// Remove the entries of the deleted document from the indexes.
+pub(crate) fn delete_entries(session: &dyn Session, col_spec: &CollectionSpecification, doc: &Document) -> DbResult<()> {
+    for info in col_spec.indexes.values() {
+        delete_entry(session, info, doc)?;
+    }
+    Ok(())
+}
+
+fn delete_entry(session: &dyn Session, info: &IndexInfo, doc: &Document) -> DbResult<()> {
+    let key = entry_key(info, doc)?;
+    let mut delete_wrapper = BTreePageDeleteWrapper::new(session, info.root_pid);
+    delete_wrapper.delete_item(&key)?;
+    delete_wrapper.flush_pages()
+}
+
+/// Replace the entries of the updated documents, given by the documents before
+/// and after the update. All the old entries are removed before the new ones are added,
+/// so the values of a unique index can be swapped by an update.
+/// The outside should write the new spec to the meta.
+pub(crate) fn update_entries(
+    session: &dyn Session,
+    col_spec: &mut CollectionSpecification,
+    updated: &[(Document, Document)],
+) -> DbResult<()> {
+    for (name, info) in col_spec.indexes.iter_mut() {
+        let mut changed = Vec::new();
+        for (before, after) in updated {
+            if entry_key(info, before)? != entry_key(info, after)? {
+                delete_entry(session, info, before)?;
+                changed.push(after);
+            }
+        }
+        for doc in changed {
+            insert_entry(session, name, info, doc)?;
+        }
+    }
+    Ok(())
+}
+
+/// The ranges of the keys of an index containing the entries of all the documents
+/// possibly satisfying a query. The query is still checked on the documents found.
+pub(crate) struct IndexScan {
+    root_pid: u32,
+    ranges:   Vec<KeyRange>,
+}
+
+impl IndexScan {
+
+    /// Choose an index by the conditions on the first field of it,
+    /// the indexes of the equalities are preferred to the ones of the ranges.
+    pub(crate) fn plan(col_spec: &CollectionSpecification, query: &Document) -> Option<IndexScan> {
+        // the document is found by the primary key
+        if let Some(id) = query.get("_id") {
+            if id.element_type() != ElementType::EmbeddedDocument {
+                return None;
+            }
+        }
+
+        let mut names: Vec<&String> = col_spec.indexes.keys().collect();
+        names.sort();
+
+        let mut best: Option<(bool, IndexScan)> = None;
+        for name in names {
+            let info = &col_spec.indexes[name];
+            let condition = match info.key.keys().next().and_then(|field| query.get(field)) {
+                Some(condition) => condition,
+                None => continue,
+            };
+            let (ranges, is_equality) = match ranges_of_condition(condition) {
+                Some(result) => result,
+                None => continue,
+            };
+            let is_better = match &best {
+                Some((best_is_equality, _)) => is_equality && !*best_is_equality,
+                None => true,
+            };
+            if is_better {
+                best = Some((is_equality, IndexScan {
+                    root_pid: info.root_pid,
+                    ranges,
+                }));
+            }
+        }
+        best.map(|(_, scan)| scan)
+    }
+
+    /// Collect the primary keys of the entries in the ranges, not sorted.
+    pub(crate) fn collect_keys(&self, session: &dyn Session) -> DbResult<Vec<Bson>> {
+        let mut result = Vec::new();
+        let mut cursor = Cursor::new(self.root_pid);
+        for (lower, upper) in &self.ranges {
+            cursor.seek(session, &binary_key(lower.clone()))?;
+            loop {
+                match cursor.peek_key()? {
+                    Some(Bson::Binary(key)) if key.bytes < *upper => (),
+                    _ => break,
+                }
+                let entry = match cursor.next(session)? {
+                    Some(entry) => entry,
+                    None => break,
+                };
+                if let Some(pkey) = entry.get(ENTRY_PKEY) {
+                    result.push(pkey.clone());
+                }
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+/// The values compared by `value_cmp`, the others are only equal or not.
+fn is_ordered(value: &Bson) -> bool {
+    matches!(
+        value,
+        Bson::Null | Bson::Undefined | Bson::DateTime(_) | Bson::Boolean(_) |
+        Bson::Int32(_) | Bson::Int64(_) | Bson::Double(_) |
+        Bson::Binary(_) | Bson::String(_) | Bson::ObjectId(_)
+    )
+}
+
+/// The prefix of the keys of the value, the numbers equal to each other
+/// by `value_cmp` have the same prefix.
+fn value_prefix(value: &Bson) -> Option<Vec<u8>> {
+    let mut buffer = Vec::new();
+    encode_value(Some(value), &mut buffer).ok()?;
+    if buffer[0] == TAG_NUMBER {
+        buffer.truncate(NUMBER_PREFIX_SIZE);
+    }
+    Some(buffer)
+}
+
+/// The least bytes greater than all the bytes starting with `prefix`.
+fn successor(prefix: &[u8]) -> Vec<u8> {
+    let mut result = prefix.to_vec();
+    while let Some(last) = result.pop() {
+        if last < 0xFF {
+            result.push(last + 1);
+            break;
+        }
+    }
+    result
+}
+
+/// The ranges of the keys satisfying the condition on a field, and whether
+/// it's only the equalities. `None` if the index can't help.
+fn ranges_of_condition(condition: &Bson) -> Option<(Vec<KeyRange>, bool)> {
+    let ops = match condition {
+        Bson::Document(ops) if ops.keys().any(|key| key.starts_with('$')) => ops,
+        _ => return point_ranges(std::slice::from_ref(condition)).map(|ranges| (ranges, true)),
+    };
+
+    let mut result: Option<Vec<KeyRange>> = None;
+    let mut is_equality = true;
+    for (op, value) in ops {
+        let ranges = match op.as_str() {
+            "$eq" => point_ranges(std::slice::from_ref(value)),
+            "$in" => value.as_array().and_then(|values| point_ranges(values)),
+            "$gt" | "$gte" => {
+                is_equality = false;
+                bound_ranges(value, true)
+            }
+            "$lt" | "$lte" => {
+                is_equality = false;
+                bound_ranges(value, false)
+            }
+            _ => None,
+        };
+        // the conditions are all satisfied, the others are checked on the documents
+        if let Some(ranges) = ranges {
+            result = Some(match result {
+                Some(prev) => intersect(&prev, &ranges),
+                None => ranges,
+            });
+        }
+    }
+    result.map(|ranges| (ranges, is_equality))
+}
+
+fn point_ranges(values: &[Bson]) -> Option<Vec<KeyRange>> {
+    let mut ranges = Vec::with_capacity(values.len());
+    for value in values {
+        // null may match the missing fields, leave it to the scan of the collection
+        if !is_ordered(value) || matches!(value, Bson::Null | Bson::Undefined) {
+            return None;
+        }
+        let prefix = value_prefix(value)?;
+        let upper = successor(&prefix);
+        ranges.push((prefix, upper));
+    }
+    Some(merge(ranges))
+}
+
+/// The values greater than the value, or less than it if `is_lower` is false,
+/// with the value itself.
+fn bound_ranges(value: &Bson, is_lower: bool) -> Option<Vec<KeyRange>> {
+    if !is_ordered(value) {
+        return None;
+    }
+    let ty = value.element_type();
+    let tag = tag_of_type(ty);
+    let prefix = value_prefix(value)?;
+    let mut ranges = vec![
+        if is_lower {
+            (prefix, vec![tag + 1])
+        } else {
+            let upper = successor(&prefix);
+            (vec![tag], upper)
+        }
+    ];
+
+    // the values of the other types are compared by the codes of the types
+    let mut other_tags: Vec<u8> = ALL_TYPES
+        .iter()
+        .filter(|other| if is_lower { **other as u8 > ty as u8 } else { (**other as u8) < ty as u8 })
+        .map(|other| tag_of_type(*other))
+        .filter(|other_tag| *other_tag != tag)
+        .collect();
+    other_tags.sort_unstable();
+    other_tags.dedup();
+    ranges.extend(other_tags.into_iter().map(|other_tag| (vec![other_tag], vec![other_tag + 1])));
+
+    Some(merge(ranges))
+}
+
+fn merge(mut ranges: Vec<KeyRange>) -> Vec<KeyRange> {
+    ranges.sort();
+    let mut result: Vec<KeyRange> = Vec::with_capacity(ranges.len());
+    for (lower, upper) in ranges {
+        if let Some(last) = result.last_mut() {
+            if lower <= last.1 {
+                if upper > last.1 {
+                    last.1 = upper;
+                }
+                continue;
+            }
+        }
+        result.push((lower, upper));
+    }
+    result
+}
+
+fn intersect(a: &[KeyRange], b: &[KeyRange]) -> Vec<KeyRange> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let lower = std::cmp::max(&a[i].0, &b[j].0);
+        let upper = std::cmp::min(&a[i].1, &b[j].1);
+        if lower < upper {
+            result.push((lower.clone(), upper.clone()));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use bson::{Bson, doc};
+    use bson::oid::ObjectId;
+    use crate::bson_utils::value_cmp;
+    use super::{encode_value, ranges_of_condition, value_prefix};
+
+    fn encode(value: &Bson) -> Vec<u8> {
+        let mut buffer = Vec::new();
+        encode_value(Some(value), &mut buffer).unwrap();
+        buffer
+    }
+
+    #[test]
+    fn test_encode_order() {
+        let groups = vec![
+            vec![Bson::Double(-1.5), Bson::Int32(0), Bson::Int64(3), Bson::Double(3.5), Bson::Int64(i64::MAX)],
+            vec![Bson::String("".into()), Bson::String("a".into()), Bson::String("a\0".into()), Bson::String("ab".into())],
+            vec![Bson::ObjectId(ObjectId::from([0; 12])), Bson::ObjectId(ObjectId::from([1; 12]))],
+            vec![Bson::Boolean(false), Bson::Boolean(true)],
+        ];
+        for values in &groups {
+            for pair in values.windows(2) {
+                assert_eq!(value_cmp(&pair[0], &pair[1]).unwrap(), Ordering::Less);
+                assert!(encode(&pair[0]) < encode(&pair[1]), "{} < {}", pair[0], pair[1]);
+            }
+        }
+        assert_eq!(value_prefix(&Bson::Int32(3)), value_prefix(&Bson::Double(3.0)));
+        assert_ne!(encode(&Bson::Int64(1 << 60)), encode(&Bson::Int64((1 << 60) + 1)));
+    }
+
+    #[test]
+    fn test_ranges_of_condition() {
+        let (ranges, is_equality) = ranges_of_condition(&Bson::Int32(5)).unwrap();
+        assert!(is_equality);
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges[0].0 <= encode(&Bson::Double(5.0)) && encode(&Bson::Double(5.0)) < ranges[0].1);
+
+        let (ranges, is_equality) = ranges_of_condition(&Bson::Document(doc! { "$gte": 10, "$lt": 20 })).unwrap();
+        assert!(!is_equality);
+        let inside = |value: Bson| {
+            let key = encode(&value);
+            ranges.iter().any(|(lower, upper)| *lower <= key && key < *upper)
+        };
+        assert!(inside(Bson::Int64(15)));
+        assert!(!inside(Bson::Int32(25)));
+        assert!(!inside(Bson::Int32(5)));
+
+        assert!(ranges_of_condition(&Bson::Document(doc! { "$ne": 1 })).is_none());
+        assert!(ranges_of_condition(&Bson::Document(doc! { "city": "Paris" })).is_none());
+        assert!(ranges_of_condition(&Bson::Array(vec![Bson::Int32(1)])).is_none());
+    }
+
+}
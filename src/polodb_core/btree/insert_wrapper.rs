@@ -3,9 +3,9 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use bson::Document;
+use bson::{Bson, Document};
 use crate::btree::btree_v2::{BTreeDataItemWithKey, BTreePageDelegate, BTreePageDelegateWithKey, serialize_key};
-use crate::DbResult;
+use crate::{Compression, DbResult};
 use crate::page::RawPage;
 use crate::error::DbErr;
 use crate::data_ticket::DataTicket;
@@ -85,13 +85,52 @@ mod doc_validation {
 // Offset 0: right pid(4 bytes)
 // Offset 4: overflow_pid(4 bytes)
 // Offset 8: data
-pub struct BTreePageInsertWrapper<'a>(BTreePageWrapperBase<'a>);
+pub struct BTreePageInsertWrapper<'a>(BTreePageWrapperBase<'a>, Compression, u64, bool);
 
 impl<'a> BTreePageInsertWrapper<'a> {
 
     pub(crate) fn new(page_handler: &dyn Session, root_page_id: u32) -> BTreePageInsertWrapper {
+        BTreePageInsertWrapper::new_with_compression(page_handler, root_page_id, Compression::None)
+    }
+
+    pub(crate) fn new_with_compression(
+        page_handler: &dyn Session,
+        root_page_id: u32,
+        compression: Compression,
+    ) -> BTreePageInsertWrapper {
         let base = BTreePageWrapperBase::new(page_handler, root_page_id);
-        BTreePageInsertWrapper(base)
+        BTreePageInsertWrapper(base, compression, 0, false)
+    }
+
+    /// The bytes of the documents stored by this wrapper.
+    #[inline]
+    pub(crate) fn stored_size(&self) -> u64 {
+        self.2
+    }
+
+    /// Fill up the pages before dividing them, for the keys inserted in the ascending order.
+    #[inline]
+    pub(crate) fn set_packed(&mut self, packed: bool) {
+        self.3 = packed;
+    }
+
+    /// The greatest key of the btree, `None` if it's empty.
+    pub(crate) fn last_key(&mut self) -> DbResult<Option<Bson>> {
+        let mut pid = self.0.root_page_id;
+        let mut parent_pid = 0;
+        loop {
+            let btree_node = self.0.get_node(pid, parent_pid)?;
+            if btree_node.is_empty() {
+                return Ok(None);
+            }
+            let last_index = btree_node.len() - 1;
+            let right_pid = btree_node.get_right_pid(last_index);
+            if right_pid == 0 {
+                return Ok(Some(btree_node.get_item(last_index).key.clone()));
+            }
+            parent_pid = pid;
+            pid = right_pid;
+        }
     }
 
     pub(crate) fn insert_item(&mut self, doc: &Document, replace: bool) -> DbResult<InsertResult> {
@@ -102,7 +141,13 @@ impl<'a> BTreePageInsertWrapper<'a> {
 
     #[inline]
     fn store_doc(&mut self, doc: &Document) -> DbResult<DataTicket> {
-        self.0.session.store_doc(doc)
+        let mut buffer = self.0.session.take_buffer();
+        let result = self.1.encode_doc_into(doc, &mut buffer).and_then(|_| {
+            self.2 += buffer.len() as u64;
+            self.0.session.store_data_in_storage(&buffer)
+        });
+        self.0.session.give_back_buffer(buffer);
+        result
     }
 
     fn store_doc_as_payload(&mut self, left_pid: u32, doc: &Document) -> DbResult<BTreeDataItemWithKey> {
@@ -110,15 +155,17 @@ impl<'a> BTreePageInsertWrapper<'a> {
 
         let payload = self.store_doc(doc)?;
 
-        let mut key_bytes: Vec<u8> = Vec::new();
-        serialize_key(pkey, &mut key_bytes)?;
-
-        let key_data_ticket = if key_bytes.len() >= 255 {
-            let data_ticket = self.0.session.store_data_in_storage(&key_bytes)?;
-            Some(data_ticket)
-        } else {
-            None
-        };
+        let mut key_bytes = self.0.session.take_buffer();
+        let key_data_ticket = serialize_key(pkey, &mut key_bytes).and_then(|_| {
+            if key_bytes.len() >= 255 {
+                let data_ticket = self.0.session.store_data_in_storage(&key_bytes)?;
+                Ok(Some(data_ticket))
+            } else {
+                Ok(None)
+            }
+        });
+        self.0.session.give_back_buffer(key_bytes);
+        let key_data_ticket = key_data_ticket?;
 
         Ok(BTreeDataItemWithKey {
             left_pid,
@@ -232,9 +279,11 @@ impl<'a> BTreePageInsertWrapper<'a> {
     fn divide_and_return_backward(&mut self, btree_page_delegate: BTreePageDelegateWithKey) -> DbResult<InsertResult> {
         let right_page_id = self.0.session.alloc_page_id()?;
 
-        let result = btree_page_delegate.divide_in_the_middle(
-            self.0.session, right_page_id,
-        )?;
+        let result = if self.3 {
+            btree_page_delegate.divide_at_the_end(self.0.session, right_page_id)?
+        } else {
+            btree_page_delegate.divide_in_the_middle(self.0.session, right_page_id)?
+        };
 
         self.0.write_btree_node(&result.left)?;
         self.0.write_btree_node(&result.right)?;
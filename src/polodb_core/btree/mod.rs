@@ -9,6 +9,8 @@ mod insert_wrapper;
 mod delete_wrapper;
 pub mod counter_helper;
 pub(crate) mod delete_all_helper;
+pub(crate) mod index_helper;
+pub(crate) mod range_helper;
 mod vli;
 mod btree_v2;
 
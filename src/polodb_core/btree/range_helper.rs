@@ -0,0 +1,195 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::Ordering;
+use bson::{Bson, Document};
+use crate::bson_utils::value_cmp;
+use crate::collection_info::CollectionSpecification;
+use crate::cursor::Cursor;
+use crate::DbResult;
+use crate::session::Session;
+
+/// A range of the primary keys, which is traversed on the btree
+/// instead of matching every document.
+///
+/// It's parsed from a query with only the range of `_id`, such as:
+///
+/// ```json
+/// { "_id": { "$gte": 10, "$lt": 20 } }
+/// ```
+#[derive(Debug, PartialEq)]
+pub(crate) struct KeyRange {
+    lower: Option<(Bson, bool)>,  // (key, inclusive)
+    upper: Option<(Bson, bool)>,
+}
+
+impl KeyRange {
+
+    pub(crate) fn from_query(query: &Document) -> Option<KeyRange> {
+        if query.len() != 1 {
+            return None;
+        }
+        let ops = match query.get("_id") {
+            Some(Bson::Document(ops)) if !ops.is_empty() => ops,
+            _ => return None,
+        };
+
+        let mut range = KeyRange {
+            lower: None,
+            upper: None,
+        };
+        for (op, value) in ops.iter() {
+            let (bound, inclusive) = match op.as_str() {
+                "$gt" => (&mut range.lower, false),
+                "$gte" => (&mut range.lower, true),
+                "$lt" => (&mut range.upper, false),
+                "$lte" => (&mut range.upper, true),
+                _ => return None,
+            };
+            // both of `$gt` and `$gte` are left to the VM
+            if bound.is_some() {
+                return None;
+            }
+            *bound = Some((value.clone(), inclusive));
+        }
+        Some(range)
+    }
+
+    fn is_after_lower(&self, key: &Bson) -> DbResult<bool> {
+        let result = match &self.lower {
+            Some((lower, inclusive)) => match value_cmp(key, lower)? {
+                Ordering::Greater => true,
+                Ordering::Equal => *inclusive,
+                Ordering::Less => false,
+            },
+            None => true,
+        };
+        Ok(result)
+    }
+
+    fn is_before_upper(&self, key: &Bson) -> DbResult<bool> {
+        let result = match &self.upper {
+            Some((upper, inclusive)) => match value_cmp(key, upper)? {
+                Ordering::Less => true,
+                Ordering::Equal => *inclusive,
+                Ordering::Greater => false,
+            },
+            None => true,
+        };
+        Ok(result)
+    }
+
+}
+
+/// Collect the primary keys in the range in ascending order, at most `limit` keys.
+pub(crate) fn collect_keys(
+    session: &dyn Session,
+    col_spec: &CollectionSpecification,
+    range: &KeyRange,
+    limit: Option<usize>,
+) -> DbResult<Vec<Bson>> {
+    let mut cursor = Cursor::new(col_spec.info.root_pid);
+    match &range.lower {
+        Some((lower, _)) => cursor.seek(session, lower)?,
+        None => cursor.reset(session)?,
+    }
+
+    let mut result = vec![];
+    while limit != Some(result.len()) {
+        let key = match cursor.next_key(session)? {
+            Some(key) => key,
+            None => break,
+        };
+        if !range.is_before_upper(&key)? {
+            break;
+        }
+        if range.is_after_lower(&key)? {
+            result.push(key);
+        }
+    }
+    Ok(result)
+}
+
+/// Sort the primary keys and remove the duplicated ones.
+pub(crate) fn sort_keys(keys: &mut Vec<Bson>) -> DbResult<()> {
+    let mut error = None;
+    keys.sort_by(|a, b| {
+        value_cmp(a, b).unwrap_or_else(|err| {
+            error.get_or_insert(err);
+            Ordering::Equal
+        })
+    });
+    if let Some(err) = error {
+        return Err(err.into());
+    }
+    keys.dedup_by(|a, b| value_cmp(a, b).map(|ord| ord == Ordering::Equal).unwrap_or(false));
+    Ok(())
+}
+
+/// Find the documents of the sorted primary keys in one traversal of the btree,
+/// return the documents found and the keys not found.
+pub(crate) fn find_by_keys(
+    session: &dyn Session,
+    col_spec: &CollectionSpecification,
+    keys: &[Bson],
+) -> DbResult<(Vec<Document>, Vec<Bson>)> {
+    let mut found = Vec::with_capacity(keys.len());
+    let mut missing = vec![];
+    let mut cursor = Cursor::new(col_spec.info.root_pid);
+
+    for (index, key) in keys.iter().enumerate() {
+        if index == 0 {
+            cursor.seek(session, key)?;
+        } else {
+            cursor.seek_forward(session, key)?;
+        }
+        let current = match cursor.peek_key()? {
+            Some(current) => current,
+            // the rest of the keys are greater than all the keys in the btree
+            None => {
+                missing.extend_from_slice(&keys[index..]);
+                break;
+            }
+        };
+        if value_cmp(&current, key)? != Ordering::Equal {
+            missing.push(key.clone());
+            continue;
+        }
+        let ticket = cursor.peek_data().unwrap();
+        found.push(session.get_doc_from_ticket(&ticket)?);
+    }
+
+    Ok((found, missing))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Bson, doc};
+    use super::{sort_keys, KeyRange};
+
+    #[test]
+    fn test_parse_key_range() {
+        let range = KeyRange::from_query(&doc! { "_id": { "$gte": 10, "$lt": 20 } }).unwrap();
+        assert_eq!(range, KeyRange {
+            lower: Some((Bson::Int32(10), true)),
+            upper: Some((Bson::Int32(20), false)),
+        });
+        assert!(range.is_after_lower(&Bson::Int32(10)).unwrap());
+        assert!(!range.is_before_upper(&Bson::Int32(20)).unwrap());
+
+        assert!(KeyRange::from_query(&doc! { "_id": 10 }).is_none());
+        assert!(KeyRange::from_query(&doc! { "_id": { "$gt": 10 }, "name": "a" }).is_none());
+        assert!(KeyRange::from_query(&doc! { "_id": { "$gt": 10, "$gte": 11 } }).is_none());
+        assert!(KeyRange::from_query(&doc! { "_id": { "$in": [1, 2] } }).is_none());
+    }
+
+    #[test]
+    fn test_sort_keys() {
+        let mut keys = vec![Bson::Int32(3), Bson::Int32(1), Bson::Int32(3), Bson::Int32(2)];
+        sort_keys(&mut keys).unwrap();
+        assert_eq!(keys, vec![Bson::Int32(1), Bson::Int32(2), Bson::Int32(3)]);
+    }
+
+}
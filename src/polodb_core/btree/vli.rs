@@ -31,7 +31,7 @@ const NEG_FLAG:   u8 = 0b11111011;
 pub fn encode(writer: &mut dyn Write, num: i64) -> BsonResult<()> {
     if num < 0 {
         writer.write_all(&[ NEG_FLAG ])?;
-        return encode_u64(writer, (num * -1) as u64);
+        return encode_u64(writer, num.unsigned_abs());
     }
     encode_u64(writer, num as u64)
 }
@@ -55,7 +55,7 @@ fn encode_u64(writer: &mut dyn Write, num: u64) -> BsonResult<()> {
     } else if num <= 0xFFFFFFFFFF {  // 6 bytes
         let num: u64 = 0b11111000 << 40 | num;
         writer.write_all(num.to_be_bytes()[2..8].as_ref())?;
-    } else if num <= 0xFFFFFFFFFFFFFFF { // 8 bytes
+    } else if num <= 0x7FFFFFFFFFFFFFF { // 8 bytes, the prefix leaves 59 bits
         let num: u64 = 0b11110000 << 56 | num;
         writer.write_all(num.to_be_bytes()[0..8].as_ref())?;
     } else {  // 9 bytes
@@ -77,13 +77,12 @@ macro_rules! read_byte_plus {
     }
 }
 
-#[allow(dead_code)]
 pub fn decode(bytes: &[u8]) -> BsonResult<(i64, usize)> {
     let mut ptr: usize = 0;
     let first_byte = read_byte_plus!(bytes, ptr);
     if first_byte == NEG_FLAG {
         let (tmp, size) = decode_u64(&bytes[1..])?;
-        return Ok(((tmp as i64) * -1, size + ptr))
+        return Ok(((tmp as i64).wrapping_neg(), size + ptr))
     }
     let (tmp, size) = decode_u64(bytes)?;
     Ok((tmp as i64, size))
@@ -324,4 +323,41 @@ mod tests {
         assert_eq!(decode_int, num)
     }
 
+    #[test]
+    fn test_golden_bytes() {
+        // the bytes are the same on all the platforms
+        let cases: Vec<(i64, Vec<u8>)> = vec![
+            (0, vec![0x00]),
+            (127, vec![0x7F]),
+            (128, vec![0x80, 0x80]),
+            (16383, vec![0xBF, 0xFF]),
+            (16384, vec![0xC0, 0x40, 0x00]),
+            (134217727, vec![0xE7, 0xFF, 0xFF, 0xFF]),
+            (0x1002030405, vec![0xF8, 0x10, 0x02, 0x03, 0x04, 0x05]),
+            (-1, vec![0xFB, 0x01]),
+            (i64::MAX, vec![0xF9, 0x7F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]),
+            (i64::MIN, vec![0xFB, 0xF9, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]),
+        ];
+        for (num, expected) in cases {
+            let mut bytes = vec![];
+            encode(&mut bytes, num).expect("encode error");
+            assert_eq!(bytes, expected, "encode {}", num);
+            assert_eq!(decode(&bytes).unwrap(), (num, expected.len()));
+        }
+    }
+
+    #[test]
+    fn test_boundaries() {
+        // the first byte of the 8 bytes form can't be taken as the 6 or 9 bytes form
+        let nums: [u64; 6] = [
+            (1 << 59) - 1, 1 << 59, 0x0FFF_FFFF_FFFF_FFFF,
+            34359738367, 34359738368, u64::MAX,
+        ];
+        for num in nums.iter() {
+            let mut bytes = vec![];
+            encode_u64(&mut bytes, *num).expect("encode error");
+            assert_eq!(decode_u64(&bytes).unwrap(), (*num, bytes.len()));
+        }
+    }
+
 }
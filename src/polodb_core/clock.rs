@@ -0,0 +1,169 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The source of the current time, set by [`Config::clock`].
+//!
+//! The clock is read for the timestamps of the generated `_id` fields, the creation
+//! time of the collections, the `$currentDate` updates, the TTL expiry of the
+//! maintenance jobs and the execution time of [`explain`](crate::Collection::explain).
+//! A [`MockClock`] makes the time-dependent features testable, and a system without
+//! a reliable clock can supply its own source.
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use polodb_core::{Config, Database};
+//! use polodb_core::bson::{DateTime, Document, doc};
+//! use polodb_core::clock::MockClock;
+//!
+//! let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+//! let db = Database::open_memory_with_config(Config {
+//!     clock: Some(clock.clone()),
+//!     ..Default::default()
+//! }).unwrap();
+//! let tasks = db.collection::<Document>("tasks");
+//!
+//! tasks.insert_one(doc! { "title": "write docs" }).unwrap();
+//! clock.advance(Duration::from_secs(60));
+//! tasks.update_one(doc! {}, doc! { "$currentDate": { "done_at": true } }).unwrap();
+//!
+//! let task = tasks.find_one(None).unwrap().unwrap();
+//! assert_eq!(task.get_object_id("_id").unwrap().timestamp(), DateTime::from_millis(1_700_000_000_000));
+//! assert_eq!(task.get_datetime("done_at").unwrap(), &DateTime::from_millis(1_700_000_060_000));
+//! ```
+//!
+//! [`Config::clock`]: crate::Config::clock
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use bson::DateTime;
+use bson::oid::ObjectId;
+
+/// A source of the current time, shared by the threads of the database.
+pub trait Clock: Send + Sync {
+    /// The time elapsed since the Unix epoch.
+    fn now(&self) -> Duration;
+}
+
+/// The clock of the system, used if [`Config::clock`] is `None`.
+///
+/// [`Config::clock`]: crate::Config::clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now(&self) -> Duration {
+        std::time::SystemTime::now()
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    // `SystemTime::now` panics on `wasm32-unknown-unknown`, but the timestamp
+    // of an `ObjectId` is read from `Date.now()`, in seconds
+    #[cfg(target_arch = "wasm32")]
+    fn now(&self) -> Duration {
+        let millis = ObjectId::new().timestamp().timestamp_millis();
+        Duration::from_millis(millis.max(0) as u64)
+    }
+
+}
+
+/// A clock only moved by hand, for the tests.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    nanos: AtomicU64,
+}
+
+impl MockClock {
+
+    pub fn new(now: Duration) -> MockClock {
+        MockClock {
+            nanos: AtomicU64::new(now.as_nanos() as u64),
+        }
+    }
+
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(now.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+
+}
+
+impl Clock for MockClock {
+
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::SeqCst))
+    }
+
+}
+
+/// The current time of the clock as a BSON `DateTime`.
+pub(crate) fn now_datetime(clock: &dyn Clock) -> DateTime {
+    DateTime::from_millis(clock.now().as_millis() as i64)
+}
+
+/// Generate an `ObjectId` with the timestamp of the clock,
+/// the rest of it is generated as usual, so it's still unique.
+pub(crate) fn new_object_id(clock: &dyn Clock) -> ObjectId {
+    let mut bytes = ObjectId::new().bytes();
+    let seconds = clock.now().as_secs() as u32;
+    bytes[0..4].copy_from_slice(&seconds.to_be_bytes());
+    ObjectId::from_bytes(bytes)
+}
+
+/// The smallest `ObjectId` greater than the given one, `None` if it's the greatest.
+pub(crate) fn next_object_id(oid: &ObjectId) -> Option<ObjectId> {
+    let mut bytes = oid.bytes();
+    for byte in bytes.iter_mut().rev() {
+        if *byte < u8::MAX {
+            *byte += 1;
+            return Some(ObjectId::from_bytes(bytes));
+        }
+        *byte = 0;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bson::DateTime;
+    use bson::oid::ObjectId;
+    use super::{Clock, MockClock, SystemClock, new_object_id, next_object_id, now_datetime};
+
+    #[test]
+    fn test_mock_clock() {
+        let clock = MockClock::new(Duration::from_millis(1_000_500));
+        assert_eq!(now_datetime(&clock), DateTime::from_millis(1_000_500));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(clock.now(), Duration::from_millis(1_001_500));
+
+        let first = new_object_id(&clock);
+        let second = new_object_id(&clock);
+        assert_ne!(first, second);
+        assert_eq!(first.timestamp(), DateTime::from_millis(1_001_000));
+
+        clock.set(Duration::ZERO);
+        assert_eq!(now_datetime(&clock), DateTime::from_millis(0));
+        assert!(SystemClock.now() > Duration::from_secs(1_600_000_000));
+    }
+
+    #[test]
+    fn test_next_object_id() {
+        let oid = ObjectId::from_bytes([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xFF]);
+        let next = next_object_id(&oid).unwrap();
+        assert_eq!(next.bytes(), [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02, 0x00]);
+        assert!(next > oid);
+
+        assert!(next_object_id(&ObjectId::from_bytes([0xFF; 12])).is_none());
+    }
+
+}
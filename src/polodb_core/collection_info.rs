@@ -4,19 +4,98 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::collections::HashMap;
-use bson::{Binary, DateTime, Document};
+use bson::{Binary, Bson, DateTime, Document};
 use serde::{Deserialize, Serialize};
+use crate::Compression;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct IndexInfo {
-    key: Document,
+    /// The fields of the index, such as `{ "age": 1 }`.
+    pub key: Document,
+
+    /// Reject the documents with the same values of the fields.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unique: bool,
 
     /// Internal
     #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u32_as_i32")]
     pub root_pid: u32,
 }
 
+/// A field referencing the `_id` of the documents of another collection,
+/// declared by [`CreateCollectionOptions::foreign_keys`].
+///
+/// A document without the field, or with it `null`, references nothing.
+///
+/// [`CreateCollectionOptions::foreign_keys`]: crate::CreateCollectionOptions::foreign_keys
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForeignKey {
+    /// The field referencing, such as `customer_id`, or a dotted path of an embedded document.
+    pub field: String,
+
+    /// The collection referenced.
+    pub references: String,
+
+    /// What happens to the referencing documents when a referenced one is deleted.
+    #[serde(default)]
+    pub on_delete: OnDelete,
+}
+
+impl ForeignKey {
+
+    pub fn new(field: &str, references: &str, on_delete: OnDelete) -> ForeignKey {
+        ForeignKey {
+            field: field.to_string(),
+            references: references.to_string(),
+            on_delete,
+        }
+    }
+
+}
+
+/// The action on the referencing documents when a referenced document is deleted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum OnDelete {
+    /// The deletion fails with [`DbErr::ForeignKeyViolation`] while the document is referenced.
+    ///
+    /// [`DbErr::ForeignKeyViolation`]: crate::DbErr::ForeignKeyViolation
+    #[default]
+    Restrict,
+
+    /// The field of the referencing documents is set to `null`.
+    SetNull,
+
+    /// The referencing documents are deleted as well, with the actions of their own references.
+    Cascade,
+}
+
+/// A field stored with the value of an expression on the document, declared by
+/// [`CreateCollectionOptions::generated_fields`].
+///
+/// [`CreateCollectionOptions::generated_fields`]: crate::CreateCollectionOptions::generated_fields
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GeneratedField {
+    /// The top-level field set, such as `email_lower`.
+    pub field: String,
+
+    /// The expression of the aggregation, such as `{ "$toLower": "$email" }`.
+    pub expression: Bson,
+}
+
+impl GeneratedField {
+
+    pub fn new(field: &str, expression: impl Into<Bson>) -> GeneratedField {
+        GeneratedField {
+            field: field.to_string(),
+            expression: expression.into(),
+        }
+    }
+
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CollectionSpecificationInfo {
@@ -30,9 +109,59 @@ pub struct CollectionSpecificationInfo {
     /// Internal
     #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u32_as_i32")]
     pub root_pid: u32,
+
+    /// The codec compressing the documents of the collection.
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+
+    /// The field increased on every update of a document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version_field: Option<String>,
+
+    /// The documents are only appended, see [`CreateCollectionOptions::append_only`].
+    ///
+    /// [`CreateCollectionOptions::append_only`]: crate::CreateCollectionOptions::append_only
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub append_only: bool,
+
+    /// The fields referencing the other collections.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub foreign_keys: Vec<ForeignKey>,
+
+    /// The fields computed on every insert and update, in the order they are computed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generated_fields: Vec<GeneratedField>,
+
+    /// The number of the documents, maintained on every write.
+    /// `None` for the collections created before it was persisted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+
+    /// The bytes of the stored documents, after the compression of the collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_size: Option<u64>,
+
+    /// The alias of the attached database the documents are moved to,
+    /// the collection is a stub in this database.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archived_in: Option<String>,
+}
+
+impl CollectionSpecificationInfo {
+
+    #[inline]
+    pub(crate) fn has_stats(&self) -> bool {
+        self.count.is_some() && self.data_size.is_some()
+    }
+
+    pub(crate) fn add_stats(&mut self, count_delta: i64, size_delta: i64) {
+        self.count = self.count.map(|count| count.saturating_add_signed(count_delta));
+        self.data_size = self.data_size.map(|size| size.saturating_add_signed(size_delta));
+    }
+
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct CollectionSpecification {
     /// The name of the collection.
@@ -77,6 +206,7 @@ mod test {
     use bson::{Binary, DateTime};
     use bson::spec::BinarySubtype;
     use crate::collection_info::{CollectionSpecification, CollectionSpecificationInfo, CollectionType};
+    use crate::Compression;
 
     #[test]
     fn test_serial() {
@@ -91,7 +221,15 @@ mod test {
                 }),
 
                 create_at: DateTime::now(),
-                root_pid:1
+                root_pid:1,
+                compression: Compression::None,
+                version_field: None,
+                append_only: false,
+                foreign_keys: Vec::new(),
+                generated_fields: Vec::new(),
+                count: None,
+                data_size: None,
+                archived_in: None,
             },
             indexes: HashMap::new(),
         };
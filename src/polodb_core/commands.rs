@@ -3,15 +3,32 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use bson::Document;
+use bson::{Bson, Document};
 use bson::oid::ObjectId;
 use serde::{Serialize, Deserialize};
-use crate::TransactionType;
+use crate::{Compression, ForeignKey, GeneratedField, TransactionType};
 
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct FindCommandOptions {
     pub session_id: Option<ObjectId>,
+    pub projection: Option<Document>,
+    /// The same as the fields of [`FindOptions`], ignored by a find with a `scan_limit`.
+    ///
+    /// [`FindOptions`]: crate::FindOptions
+    pub sort: Option<Document>,
+    pub skip: Option<u64>,
+    pub limit: Option<u64>,
+    /// Return the first batch of the documents and a cursor of the rest
+    /// as `{ cursorId, batch }`, fetched by [`GetMoreCommand`].
+    pub batch_size: Option<u32>,
+    /// Examine at most `scan_limit` documents of a multi find, and return the ones
+    /// satisfying the filter as `{ batch, resumeFrom }`. The scan is continued by
+    /// the next find with the `resumeFrom` returned, which is missing when the scan
+    /// is done. The documents changed between the finds may be seen or not.
+    /// The `batch_size` is ignored.
+    pub scan_limit: Option<u32>,
+    pub resume_from: Option<Bson>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -22,10 +39,29 @@ pub struct FindCommand {
     pub options: Option<FindCommandOptions>,
 }
 
+/// Fetch the next batch of a cursor returned by a find with a `batchSize`,
+/// the rest of the documents if `batch_size` is `None`.
+/// The `cursorId` returned is 0 when the cursor is exhausted.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetMoreCommand {
+    pub ns: String,
+    pub cursor_id: i64,
+    pub batch_size: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct KillCursorsCommand {
+    pub ns: String,
+    pub cursor_ids: Vec<i64>,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InsertCommandOptions {
     pub session_id: Option<ObjectId>,
+    pub ordered: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -39,6 +75,7 @@ pub struct InsertCommand {
 #[serde(rename_all = "camelCase")]
 pub struct UpdateCommandOptions {
     pub session_id: Option<ObjectId>,
+    pub upsert: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -68,6 +105,14 @@ pub struct DeleteCommand {
 #[serde(rename_all = "camelCase")]
 pub struct CreateCollectionCommandOptions {
     pub session_id: Option<ObjectId>,
+    pub compression: Option<Compression>,
+    pub version_field: Option<String>,
+    #[serde(default)]
+    pub append_only: bool,
+    #[serde(default)]
+    pub foreign_keys: Vec<ForeignKey>,
+    #[serde(default)]
+    pub generated_fields: Vec<GeneratedField>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -97,9 +142,36 @@ pub struct CountDocumentsCommandOptions {
 #[derive(Serialize, Deserialize)]
 pub struct CountDocumentsCommand {
     pub ns: String,
+    pub filter: Option<Document>,
     pub options: Option<CountDocumentsCommandOptions>,
 }
 
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzeSchemaCommandOptions {
+    pub session_id: Option<ObjectId>,
+    /// The documents sampled, [`DEFAULT_SAMPLE_SIZE`] by default.
+    ///
+    /// [`DEFAULT_SAMPLE_SIZE`]: crate::schema::DEFAULT_SAMPLE_SIZE
+    pub sample_size: Option<u32>,
+}
+
+/// Report the fields of a sample of the documents,
+/// the result is the document of [`SchemaAnalysis::to_document`].
+///
+/// [`SchemaAnalysis::to_document`]: crate::schema::SchemaAnalysis::to_document
+#[derive(Serialize, Deserialize)]
+pub struct AnalyzeSchemaCommand {
+    pub ns: String,
+    pub options: Option<AnalyzeSchemaCommandOptions>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DropSessionCommand {
+    pub session_id: ObjectId,
+}
+
 #[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct StartTransactionCommand {
@@ -123,14 +195,94 @@ pub struct RollbackCommand {
 #[serde(tag = "command")]
 pub enum CommandMessage {
     Find(FindCommand),
+    GetMore(GetMoreCommand),
+    KillCursors(KillCursorsCommand),
     Insert(InsertCommand),
     Update(UpdateCommand),
     Delete(DeleteCommand),
     CreateCollection(CreateCollectionCommand),
     DropCollection(DropCollectionCommand),
     CountDocuments(CountDocumentsCommand),
+    AnalyzeSchema(AnalyzeSchemaCommand),
+    StartSession,
+    DropSession(DropSessionCommand),
     StartTransaction(StartTransactionCommand),
     Commit(CommitCommand),
     Rollback(RollbackCommand),
     SafelyQuit,
+    /// Compact the database, see [`Database::compact`](crate::Database::compact).
+    Compact,
+}
+
+impl CommandMessage {
+
+    /// The name of the command, the same as the `command` tag in the request.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandMessage::Find(_) => "Find",
+            CommandMessage::GetMore(_) => "GetMore",
+            CommandMessage::KillCursors(_) => "KillCursors",
+            CommandMessage::Insert(_) => "Insert",
+            CommandMessage::Update(_) => "Update",
+            CommandMessage::Delete(_) => "Delete",
+            CommandMessage::CreateCollection(_) => "CreateCollection",
+            CommandMessage::DropCollection(_) => "DropCollection",
+            CommandMessage::CountDocuments(_) => "CountDocuments",
+            CommandMessage::AnalyzeSchema(_) => "AnalyzeSchema",
+            CommandMessage::StartSession => "StartSession",
+            CommandMessage::DropSession(_) => "DropSession",
+            CommandMessage::StartTransaction(_) => "StartTransaction",
+            CommandMessage::Commit(_) => "Commit",
+            CommandMessage::Rollback(_) => "Rollback",
+            CommandMessage::SafelyQuit => "SafelyQuit",
+            CommandMessage::Compact => "Compact",
+        }
+    }
+
+    /// The collection the command operates on, if any.
+    pub fn ns(&self) -> Option<&str> {
+        match self {
+            CommandMessage::Find(find) => Some(&find.ns),
+            CommandMessage::GetMore(get_more) => Some(&get_more.ns),
+            CommandMessage::KillCursors(kill_cursors) => Some(&kill_cursors.ns),
+            CommandMessage::Insert(insert) => Some(&insert.ns),
+            CommandMessage::Update(update) => Some(&update.ns),
+            CommandMessage::Delete(delete) => Some(&delete.ns),
+            CommandMessage::CreateCollection(create) => Some(&create.ns),
+            CommandMessage::DropCollection(drop) => Some(&drop.ns),
+            CommandMessage::CountDocuments(count) => Some(&count.ns),
+            CommandMessage::AnalyzeSchema(analyze) => Some(&analyze.ns),
+            _ => None,
+        }
+    }
+
+    /// Replace the collection the command operates on.
+    pub fn set_ns(&mut self, ns: String) {
+        match self {
+            CommandMessage::Find(find) => find.ns = ns,
+            CommandMessage::GetMore(get_more) => get_more.ns = ns,
+            CommandMessage::KillCursors(kill_cursors) => kill_cursors.ns = ns,
+            CommandMessage::Insert(insert) => insert.ns = ns,
+            CommandMessage::Update(update) => update.ns = ns,
+            CommandMessage::Delete(delete) => delete.ns = ns,
+            CommandMessage::CreateCollection(create) => create.ns = ns,
+            CommandMessage::DropCollection(drop) => drop.ns = ns,
+            CommandMessage::CountDocuments(count) => count.ns = ns,
+            CommandMessage::AnalyzeSchema(analyze) => analyze.ns = ns,
+            _ => (),
+        }
+    }
+
+    /// Return true if the command modifies the database.
+    pub fn is_write(&self) -> bool {
+        matches!(self,
+            CommandMessage::Insert(_) |
+            CommandMessage::Update(_) |
+            CommandMessage::Delete(_) |
+            CommandMessage::CreateCollection(_) |
+            CommandMessage::DropCollection(_) |
+            CommandMessage::Compact
+        )
+    }
+
 }
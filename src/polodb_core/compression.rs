@@ -0,0 +1,333 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::borrow::Cow;
+use std::convert::TryFrom;
+use bson::{Binary, Bson, Document, RawBsonRef, RawDocument};
+use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+use crate::{DbErr, DbResult};
+use crate::projection::{FieldSlice, ScanFields};
+
+const CODEC_LZ4: u8 = 1;
+const CODEC_ZSTD: u8 = 2;
+
+// A BSON document begins with its length in i32,
+// the highest byte is never 0xFF for a valid document.
+// So the compressed data is marked by it.
+const COMPRESSED_MARK: u8 = 0xFF;
+
+// Offset 0: codec(1 byte)
+// Offset 1: reserved(2 bytes)
+// Offset 3: mark(1 byte)
+// Offset 4: size of the raw data(4 bytes)
+// Offset 8: compressed data
+const HEADER_SIZE: usize = 8;
+
+// The first byte of a page not compressed is the magic of its type,
+// the title of the header page or zero. So the compressed page is marked by it.
+const PAGE_COMPRESSED_MARK: u8 = 0xFE;
+
+// Offset 0: mark(1 byte)
+// Offset 1: codec(1 byte)
+// Offset 2: reserved(2 bytes)
+// Offset 4: size of the compressed data(4 bytes)
+// Offset 8: compressed data
+const PAGE_HEADER_SIZE: usize = 8;
+
+/// The codec used to compress the documents of a collection.
+///
+/// The codec is chosen when the collection is created.
+/// Documents which can't be shrunk are stored as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(tag = "codec", rename_all = "camelCase")]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd {
+        level: i32,
+    },
+}
+
+impl Compression {
+
+    #[inline]
+    pub fn is_none(&self) -> bool {
+        matches!(self, Compression::None)
+    }
+
+    #[allow(dead_code)]
+    pub(crate) fn encode_doc(&self, doc: &Document) -> DbResult<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.encode_doc_into(doc, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Encode the document into the buffer, which is cleared at first.
+    pub(crate) fn encode_doc_into(&self, doc: &Document, buffer: &mut Vec<u8>) -> DbResult<()> {
+        buffer.clear();
+        doc.to_writer(&mut *buffer)?;
+        if let Some(compressed) = self.compress(buffer)? {
+            *buffer = compressed;
+        }
+        Ok(())
+    }
+
+    /// Return `None` if the bytes are stored as is.
+    fn compress(&self, bytes: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        let (codec, compressed) = match self {
+            Compression::None => return Ok(None),
+            Compression::Lz4 => (CODEC_LZ4, lz4_flex::compress(bytes)),
+            Compression::Zstd { level } => (CODEC_ZSTD, zstd_compress(bytes, *level)?),
+        };
+
+        if compressed.len() + HEADER_SIZE >= bytes.len() {
+            return Ok(None);
+        }
+
+        let mut result = Vec::with_capacity(compressed.len() + HEADER_SIZE);
+        result.extend_from_slice(&[codec, 0, 0, COMPRESSED_MARK]);
+        let mut size_buf = [0u8; 4];
+        LittleEndian::write_u32(&mut size_buf, bytes.len() as u32);
+        result.extend_from_slice(&size_buf);
+        result.extend_from_slice(&compressed);
+
+        Ok(Some(result))
+    }
+
+    /// Compress a page of the database file,
+    /// return `None` if the page is stored as is.
+    #[cfg_attr(not(feature = "fs"), allow(dead_code))]
+    pub(crate) fn compress_page(&self, data: &[u8]) -> DbResult<Option<Vec<u8>>> {
+        let (codec, compressed) = match self {
+            Compression::None => return Ok(None),
+            Compression::Lz4 => (CODEC_LZ4, lz4_flex::compress(data)),
+            Compression::Zstd { level } => (CODEC_ZSTD, zstd_compress(data, *level)?),
+        };
+
+        if compressed.len() + PAGE_HEADER_SIZE >= data.len() {
+            return Ok(None);
+        }
+
+        let mut result = Vec::with_capacity(compressed.len() + PAGE_HEADER_SIZE);
+        result.extend_from_slice(&[PAGE_COMPRESSED_MARK, codec, 0, 0]);
+        let mut size_buf = [0u8; 4];
+        LittleEndian::write_u32(&mut size_buf, compressed.len() as u32);
+        result.extend_from_slice(&size_buf);
+        result.extend_from_slice(&compressed);
+
+        Ok(Some(result))
+    }
+
+}
+
+/// Decompress a page read from the database file in place,
+/// the page not compressed is kept.
+#[cfg_attr(not(feature = "fs"), allow(dead_code))]
+pub(crate) fn decompress_page(data: &mut [u8]) -> DbResult<()> {
+    if data.len() < PAGE_HEADER_SIZE || data[0] != PAGE_COMPRESSED_MARK {
+        return Ok(());
+    }
+
+    let size = LittleEndian::read_u32(&data[4..8]) as usize;
+    let compressed = data.get(PAGE_HEADER_SIZE..PAGE_HEADER_SIZE.saturating_add(size))
+        .ok_or_else(|| DbErr::CompressionError("the compressed page is broken".into()))?;
+    let raw = match data[1] {
+        CODEC_LZ4 => lz4_flex::decompress(compressed, data.len())
+            .map_err(|err| DbErr::CompressionError(err.to_string()))?,
+        CODEC_ZSTD => zstd_decompress(compressed, data.len())?,
+        codec => return Err(DbErr::CompressionError(format!("unknown codec: {}", codec))),
+    };
+    if raw.len() != data.len() {
+        return Err(DbErr::CompressionError("the compressed page is broken".into()));
+    }
+
+    data.copy_from_slice(&raw);
+    Ok(())
+}
+
+/// Return the raw BSON bytes of the stored data,
+/// whatever codec it's compressed by.
+fn decompress(bytes: &[u8]) -> DbResult<Cow<'_, [u8]>> {
+    if bytes.len() < HEADER_SIZE || bytes[3] != COMPRESSED_MARK {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    let raw_size = LittleEndian::read_u32(&bytes[4..8]) as usize;
+    let data = &bytes[HEADER_SIZE..];
+    let result = match bytes[0] {
+        CODEC_LZ4 => lz4_flex::decompress(data, raw_size)
+            .map_err(|err| DbErr::CompressionError(err.to_string()))?,
+        CODEC_ZSTD => zstd_decompress(data, raw_size)?,
+        codec => return Err(DbErr::CompressionError(format!("unknown codec: {}", codec))),
+    };
+
+    Ok(Cow::Owned(result))
+}
+
+pub(crate) fn decode_doc(bytes: &[u8]) -> DbResult<Document> {
+    let raw = decompress(bytes)?;
+    let doc = bson::from_slice(raw.as_ref())?;
+    Ok(doc)
+}
+
+/// Decode only the top-level fields of the stored document needed by the scan,
+/// the other elements are skipped without being deserialized.
+/// The elements of the sliced fields out of the slices are skipped too.
+pub(crate) fn decode_doc_fields(bytes: &[u8], scan: &ScanFields) -> DbResult<Document> {
+    let raw = decompress(bytes)?;
+    let raw_doc = RawDocument::from_bytes(raw.as_ref())
+        .map_err(|err| DbErr::ParseError(err.to_string()))?;
+
+    let mut doc = Document::new();
+    for element in raw_doc.iter() {
+        let (key, value) = element.map_err(|err| DbErr::ParseError(err.to_string()))?;
+        if let Some(fields) = &scan.fields {
+            if !fields.iter().any(|field| field == key) {
+                continue;
+            }
+        }
+        let value = match scan.slices.iter().find(|slice| slice.field == key) {
+            Some(slice) => decode_slice(value, slice)?,
+            None => raw_to_bson(value)?,
+        };
+        doc.insert(key, value);
+    }
+
+    Ok(doc)
+}
+
+fn decode_slice(value: RawBsonRef, slice: &FieldSlice) -> DbResult<Bson> {
+    match value {
+        RawBsonRef::Array(raw_arr) => {
+            let mut len = 0;
+            for item in raw_arr {
+                item.map_err(|err| DbErr::ParseError(err.to_string()))?;
+                len += 1;
+            }
+            let range = slice.range(len);
+            let mut arr = Vec::with_capacity(range.len());
+            for item in raw_arr.into_iter().skip(range.start).take(range.len()) {
+                let item = item.map_err(|err| DbErr::ParseError(err.to_string()))?;
+                arr.push(raw_to_bson(item)?);
+            }
+            Ok(Bson::Array(arr))
+        }
+        RawBsonRef::Binary(binary) => {
+            let range = slice.range(binary.bytes.len());
+            Ok(Bson::Binary(Binary {
+                subtype: binary.subtype,
+                bytes: binary.bytes[range].to_vec(),
+            }))
+        }
+        _ => raw_to_bson(value),
+    }
+}
+
+fn raw_to_bson(value: RawBsonRef) -> DbResult<Bson> {
+    Bson::try_from(value.to_raw_bson())
+        .map_err(|err| DbErr::ParseError(err.to_string()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn zstd_compress(bytes: &[u8], level: i32) -> DbResult<Vec<u8>> {
+    zstd::bulk::compress(bytes, level)
+        .map_err(|err| DbErr::CompressionError(err.to_string()))
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn zstd_decompress(bytes: &[u8], raw_size: usize) -> DbResult<Vec<u8>> {
+    zstd::bulk::decompress(bytes, raw_size)
+        .map_err(|err| DbErr::CompressionError(err.to_string()))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn zstd_compress(_bytes: &[u8], _level: i32) -> DbResult<Vec<u8>> {
+    Err(DbErr::CompressionError("zstd is not supported on this platform".into()))
+}
+
+#[cfg(target_arch = "wasm32")]
+fn zstd_decompress(_bytes: &[u8], _raw_size: usize) -> DbResult<Vec<u8>> {
+    Err(DbErr::CompressionError("zstd is not supported on this platform".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use crate::Compression;
+    use crate::projection::{Projection, ScanFields};
+    use super::{decode_doc, decode_doc_fields, decompress_page};
+
+    fn large_doc() -> bson::Document {
+        doc! {
+            "_id": 1,
+            "content": "PoloDB ".repeat(200),
+        }
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        let doc = large_doc();
+        let raw_size = bson::to_vec(&doc).unwrap().len();
+        for codec in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+            let bytes = codec.encode_doc(&doc).unwrap();
+            assert!(bytes.len() < raw_size);
+            assert_eq!(decode_doc(&bytes).unwrap(), doc);
+        }
+    }
+
+    #[test]
+    fn test_compress_page_round_trip() {
+        let mut page = vec![0u8; 4096];
+        page[0] = 0xFF;
+        page[100..120].copy_from_slice(b"PoloDB PoloDB PoloDB");
+        for codec in [Compression::Lz4, Compression::Zstd { level: 3 }] {
+            let compressed = codec.compress_page(&page).unwrap().unwrap();
+            let mut stored = vec![0u8; page.len()];
+            stored[..compressed.len()].copy_from_slice(&compressed);
+            decompress_page(&mut stored).unwrap();
+            assert_eq!(stored, page);
+        }
+
+        // a page not compressed is kept
+        let mut stored = page.clone();
+        decompress_page(&mut stored).unwrap();
+        assert_eq!(stored, page);
+    }
+
+    #[test]
+    fn test_decode_fields() {
+        let doc = large_doc();
+        let bytes = Compression::Lz4.encode_doc(&doc).unwrap();
+        let result = decode_doc_fields(&bytes, &ScanFields {
+            fields: Some(vec!["_id".to_string()]),
+            slices: vec![],
+        }).unwrap();
+        assert_eq!(result, doc! { "_id": 1 });
+    }
+
+    #[test]
+    fn test_decode_slices() {
+        let messages: Vec<i32> = (0..1000).collect();
+        let doc = doc! {
+            "_id": 1,
+            "messages": messages,
+        };
+        let bytes = Compression::Lz4.encode_doc(&doc).unwrap();
+        let projection = Projection::parse(&doc! { "messages": { "$slice": [-10, 2] } }, None).unwrap();
+        let result = decode_doc_fields(&bytes, &projection.scan_fields().unwrap()).unwrap();
+        assert_eq!(result, doc! { "_id": 1, "messages": [990, 991] });
+    }
+
+    #[test]
+    fn test_keep_small_doc_raw() {
+        let doc = doc! { "_id": 1 };
+        let bytes = Compression::Lz4.encode_doc(&doc).unwrap();
+        assert_eq!(bytes, bson::to_vec(&doc).unwrap());
+        assert_eq!(decode_doc(&bytes).unwrap(), doc);
+    }
+
+}
@@ -4,11 +4,122 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::num::NonZeroU64;
+use std::num::{NonZeroU32, NonZeroU64, NonZeroUsize};
+use std::sync::Arc;
+use std::time::Duration;
+use crate::clock::{Clock, SystemClock};
+use crate::compression::Compression;
+use crate::vfs::Vfs;
 
+#[derive(Clone)]
 pub struct Config {
     pub init_block_count:  NonZeroU64,
+    /// The size in bytes of the pages of a new database file, an existing file created
+    /// with another page size is rejected with [`DbErr::PageSizeMismatch`].
+    /// It's used by the file backend only, the other backends use pages of 4 KiB.
+    ///
+    /// [`DbErr::PageSizeMismatch`]: crate::DbErr::PageSizeMismatch
+    pub page_size:         NonZeroU32,
+    /// The count of pages in the journal which makes a commit checkpoint,
+    /// if no session is running. It can be changed by [`Database::set_journal_full_size`].
+    ///
+    /// [`Database::set_journal_full_size`]: crate::Database::set_journal_full_size
     pub journal_full_size: u64,
+    /// The count of pages kept in the page cache of the file backend.
+    pub page_cache_size:   NonZeroUsize,
+    /// Which pages the page cache evicts when it's full.
+    pub page_cache_policy: PageCachePolicy,
+    /// The memory in bytes a hash join can use before
+    /// spilling its partitions to temporary files,
+    /// the join is always in memory without the feature `fs`.
+    pub join_memory_limit: usize,
+    /// Delay the commits when the journal grows beyond `journal_full_size`
+    /// because the sessions block the checkpoint. `None` disables the throttling.
+    pub journal_throttle:  Option<JournalThrottle>,
+    /// How much the database file grows when the allocated pages run out.
+    pub growth_strategy:   GrowthStrategy,
+    /// The max size of the database in bytes, a transaction allocating a page beyond it
+    /// fails with [`DbErr::DatabaseSizeLimitExceeded`] and is rolled back, the pages freed
+    /// by the deletions are still reused. So the file never outgrows the quota of the app.
+    /// The size is limited by the 32-bit page ids anyway, which is 16 TiB with the pages
+    /// of 4 KiB, the same error is returned beyond it without the quota.
+    ///
+    /// [`DbErr::DatabaseSizeLimitExceeded`]: crate::DbErr::DatabaseSizeLimitExceeded
+    pub max_db_size:       Option<NonZeroU64>,
+    /// Read and write the database file bypassing the cache of the OS,
+    /// with `O_DIRECT` on Linux, `F_NOCACHE` on macOS and `FILE_FLAG_NO_BUFFERING`
+    /// on Windows. The pages are cached only once by the page cache, which
+    /// should be large enough. It's ignored if the file system doesn't support it.
+    pub direct_io:         bool,
+    /// Experimental. Submit the reads and writes of the file backend by io_uring,
+    /// the frames of a transaction are appended to the journal by one submission
+    /// on commit. It needs the `io_uring` feature on Linux, ignored otherwise.
+    pub io_uring:          bool,
+    /// Append the changed bytes of a page to the journal instead of the whole page
+    /// if the last content of it is cached, which shrinks the journal of the small updates.
+    /// A whole page is written after several deltas, so a page is read
+    /// by applying a few deltas at most.
+    pub delta_journal:     bool,
+    /// Punch holes in the database file for the runs of at least so many free pages
+    /// after a checkpoint, with `fallocate(FALLOC_FL_PUNCH_HOLE)` on Linux, which returns
+    /// the space of the deleted data to the OS without rewriting the file.
+    /// `None` disables it, and it's ignored if the file system doesn't support it.
+    pub hole_punch_min_pages: Option<NonZeroU32>,
+    /// Encrypt the pages of the database file and the journal by AES-256-GCM with the key.
+    /// A database must be opened with the key it's created with, it's not encrypted
+    /// if it's created without a key. It needs the `encryption` feature, the file
+    /// backend fails to open with [`DbErr::EncryptionError`] otherwise.
+    /// The direct I/O is disabled with the encryption.
+    ///
+    /// [`DbErr::EncryptionError`]: crate::DbErr::EncryptionError
+    pub encryption_key:    Option<[u8; 32]>,
+    /// Compress the pages of the database file by the codec when they are written
+    /// to it, the compressed pages are read by any config. A hole is punched after
+    /// a compressed page, so the file shrinks on the file systems supporting the holes
+    /// with the blocks smaller than the pages. The files written with it can't be
+    /// opened by the versions without the support. It's ignored with the encryption
+    /// and by the backends other than the file.
+    pub compression:       Compression,
+    /// The file system the database file and the journal are opened on,
+    /// the file system of the OS if it's `None`.
+    pub vfs:               Option<Arc<dyn Vfs>>,
+    /// How the unknown `$` operators of the queries and the updates are handled,
+    /// they are rejected by default.
+    pub operator_mode:     OperatorMode,
+    /// The source of the current time, the clock of the system if it's `None`.
+    /// See [`clock`](crate::clock).
+    pub clock:             Option<Arc<dyn Clock>>,
+    /// The max count of the open cursors, a new one fails with [`DbErr::TooManyOpenCursors`].
+    /// The cursors are the ones of the finds with a `batchSize` of the wire protocol
+    /// not exhausted, and the [`Subscription`]s not dropped.
+    ///
+    /// [`DbErr::TooManyOpenCursors`]: crate::DbErr::TooManyOpenCursors
+    /// [`Subscription`]: crate::subscription::Subscription
+    pub max_open_cursors:  Option<NonZeroUsize>,
+    /// The cursors of the wire protocol not accessed for so long are closed,
+    /// and their queries are logged to find the clients leaking the cursors.
+    /// `None` keeps them open until they are exhausted or killed.
+    pub cursor_timeout:    Option<Duration>,
+    /// The max count of the sessions started and not dropped, a new one fails
+    /// with [`DbErr::TooManySessions`]. The sessions used by the database itself
+    /// are not counted.
+    ///
+    /// [`DbErr::TooManySessions`]: crate::DbErr::TooManySessions
+    pub max_sessions:      Option<NonZeroUsize>,
+    /// The max count of the collections, creating one more fails with [`DbErr::TooManyCollections`],
+    /// including the collections created by the first insertion.
+    ///
+    /// [`DbErr::TooManyCollections`]: crate::DbErr::TooManyCollections
+    pub max_collections:   Option<NonZeroUsize>,
+    /// The commits to the IndexedDB backend within the interval are written
+    /// by one IndexedDB transaction, `Duration::ZERO` writes every commit at once.
+    /// The commits not written yet are lost if the page is closed.
+    /// It's ignored by the other backends.
+    pub indexeddb_flush_interval: Duration,
+    /// Collapse the frames of the commits in IndexedDB into a snapshot of the pages
+    /// when too many are appended, or the store grows without bound.
+    /// `None` keeps all the frames. It's ignored by the other backends.
+    pub indexeddb_compaction: Option<IndexedDbCompaction>,
 }
 
 impl Default for Config {
@@ -16,8 +127,241 @@ impl Default for Config {
     fn default() -> Self {
         Config {
             init_block_count:  NonZeroU64::new(16).unwrap(),
+            page_size:         NonZeroU32::new(4096).unwrap(),
             journal_full_size: 1000,
+            page_cache_size:   NonZeroUsize::new(1024).unwrap(),
+            page_cache_policy: PageCachePolicy::TwoQueue,
+            join_memory_limit: 64 * 1024 * 1024,
+            journal_throttle:  None,
+            growth_strategy:   GrowthStrategy::Fixed(NonZeroU64::new(16).unwrap()),
+            max_db_size:       None,
+            direct_io:         false,
+            io_uring:          false,
+            delta_journal:     false,
+            hole_punch_min_pages: None,
+            encryption_key:    None,
+            compression:       Compression::None,
+            vfs:               None,
+            operator_mode:     OperatorMode::Strict,
+            clock:             None,
+            max_open_cursors:  None,
+            cursor_timeout:    Some(Duration::from_secs(600)),
+            max_sessions:      None,
+            max_collections:   None,
+            indexeddb_flush_interval: Duration::from_millis(100),
+            indexeddb_compaction: Some(IndexedDbCompaction::default()),
         }
     }
 
 }
+
+impl Config {
+
+    pub(crate) fn clock(&self) -> &dyn Clock {
+        match &self.clock {
+            Some(clock) => clock.as_ref(),
+            None => &SystemClock,
+        }
+    }
+
+}
+
+/// How the unknown `$` operators are handled, the same for the queries
+/// executed by the VM, the filters of the subscriptions and the `$match` stages.
+///
+/// ```rust
+/// use polodb_core::{Config, Database, OperatorMode};
+/// use polodb_core::bson::{Document, doc};
+///
+/// let db = Database::open_memory_with_config(Config {
+///     operator_mode: OperatorMode::Lax,
+///     ..Default::default()
+/// }).unwrap();
+/// let prices = db.collection::<Document>("prices");
+/// prices.insert_one(doc! { "_id": 1, "range": { "$min": 1, "$max": 5 } }).unwrap();
+///
+/// let doc = prices.find_one(doc! { "range": { "$min": 1, "$max": 5 } }).unwrap();
+/// assert!(doc.is_some());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperatorMode {
+    /// Reject an unknown operator with [`DbErr::UnknownQueryOperator`]
+    /// or [`DbErr::UnknownUpdateOperation`] naming the operator.
+    ///
+    /// [`DbErr::UnknownQueryOperator`]: crate::DbErr::UnknownQueryOperator
+    /// [`DbErr::UnknownUpdateOperation`]: crate::DbErr::UnknownUpdateOperation
+    Strict,
+
+    /// Treat an unknown operator as a literal field. `{ "$foo": 1 }` matches the field `$foo`,
+    /// a condition with an unknown operator such as `{ "a": { "$foo": 1 } }` matches
+    /// the document `{ "$foo": 1 }` of the field `a`, and an update `{ "$foo": 1 }`
+    /// sets the field `$foo`.
+    Lax,
+}
+
+impl Default for OperatorMode {
+
+    fn default() -> Self {
+        OperatorMode::Strict
+    }
+
+}
+
+/// The thresholds compacting the frames of the IndexedDB backend, see [`Config::indexeddb_compaction`].
+///
+/// Every commit appends the changed pages as a frame to the object store of the frames.
+/// When the frames appended since the last compaction reach either threshold,
+/// all the pages are written as the frames of a snapshot and the older frames are deleted,
+/// by the same IndexedDB transaction. The frames of the snapshot are not counted,
+/// so a database larger than `max_bytes` isn't compacted on every commit.
+#[derive(Debug, Clone)]
+pub struct IndexedDbCompaction {
+    /// The count of the frames appended.
+    pub max_frames: u32,
+    /// The bytes of the frames appended, after the compression.
+    pub max_bytes:  u64,
+}
+
+impl Default for IndexedDbCompaction {
+
+    fn default() -> Self {
+        IndexedDbCompaction {
+            max_frames: 1000,
+            max_bytes:  64 * 1024 * 1024,
+        }
+    }
+
+}
+
+/// The replacement policy of the page cache.
+///
+/// ```rust
+/// use polodb_core::{Config, PageCachePolicy};
+///
+/// let config = Config {
+///     page_cache_policy: PageCachePolicy::Lru,
+///     ..Default::default()
+/// };
+/// assert_ne!(config.page_cache_policy, PageCachePolicy::default());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageCachePolicy {
+    /// Evict the page least recently used.
+    /// A scan of a large collection evicts all the pages cached before.
+    Lru,
+
+    /// The 2Q policy. A page read for the first time is kept in a small FIFO queue,
+    /// it's moved to the LRU queue of the hot pages only if it's read again
+    /// after evicted from the FIFO queue. So the pages read once by a scan
+    /// don't evict the hot pages, such as the internal pages of the B-trees.
+    TwoQueue,
+}
+
+impl Default for PageCachePolicy {
+
+    fn default() -> Self {
+        PageCachePolicy::TwoQueue
+    }
+
+}
+
+/// The progressive delay of the commits by the pages of the journal
+/// which can't be checkpointed.
+///
+/// ```rust
+/// use std::time::Duration;
+/// use polodb_core::{Config, JournalThrottle};
+///
+/// let config = Config {
+///     journal_throttle: Some(JournalThrottle::default()),
+///     ..Default::default()
+/// };
+/// let throttle = config.journal_throttle.unwrap();
+/// assert_eq!(throttle.delay(1000, config.journal_full_size), Duration::ZERO);
+/// assert_eq!(throttle.delay(16000, config.journal_full_size), throttle.max_delay);
+/// ```
+#[derive(Debug, Clone)]
+pub struct JournalThrottle {
+    /// The throttling starts when the journal has `start_factor` times
+    /// of `journal_full_size` pages.
+    pub start_factor: u64,
+    /// The delay grows linearly to `max_delay` at `max_factor` times of `journal_full_size`.
+    pub max_factor:   u64,
+    pub max_delay:    Duration,
+}
+
+impl JournalThrottle {
+
+    /// The delay of a commit when the journal has `journal_len` pages.
+    pub fn delay(&self, journal_len: u64, journal_full_size: u64) -> Duration {
+        let start = journal_full_size.saturating_mul(self.start_factor);
+        if journal_len <= start {
+            return Duration::ZERO;
+        }
+        let max = journal_full_size.saturating_mul(self.max_factor);
+        if journal_len >= max || max <= start {
+            return self.max_delay;
+        }
+        let ratio = (journal_len - start) as f64 / (max - start) as f64;
+        self.max_delay.mul_f64(ratio)
+    }
+
+}
+
+impl Default for JournalThrottle {
+
+    fn default() -> Self {
+        JournalThrottle {
+            start_factor: 4,
+            max_factor:   16,
+            max_delay:    Duration::from_millis(50),
+        }
+    }
+
+}
+
+/// The size the database file grows by when a page is allocated beyond it.
+/// The space is preallocated by the file system where it's supported,
+/// such as `fallocate` on Linux, to keep the file contiguous.
+///
+/// ```rust
+/// use std::num::NonZeroU64;
+/// use polodb_core::GrowthStrategy;
+///
+/// let strategy = GrowthStrategy::Doubling { max_pages: NonZeroU64::new(64).unwrap() };
+/// assert_eq!(strategy.next_db_size(16 * 4096, 16 * 4096, 4096), 32 * 4096);
+/// assert_eq!(strategy.next_db_size(256 * 4096, 256 * 4096, 4096), 320 * 4096);
+/// ```
+#[derive(Debug, Clone)]
+pub enum GrowthStrategy {
+    /// Grow by the same count of pages every time.
+    Fixed(NonZeroU64),
+
+    /// Grow by the current size of the file, at most `max_pages` pages every time.
+    Doubling {
+        max_pages: NonZeroU64,
+    },
+}
+
+impl GrowthStrategy {
+
+    /// The new size of the database which is `db_size` bytes now,
+    /// to hold the `required` bytes at least.
+    pub fn next_db_size(&self, db_size: u64, required: u64, page_size: u64) -> u64 {
+        let pages = match self {
+            GrowthStrategy::Fixed(pages) => pages.get(),
+            GrowthStrategy::Doubling { max_pages } => {
+                (db_size / page_size).clamp(1, max_pages.get())
+            }
+        };
+        let mut next_size = db_size.saturating_add(pages.saturating_mul(page_size));
+        if next_size < required {
+            // round up to the pages of the increment
+            let increment = pages.saturating_mul(page_size);
+            let lack = required - next_size;
+            next_size = next_size.saturating_add((lack + increment - 1) / increment * increment);
+        }
+        next_size
+    }
+
+}
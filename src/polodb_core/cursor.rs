@@ -3,11 +3,13 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::cmp::Ordering;
 use std::sync::{Arc, Mutex};
 use std::collections::LinkedList;
 use bson::{Document, Bson};
 use crate::btree::{BTreePageDelegate, BTreePageDelegateWithKey, SearchKeyResult};
-use crate::DbResult;
+use crate::{Compression, DbResult};
+use crate::bson_utils::value_cmp;
 use crate::data_ticket::DataTicket;
 use crate::session::Session;
 
@@ -110,6 +112,99 @@ impl Cursor {
         Ok(false)
     }
 
+    /// Move to the first item whose key is not less than `pkey`.
+    pub fn seek(&mut self, session: &dyn Session, pkey: &Bson) -> DbResult<()> {
+        self.btree_stack.clear();
+        self.seek_from(session, self.root_pid, pkey)
+    }
+
+    /// Move forward to the first item whose key is not less than `pkey`,
+    /// which is not less than the key of the current item.
+    ///
+    /// The nodes on the path shared with the current item are not read again,
+    /// so the keys in order are found in one traversal of the btree.
+    pub fn seek_forward(&mut self, session: &dyn Session, pkey: &Bson) -> DbResult<()> {
+        if self.btree_stack.is_empty() {
+            return Ok(());
+        }
+
+        // The keys in the subtree of a node are less than
+        // the current key of its parent, find the deepest node
+        // whose subtree may contain the key.
+        let mut keep = 1;
+        for item in self.btree_stack.iter().take(self.btree_stack.len() - 1) {
+            let node = item.node.lock()?;
+            if item.index < node.len() {
+                let bound = &node.get_item(item.index).key;
+                if value_cmp(pkey, bound)? != Ordering::Less {
+                    break;
+                }
+            }
+            keep += 1;
+        }
+        let _ = self.btree_stack.split_off(keep);
+
+        let top = self.btree_stack.pop_back().unwrap();
+        let search_result = {
+            let node = top.node.lock()?;
+            node.search(pkey)?
+        };
+        match search_result {
+            SearchKeyResult::Node(index) => {
+                self.btree_stack.push_back(CursorItem {
+                    node: top.node,
+                    index,
+                });
+                Ok(())
+            }
+
+            SearchKeyResult::Index(index) => {
+                let next_pid = {
+                    let node = top.node.lock()?;
+                    node.get_left_pid(index)
+                };
+                self.btree_stack.push_back(CursorItem {
+                    node: top.node,
+                    index,
+                });
+                self.seek_from(session, next_pid, pkey)
+            }
+        }
+    }
+
+    fn seek_from(&mut self, session: &dyn Session, pid: u32, pkey: &Bson) -> DbResult<()> {
+        let mut current_pid = pid;
+
+        while current_pid > 0 {
+            let btree_page = session.read_page(current_pid)?;
+            let delegate = BTreePageDelegate::from_page(btree_page.as_ref(), 0)?;
+            let btree_node = BTreePageDelegateWithKey::read_from_session(delegate, session)?;
+
+            if btree_node.is_empty() {
+                break;
+            }
+
+            match btree_node.search(pkey)? {
+                SearchKeyResult::Node(index) => {
+                    self.btree_stack.push_back(CursorItem::new(btree_node, index));
+                    return Ok(());
+                }
+
+                SearchKeyResult::Index(index) => {
+                    // the items on the left of `index` are less than the key
+                    current_pid = btree_node.get_left_pid(index);
+                    self.btree_stack.push_back(CursorItem::new(btree_node, index));
+                }
+
+            }
+        }
+
+        // the leaf may have no item greater than the key
+        self.pop_all_right_most_item();
+
+        Ok(())
+    }
+
     fn mk_initial_btree(&mut self, session: &dyn Session, root_page_id: u32) -> DbResult<()> {
         self.btree_stack.clear();
 
@@ -168,20 +263,31 @@ impl Cursor {
         Some(ticket)
     }
 
-    pub fn update_current(&mut self, session: &dyn Session, doc: &Document) -> DbResult<()> {
+    /// Replace the current document, returns the change of the stored bytes,
+    /// and whether the stored bytes are changed.
+    pub fn update_current(&mut self, session: &dyn Session, doc: &Document, compression: &Compression) -> DbResult<(i64, bool)> {
         let top = self.btree_stack.pop_back().unwrap();
 
-        {
+        let (size_delta, changed) = {
             let mut content = top.node.lock()?;
 
-            session.free_data_ticket(&content.get_item(top.index).payload)?;
-            let new_ticket = session.store_doc(doc)?;
-            content.update_payload(top.index, new_ticket);
-        }
+            let old_bytes = session.free_data_ticket(&content.get_item(top.index).payload)?;
+            let mut buffer = session.take_buffer();
+            let new_ticket = compression.encode_doc_into(doc, &mut buffer)
+                .and_then(|_| session.store_data_in_storage(&buffer));
+            let new_size = buffer.len() as i64;
+            let changed = buffer != old_bytes;
+            session.give_back_buffer(buffer);
+            content.update_payload(top.index, new_ticket?);
+
+            (new_size - old_bytes.len() as i64, changed)
+        };
 
         self.btree_stack.push_back(top);
 
-        self.sync_top_btree_node(session)
+        self.sync_top_btree_node(session)?;
+
+        Ok((size_delta, changed))
     }
 
     fn sync_top_btree_node(&mut self, session: &dyn Session) -> DbResult<()> {
@@ -198,39 +304,59 @@ impl Cursor {
         !self.btree_stack.is_empty()
     }
 
-    pub fn next(&mut self, session: &dyn Session) -> DbResult<Option<Document>> {
-        if self.btree_stack.is_empty() {
-            return Ok(None);
+    /// Return the key of the current item.
+    pub fn peek_key(&self) -> DbResult<Option<Bson>> {
+        match self.btree_stack.back() {
+            Some(top) => {
+                let top_content = top.node.lock()?;
+                Ok(Some(top_content.get_item(top.index).key.clone()))
+            }
+            None => Ok(None),
         }
+    }
 
-        let top = self.btree_stack.pop_back().unwrap();
-        let (result, top_content_len) = {
-            let top_content = top.node.lock()?;
-            let result_ticket = &top_content.get_item(top.index).payload;
-            let result = session.get_doc_from_ticket(result_ticket)?;
-            (result, top_content.len())
+    pub fn next(&mut self, session: &dyn Session) -> DbResult<Option<Document>> {
+        let ticket = match self.peek_data() {
+            Some(ticket) => ticket,
+            None => return Ok(None),
         };
+        let result = session.get_doc_from_ticket(&ticket)?;
 
-        let next_index = top.index + 1;
+        self.advance(session)?;
 
-        if next_index >= top_content_len {  // right most index
-            let right_most_index = top.right_pid();
+        self.current = Some(result.clone());
+        Ok(Some(result))
+    }
 
-            if right_most_index != 0 {
-                self.btree_stack.push_back(CursorItem {
-                    node: top.node.clone(),
-                    index: next_index,
-                });
+    /// Return the key of the current item and move to the next one,
+    /// the document is not read.
+    pub fn next_key(&mut self, session: &dyn Session) -> DbResult<Option<Bson>> {
+        let key = match self.btree_stack.back() {
+            Some(top) => {
+                let top_content = top.node.lock()?;
+                top_content.get_item(top.index).key.clone()
+            }
+            None => return Ok(None),
+        };
 
-                self.push_all_left_nodes(session)?;
+        self.advance(session)?;
 
-                return Ok(Some(result));
-            }
+        Ok(Some(key))
+    }
 
+    fn advance(&mut self, session: &dyn Session) -> DbResult<()> {
+        let top = self.btree_stack.pop_back().unwrap();
+        let top_content_len = {
+            let top_content = top.node.lock()?;
+            top_content.len()
+        };
+
+        let next_index = top.index + 1;
+
+        if next_index >= top_content_len && top.right_pid() == 0 {  // right most index
             // pop
             self.pop_all_right_most_item();
-
-            return Ok(Some(result));
+            return Ok(());
         }
 
         self.btree_stack.push_back(CursorItem {
@@ -238,10 +364,7 @@ impl Cursor {
             index: next_index,
         });
 
-        self.push_all_left_nodes(session)?;
-
-        self.current = Some(result.clone());
-        Ok(Some(result))
+        self.push_all_left_nodes(session)
     }
 
     pub fn pop_all_right_most_item(&mut self) {
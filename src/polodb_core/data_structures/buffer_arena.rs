@@ -0,0 +1,96 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+// the buffers kept for the next transaction, the others are released
+const KEPT_BUFFERS: usize = 8;
+const KEPT_BUFFER_CAPACITY: usize = 64 * 1024;
+
+/// The byte buffers of a transaction, such as the encoded documents and keys.
+///
+/// A buffer is taken for an operation and given back after it, so the next operation
+/// of the transaction writes into the memory already allocated. The buffers are released
+/// together when the transaction ends, except a few small ones kept for the next one.
+pub(crate) struct BufferArena {
+    free:      Vec<Vec<u8>>,
+    // the buffers allocated in the transaction
+    allocated: usize,
+}
+
+impl BufferArena {
+
+    pub(crate) fn new() -> BufferArena {
+        BufferArena {
+            free: Vec::with_capacity(KEPT_BUFFERS),
+            allocated: 0,
+        }
+    }
+
+    /// Take an empty buffer, give it back by [`BufferArena::give_back`].
+    pub(crate) fn take(&mut self) -> Vec<u8> {
+        match self.free.pop() {
+            Some(buffer) => buffer,
+            None => {
+                self.allocated += 1;
+                Vec::new()
+            }
+        }
+    }
+
+    pub(crate) fn give_back(&mut self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        self.free.push(buffer);
+    }
+
+    /// Called when the transaction is committed or rolled back.
+    pub(crate) fn reset(&mut self) {
+        if self.allocated > 0 {
+            crate::polo_log!("buffer arena reset, {} buffers allocated", self.allocated);
+        }
+        self.free.retain(|buffer| buffer.capacity() <= KEPT_BUFFER_CAPACITY);
+        self.free.truncate(KEPT_BUFFERS);
+        self.allocated = 0;
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BufferArena, KEPT_BUFFERS, KEPT_BUFFER_CAPACITY};
+
+    #[test]
+    fn test_reuse_buffers() {
+        let mut arena = BufferArena::new();
+        let mut buffer = arena.take();
+        buffer.extend_from_slice(&[1; 100]);
+        let ptr = buffer.as_ptr();
+        arena.give_back(buffer);
+
+        let buffer = arena.take();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.as_ptr(), ptr);
+        assert_eq!(arena.allocated, 1);
+        arena.give_back(buffer);
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut arena = BufferArena::new();
+        let buffers: Vec<Vec<u8>> = (0..(KEPT_BUFFERS * 2)).map(|_| arena.take()).collect();
+        for mut buffer in buffers {
+            buffer.reserve(1024);
+            arena.give_back(buffer);
+        }
+        let mut large = arena.take();
+        large.reserve(KEPT_BUFFER_CAPACITY * 2);
+        arena.give_back(large);
+
+        arena.reset();
+        assert_eq!(arena.free.len(), KEPT_BUFFERS);
+        assert!(arena.free.iter().all(|buffer| buffer.capacity() <= KEPT_BUFFER_CAPACITY));
+        assert_eq!(arena.allocated, 0);
+    }
+
+}
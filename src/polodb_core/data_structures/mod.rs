@@ -3,4 +3,6 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-pub(crate) mod trans_map;
\ No newline at end of file
+pub(crate) mod trans_map;
+pub(crate) mod buffer_arena;
+
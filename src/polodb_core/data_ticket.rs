@@ -59,3 +59,20 @@ impl fmt::Display for DataTicket {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::data_ticket::DataTicket;
+
+    #[test]
+    fn test_bytes_big_endian() {
+        let ticket = DataTicket { pid: 0x01020304, index: 0x0506 };
+        let bytes = ticket.to_bytes();
+        assert_eq!(bytes, [0x01, 0x02, 0x03, 0x04, 0x05, 0x06]);
+
+        let ticket = DataTicket::from_bytes(&bytes);
+        assert_eq!(ticket.pid, 0x01020304);
+        assert_eq!(ticket.index, 0x0506);
+    }
+
+}
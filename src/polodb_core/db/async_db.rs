@@ -0,0 +1,313 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::future::Future;
+use std::marker::PhantomData;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use bson::Document;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use crate::{Database, DbErr, DbResult, IndexOptions};
+use crate::results::{CreateIndexResult, DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
+
+const DEFAULT_THREADS: usize = 4;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// The threads running the blocking operations of the async API.
+///
+/// The threads exit when the pool is dropped and the queued jobs are done.
+struct BlockingPool {
+    // `Sender` is not `Sync` on the older compilers
+    sender: Mutex<Sender<Job>>,
+}
+
+impl BlockingPool {
+
+    fn new(threads: NonZeroUsize) -> BlockingPool {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for index in 0..threads.get() {
+            let receiver = receiver.clone();
+            thread::Builder::new()
+                .name(format!("polodb-async-{}", index))
+                .spawn(move || run_jobs(&receiver))
+                .expect("spawn the thread of the async API");
+        }
+        BlockingPool {
+            sender: Mutex::new(sender),
+        }
+    }
+
+    fn spawn<T, F>(&self, f: F) -> AsyncTask<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> DbResult<T> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let job_state = state.clone();
+        let job: Job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f))
+                .unwrap_or(Err(DbErr::TaskPanicked));
+            complete(&job_state, result);
+        });
+        let sent = match self.sender.lock() {
+            Ok(sender) => sender.send(job).is_ok(),
+            Err(_) => false,
+        };
+        if !sent {
+            complete(&state, Err(DbErr::TaskPanicked));
+        }
+        AsyncTask { state }
+    }
+
+}
+
+fn run_jobs(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        match job {
+            Ok(job) => job(),
+            // the pool is dropped
+            Err(_) => return,
+        }
+    }
+}
+
+struct TaskState<T> {
+    result: Option<DbResult<T>>,
+    waker:  Option<Waker>,
+}
+
+fn complete<T>(state: &Mutex<TaskState<T>>, result: DbResult<T>) {
+    let waker = match state.lock() {
+        Ok(mut state) => {
+            state.result = Some(result);
+            state.waker.take()
+        }
+        Err(_) => return,
+    };
+    if let Some(waker) = waker {
+        waker.wake();
+    }
+}
+
+/// The result of an operation of [`AsyncDatabase`] or [`AsyncCollection`],
+/// running on the threads of the database.
+///
+/// It's not bound to any runtime, so it can be awaited in tokio or any other executor.
+/// The operation runs even if the task is dropped before it's done.
+#[must_use = "the result of the operation is only returned by awaiting the task"]
+pub struct AsyncTask<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+impl<T> Future for AsyncTask<T> {
+    type Output = DbResult<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<DbResult<T>> {
+        let mut state = self.state.lock()?;
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// The async API of a [`Database`].
+///
+/// The operations of [`Database`] are blocking, the reads and writes of the pages
+/// wait for the disk. `AsyncDatabase` runs them on a pool of threads of its own
+/// and returns the futures of them, so the database can be used in an async web
+/// server without wrapping every call by `spawn_blocking`. The futures don't
+/// depend on any runtime.
+///
+/// The handle is cheap to clone, the clones share the database and the threads.
+///
+/// ```rust
+/// use polodb_core::{AsyncDatabase, Database};
+/// use polodb_core::bson::{Document, doc};
+///
+/// # let db_path = polodb_core::test_utils::mk_db_path("doc-test-polo-async");
+/// let db = AsyncDatabase::new(Database::open_file(db_path).unwrap());
+/// # polodb_core::test_utils::block_on(async move {
+/// let books = db.collection::<Document>("books");
+/// books.insert_one(doc! { "title": "1984" }).await.unwrap();
+///
+/// let book = books.find_one(doc! { "title": "1984" }).await.unwrap();
+/// assert!(book.is_some());
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    db:   Arc<Database>,
+    pool: Arc<BlockingPool>,
+}
+
+impl AsyncDatabase {
+
+    /// Run the operations of the database on 4 threads.
+    pub fn new(db: impl Into<Arc<Database>>) -> AsyncDatabase {
+        AsyncDatabase::with_threads(db, NonZeroUsize::new(DEFAULT_THREADS).unwrap())
+    }
+
+    /// Run the operations of the database on `threads` threads.
+    ///
+    /// The writes are serialized by the database anyway,
+    /// more threads only let more reads run at the same time.
+    pub fn with_threads(db: impl Into<Arc<Database>>, threads: NonZeroUsize) -> AsyncDatabase {
+        AsyncDatabase {
+            db: db.into(),
+            pool: Arc::new(BlockingPool::new(threads)),
+        }
+    }
+
+    /// The blocking handle of the database.
+    pub fn database(&self) -> &Arc<Database> {
+        &self.db
+    }
+
+    /// Run `f` with the database on the threads, for the operations
+    /// not covered by the async API.
+    pub fn run<T, F>(&self, f: F) -> AsyncTask<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> DbResult<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        self.pool.spawn(move || f(&db))
+    }
+
+    pub fn collection<T>(&self, name: &str) -> AsyncCollection<T> {
+        AsyncCollection {
+            db: self.clone(),
+            name: name.to_string(),
+            _phantom: PhantomData,
+        }
+    }
+
+    pub fn create_collection(&self, name: &str) -> AsyncTask<()> {
+        let name = name.to_string();
+        self.run(move |db| db.create_collection(&name))
+    }
+
+    pub fn list_collection_names(&self) -> AsyncTask<Vec<String>> {
+        self.run(|db| db.list_collection_names())
+    }
+
+    /// Fetch the pages of the object store ahead on the threads,
+    /// see [`Database::prefetch`].
+    pub fn prefetch(&self) -> AsyncTask<usize> {
+        self.run(|db| db.prefetch())
+    }
+
+}
+
+/// The async API of a [`Collection`](crate::Collection), created by [`AsyncDatabase::collection`].
+pub struct AsyncCollection<T> {
+    db:       AsyncDatabase,
+    name:     String,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for AsyncCollection<T> {
+    fn clone(&self) -> Self {
+        AsyncCollection {
+            db: self.db.clone(),
+            name: self.name.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> AsyncCollection<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run<R, F>(&self, f: F) -> AsyncTask<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(crate::Collection<T>) -> DbResult<R> + Send + 'static,
+    {
+        let name = self.name.clone();
+        self.db.run(move |db| f(db.collection::<T>(&name)))
+    }
+
+    pub fn count_documents(&self) -> AsyncTask<u64> {
+        self.run(|col| col.count_documents())
+    }
+
+    pub fn insert_one(&self, doc: T) -> AsyncTask<InsertOneResult> {
+        self.run(move |col| col.insert_one(doc))
+    }
+
+    pub fn insert_many(&self, docs: Vec<T>) -> AsyncTask<InsertManyResult> {
+        self.run(move |col| col.insert_many(docs))
+    }
+
+    pub fn find_one(&self, filter: impl Into<Option<Document>>) -> AsyncTask<Option<T>> {
+        let filter = filter.into();
+        self.run(move |col| col.find_one(filter))
+    }
+
+    pub fn find_many(&self, filter: impl Into<Option<Document>>) -> AsyncTask<Vec<T>> {
+        let filter = filter.into();
+        self.run(move |col| col.find_many(filter))
+    }
+
+    pub fn update_one(&self, query: Document, update: Document) -> AsyncTask<UpdateResult> {
+        self.run(move |col| col.update_one(query, update))
+    }
+
+    pub fn update_many(&self, query: Document, update: Document) -> AsyncTask<UpdateResult> {
+        self.run(move |col| col.update_many(query, update))
+    }
+
+    pub fn delete_one(&self, query: Document) -> AsyncTask<DeleteResult> {
+        self.run(move |col| col.delete_one(query))
+    }
+
+    pub fn delete_many(&self, query: Document) -> AsyncTask<DeleteResult> {
+        self.run(move |col| col.delete_many(query))
+    }
+
+    pub fn aggregate(&self, pipeline: Vec<Document>) -> AsyncTask<Vec<Document>> {
+        self.run(move |col| col.aggregate(pipeline))
+    }
+
+    pub fn create_index(&self, keys: Document, options: IndexOptions) -> AsyncTask<CreateIndexResult> {
+        self.run(move |col| col.create_index(keys, options))
+    }
+
+    pub fn drop_index(&self, name: &str) -> AsyncTask<()> {
+        let name = name.to_string();
+        self.run(move |col| col.drop_index(&name))
+    }
+
+    pub fn drop(&self) -> AsyncTask<()> {
+        self.run(|col| col.drop())
+    }
+
+}
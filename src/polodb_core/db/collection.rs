@@ -4,11 +4,20 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use serde::Serialize;
-use bson::Document;
+use bson::{Bson, Document, doc};
 use std::borrow::Borrow;
 use serde::de::DeserializeOwned;
-use crate::{ClientSession, Database, DbResult};
-use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
+use crate::{ClientSession, CollectionOptions, Database, DbResult, DeleteOptions, FindOptions, IndexOptions, InsertManyOptions, UpdateOptions};
+use crate::results::{CollectionStats, CreateIndexResult, DeleteResult, FindByIdsResult, InsertManyResult, InsertOneResult, PinResult, UpdateIfResult, UpdateResult};
+use crate::ejson;
+use crate::pagination::{self, Page};
+use crate::explain::{Explain, ExplainVerbosity};
+use crate::schema::SchemaAnalysis;
+use crate::subscription::Subscription;
+use super::db::from_stored_document;
+
+/// The field of the date a document expires at, see [`Collection::delete_expired`].
+pub(crate) const EXPIRE_AT_FIELD: &str = "expireAt";
 
 /// A wrapper of collection in struct.
 ///
@@ -18,6 +27,7 @@ use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResu
 pub struct Collection<'a, T> {
     db: &'a Database,
     name: String,
+    options: CollectionOptions,
     _phantom: std::marker::PhantomData<T>,
 }
 
@@ -28,6 +38,7 @@ impl<'a, T>  Collection<'a, T>
         Collection {
             db,
             name: name.into(),
+            options: CollectionOptions::default(),
             _phantom: std::default::Default::default(),
         }
     }
@@ -36,6 +47,55 @@ impl<'a, T>  Collection<'a, T>
         &self.name
     }
 
+    /// Return a handle of the same collection with the default `options`,
+    /// the options of this handle are not changed.
+    ///
+    /// ```rust
+    /// use polodb_core::{CollectionOptions, Database, FindOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let posts = db.collection::<Document>("posts").with_options(CollectionOptions {
+    ///     find: FindOptions {
+    ///         projection: Some(doc! { "body": 0 }),
+    ///         ..Default::default()
+    ///     },
+    ///     ..Default::default()
+    /// });
+    /// posts.insert_one(doc! { "_id": 1, "title": "Hello", "body": "..." }).unwrap();
+    ///
+    /// let post = posts.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    /// assert_eq!(post, doc! { "_id": 1, "title": "Hello" });
+    /// ```
+    pub fn with_options(&self, options: CollectionOptions) -> Collection<'a, T> {
+        Collection {
+            db: self.db,
+            name: self.name.clone(),
+            options,
+            _phantom: std::default::Default::default(),
+        }
+    }
+
+    /// Return the default options of this handle.
+    pub fn options(&self) -> &CollectionOptions {
+        &self.options
+    }
+
+    fn find_options(&self, options: FindOptions) -> FindOptions {
+        FindOptions {
+            projection: options.projection.or_else(|| self.options.find.projection.clone()),
+            sort: options.sort.or_else(|| self.options.find.sort.clone()),
+            skip: options.skip.or(self.options.find.skip),
+            limit: options.limit.or(self.options.find.limit),
+        }
+    }
+
+    fn delete_options(&self, options: DeleteOptions) -> DeleteOptions {
+        DeleteOptions {
+            batch_size: options.batch_size.or(self.options.delete.batch_size),
+        }
+    }
+
     /// Return the size of all data in the collection.
     pub fn count_documents(&self) -> DbResult<u64> {
         self.db.count_documents(&self.name, None)
@@ -46,28 +106,242 @@ impl<'a, T>  Collection<'a, T>
         self.db.count_documents(&self.name, Some(&session.id))
     }
 
+    /// Return the number of the documents and the bytes they take,
+    /// which are maintained on every write instead of scanning the collection.
+    /// The reads and the writes on the collection are in `metrics`
+    /// if [`crate::Metrics::enable`] is called.
+    pub fn stats(&self) -> DbResult<CollectionStats> {
+        self.db.collection_stats(&self.name, None)
+    }
+
+    pub fn stats_with_session(&self, session: &mut ClientSession) -> DbResult<CollectionStats> {
+        self.db.collection_stats(&self.name, Some(&session.id))
+    }
+
+    /// Report the fields of at most `sample_size` documents, with the ratio of the documents
+    /// having them, the types of the values and the count of the distinct values.
+    /// See [`schema`](crate::schema).
+    pub fn analyze_schema(&self, sample_size: usize) -> DbResult<SchemaAnalysis> {
+        self.db.analyze_schema(&self.name, sample_size, None)
+    }
+
+    pub fn analyze_schema_with_session(&self, sample_size: usize, session: &mut ClientSession) -> DbResult<SchemaAnalysis> {
+        self.db.analyze_schema(&self.name, sample_size, Some(&session.id))
+    }
+
+    /// Keep the pages of the collection and its indexes in the page cache,
+    /// such as a small collection of the configs read all the time.
+    ///
+    /// The pinned pages of all the collections take at most half of
+    /// [`Config::page_cache_size`], [`DbErr::PinnedPagesExceeded`] if they are more.
+    /// The pages are pinned again after the writes on the collection,
+    /// the ones pinned before are kept if it grows beyond the budget.
+    /// Nothing is evicted from a database in memory.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let countries = db.collection::<Document>("countries");
+    /// countries.insert_one(doc! { "_id": "FR", "name": "France" }).unwrap();
+    ///
+    /// let result = countries.pin().unwrap();
+    /// assert!(result.pages > 0);
+    /// countries.unpin().unwrap();
+    /// ```
+    ///
+    /// [`Config::page_cache_size`]: crate::Config::page_cache_size
+    /// [`DbErr::PinnedPagesExceeded`]: crate::DbErr::PinnedPagesExceeded
+    pub fn pin(&self) -> DbResult<PinResult> {
+        self.db.pin_collection(&self.name)
+    }
+
+    /// Let the pages of the collection be evicted from the page cache again.
+    pub fn unpin(&self) -> DbResult<()> {
+        self.db.unpin_collection(&self.name)
+    }
+
     /// Updates up to one document matching `query` in the collection.
     /// [documentation](https://www.polodb.org/docs/curd/update) for more information on specifying updates.
     pub fn update_one(&self, query: Document, update: Document) -> DbResult<UpdateResult> {
-        self.db.update_one(&self.name, query, update, None)
+        self.db.update_one(&self.name, query, update, UpdateOptions::default(), None)
+    }
+
+    /// Updates up to one document matching `query` with the options,
+    /// a document is inserted if nothing matches and [`UpdateOptions::upsert`] is set.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, UpdateOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let counters = db.collection::<Document>("counters");
+    /// let options = UpdateOptions {
+    ///     upsert: Some(true),
+    /// };
+    ///
+    /// let result = counters.update_one_with_options(
+    ///     doc! { "_id": "visits" },
+    ///     doc! { "$inc": { "count": 1 } },
+    ///     options.clone(),
+    /// ).unwrap();
+    /// assert_eq!(result.upserted_id, Some("visits".into()));
+    ///
+    /// let result = counters.update_one_with_options(
+    ///     doc! { "_id": "visits" },
+    ///     doc! { "$inc": { "count": 1 } },
+    ///     options,
+    /// ).unwrap();
+    /// assert_eq!((result.matched_count, result.modified_count), (1, 1));
+    /// assert_eq!(result.upserted_id, None);
+    /// ```
+    pub fn update_one_with_options(&self, query: Document, update: Document, options: UpdateOptions) -> DbResult<UpdateResult> {
+        self.db.update_one(&self.name, query, update, options, None)
     }
 
     /// Updates up to one document matching `query` in the collection.
     /// [documentation](https://www.polodb.org/docs/curd/update) for more information on specifying updates.
     pub fn update_one_with_session(&self, query: Document, update: Document, session: &mut ClientSession) -> DbResult<UpdateResult> {
-        self.db.update_one(&self.name, query, update, Some(&session.id))
+        self.db.update_one(&self.name, query, update, UpdateOptions::default(), Some(&session.id))
+    }
+
+    /// Updates the document matching `query` only if the version in `query` is
+    /// still the current one, the version field is set by [`CreateCollectionOptions::version_field`]
+    /// and increased on every update.
+    ///
+    /// ```rust
+    /// use polodb_core::{CreateCollectionOptions, Database};
+    /// use polodb_core::bson::{Document, doc};
+    /// use polodb_core::results::UpdateIfResult;
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.create_collection_with_options("accounts", CreateCollectionOptions {
+    ///     version_field: Some("version".into()),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// let accounts = db.collection::<Document>("accounts");
+    /// accounts.insert_one(doc! { "_id": 1, "balance": 100, "version": 0 }).unwrap();
+    ///
+    /// let result = accounts.update_one_if(
+    ///     doc! { "_id": 1, "version": 0 },
+    ///     doc! { "$set": { "balance": 80 } },
+    /// ).unwrap();
+    /// assert_eq!(result, UpdateIfResult::Updated);
+    ///
+    /// // the version has been increased by the previous update
+    /// let result = accounts.update_one_if(
+    ///     doc! { "_id": 1, "version": 0 },
+    ///     doc! { "$set": { "balance": 50 } },
+    /// ).unwrap();
+    /// assert_eq!(result, UpdateIfResult::VersionConflict { current_version: 1.into() });
+    /// ```
+    ///
+    /// [`CreateCollectionOptions::version_field`]: crate::CreateCollectionOptions::version_field
+    pub fn update_one_if(&self, query: Document, update: Document) -> DbResult<UpdateIfResult> {
+        self.db.update_one_if(&self.name, query, update, None)
+    }
+
+    /// Updates the document matching `query` only if the version in `query` is still the current one.
+    pub fn update_one_if_with_session(&self, query: Document, update: Document, session: &mut ClientSession) -> DbResult<UpdateIfResult> {
+        self.db.update_one_if(&self.name, query, update, Some(&session.id))
     }
 
     /// Updates all documents matching `query` in the collection.
     /// [documentation](https://www.polodb.org/docs/curd/update) for more information on specifying updates.
     pub fn update_many(&self, query: Document, update: Document) -> DbResult<UpdateResult> {
-        self.db.update_many(&self.name, query, update, None)
+        self.db.update_many(&self.name, query, update, UpdateOptions::default(), None)
+    }
+
+    /// Updates all documents matching `query` with the options.
+    pub fn update_many_with_options(&self, query: Document, update: Document, options: UpdateOptions) -> DbResult<UpdateResult> {
+        self.db.update_many(&self.name, query, update, options, None)
     }
 
     /// Updates all documents matching `query` in the collection.
     /// [documentation](https://www.polodb.org/docs/curd/update) for more information on specifying updates.
     pub fn update_many_with_session(&self, query: Document, update: Document, session: &mut ClientSession) -> DbResult<UpdateResult> {
-        self.db.update_many(&self.name, query, update, Some(&session.id))
+        self.db.update_many(&self.name, query, update, UpdateOptions::default(), Some(&session.id))
+    }
+
+    /// Updates up to one document with the query and the update of [Extended JSON](crate::ejson).
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::Document;
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let users = db.collection::<Document>("users");
+    /// users.insert_one_ejson(r#"{"_id": {"$oid": "65a1b2c3d4e5f60718293a4b"}, "name": "Alice"}"#).unwrap();
+    ///
+    /// let result = users.update_one_ejson(
+    ///     r#"{"_id": {"$oid": "65a1b2c3d4e5f60718293a4b"}}"#,
+    ///     r#"{"$set": {"last_login": {"$date": "2024-01-01T00:00:00Z"}}}"#,
+    /// ).unwrap();
+    /// assert_eq!(result.modified_count, 1);
+    /// ```
+    pub fn update_one_ejson(&self, query: &str, update: &str) -> DbResult<UpdateResult> {
+        self.update_one(ejson::parse_document(query)?, ejson::parse_document(update)?)
+    }
+
+    /// Updates all documents with the query and the update of [Extended JSON](crate::ejson).
+    pub fn update_many_ejson(&self, query: &str, update: &str) -> DbResult<UpdateResult> {
+        self.update_many(ejson::parse_document(query)?, ejson::parse_document(update)?)
+    }
+
+    /// Inserts a document of [Extended JSON](crate::ejson) into the collection.
+    pub fn insert_one_ejson(&self, doc: &str) -> DbResult<InsertOneResult> {
+        self.db.insert_one::<Document>(&self.name, ejson::parse_document(doc)?, None)
+    }
+
+    /// Inserts an array of documents of [Extended JSON](crate::ejson) into the collection.
+    pub fn insert_many_ejson(&self, docs: &str) -> DbResult<InsertManyResult> {
+        self.db.insert_many::<Document>(&self.name, ejson::parse_documents(docs)?, InsertManyOptions::default(), None)
+    }
+
+    /// Run the aggregation pipeline on the collection.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.collection::<Document>("authors").insert_one(doc! { "_id": 1, "name": "George Orwell" }).unwrap();
+    /// let books = db.collection::<Document>("books");
+    /// books.insert_one(doc! { "title": "1984", "authorId": 1 }).unwrap();
+    ///
+    /// let result = books.aggregate(vec![
+    ///     doc! {
+    ///         "$lookup": {
+    ///             "from": "authors",
+    ///             "localField": "authorId",
+    ///             "foreignField": "_id",
+    ///             "as": "authors",
+    ///         },
+    ///     },
+    /// ]).unwrap();
+    /// assert_eq!(result[0].get_array("authors").unwrap().len(), 1);
+    /// ```
+    pub fn aggregate(&self, pipeline: Vec<Document>) -> DbResult<Vec<Document>> {
+        self.db.aggregate(&self.name, &pipeline, None)
+    }
+
+    /// Run the aggregation pipeline on the collection.
+    pub fn aggregate_with_session(&self, pipeline: Vec<Document>, session: &mut ClientSession) -> DbResult<Vec<Document>> {
+        self.db.aggregate(&self.name, &pipeline, Some(&session.id))
+    }
+
+    /// Subscribe the changes of the documents matching `filter` in the collection,
+    /// see [`subscription`](crate::subscription).
+    pub fn subscribe(&self, filter: impl Into<Option<Document>>) -> DbResult<Subscription> {
+        self.db.subscribe(&self.name, filter.into())
+    }
+
+    /// Watch all the changes of the collection, which is `subscribe(None)`.
+    /// The returned [`Subscription`] is an iterator of the changes,
+    /// which are sent after the transactions are committed.
+    pub fn watch(&self) -> DbResult<Subscription> {
+        self.db.subscribe(&self.name, None)
     }
 
     /// Deletes up to one document found matching `query`.
@@ -84,20 +358,102 @@ impl<'a, T>  Collection<'a, T>
     ///
     /// The size of data deleted returns.
     pub fn delete_many(&self, query: Document) -> DbResult<DeleteResult> {
-        self.db.delete_many(&self.name, query, None)
+        self.db.delete_many(&self.name, query, self.delete_options(DeleteOptions::default()), None)
+    }
+
+    /// Deletes all documents matching `query` with the options.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, DeleteOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let events = db.collection::<Document>("events");
+    /// events.insert_many((0..100).map(|i| doc! { "_id": i })).unwrap();
+    ///
+    /// let result = events.delete_many_with_options(doc! { "_id": { "$lt": 50 } }, DeleteOptions {
+    ///     batch_size: Some(16),
+    /// }).unwrap();
+    /// assert_eq!(result.deleted_count, 50);
+    /// ```
+    pub fn delete_many_with_options(&self, query: Document, options: DeleteOptions) -> DbResult<DeleteResult> {
+        self.db.delete_many(&self.name, query, self.delete_options(options), None)
     }
 
     /// When query is `None`, all the data in the collection will be deleted.
     ///
     /// The size of data deleted returns.
     pub fn delete_many_with_session(&self, query: Document, session: &mut ClientSession) -> DbResult<DeleteResult> {
-        self.db.delete_many(&self.name, query, Some(&session.id))
+        self.db.delete_many(&self.name, query, self.delete_options(DeleteOptions::default()), Some(&session.id))
     }
 
-    /// release in 0.12
-    #[allow(dead_code)]
-    fn create_index(&self, keys: &Document, options: Option<&Document>) -> DbResult<()> {
-        self.db.create_index(&self.name, keys, options, None)
+    /// Delete the documents whose `expireAt` field is a date in the past,
+    /// by the [`clock`](crate::clock) of the config. The field should be a date
+    /// if it's set, the documents without it never expire.
+    ///
+    /// It's a TTL of the documents without an index: the query uses an index
+    /// of `expireAt` if there is one, otherwise it scans the collection, which is
+    /// cheap for the small collections. It's run by [`MaintenanceJob::ExpireAt`] too.
+    ///
+    /// [`MaintenanceJob::ExpireAt`]: crate::maintenance::MaintenanceJob::ExpireAt
+    pub fn delete_expired(&self) -> DbResult<DeleteResult> {
+        let query = self.db.expired_query()?;
+        self.delete_many(query)
+    }
+
+    /// Deletes all the documents by freeing the pages of the collection at once,
+    /// instead of deleting them one by one as `delete_many({})` does.
+    ///
+    /// The collection and its options are kept.
+    pub fn truncate(&self) -> DbResult<DeleteResult> {
+        self.db.truncate(&self.name, None)
+    }
+
+    /// Deletes all the documents by freeing the pages of the collection at once.
+    pub fn truncate_with_session(&self, session: &mut ClientSession) -> DbResult<DeleteResult> {
+        self.db.truncate(&self.name, Some(&session.id))
+    }
+
+    /// Creates an index on the fields of `keys`, such as `{ "age": 1, "name": -1 }`,
+    /// and returns the name of the index.
+    ///
+    /// The queries on the fields of an index only scan the documents found by the index.
+    /// Creating an index already existing with the same keys and options does nothing.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, IndexOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let users = db.collection::<Document>("users");
+    /// let result = users.create_index(doc! { "email": 1 }, IndexOptions {
+    ///     unique: Some(true),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(result.index_name, "email_1");
+    ///
+    /// users.insert_one(doc! { "email": "alice@example.com" }).unwrap();
+    /// assert!(users.insert_one(doc! { "email": "alice@example.com" }).is_err());
+    /// ```
+    pub fn create_index(&self, keys: Document, options: IndexOptions) -> DbResult<CreateIndexResult> {
+        let index_name = self.db.create_index(&self.name, &keys, &options, None)?;
+        Ok(CreateIndexResult { index_name })
+    }
+
+    pub fn create_index_with_session(&self, keys: Document, options: IndexOptions, session: &mut ClientSession) -> DbResult<CreateIndexResult> {
+        let index_name = self.db.create_index(&self.name, &keys, &options, Some(&session.id))?;
+        Ok(CreateIndexResult { index_name })
+    }
+
+    /// Drops the index named `name`, returns [`DbErr::IndexNotFound`] if it doesn't exist.
+    ///
+    /// [`DbErr::IndexNotFound`]: crate::DbErr::IndexNotFound
+    pub fn drop_index(&self, name: &str) -> DbResult<()> {
+        self.db.drop_index(&self.name, name, None)
+    }
+
+    pub fn drop_index_with_session(&self, name: &str, session: &mut ClientSession) -> DbResult<()> {
+        self.db.drop_index(&self.name, name, Some(&session.id))
     }
 
     pub fn drop(&self) -> DbResult<()> {
@@ -125,7 +481,37 @@ where
 
     /// Inserts the data in `docs` into the collection.
     pub fn insert_many(&self, docs: impl IntoIterator<Item = impl Borrow<T>>) -> DbResult<InsertManyResult> {
-        self.db.insert_many(&self.name, docs, None)
+        self.db.insert_many(&self.name, docs, InsertManyOptions::default(), None)
+    }
+
+    /// Inserts the data in `docs` with the options, the documents which can't be
+    /// inserted are skipped if [`InsertManyOptions::ordered`] is `false`.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, InsertManyOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let users = db.collection::<Document>("users");
+    /// users.insert_one(doc! { "_id": 2, "name": "Bob" }).unwrap();
+    ///
+    /// let result = users.insert_many_with_options(vec![
+    ///     doc! { "_id": 1, "name": "Alice" },
+    ///     doc! { "_id": 2, "name": "Bob" },
+    ///     doc! { "_id": 3, "name": "Carol" },
+    /// ], InsertManyOptions {
+    ///     ordered: Some(false),
+    /// }).unwrap();
+    /// assert_eq!(result.inserted_ids.len(), 2);
+    /// assert_eq!(result.write_errors[0].index, 1);
+    /// assert_eq!(users.count_documents().unwrap(), 3);
+    /// ```
+    pub fn insert_many_with_options(
+        &self,
+        docs: impl IntoIterator<Item = impl Borrow<T>>,
+        options: InsertManyOptions,
+    ) -> DbResult<InsertManyResult> {
+        self.db.insert_many(&self.name, docs, options, None)
     }
 
     /// Inserts the data in `docs` into the collection.
@@ -134,7 +520,7 @@ where
         docs: impl IntoIterator<Item = impl Borrow<T>>,
         session: &mut ClientSession
     ) -> DbResult<InsertManyResult> {
-        self.db.insert_many(&self.name, docs, Some(&session.id))
+        self.db.insert_many(&self.name, docs, InsertManyOptions::default(), Some(&session.id))
     }
 }
 
@@ -145,55 +531,244 @@ impl<'a, T>  Collection<'a, T>
     /// When query document is passed to the function. The result satisfies
     /// the query document.
     pub fn find_many(&self, filter: impl Into<Option<Document>>) -> DbResult<Vec<T>> {
-        self.db.find_many(&self.name, filter, None)
+        self.db.find_many(&self.name, filter, self.find_options(FindOptions::default()), None)
     }
 
     /// When query document is passed to the function. The result satisfies
     /// the query document.
     pub fn find_many_with_session(&self, filter: impl Into<Option<Document>>, session: &mut ClientSession) -> DbResult<Vec<T>> {
-        self.db.find_many(&self.name, filter, Some(&session.id))
+        self.db.find_many(&self.name, filter, self.find_options(FindOptions::default()), Some(&session.id))
+    }
+
+    /// Return the documents satisfying the query as an iterator of `T`.
+    ///
+    /// Each document is deserialized when the iterator reaches it, so a stored document
+    /// not matching `T` is an [`DbErr::DeserializeDocument`] of its own
+    /// instead of failing the others.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, DbErr};
+    /// use polodb_core::bson::{Document, doc};
+    /// use serde::{Deserialize, Serialize};
+    ///
+    /// #[derive(Debug, Serialize, Deserialize)]
+    /// struct Book {
+    ///     title: String,
+    ///     pages: i32,
+    /// }
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.collection::<Document>("books").insert_one(doc! { "_id": 1, "title": "1984" }).unwrap();
+    ///
+    /// let books = db.collection::<Book>("books");
+    /// books.insert_one(Book { title: "Dune".into(), pages: 412 }).unwrap();
+    ///
+    /// let (found, failed): (Vec<_>, Vec<_>) = books.find(None).unwrap().partition(|book| book.is_ok());
+    /// assert_eq!(found[0].as_ref().unwrap().title, "Dune");
+    /// assert!(matches!(failed[0], Err(DbErr::DeserializeDocument(_))));
+    /// ```
+    ///
+    /// [`DbErr::DeserializeDocument`]: crate::DbErr::DeserializeDocument
+    pub fn find(&self, filter: impl Into<Option<Document>>) -> DbResult<impl Iterator<Item = DbResult<T>>> {
+        self.find_with_options(filter, FindOptions::default())
+    }
+
+    /// Return the documents satisfying the query with the options as an iterator of `T`.
+    pub fn find_with_options(
+        &self,
+        filter: impl Into<Option<Document>>,
+        options: FindOptions,
+    ) -> DbResult<impl Iterator<Item = DbResult<T>>> {
+        let docs: Vec<Document> = self.db.find_many(&self.name, filter, self.find_options(options), None)?;
+        let name = self.name.clone();
+        Ok(docs.into_iter().map(move |doc| from_stored_document(&name, doc)))
+    }
+
+    /// Return the documents satisfying the query as an iterator of `T`
+    /// in the transaction of the session.
+    pub fn find_with_session(
+        &self,
+        filter: impl Into<Option<Document>>,
+        session: &mut ClientSession,
+    ) -> DbResult<impl Iterator<Item = DbResult<T>>> {
+        let docs: Vec<Document> = self.db.find_many(&self.name, filter, self.find_options(FindOptions::default()), Some(&session.id))?;
+        let name = self.name.clone();
+        Ok(docs.into_iter().map(move |doc| from_stored_document(&name, doc)))
+    }
+
+    /// Return a page of the documents satisfying the query in the sort order.
+    ///
+    /// Pass the `next_token` of the previous page as `after` to continue,
+    /// the documents of the previous pages are excluded by a filter generated
+    /// by [`paginate_after`] instead of being skipped.
+    ///
+    /// [`paginate_after`]: crate::pagination::paginate_after
+    pub fn paginate(
+        &self,
+        filter: impl Into<Option<Document>>,
+        sort: Document,
+        after: Option<&str>,
+        limit: usize,
+    ) -> DbResult<Page<T>> {
+        let mut filter: Option<Document> = filter.into();
+        if let Some(token) = after {
+            let last_seen_key = pagination::decode_token(token)?;
+            let keyset = pagination::paginate_after(&sort, &last_seen_key)?;
+            filter = Some(match filter {
+                Some(filter) => doc! { "$and": [filter, keyset] },
+                None => keyset,
+            });
+        }
+        let docs: Vec<Document> = self.db.find_many(&self.name, filter, FindOptions::default(), None)?;
+        let page = pagination::paginate_documents(docs, &sort, limit)?;
+
+        let mut items = Vec::with_capacity(page.items.len());
+        for doc in page.items {
+            items.push(from_stored_document(&self.name, doc)?);
+        }
+        Ok(Page {
+            items,
+            next_token: page.next_token,
+        })
     }
 
     /// Return the first element in the collection satisfies the query.
     pub fn find_one(&self, filter: impl Into<Option<Document>>) -> DbResult<Option<T>> {
-        self.db.find_one(&self.name, filter, None)
+        self.db.find_one(&self.name, filter, self.find_options(FindOptions::default()), None)
+    }
+
+    /// Return the documents satisfying the query of [Extended JSON](crate::ejson).
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::Document;
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let events = db.collection::<Document>("events");
+    /// events.insert_many_ejson(r#"[
+    ///     {"name": "launch", "at": {"$date": "2024-01-01T00:00:00Z"}},
+    ///     {"name": "review", "at": {"$date": "2024-03-01T00:00:00Z"}}
+    /// ]"#).unwrap();
+    ///
+    /// let result = events.find_ejson(r#"{"at": {"$gt": {"$date": "2024-02-01T00:00:00Z"}}}"#).unwrap();
+    /// assert_eq!(result.len(), 1);
+    /// assert_eq!(result[0].get_str("name").unwrap(), "review");
+    /// ```
+    pub fn find_ejson(&self, filter: &str) -> DbResult<Vec<T>> {
+        self.find_many(ejson::parse_document(filter)?)
+    }
+
+    /// Return the first document satisfying the query of [Extended JSON](crate::ejson).
+    pub fn find_one_ejson(&self, filter: &str) -> DbResult<Option<T>> {
+        self.find_one(ejson::parse_document(filter)?)
     }
 
     /// Return the first element in the collection satisfies the query.
     pub fn find_one_with_session(&self, filter: impl Into<Option<Document>>, session: &mut ClientSession) -> DbResult<Option<T>> {
-        self.db.find_one(&self.name, filter, Some(&session.id))
+        self.db.find_one(&self.name, filter, self.find_options(FindOptions::default()), Some(&session.id))
+    }
+
+    /// Return the documents of the `_id` fields, which are found in one traversal
+    /// of the collection instead of a [`Collection::find_one`] for each of them.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let users = db.collection::<Document>("users");
+    /// users.insert_many(vec![
+    ///     doc! { "_id": 1, "name": "Alice" },
+    ///     doc! { "_id": 2, "name": "Bob" },
+    /// ]).unwrap();
+    ///
+    /// let result = users.find_many_by_ids(vec![2, 3, 1]).unwrap();
+    /// assert_eq!(result.found.len(), 2);
+    /// assert_eq!(result.missing, vec![3.into()]);
+    /// ```
+    pub fn find_many_by_ids(&self, ids: impl IntoIterator<Item = impl Into<Bson>>) -> DbResult<FindByIdsResult<T>> {
+        let ids = ids.into_iter().map(Into::into).collect();
+        self.db.find_many_by_ids(&self.name, ids, None)
+    }
+
+    /// Return the documents of the `_id` fields in the transaction of the session.
+    pub fn find_many_by_ids_with_session(
+        &self,
+        ids: impl IntoIterator<Item = impl Into<Bson>>,
+        session: &mut ClientSession,
+    ) -> DbResult<FindByIdsResult<T>> {
+        let ids = ids.into_iter().map(Into::into).collect();
+        self.db.find_many_by_ids(&self.name, ids, Some(&session.id))
     }
-}
 
-// #[cfg(test)]
-// mod tests {
-//     use bson::{Document, doc};
-//     use crate::test_utils::prepare_db;
-//
-//     #[test]
-//     fn test_create_index() {
-//         let db = prepare_db("test-create-index").unwrap();
-//         let collection = db.collection::<Document>("test");
-//
-//         let keys = doc! {
-//             "user_id": 1,
-//         };
-//
-//         collection.create_index(&keys, None).unwrap();
-//
-//         for i in 0..10 {
-//             let str = i.to_string();
-//             let data = doc! {
-//                 "name": str.clone(),
-//                 "user_id": str.clone(),
-//             };
-//             collection.insert_one(data).unwrap();
-//         }
-//
-//         let data = doc! {
-//             "name": "what",
-//             "user_id": 3,
-//         };
-//         collection.insert_one(data).expect_err("not comparable");
-//     }
-// }
+    /// Return the documents satisfying the query with the options.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, FindOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let collection = db.collection::<Document>("books");
+    /// collection.insert_one(doc! { "title": "1984", "author": "George Orwell" }).unwrap();
+    ///
+    /// let books = collection.find_many_with_options(None, FindOptions {
+    ///     projection: Some(doc! { "_id": 0, "title": 1 }),
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// assert_eq!(books, vec![doc! { "title": "1984" }]);
+    /// ```
+    pub fn find_many_with_options(&self, filter: impl Into<Option<Document>>, options: FindOptions) -> DbResult<Vec<T>> {
+        self.db.find_many(&self.name, filter, self.find_options(options), None)
+    }
+
+    /// Return the first element in the collection satisfies the query with the options.
+    pub fn find_one_with_options(&self, filter: impl Into<Option<Document>>, options: FindOptions) -> DbResult<Option<T>> {
+        self.db.find_one(&self.name, filter, self.find_options(options), None)
+    }
+
+    /// Describe how the query is executed, the query is run
+    /// if the verbosity is [`ExplainVerbosity::ExecutionStats`].
+    pub fn explain(&self, filter: impl Into<Option<Document>>, verbosity: ExplainVerbosity) -> DbResult<Explain> {
+        self.db.explain(&self.name, filter.into(), self.find_options(FindOptions::default()), verbosity, None)
+    }
+
+    /// Describe how the query is executed with the options.
+    pub fn explain_with_options(
+        &self,
+        filter: impl Into<Option<Document>>,
+        options: FindOptions,
+        verbosity: ExplainVerbosity,
+    ) -> DbResult<Explain> {
+        self.db.explain(&self.name, filter.into(), self.find_options(options), verbosity, None)
+    }
+
+    /// Delete the documents satisfying the query and return them,
+    /// in one transaction, so a document is never returned twice.
+    ///
+    /// It can be used to claim the jobs of a durable queue.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let jobs = db.collection::<Document>("jobs");
+    /// jobs.insert_many(vec![
+    ///     doc! { "_id": 1, "queue": "mail" },
+    ///     doc! { "_id": 2, "queue": "sms" },
+    ///     doc! { "_id": 3, "queue": "mail" },
+    /// ]).unwrap();
+    ///
+    /// let claimed = jobs.drain(doc! { "queue": "mail" }).unwrap();
+    /// assert_eq!(claimed.len(), 2);
+    /// assert_eq!(jobs.count_documents().unwrap(), 1);
+    /// ```
+    pub fn drain(&self, filter: impl Into<Option<Document>>) -> DbResult<Vec<T>> {
+        self.db.drain(&self.name, filter.into(), None)
+    }
+
+    /// Delete the documents satisfying the query and return them.
+    pub fn drain_with_session(&self, filter: impl Into<Option<Document>>, session: &mut ClientSession) -> DbResult<Vec<T>> {
+        self.db.drain(&self.name, filter.into(), Some(&session.id))
+    }
+}
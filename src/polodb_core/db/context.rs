@@ -4,39 +4,52 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::borrow::Borrow;
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroUsize};
 use std::sync::Arc;
-use bson::{Binary, Bson, DateTime, Document};
+use bson::{Binary, Bson, DateTime, Document, doc};
 use serde::Serialize;
 use super::db::DbResult;
-use crate::error::DbErr;
+use crate::error::{CollectionArchivedError, DbErr};
 use crate::TransactionType;
-use crate::Config;
+use crate::{Compression, Config, CreateCollectionOptions, IndexOptions, OperatorMode};
+use crate::clock::{self, Clock};
 use crate::vm::{SubProgram, VM, VmState};
 use crate::meta_doc_helper::meta_doc_key;
 // use crate::index_ctx::{IndexCtx, merge_options_into_default};
 use crate::btree::*;
+use crate::btree::index_helper::{self, IndexScan};
+use crate::btree::range_helper::{self, KeyRange};
 use crate::transaction::TransactionState;
 use crate::backend::memory::MemoryBackend;
 use crate::page::RawPage;
 use crate::db::db_handle::DbHandle;
-use crate::dump::{BTreePageDump, DataPageDump, FreeListPageDump, FullDump, OverflowDataPageDump, PageDump};
-use crate::page::header_page_wrapper::HeaderPageWrapper;
+use crate::dump::{BTreePageDump, CollectionSummary, DataPageDump, FreeListPageDump, FullDump, HeaderDump, OverflowDataPageDump, PageDump};
+use crate::page::header_page_wrapper::{HeaderPageWrapper, FEATURE_COMPAT_FOREIGN_KEYS, FEATURE_INCOMPAT_COMPRESSION, FEATURE_INCOMPAT_INDEXES, FREE_LIST_OFFSET};
 use crate::backend::Backend;
-use crate::results::{InsertManyResult, InsertOneResult};
+use crate::results::{CollectionStats, CompactResult, InsertManyResult, PinResult, InsertOneResult, UpdateResult, WriteError};
 use crate::session::{BaseSession, DynamicSession, Session};
-#[cfg(not(target_arch = "wasm32"))]
-use crate::backend::file::FileBackend;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "fs")]
+use crate::backend::file::{FileBackend, InspectBackend, RecoveryReport};
+#[cfg(feature = "fs")]
+use crate::backend::PageCache;
+use crate::backend::object_store::{ObjectStore, ObjectStoreBackend, PrefetchPlan};
+#[cfg(feature = "fs")]
 use std::path::Path;
-#[cfg(target_arch = "wasm32")]
+#[cfg(feature = "indexeddb")]
 use crate::backend::indexeddb::IndexedDbBackend;
+#[cfg(feature = "opfs")]
+use crate::backend::opfs::OpfsBackend;
 use bson::oid::ObjectId;
 use bson::spec::BinarySubtype;
-use crate::collection_info::{CollectionSpecification, CollectionSpecificationInfo, CollectionType};
+use crate::collection_info::{CollectionSpecification, CollectionSpecificationInfo, CollectionType, ForeignKey, OnDelete};
+use crate::aggregation;
 use crate::cursor::Cursor;
+use crate::db::kv::KV_COLLECTION_PREFIX;
+use crate::bson_utils::value_cmp;
 use crate::metrics::Metrics;
+use crate::projection::{Projection, ScanFields};
 
 macro_rules! try_multiple {
     ($err: expr, $action: expr) => {
@@ -65,16 +78,23 @@ macro_rules! try_db_op {
     }
 }
 
+// the idle sessions kept for the reads without a session of the user
+const SESSION_POOL_SIZE: usize = 8;
+
 /**
  * API for all platforms
  */
 pub(crate) struct DbContext {
     base_session: BaseSession,
-    session_map:  hashbrown::HashMap<ObjectId, Box<dyn Session + Send>>,
+    session_map:  hashbrown::HashMap<ObjectId, DynamicSession>,
+    session_pool: Vec<ObjectId>,
+    // the sessions of the pool, not counted by `max_sessions`
+    pool_sessions: hashbrown::HashSet<ObjectId>,
     node_id:      [u8; 6],
     metrics:      Metrics,
-    #[allow(dead_code)]
     config:       Arc<Config>,
+    // the collections of the pages pinned in the page cache
+    pinned_collections: Vec<String>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -84,26 +104,76 @@ pub struct MetaSource {
 
 impl DbContext {
 
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(feature = "fs")]
     pub fn open_file(path: &Path, config: Config) -> DbResult<DbContext> {
+        let (ctx, _) = DbContext::open_file_with_report(path, config)?;
+        Ok(ctx)
+    }
+
+    /// Open the file and return what is done with the journal left by the last process.
+    #[cfg(feature = "fs")]
+    pub fn open_file_with_report(path: &Path, config: Config) -> DbResult<(DbContext, RecoveryReport)> {
         let metrics = Metrics::new();
-        let page_size = NonZeroU32::new(4096).unwrap();
+        let page_size = config.page_size;
 
         let config = Arc::new(config);
-        let backend = Box::new(FileBackend::open(
+        let backend = FileBackend::open(
             path, page_size, config.clone(), metrics.clone(),
+        )?;
+        let report = backend.recovery_report().clone();
+        let ctx = DbContext::open_with_backend(Box::new(backend), page_size, config, metrics)?;
+        Ok((ctx, report))
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn open_file_with_page_cache(path: &Path, config: Config, page_cache: PageCache) -> DbResult<DbContext> {
+        let metrics = Metrics::new();
+        let page_size = page_cache.page_size();
+
+        let config = Arc::new(config);
+        let backend = Box::new(FileBackend::open_with_page_cache(
+            path, page_size, config.clone(), metrics.clone(), page_cache,
         )?);
         DbContext::open_with_backend(backend, page_size, config, metrics)
     }
 
-    #[cfg(target_arch = "wasm32")]
-    pub fn open_indexeddb(ctx: crate::IndexedDbContext, config: Config) -> DbResult<DbContext> {
+    pub fn open_object_store(store: Arc<dyn ObjectStore>, prefix: &str, config: Config) -> DbResult<DbContext> {
         let metrics = Metrics::new();
         let page_size = NonZeroU32::new(4096).unwrap();
+
         let config = Arc::new(config);
-        let backend = Box::new(IndexedDbBackend::open(
-            ctx, page_size, config.init_block_count
-        ));
+        let backend = Box::new(ObjectStoreBackend::open(
+            store, prefix, page_size, config.clone(), metrics.clone(),
+        )?);
+        DbContext::open_with_backend(backend, page_size, config, metrics)
+    }
+
+    #[cfg(feature = "indexeddb")]
+    pub fn open_indexeddb(
+        ctx: crate::IndexedDbContext,
+        config: Config,
+        loaded: impl FnOnce(DbResult<DbContext>) + 'static,
+    ) -> DbResult<()> {
+        let metrics = Metrics::new();
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let config = Arc::new(config);
+        let init_block_count = config.init_block_count;
+        let flush_interval = config.indexeddb_flush_interval;
+        let compaction = config.indexeddb_compaction.clone();
+        IndexedDbBackend::open(ctx, page_size, init_block_count, flush_interval, compaction, move |backend| {
+            let result = backend.and_then(|backend| {
+                DbContext::open_with_backend(Box::new(backend), page_size, config, metrics)
+            });
+            loaded(result);
+        })
+    }
+
+    #[cfg(feature = "opfs")]
+    pub fn open_opfs(ctx: crate::OpfsContext, config: Config) -> DbResult<DbContext> {
+        let metrics = Metrics::new();
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let config = Arc::new(config);
+        let backend = Box::new(OpfsBackend::open(ctx, page_size, config.clone(), metrics.clone())?);
         DbContext::open_with_backend(backend, page_size, config, metrics)
     }
 
@@ -115,6 +185,16 @@ impl DbContext {
         DbContext::open_with_backend(backend, page_size, config, metrics)
     }
 
+    /// Load the database file into memory, see [`MemoryBackend::load_from`].
+    #[cfg(feature = "fs")]
+    pub fn open_memory_from_file(path: &Path, config: Config) -> DbResult<DbContext> {
+        let metrics = Metrics::new();
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let config = Arc::new(config);
+        let backend = Box::new(MemoryBackend::load_from(path, page_size)?);
+        DbContext::open_with_backend(backend, page_size, config, metrics)
+    }
+
     fn open_with_backend(
         backend: Box<dyn Backend + Send>,
         page_size: NonZeroU32,
@@ -137,8 +217,11 @@ impl DbContext {
             // first_page,
             node_id,
             session_map,
+            session_pool: Vec::new(),
+            pool_sessions: hashbrown::HashSet::new(),
             metrics,
             config,
+            pinned_collections: Vec::new(),
         };
 
         Ok(ctx)
@@ -148,15 +231,223 @@ impl DbContext {
         self.metrics.clone()
     }
 
+    pub fn config(&self) -> Arc<Config> {
+        self.config.clone()
+    }
+
+    pub fn checkpoint(&mut self) -> DbResult<()> {
+        self.base_session.checkpoint()
+    }
+
+    pub fn prefetch_plan(&self) -> DbResult<Option<PrefetchPlan>> {
+        self.base_session.prefetch_plan(self.config.page_cache_size.get())
+    }
+
+    pub fn put_prefetched(&self, plan: &PrefetchPlan, pages: Vec<Arc<RawPage>>) -> DbResult<()> {
+        self.base_session.put_prefetched(plan, pages)
+    }
+
+    pub fn set_journal_full_size(&mut self, size: u64) -> DbResult<()> {
+        self.base_session.set_journal_full_size(size)?;
+        Arc::make_mut(&mut self.config).journal_full_size = size;
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn persist_to(&mut self, path: &Path) -> DbResult<()> {
+        self.base_session.persist_to(path)
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn page_size(&self) -> NonZeroU32 {
+        self.base_session.page_size()
+    }
+
+    /// Take a snapshot of the committed pages for a backup,
+    /// return the id of it and the count of the pages.
+    /// The snapshot is a session of the backend, which keeps the checkpoints
+    /// from overwriting the pages until [`DbContext::finish_backup`].
+    #[cfg(feature = "fs")]
+    pub fn start_backup(&mut self) -> DbResult<(ObjectId, u32)> {
+        let id = ObjectId::new();
+        self.base_session.new_session(&id)?;
+        match self.base_session.pipeline_read_page(0, Some(&id)) {
+            Ok(header_page) => {
+                let header = HeaderPageWrapper::from_raw_page(header_page.as_ref().clone());
+                Ok((id, header.get_null_page_bar()))
+            }
+            Err(err) => {
+                self.base_session.remove_session(&id)?;
+                Err(err)
+            }
+        }
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn read_backup_pages(&mut self, id: &ObjectId, page_ids: std::ops::Range<u32>) -> DbResult<Vec<Arc<RawPage>>> {
+        page_ids
+            .map(|page_id| self.base_session.pipeline_read_page(page_id, Some(id)))
+            .collect()
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn finish_backup(&mut self, id: &ObjectId) -> DbResult<()> {
+        self.base_session.remove_session(id)
+    }
+
+    /// Rebuild the collections and their indexes into a new image in memory,
+    /// then write its pages over the database by one transaction and drop the pages
+    /// beyond them. The pages are written through the journal, so the database is
+    /// either the old one or the compacted one after a crash.
+    pub fn compact(&mut self) -> DbResult<CompactResult> {
+        // the pages are rewritten, no session can read the old ones
+        for id in std::mem::take(&mut self.session_pool) {
+            self.drop_session(&id)?;
+        }
+        if !self.session_map.is_empty() || !self.base_session.transaction_state().is_no_trans() {
+            return Err(DbErr::Busy);
+        }
+        let size_before = self.base_session.db_size();
+
+        // the documents are read in one transaction
+        let mut image = DbContext::open_memory(self.config.as_ref().clone())?;
+        self.start_transaction(Some(TransactionType::Read), None)?;
+        image.start_transaction(Some(TransactionType::Write), None)?;
+        let copy_result = DbContext::copy_collections(
+            &self.base_session, &image.base_session, &self.node_id, self.config.as_ref(),
+        ).and_then(|()| image.commit(None));
+        match copy_result {
+            Ok(()) => self.commit(None)?,
+            Err(err) => {
+                try_multiple!(err, self.rollback(None));
+                return Err(err);
+            }
+        }
+
+        self.start_transaction(Some(TransactionType::Write), None)?;
+        let size_after = match DbContext::write_compacted_pages(&self.base_session, &image.base_session) {
+            Ok(size) => {
+                self.commit(None)?;
+                size
+            }
+            Err(err) => {
+                try_multiple!(err, self.rollback(None));
+                return Err(err);
+            }
+        };
+
+        // the file is truncated by the checkpoint, or the next one if a backup is reading it
+        match self.base_session.checkpoint() {
+            Ok(()) | Err(DbErr::Busy) => (),
+            Err(err) => return Err(err),
+        }
+        self.refresh_pins()?;
+
+        Ok(CompactResult {
+            size_before,
+            size_after,
+        })
+    }
+
+    fn copy_collections(from: &dyn Session, to: &dyn Session, node_id: &[u8; 6], config: &Config) -> DbResult<()> {
+        let metas = DbContext::query_all_meta_internal(from)?;
+        for meta in metas {
+            let col_spec: CollectionSpecification = bson::from_document(meta)?;
+            DbContext::copy_collection(from, to, &col_spec, node_id, config)?;
+        }
+        Ok(())
+    }
+
+    fn copy_collection(
+        from: &dyn Session,
+        to: &dyn Session,
+        col_spec: &CollectionSpecification,
+        node_id: &[u8; 6],
+        config: &Config,
+    ) -> DbResult<()> {
+        let options = CreateCollectionOptions {
+            compression: col_spec.info.compression,
+            version_field: col_spec.info.version_field.clone(),
+            append_only: col_spec.info.append_only,
+            foreign_keys: col_spec.info.foreign_keys.clone(),
+            generated_fields: col_spec.info.generated_fields.clone(),
+        };
+        let mut new_spec = DbContext::internal_create_collection(
+            to, col_spec.name(), &options, node_id, config.clock(), None,
+        )?;
+        new_spec.info.uuid = col_spec.info.uuid.clone();
+        new_spec.info.create_at = col_spec.info.create_at;
+        new_spec.info.archived_in = col_spec.info.archived_in.clone();
+
+        // the documents of a stub are in the attached database
+        if new_spec.info.archived_in.is_some() {
+            return DbContext::update_collection_spec(to, &new_spec);
+        }
+
+        let mut handle = DbContext::find_internal(from, col_spec, None, None, config.operator_mode)?;
+        handle.step()?;
+        while handle.has_row() {
+            let doc = handle.get().as_document().unwrap().clone();
+            let (_, spec) = DbContext::insert_one_with_meta(to, new_spec, doc, config.clock())?;
+            new_spec = spec;
+            handle.step()?;
+        }
+        handle.commit_and_close_vm()?;
+        DbContext::update_collection_spec(to, &new_spec)?;
+
+        for (name, index) in &col_spec.indexes {
+            let options = IndexOptions {
+                name: Some(name.clone()),
+                unique: Some(index.unique),
+            };
+            DbContext::internal_create_index(to, col_spec.name(), &index.key, &options)?;
+        }
+        Ok(())
+    }
+
+    /// Write the pages of the image, return the new size of the database.
+    /// The header page keeps the features and the metadata of the database.
+    fn write_compacted_pages(session: &BaseSession, image: &BaseSession) -> DbResult<u64> {
+        let image_header = HeaderPageWrapper::from_raw_page(image.read_page(0)?.as_ref().clone());
+        let mut header = HeaderPageWrapper::from_raw_page(session.read_page(0)?.as_ref().clone());
+        header.set_null_page_bar(image_header.get_null_page_bar());
+        header.set_meta_page_id(image_header.get_meta_page_id());
+        header.set_data_allocator(image_header.get_data_allocator());
+        header.set_compat_features(header.get_compat_features() | image_header.get_compat_features());
+        header.set_incompat_features(header.get_incompat_features() | image_header.get_incompat_features());
+        let free_list = FREE_LIST_OFFSET as usize;
+        header.0.data[free_list..].copy_from_slice(&image_header.0.data[free_list..]);
+        session.write_page(&header.0)?;
+
+        let page_count = image_header.get_null_page_bar();
+        for page_id in 1..page_count {
+            let page = image.read_page(page_id)?;
+            session.write_page(page.as_ref())?;
+        }
+
+        let db_size = (page_count as u64) * (session.page_size().get() as u64);
+        session.shrink_db_size(db_size)?;
+        Ok(db_size)
+    }
+
     pub fn start_session(&mut self) -> DbResult<ObjectId> {
+        if let Some(max_sessions) = self.config.max_sessions {
+            if self.session_map.len() - self.pool_sessions.len() >= max_sessions.get() {
+                return Err(DbErr::TooManySessions(max_sessions.get()));
+            }
+        }
+        self.new_session()
+    }
+
+    fn new_session(&mut self) -> DbResult<ObjectId> {
         let id = ObjectId::new();
 
         let base_session = self.base_session.clone();
-        let session = Box::new(DynamicSession::new(
+        let session = DynamicSession::new(
             id.clone(),
             base_session,
             self.metrics.clone_with_sid(id.clone()),
-        ));
+        );
         let insert_result = self.session_map.insert(id, session);
         if insert_result.is_none() {
             self.base_session.new_session(&id)?;
@@ -165,9 +456,56 @@ impl DbContext {
         Ok(id)
     }
 
+    /// Take a session from the pool for the reads without a session of the user,
+    /// or start a new one if the pool is empty. The session reads the latest version.
+    pub fn acquire_session(&mut self) -> DbResult<ObjectId> {
+        let id = match self.session_pool.pop() {
+            Some(id) => id,
+            None => return self.new_pool_session(),
+        };
+        let renew_result = match self.session_map.get(&id) {
+            Some(session) => session.renew(),
+            None => return self.new_pool_session(),
+        };
+        if let Err(err) = renew_result {
+            let _ = self.drop_session(&id);
+            return Err(err);
+        }
+        Ok(id)
+    }
+
+    fn new_pool_session(&mut self) -> DbResult<ObjectId> {
+        let id = self.new_session()?;
+        self.pool_sessions.insert(id);
+        Ok(id)
+    }
+
+    /// Return the session taken by [`DbContext::acquire_session`],
+    /// it's dropped if the pool is full.
+    pub fn release_session(&mut self, id: ObjectId) -> DbResult<()> {
+        if self.session_pool.len() >= SESSION_POOL_SIZE {
+            return self.drop_session(&id);
+        }
+        let session = match self.session_map.get(&id) {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+        session.park()?;
+        self.session_pool.push(id);
+        Ok(())
+    }
+
     fn internal_get_collection_id_by_name(session: &dyn Session, name: &str) -> DbResult<CollectionSpecification> {
         let meta_source = DbContext::get_meta_source(session)?;
-        DbContext::internal_get_collection_id_by_name_with_pid(session, meta_source.meta_pid, name)
+        let col_spec = DbContext::internal_get_collection_id_by_name_with_pid(session, meta_source.meta_pid, name)?;
+        // the documents of a stub are in the attached database
+        if let Some(alias) = &col_spec.info.archived_in {
+            return Err(DbErr::CollectionArchived(Box::new(CollectionArchivedError {
+                collection: name.to_string(),
+                alias: alias.clone(),
+            })));
+        }
+        Ok(col_spec)
     }
 
     fn internal_get_collection_id_by_name_with_pid(session: &dyn Session, root_pid: u32, name: &str) -> DbResult<CollectionSpecification> {
@@ -205,18 +543,28 @@ impl DbContext {
 
         let result = try_db_op!(
             session,
-            DbContext::get_collection_meta_by_name_advanced(session, name, create_if_not_exist, &self.node_id)
+            DbContext::get_collection_meta_by_name_advanced(
+                session, name, create_if_not_exist, &self.node_id, self.config.clock(), self.config.max_collections,
+            )
         );
 
         Ok(result)
     }
 
-    pub fn get_collection_meta_by_name_advanced(session: &dyn Session, name: &str, create_if_not_exist: bool, node_id: &[u8; 6]) -> DbResult<Option<CollectionSpecification>> {
+    pub fn get_collection_meta_by_name_advanced(
+        session: &dyn Session,
+        name: &str,
+        create_if_not_exist: bool,
+        node_id: &[u8; 6],
+        clock: &dyn Clock,
+        max_collections: Option<NonZeroUsize>,
+    ) -> DbResult<Option<CollectionSpecification>> {
         match DbContext::internal_get_collection_id_by_name(session, name) {
             Ok(meta) => Ok(Some(meta)),
             Err(DbErr::CollectionNotFound(_)) => {
                 if create_if_not_exist {
-                    let meta = DbContext::internal_create_collection(session, name, node_id)?;
+                    let options = CreateCollectionOptions::default();
+                    let meta = DbContext::internal_create_collection(session, name, &options, node_id, clock, max_collections)?;
                     Ok(Some(meta))
                 } else {
                     Ok(None)
@@ -254,8 +602,8 @@ impl DbContext {
     fn get_session_by_id(&self, session_id: Option<&ObjectId>) -> DbResult<&dyn Session> {
         match session_id {
             Some(session_id) => {
-                let session = match self.session_map.get(session_id) {
-                    Some(session) => session.as_ref(),
+                let session: &dyn Session = match self.session_map.get(session_id) {
+                    Some(session) => session,
                     None => {
                         let err = DbErr::InvalidSession(Box::new(session_id.clone()));
                         return Err(err);
@@ -268,11 +616,13 @@ impl DbContext {
         }
     }
 
-    pub fn create_collection(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<CollectionSpecification> {
+    pub fn create_collection(&mut self, name: &str, options: &CreateCollectionOptions, session_id: Option<&ObjectId>) -> DbResult<CollectionSpecification> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let meta = try_db_op!(session, DbContext::internal_create_collection(session, name, &self.node_id));
+        let meta = try_db_op!(session, DbContext::internal_create_collection(
+            session, name, options, &self.node_id, self.config.clock(), self.config.max_collections,
+        ));
 
         Ok(meta)
     }
@@ -286,7 +636,25 @@ impl DbContext {
         }
     }
 
-    fn internal_create_collection(session: &dyn Session, name: &str, node_id: &[u8; 6]) -> DbResult<CollectionSpecification> {
+    fn check_collection_limit(session: &dyn Session, max_collections: NonZeroUsize) -> DbResult<()> {
+        let count = DbContext::query_all_meta_internal(session)?
+            .iter()
+            .filter(|doc| !doc.get_str("_id").unwrap_or_default().starts_with(KV_COLLECTION_PREFIX))
+            .count();
+        if count >= max_collections.get() {
+            return Err(DbErr::TooManyCollections(max_collections.get()));
+        }
+        Ok(())
+    }
+
+    fn internal_create_collection(
+        session: &dyn Session,
+        name: &str,
+        options: &CreateCollectionOptions,
+        node_id: &[u8; 6],
+        clock: &dyn Clock,
+        max_collections: Option<NonZeroUsize>,
+    ) -> DbResult<CollectionSpecification> {
         if name.is_empty() {
             return Err(DbErr::IllegalCollectionName(name.into()));
         }
@@ -294,10 +662,34 @@ impl DbContext {
         if exist {
             return Err(DbErr::CollectionAlreadyExits(name.into()));
         }
+        for foreign_key in &options.foreign_keys {
+            if foreign_key.field.is_empty() || foreign_key.references.is_empty() {
+                return Err(DbErr::ValidationError("the field and the collection of a foreign key can't be empty".into()));
+            }
+        }
+        for generated in &options.generated_fields {
+            let field = generated.field.as_str();
+            if field.is_empty() || field == "_id" || field.starts_with('$') || field.contains('.') {
+                return Err(DbErr::ValidationError(format!("\"{}\" can't be a generated field", field)));
+            }
+        }
+        // the namespaces of the key-value API are not collections
+        if let Some(max_collections) = max_collections {
+            if !name.starts_with(KV_COLLECTION_PREFIX) {
+                DbContext::check_collection_limit(session, max_collections)?;
+            }
+        }
 
         let mut meta_source = DbContext::get_meta_source(session)?;
         let root_pid = session.alloc_page_id()?;
 
+        if options.compression != Compression::None {
+            DbContext::enable_incompat_feature(session, FEATURE_INCOMPAT_COMPRESSION)?;
+        }
+        if !options.foreign_keys.is_empty() {
+            DbContext::enable_compat_feature(session, FEATURE_COMPAT_FOREIGN_KEYS)?;
+        }
+
         let uuid = uuid::Uuid::now_v1(node_id);
 
         let spec = CollectionSpecification {
@@ -309,9 +701,25 @@ impl DbContext {
                     bytes: uuid.as_bytes().to_vec(),
                 }),
 
-                create_at: DateTime::now(),
+                create_at: clock::now_datetime(clock),
 
                 root_pid,
+
+                compression: options.compression,
+
+                version_field: options.version_field.clone(),
+
+                append_only: options.append_only,
+
+                foreign_keys: options.foreign_keys.clone(),
+
+                generated_fields: options.generated_fields.clone(),
+
+                count: Some(0),
+
+                data_size: Some(0),
+
+                archived_in: None,
             },
             indexes: HashMap::new(),
         };
@@ -344,6 +752,36 @@ impl DbContext {
         Ok(spec)
     }
 
+    /// Mark the feature in the header page, the versions which don't know it can't open the database.
+    fn enable_incompat_feature(session: &dyn Session, feature: u64) -> DbResult<()> {
+        let head_page = session.read_page(0)?;
+        let mut head_page_wrapper = HeaderPageWrapper::from_raw_page(head_page.as_ref().clone());
+        let features = head_page_wrapper.get_incompat_features();
+        if features & feature != 0 {
+            return Ok(());
+        }
+        head_page_wrapper.set_incompat_features(features | feature);
+        session.write_page(&head_page_wrapper.0)
+    }
+
+    /// Mark the feature in the header page, the versions which don't know it can ignore it.
+    fn enable_compat_feature(session: &dyn Session, feature: u64) -> DbResult<()> {
+        let head_page = session.read_page(0)?;
+        let mut head_page_wrapper = HeaderPageWrapper::from_raw_page(head_page.as_ref().clone());
+        let features = head_page_wrapper.get_compat_features();
+        if features & feature != 0 {
+            return Ok(());
+        }
+        head_page_wrapper.set_compat_features(features | feature);
+        session.write_page(&head_page_wrapper.0)
+    }
+
+    fn has_compat_feature(session: &dyn Session, feature: u64) -> DbResult<bool> {
+        let head_page = session.read_page(0)?;
+        let head_page_wrapper = HeaderPageWrapper::from_raw_page(head_page.as_ref().clone());
+        Ok(head_page_wrapper.get_compat_features() & feature != 0)
+    }
+
     fn update_meta_source(session: &dyn Session, meta_source: &MetaSource) -> DbResult<()> {
         let head_page = session.read_page(0)?;
         let mut head_page_wrapper = HeaderPageWrapper::from_raw_page(head_page.as_ref().clone());
@@ -356,141 +794,177 @@ impl DbContext {
         DbHandle::new(vm)
     }
 
-    pub fn create_index(&mut self, col_id: u32, keys: &Document, options: Option<&Document>, session_id: Option<&ObjectId>) -> DbResult<()> {
+    pub fn create_index(&mut self, col_name: &str, keys: &Document, options: &IndexOptions, session_id: Option<&ObjectId>) -> DbResult<String> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        let result = try_db_op!(session, DbContext::internal_create_index(session, col_name, keys, options));
+
+        Ok(result)
+    }
+
+    fn internal_create_index(session: &dyn Session, col_name: &str, keys: &Document, options: &IndexOptions) -> DbResult<String> {
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, col_name)?;
+        let name = index_helper::create_index(session, &mut col_spec, keys, options)?;
+        DbContext::update_collection_spec(session, &col_spec)?;
+        DbContext::enable_incompat_feature(session, FEATURE_INCOMPAT_INDEXES)?;
+        Ok(name)
+    }
+
+    pub fn drop_index(&mut self, col_name: &str, name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
         let session = self.get_session_by_id(session_id)?;
-        session .auto_start_transaction(TransactionType::Write)?;
+        session.auto_start_transaction(TransactionType::Write)?;
 
-        try_db_op!(session, DbContext::internal_create_index(session, col_id, keys, options));
+        try_db_op!(session, DbContext::internal_drop_index(session, col_name, name));
 
         Ok(())
     }
 
-    fn internal_create_index(_session: &dyn Session, _col_id: u32, _keys: &Document, _options: Option<&Document>) -> DbResult<()> {
-        unimplemented!()
-        // let meta_source = DbContext::get_meta_source(session)?;
-        // let mut meta_doc = DbContext::find_collection_root_pid_by_id(
-        //     session, 0, meta_source.meta_pid, col_id
-        // )?;
-        //
-        // for (key_name, value_of_key) in keys.iter() {
-        //     if let Bson::Int32(1) = value_of_key {
-        //         // nothing
-        //     } else if let Bson::Int64(1) = value_of_key {
-        //         // nothing
-        //     } else {
-        //         return Err(DbErr::InvalidOrderOfIndex(key_name.clone()));
-        //     }
-        //
-        //     match meta_doc.doc_ref().get(meta_doc_key::INDEXES) {
-        //         Some(indexes_obj) => match indexes_obj {
-        //             Bson::Document(index_doc) => {
-        //                 if index_already_exists(index_doc.borrow(), key_name) {
-        //                     return Err(DbErr::IndexAlreadyExists(key_name.clone()));
-        //                 }
-        //
-        //                 unimplemented!()
-        //             }
-        //
-        //             _ => {
-        //                 panic!("unexpected: indexes object is not a Document");
-        //             }
-        //
-        //         },
-        //
-        //         None => {
-        //             // create indexes
-        //             let mut doc = doc!();
-        //
-        //             let root_pid = session.alloc_page_id()?;
-        //             let options_doc = merge_options_into_default(root_pid, options)?;
-        //             doc.insert(key_name.clone(), Bson::Document(options_doc));
-        //
-        //             meta_doc.set_indexes(doc);
-        //         }
-        //
-        //     }
-        // }
-        //
-        // let key_col = Bson::from(col_id);
-        //
-        // let meta_source = DbContext::get_meta_source(session)?;
-        // let inserted = DbContext::update_by_root_pid(
-        //     session, 0, meta_source.meta_pid,
-        //     &key_col, meta_doc.doc_ref()
-        // )?;
-        // if !inserted {
-        //     panic!("update failed");
-        // }
-        //
-        // Ok(())
+    fn internal_drop_index(session: &dyn Session, col_name: &str, name: &str) -> DbResult<()> {
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, col_name)?;
+        index_helper::drop_index(session, &mut col_spec, name)?;
+        DbContext::update_collection_spec(session, &col_spec)
     }
 
     #[inline]
-    fn fix_doc(mut doc: Document) -> Document {
+    fn fix_doc(mut doc: Document, clock: &dyn Clock) -> Document {
         if doc.get(meta_doc_key::ID).is_some() {
             return doc;
         }
 
-        let new_oid = ObjectId::new();
+        let new_oid = clock::new_object_id(clock);
         doc.insert::<String, Bson>(meta_doc_key::ID.into(), new_oid.into());
         doc
     }
 
+    /// The document appended to an append-only collection should have the greatest key,
+    /// the generated `_id` is moved after the last one if the clock goes back.
+    fn fix_appended_doc(mut doc: Document, last_key: Option<Bson>, clock: &dyn Clock) -> DbResult<Document> {
+        let last_key = match last_key {
+            Some(last_key) => last_key,
+            None => return Ok(DbContext::fix_doc(doc, clock)),
+        };
+
+        if let Some(pkey) = doc.get(meta_doc_key::ID) {
+            return match value_cmp(pkey, &last_key) {
+                Ok(Ordering::Greater) => Ok(doc),
+                _ => Err(DbErr::AppendOutOfOrder(pkey.to_string())),
+            };
+        }
+
+        let last_oid = match &last_key {
+            Bson::ObjectId(oid) => oid,
+            _ => return Err(DbErr::AppendOutOfOrder("<generated ObjectId>".into())),
+        };
+        let mut new_oid = clock::new_object_id(clock);
+        if new_oid <= *last_oid {
+            new_oid = clock::next_object_id(last_oid)
+                .ok_or_else(|| DbErr::AppendOutOfOrder("<generated ObjectId>".into()))?;
+        }
+        doc.insert::<String, Bson>(meta_doc_key::ID.into(), new_oid.into());
+        Ok(doc)
+    }
+
     pub fn insert_one_auto(&mut self, col_name: &str, doc: Document, session_id: Option<&ObjectId>) -> DbResult<InsertOneResult> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let changed = try_db_op!(session, DbContext::insert_one(session, col_name, doc, &self.node_id));
+        let changed = try_db_op!(session, DbContext::insert_one(
+            session, col_name, doc, &self.node_id, self.config.clock(), self.config.max_collections,
+        ));
 
         Ok(changed)
     }
 
-    fn insert_one(session: &dyn Session, col_name: &str, doc: Document, node_id: &[u8; 6]) -> DbResult<InsertOneResult> {
-        let col_meta = DbContext::get_collection_meta_by_name_advanced(session, col_name, true, node_id)?
+    fn insert_one(
+        session: &dyn Session,
+        col_name: &str,
+        doc: Document,
+        node_id: &[u8; 6],
+        clock: &dyn Clock,
+        max_collections: Option<NonZeroUsize>,
+    ) -> DbResult<InsertOneResult> {
+        let mut col_spec = DbContext::get_collection_meta_by_name_advanced(session, col_name, true, node_id, clock, max_collections)?
             .expect("internal: meta must exist");
-        let (result, _) = DbContext::insert_one_with_meta(session, col_meta, doc)?;
+        DbContext::ensure_stats(session, &mut col_spec)?;
+        DbContext::check_references(session, &col_spec, &doc, None)?;
+        let (result, col_spec) = DbContext::insert_one_with_meta(session, col_spec, doc, clock)?;
+        DbContext::update_collection_spec(session, &col_spec)?;
+        Ok(result)
+    }
+
+    /// Insert the document and update it in one transaction, return its `_id`.
+    pub fn upsert(&mut self, col_name: &str, doc: Document, update: &Document, session_id: Option<&ObjectId>) -> DbResult<Bson> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        let result = try_db_op!(session, DbContext::internal_upsert(
+            session, col_name, doc, update, &self.node_id, self.config.operator_mode, self.config.clock(), self.config.max_collections,
+        ));
+
         Ok(result)
     }
 
+    fn internal_upsert(
+        session: &dyn Session,
+        col_name: &str,
+        doc: Document,
+        update: &Document,
+        node_id: &[u8; 6],
+        operator_mode: OperatorMode,
+        clock: &dyn Clock,
+        max_collections: Option<NonZeroUsize>,
+    ) -> DbResult<Bson> {
+        let mut col_spec = DbContext::get_collection_meta_by_name_advanced(session, col_name, true, node_id, clock, max_collections)?
+            .expect("internal: meta must exist");
+        DbContext::check_mutable(&col_spec)?;
+        DbContext::ensure_stats(session, &mut col_spec)?;
+        DbContext::check_references(session, &col_spec, &doc, None)?;
+        let (result, col_spec) = DbContext::insert_one_with_meta(session, col_spec, doc, clock)?;
+        DbContext::update_collection_spec(session, &col_spec)?;
+
+        let query = doc! { "_id": result.inserted_id.clone() };
+        let now = clock::now_datetime(clock);
+        DbContext::internal_update(session, &col_spec, Some(&query), update, false, operator_mode, now)?;
+
+        Ok(result.inserted_id)
+    }
+
     /// Insert one item with the collection spec
-    /// return the new spec for the outside to do the following operation
-    fn insert_one_with_meta(session: &dyn Session, mut col_spec: CollectionSpecification, doc: Document) -> DbResult<(InsertOneResult, CollectionSpecification)> {
-        let col_id = col_spec.name().to_string();
+    /// return the new spec for the outside to do the following operation,
+    /// the outside should write the new spec to the meta
+    fn insert_one_with_meta(
+        session: &dyn Session,
+        mut col_spec: CollectionSpecification,
+        doc: Document,
+        clock: &dyn Clock,
+    ) -> DbResult<(InsertOneResult, CollectionSpecification)> {
+        let mut insert_wrapper = BTreePageInsertWrapper::new_with_compression(
+            session,
+            col_spec.info.root_pid,
+            col_spec.info.compression,
+        );
 
-        let meta_source = DbContext::get_meta_source(session)?;
-        let doc  = DbContext::fix_doc(doc);
+        let mut doc = if col_spec.info.append_only {
+            insert_wrapper.set_packed(true);
+            let last_key = insert_wrapper.last_key()?;
+            DbContext::fix_appended_doc(doc, last_key, clock)?
+        } else {
+            DbContext::fix_doc(doc, clock)
+        };
+        if !col_spec.info.generated_fields.is_empty() {
+            aggregation::set_generated_fields(&mut doc, &col_spec.info.generated_fields)?;
+        }
 
         let pkey = doc.get("_id").unwrap();
 
         // let mut is_pkey_check_skipped = false;
         // collection_meta.check_pkey_ty(&pkey, &mut is_pkey_check_skipped)?;
 
-        let mut is_meta_changed = false;
-
-        // // insert index begin
-        // let mut index_ctx_opt = IndexCtx::from_meta_doc(col_spec);
-        // if let Some(index_ctx) = &mut index_ctx_opt {
-        //     let mut is_ctx_changed = false;
-        //
-        //     index_ctx.insert_index_by_content(
-        //         &doc,
-        //         &pkey,
-        //         &mut is_ctx_changed,
-        //         session,
-        //     )?;
-        //
-        //     if is_ctx_changed {
-        //         index_ctx.merge_to_meta_doc(&mut collection_meta);
-        //         is_meta_changed = true;
-        //     }
-        // }
-        // // insert index end
+        index_helper::check_unique(session, &col_spec, &doc)?;
 
-        let mut insert_wrapper = BTreePageInsertWrapper::new(
-            session,
-            col_spec.info.root_pid,
-        );
         let insert_result: InsertResult = insert_wrapper.insert_item(&doc, false)?;
+        let stored_size = insert_wrapper.stored_size();
 
         if let Some(backward_item) = &insert_result.backward_item {
             let root_pid = col_spec.info.root_pid;
@@ -498,30 +972,16 @@ impl DbContext {
                 session, &mut col_spec,
                 root_pid, backward_item
             )?;
-            is_meta_changed = true;
         }
 
         // // insert successfully
         // if is_pkey_check_skipped {
         //     collection_meta.merge_pkey_ty_to_meta(&doc);
-        //     is_meta_changed = true;
         // }
 
-        // update meta begin
-        if is_meta_changed {
-            let key = Bson::from(col_id);
-            let doc = bson::to_document(&col_spec)?;
-            let updated= DbContext::update_by_root_pid(
-                session,
-                meta_source.meta_pid,
-                &key,
-                &doc,
-            )?;
-            if !updated {
-                panic!("unexpected: update meta page failed")
-            }
-        }
-        // update meta end
+        index_helper::insert_entries(session, &mut col_spec, &doc)?;
+
+        col_spec.info.add_stats(1, stored_size as i64);
 
         Ok((
             InsertOneResult { inserted_id: pkey.clone() },
@@ -533,98 +993,318 @@ impl DbContext {
         &mut self,
         col_name: &str,
         docs: impl IntoIterator<Item = impl Borrow<T>>,
+        ordered: bool,
         session_id: Option<&ObjectId>
     ) -> DbResult<InsertManyResult> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let result = try_db_op!(session, DbContext::insert_many(session, col_name, docs, &self.node_id));
+        let result = try_db_op!(session, DbContext::insert_many(
+            session, col_name, docs, ordered, &self.node_id, self.config.clock(), self.config.max_collections,
+        ));
 
         Ok(result)
     }
 
+    // The documents failed to be serialized or with an existing `_id`
+    // are skipped if it's not ordered, nothing is written for them.
     fn insert_many<T: Serialize>(
         session: &dyn Session,
         col_name: &str,
         docs: impl IntoIterator<Item = impl Borrow<T>>,
+        ordered: bool,
         node_id: &[u8; 6],
+        clock: &dyn Clock,
+        max_collections: Option<NonZeroUsize>,
     ) -> DbResult<InsertManyResult> {
-        let mut col_spec = DbContext::get_collection_meta_by_name_advanced(session, col_name, true, node_id)?
+        let mut col_spec = DbContext::get_collection_meta_by_name_advanced(session, col_name, true, node_id, clock, max_collections)?
             .expect("internal: meta must exist");
+        DbContext::ensure_stats(session, &mut col_spec)?;
         let mut inserted_ids: HashMap<usize, Bson> = HashMap::new();
-        let mut counter: usize = 0;
-
-        for item in docs {
-            let doc = bson::to_document(item.borrow())?;
-            let (insert_one_result, new_col_spec) = DbContext::insert_one_with_meta(session, col_spec, doc)?;
-            inserted_ids.insert(counter, insert_one_result.inserted_id);
+        let mut write_errors: Vec<WriteError> = vec![];
+
+        for (index, item) in docs.into_iter().enumerate() {
+            let doc = match bson::to_document(item.borrow()) {
+                Ok(doc) => doc,
+                Err(err) if !ordered => {
+                    write_errors.push(WriteError {
+                        index,
+                        message: DbErr::from(err).to_string(),
+                    });
+                    continue;
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let (insert_one_result, new_col_spec) = if ordered {
+                DbContext::check_references(session, &col_spec, &doc, None)?;
+                DbContext::insert_one_with_meta(session, col_spec, doc, clock)?
+            } else {
+                let result = DbContext::check_references(session, &col_spec, &doc, None)
+                    .and_then(|_| DbContext::insert_one_with_meta(session, col_spec.clone(), doc, clock));
+                match result {
+                    Ok(result) => result,
+                    Err(err @ DbErr::DataExist(_))
+                    | Err(err @ DbErr::DuplicateKey(_))
+                    | Err(err @ DbErr::AppendOutOfOrder(_))
+                    | Err(err @ DbErr::ForeignKeyViolation(_)) => {
+                        write_errors.push(WriteError {
+                            index,
+                            message: err.to_string(),
+                        });
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                }
+            };
+            inserted_ids.insert(index, insert_one_result.inserted_id);
 
-            counter += 1;
             col_spec = new_col_spec;
         }
 
+        DbContext::update_collection_spec(session, &col_spec)?;
+
         Ok(InsertManyResult {
             inserted_ids,
+            write_errors,
         })
     }
 
     /// query: None for findAll
-    pub fn find(&mut self, col_spec: &CollectionSpecification, query: Option<Document>, session_id: Option<&ObjectId>) -> DbResult<DbHandle> {
+    pub fn find(
+        &mut self,
+        col_spec: &CollectionSpecification,
+        query: Option<Document>,
+        projection: Option<&Projection>,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<DbHandle> {
+        let session = self.get_session_by_id(session_id)?;
+        let scan_fields = projection.and_then(Projection::scan_fields);
+        DbContext::find_internal(session, col_spec, query, scan_fields, self.config.operator_mode)
+    }
+
+    /// Find the documents in the order of the primary keys, the first `skip` ones
+    /// are skipped by the VM, and the scan ends after `limit` documents are returned.
+    pub fn find_window(
+        &mut self,
+        col_spec: &CollectionSpecification,
+        query: Option<Document>,
+        projection: Option<&Projection>,
+        skip: u64,
+        limit: Option<u64>,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<DbHandle> {
         let session = self.get_session_by_id(session_id)?;
-        DbContext::find_internal(session, col_spec, query)
+        let scan_fields = projection.and_then(Projection::scan_fields);
+        let scan_keys = DbContext::index_scan_keys(session, col_spec, query.as_ref())?;
+        let mut subprogram = DbContext::compile_find(col_spec, query, scan_fields, self.config.operator_mode)?;
+        if let Some(keys) = scan_keys {
+            subprogram.set_scan_keys(keys);
+        }
+        subprogram.set_result_window(skip, limit);
+        Ok(DbContext::make_handle(session, subprogram))
     }
 
-    fn find_internal<'a, 'b>(session: &'a dyn Session, col_spec: &'b CollectionSpecification, query: Option<Document>) -> DbResult<DbHandle<'a>> {
+    /// Find from the key `resume_from`, the scan is suspended after
+    /// `scan_limit` documents are examined, see `DbHandle::resume_key`.
+    pub fn find_slice(
+        &mut self,
+        col_spec: &CollectionSpecification,
+        query: Option<Document>,
+        projection: Option<&Projection>,
+        resume_from: Option<Bson>,
+        scan_limit: u64,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<DbHandle> {
+        let session = self.get_session_by_id(session_id)?;
+        let scan_fields = projection.and_then(Projection::scan_fields);
+        let scan_keys = DbContext::index_scan_keys(session, col_spec, query.as_ref())?;
+        let mut subprogram = DbContext::compile_find(col_spec, query, scan_fields, self.config.operator_mode)?;
+        if let Some(mut keys) = scan_keys {
+            // the keys before the resumed one are examined by the previous slices
+            if let Some(resume_from) = &resume_from {
+                keys.retain(|key| !matches!(value_cmp(key, resume_from), Ok(Ordering::Less)));
+            }
+            subprogram.set_scan_keys(keys);
+        }
+        subprogram.set_scan_slice(resume_from, scan_limit);
+        Ok(DbContext::make_handle(session, subprogram))
+    }
+
+    fn find_internal<'a, 'b>(
+        session: &'a dyn Session,
+        col_spec: &'b CollectionSpecification,
+        query: Option<Document>,
+        scan_fields: Option<ScanFields>,
+        operator_mode: OperatorMode,
+    ) -> DbResult<DbHandle<'a>> {
         // let meta_source = DbContext::get_meta_source(session)?;
         // let collection_meta = DbContext::find_collection_root_pid_by_id(
         //     session, 0,
         //     meta_source.meta_pid, col_id
         // )?;
 
-        let subprogram = match query {
+        let scan_keys = DbContext::index_scan_keys(session, col_spec, query.as_ref())?;
+        let mut subprogram = DbContext::compile_find(col_spec, query, scan_fields, operator_mode)?;
+        if let Some(keys) = scan_keys {
+            subprogram.set_scan_keys(keys);
+        }
+        let handle = DbContext::make_handle(session, subprogram);
+        Ok(handle)
+    }
+
+    /// The sorted primary keys of the documents possibly satisfying the query,
+    /// found by an index of the collection. `None` if no index helps.
+    fn index_scan_keys(session: &dyn Session, col_spec: &CollectionSpecification, query: Option<&Document>) -> DbResult<Option<Vec<Bson>>> {
+        let scan = match query.and_then(|query| IndexScan::plan(col_spec, query)) {
+            Some(scan) => scan,
+            None => return Ok(None),
+        };
+        let mut keys = scan.collect_keys(session)?;
+        range_helper::sort_keys(&mut keys)?;
+        Ok(Some(keys))
+    }
+
+    fn compile_find(
+        col_spec: &CollectionSpecification,
+        query: Option<Document>,
+        scan_fields: Option<ScanFields>,
+        operator_mode: OperatorMode,
+    ) -> DbResult<SubProgram> {
+        let mut subprogram = match query {
             Some(query) => SubProgram::compile_query(
                 col_spec,
                 &query,
-                true
+                true,
+                operator_mode,
             ),
             None => SubProgram::compile_query_all(col_spec, true),
         }?;
-
-        let handle = DbContext::make_handle(session, subprogram);
-        Ok(handle)
+        if let Some(fields) = scan_fields {
+            subprogram.set_scan_fields(fields);
+        }
+        Ok(subprogram)
     }
 
-    pub fn update_many(&mut self, col_spec: &CollectionSpecification, query: Option<&Document>, update: &Document, session_id: Option<&ObjectId>) -> DbResult<usize> {
+    pub fn update_many(&mut self, col_spec: &CollectionSpecification, query: Option<&Document>, update: &Document, session_id: Option<&ObjectId>) -> DbResult<UpdateResult> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let result = try_db_op!(session, DbContext::internal_update(session, col_spec, query, update, true));
+        let now = clock::now_datetime(self.config.clock());
+        let result = try_db_op!(session, DbContext::internal_update(session, col_spec, query, update, true, self.config.operator_mode, now));
 
         Ok(result)
     }
 
-    pub fn update_one(&mut self, col_spec: &CollectionSpecification, query: Option<&Document>, update: &Document, session_id: Option<&ObjectId>) -> DbResult<usize> {
+    pub fn update_one(&mut self, col_spec: &CollectionSpecification, query: Option<&Document>, update: &Document, session_id: Option<&ObjectId>) -> DbResult<UpdateResult> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let result = try_db_op!(session, DbContext::internal_update(session, col_spec, query, update, false));
+        let now = clock::now_datetime(self.config.clock());
+        let result = try_db_op!(session, DbContext::internal_update(session, col_spec, query, update, false, self.config.operator_mode, now));
 
         Ok(result)
     }
 
-    fn internal_update(session: &dyn Session, col_spec: &CollectionSpecification, query: Option<&Document>, update: &Document, is_many: bool) -> DbResult<usize> {
-        let subprogram = SubProgram::compile_update(
-            col_spec,
-            query,
-            update,
-            true,
-            is_many,
-        )?;
+    fn internal_update(
+        session: &dyn Session,
+        col_spec: &CollectionSpecification,
+        query: Option<&Document>,
+        update: &Document,
+        is_many: bool,
+        operator_mode: OperatorMode,
+        now: DateTime,
+    ) -> DbResult<UpdateResult> {
+        // the spec passed in may be read before the transaction
+        let mut stats_spec = DbContext::internal_get_collection_id_by_name(session, col_spec.name())?;
+        DbContext::check_mutable(&stats_spec)?;
+        let stats_scanned = DbContext::ensure_stats(session, &mut stats_spec)?;
+
+        let versioned_update = DbContext::increase_version(col_spec, update);
+        let update = versioned_update.as_ref().unwrap_or(update);
+
+        let scan_keys = DbContext::index_scan_keys(session, &stats_spec, query)?;
+        let compile = || -> DbResult<SubProgram> {
+            let mut subprogram = SubProgram::compile_update(
+                col_spec,
+                query,
+                update,
+                true,
+                is_many,
+                operator_mode,
+                now,
+            )?;
+            if let Some(keys) = &scan_keys {
+                subprogram.set_scan_keys(keys.clone());
+            }
+            Ok(subprogram)
+        };
+
+        // the updates are checked before anything is written
+        let has_unique = stats_spec.indexes.values().any(|info| info.unique);
+        if has_unique || !stats_spec.info.foreign_keys.is_empty() {
+            let mut subprogram = compile()?;
+            subprogram.set_dry_run();
+            let mut vm = VM::new(session, subprogram);
+            vm.execute()?;
+            let updated_docs = std::mem::take(&mut vm.updated_docs);
+            vm.commit_and_close()?;
+
+            for (before, after) in &updated_docs {
+                DbContext::check_references(session, &stats_spec, after, Some(before))?;
+            }
+            index_helper::check_unique_updates(session, &stats_spec, &updated_docs)?;
+        }
+
+        let mut subprogram = compile()?;
+        if !stats_spec.indexes.is_empty() {
+            subprogram.set_keep_updated_docs();
+        }
 
         let mut vm = VM::new(session, subprogram);
         vm.execute()?;
 
-        Ok(vm.r2 as usize)
+        if !vm.updated_docs.is_empty() {
+            index_helper::update_entries(session, &mut stats_spec, &vm.updated_docs)?;
+        }
+
+        if vm.size_delta != 0 || stats_scanned || !vm.updated_docs.is_empty() {
+            stats_spec.info.add_stats(0, vm.size_delta);
+            DbContext::update_collection_spec(session, &stats_spec)?;
+        }
+
+        Ok(UpdateResult {
+            matched_count: vm.r2 as u64,
+            modified_count: vm.modified_count,
+            upserted_id: None,
+        })
+    }
+
+    /// Increase the version field of the collection on every update,
+    /// unless the update sets it by itself.
+    fn increase_version(col_spec: &CollectionSpecification, update: &Document) -> Option<Document> {
+        let field = col_spec.info.version_field.as_ref()?;
+        let is_set = update.values().any(|fields| match fields {
+            Bson::Document(fields) => fields.contains_key(field),
+            _ => false,
+        });
+        if is_set {
+            return None;
+        }
+
+        let mut result = update.clone();
+        match result.get_mut("$inc") {
+            Some(Bson::Document(inc)) => {
+                inc.insert(field.clone(), Bson::Int32(1));
+            }
+            // leave the invalid `$inc` to the compiler to report
+            Some(_) => (),
+            None => {
+                let mut inc = Document::new();
+                inc.insert(field.clone(), Bson::Int32(1));
+                result.insert("$inc", inc);
+            }
+        }
+        Some(result)
     }
 
     pub fn drop_collection(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
@@ -638,8 +1318,10 @@ impl DbContext {
 
     fn internal_drop(session: &dyn Session, name: &str) -> DbResult<()> {
         let meta_source = DbContext::get_meta_source(session)?;
-        let collection_meta = DbContext::internal_get_collection_id_by_name(session, name)?;
+        // a stub of an archived collection can be dropped too
+        let collection_meta = DbContext::internal_get_collection_id_by_name_with_pid(session, meta_source.meta_pid, name)?;
         delete_all_helper::delete_all(session, &collection_meta)?;
+        index_helper::drop_all(session, &collection_meta)?;
 
         let mut btree_wrapper = BTreePageDeleteWrapper::new(
             session, meta_source.meta_pid);
@@ -650,33 +1332,146 @@ impl DbContext {
         DbContext::update_meta_source(session, &meta_source)
     }
 
-    pub fn delete(&mut self, col_name: &str, query: Document, is_many: bool, session_id: Option<&ObjectId>) -> DbResult<usize> {
+    pub fn truncate(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<usize> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        let result = try_db_op!(session, DbContext::internal_truncate(session, name));
+
+        Ok(result)
+    }
+
+    /// Free the pages of the btree and start with an empty root,
+    /// the spec of the collection is kept.
+    fn internal_truncate(session: &dyn Session, name: &str) -> DbResult<usize> {
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
+        DbContext::ensure_stats(session, &mut col_spec)?;
+        let count = col_spec.info.count.unwrap_or(0) as usize;
+        if count == 0 {
+            return Ok(0);
+        }
+
+        delete_all_helper::delete_all(session, &col_spec)?;
+
+        // the freed pages may be reused, so the new root is written empty
+        let root_pid = session.alloc_page_id()?;
+        let root_page = RawPage::new(root_pid, session.page_size());
+        session.write_page(&root_page)?;
+
+        col_spec.info.root_pid = root_pid;
+        col_spec.info.count = Some(0);
+        col_spec.info.data_size = Some(0);
+        index_helper::truncate_all(session, &mut col_spec)?;
+        DbContext::update_collection_spec(session, &col_spec)?;
+
+        Ok(count)
+    }
+
+    /// Return the alias of the attached database if the collection is archived.
+    pub fn archived_in(&mut self, name: &str) -> DbResult<Option<String>> {
+        let session = self.get_session_by_id(None)?;
+        let meta_source = DbContext::get_meta_source(session)?;
+        match DbContext::internal_get_collection_id_by_name_with_pid(session, meta_source.meta_pid, name) {
+            Ok(col_spec) => Ok(col_spec.info.archived_in),
+            Err(DbErr::CollectionNotFound(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Free the pages of the collection and leave a stub pointing to `alias`.
+    pub fn mark_archived(&mut self, name: &str, alias: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        try_db_op!(session, DbContext::internal_mark_archived(session, name, alias));
+
+        Ok(())
+    }
+
+    fn internal_mark_archived(session: &dyn Session, name: &str, alias: &str) -> DbResult<()> {
+        DbContext::internal_truncate(session, name)?;
+
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
+        col_spec.info.count = Some(0);
+        col_spec.info.data_size = Some(0);
+        col_spec.info.archived_in = Some(alias.to_string());
+        DbContext::update_collection_spec(session, &col_spec)
+    }
+
+    /// Delete the documents satisfying the query, at most `limit` documents.
+    pub fn delete(&mut self, col_name: &str, query: Document, limit: Option<usize>, session_id: Option<&ObjectId>) -> DbResult<usize> {
         let session = self.get_session_by_id(session_id)?;
         session.auto_start_transaction(TransactionType::Write)?;
 
-        let result = try_db_op!(session, DbContext::internal_delete_by_query(session, col_name, query, is_many));
+        let result = try_db_op!(session, DbContext::internal_delete_by_query(session, col_name, query, limit, self.config.operator_mode));
 
         Ok(result)
     }
 
     fn internal_delete(session: &dyn Session, col_name: &str, primary_keys: &[Bson]) -> DbResult<usize> {
-        let mut count: usize = 0;
+        let deleted = DbContext::internal_delete_documents(session, col_name, primary_keys)?;
+        Ok(deleted.len())
+    }
+
+    /// Delete the documents by the primary keys, return the deleted documents.
+    fn internal_delete_documents(session: &dyn Session, col_name: &str, primary_keys: &[Bson]) -> DbResult<Vec<Document>> {
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, col_name)?;
+        DbContext::check_mutable(&col_spec)?;
+        let stats_scanned = DbContext::ensure_stats(session, &mut col_spec)?;
+        let referencing = DbContext::referencing_keys(session, col_name)?;
+        DbContext::check_restrict(session, col_name, &referencing, primary_keys)?;
+
+        let mut deleted: Vec<Document> = Vec::with_capacity(primary_keys.len());
         for pkey in primary_keys {
-            let delete_result = DbContext::internal_delete_by_pkey(session, col_name, pkey)?;
-            if delete_result.is_some() {
-                count += 1;
+            let delete_result = DbContext::delete_by_pkey_with_meta(session, &mut col_spec, pkey)?;
+            if let Some(doc) = delete_result {
+                deleted.push(doc);
             }
         }
 
-        Ok(count)
+        if !deleted.is_empty() || stats_scanned {
+            DbContext::update_collection_spec(session, &col_spec)?;
+        }
+
+        let deleted_keys: Vec<Bson> = deleted.iter().filter_map(|doc| doc.get("_id").cloned()).collect();
+        DbContext::apply_on_delete(session, &referencing, &deleted_keys)?;
+
+        Ok(deleted)
     }
 
-    fn internal_delete_by_query(session: &dyn Session, col_name: &str, query: Document, is_many: bool) -> DbResult<usize> {
+    pub fn drain(&mut self, col_name: &str, query: Option<Document>, session_id: Option<&ObjectId>) -> DbResult<Vec<Document>> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        let result = try_db_op!(session, DbContext::internal_drain(session, col_name, query, self.config.operator_mode));
+
+        Ok(result)
+    }
+
+    fn internal_drain(session: &dyn Session, col_name: &str, query: Option<Document>, operator_mode: OperatorMode) -> DbResult<Vec<Document>> {
+        let primary_keys = DbContext::get_primary_keys_by_query(
+            session,
+            col_name,
+            query,
+            None,
+            operator_mode,
+        )?;
+        DbContext::internal_delete_documents(session, col_name, &primary_keys)
+    }
+
+    fn internal_delete_by_query(
+        session: &dyn Session,
+        col_name: &str,
+        query: Document,
+        limit: Option<usize>,
+        operator_mode: OperatorMode,
+    ) -> DbResult<usize> {
         let primary_keys = DbContext::get_primary_keys_by_query(
             session,
             col_name,
             Some(query),
-            is_many,
+            limit,
+            operator_mode,
         )?;
         DbContext::internal_delete(session, col_name, &primary_keys)
     }
@@ -686,7 +1481,8 @@ impl DbContext {
             session,
             col_name,
             None,
-            true,
+            None,
+            OperatorMode::default(),
         )?;
         DbContext::internal_delete(session, col_name, &primary_keys)
     }
@@ -700,9 +1496,21 @@ impl DbContext {
         Ok(result)
     }
 
-    fn get_primary_keys_by_query(session: &dyn Session, col_name: &str, query: Option<Document>, is_many: bool) -> DbResult<Vec<Bson>> {
+    fn get_primary_keys_by_query(
+        session: &dyn Session,
+        col_name: &str,
+        query: Option<Document>,
+        limit: Option<usize>,
+        operator_mode: OperatorMode,
+    ) -> DbResult<Vec<Bson>> {
         let col_spec = DbContext::internal_get_collection_id_by_name(session, col_name)?;
-        let mut handle = DbContext::find_internal(session, &col_spec, query)?;
+
+        // the keys in a range of `_id` are collected on the btree without reading the documents
+        if let Some(range) = query.as_ref().and_then(KeyRange::from_query) {
+            return range_helper::collect_keys(session, &col_spec, &range, limit);
+        }
+
+        let mut handle = DbContext::find_internal(session, &col_spec, query, None, operator_mode)?;
         let mut buffer: Vec<Bson> = vec![];
 
         handle.step()?;
@@ -712,7 +1520,7 @@ impl DbContext {
             let pkey = doc.get("_id").unwrap();
             buffer.push(pkey.clone());
 
-            if !is_many {
+            if limit == Some(buffer.len()) {
                 handle.commit_and_close_vm()?;
                 return Ok(buffer);
             }
@@ -724,6 +1532,187 @@ impl DbContext {
         Ok(buffer)
     }
 
+    /// The documents of an append-only collection can't be updated or deleted.
+    fn check_mutable(col_spec: &CollectionSpecification) -> DbResult<()> {
+        if col_spec.info.append_only {
+            return Err(DbErr::AppendOnlyCollection(col_spec.name().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Check the referenced documents of the foreign keys exist. The fields not changed
+    /// from the document `before` an update are not checked.
+    fn check_references(
+        session: &dyn Session,
+        col_spec: &CollectionSpecification,
+        doc: &Document,
+        before: Option<&Document>,
+    ) -> DbResult<()> {
+        for foreign_key in &col_spec.info.foreign_keys {
+            let value = match index_helper::get_path(doc, &foreign_key.field) {
+                None | Some(Bson::Null) => continue,
+                Some(value) => value,
+            };
+            if before.map_or(false, |before| index_helper::get_path(before, &foreign_key.field) == Some(value)) {
+                continue;
+            }
+            let exists = match DbContext::internal_get_collection_id_by_name(session, &foreign_key.references) {
+                Ok(ref_spec) => {
+                    let (found, _) = range_helper::find_by_keys(session, &ref_spec, std::slice::from_ref(value))?;
+                    !found.is_empty()
+                }
+                Err(DbErr::CollectionNotFound(_)) => false,
+                Err(err) => return Err(err),
+            };
+            if !exists {
+                return Err(DbErr::ForeignKeyViolation(format!(
+                    "{}.{} references {} not found in \"{}\"",
+                    col_spec.name(), foreign_key.field, value, foreign_key.references,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// The foreign keys of the collections referencing the collection, with the names of them.
+    fn referencing_keys(session: &dyn Session, col_name: &str) -> DbResult<Vec<(String, ForeignKey)>> {
+        let mut result = vec![];
+        // the metas are not read if no collection has foreign keys
+        if !DbContext::has_compat_feature(session, FEATURE_COMPAT_FOREIGN_KEYS)? {
+            return Ok(result);
+        }
+        for meta in DbContext::query_all_meta_internal(session)? {
+            let has_foreign_keys = meta
+                .get_document("info")
+                .map_or(false, |info| info.contains_key("foreignKeys"));
+            if !has_foreign_keys {
+                continue;
+            }
+            let col_spec: CollectionSpecification = bson::from_document(meta)?;
+            for foreign_key in col_spec.info.foreign_keys {
+                if foreign_key.references == col_name {
+                    result.push((col_spec._id.clone(), foreign_key));
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    /// Fail if the documents to delete are referenced by the foreign keys of [`OnDelete::Restrict`].
+    /// It's checked before anything is deleted, the documents referencing each other
+    /// in the same collection can be deleted together.
+    fn check_restrict(
+        session: &dyn Session,
+        col_name: &str,
+        referencing: &[(String, ForeignKey)],
+        keys: &[Bson],
+    ) -> DbResult<()> {
+        if keys.is_empty() {
+            return Ok(());
+        }
+        for (ref_name, foreign_key) in referencing {
+            if foreign_key.on_delete != OnDelete::Restrict {
+                continue;
+            }
+            let mut query = Document::new();
+            query.insert(foreign_key.field.clone(), doc! { "$in": keys.to_vec() });
+            let is_self = ref_name == col_name;
+            let limit = if is_self { None } else { Some(1) };
+            let ref_keys = DbContext::get_primary_keys_by_query(session, ref_name, Some(query), limit, OperatorMode::default())?;
+            if let Some(key) = ref_keys.iter().find(|key| !is_self || !keys.contains(key)) {
+                return Err(DbErr::ForeignKeyViolation(format!(
+                    "the document {} of \"{}\" references the documents deleted from \"{}\"",
+                    key, ref_name, col_name,
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Apply the [`OnDelete`] of the foreign keys referencing the documents deleted,
+    /// the ones of [`OnDelete::Restrict`] are checked by [`DbContext::check_restrict`].
+    fn apply_on_delete(
+        session: &dyn Session,
+        referencing: &[(String, ForeignKey)],
+        deleted_keys: &[Bson],
+    ) -> DbResult<()> {
+        if deleted_keys.is_empty() {
+            return Ok(());
+        }
+        for (ref_name, foreign_key) in referencing {
+            let mut query = Document::new();
+            query.insert(foreign_key.field.clone(), doc! { "$in": deleted_keys.to_vec() });
+            match foreign_key.on_delete {
+                OnDelete::Restrict => (),
+                OnDelete::SetNull => {
+                    let ref_spec = DbContext::internal_get_collection_id_by_name(session, ref_name)?;
+                    let mut fields = Document::new();
+                    fields.insert(foreign_key.field.clone(), Bson::Null);
+                    let update = doc! { "$set": fields };
+                    // the update has no `$currentDate`, the time is not used
+                    DbContext::internal_update(session, &ref_spec, Some(&query), &update, true, OperatorMode::default(), DateTime::now())?;
+                }
+                OnDelete::Cascade => {
+                    let keys = DbContext::get_primary_keys_by_query(session, ref_name, Some(query), None, OperatorMode::default())?;
+                    DbContext::internal_delete_documents(session, ref_name, &keys)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the changed spec of the collection to the meta.
+    fn update_collection_spec(session: &dyn Session, col_spec: &CollectionSpecification) -> DbResult<()> {
+        let meta_source = DbContext::get_meta_source(session)?;
+        let key = Bson::from(col_spec.name());
+        let doc = bson::to_document(col_spec)?;
+        let updated = DbContext::update_by_root_pid(
+            session,
+            meta_source.meta_pid,
+            &key,
+            &doc,
+        )?;
+        if !updated {
+            panic!("unexpected: update meta page failed")
+        }
+        Ok(())
+    }
+
+    /// Count and persist the stats of the collection if it has none,
+    /// return true if they are counted.
+    #[cfg(all(feature = "maintenance", not(target_arch = "wasm32")))]
+    pub fn refresh_stats(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<bool> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Write)?;
+
+        let result = try_db_op!(session, DbContext::internal_refresh_stats(session, name));
+
+        Ok(result)
+    }
+
+    #[cfg(all(feature = "maintenance", not(target_arch = "wasm32")))]
+    fn internal_refresh_stats(session: &dyn Session, name: &str) -> DbResult<bool> {
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
+        let scanned = DbContext::ensure_stats(session, &mut col_spec)?;
+        if scanned {
+            DbContext::update_collection_spec(session, &col_spec)?;
+        }
+        Ok(scanned)
+    }
+
+    /// The collections created by the older versions have no persisted stats,
+    /// count them by a scan before the first write.
+    /// Return true if the stats are counted.
+    fn ensure_stats(session: &dyn Session, col_spec: &mut CollectionSpecification) -> DbResult<bool> {
+        if col_spec.info.has_stats() {
+            return Ok(false);
+        }
+        let (count, data_size) = counter_helper::stats(session, col_spec)?;
+        col_spec.info.count = Some(count);
+        col_spec.info.data_size = Some(data_size);
+        Ok(true)
+    }
+
     fn update_by_root_pid(session: &dyn Session, root_pid: u32, key: &Bson, doc: &Document) -> DbResult<bool> {
         let mut cursor = Cursor::new(root_pid);
 
@@ -733,7 +1722,7 @@ impl DbContext {
             return Ok(false);
         }
 
-        cursor.update_current(session, doc)?;
+        cursor.update_current(session, doc, &Compression::None)?;
 
         Ok(true)
     }
@@ -766,22 +1755,39 @@ impl DbContext {
     }
 
     fn internal_delete_by_pkey(session: &dyn Session, col_name: &str, key: &Bson) -> DbResult<Option<Document>> {
-        let collection_meta = DbContext::internal_get_collection_id_by_name(
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(
             session, col_name,
         )?;
+        let stats_scanned = DbContext::ensure_stats(session, &mut col_spec)?;
+        let referencing = DbContext::referencing_keys(session, col_name)?;
+        DbContext::check_restrict(session, col_name, &referencing, std::slice::from_ref(key))?;
+
+        let result = DbContext::delete_by_pkey_with_meta(session, &mut col_spec, key)?;
+        if result.is_some() || stats_scanned {
+            DbContext::update_collection_spec(session, &col_spec)?;
+        }
+        if result.is_some() {
+            DbContext::apply_on_delete(session, &referencing, std::slice::from_ref(key))?;
+        }
+
+        Ok(result)
+    }
 
+    /// Delete one item and apply the change to the stats of the spec,
+    /// the outside should write the new spec to the meta
+    fn delete_by_pkey_with_meta(session: &dyn Session, col_spec: &mut CollectionSpecification, key: &Bson) -> DbResult<Option<Document>> {
+        DbContext::check_mutable(col_spec)?;
         let mut delete_wrapper = BTreePageDeleteWrapper::new(
             session,
-            collection_meta.info.root_pid as u32,
+            col_spec.info.root_pid,
         );
         let result = delete_wrapper.delete_item(key)?;
         delete_wrapper.flush_pages()?;
 
-        if let Some(_deleted_item) = &result {
-            // let index_ctx_opt = IndexCtx::from_meta_doc(collection_meta.doc_ref());
-            // if let Some(index_ctx) = &index_ctx_opt {
-            //     index_ctx.delete_index_by_content(deleted_item.borrow(), session)?;
-            // }
+        if let Some(deleted_item) = &result {
+            col_spec.info.add_stats(-1, -(delete_wrapper.deleted_size() as i64));
+
+            index_helper::delete_entries(session, col_spec, deleted_item)?;
 
             return Ok(result)
         }
@@ -796,7 +1802,118 @@ impl DbContext {
 
     fn count_internal(session: &dyn Session, name: &str) -> DbResult<u64> {
         let col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
-        counter_helper::count(session, &col_spec)
+        match col_spec.info.count {
+            Some(count) => Ok(count),
+            None => counter_helper::count(session, &col_spec),
+        }
+    }
+
+    /// Find the documents of the sorted primary keys,
+    /// return the documents found and the keys not found.
+    pub fn find_by_ids(&mut self, name: &str, keys: &[Bson], session_id: Option<&ObjectId>) -> DbResult<(Vec<Document>, Vec<Bson>)> {
+        let session = self.get_session_by_id(session_id)?;
+        session.auto_start_transaction(TransactionType::Read)?;
+
+        let result = try_db_op!(session, DbContext::find_by_ids_internal(session, name, keys));
+
+        Ok(result)
+    }
+
+    fn find_by_ids_internal(session: &dyn Session, name: &str, keys: &[Bson]) -> DbResult<(Vec<Document>, Vec<Bson>)> {
+        let col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
+        range_helper::find_by_keys(session, &col_spec, keys)
+    }
+
+    pub fn stats(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<CollectionStats> {
+        let session = self.get_session_by_id(session_id)?;
+        let mut col_spec = DbContext::internal_get_collection_id_by_name(session, name)?;
+        DbContext::ensure_stats(session, &mut col_spec)?;
+        Ok(CollectionStats {
+            count: col_spec.info.count.unwrap_or(0),
+            data_size: col_spec.info.data_size.unwrap_or(0),
+            metrics: None,
+        })
+    }
+
+    /// Pin the pages of the collection and its indexes in the page cache,
+    /// with the pages of the collections pinned before.
+    pub fn pin_collection(&mut self, name: &str) -> DbResult<PinResult> {
+        let session = self.get_session_by_id(None)?;
+        DbContext::internal_get_collection_id_by_name(session, name)?;
+
+        let mut names = self.pinned_collections.clone();
+        if !names.iter().any(|pinned| pinned == name) {
+            names.push(name.to_string());
+        }
+        let (counts, total_pages) = self.pin_collections(&names)?;
+        self.pinned_collections = names;
+
+        let pages = counts.iter()
+            .find(|(pinned, _)| pinned == name)
+            .map(|(_, pages)| *pages)
+            .unwrap_or(0);
+        Ok(PinResult {
+            pages,
+            total_pages,
+        })
+    }
+
+    pub fn unpin_collection(&mut self, name: &str) -> DbResult<()> {
+        if !self.is_pinned(name) {
+            return Ok(());
+        }
+        let names: Vec<String> = self.pinned_collections
+            .iter()
+            .filter(|pinned| *pinned != name)
+            .cloned()
+            .collect();
+        self.pin_collections(&names)?;
+        self.pinned_collections = names;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn is_pinned(&self, name: &str) -> bool {
+        self.pinned_collections.iter().any(|pinned| pinned == name)
+    }
+
+    /// Pin the pages of the pinned collections again after they are written,
+    /// the collections dropped are unpinned.
+    pub fn refresh_pins(&mut self) -> DbResult<()> {
+        if self.pinned_collections.is_empty() {
+            return Ok(());
+        }
+        let names = self.pinned_collections.clone();
+        let (counts, _) = self.pin_collections(&names)?;
+        self.pinned_collections = counts.into_iter().map(|(name, _)| name).collect();
+        Ok(())
+    }
+
+    // Return the pages of each collection found, and the pages pinned of all of them.
+    fn pin_collections(&mut self, names: &[String]) -> DbResult<(Vec<(String, u64)>, u64)> {
+        let session = self.get_session_by_id(None)?;
+        let mut counts = Vec::with_capacity(names.len());
+        let mut pages: Vec<u32> = Vec::new();
+        for name in names {
+            let col_spec = match DbContext::internal_get_collection_id_by_name(session, name) {
+                Ok(col_spec) => col_spec,
+                Err(DbErr::CollectionNotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            let mut col_pages = counter_helper::pages(session, col_spec.info.root_pid)?;
+            for index in col_spec.indexes.values() {
+                col_pages.extend(counter_helper::pages(session, index.root_pid)?);
+            }
+            col_pages.sort_unstable();
+            col_pages.dedup();
+            counts.push((name.clone(), col_pages.len() as u64));
+            pages.extend(col_pages);
+        }
+        pages.sort_unstable();
+        pages.dedup();
+
+        self.base_session.pin_pages(&pages)?;
+        Ok((counts, pages.len() as u64))
     }
 
     pub(crate) fn query_all_meta(&mut self, session_id: Option<&ObjectId>) -> DbResult<Vec<Document>> {
@@ -814,6 +1931,14 @@ impl DbContext {
                 uuid: None,
                 create_at: DateTime::now(),
                 root_pid: meta_src.meta_pid,
+                compression: Compression::None,
+                version_field: None,
+                append_only: false,
+                foreign_keys: Vec::new(),
+                generated_fields: Vec::new(),
+                count: None,
+                data_size: None,
+                archived_in: None,
             },
             indexes: HashMap::new(),
         };
@@ -883,6 +2008,7 @@ impl DbContext {
 
     pub fn drop_session(&mut self, session_id: &ObjectId) -> DbResult<()> {
         let remove_result = self.session_map.remove(session_id);
+        self.pool_sessions.remove(session_id);
         if remove_result.is_some() {
             self.base_session.remove_session(session_id)?;
         }
@@ -907,11 +2033,83 @@ impl DbContext {
             free_list_pid,
             free_list_size,
             page_size,
+            compat_features: first_page_wrapper.get_compat_features(),
+            incompat_features: first_page_wrapper.get_incompat_features(),
+            created_at: first_page_wrapper.get_created_at().map(bson::DateTime::from_millis),
+            checkpoint_lsn: first_page_wrapper.get_checkpoint_lsn(),
+            metadata: first_page_wrapper.metadata_entries(),
             pages: vec![],
         };
         Ok(full_dump)
     }
 
+    #[cfg(feature = "debug")]
+    pub fn debug_page(&mut self, page_id: u32) -> DbResult<crate::debug::PageDebug> {
+        let page_count = self.base_session.db_size() / (self.base_session.page_size().get() as u64);
+        if (page_id as u64) >= page_count {
+            return Err(DbErr::PageIdOutOfRange(page_id, page_count));
+        }
+        let session = self.get_session_by_id(None)?;
+        session.auto_start_transaction(TransactionType::Read)?;
+
+        let result = try_db_op!(session, crate::debug::debug_page(session, page_id));
+
+        Ok(result)
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn journal_frames(&mut self) -> DbResult<Vec<crate::dump::JournalFrameDump>> {
+        self.base_session.journal_frames()
+    }
+
+    /// Read the header and the collection catalog of the database file without opening it,
+    /// the file is not locked and the journal is not replayed.
+    #[cfg(feature = "fs")]
+    pub fn inspect_header(path: &Path) -> DbResult<HeaderDump> {
+        let backend = InspectBackend::open(path)?;
+        let page_size = backend.page_size();
+        let page_count = backend.db_size() / (page_size.get() as u64);
+        let base_session = BaseSession::new(
+            Box::new(backend), page_size, Arc::new(Config::default()), Metrics::new(),
+        )?;
+
+        let header_page = base_session.read_page(0)?;
+        DbContext::check_first_page_valid(&header_page)?;
+        let header = HeaderPageWrapper::from_raw_page(header_page.as_ref().clone());
+
+        let metas = DbContext::query_all_meta_internal(&base_session)?;
+        let mut collections = Vec::with_capacity(metas.len());
+        for meta in metas {
+            let col_spec: CollectionSpecification = bson::from_document(meta)?;
+            let mut indexes: Vec<String> = col_spec.indexes.keys().cloned().collect();
+            indexes.sort();
+            collections.push(CollectionSummary {
+                name: col_spec._id,
+                created_at: col_spec.info.create_at,
+                count: col_spec.info.count,
+                indexes,
+            });
+        }
+
+        let journal_size = std::fs::metadata(FileBackend::mk_journal_path(path))
+            .ok()
+            .map(|meta| meta.len());
+
+        Ok(HeaderDump {
+            identifier: header.get_title(),
+            version: dump_version(&header.get_version()),
+            page_size,
+            page_count,
+            compat_features: header.get_compat_features(),
+            incompat_features: header.get_incompat_features(),
+            created_at: header.get_created_at().map(DateTime::from_millis),
+            checkpoint_lsn: header.get_checkpoint_lsn(),
+            checkpoint_at: header.get_checkpoint_at().map(DateTime::from_millis),
+            journal_size,
+            collections,
+        })
+    }
+
     #[allow(dead_code)]
     fn dump_all_pages(&mut self, file_len: u64) -> DbResult<Vec<PageDump>> {
         let page_count = file_len / (self.base_session.page_size().get() as u64);
@@ -0,0 +1,229 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use bson::Document;
+use hashbrown::HashMap;
+use crate::{Config, Database, DbErr, DbResult, PageCachePolicy};
+use crate::backend::PageCache;
+
+type MaintenanceTask = Box<dyn Fn(&str, &Database) -> DbResult<()> + Send + Sync>;
+
+pub struct DatabaseManagerConfig {
+    /// The count of pages shared by all the databases opened by the manager.
+    pub page_cache_size:      NonZeroUsize,
+    /// Which pages the shared page cache evicts when it's full.
+    pub page_cache_policy:    PageCachePolicy,
+    /// How often the background thread runs the maintenance tasks.
+    pub maintenance_interval: Duration,
+    /// Delete the expired documents of all the collections in the maintenance,
+    /// see [`Collection::delete_expired`](crate::Collection::delete_expired).
+    pub expire_documents:     bool,
+}
+
+impl Default for DatabaseManagerConfig {
+
+    fn default() -> Self {
+        DatabaseManagerConfig {
+            page_cache_size:      NonZeroUsize::new(4096).unwrap(),
+            page_cache_policy:    PageCachePolicy::default(),
+            maintenance_interval: Duration::from_secs(5),
+            expire_documents:     false,
+        }
+    }
+
+}
+
+///
+/// Open many database files in one process.
+///
+/// All the databases opened by the manager share one page cache,
+/// so the memory is bounded by [`DatabaseManagerConfig::page_cache_size`]
+/// no matter how many files are opened. The pages of the cache are of the
+/// [`Config::page_size`] of the first database opened, a database of
+/// another page size is rejected with [`DbErr::PageSizeMismatch`].
+///
+/// One background thread is shared by all the databases. It checkpoints the journals,
+/// deletes the expired documents if [`DatabaseManagerConfig::expire_documents`] is set,
+/// and runs the tasks registered by [`DatabaseManager::add_maintenance_task`].
+///
+/// ```rust
+/// use polodb_core::DatabaseManager;
+/// use polodb_core::bson::{Document, doc};
+///
+/// let manager = DatabaseManager::new(Default::default());
+/// # let tenant_path = polodb_core::test_utils::mk_db_path("doc-test-manager-tenant");
+/// # let _ = std::fs::remove_file(&tenant_path);
+/// let db = manager.open("tenant-1", tenant_path).unwrap();
+/// db.collection::<Document>("books").insert_one(doc! { "title": "1984" }).unwrap();
+/// ```
+pub struct DatabaseManager {
+    inner: Arc<ManagerInner>,
+    stop_sender: Option<Sender<()>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+struct ManagerInner {
+    page_cache_size: NonZeroUsize,
+    page_cache_policy: PageCachePolicy,
+    // created by the first database opened
+    page_cache: Mutex<Option<PageCache>>,
+    databases: RwLock<HashMap<String, Arc<Database>>>,
+    expire_documents: bool,
+    tasks: RwLock<Vec<MaintenanceTask>>,
+}
+
+impl ManagerInner {
+
+    fn share_page_cache(&self, page_size: NonZeroU32) -> DbResult<PageCache> {
+        let mut page_cache = self.page_cache.lock()?;
+        let page_cache = page_cache.get_or_insert_with(|| {
+            PageCache::new(self.page_cache_size.get(), page_size, self.page_cache_policy)
+        });
+        if page_cache.page_size() != page_size {
+            return Err(DbErr::PageSizeMismatch(page_cache.page_size().get(), page_size.get()));
+        }
+        Ok(page_cache.share())
+    }
+
+    fn expire_documents(db: &Database) -> DbResult<()> {
+        for name in db.list_collection_names()? {
+            db.collection::<Document>(&name).delete_expired()?;
+        }
+        Ok(())
+    }
+
+    fn run_maintenance(&self) {
+        let databases: Vec<(String, Arc<Database>)> = {
+            let databases = self.databases.read().unwrap();
+            databases
+                .iter()
+                .map(|(name, db)| (name.clone(), db.clone()))
+                .collect()
+        };
+
+        let tasks = self.tasks.read().unwrap();
+        for (name, db) in &databases {
+            match db.checkpoint() {
+                Ok(()) | Err(DbErr::Busy) => (),
+                Err(err) => crate::polo_log!("checkpoint {} failed: {}", name, err),
+            }
+
+            if self.expire_documents {
+                if let Err(err) = ManagerInner::expire_documents(db) {
+                    crate::polo_log!("expiring the documents of {} failed: {}", name, err);
+                }
+            }
+
+            for task in tasks.iter() {
+                if let Err(err) = task(name, db) {
+                    crate::polo_log!("maintenance task for {} failed: {}", name, err);
+                }
+            }
+        }
+    }
+
+}
+
+impl DatabaseManager {
+
+    pub fn new(config: DatabaseManagerConfig) -> DatabaseManager {
+        let inner = Arc::new(ManagerInner {
+            page_cache_size: config.page_cache_size,
+            page_cache_policy: config.page_cache_policy,
+            page_cache: Mutex::new(None),
+            databases: RwLock::new(HashMap::new()),
+            expire_documents: config.expire_documents,
+            tasks: RwLock::new(Vec::new()),
+        });
+
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let worker_inner = inner.clone();
+        let interval = config.maintenance_interval;
+        let worker = std::thread::Builder::new()
+            .name("polodb-maintenance".into())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                    worker_inner.run_maintenance();
+                }
+            })
+            .expect("spawn maintenance thread failed");
+
+        DatabaseManager {
+            inner,
+            stop_sender: Some(stop_sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Open the database file under `name`, return the opened one
+    /// if the name is already used.
+    pub fn open<P: AsRef<Path>>(&self, name: &str, path: P) -> DbResult<Arc<Database>> {
+        self.open_with_config(name, path, Config::default())
+    }
+
+    pub fn open_with_config<P: AsRef<Path>>(&self, name: &str, path: P, config: Config) -> DbResult<Arc<Database>> {
+        let mut databases = self.inner.databases.write()?;
+        if let Some(db) = databases.get(name) {
+            return Ok(db.clone());
+        }
+
+        let page_cache = self.inner.share_page_cache(config.page_size)?;
+        let db = Arc::new(Database::open_file_with_page_cache(path, config, page_cache)?);
+        databases.insert(name.to_string(), db.clone());
+
+        Ok(db)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<Database>> {
+        let databases = self.inner.databases.read().unwrap();
+        databases.get(name).cloned()
+    }
+
+    /// Remove the database from the manager. The file is closed
+    /// when the last reference of the database is dropped.
+    pub fn close(&self, name: &str) -> bool {
+        let mut databases = self.inner.databases.write().unwrap();
+        databases.remove(name).is_some()
+    }
+
+    pub fn database_names(&self) -> Vec<String> {
+        let databases = self.inner.databases.read().unwrap();
+        databases.keys().cloned().collect()
+    }
+
+    /// Register a task executed periodically on every database
+    /// by the background thread.
+    pub fn add_maintenance_task<F>(&self, task: F)
+    where
+        F: Fn(&str, &Database) -> DbResult<()> + Send + Sync + 'static
+    {
+        let mut tasks = self.inner.tasks.write().unwrap();
+        tasks.push(Box::new(task));
+    }
+
+    /// Run the maintenance tasks on the current thread immediately.
+    pub fn run_maintenance(&self) {
+        self.inner.run_maintenance();
+    }
+
+}
+
+impl Drop for DatabaseManager {
+
+    fn drop(&mut self) {
+        // dropping the sender wakes up the worker
+        self.stop_sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+
+}
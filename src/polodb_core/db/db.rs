@@ -4,35 +4,80 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::borrow::Borrow;
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "fs")]
 use std::path::Path;
 use std::io::Read;
 use std::sync::atomic::{AtomicBool, Ordering};
-use bson::{Bson, Document};
+use bson::{doc, Bson, Document};
 use serde::Serialize;
 use serde::de::DeserializeOwned;
 use byteorder::{self, BigEndian, ReadBytesExt};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::collections::BTreeMap;
+use hashbrown::HashMap;
 use bson::oid::ObjectId;
-use crate::error::DbErr;
-use crate::{ClientSession, Config};
+use crate::error::{DbErr, DeserializeDocumentError};
+use crate::{ClientSession, Config, CustomAccumulator, ExprFunction};
 use super::context::DbContext;
+use super::priority_gate::PriorityGate;
 use crate::{DbHandle, TransactionType};
 use crate::collection_info::CollectionSpecification;
+use crate::btree::index_helper::IndexScan;
+use crate::btree::range_helper;
+use crate::options::{CreateCollectionOptions, DeleteOptions, FindOptions, HandleOptions, IndexOptions, InsertManyOptions, Priority, UpdateOptions};
+use crate::projection::Projection;
+use crate::aggregation::{self, AggregationContext, DocumentFilter, SortSpec};
 use crate::db::collection::Collection;
-use crate::dump::FullDump;
-use crate::results::{DeleteResult, InsertManyResult, InsertOneResult, UpdateResult};
+use crate::db::kv::{KvNamespace, KV_COLLECTION_PREFIX};
+use crate::dump::{FullDump, HeaderDump};
+use crate::explain::{self, ExecutionStats, Explain, ExplainVerbosity, StageStats, Stopwatch, STAGE_PROJECTION, STAGE_SORT};
+use crate::schema::{self, SchemaAnalysis, SchemaAnalyzer};
+use crate::migrations::{self, Migration};
+use crate::results::{CollectionStats, CompactResult, DeleteResult, FindByIdsResult, InsertManyResult, InsertOneResult, MigrateResult, PinResult, UpdateIfResult, UpdateResult, WriteError};
 use crate::commands::*;
 use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::subscription::{ChangeEvent, ChangeOperation, Subscription, Subscriptions};
+use crate::server_cursor::ServerCursors;
+use crate::db::collection::EXPIRE_AT_FIELD;
+#[cfg(feature = "fs")]
+use crate::backend::PageCache;
+#[cfg(feature = "fs")]
+use crate::backend::file::{BackupFile, RecoveryReport};
+use crate::backend::object_store::ObjectStore;
 
 pub(crate) static SHOULD_LOG: AtomicBool = AtomicBool::new(false);
 
-pub(super) fn consume_handle_to_vec<T: DeserializeOwned>(handle: &mut DbHandle, result: &mut Vec<T>) -> DbResult<()> {
+// the pages copied by a backup with the lock of the database held
+#[cfg(feature = "fs")]
+const BACKUP_BATCH_PAGES: u32 = 256;
+
+/// Deserialize a document read from the collection, the error tells
+/// which document doesn't match the type.
+pub(crate) fn from_stored_document<T: DeserializeOwned>(col_name: &str, doc: Document) -> DbResult<T> {
+    let id = doc.get("_id").cloned();
+    bson::from_document(doc).map_err(|error| {
+        DbErr::DeserializeDocument(Box::new(DeserializeDocumentError {
+            collection: col_name.to_string(),
+            id,
+            error,
+        }))
+    })
+}
+
+pub(super) fn consume_handle_to_vec<T: DeserializeOwned>(
+    handle: &mut DbHandle,
+    projection: Option<&Projection>,
+    result: &mut Vec<T>,
+) -> DbResult<()> {
     handle.step()?;
 
     while handle.has_row() {
-        let doc_result = handle.get().as_document().unwrap();
-        let item: T = bson::from_document(doc_result.clone())?;
+        let mut doc_result = handle.get().as_document().unwrap().clone();
+        if let Some(projection) = projection {
+            doc_result = projection.apply_scanned(doc_result);
+        }
+        let item: T = bson::from_document(doc_result)?;
         result.push(item);
 
         handle.step()?;
@@ -41,9 +86,99 @@ pub(super) fn consume_handle_to_vec<T: DeserializeOwned>(handle: &mut DbHandle,
     Ok(())
 }
 
+// The sort of the find, `None` if the documents are in the order of the scan.
+fn parse_find_sort(options: &FindOptions) -> DbResult<Option<SortSpec>> {
+    let sort = match options.sort.as_ref().filter(|sort| !sort.is_empty()) {
+        Some(sort) => SortSpec::parse("sort", sort)?,
+        None => return Ok(None),
+    };
+    if sort.single_field() == Some(("_id", true)) {
+        return Ok(None);
+    }
+    Ok(Some(sort))
+}
+
+fn sort_and_slice(docs: &mut Vec<Document>, sort: Option<&SortSpec>, skip: u64, limit: Option<u64>) {
+    if let Some(sort) = sort {
+        sort.sort(docs);
+    }
+    let skip = std::cmp::min(skip, docs.len() as u64) as usize;
+    docs.drain(..skip);
+    if let Some(limit) = limit {
+        docs.truncate(limit as usize);
+    }
+}
+
+fn receive_request_body<R: Read>(pipe_in: &mut R) -> DbResult<Bson> {
+    let request_size = pipe_in.read_u32::<BigEndian>()? as usize;
+    if request_size == 0 {
+        return Ok(Bson::Null);
+    }
+    let mut request_body = vec![0u8; request_size];
+    pipe_in.read_exact(&mut request_body)?;
+    let body_ref: &[u8] = request_body.as_slice();
+    let val = bson::from_slice(body_ref)?;
+    Ok(val)
+}
+
+// The document inserted by an upsert, with the equality conditions
+// of the top-level fields of the query.
+fn upsert_document(query: &Document) -> Document {
+    let mut doc = Document::new();
+    for (key, value) in query {
+        if key.starts_with('$') || key.contains('.') {
+            continue;
+        }
+        match value {
+            Bson::Document(condition) if condition.keys().any(|k| k.starts_with('$')) => {
+                if let (1, Some(value)) = (condition.len(), condition.get("$eq")) {
+                    doc.insert(key.clone(), value.clone());
+                }
+            }
+            _ => {
+                doc.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    doc
+}
+
+#[cfg(feature = "indexeddb")]
 pub struct IndexedDbContext {
     pub name: String,
     pub idb: web_sys::IdbDatabase,
+    /// Called with `{ framesLoaded, framesTotal, bytesLoaded }` for every frame
+    /// read from IndexedDB on opening, and with `done: true` at the end,
+    /// so a large database can show a loading bar.
+    pub on_progress: Option<js_sys::Function>,
+}
+
+#[cfg(feature = "indexeddb")]
+impl IndexedDbContext {
+
+    /// The object store the committed pages are written to, which should be
+    /// created without a `keyPath` when the IndexedDB database is upgraded,
+    /// the frames are put with the keys `[sid, seq]`.
+    pub const FRAME_STORE: &'static str = "frames";
+
+    /// The object store of the state of the database, created without a `keyPath`.
+    /// The key `lastCommit` is `{ sid, seq }`, the key of the last frame committed,
+    /// which is put by the transaction writing the frames. It's not written
+    /// if the store is not created by the older versions.
+    pub const META_STORE: &'static str = "meta";
+
+}
+
+/// The files of a database in the Origin Private File System, opened by
+/// `createSyncAccessHandle()` in a dedicated Web Worker. The handles are
+/// closed when the database is closed.
+#[cfg(feature = "opfs")]
+pub struct OpfsContext {
+    /// The database file, such as `books.db`.
+    pub db_file: web_sys::FileSystemSyncAccessHandle,
+    /// The journal beside it, such as `books.db.journal`, the commits are appended
+    /// to it before they are written to the database file.
+    pub journal_file: web_sys::FileSystemSyncAccessHandle,
 }
 
 ///
@@ -61,17 +196,48 @@ pub struct IndexedDbContext {
 /// You can use [`Database::create_collection`] to create a data collection.
 /// To obtain an exist collection, use [`Database::collection`],
 ///
+/// # Sharing
+/// A `Database` is a handle of the opened database, which is `Send` and `Sync`.
+/// Cloning it is cheap, the clones share the same file, the middlewares,
+/// the attached databases and the registered functions, so it can be shared
+/// by the worker threads without wrapping it in `Arc<Mutex<_>>`.
+/// The operations of the handles are serialized by the lock of the database.
+///
+/// The file is released when all the clones are dropped.
+/// Use [`Database::with_options`] to get a handle with its own defaults,
+/// such as a read-only handle for the readers.
+///
+#[derive(Clone)]
 pub struct Database {
+    shared: Arc<DatabaseShared>,
+    options: HandleOptions,
+}
+
+// the database is shared by the threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Database>();
+};
+
+struct DatabaseShared {
     inner: Mutex<DatabaseInner>,
+    gate: PriorityGate,
+    middlewares: RwLock<Vec<Arc<dyn Middleware>>>,
+    attached: RwLock<HashMap<String, Arc<Database>>>,
+    accumulators: RwLock<HashMap<String, Arc<dyn CustomAccumulator>>>,
+    functions: RwLock<HashMap<String, ExprFunction>>,
+    migrations: RwLock<BTreeMap<u64, Arc<Migration>>>,
 }
 
 pub(super) struct DatabaseInner {
     pub(super) ctx: DbContext,
+    subscriptions: Subscriptions,
+    cursors: ServerCursors,
 }
 
 pub type DbResult<T> = Result<T, DbErr>;
 
-#[derive(Clone)]
+#[derive(Debug, Clone)]
 pub struct HandleRequestResult {
     pub is_quit: bool,
     pub value: Bson,
@@ -89,15 +255,111 @@ impl Database {
         VERSION.into()
     }
 
-    #[cfg(target_arch = "wasm32")]
-    pub fn open_indexeddb(ctx: IndexedDbContext) -> DbResult<Database> {
-        let inner = DatabaseInner::open_indexeddb(ctx, Config::default())?;
-
-        Ok(Database {
+    fn from_inner(inner: DatabaseInner) -> Database {
+        let shared = DatabaseShared {
             inner: Mutex::new(inner),
+            gate: PriorityGate::new(),
+            middlewares: RwLock::new(Vec::new()),
+            attached: RwLock::new(HashMap::new()),
+            accumulators: RwLock::new(HashMap::new()),
+            functions: RwLock::new(HashMap::new()),
+            migrations: RwLock::new(BTreeMap::new()),
+        };
+        Database {
+            shared: Arc::new(shared),
+            options: HandleOptions::default(),
+        }
+    }
+
+    /// Return a handle of the same database with the `options`,
+    /// the options of this handle are not changed.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, DbErr, HandleOptions};
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let reader = db.with_options(HandleOptions {
+    ///     read_only: true,
+    ///     ..Default::default()
+    /// });
+    ///
+    /// db.collection::<Document>("users").insert_one(doc! { "name": "Alice" }).unwrap();
+    /// assert_eq!(reader.collection::<Document>("users").count_documents().unwrap(), 1);
+    ///
+    /// let err = reader.collection::<Document>("users").insert_one(doc! { "name": "Bob" }).unwrap_err();
+    /// assert!(matches!(err, DbErr::PermissionDenied(_)));
+    /// ```
+    pub fn with_options(&self, options: HandleOptions) -> Database {
+        Database {
+            shared: self.shared.clone(),
+            options,
+        }
+    }
+
+    /// Return the options of this handle.
+    pub fn handle_options(&self) -> &HandleOptions {
+        &self.options
+    }
+
+    /// Take the lock of the database by the priority of the handle.
+    fn lock_inner(&self) -> DbResult<MutexGuard<'_, DatabaseInner>> {
+        match self.options.priority {
+            Priority::Interactive => {
+                let _ticket = self.shared.gate.enter_interactive();
+                Ok(self.shared.inner.lock()?)
+            }
+            Priority::Background => {
+                self.shared.gate.yield_to_interactive();
+                Ok(self.shared.inner.lock()?)
+            }
+        }
+    }
+
+    fn check_writable(&self, op: &str) -> DbResult<()> {
+        if self.options.read_only {
+            return Err(DbErr::PermissionDenied(op.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Open the database kept in IndexedDB. The frames committed are read and replayed
+    /// into the memory asynchronously, `loaded` is called with the database after them.
+    /// The errors before the frames are read are returned.
+    #[cfg(feature = "indexeddb")]
+    pub fn open_indexeddb(
+        ctx: IndexedDbContext,
+        loaded: impl FnOnce(DbResult<Database>) + 'static,
+    ) -> DbResult<()> {
+        Database::open_indexeddb_with_config(ctx, Config::default(), loaded)
+    }
+
+    #[cfg(feature = "indexeddb")]
+    pub fn open_indexeddb_with_config(
+        ctx: IndexedDbContext,
+        config: Config,
+        loaded: impl FnOnce(DbResult<Database>) + 'static,
+    ) -> DbResult<()> {
+        DatabaseInner::open_indexeddb(ctx, config, move |inner| {
+            loaded(inner.map(Database::from_inner));
         })
     }
 
+    /// Open the database in the Origin Private File System of the browser. The pages are
+    /// read and written synchronously by the handles, so it's opened at once, but only
+    /// in a Web Worker, where the sync access handles are available.
+    #[cfg(feature = "opfs")]
+    pub fn open_opfs(ctx: OpfsContext) -> DbResult<Database> {
+        Database::open_opfs_with_config(ctx, Config::default())
+    }
+
+    #[cfg(feature = "opfs")]
+    pub fn open_opfs_with_config(ctx: OpfsContext, config: Config) -> DbResult<Database> {
+        let inner = DatabaseInner::open_opfs(ctx, config)?;
+
+        Ok(Database::from_inner(inner))
+    }
+
     pub fn open_memory() -> DbResult<Database> {
         Database::open_memory_with_config(Config::default())
     }
@@ -105,41 +367,488 @@ impl Database {
     pub fn open_memory_with_config(config: Config) -> DbResult<Database> {
         let inner = DatabaseInner::open_memory_with_config(config)?;
 
-        Ok(Database {
-            inner: Mutex::new(inner),
-        })
+        Ok(Database::from_inner(inner))
+    }
+
+    /// Load a database file into memory, the changes are kept in memory
+    /// until they are saved by [`Database::persist_to`].
+    ///
+    /// The journal left by the last process is merged into the file at first,
+    /// it fails with [`DbErr::DatabaseOccupied`] if the file is opened by another process.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{doc, Document};
+    /// # let path = polodb_core::test_utils::mk_db_path("doc-test-open-memory-from-file");
+    /// # let _ = std::fs::remove_file(&path);
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+    /// db.persist_to(&path).unwrap();
+    ///
+    /// let db = Database::open_memory_from_file(&path).unwrap();
+    /// assert_eq!(db.collection::<Document>("books").count_documents().unwrap(), 1);
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn open_memory_from_file<P: AsRef<Path>>(path: P) -> DbResult<Database> {
+        Database::open_memory_from_file_with_config(path, Config::default())
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(feature = "fs")]
+    pub fn open_memory_from_file_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<Database> {
+        let ctx = DbContext::open_memory_from_file(path.as_ref(), config)?;
+        Ok(Database::from_inner(DatabaseInner::new(ctx)))
+    }
+
+    #[cfg(feature = "fs")]
     pub fn open_file<P: AsRef<Path>>(path: P) -> DbResult<Database>  {
         Database::open_file_with_config(path, Config::default())
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
+    #[cfg(feature = "fs")]
     pub fn open_file_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<Database>  {
         let inner = DatabaseInner::open_file_with_config(path, config)?;
 
-        Ok(Database {
-            inner: Mutex::new(inner)
-        })
+        Ok(Database::from_inner(inner))
+    }
+
+    /// Read the header and the collection catalog of a database file without opening it.
+    ///
+    /// The file is not locked, so a database opened by another process can be inspected,
+    /// and the journal is not replayed, the changes not checkpointed are not seen.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{doc, Document};
+    /// # let path = polodb_core::test_utils::mk_db_path("doc-test-inspect-header");
+    /// # let _ = std::fs::remove_file(&path);
+    /// # let _ = std::fs::remove_file(polodb_core::test_utils::mk_journal_path("doc-test-inspect-header"));
+    ///
+    /// let db = Database::open_file(&path).unwrap();
+    /// db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+    /// db.checkpoint().unwrap();
+    ///
+    /// let header = Database::inspect_header(&path).unwrap();
+    /// assert_eq!(header.page_size.get(), 4096);
+    /// assert_eq!(header.collections[0].name, "books");
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn inspect_header<P: AsRef<Path>>(path: P) -> DbResult<HeaderDump> {
+        DbContext::inspect_header(path.as_ref())
+    }
+
+    /// Open the database file, and return the report of the recovery
+    /// of the journal left by the last process.
+    ///
+    /// ```rust
+    /// use polodb_core::{Config, Database, RecoveryState};
+    /// # let path = polodb_core::test_utils::mk_db_path("doc-test-recovery-report");
+    /// # let _ = std::fs::remove_file(&path);
+    /// # let _ = std::fs::remove_file(polodb_core::test_utils::mk_journal_path("doc-test-recovery-report"));
+    ///
+    /// let (db, report) = Database::open_file_with_report(&path, Config::default()).unwrap();
+    /// assert_eq!(report.state, RecoveryState::Clean);
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn open_file_with_report<P: AsRef<Path>>(path: P, config: Config) -> DbResult<(Database, RecoveryReport)> {
+        let (ctx, report) = DbContext::open_file_with_report(path.as_ref(), config)?;
+        let db = Database::from_inner(DatabaseInner::new(ctx));
+        Ok((db, report))
+    }
+
+    /// Open the database stored in an object storage under `prefix`, it's created
+    /// if the prefix is empty. The pages are fetched lazily and cached in memory,
+    /// the pages written are uploaded by [`Database::checkpoint`] or when the database
+    /// is closed, so a read-mostly dataset can be shared across machines.
+    ///
+    /// Only one process should write the database. A reader sees the version uploaded
+    /// when it's opened, the objects of the old versions are not deleted.
+    ///
+    /// ```rust
+    /// use std::collections::HashMap;
+    /// use std::sync::{Arc, Mutex};
+    /// use polodb_core::{Config, Database, DbResult, ObjectStore};
+    /// use polodb_core::bson::{doc, Document};
+    ///
+    /// #[derive(Default)]
+    /// struct MemoryStore(Mutex<HashMap<String, Vec<u8>>>);
+    ///
+    /// impl ObjectStore for MemoryStore {
+    ///     fn get(&self, key: &str) -> DbResult<Option<Vec<u8>>> {
+    ///         Ok(self.0.lock().unwrap().get(key).cloned())
+    ///     }
+    ///
+    ///     fn put(&self, key: &str, data: &[u8]) -> DbResult<()> {
+    ///         self.0.lock().unwrap().insert(key.to_string(), data.to_vec());
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let store = Arc::new(MemoryStore::default());
+    /// {
+    ///     let db = Database::open_object_store(store.clone(), "books", Config::default()).unwrap();
+    ///     db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+    /// }
+    ///
+    /// let db = Database::open_object_store(store, "books", Config::default()).unwrap();
+    /// assert_eq!(db.collection::<Document>("books").count_documents().unwrap(), 1);
+    /// ```
+    pub fn open_object_store(store: Arc<dyn ObjectStore>, prefix: &str, config: Config) -> DbResult<Database> {
+        let ctx = DbContext::open_object_store(store, prefix, config)?;
+        Ok(Database::from_inner(DatabaseInner::new(ctx)))
+    }
+
+    #[cfg(feature = "fs")]
+    pub(crate) fn open_file_with_page_cache<P: AsRef<Path>>(path: P, config: Config, page_cache: PageCache) -> DbResult<Database>  {
+        let ctx = DbContext::open_file_with_page_cache(path.as_ref(), config, page_cache)?;
+        Ok(Database::from_inner(DatabaseInner::new(ctx)))
+    }
+
+    /// Merge the journal into the main file, or upload the pages written
+    /// to the object store.
+    ///
+    /// Return [`DbErr::Busy`] if a transaction or a session is running.
+    pub fn checkpoint(&self) -> DbResult<()> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.checkpoint()
+    }
+
+    /// Fetch the pages of the object store ahead into the page cache, at most
+    /// [`Config::page_cache_size`] of them. The lock of the database is not held
+    /// while they are downloaded, so the other operations are not blocked by
+    /// the slow fetches. Return the count of the pages fetched, 0 for the other backends.
+    ///
+    /// The pages changed by the checkpoints meanwhile are dropped.
+    pub fn prefetch(&self) -> DbResult<usize> {
+        let plan = {
+            let inner = self.lock_inner()?;
+            inner.ctx.prefetch_plan()?
+        };
+        let plan = match plan {
+            Some(plan) => plan,
+            None => return Ok(0),
+        };
+        let pages = plan.fetch()?;
+        let count = pages.len();
+
+        let inner = self.lock_inner()?;
+        inner.ctx.put_prefetched(&plan, pages)?;
+        Ok(count)
+    }
+
+    /// Change [`Config::journal_full_size`] of the opened database, it takes effect
+    /// from the next commit. Set it to `u64::MAX` to checkpoint only by [`Database::checkpoint`],
+    /// an app can checkpoint when it's idle instead of in the middle of the writes.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{doc, Document};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.set_journal_full_size(u64::MAX).unwrap();
+    /// db.collection::<Document>("logs").insert_one(doc! { "level": "info" }).unwrap();
+    ///
+    /// // idle now
+    /// db.checkpoint().unwrap();
+    /// ```
+    pub fn set_journal_full_size(&self, size: u64) -> DbResult<()> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.set_journal_full_size(size)
+    }
+
+    /// Save an in-memory database as a database file in the standard format,
+    /// which can be opened by [`Database::open_file`] or [`Database::open_memory_from_file`].
+    /// The committed data is saved, the running transactions are not.
+    ///
+    /// The file is replaced at once, it must not be opened by another database.
+    /// Return [`DbErr::NotSupportedByBackend`] if the database is not in memory.
+    #[cfg(feature = "fs")]
+    pub fn persist_to<P: AsRef<Path>>(&self, path: P) -> DbResult<()> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.persist_to(path.as_ref())
+    }
+
+    /// Rewrite the collections and their indexes into as few pages as they need,
+    /// and shrink the database to them. The space of the deleted documents is reused
+    /// by the later writes anyway, compact the database to return it to the file system.
+    ///
+    /// The documents are rebuilt in memory before they are written back through the journal,
+    /// so it needs the memory and the journal space of the data. It returns [`DbErr::Busy`]
+    /// if a session is started or a transaction is running. The file backend truncates the file
+    /// by the checkpoint after it, the other backends keep their storage but reuse the pages.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{doc, Document};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let logs = db.collection::<Document>("logs");
+    /// for i in 0..1000 {
+    ///     logs.insert_one(doc! { "_id": i, "message": "a message to delete" }).unwrap();
+    /// }
+    /// logs.delete_many(doc! { "_id": { "$gte": 10 } }).unwrap();
+    ///
+    /// let result = db.compact().unwrap();
+    /// assert!(result.size_after < result.size_before);
+    /// assert_eq!(logs.count_documents().unwrap(), 10);
+    /// ```
+    pub fn compact(&self) -> DbResult<CompactResult> {
+        self.check_writable("Compact")?;
+        let mut inner = self.lock_inner()?;
+        inner.ctx.compact()
+    }
+
+    /// Copy the committed data into a new database file at `path`, which can be opened
+    /// by [`Database::open_file`] with the same config, while the database is read and written.
+    ///
+    /// The pages are copied from a snapshot taken at first, in batches, and the lock of
+    /// the database is released between the batches. The checkpoints return [`DbErr::Busy`]
+    /// until the backup is done, so the journal grows meanwhile. The backup is encrypted and
+    /// compressed by the config of the database. The attached databases are not copied.
+    ///
+    /// It fails if the file exists, and the file is removed if the backup fails.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{doc, Document};
+    /// # let path = polodb_core::test_utils::mk_db_path("doc-test-backup-to");
+    /// # let _ = std::fs::remove_file(&path);
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+    /// db.backup_to(&path).unwrap();
+    ///
+    /// let backup = Database::open_file(&path).unwrap();
+    /// assert_eq!(backup.collection::<Document>("books").count_documents().unwrap(), 1);
+    /// ```
+    #[cfg(feature = "fs")]
+    pub fn backup_to<P: AsRef<Path>>(&self, path: P) -> DbResult<()> {
+        let (snapshot_id, page_count, page_size, config) = {
+            let mut inner = self.lock_inner()?;
+            let (snapshot_id, page_count) = inner.ctx.start_backup()?;
+            (snapshot_id, page_count, inner.ctx.page_size(), inner.ctx.config())
+        };
+
+        let result = BackupFile::create(path.as_ref(), page_size, page_count, &config)
+            .and_then(|mut backup| {
+                match self.copy_backup_pages(&mut backup, &snapshot_id, page_count) {
+                    Ok(()) => backup.finish(),
+                    Err(err) => {
+                        backup.abort();
+                        Err(err)
+                    }
+                }
+            });
+
+        let mut inner = self.lock_inner()?;
+        inner.ctx.finish_backup(&snapshot_id)?;
+        result
+    }
+
+    #[cfg(feature = "fs")]
+    fn copy_backup_pages(&self, backup: &mut BackupFile, snapshot_id: &ObjectId, page_count: u32) -> DbResult<()> {
+        let mut start = 0;
+        while start < page_count {
+            let end = page_count.min(start + BACKUP_BATCH_PAGES);
+            let pages = {
+                let mut inner = self.lock_inner()?;
+                inner.ctx.read_backup_pages(snapshot_id, start..end)?
+            };
+            backup.write_pages(pages)?;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Attach another database file under `alias`.
+    ///
+    /// The collections of the attached database are referenced by
+    /// `<alias>.<collection>`, for example `db.collection("archive.orders")`.
+    /// The attached database doesn't share the sessions of this database.
+    #[cfg(feature = "fs")]
+    pub fn attach<P: AsRef<Path>>(&self, alias: &str, path: P) -> DbResult<()> {
+        // check before opening, the file may be locked by the attached one
+        if self.shared.attached.read()?.contains_key(alias) {
+            return Err(DbErr::AttachAliasAlreadyExists(alias.to_string()));
+        }
+        let db = Database::open_file(path)?;
+        self.attach_database(alias, db)
+    }
+
+    /// Attach an opened database under `alias`.
+    pub fn attach_database(&self, alias: &str, db: Database) -> DbResult<()> {
+        if alias.is_empty() || alias.contains('.') {
+            return Err(DbErr::IllegalAttachAlias(alias.to_string()));
+        }
+        let mut attached = self.shared.attached.write()?;
+        if attached.contains_key(alias) {
+            return Err(DbErr::AttachAliasAlreadyExists(alias.to_string()));
+        }
+        attached.insert(alias.to_string(), Arc::new(db));
+        Ok(())
+    }
+
+    /// Detach the database attached under `alias`.
+    pub fn detach(&self, alias: &str) -> DbResult<()> {
+        let mut attached = self.shared.attached.write()?;
+        match attached.remove(alias) {
+            Some(_) => Ok(()),
+            None => Err(DbErr::AttachAliasNotFound(alias.to_string())),
+        }
+    }
+
+    /// Gets the aliases of the attached databases.
+    pub fn list_attached(&self) -> Vec<String> {
+        let attached = self.shared.attached.read().unwrap();
+        attached.keys().cloned().collect()
+    }
+
+    /// Move the documents of the collection to the database attached under `alias`,
+    /// a stub is left in this database to route the operations of the collection
+    /// to the attached one, so the file of this database keeps small.
+    ///
+    /// The documents are inserted into the collection of the same name in the attached
+    /// database, then the pages of the collection are freed in this database.
+    /// If the attached database is not attached when the stub is accessed,
+    /// [`DbErr::CollectionArchived`] is returned.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.attach_database("cold", Database::open_memory().unwrap()).unwrap();
+    ///
+    /// let logs = db.collection::<Document>("logs");
+    /// logs.insert_one(doc! { "level": "info" }).unwrap();
+    /// db.archive_collection("logs", "cold").unwrap();
+    ///
+    /// assert_eq!(logs.count_documents().unwrap(), 1);
+    /// assert_eq!(db.collection::<Document>("cold.logs").count_documents().unwrap(), 1);
+    /// ```
+    pub fn archive_collection(&self, col_name: &str, alias: &str) -> DbResult<()> {
+        self.check_writable("ArchiveCollection")?;
+        if self.resolve_alias(col_name).is_some() {
+            return Err(DbErr::IllegalCollectionName(col_name.to_string()));
+        }
+        let archive = match self.shared.attached.read()?.get(alias) {
+            Some(db) => db.clone(),
+            None => return Err(DbErr::AttachAliasNotFound(alias.to_string())),
+        };
+
+        // hold the lock to keep the documents unchanged until the stub is written
+        let mut inner = self.lock_inner()?;
+        let col_spec = match inner.get_collection_meta_by_name(col_name, false, None)? {
+            Some(col_spec) => col_spec,
+            None => return Err(DbErr::CollectionNotFound(col_name.to_string())),
+        };
+        let docs: Vec<Document> = inner.find_many(col_name, None, FindOptions::default(), None)?;
+
+        let options = CreateCollectionOptions {
+            compression: col_spec.info.compression,
+            version_field: col_spec.info.version_field.clone(),
+            append_only: col_spec.info.append_only,
+            // the referenced collections are not moved with it
+            foreign_keys: Vec::new(),
+            generated_fields: col_spec.info.generated_fields.clone(),
+        };
+        match archive.create_collection_with_options(col_name, options) {
+            Ok(()) | Err(DbErr::CollectionAlreadyExits(_)) => (),
+            Err(err) => return Err(err),
+        }
+        if !docs.is_empty() {
+            archive.insert_many::<Document>(col_name, &docs, InsertManyOptions::default(), None)?;
+        }
+
+        inner.ctx.mark_archived(col_name, alias, None)
+    }
+
+    /// Return the attached database and the collection name in it
+    /// if `col_name` is prefixed by an alias, or the collection is archived.
+    pub(crate) fn resolve_attached(&self, col_name: &str) -> Option<(Arc<Database>, String)> {
+        self.resolve_alias(col_name)
+            .or_else(|| self.resolve_archived(col_name))
+    }
+
+    fn resolve_alias(&self, col_name: &str) -> Option<(Arc<Database>, String)> {
+        let (alias, name) = col_name.split_once('.')?;
+        let attached = self.shared.attached.read().unwrap();
+        attached
+            .get(alias)
+            .map(|db| (db.clone(), name.to_string()))
+    }
+
+    // The stub is read only if there are attached databases,
+    // the stub without the attached database is reported by the context.
+    fn resolve_archived(&self, col_name: &str) -> Option<(Arc<Database>, String)> {
+        let attached = self.shared.attached.read().unwrap();
+        if attached.is_empty() {
+            return None;
+        }
+        let alias = {
+            let mut inner = self.lock_inner().unwrap();
+            inner.ctx.archived_in(col_name).ok()??
+        };
+        attached
+            .get(&alias)
+            .map(|db| (db.clone(), col_name.to_string()))
     }
 
     /// Return the metrics object of the database
     pub fn metrics(&self) -> Metrics {
-        let inner = self.inner.lock().unwrap();
+        let inner = self.lock_inner().unwrap();
         inner.ctx.metrics()
     }
 
+    pub(crate) fn config(&self) -> DbResult<Arc<Config>> {
+        let inner = self.lock_inner()?;
+        Ok(inner.ctx.config())
+    }
+
+    /// The query of the documents whose [`EXPIRE_AT_FIELD`] is a date earlier than now,
+    /// by the clock of the config.
+    pub(crate) fn expired_query(&self) -> DbResult<Document> {
+        let now = self.config()?.clock().now();
+        Ok(doc! {
+            EXPIRE_AT_FIELD: { "$lt": bson::DateTime::from_millis(now.as_millis() as i64) },
+        })
+    }
+
     /// Creates a new collection in the database with the given `name`.
     pub fn create_collection(&self, name: &str) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
-        inner.create_collection(name, None)
+        self.check_writable("CreateCollection")?;
+        if let Some((db, name)) = self.resolve_attached(name) {
+            return db.create_collection(&name);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.create_collection(name, &CreateCollectionOptions::default(), None)
+    }
+
+    /// Creates a new collection in the database with the given `name` and `options`.
+    ///
+    /// ```rust
+    /// use polodb_core::{Compression, CreateCollectionOptions, Database};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.create_collection_with_options("logs", CreateCollectionOptions {
+    ///     compression: Compression::Zstd { level: 3 },
+    ///     ..Default::default()
+    /// }).unwrap();
+    /// ```
+    pub fn create_collection_with_options(&self, name: &str, options: CreateCollectionOptions) -> DbResult<()> {
+        self.check_writable("CreateCollection")?;
+        if let Some((db, name)) = self.resolve_attached(name) {
+            return db.create_collection_with_options(&name, options);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.create_collection(name, &options, None)
     }
 
     /// Creates a new collection in the database with the given `name`.
     pub fn create_collection_with_session(&self, name: &str, session: &mut ClientSession) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
-        inner.create_collection(name, Some(&session.id))
+        self.check_writable("CreateCollection")?;
+        let mut inner = self.lock_inner()?;
+        inner.create_collection(name, &CreateCollectionOptions::default(), Some(&session.id))
     }
 
     ///
@@ -152,96 +861,423 @@ impl Database {
         Collection::new(self, col_name)
     }
 
+    /// Return a namespace of the key-value API, for the settings and the flags
+    /// which don't need the documents of a collection.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let settings = db.kv("settings");
+    /// settings.set("theme", "dark").unwrap();
+    ///
+    /// assert_eq!(settings.get("theme").unwrap(), Some("dark".into()));
+    /// assert!(settings.delete("theme").unwrap());
+    /// assert_eq!(settings.get("theme").unwrap(), None);
+    /// ```
+    pub fn kv(&self, namespace: &str) -> KvNamespace {
+        KvNamespace::new(self, namespace)
+    }
+
     pub fn start_session(&self) -> DbResult<ClientSession> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         let session_id = inner.ctx.start_session()?;
         Ok(ClientSession::new(self, session_id))
     }
 
     pub(crate) fn start_transaction(&self, ty: Option<TransactionType>, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.start_transaction(ty, session_id)
     }
 
     pub(crate) fn commit(&self, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.commit(session_id)
     }
 
     pub(crate) fn rollback(&self, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.rollback(session_id)
     }
 
     pub(crate) fn drop_session(&self, session_id: &ObjectId) -> DbResult<()> {
-        let mut inner = self.inner.lock().unwrap();
+        let mut inner = self.lock_inner().unwrap();
         inner.drop_session(session_id)
     }
 
     pub fn dump(&self) -> DbResult<FullDump> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.dump()
     }
 
+    /// Decode the page of `page_id` by its type, see [`crate::debug`].
+    /// The page is read in a transaction, so the pages in the journal are read
+    /// instead of the ones in the main file.
+    ///
+    /// Return [`DbErr::PageIdOutOfRange`] if the page is beyond the end of the database.
+    #[cfg(feature = "debug")]
+    pub fn debug_page(&self, page_id: u32) -> DbResult<crate::debug::PageDebug> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.debug_page(page_id)
+    }
+
+    /// The frames in the journal not merged into the main file yet.
+    /// Return [`DbErr::NotSupportedByBackend`] if the database has no journal.
+    #[cfg(feature = "debug")]
+    pub fn debug_journal_frames(&self) -> DbResult<Vec<crate::dump::JournalFrameDump>> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.journal_frames()
+    }
+
     /// Gets the names of the collections in the database.
     pub fn list_collection_names(&self) -> DbResult<Vec<String>> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.list_collection_names()
     }
 
     /// Gets the names of the collections in the database.
     pub fn list_collection_names_with_session(&self, session: &mut ClientSession) -> DbResult<Vec<String>> {
-        let mut inner = self.inner.lock()?;
+        let mut inner = self.lock_inner()?;
         inner.list_collection_names_with_session(session)
     }
 
+    /// Append a middleware to the request routing layer.
+    ///
+    /// The requests handled by [`Database::handle_request`] and [`Database::handle_request_doc`]
+    /// go through the middlewares in the order they are added before being executed.
+    pub fn use_middleware<M: Middleware + 'static>(&self, middleware: M) {
+        let mut middlewares = self.shared.middlewares.write().unwrap();
+        middlewares.push(Arc::new(middleware));
+    }
+
+    /// Register a custom accumulator, which is used in `$group`
+    /// by `{ "$accumulator": { "name": <name>, "input": <expression> } }`.
+    ///
+    /// The accumulator registered with the same name is replaced.
+    pub fn register_accumulator(&self, name: &str, accumulator: Arc<dyn CustomAccumulator>) {
+        let mut accumulators = self.shared.accumulators.write().unwrap();
+        accumulators.insert(name.to_string(), accumulator);
+    }
+
+    pub(crate) fn custom_accumulator(&self, name: &str) -> Option<Arc<dyn CustomAccumulator>> {
+        let accumulators = self.shared.accumulators.read().unwrap();
+        accumulators.get(name).cloned()
+    }
+
+    /// Register a function, which is called from the `$expr` of the queries
+    /// and the aggregation expressions by `{ "$fn": { "name": <name>, "args": [...] } }`.
+    ///
+    /// ```rust
+    /// use std::sync::Arc;
+    /// use polodb_core::{Database, DbErr};
+    /// use polodb_core::bson::{Bson, Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// db.register_function("isEven", Arc::new(|args: &[Bson]| match args.first() {
+    ///     Some(Bson::Int32(i)) => Ok(Bson::Boolean(i % 2 == 0)),
+    ///     _ => Err(DbErr::ValidationError("isEven takes an integer".into())),
+    /// }));
+    ///
+    /// let numbers = db.collection::<Document>("numbers");
+    /// numbers.insert_many(vec![doc! { "n": 1 }, doc! { "n": 2 }]).unwrap();
+    /// let result = numbers.find_many(doc! {
+    ///     "$expr": { "$fn": { "name": "isEven", "args": ["$n"] } },
+    /// }).unwrap();
+    /// assert_eq!(result.len(), 1);
+    /// ```
+    pub fn register_function(&self, name: &str, function: ExprFunction) {
+        let mut functions = self.shared.functions.write().unwrap();
+        functions.insert(name.to_string(), function);
+    }
+
+    pub(crate) fn functions(&self) -> HashMap<String, ExprFunction> {
+        let functions = self.shared.functions.read().unwrap();
+        functions.clone()
+    }
+
+    /// Register a migration of the data applied by [`Database::migrate`],
+    /// see [`migrations`](crate::migrations).
+    ///
+    /// The migration registered with the same version is replaced.
+    pub fn register_migration(&self, migration: Migration) {
+        let mut migrations = self.shared.migrations.write().unwrap();
+        migrations.insert(migration.version(), Arc::new(migration));
+    }
+
+    /// Apply the registered migrations not applied to the database yet, in the order of the versions.
+    /// It's supposed to be called after the database is opened, before the other operations.
+    ///
+    /// Every migration is applied in its own transaction with the record of its version.
+    /// If a migration fails, the ones before it stay applied, and the error is returned.
+    /// A pending migration older than the latest applied version fails with [`DbErr::MigrationOutOfOrder`].
+    pub fn migrate(&self) -> DbResult<MigrateResult> {
+        let registered: Vec<Arc<Migration>> = {
+            let migrations = self.shared.migrations.read().unwrap();
+            migrations.values().cloned().collect()
+        };
+        migrations::migrate(self, &registered)
+    }
+
+    /// The versions of the migrations applied to the database, in the ascending order.
+    pub fn applied_migrations(&self) -> DbResult<Vec<u64>> {
+        migrations::applied_versions(self)
+    }
+
     /// handle request for database
     pub fn handle_request<R: Read>(&self, pipe_in: &mut R) -> DbResult<HandleRequestResult> {
-        let mut inner = self.inner.lock()?;
-        inner.handle_request(pipe_in)
+        let value = receive_request_body(pipe_in)?;
+        self.handle_request_doc(value)
     }
 
     pub fn handle_request_doc(&self, value: Bson) -> DbResult<HandleRequestResult> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.handle_request_doc(value)
+        let command_message = bson::from_bson::<CommandMessage>(value)?;
+
+        // clone the chain to avoid holding the lock while executing
+        let middlewares = self.shared.middlewares.read()?.clone();
+        let mut executor = |mut command: CommandMessage| {
+            if command.is_write() {
+                self.check_writable(command.name())?;
+            }
+            let attached = command
+                .ns()
+                .and_then(|ns| self.resolve_attached(ns));
+            if let Some((db, name)) = attached {
+                command.set_ns(name);
+                let mut inner = db.lock_inner()?;
+                return inner.handle_command(command);
+            }
+            let mut inner = self.lock_inner()?;
+            inner.handle_command(command)
+        };
+
+        Next::new(&middlewares, &mut executor).run(command_message)
     }
 
     pub(super) fn count_documents(&self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<u64> {
-        let mut inner = self.inner.lock()?;
-        inner.count_documents(col_name, session_id)
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.count_documents(&name, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, false, |inner| inner.count_documents(col_name, session_id))
+    }
+
+    pub(super) fn pin_collection(&self, col_name: &str) -> DbResult<PinResult> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.pin_collection(&name);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.ctx.pin_collection(col_name)
+    }
+
+    pub(super) fn unpin_collection(&self, col_name: &str) -> DbResult<()> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.unpin_collection(&name);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.ctx.unpin_collection(col_name)
+    }
+
+    pub(super) fn collection_stats(&self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<CollectionStats> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.collection_stats(&name, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.collection_stats(col_name, session_id)
     }
 
     pub(super) fn find_one<T: DeserializeOwned>(
         &self, col_name: &str,
         filter: impl Into<Option<Document>>,
+        options: FindOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<Option<T>> {
-        let mut inner = self.inner.lock()?;
-        inner.find_one(col_name, filter, session_id)
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.find_one(&name, filter, options, session_id);
+        }
+        let filter = filter.into();
+        if let Some(query) = filter.as_ref().filter(|query| aggregation::has_expr(query)) {
+            let docs = self.find_by_evaluator(col_name, query, &options, session_id)?;
+            return match docs.into_iter().next() {
+                Some(doc) => Ok(Some(from_stored_document(col_name, doc)?)),
+                None => Ok(None),
+            };
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, false, |inner| inner.find_one(col_name, filter, options, session_id))
+    }
+
+    pub(super) fn find_many_by_ids<T: DeserializeOwned>(
+        &self, col_name: &str,
+        ids: Vec<Bson>,
+        session_id: Option<&ObjectId>
+    ) -> DbResult<FindByIdsResult<T>> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.find_many_by_ids(&name, ids, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, false, |inner| inner.find_many_by_ids(col_name, ids, session_id))
+    }
+
+    pub(super) fn explain(
+        &self, col_name: &str,
+        filter: Option<Document>,
+        options: FindOptions,
+        verbosity: ExplainVerbosity,
+        session_id: Option<&ObjectId>
+    ) -> DbResult<Explain> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.explain(&name, filter, options, verbosity, session_id);
+        }
+        if filter.as_ref().map(aggregation::has_expr).unwrap_or(false) {
+            return Err(DbErr::ValidationError("explain: the query with $expr is not supported".into()));
+        }
+        let mut inner = self.lock_inner()?;
+        inner.explain(col_name, filter, options, verbosity, session_id)
     }
 
-    pub(super) fn find_many<T: DeserializeOwned>(
+    pub(super) fn analyze_schema(&self, col_name: &str, sample_size: usize, session_id: Option<&ObjectId>) -> DbResult<SchemaAnalysis> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.analyze_schema(&name, sample_size, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.analyze_schema(col_name, sample_size, session_id)
+    }
+
+    pub(crate) fn find_many<T: DeserializeOwned>(
         &self, col_name: &str,
         filter: impl Into<Option<Document>>,
+        options: FindOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<Vec<T>> {
-        let mut inner = self.inner.lock()?;
-        inner.find_many(col_name, filter, session_id)
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.find_many(&name, filter, options, session_id);
+        }
+        let filter = filter.into();
+        if let Some(query) = filter.as_ref().filter(|query| aggregation::has_expr(query)) {
+            let docs = self.find_by_evaluator(col_name, query, &options, session_id)?;
+            let mut result = Vec::with_capacity(docs.len());
+            for doc in docs {
+                result.push(from_stored_document(col_name, doc)?);
+            }
+            return Ok(result);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, false, |inner| inner.find_many(col_name, filter, options, session_id))
+    }
+
+    // The query with `$expr` is evaluated on the scanned documents,
+    // because the VM can't call the registered functions.
+    fn find_by_evaluator(
+        &self,
+        col_name: &str,
+        query: &Document,
+        options: &FindOptions,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Vec<Document>> {
+        let projection = options.projection.as_ref().map(|p| Projection::parse(p, Some(query))).transpose()?;
+        let docs: Vec<Document> = {
+            let mut inner = self.lock_inner()?;
+            inner.track(col_name, false, |inner| inner.find_many(col_name, None, FindOptions::default(), session_id))?
+        };
+        let operator_mode = self.config()?.operator_mode;
+        let mut result = aggregation::filter_documents(query, docs, self.functions(), operator_mode)?;
+        let sort = parse_find_sort(options)?;
+        let limit = options.limit.filter(|limit| *limit > 0);
+        sort_and_slice(&mut result, sort.as_ref(), options.skip.unwrap_or(0), limit);
+        if let Some(projection) = projection {
+            result = result.into_iter().map(|doc| projection.apply(doc)).collect();
+        }
+        Ok(result)
+    }
+
+    pub(super) fn aggregate(
+        &self,
+        col_name: &str,
+        pipeline: &[Document],
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Vec<Document>> {
+        match session_id {
+            Some(session_id) => self.aggregate_in_snapshot(col_name, pipeline, session_id),
+            None => {
+                let session_id = self.acquire_session()?;
+                let result = self.aggregate_in_snapshot(col_name, pipeline, &session_id);
+                self.release_session(session_id)?;
+                result
+            }
+        }
+    }
+
+    fn acquire_session(&self) -> DbResult<ObjectId> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.acquire_session()
+    }
+
+    fn release_session(&self, session_id: ObjectId) -> DbResult<()> {
+        let mut inner = self.lock_inner()?;
+        inner.ctx.release_session(session_id)
+    }
+
+    pub(super) fn subscribe(&self, col_name: &str, filter: Option<Document>) -> DbResult<Subscription> {
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.subscribe(&name, filter);
+        }
+        let operator_mode = self.config()?.operator_mode;
+        let filter = filter.map(|query| DocumentFilter::new(query, self.functions(), operator_mode));
+        let mut inner = self.lock_inner()?;
+        inner.check_open_cursors()?;
+        Ok(inner.subscriptions.subscribe(col_name, filter))
+    }
+
+    // All the collections are read at the same snapshot by a read transaction
+    // held for the whole pipeline, unless the session is in a transaction already.
+    fn aggregate_in_snapshot(
+        &self,
+        col_name: &str,
+        pipeline: &[Document],
+        session_id: &ObjectId,
+    ) -> DbResult<Vec<Document>> {
+        let pinned = match self.start_transaction(Some(TransactionType::Read), Some(session_id)) {
+            Ok(()) => true,
+            Err(DbErr::StartTransactionInAnotherTransaction) => false,
+            Err(err) => return Err(err),
+        };
+
+        let ctx = AggregationContext::new(self, Some(session_id));
+        let result = aggregation::aggregate(&ctx, col_name, pipeline);
+
+        if pinned {
+            match &result {
+                Ok(_) => self.commit(Some(session_id))?,
+                Err(_) => {
+                    let _ = self.rollback(Some(session_id));
+                }
+            }
+        }
+        result
     }
 
     pub(super) fn insert_one<T: Serialize>(&self, col_name: &str, doc: impl Borrow<T>, session_id: Option<&ObjectId>) -> DbResult<InsertOneResult> {
-        let mut inner = self.inner.lock()?;
-        inner.insert_one(col_name, doc, session_id)
+        self.check_writable("Insert")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.insert_one::<T>(&name, doc, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.insert_one(col_name, doc, session_id))
     }
 
     pub(super) fn insert_many<T: Serialize>(
         &self,
         col_name: &str,
         docs: impl IntoIterator<Item = impl Borrow<T>>,
+        options: InsertManyOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<InsertManyResult> {
-        let mut inner = self.inner.lock()?;
-        inner.insert_many(col_name, docs, session_id)
+        self.check_writable("Insert")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.insert_many::<T>(&name, docs, options, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.insert_many(col_name, docs, &options, session_id))
     }
 
     pub(super) fn update_one(
@@ -249,10 +1285,30 @@ impl Database {
         col_name: &str,
         query: Document,
         update: Document,
+        options: UpdateOptions,
         session_id: Option<&ObjectId>,
     ) -> DbResult<UpdateResult> {
-        let mut inner = self.inner.lock()?;
-        inner.update_one(col_name, query, update, session_id)
+        self.check_writable("Update")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.update_one(&name, query, update, options, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.update_one(col_name, query, update, &options, session_id))
+    }
+
+    pub(super) fn update_one_if(
+        &self,
+        col_name: &str,
+        query: Document,
+        update: Document,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<UpdateIfResult> {
+        self.check_writable("Update")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.update_one_if(&name, query, update, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.update_one_if(col_name, query, update, session_id))
     }
 
     pub(super) fn update_many(
@@ -260,63 +1316,185 @@ impl Database {
         col_name: &str,
         query: Document,
         update: Document,
+        options: UpdateOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<UpdateResult> {
-        let mut inner = self.inner.lock()?;
-        inner.update_many(col_name, query, update, session_id)
+        self.check_writable("Update")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.update_many(&name, query, update, options, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.update_many(col_name, query, update, &options, session_id))
     }
 
     pub(super) fn delete_one(&self, col_name: &str, query: Document, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
-        let mut inner = self.inner.lock()?;
-        inner.delete_one(col_name, query, session_id)
+        self.check_writable("Delete")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.delete_one(&name, query, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.delete_one(col_name, query, session_id))
+    }
+
+    pub(super) fn delete_many(
+        &self,
+        col_name: &str,
+        query: Document,
+        mut options: DeleteOptions,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<DeleteResult> {
+        self.check_writable("Delete")?;
+        if options.batch_size.is_none() {
+            options.batch_size = self.options.delete_batch_size;
+        }
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.delete_many(&name, query, options, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.delete_many(col_name, query, &options, session_id))
+    }
+
+    /// Delete at most `batch_size` documents in one transaction,
+    /// the lock of the database is released after the batch.
+    #[cfg(all(feature = "maintenance", not(target_arch = "wasm32")))]
+    pub(crate) fn delete_batch(&self, col_name: &str, query: Document, batch_size: usize) -> DbResult<usize> {
+        self.check_writable("Delete")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.delete_batch(&name, query, batch_size);
+        }
+        let mut inner = self.lock_inner()?;
+        let before = inner.find_for_subscriptions(col_name, &query, Some(batch_size), None)?;
+        match inner.ctx.delete(col_name, query, Some(batch_size), None) {
+            Err(DbErr::CollectionNotFound(_)) => Ok(0),
+            Ok(count) => {
+                inner.publish_deletes(col_name, before, None);
+                Ok(count)
+            }
+            result => result,
+        }
+    }
+
+    /// Count and persist the stats of the collection created by the older versions.
+    #[cfg(all(feature = "maintenance", not(target_arch = "wasm32")))]
+    pub(crate) fn refresh_stats(&self, col_name: &str) -> DbResult<bool> {
+        let mut inner = self.lock_inner()?;
+        match inner.ctx.refresh_stats(col_name, None) {
+            Err(DbErr::CollectionNotFound(_)) | Err(DbErr::CollectionArchived(_)) => Ok(false),
+            result => result,
+        }
+    }
+
+    pub(super) fn drain<T: DeserializeOwned>(
+        &self,
+        col_name: &str,
+        query: Option<Document>,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Vec<T>> {
+        self.check_writable("Delete")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.drain(&name, query, session_id);
+        }
+        let docs = {
+            let mut inner = self.lock_inner()?;
+            inner.track(col_name, true, |inner| inner.drain(col_name, query, session_id))?
+        };
+        let mut result = Vec::with_capacity(docs.len());
+        for doc in docs {
+            result.push(from_stored_document(col_name, doc)?);
+        }
+        Ok(result)
+    }
+
+    pub(super) fn truncate(&self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
+        self.check_writable("Delete")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.truncate(&name, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.track(col_name, true, |inner| inner.truncate(col_name, session_id))
     }
 
-    pub(super) fn delete_many(&self, col_name: &str, query: Document, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
-        let mut inner = self.inner.lock()?;
-        inner.delete_many(col_name, query, session_id)
+    pub(super) fn create_index(&self, col_name: &str, keys: &Document, options: &IndexOptions, session_id: Option<&ObjectId>) -> DbResult<String> {
+        self.check_writable("CreateIndex")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.create_index(&name, keys, options, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        let name = inner.create_index(col_name, keys, options, session_id)?;
+        inner.refresh_pins(col_name);
+        Ok(name)
     }
 
-    pub(super) fn create_index(&self, col_name: &str, keys: &Document, options: Option<&Document>, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
-        inner.create_index(col_name, keys, options, session_id)
+    pub(super) fn drop_index(&self, col_name: &str, index_name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
+        self.check_writable("DropIndex")?;
+        if let Some((db, name)) = self.resolve_attached(col_name) {
+            return db.drop_index(&name, index_name, session_id);
+        }
+        let mut inner = self.lock_inner()?;
+        inner.ctx.drop_index(col_name, index_name, session_id)?;
+        inner.refresh_pins(col_name);
+        Ok(())
     }
 
     pub(super) fn drop(&self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let mut inner = self.inner.lock()?;
-        inner.drop_collection(col_name, session_id)
+        self.check_writable("DropCollection")?;
+        if let Some((db, name)) = self.resolve_alias(col_name) {
+            return Database::drop(&db, &name, session_id);
+        }
+        // the stub is dropped with the archived collection
+        if let Some((db, name)) = self.resolve_archived(col_name) {
+            Database::drop(&db, &name, None)?;
+        }
+        let mut inner = self.lock_inner()?;
+        inner.drop_collection(col_name, session_id)?;
+        inner.refresh_pins(col_name);
+        Ok(())
     }
 }
 
 impl DatabaseInner {
 
-    #[cfg(not(target_arch = "wasm32"))]
+    fn new(ctx: DbContext) -> DatabaseInner {
+        DatabaseInner {
+            ctx,
+            subscriptions: Subscriptions::new(),
+            cursors: ServerCursors::new(),
+        }
+    }
+
+    #[cfg(feature = "fs")]
     fn open_file_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<DatabaseInner>  {
         let ctx = DbContext::open_file(path.as_ref(), config)?;
 
-        Ok(DatabaseInner {
-            ctx,
+        Ok(DatabaseInner::new(ctx))
+    }
+
+    #[cfg(feature = "indexeddb")]
+    pub fn open_indexeddb(
+        ctx: IndexedDbContext,
+        config: Config,
+        loaded: impl FnOnce(DbResult<DatabaseInner>) + 'static,
+    ) -> DbResult<()> {
+        DbContext::open_indexeddb(ctx, config, move |ctx| {
+            loaded(ctx.map(DatabaseInner::new));
         })
     }
 
-    #[cfg(target_arch = "wasm32")]
-    pub fn open_indexeddb(ctx: IndexedDbContext, config: Config) -> DbResult<DatabaseInner> {
-        let ctx = DbContext::open_indexeddb(ctx, config)?;
+    #[cfg(feature = "opfs")]
+    fn open_opfs(ctx: OpfsContext, config: Config) -> DbResult<DatabaseInner> {
+        let ctx = DbContext::open_opfs(ctx, config)?;
 
-        Ok(DatabaseInner {
-            ctx,
-        })
+        Ok(DatabaseInner::new(ctx))
     }
 
     fn open_memory_with_config(config: Config) -> DbResult<DatabaseInner> {
         let ctx = DbContext::open_memory(config)?;
 
-        Ok(DatabaseInner {
-            ctx,
-        })
+        Ok(DatabaseInner::new(ctx))
     }
 
-    fn create_collection(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let _collection_meta = self.ctx.create_collection(name, session_id)?;
+    fn create_collection(&mut self, name: &str, options: &CreateCollectionOptions, session_id: Option<&ObjectId>) -> DbResult<()> {
+        let _collection_meta = self.ctx.create_collection(name, options, session_id)?;
         Ok(())
     }
 
@@ -340,18 +1518,23 @@ impl DatabaseInner {
         self.ctx.start_transaction(ty, session_id)
     }
 
-    #[inline]
     fn commit(&mut self, session_id: Option<&ObjectId>) -> DbResult<()> {
-        self.ctx.commit(session_id)
+        self.ctx.commit(session_id)?;
+        if let Some(session_id) = session_id {
+            self.subscriptions.commit(session_id);
+        }
+        Ok(())
     }
 
-    #[inline]
     fn rollback(&mut self, session_id: Option<&ObjectId>) -> DbResult<()> {
+        if let Some(session_id) = session_id {
+            self.subscriptions.discard(session_id);
+        }
         self.ctx.rollback(session_id)
     }
 
-    #[inline]
     fn drop_session(&mut self, session_id: &ObjectId) -> DbResult<()> {
+        self.subscriptions.discard(session_id);
         self.ctx.drop_session(session_id)
     }
 
@@ -376,13 +1559,11 @@ impl DatabaseInner {
                 let name = doc.get("_id").unwrap().as_str().unwrap().to_string();
                 name
             })
+            // the namespaces of the key-value API are not collections
+            .filter(|name| !name.starts_with(KV_COLLECTION_PREFIX))
             .collect()
     }
 
-    fn handle_request<R: Read>(&mut self, pipe_in: &mut R) -> DbResult<HandleRequestResult> {
-        self.handle_request_with_result(pipe_in)
-    }
-
     fn count_documents(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<u64> {
         let test_result = self.ctx.count(name, session_id);
         match test_result {
@@ -392,6 +1573,55 @@ impl DatabaseInner {
         }
     }
 
+    fn collection_stats(&mut self, name: &str, session_id: Option<&ObjectId>) -> DbResult<CollectionStats> {
+        let mut stats = match self.ctx.stats(name, session_id) {
+            Err(DbErr::CollectionNotFound(_)) => CollectionStats {
+                count: 0,
+                data_size: 0,
+                metrics: None,
+            },
+            result => result?,
+        };
+        stats.metrics = self.ctx.metrics().collection(name);
+        Ok(stats)
+    }
+
+    // Count the operation on the collection if the metrics are enabled,
+    // the lock of the database is held, so the bytes counted are of this operation.
+    fn track<R>(
+        &mut self,
+        col_name: &str,
+        is_write: bool,
+        op: impl FnOnce(&mut DatabaseInner) -> DbResult<R>,
+    ) -> DbResult<R> {
+        let metrics = self.ctx.metrics();
+        let result = if metrics.is_enabled() {
+            let config = self.ctx.config();
+            metrics.start_operation();
+            let start = Stopwatch::start(config.clock());
+            let result = op(self);
+            metrics.finish_operation(col_name, is_write, start.elapsed());
+            result
+        } else {
+            op(self)
+        };
+        if is_write && result.is_ok() {
+            self.refresh_pins(col_name);
+        }
+        result
+    }
+
+    // The pages of a pinned collection are changed by the writes. The pages pinned before
+    // are kept if the collection grows beyond the budget, the write is done anyway.
+    fn refresh_pins(&mut self, col_name: &str) {
+        if !self.ctx.is_pinned(col_name) {
+            return;
+        }
+        if let Err(err) = self.ctx.refresh_pins() {
+            crate::polo_log!("refresh the pinned pages of {}: {}", col_name, err);
+        }
+    }
+
     // fn send_response_with_result<W: Write>(&mut self, pipe_out: &mut W, result: DbResult<HandleRequestResult>, body: Vec<u8>) -> DbResult<HandleRequestResult> {
     //     match &result {
     //         Ok(_) => {
@@ -410,64 +1640,239 @@ impl DatabaseInner {
     //     result
     // }
 
-    fn find_one<T: DeserializeOwned>(&mut self, col_name: &str, filter: impl Into<Option<Document>>, session_id: Option<&ObjectId>) -> DbResult<Option<T>> {
-        let filter_query = filter.into();
-        let col_spec = self.get_collection_meta_by_name(col_name, false, session_id)?;
-        let result: Option<T> = if let Some(col_spec) = col_spec {
-            let mut handle = self.ctx.find(
-                &col_spec,
-                filter_query,
-                session_id
-            )?;
-            handle.step()?;
+    fn find_one<T: DeserializeOwned>(
+        &mut self,
+        col_name: &str,
+        filter: impl Into<Option<Document>>,
+        options: FindOptions,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Option<T>> {
+        let docs = self.find_documents(col_name, filter.into(), &options, Some(1), session_id)?;
+        let result = docs.into_iter().next().map(|doc| from_stored_document(col_name, doc)).transpose()?;
+        Ok(result)
+    }
 
-            if !handle.has_row() {
-                handle.commit_and_close_vm()?;
-                return Ok(None);
+    fn find_many_by_ids<T: DeserializeOwned>(
+        &mut self, col_name: &str,
+        mut ids: Vec<Bson>,
+        session_id: Option<&ObjectId>
+    ) -> DbResult<FindByIdsResult<T>> {
+        range_helper::sort_keys(&mut ids)?;
+        let (docs, missing) = match self.ctx.find_by_ids(col_name, &ids, session_id) {
+            Err(DbErr::CollectionNotFound(_)) => {
+                return Ok(FindByIdsResult {
+                    found: vec![],
+                    missing: ids,
+                });
             }
-
-            let result_doc = handle.get().as_document().unwrap().clone();
-
-            handle.commit_and_close_vm()?;
-
-            bson::from_document(result_doc)?
-        } else {
-            None
+            result => result?,
         };
 
-        Ok(result)
+        let mut found = Vec::with_capacity(docs.len());
+        for doc in docs {
+            found.push(from_stored_document(col_name, doc)?);
+        }
+        Ok(FindByIdsResult {
+            found,
+            missing,
+        })
     }
 
     fn find_many<T: DeserializeOwned>(
         &mut self, col_name: &str,
         filter: impl Into<Option<Document>>,
+        options: FindOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<Vec<T>> {
-        let filter_query = filter.into();
-        let meta_opt = self.get_collection_meta_by_name(col_name, false, session_id)?;
-        match meta_opt {
-            Some(col_spec) => {
-                let mut handle = self.ctx.find(
-                    &col_spec,
-                    filter_query,
-                    session_id
-                )?;
-
-                let mut result: Vec<T> = Vec::new();
-                consume_handle_to_vec::<T>(&mut handle, &mut result)?;
+        let limit = options.limit.filter(|limit| *limit > 0);
+        let docs = self.find_documents(col_name, filter.into(), &options, limit, session_id)?;
+        let mut result = Vec::with_capacity(docs.len());
+        for doc in docs {
+            result.push(from_stored_document(col_name, doc)?);
+        }
+        Ok(result)
+    }
 
-                Ok(result)
+    // The documents scanned in the order of `_id` are skipped and limited by the VM,
+    // so the scan ends after the limit. The others are sorted after all of them are found.
+    fn find_documents(
+        &mut self,
+        col_name: &str,
+        filter: Option<Document>,
+        options: &FindOptions,
+        limit: Option<u64>,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Vec<Document>> {
+        let sort = parse_find_sort(options)?;
+        let mut projection = options.projection.as_ref().map(|p| Projection::parse(p, filter.as_ref())).transpose()?;
+        if let (Some(sort), Some(projection)) = (&sort, &mut projection) {
+            projection.read_fields(sort.fields());
+        }
+        let col_spec = match self.get_collection_meta_by_name(col_name, false, session_id)? {
+            Some(col_spec) => col_spec,
+            None => return Ok(vec![]),
+        };
 
+        let skip = options.skip.unwrap_or(0);
+        let mut docs: Vec<Document> = Vec::new();
+        match &sort {
+            Some(sort) => {
+                let mut handle = self.ctx.find(&col_spec, filter, projection.as_ref(), session_id)?;
+                consume_handle_to_vec(&mut handle, None, &mut docs)?;
+                sort_and_slice(&mut docs, Some(sort), skip, limit);
             }
             None => {
-                Ok(vec![])
+                let mut handle = self.ctx.find_window(&col_spec, filter, projection.as_ref(), skip, limit, session_id)?;
+                consume_handle_to_vec(&mut handle, None, &mut docs)?;
+            }
+        }
+
+        if let Some(projection) = &projection {
+            docs = docs.into_iter().map(|doc| projection.apply_scanned(doc)).collect();
+        }
+        Ok(docs)
+    }
+
+    fn explain(
+        &mut self, col_name: &str,
+        filter: Option<Document>,
+        options: FindOptions,
+        verbosity: ExplainVerbosity,
+        session_id: Option<&ObjectId>
+    ) -> DbResult<Explain> {
+        let sort = parse_find_sort(&options)?;
+        let mut projection = options.projection.as_ref().map(|p| Projection::parse(p, filter.as_ref())).transpose()?;
+        if let (Some(sort), Some(projection)) = (&sort, &mut projection) {
+            projection.read_fields(sort.fields());
+        }
+        let col_spec = self.get_collection_meta_by_name(col_name, false, session_id)?;
+        let uses_index = match (&col_spec, &filter) {
+            (Some(col_spec), Some(filter)) => IndexScan::plan(col_spec, filter).is_some(),
+            _ => false,
+        };
+        let mut result = Explain {
+            namespace: col_name.to_string(),
+            stages: explain::plan_stages(filter.as_ref(), uses_index, sort.is_some(), projection.is_some()),
+            execution_stats: None,
+        };
+        if verbosity == ExplainVerbosity::QueryPlanner {
+            return Ok(result);
+        }
+
+        // the pages are counted by the metrics if they are enabled,
+        // the lock of the database is held, so no other operation is counted
+        let metrics = self.ctx.metrics();
+        let metrics_before = metrics.data();
+        let config = self.ctx.config();
+        let start = Stopwatch::start(config.clock());
+
+        let skip = options.skip.unwrap_or(0);
+        let limit = options.limit.filter(|limit| *limit > 0);
+        let mut stats = ExecutionStats::default();
+        let mut docs: Vec<Document> = Vec::new();
+        if let Some(col_spec) = col_spec {
+            let mut handle = match &sort {
+                Some(_) => self.ctx.find(&col_spec, filter, projection.as_ref(), session_id)?,
+                None => self.ctx.find_window(&col_spec, filter, projection.as_ref(), skip, limit, session_id)?,
+            };
+            handle.step()?;
+            while handle.has_row() {
+                docs.push(handle.get().as_document().unwrap().clone());
+                handle.step()?;
+            }
+            stats.total_keys_examined = handle.keys_examined();
+            stats.total_docs_examined = handle.docs_examined();
+        }
+        stats.stages.push(StageStats {
+            stage: result.stages[0].clone(),
+            n_returned: docs.len() as u64,
+            execution_time: start.elapsed(),
+        });
+
+        if let Some(sort) = &sort {
+            let sort_start = Stopwatch::start(config.clock());
+            sort_and_slice(&mut docs, Some(sort), skip, limit);
+            stats.stages.push(StageStats {
+                stage: STAGE_SORT.to_string(),
+                n_returned: docs.len() as u64,
+                execution_time: sort_start.elapsed(),
+            });
+        }
+
+        if let Some(projection) = &projection {
+            let projection_start = Stopwatch::start(config.clock());
+            docs = docs.into_iter().map(|doc| projection.apply_scanned(doc)).collect();
+            stats.stages.push(StageStats {
+                stage: STAGE_PROJECTION.to_string(),
+                n_returned: docs.len() as u64,
+                execution_time: projection_start.elapsed(),
+            });
+        }
+
+        stats.n_returned = docs.len() as u64;
+        stats.execution_time = start.elapsed();
+        if metrics.is_enabled() {
+            explain::set_page_stats(&mut stats, &metrics_before, &metrics.data());
+        }
+        result.execution_stats = Some(stats);
+
+        Ok(result)
+    }
+
+    // the documents satisfying the filter are counted while they are scanned,
+    // none of them is kept
+    fn count_matched(&mut self, col_name: &str, filter: Document, session_id: Option<&ObjectId>) -> DbResult<u64> {
+        let col_spec = match self.get_collection_meta_by_name(col_name, false, session_id)? {
+            Some(col_spec) => col_spec,
+            None => return Ok(0),
+        };
+        let mut handle = self.ctx.find(&col_spec, Some(filter), None, session_id)?;
+        let mut count: u64 = 0;
+        handle.step()?;
+        while handle.has_row() {
+            count += 1;
+            handle.step()?;
+        }
+        Ok(count)
+    }
+
+    /// Sample every n-th document by the count of the collection,
+    /// so the sample spreads over the collection instead of the oldest documents.
+    fn analyze_schema(&mut self, col_name: &str, sample_size: usize, session_id: Option<&ObjectId>) -> DbResult<SchemaAnalysis> {
+        let mut analyzer = SchemaAnalyzer::new(col_name);
+        let col_spec = match self.get_collection_meta_by_name(col_name, false, session_id)? {
+            Some(col_spec) => col_spec,
+            None => return Ok(analyzer.finish()),
+        };
+        let sample_size = sample_size.max(1) as u64;
+        let count = self.ctx.stats(col_name, session_id)?.count;
+        let stride = ((count + sample_size - 1) / sample_size).max(1);
+
+        let mut handle = self.ctx.find(&col_spec, None, None, session_id)?;
+        let mut index: u64 = 0;
+        handle.step()?;
+        while handle.has_row() && analyzer.sampled() < sample_size {
+            if index % stride == 0 {
+                analyzer.add(handle.get().as_document().unwrap());
             }
+            index += 1;
+            handle.step()?;
         }
+
+        Ok(analyzer.finish())
     }
 
     fn insert_one<T: Serialize>(&mut self, col_name: &str, doc: impl Borrow<T>, session_id: Option<&ObjectId>) -> DbResult<InsertOneResult> {
         let doc = bson::to_document(doc.borrow())?;
+        if !self.subscriptions.is_subscribed(col_name) {
+            return self.ctx.insert_one_auto(col_name, doc, session_id);
+        }
+
+        let mut full_document = doc.clone();
         let result = self.ctx.insert_one_auto(col_name, doc, session_id)?;
+        full_document.insert("_id", result.inserted_id.clone());
+        let event = ChangeEvent::new(ChangeOperation::Insert, col_name, full_document);
+        self.subscriptions.publish(vec![event], session_id);
         Ok(result)
     }
 
@@ -475,61 +1880,239 @@ impl DatabaseInner {
         &mut self,
         col_name: &str,
         docs: impl IntoIterator<Item = impl Borrow<T>>,
+        options: &InsertManyOptions,
         session_id: Option<&ObjectId>
     ) -> DbResult<InsertManyResult> {
-        self.ctx.insert_many_auto(col_name, docs, session_id)
+        let ordered = options.ordered.unwrap_or(true);
+        if !self.subscriptions.is_subscribed(col_name) {
+            return self.ctx.insert_many_auto(col_name, docs, ordered, session_id);
+        }
+
+        // the indexes of the documents serialized in the documents passed in
+        let mut indexes = vec![];
+        let mut full_documents = vec![];
+        let mut write_errors = vec![];
+        for (index, doc) in docs.into_iter().enumerate() {
+            match bson::to_document(doc.borrow()) {
+                Ok(doc) => {
+                    indexes.push(index);
+                    full_documents.push(doc);
+                }
+                Err(err) if !ordered => write_errors.push(WriteError {
+                    index,
+                    message: DbErr::from(err).to_string(),
+                }),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let result = self.ctx.insert_many_auto::<Document>(col_name, &full_documents, ordered, session_id)?;
+
+        let mut events = vec![];
+        for (index, mut full_document) in full_documents.into_iter().enumerate() {
+            if let Some(id) = result.inserted_ids.get(&index) {
+                full_document.insert("_id", id.clone());
+                events.push(ChangeEvent::new(ChangeOperation::Insert, col_name, full_document));
+            }
+        }
+        self.subscriptions.publish(events, session_id);
+
+        let inserted_ids = result.inserted_ids
+            .into_iter()
+            .map(|(index, id)| (indexes[index], id))
+            .collect();
+        write_errors.extend(result.write_errors.into_iter().map(|mut err| {
+            err.index = indexes[err.index];
+            err
+        }));
+        write_errors.sort_by_key(|err| err.index);
+        Ok(InsertManyResult {
+            inserted_ids,
+            write_errors,
+        })
+    }
+
+    // The documents going to be changed by the query, which are read for the subscriptions.
+    fn find_for_subscriptions(
+        &mut self,
+        col_name: &str,
+        query: &Document,
+        limit: Option<usize>,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Vec<Document>> {
+        if !self.subscriptions.is_subscribed(col_name) {
+            return Ok(vec![]);
+        }
+        let result: DbResult<Vec<Document>> = match limit {
+            Some(1) => self.find_one(col_name, query.clone(), FindOptions::default(), session_id)
+                .map(|doc: Option<Document>| doc.into_iter().collect()),
+            _ => self.find_many(col_name, query.clone(), FindOptions::default(), session_id)
+                .map(|mut docs: Vec<Document>| {
+                    docs.truncate(limit.unwrap_or(usize::MAX));
+                    docs
+                }),
+        };
+        match result {
+            Err(DbErr::CollectionNotFound(_)) => Ok(vec![]),
+            result => result,
+        }
+    }
+
+    // Read the documents again after they are updated, the unchanged ones are skipped.
+    fn publish_updates(&mut self, col_name: &str, before: Vec<Document>, session_id: Option<&ObjectId>) -> DbResult<()> {
+        let mut events = Vec::with_capacity(before.len());
+        for doc in before {
+            let key = doc.get("_id").cloned().unwrap_or(Bson::Null);
+            let after: Option<Document> = self.find_one(col_name, doc! { "_id": key }, FindOptions::default(), session_id)?;
+            if let Some(after) = after.filter(|after| *after != doc) {
+                events.push(ChangeEvent::new(ChangeOperation::Update, col_name, after));
+            }
+        }
+        self.subscriptions.publish(events, session_id);
+        Ok(())
+    }
+
+    fn publish_deletes(&mut self, col_name: &str, before: Vec<Document>, session_id: Option<&ObjectId>) {
+        let events = before
+            .into_iter()
+            .map(|doc| ChangeEvent::new(ChangeOperation::Delete, col_name, doc))
+            .collect();
+        self.subscriptions.publish(events, session_id);
+    }
+
+    fn update_one(
+        &mut self,
+        col_name: &str,
+        query: Document,
+        update: Document,
+        options: &UpdateOptions,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<UpdateResult> {
+        self.update_internal(col_name, query, update, options, false, session_id)
     }
 
-    fn update_one(&mut self, col_name: &str, query: Document, update: Document, session_id: Option<&ObjectId>) -> DbResult<UpdateResult> {
+    fn update_internal(
+        &mut self,
+        col_name: &str,
+        query: Document,
+        update: Document,
+        options: &UpdateOptions,
+        is_many: bool,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<UpdateResult> {
         let meta_opt = self.get_collection_meta_by_name(col_name, false, session_id)?;
-        let modified_count: u64 = match meta_opt {
+        let result = match meta_opt {
             Some(col_spec) => {
-                let size = self.ctx.update_one(
-                    &col_spec,
-                    Some(&query),
-                    &update,
-                    session_id
-                )?;
-                size as u64
+                let limit = if is_many { None } else { Some(1) };
+                let before = self.find_for_subscriptions(col_name, &query, limit, session_id)?;
+                let result = if is_many {
+                    self.ctx.update_many(&col_spec, Some(&query), &update, session_id)?
+                } else {
+                    self.ctx.update_one(&col_spec, Some(&query), &update, session_id)?
+                };
+                if result.modified_count > 0 {
+                    self.publish_updates(col_name, before, session_id)?;
+                }
+                result
             }
-            None => 0,
+            None => UpdateResult::default(),
         };
+        if result.matched_count > 0 || !options.upsert.unwrap_or(false) {
+            return Ok(result);
+        }
+
+        let id = self.ctx.upsert(col_name, upsert_document(&query), &update, session_id)?;
+        if self.subscriptions.is_subscribed(col_name) {
+            let full_document: Option<Document> = self.find_one(
+                col_name,
+                doc! { "_id": id.clone() },
+                FindOptions::default(),
+                session_id,
+            )?;
+            if let Some(full_document) = full_document {
+                let event = ChangeEvent::new(ChangeOperation::Insert, col_name, full_document);
+                self.subscriptions.publish(vec![event], session_id);
+            }
+        }
         Ok(UpdateResult {
-            modified_count,
+            matched_count: 0,
+            modified_count: 0,
+            upserted_id: Some(id),
         })
     }
 
-    fn update_many(&mut self, col_name: &str, query: Document, update: Document, session_id: Option<&ObjectId>) -> DbResult<UpdateResult> {
-        let meta_opt = self.get_collection_meta_by_name(col_name, false, session_id)?;
-        let modified_count: u64 = match meta_opt {
-            Some(col_spec) => {
-                let size = self.ctx.update_many(
-                    &col_spec,
-                    Some(&query),
-                    &update,
-                    session_id
-                )?;
-                size as u64
+    fn update_one_if(&mut self, col_name: &str, query: Document, update: Document, session_id: Option<&ObjectId>) -> DbResult<UpdateIfResult> {
+        let col_spec = match self.get_collection_meta_by_name(col_name, false, session_id)? {
+            Some(col_spec) => col_spec,
+            None => return Ok(UpdateIfResult::NotFound),
+        };
+        let version_field = match &col_spec.info.version_field {
+            Some(field) => field.clone(),
+            None => {
+                return Err(DbErr::ValidationError(
+                    format!("update_one_if: the collection '{}' has no version field", col_name)
+                ));
             }
-            None => 0,
         };
-        Ok(UpdateResult {
-            modified_count,
-        })
+        if !query.contains_key(&version_field) {
+            return Err(DbErr::ValidationError(
+                format!("update_one_if: the query should have the version field '{}'", version_field)
+            ));
+        }
+
+        let before = self.find_for_subscriptions(col_name, &query, Some(1), session_id)?;
+        let result = self.ctx.update_one(&col_spec, Some(&query), &update, session_id)?;
+        if result.matched_count > 0 {
+            self.publish_updates(col_name, before, session_id)?;
+            return Ok(UpdateIfResult::Updated);
+        }
+
+        // tell the conflict from the absence by the query without the version
+        let mut query_without_version = query;
+        query_without_version.remove(&version_field);
+        let current: Option<Document> = self.find_one(
+            col_name,
+            query_without_version,
+            FindOptions::default(),
+            session_id,
+        )?;
+        let result = match current {
+            Some(doc) => UpdateIfResult::VersionConflict {
+                current_version: doc.get(&version_field).cloned().unwrap_or(Bson::Null),
+            },
+            None => UpdateIfResult::NotFound,
+        };
+        Ok(result)
+    }
+
+    fn update_many(
+        &mut self,
+        col_name: &str,
+        query: Document,
+        update: Document,
+        options: &UpdateOptions,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<UpdateResult> {
+        self.update_internal(col_name, query, update, options, true, session_id)
     }
 
     fn delete_one(&mut self, col_name: &str, query: Document, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
+        let before = self.find_for_subscriptions(col_name, &query, Some(1), session_id)?;
         let test_count = self.ctx.delete(
             col_name,
             query,
-            false,
+            Some(1),
             session_id,
         );
 
         match test_count {
-            Ok(count) => Ok(DeleteResult {
-                deleted_count: count as u64,
-            }),
+            Ok(count) => {
+                if count > 0 {
+                    self.publish_deletes(col_name, before, session_id);
+                }
+                Ok(DeleteResult {
+                    deleted_count: count as u64,
+                })
+            }
             Err(DbErr::CollectionNotFound(_)) => Ok(DeleteResult {
                 deleted_count: 0,
             }),
@@ -538,16 +2121,31 @@ impl DatabaseInner {
 
     }
 
-    fn delete_many(&mut self, col_name: &str, query: Document, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
-        let test_deleted_count = if query.len() == 0 {
-            self.ctx.delete_all(col_name, session_id)
-        } else {
-            self.ctx.delete(col_name, query, true, session_id)
+    fn drain(&mut self, col_name: &str, query: Option<Document>, session_id: Option<&ObjectId>) -> DbResult<Vec<Document>> {
+        let docs = match self.ctx.drain(col_name, query, session_id) {
+            Err(DbErr::CollectionNotFound(_)) => return Ok(vec![]),
+            result => result?,
+        };
+        if self.subscriptions.is_subscribed(col_name) {
+            self.publish_deletes(col_name, docs.clone(), session_id);
+        }
+        Ok(docs)
+    }
+
+    fn delete_many(&mut self, col_name: &str, query: Document, options: &DeleteOptions, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
+        let before = self.find_for_subscriptions(col_name, &query, None, session_id)?;
+        let test_deleted_count = match options.batch_size {
+            Some(batch_size) => self.delete_in_batches(col_name, query, batch_size.max(1), session_id),
+            None if query.is_empty() => self.ctx.delete_all(col_name, session_id),
+            None => self.ctx.delete(col_name, query, None, session_id),
         };
         match test_deleted_count {
-            Ok(deleted_count) => Ok(DeleteResult {
-                deleted_count: deleted_count as u64,
-            }),
+            Ok(deleted_count) => {
+                self.publish_deletes(col_name, before, session_id);
+                Ok(DeleteResult {
+                    deleted_count: deleted_count as u64,
+                })
+            }
             Err(DbErr::CollectionNotFound(_)) => Ok(DeleteResult {
                 deleted_count: 0
             }),
@@ -555,33 +2153,39 @@ impl DatabaseInner {
         }
     }
 
+    // Every batch is deleted in its own transaction,
+    // until a batch deletes fewer documents than the size.
+    fn delete_in_batches(&mut self, col_name: &str, query: Document, batch_size: usize, session_id: Option<&ObjectId>) -> DbResult<usize> {
+        let mut deleted_count: usize = 0;
+        loop {
+            let count = self.ctx.delete(col_name, query.clone(), Some(batch_size), session_id)?;
+            deleted_count += count;
+            if count < batch_size {
+                return Ok(deleted_count);
+            }
+        }
+    }
+
+    fn truncate(&mut self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<DeleteResult> {
+        let before = self.find_for_subscriptions(col_name, &Document::new(), None, session_id)?;
+        let deleted_count = match self.ctx.truncate(col_name, session_id) {
+            Ok(count) => count as u64,
+            Err(DbErr::CollectionNotFound(_)) => 0,
+            Err(err) => return Err(err),
+        };
+        self.publish_deletes(col_name, before, session_id);
+        Ok(DeleteResult { deleted_count })
+    }
+
     fn drop_collection(&mut self, col_name: &str, session_id: Option<&ObjectId>) -> DbResult<()> {
         self.ctx.drop_collection(col_name, session_id)?;
         Ok(())
     }
 
-    /// release in 0.12
-    fn create_index(&mut self, col_name: &str, keys: &Document, options: Option<&Document>, session_id: Option<&ObjectId>) -> DbResult<()> {
-        let col_spec = self.get_collection_meta_by_name(col_name, true, session_id)?
-            .unwrap();
-        self.ctx.create_index(
-            col_spec.info.root_pid,
-            keys,
-            options,
-            session_id,
-        )
-    }
-
-    fn receive_request_body<R: Read>(&mut self, pipe_in: &mut R) -> DbResult<Bson> {
-        let request_size = pipe_in.read_u32::<BigEndian>()? as usize;
-        if request_size == 0 {
-            return Ok(Bson::Null);
-        }
-        let mut request_body = vec![0u8; request_size];
-        pipe_in.read_exact(&mut request_body)?;
-        let body_ref: &[u8] = request_body.as_slice();
-        let val = bson::from_slice(body_ref)?;
-        Ok(val)
+    fn create_index(&mut self, col_name: &str, keys: &Document, options: &IndexOptions, session_id: Option<&ObjectId>) -> DbResult<String> {
+        // the collection is created implicitly as the inserts do
+        self.get_collection_meta_by_name(col_name, true, session_id)?;
+        self.ctx.create_index(col_name, keys, options, session_id)
     }
 
     fn handle_start_transaction(&mut self, start_transaction: StartTransactionCommand) -> DbResult<Bson> {
@@ -589,32 +2193,37 @@ impl DatabaseInner {
         Ok(Bson::Null)
     }
 
-    fn handle_request_with_result<R: Read>(&mut self, pipe_in: &mut R) -> DbResult<HandleRequestResult> {
-        let value = self.receive_request_body(pipe_in)?;
-        self.handle_request_doc(value)
-    }
-
-    fn handle_request_doc(&mut self, value: Bson) -> DbResult<HandleRequestResult> {
-        let command_message = bson::from_bson::<CommandMessage>(value)?;
-
+    fn handle_command(&mut self, command_message: CommandMessage) -> DbResult<HandleRequestResult> {
         let is_quit = if let CommandMessage::SafelyQuit = command_message {
             true
         } else {
             false
         };
+        self.reap_idle_cursors();
 
         let result_value: Bson = match command_message {
             CommandMessage::Find(find) => {
-                self.handle_find_operation(find)?
+                let col_name = find.ns.clone();
+                self.track(&col_name, false, |inner| inner.handle_find_operation(find))?
+            }
+            CommandMessage::GetMore(get_more) => {
+                self.handle_get_more(get_more)?
+            }
+            CommandMessage::KillCursors(kill_cursors) => {
+                let count = self.cursors.kill(&kill_cursors.ns, &kill_cursors.cursor_ids);
+                Bson::Int64(count as i64)
             }
             CommandMessage::Insert(insert) => {
-                self.handle_insert_operation(insert)?
+                let col_name = insert.ns.clone();
+                self.track(&col_name, true, |inner| inner.handle_insert_operation(insert))?
             }
             CommandMessage::Update(update) => {
-                self.handle_update_operation(update)?
+                let col_name = update.ns.clone();
+                self.track(&col_name, true, |inner| inner.handle_update_operation(update))?
             }
             CommandMessage::Delete(delete) => {
-                self.handle_delete_operation(delete)?
+                let col_name = delete.ns.clone();
+                self.track(&col_name, true, |inner| inner.handle_delete_operation(delete))?
             }
             CommandMessage::CreateCollection(create_collection) => {
                 self.handle_create_collection(create_collection)?
@@ -622,6 +2231,14 @@ impl DatabaseInner {
             CommandMessage::DropCollection(drop_collection) => {
                 self.handle_drop_collection(drop_collection)?
             }
+            CommandMessage::StartSession => {
+                let session_id = self.ctx.start_session()?;
+                Bson::ObjectId(session_id)
+            }
+            CommandMessage::DropSession(drop_session) => {
+                self.drop_session(&drop_session.session_id)?;
+                Bson::Null
+            }
             CommandMessage::StartTransaction(start_transaction) => {
                 self.handle_start_transaction(start_transaction)?
             }
@@ -636,8 +2253,15 @@ impl DatabaseInner {
             CommandMessage::SafelyQuit => {
                 Bson::Null
             }
+            CommandMessage::Compact => {
+                bson::to_bson(&self.ctx.compact()?)?
+            }
             CommandMessage::CountDocuments(count_documents) => {
-                self.handle_count_operation(count_documents)?
+                let col_name = count_documents.ns.clone();
+                self.track(&col_name, false, |inner| inner.handle_count_operation(count_documents))?
+            }
+            CommandMessage::AnalyzeSchema(analyze_schema) => {
+                self.handle_analyze_schema(analyze_schema)?
             }
         };
 
@@ -653,16 +2277,35 @@ impl DatabaseInner {
             .as_ref()
             .map(|o| o.session_id.as_ref())
             .flatten();
+        let options = FindOptions {
+            projection: find.options.as_ref().and_then(|o| o.projection.clone()),
+            sort: find.options.as_ref().and_then(|o| o.sort.clone()),
+            skip: find.options.as_ref().and_then(|o| o.skip),
+            limit: find.options.as_ref().and_then(|o| o.limit),
+        };
+        if let Some(scan_limit) = find.options.as_ref().and_then(|o| o.scan_limit).filter(|_| find.multi) {
+            let resume_from = find.options.as_ref().and_then(|o| o.resume_from.clone());
+            return self.find_slice(col_name, find.filter, options, resume_from, scan_limit, session_id);
+        }
+        let batch_size = find.options.as_ref().and_then(|o| o.batch_size);
+        let cursor_filter = match batch_size {
+            Some(_) => find.filter.clone(),
+            None => None,
+        };
         let result = if find.multi {
-            self.find_many(col_name, find.filter, session_id)?
+            self.find_many(col_name, find.filter, options, session_id)?
         } else {
-            let result = self.find_one(col_name, find.filter, session_id)?;
+            let result = self.find_one(col_name, find.filter, options, session_id)?;
             match result {
                 Some(doc) => vec![doc],
                 None => vec![],
             }
         };
 
+        if let Some(batch_size) = batch_size {
+            return self.open_cursor(col_name, cursor_filter.as_ref(), result, batch_size as usize);
+        }
+
         let mut value_arr = bson::Array::new();
 
         for item in result {
@@ -674,13 +2317,98 @@ impl DatabaseInner {
         Ok(result_value)
     }
 
+    /// Examine at most `scan_limit` documents from the key `resume_from`,
+    /// return the documents found as `{ batch, resumeFrom }`.
+    fn find_slice(
+        &mut self,
+        col_name: &str,
+        filter: Option<Document>,
+        options: FindOptions,
+        resume_from: Option<Bson>,
+        scan_limit: u32,
+        session_id: Option<&ObjectId>,
+    ) -> DbResult<Bson> {
+        let projection = options.projection.as_ref().map(|p| Projection::parse(p, filter.as_ref())).transpose()?;
+        let mut docs: Vec<Document> = Vec::new();
+        let mut next_key = None;
+        if let Some(col_spec) = self.get_collection_meta_by_name(col_name, false, session_id)? {
+            let mut handle = self.ctx.find_slice(
+                &col_spec,
+                filter,
+                projection.as_ref(),
+                resume_from,
+                scan_limit.max(1) as u64,
+                session_id,
+            )?;
+            consume_handle_to_vec(&mut handle, projection.as_ref(), &mut docs)?;
+            next_key = handle.resume_key().cloned();
+        }
+
+        let batch: bson::Array = docs.into_iter().map(Bson::Document).collect();
+        let mut result = doc! {
+            "batch": batch,
+        };
+        if let Some(next_key) = next_key {
+            result.insert("resumeFrom", next_key);
+        }
+        Ok(Bson::Document(result))
+    }
+
+    fn open_cursor(&mut self, col_name: &str, filter: Option<&Document>, docs: Vec<Document>, batch_size: usize) -> DbResult<Bson> {
+        if docs.len() > batch_size {
+            self.check_open_cursors()?;
+        }
+        let now = self.ctx.config().clock().now();
+        let (cursor_id, batch) = self.cursors.open(col_name, filter, docs, batch_size, now);
+        Ok(DatabaseInner::cursor_batch(cursor_id, batch))
+    }
+
+    fn handle_get_more(&mut self, get_more: GetMoreCommand) -> DbResult<Bson> {
+        let now = self.ctx.config().clock().now();
+        let batch_size = get_more.batch_size.map(|size| size as usize);
+        let (cursor_id, batch) = self.cursors.get_more(&get_more.ns, get_more.cursor_id, batch_size, now)?;
+        Ok(DatabaseInner::cursor_batch(cursor_id, batch))
+    }
+
+    fn cursor_batch(cursor_id: i64, batch: Vec<Document>) -> Bson {
+        let batch: bson::Array = batch.into_iter().map(Bson::Document).collect();
+        Bson::Document(doc! {
+            "cursorId": cursor_id,
+            "batch": batch,
+        })
+    }
+
+    /// The cursors of the wire protocol and the subscriptions are limited together.
+    fn check_open_cursors(&self) -> DbResult<()> {
+        if let Some(max_open_cursors) = self.ctx.config().max_open_cursors {
+            if self.cursors.len() + self.subscriptions.open_count() >= max_open_cursors.get() {
+                return Err(DbErr::TooManyOpenCursors(max_open_cursors.get()));
+            }
+        }
+        Ok(())
+    }
+
+    // the cursors are reaped by the commands, nothing is running in the background
+    fn reap_idle_cursors(&mut self) {
+        if self.cursors.is_empty() {
+            return;
+        }
+        let config = self.ctx.config();
+        if let Some(timeout) = config.cursor_timeout {
+            self.cursors.reap(config.clock().now(), timeout);
+        }
+    }
+
     fn handle_insert_operation(&mut self, insert: InsertCommand) -> DbResult<Bson> {
         let col_name = &insert.ns;
         let session_id = insert.options
             .as_ref()
             .map(|o| o.session_id.as_ref())
             .flatten();
-        let insert_result = self.insert_many(col_name, insert.documents, session_id)?;
+        let options = InsertManyOptions {
+            ordered: insert.options.as_ref().and_then(|o| o.ordered),
+        };
+        let insert_result = self.insert_many(col_name, insert.documents, &options, session_id)?;
         let bson_val = bson::to_bson(&insert_result)?;
         Ok(bson_val)
     }
@@ -692,10 +2420,13 @@ impl DatabaseInner {
             .as_ref()
             .map(|o| o.session_id.as_ref())
             .flatten();
+        let options = UpdateOptions {
+            upsert: update.options.as_ref().and_then(|o| o.upsert),
+        };
         let result = if update.multi {
-            self.update_many(col_name, update.filter, update.update, session_id)?
+            self.update_many(col_name, update.filter, update.update, &options, session_id)?
         } else {
-            self.update_one(col_name, update.filter, update.update, session_id)?
+            self.update_one(col_name, update.filter, update.update, &options, session_id)?
         };
 
         let bson_val = bson::to_bson(&result)?;
@@ -710,7 +2441,7 @@ impl DatabaseInner {
             .map(|o| o.session_id.as_ref())
             .flatten();
         let result = if delete.multi {
-            self.delete_many(col_name, delete.filter, session_id)?
+            self.delete_many(col_name, delete.filter, &DeleteOptions::default(), session_id)?
         } else {
             self.delete_one(col_name, delete.filter, session_id)?
         };
@@ -720,8 +2451,29 @@ impl DatabaseInner {
     }
 
     fn handle_create_collection(&mut self, create_collection: CreateCollectionCommand) -> DbResult<Bson> {
+        let options = CreateCollectionOptions {
+            compression: create_collection.options
+                .as_ref()
+                .and_then(|o| o.compression)
+                .unwrap_or_default(),
+            version_field: create_collection.options
+                .as_ref()
+                .and_then(|o| o.version_field.clone()),
+            append_only: create_collection.options
+                .as_ref()
+                .map_or(false, |o| o.append_only),
+            foreign_keys: create_collection.options
+                .as_ref()
+                .map(|o| o.foreign_keys.clone())
+                .unwrap_or_default(),
+            generated_fields: create_collection.options
+                .as_ref()
+                .map(|o| o.generated_fields.clone())
+                .unwrap_or_default(),
+        };
         let ret = match self.create_collection(
             &create_collection.ns,
+            &options,
             create_collection.options
                 .as_ref()
                 .map(|o| o.session_id.as_ref())
@@ -745,14 +2497,26 @@ impl DatabaseInner {
         Ok(Bson::Null)
     }
 
+    fn handle_analyze_schema(&mut self, analyze_schema: AnalyzeSchemaCommand) -> DbResult<Bson> {
+        let options = analyze_schema.options.as_ref();
+        let sample_size = options
+            .and_then(|o| o.sample_size)
+            .map(|size| size as usize)
+            .unwrap_or(schema::DEFAULT_SAMPLE_SIZE);
+        let session_id = options.and_then(|o| o.session_id.as_ref());
+        let analysis = self.analyze_schema(&analyze_schema.ns, sample_size, session_id)?;
+        Ok(Bson::Document(analysis.to_document()))
+    }
+
     fn handle_count_operation(&mut self, count_documents: CountDocumentsCommand) -> DbResult<Bson> {
-        let count = self.count_documents(
-            &count_documents.ns,
-            count_documents.options
-                .as_ref()
-                .map(|o| o.session_id.as_ref())
-                .flatten()
-        )?;
+        let session_id = count_documents.options
+            .as_ref()
+            .map(|o| o.session_id.as_ref())
+            .flatten();
+        let count = match count_documents.filter {
+            Some(filter) => self.count_matched(&count_documents.ns, filter, session_id)?,
+            None => self.count_documents(&count_documents.ns, session_id)?,
+        };
         Ok(Bson::Int64(count as i64))
     }
 }
@@ -50,6 +50,25 @@ impl<'a> DbHandle<'a> {
         self.0.set_rollback_on_drop(value)
     }
 
+    /// The keys of the btree examined by the program.
+    #[inline]
+    pub(crate) fn keys_examined(&self) -> u64 {
+        self.0.keys_examined
+    }
+
+    /// The documents read by the program.
+    #[inline]
+    pub(crate) fn docs_examined(&self) -> u64 {
+        self.0.docs_examined
+    }
+
+    /// The key to continue the scan suspended by the scan limit,
+    /// `None` if the scan reaches the end.
+    #[inline]
+    pub(crate) fn resume_key(&self) -> Option<&Bson> {
+        self.0.resume_key.as_ref()
+    }
+
 }
 
 impl<'a> fmt::Display for DbHandle<'a> {
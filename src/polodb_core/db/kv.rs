@@ -0,0 +1,180 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::{Bson, Document, doc};
+use bson::oid::ObjectId;
+use crate::{ClientSession, Database, DbErr, DbResult, FindOptions, UpdateOptions};
+
+/// The prefix of the collections storing the namespaces,
+/// they are not listed in the names of the collections.
+pub(crate) const KV_COLLECTION_PREFIX: &str = "$kv:";
+
+/// A namespace of the key-value API, returned by [`Database::kv`].
+///
+/// The namespace is stored in its own btree with a document
+/// `{ "_id": <key>, "value": <value> }` for every key, so the operations
+/// with a session are in the same transaction as the operations of the collections.
+pub struct KvNamespace<'a> {
+    db: &'a Database,
+    col_name: String,
+}
+
+impl<'a> KvNamespace<'a> {
+
+    pub(super) fn new(db: &'a Database, namespace: &str) -> KvNamespace<'a> {
+        KvNamespace {
+            db,
+            col_name: format!("{}{}", KV_COLLECTION_PREFIX, namespace),
+        }
+    }
+
+    /// The name of the namespace.
+    pub fn namespace(&self) -> &str {
+        &self.col_name[KV_COLLECTION_PREFIX.len()..]
+    }
+
+    /// Set the value of a key, replacing the old one.
+    pub fn set(&self, key: &str, value: impl Into<Bson>) -> DbResult<()> {
+        self.set_internal(key, value.into(), None)
+    }
+
+    /// Set the value of a key in the transaction of the session.
+    pub fn set_with_session(&self, key: &str, value: impl Into<Bson>, session: &mut ClientSession) -> DbResult<()> {
+        self.set_internal(key, value.into(), Some(&session.id))
+    }
+
+    fn set_internal(&self, key: &str, value: Bson, session_id: Option<&ObjectId>) -> DbResult<()> {
+        let result = self.db.update_one(
+            &self.col_name,
+            doc! { "_id": key },
+            doc! { "$set": { "value": value.clone() } },
+            UpdateOptions::default(),
+            session_id,
+        )?;
+        if result.matched_count > 0 {
+            return Ok(());
+        }
+        match self.db.insert_one::<Document>(&self.col_name, doc! { "_id": key, "value": value }, session_id) {
+            Ok(_) | Err(DbErr::DataExist(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Return the value of a key.
+    pub fn get(&self, key: &str) -> DbResult<Option<Bson>> {
+        self.get_internal(key, None)
+    }
+
+    /// Return the value of a key in the transaction of the session.
+    pub fn get_with_session(&self, key: &str, session: &mut ClientSession) -> DbResult<Option<Bson>> {
+        self.get_internal(key, Some(&session.id))
+    }
+
+    fn get_internal(&self, key: &str, session_id: Option<&ObjectId>) -> DbResult<Option<Bson>> {
+        let doc: Option<Document> = self.db.find_one(
+            &self.col_name,
+            doc! { "_id": key },
+            FindOptions::default(),
+            session_id,
+        )?;
+        Ok(doc.and_then(|mut doc| doc.remove("value")))
+    }
+
+    /// Delete a key, return `false` if it doesn't exist.
+    pub fn delete(&self, key: &str) -> DbResult<bool> {
+        self.delete_internal(key, None)
+    }
+
+    /// Delete a key in the transaction of the session.
+    pub fn delete_with_session(&self, key: &str, session: &mut ClientSession) -> DbResult<bool> {
+        self.delete_internal(key, Some(&session.id))
+    }
+
+    fn delete_internal(&self, key: &str, session_id: Option<&ObjectId>) -> DbResult<bool> {
+        let result = self.db.delete_one(&self.col_name, doc! { "_id": key }, session_id)?;
+        Ok(result.deleted_count > 0)
+    }
+
+    /// Return the keys starting with the prefix and their values, in the order of the keys.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let flags = db.kv("flags");
+    /// flags.set("ui.dark_mode", true).unwrap();
+    /// flags.set("ui.compact", false).unwrap();
+    /// flags.set("sync.enabled", true).unwrap();
+    ///
+    /// let keys: Vec<String> = flags.scan_prefix("ui.").unwrap()
+    ///     .into_iter()
+    ///     .map(|(key, _)| key)
+    ///     .collect();
+    /// assert_eq!(keys, vec!["ui.compact", "ui.dark_mode"]);
+    /// ```
+    pub fn scan_prefix(&self, prefix: &str) -> DbResult<Vec<(String, Bson)>> {
+        self.scan_prefix_internal(prefix, None)
+    }
+
+    /// Return the keys starting with the prefix in the transaction of the session.
+    pub fn scan_prefix_with_session(&self, prefix: &str, session: &mut ClientSession) -> DbResult<Vec<(String, Bson)>> {
+        self.scan_prefix_internal(prefix, Some(&session.id))
+    }
+
+    fn scan_prefix_internal(&self, prefix: &str, session_id: Option<&ObjectId>) -> DbResult<Vec<(String, Bson)>> {
+        let mut range = doc! { "$gte": prefix };
+        if let Some(upper) = prefix_upper_bound(prefix) {
+            range.insert("$lt", upper);
+        }
+        let docs: Vec<Document> = self.db.find_many(
+            &self.col_name,
+            doc! { "_id": range },
+            FindOptions::default(),
+            session_id,
+        )?;
+
+        let mut result = Vec::with_capacity(docs.len());
+        for mut doc in docs {
+            let value = doc.remove("value").unwrap_or(Bson::Null);
+            if let Some(Bson::String(key)) = doc.remove("_id") {
+                result.push((key, value));
+            }
+        }
+        Ok(result)
+    }
+
+}
+
+/// The smallest string greater than all the strings starting with the prefix,
+/// `None` if there is no such string.
+fn prefix_upper_bound(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(last) = chars.pop() {
+        let next = match last {
+            '\u{D7FF}' => Some('\u{E000}'),
+            _ => char::from_u32(last as u32 + 1),
+        };
+        if let Some(next) = next {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::prefix_upper_bound;
+
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound("ui."), Some("ui/".to_string()));
+        assert_eq!(prefix_upper_bound("a\u{D7FF}"), Some("a\u{E000}".to_string()));
+        assert_eq!(prefix_upper_bound("a\u{10FFFF}"), Some("b".to_string()));
+        assert_eq!(prefix_upper_bound("\u{10FFFF}"), None);
+        assert_eq!(prefix_upper_bound(""), None);
+    }
+
+}
@@ -5,9 +5,24 @@
  */
 mod db;
 mod collection;
+mod kv;
 mod context;
+mod priority_gate;
 pub mod db_handle;
+#[cfg(feature = "fs")]
+mod database_manager;
+#[cfg(not(target_arch = "wasm32"))]
+mod async_db;
 
 pub use collection::Collection;
-pub use db::{Database, DbResult, IndexedDbContext};
+pub use kv::KvNamespace;
+pub use db::{Database, DbResult, HandleRequestResult};
+#[cfg(feature = "indexeddb")]
+pub use db::IndexedDbContext;
+#[cfg(feature = "opfs")]
+pub use db::OpfsContext;
 pub(crate) use db::SHOULD_LOG;
+#[cfg(feature = "fs")]
+pub use database_manager::{DatabaseManager, DatabaseManagerConfig};
+#[cfg(not(target_arch = "wasm32"))]
+pub use async_db::{AsyncCollection, AsyncDatabase, AsyncTask};
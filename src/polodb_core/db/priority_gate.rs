@@ -0,0 +1,107 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// The longest time a background operation gives way to the interactive ones,
+/// so it's not starved by a busy foreground.
+const MAX_YIELD: Duration = Duration::from_millis(50);
+
+/// Count the interactive operations waiting for the lock of the database,
+/// the background operations wait until there is none before taking the lock.
+pub(super) struct PriorityGate {
+    waiting: Mutex<usize>,
+    cond:    Condvar,
+}
+
+impl PriorityGate {
+
+    pub(super) fn new() -> PriorityGate {
+        PriorityGate {
+            waiting: Mutex::new(0),
+            cond: Condvar::new(),
+        }
+    }
+
+    // the counter is always valid, a panic can't leave it in the middle of a change
+    fn lock_waiting(&self) -> MutexGuard<'_, usize> {
+        self.waiting.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Mark an interactive operation waiting until the ticket is dropped.
+    pub(super) fn enter_interactive(&self) -> InteractiveTicket<'_> {
+        *self.lock_waiting() += 1;
+        InteractiveTicket(self)
+    }
+
+    /// Wait for the interactive operations to take the lock first.
+    pub(super) fn yield_to_interactive(&self) {
+        let deadline = Instant::now() + MAX_YIELD;
+        let mut waiting = self.lock_waiting();
+        while *waiting > 0 {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            waiting = match self.cond.wait_timeout(waiting, deadline - now) {
+                Ok((waiting, _)) => waiting,
+                Err(err) => err.into_inner().0,
+            };
+        }
+    }
+
+}
+
+pub(super) struct InteractiveTicket<'a>(&'a PriorityGate);
+
+impl Drop for InteractiveTicket<'_> {
+
+    fn drop(&mut self) {
+        let mut waiting = self.0.lock_waiting();
+        *waiting -= 1;
+        if *waiting == 0 {
+            self.0.cond.notify_all();
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use super::{PriorityGate, MAX_YIELD};
+
+    #[test]
+    fn background_yields_to_interactive() {
+        let gate = Arc::new(PriorityGate::new());
+
+        let start = Instant::now();
+        gate.yield_to_interactive();
+        assert!(start.elapsed() < MAX_YIELD);
+
+        // the background waits until the interactive operation is done
+        let ticket = gate.enter_interactive();
+        let background_gate = gate.clone();
+        let background = thread::spawn(move || {
+            let start = Instant::now();
+            background_gate.yield_to_interactive();
+            start.elapsed()
+        });
+        thread::sleep(Duration::from_millis(10));
+        drop(ticket);
+        let elapsed = background.join().unwrap();
+        assert!(elapsed >= Duration::from_millis(5));
+
+        // but not forever
+        let _ticket = gate.enter_interactive();
+        let start = Instant::now();
+        gate.yield_to_interactive();
+        assert!(start.elapsed() >= MAX_YIELD);
+    }
+
+}
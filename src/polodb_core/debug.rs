@@ -0,0 +1,286 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Print the pages and the journal frames of a database, to diagnose
+//! the corruptions reported by the users. It's enabled by the feature `debug`.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{doc, Document};
+//! use polodb_core::debug::PageDebug;
+//!
+//! let db = Database::open_memory().unwrap();
+//! db.collection::<Document>("books").insert_one(doc! { "_id": 1, "title": "Dune" }).unwrap();
+//!
+//! let header = db.debug_page(0).unwrap();
+//! let meta_pid = match &header {
+//!     PageDebug::Header { meta_pid, .. } => *meta_pid,
+//!     _ => unreachable!(),
+//! };
+//! println!("{}", header);
+//! println!("{}", db.debug_page(meta_pid).unwrap());
+//! ```
+use std::fmt;
+use bson::Bson;
+use crate::btree::{BTreePageDelegate, BTreePageDelegateWithKey};
+use crate::dump::JournalFrameDump;
+use crate::page::{FreeListDataWrapper, PageType, RawPage};
+use crate::page::data_allocator_wrapper::DataAllocatorWrapper;
+use crate::page::data_page_wrapper::DataPageWrapper;
+use crate::page::header_page_wrapper::HeaderPageWrapper;
+use crate::page::large_data_page_wrapper::LargeDataPageWrapper;
+use crate::session::Session;
+use crate::DbResult;
+
+/// The content of a page decoded by its type.
+#[derive(Debug, Clone)]
+pub enum PageDebug {
+    /// The first page of the database.
+    Header {
+        identifier:    String,
+        page_size:     u32,
+        meta_pid:      u32,
+        null_page_bar: u32,
+        free_list_pid: u32,
+        /// The free pages listed in the header page.
+        free_pages:    Vec<u32>,
+    },
+    BTree {
+        page_id:     u32,
+        right_pid:   u32,
+        remain_size: i32,
+        items:       Vec<BTreeItemDebug>,
+    },
+    Data {
+        page_id:     u32,
+        /// The documents stored in the page.
+        items:       u32,
+        /// The slots of the page, including the ones of the documents removed.
+        slots:       u32,
+        remain_size: u32,
+    },
+    LargeData {
+        page_id:  u32,
+        len:      u32,
+        next_pid: u32,
+    },
+    FreeList {
+        page_id:    u32,
+        next_pid:   u32,
+        free_pages: Vec<u32>,
+    },
+    DataAllocator {
+        page_id: u32,
+        /// The data pages with their remaining sizes.
+        pages:   Vec<(u32, u32)>,
+    },
+    /// A page never written, all the bytes are zero.
+    Empty(u32),
+    /// A page of an unknown magic number, it's probably broken.
+    Unknown {
+        page_id: u32,
+        magic:   [u8; 2],
+    },
+}
+
+/// An item of a B-tree page.
+#[derive(Debug, Clone)]
+pub struct BTreeItemDebug {
+    /// The child page of the keys less than this one, 0 in a leaf page.
+    pub left_pid:      u32,
+    pub key:           Bson,
+    /// The page of the document.
+    pub payload_pid:   u32,
+    /// The slot of the document in the data page, `u16::MAX` for a large document.
+    pub payload_index: u16,
+}
+
+pub(crate) fn debug_page(session: &dyn Session, page_id: u32) -> DbResult<PageDebug> {
+    let page = session.read_page(page_id)?;
+    let page = page.as_ref();
+    if page_id == 0 {
+        return Ok(debug_header_page(page));
+    }
+    if page.data.iter().all(|byte| *byte == 0) {
+        return Ok(PageDebug::Empty(page_id));
+    }
+
+    let magic = [page.data[0], page.data[1]];
+    let result = match magic {
+        [0xFF, ty] if ty == PageType::BTreeNode as u8 => debug_btree_page(session, page)?,
+        [0xFF, ty] if ty == PageType::Data as u8 => {
+            let wrapper = DataPageWrapper::from_raw(page.clone());
+            PageDebug::Data {
+                page_id,
+                items: wrapper.data_len(),
+                slots: wrapper.bar_len(),
+                remain_size: wrapper.remain_size(),
+            }
+        }
+        [0xFF, ty] if ty == PageType::LargeData as u8 => {
+            let wrapper = LargeDataPageWrapper::from_raw(page.clone());
+            PageDebug::LargeData {
+                page_id,
+                len: page.get_u16(2) as u32,
+                next_pid: wrapper.next_pid(),
+            }
+        }
+        [0xFF, ty] if ty == PageType::FreeList as u8 => {
+            let wrapper = FreeListDataWrapper::from_raw(page.clone());
+            PageDebug::FreeList {
+                page_id,
+                next_pid: wrapper.next_pid(),
+                free_pages: wrapper.page_ids(),
+            }
+        }
+        [0xFF, ty] if ty == PageType::DataAllocator as u8 => {
+            let wrapper = DataAllocatorWrapper::from_raw_page(page);
+            PageDebug::DataAllocator {
+                page_id,
+                pages: wrapper.free_pages().to_vec(),
+            }
+        }
+        _ => PageDebug::Unknown {
+            page_id,
+            magic,
+        },
+    };
+    Ok(result)
+}
+
+fn debug_header_page(page: &RawPage) -> PageDebug {
+    let mut wrapper = HeaderPageWrapper::from_raw_page(page.clone());
+    let free_pages = (0..wrapper.get_free_list_size())
+        .map(|index| wrapper.get_free_list_content(index))
+        .collect();
+    PageDebug::Header {
+        identifier: wrapper.get_title(),
+        page_size: wrapper.get_page_size(),
+        meta_pid: wrapper.get_meta_page_id(),
+        null_page_bar: wrapper.get_null_page_bar(),
+        free_list_pid: wrapper.get_free_list_page_id(),
+        free_pages,
+    }
+}
+
+fn debug_btree_page(session: &dyn Session, page: &RawPage) -> DbResult<PageDebug> {
+    let delegate = BTreePageDelegate::from_page(page, 0)?;
+    let delegate = BTreePageDelegateWithKey::read_from_session(delegate, session)?;
+    let items = (0..delegate.len())
+        .map(|index| {
+            let item = delegate.get_item(index);
+            BTreeItemDebug {
+                left_pid: item.left_pid,
+                key: item.key.clone(),
+                payload_pid: item.payload.pid,
+                payload_index: item.payload.index,
+            }
+        })
+        .collect();
+    Ok(PageDebug::BTree {
+        page_id: page.page_id,
+        right_pid: delegate.right_pid,
+        remain_size: delegate.remain_size(),
+        items,
+    })
+}
+
+macro_rules! write_kv {
+    ($formatter:expr, $key:expr, $value:expr) => {
+        writeln!($formatter, "{:16}{}", concat!($key, ":"), $value)
+    }
+}
+
+fn write_page_ids(f: &mut fmt::Formatter<'_>, page_ids: &[u32]) -> fmt::Result {
+    let page_ids: Vec<String> = page_ids.iter().map(|pid| pid.to_string()).collect();
+    write_kv!(f, "Pages", format!("[{}]", page_ids.join(", ")))
+}
+
+impl fmt::Display for PageDebug {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PageDebug::Header { identifier, page_size, meta_pid, null_page_bar, free_list_pid, free_pages } => {
+                write_kv!(f, "Header Page", 0)?;
+                write_kv!(f, "Identifier", identifier)?;
+                write_kv!(f, "Page Size", page_size)?;
+                write_kv!(f, "Meta Page Id", meta_pid)?;
+                write_kv!(f, "Null Page Bar", null_page_bar)?;
+                write_kv!(f, "Free List Page", free_list_pid)?;
+                write_page_ids(f, free_pages)
+            }
+
+            PageDebug::BTree { page_id, right_pid, remain_size, items } => {
+                write_kv!(f, "BTree Page", page_id)?;
+                write_kv!(f, "Right Page Id", right_pid)?;
+                write_kv!(f, "Remain Size", remain_size)?;
+                write_kv!(f, "Items", items.len())?;
+                for (index, item) in items.iter().enumerate() {
+                    let payload = if item.payload_index == u16::MAX {
+                        format!("large@{}", item.payload_pid)
+                    } else {
+                        format!("{}:{}", item.payload_pid, item.payload_index)
+                    };
+                    writeln!(f, "  #{:<4} left: {:<8} key: {}  data: {}", index, item.left_pid, item.key, payload)?;
+                }
+                Ok(())
+            }
+
+            PageDebug::Data { page_id, items, slots, remain_size } => {
+                write_kv!(f, "Data Page", page_id)?;
+                write_kv!(f, "Items", items)?;
+                write_kv!(f, "Slots", slots)?;
+                write_kv!(f, "Remain Size", remain_size)
+            }
+
+            PageDebug::LargeData { page_id, len, next_pid } => {
+                write_kv!(f, "Large Data Page", page_id)?;
+                write_kv!(f, "Length", len)?;
+                write_kv!(f, "Next Page Id", next_pid)
+            }
+
+            PageDebug::FreeList { page_id, next_pid, free_pages } => {
+                write_kv!(f, "Free List Page", page_id)?;
+                write_kv!(f, "Next Page Id", next_pid)?;
+                write_page_ids(f, free_pages)
+            }
+
+            PageDebug::DataAllocator { page_id, pages } => {
+                write_kv!(f, "Allocator Page", page_id)?;
+                for (pid, remain_size) in pages {
+                    writeln!(f, "  page: {:<8} remain: {}", pid, remain_size)?;
+                }
+                Ok(())
+            }
+
+            PageDebug::Empty(page_id) => write_kv!(f, "Empty Page", page_id),
+
+            PageDebug::Unknown { page_id, magic } => {
+                write_kv!(f, "Unknown Page", page_id)?;
+                write_kv!(f, "Magic", format!("0x{:02X} 0x{:02X}", magic[0], magic[1]))
+            }
+        }
+    }
+
+}
+
+impl fmt::Display for JournalFrameDump {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame #{:<6} page: {:<8}", self.frame_id, self.page_id)?;
+        if self.delta_size > 0 {
+            write!(f, " delta: {:<6}", self.delta_size)?;
+        } else {
+            write!(f, " {:13}", "full")?;
+        }
+        // the db size is written only in the last frame of a transaction
+        if self.db_size > 0 {
+            write!(f, " commit, db size: {}", self.db_size)?;
+        }
+        write!(f, " salt: {:08X} {:08X}", self.salt1, self.salt2)
+    }
+
+}
@@ -25,11 +25,55 @@ pub struct FullDump {
     pub free_list_pid:  u32,
     pub free_list_size: u32,
     pub page_size:      NonZeroU32,
+    pub compat_features:   u64,
+    pub incompat_features: u64,
+    /// `None` for the databases created by the old versions.
+    pub created_at:     Option<bson::DateTime>,
+    /// The journal frames checkpointed into the file, `None` before the first checkpoint.
+    pub checkpoint_lsn: Option<u64>,
+    /// All the entries of the metadata area of the header page.
+    pub metadata:       Vec<(String, Vec<u8>)>,
     pub pages:          Vec<PageDump>,
 }
 
+/// The header of a database file read by [`Database::inspect_header`],
+/// the journal is not replayed, so the changes not checkpointed are not seen.
+///
+/// [`Database::inspect_header`]: crate::Database::inspect_header
+#[derive(Debug, Clone)]
+pub struct HeaderDump {
+    pub identifier:        String,
+    pub version:           String,
+    pub page_size:         NonZeroU32,
+    /// The pages of the file, including the free ones.
+    pub page_count:        u64,
+    pub compat_features:   u64,
+    pub incompat_features: u64,
+    /// `None` for the databases created by the old versions.
+    pub created_at:        Option<bson::DateTime>,
+    pub checkpoint_lsn:    Option<u64>,
+    /// `None` before the first checkpoint, or for the databases of the old versions.
+    pub checkpoint_at:     Option<bson::DateTime>,
+    /// The size of the journal left, the changes in it are not checkpointed.
+    pub journal_size:      Option<u64>,
+    pub collections:       Vec<CollectionSummary>,
+}
+
+/// An entry of the collection catalog in [`HeaderDump`].
+#[derive(Debug, Clone)]
+pub struct CollectionSummary {
+    pub name:       String,
+    pub created_at: bson::DateTime,
+    /// `None` if the collection doesn't keep the count.
+    pub count:      Option<u64>,
+    pub indexes:    Vec<String>,
+}
+
 pub struct JournalFrameDump {
     pub frame_id:      u32,
+    pub page_id:       u32,
+    /// 0 for the frame of a whole page.
+    pub delta_size:    u32,
     pub db_size:       u64,
     pub salt1:         u32,
     pub salt2:         NonZeroU32,
@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Convert between BSON and JSON, and parse the documents of
+//! [Extended JSON](https://www.mongodb.com/docs/manual/reference/mongodb-extended-json/).
+//!
+//! Both the canonical and the relaxed formats are accepted, so the values which
+//! can't be written in plain JSON are wrapped, e.g. `{"$oid": "..."}` for an `ObjectId`
+//! and `{"$date": "2024-01-01T00:00:00Z"}` for a `DateTime`. The order of the fields is kept.
+//!
+//! The `*_ejson` methods of [`Collection`](crate::Collection) take the strings directly,
+//! which is convenient for a shell and quick scripts.
+//!
+//! ```rust
+//! use polodb_core::ejson;
+//! use polodb_core::bson::{Bson, DateTime};
+//!
+//! let doc = ejson::parse_document(r#"{
+//!     "_id": {"$oid": "65a1b2c3d4e5f60718293a4b"},
+//!     "created": {"$date": "2024-01-01T00:00:00Z"},
+//!     "age": {"$gt": 18}
+//! }"#).unwrap();
+//! assert_eq!(doc.get_object_id("_id").unwrap().to_hex(), "65a1b2c3d4e5f60718293a4b");
+//! assert_eq!(doc.get("created"), Some(&Bson::DateTime(DateTime::from_millis(1704067200000))));
+//! assert_eq!(doc.get_document("age").unwrap().get_i32("$gt").unwrap(), 18);
+//! ```
+//!
+//! The other way, [`to_json`] converts a value to a [`serde_json::Value`], the types
+//! without a counterpart in JSON are kept as Extended JSON by default, or converted
+//! to plain values by [`JsonOptions`]:
+//!
+//! ```rust
+//! use polodb_core::ejson::{self, JsonOptions, TypeFidelity};
+//! use polodb_core::bson::{DateTime, doc};
+//! use polodb_core::bson::oid::ObjectId;
+//!
+//! let doc = doc! {
+//!     "_id": ObjectId::parse_str("65a1b2c3d4e5f60718293a4b").unwrap(),
+//!     "created": DateTime::from_millis(1704067200000),
+//! };
+//!
+//! let value = ejson::document_to_json(doc.clone(), &JsonOptions::default());
+//! assert_eq!(value["_id"]["$oid"], "65a1b2c3d4e5f60718293a4b");
+//! assert_eq!(value["created"]["$date"], "2024-01-01T00:00:00Z");
+//!
+//! let value = ejson::document_to_json(doc, &JsonOptions {
+//!     object_id: TypeFidelity::Stringify,
+//!     date_time: TypeFidelity::Lossy,
+//!     ..Default::default()
+//! });
+//! assert_eq!(value["_id"], "65a1b2c3d4e5f60718293a4b");
+//! assert_eq!(value["created"], 1704067200000i64);
+//! ```
+use std::convert::TryFrom;
+use bson::{Bson, Document};
+use serde_json::{Map, Value};
+use crate::{DbErr, DbResult};
+
+/// How a value of the types without a counterpart in JSON is converted by [`to_json`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TypeFidelity {
+    /// Keep the type in relaxed Extended JSON, e.g. `{"$oid": "..."}`,
+    /// so it's parsed back to the same value.
+    ExtendedJson,
+
+    /// A string: the hex of an `ObjectId`, the RFC 3339 time of a `DateTime`
+    /// and the base64 of a `Binary`.
+    Stringify,
+
+    /// The closest plain value: the hex of an `ObjectId`, the milliseconds
+    /// since the epoch of a `DateTime` and the array of the bytes of a `Binary`.
+    Lossy,
+}
+
+impl Default for TypeFidelity {
+
+    fn default() -> Self {
+        TypeFidelity::ExtendedJson
+    }
+
+}
+
+/// The options of [`to_json`], the types not listed here are
+/// always converted to relaxed Extended JSON.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    pub object_id: TypeFidelity,
+    pub date_time: TypeFidelity,
+    pub binary: TypeFidelity,
+}
+
+/// Convert a value to JSON.
+pub fn to_json(value: Bson, options: &JsonOptions) -> Value {
+    match value {
+        Bson::Document(doc) => document_to_json(doc, options),
+        Bson::Array(arr) => Value::Array(arr.into_iter().map(|item| to_json(item, options)).collect()),
+        Bson::ObjectId(oid) => match options.object_id {
+            TypeFidelity::ExtendedJson => Bson::ObjectId(oid).into_relaxed_extjson(),
+            TypeFidelity::Stringify | TypeFidelity::Lossy => Value::String(oid.to_hex()),
+        },
+        Bson::DateTime(datetime) => match options.date_time {
+            TypeFidelity::ExtendedJson => Bson::DateTime(datetime).into_relaxed_extjson(),
+            // the time out of the range of RFC 3339 is kept as the milliseconds
+            TypeFidelity::Stringify => match datetime.try_to_rfc3339_string() {
+                Ok(time) => Value::String(time),
+                Err(_) => Value::from(datetime.timestamp_millis()),
+            },
+            TypeFidelity::Lossy => Value::from(datetime.timestamp_millis()),
+        },
+        Bson::Binary(binary) => match options.binary {
+            TypeFidelity::ExtendedJson => Bson::Binary(binary).into_relaxed_extjson(),
+            TypeFidelity::Stringify => {
+                let mut value = Bson::Binary(binary).into_relaxed_extjson();
+                value["$binary"]["base64"].take()
+            }
+            TypeFidelity::Lossy => Value::Array(binary.bytes.into_iter().map(Value::from).collect()),
+        },
+        value => value.into_relaxed_extjson(),
+    }
+}
+
+/// Convert a document to a JSON object.
+pub fn document_to_json(doc: Document, options: &JsonOptions) -> Value {
+    let map: Map<String, Value> = doc
+        .into_iter()
+        .map(|(key, value)| (key, to_json(value, options)))
+        .collect();
+    Value::Object(map)
+}
+
+/// Convert a JSON value of Extended JSON to a value.
+pub fn from_json(value: Value) -> DbResult<Bson> {
+    Bson::try_from(value)
+        .map_err(|err| DbErr::ParseError(format!("invalid extended JSON: {}", err)))
+}
+
+/// Parse a value of Extended JSON.
+pub fn parse(s: &str) -> DbResult<Bson> {
+    let value: Value = serde_json::from_str(s)
+        .map_err(|err| DbErr::ParseError(format!("invalid extended JSON: {}", err)))?;
+    from_json(value)
+}
+
+/// Parse a document of Extended JSON, the value must be an object.
+pub fn parse_document(s: &str) -> DbResult<Document> {
+    match parse(s)? {
+        Bson::Document(doc) => Ok(doc),
+        value => Err(DbErr::ParseError(format!("expected an extended JSON object, got: {}", value))),
+    }
+}
+
+/// Parse an array of documents of Extended JSON, a single object is
+/// parsed as an array of one document.
+pub fn parse_documents(s: &str) -> DbResult<Vec<Document>> {
+    match parse(s)? {
+        Bson::Document(doc) => Ok(vec![doc]),
+        Bson::Array(arr) => arr
+            .into_iter()
+            .map(|value| match value {
+                Bson::Document(doc) => Ok(doc),
+                value => Err(DbErr::ParseError(format!("expected an extended JSON object, got: {}", value))),
+            })
+            .collect(),
+        value => Err(DbErr::ParseError(format!("expected an extended JSON array, got: {}", value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Binary, Bson, DateTime, doc};
+    use bson::oid::ObjectId;
+    use bson::spec::BinarySubtype;
+    use serde_json::json;
+    use crate::DbErr;
+    use super::{JsonOptions, TypeFidelity, document_to_json, parse, parse_document, parse_documents, to_json};
+
+    #[test]
+    fn test_parse_document() {
+        let doc = parse_document(r#"{"b": 1, "a": {"$oid": "65a1b2c3d4e5f60718293a4b"}, "c": [1.5, "x"]}"#).unwrap();
+        assert_eq!(doc, doc! {
+            "b": 1,
+            "a": ObjectId::parse_str("65a1b2c3d4e5f60718293a4b").unwrap(),
+            "c": [1.5, "x"],
+        });
+        assert_eq!(doc.keys().collect::<Vec<_>>(), vec!["b", "a", "c"]);
+
+        assert!(matches!(parse_document("[1, 2]"), Err(DbErr::ParseError(_))));
+        assert!(matches!(parse_document("{\"a\": "), Err(DbErr::ParseError(_))));
+        assert!(matches!(parse_document(r#"{"a": {"$oid": "xyz"}}"#), Err(DbErr::ParseError(_))));
+    }
+
+    #[test]
+    fn test_parse_documents() {
+        assert_eq!(parse_documents(r#"[{"a": 1}, {"a": 2}]"#).unwrap(), vec![doc! { "a": 1 }, doc! { "a": 2 }]);
+        assert_eq!(parse_documents(r#"{"a": 1}"#).unwrap(), vec![doc! { "a": 1 }]);
+        assert!(parse_documents("[]").unwrap().is_empty());
+        assert!(matches!(parse_documents("[{\"a\": 1}, 2]"), Err(DbErr::ParseError(_))));
+    }
+
+    fn all(fidelity: TypeFidelity) -> JsonOptions {
+        JsonOptions {
+            object_id: fidelity,
+            date_time: fidelity,
+            binary: fidelity,
+        }
+    }
+
+    #[test]
+    fn test_to_json() {
+        let doc = doc! {
+            "id": ObjectId::parse_str("65a1b2c3d4e5f60718293a4b").unwrap(),
+            "at": DateTime::from_millis(1704067200000),
+            "data": Binary { subtype: BinarySubtype::Generic, bytes: vec![1, 2, 3] },
+            "nested": [{ "n": 1, "x": 1.5, "l": 1i64 << 40 }],
+        };
+
+        let value = document_to_json(doc.clone(), &JsonOptions::default());
+        assert_eq!(value, json!({
+            "id": { "$oid": "65a1b2c3d4e5f60718293a4b" },
+            "at": { "$date": "2024-01-01T00:00:00Z" },
+            "data": { "$binary": { "base64": "AQID", "subType": "00" } },
+            "nested": [{ "n": 1, "x": 1.5, "l": 1i64 << 40 }],
+        }));
+        assert_eq!(parse(&value.to_string()).unwrap(), Bson::Document(doc.clone()));
+
+        let value = document_to_json(doc.clone(), &all(TypeFidelity::Stringify));
+        assert_eq!(value["id"], "65a1b2c3d4e5f60718293a4b");
+        assert_eq!(value["at"], "2024-01-01T00:00:00Z");
+        assert_eq!(value["data"], "AQID");
+
+        let value = document_to_json(doc, &all(TypeFidelity::Lossy));
+        assert_eq!(value["id"], "65a1b2c3d4e5f60718293a4b");
+        assert_eq!(value["at"], 1704067200000i64);
+        assert_eq!(value["data"], json!([1, 2, 3]));
+
+        // the keys are kept in the order
+        let value = to_json(Bson::Document(doc! { "b": 1, "a": 2 }), &JsonOptions::default());
+        assert_eq!(value.as_object().unwrap().keys().collect::<Vec<_>>(), vec!["b", "a"]);
+    }
+
+}
@@ -111,6 +111,57 @@ pub struct VersionMismatchError {
     pub expect_version: [u8; 4],
 }
 
+/// A stored document can't be deserialized into the type of the collection.
+#[derive(Debug)]
+pub struct DeserializeDocumentError {
+    pub collection: String,
+    pub id: Option<bson::Bson>,
+    pub error: bson::de::Error,
+}
+
+impl fmt::Display for DeserializeDocumentError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.id {
+            Some(id) => write!(f, "the document {} of collection '{}' can't be deserialized: {}",
+                               id, self.collection, self.error),
+            None => write!(f, "a document of collection '{}' can't be deserialized: {}",
+                           self.collection, self.error),
+        }
+    }
+
+}
+
+/// The values of the fields are taken by another document in the unique index.
+#[derive(Debug)]
+pub struct DuplicateKeyError {
+    pub index: String,
+    pub key: String,
+}
+
+impl fmt::Display for DuplicateKeyError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "duplicate key {} of the unique index \"{}\"", self.key, self.index)
+    }
+
+}
+
+/// The collection is archived in a database not attached.
+#[derive(Debug)]
+pub struct CollectionArchivedError {
+    pub collection: String,
+    pub alias: String,
+}
+
+impl fmt::Display for CollectionArchivedError {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "collection \"{}\" is archived in '{}', which is not attached", self.collection, self.alias)
+    }
+
+}
+
 #[derive(Debug)]
 pub enum DbErr {
     UnexpectedIdType(u8, u8),
@@ -119,6 +170,8 @@ pub enum DbErr {
     ValidationError(String),
     InvalidOrderOfIndex(String),
     IndexAlreadyExists(String),
+    IndexNotFound(String),
+    DuplicateKey(Box<DuplicateKeyError>),
     FieldTypeUnexpected(Box<FieldTypeUnexpectedStruct>),
     UnexpectedTypeForOp(Box<UnexpectedTypeForOpStruct>),
     ParseError(String),
@@ -150,6 +203,7 @@ pub enum DbErr {
     UnknownTransactionType,
     BufferNotEnough(usize),
     UnknownUpdateOperation(String),
+    UnknownQueryOperator(String),
     IncrementNullField,
     VmIsHalt,
     CollectionAlreadyExits(String),
@@ -164,6 +218,34 @@ pub enum DbErr {
     NoTransactionStarted,
     InvalidSession(Box<ObjectId>),
     SessionOutdated,
+    PermissionDenied(String),
+    IllegalAttachAlias(String),
+    AttachAliasAlreadyExists(String),
+    AttachAliasNotFound(String),
+    CollectionArchived(Box<CollectionArchivedError>),
+    CompressionError(String),
+    UnknownAggregationOperation(String),
+    ConversionFailure(String),
+    UnsupportedFeatures(u64),
+    HeaderMetadataFull,
+    DatabaseSizeLimitExceeded(u64),
+    NotSupportedByBackend(String),
+    TooManyOpenCursors(usize),
+    TooManySessions(usize),
+    TooManyCollections(usize),
+    CursorNotFound(i64),
+    IndexedDbError(String),
+    EncryptionError(String),
+    TaskPanicked,
+    PinnedPagesExceeded(usize, usize),
+    DeserializeDocument(Box<DeserializeDocumentError>),
+    PageIdOutOfRange(u32, u64),
+    AppendOnlyCollection(String),
+    AppendOutOfOrder(String),
+    MigrationOutOfOrder(u64, u64),
+    ForeignKeyViolation(String),
+    OpfsError(String),
+    PageSizeMismatch(u32, u32),
 }
 
 impl DbErr {
@@ -198,6 +280,8 @@ impl fmt::Display for DbErr {
             DbErr::ValidationError(reason) => write!(f, "ValidationError: {}", reason),
             DbErr::InvalidOrderOfIndex(index_key_name) => write!(f, "invalid order of index: {}", index_key_name),
             DbErr::IndexAlreadyExists(index_key_name) => write!(f, "index for {} already exists", index_key_name),
+            DbErr::IndexNotFound(name) => write!(f, "index \"{}\" not found", name),
+            DbErr::DuplicateKey(err) => write!(f, "{}", err),
             DbErr::FieldTypeUnexpected(st) => write!(f, "{}", st),
             DbErr::UnexpectedTypeForOp(st) =>
                 write!(f, "unexpected type: {} for op: {}, expected: {}", st.actual_ty, st.operation, st.expected_ty),
@@ -233,6 +317,7 @@ impl fmt::Display for DbErr {
             DbErr::UnknownTransactionType => write!(f, "unknown transaction type"),
             DbErr::BufferNotEnough(buffer_size) => write!(f, "buffer not enough, {} needed", buffer_size),
             DbErr::UnknownUpdateOperation(op) => write!(f, "unknown update operation: '{}'", op),
+            DbErr::UnknownQueryOperator(op) => write!(f, "unknown query operator: '{}'", op),
             DbErr::IncrementNullField => write!(f, "can not increment a field which is null"),
             DbErr::VmIsHalt => write!(f, "Vm can not execute because it's halt"),
             DbErr::Busy => write!(f, "database busy"),
@@ -261,6 +346,36 @@ impl fmt::Display for DbErr {
             DbErr::NoTransactionStarted => write!(f, "no transaction started"),
             DbErr::InvalidSession(sid) => write!(f, "invalid session: {}", sid),
             DbErr::SessionOutdated => write!(f, "session is outdated"),
+            DbErr::PermissionDenied(op) => write!(f, "permission denied for '{}'", op),
+            DbErr::IllegalAttachAlias(alias) => write!(f, "attach alias \"{}\" is illegal", alias),
+            DbErr::AttachAliasAlreadyExists(alias) => write!(f, "a database is already attached as '{}'", alias),
+            DbErr::AttachAliasNotFound(alias) => write!(f, "no database is attached as '{}'", alias),
+            DbErr::CollectionArchived(err) => write!(f, "{}", err),
+            DbErr::CompressionError(msg) => write!(f, "compression error: {}", msg),
+            DbErr::UnknownAggregationOperation(op) => write!(f, "unknown aggregation operation: {}", op),
+            DbErr::ConversionFailure(msg) => write!(f, "conversion failure: {}", msg),
+            DbErr::UnsupportedFeatures(flags) => write!(f, "the database uses unsupported features: 0x{:016X}, please upgrade", flags),
+            DbErr::HeaderMetadataFull => write!(f, "the metadata area of the header page is full"),
+            DbErr::DatabaseSizeLimitExceeded(max_size) => write!(f, "the database exceeds the size limit: {} bytes", max_size),
+            DbErr::NotSupportedByBackend(op) => write!(f, "'{}' is not supported by the backend", op),
+            DbErr::TooManyOpenCursors(max) => write!(f, "too many open cursors, the max is {}", max),
+            DbErr::TooManySessions(max) => write!(f, "too many sessions, the max is {}", max),
+            DbErr::TooManyCollections(max) => write!(f, "too many collections, the max is {}", max),
+            DbErr::CursorNotFound(id) => write!(f, "cursor {} not found", id),
+            DbErr::IndexedDbError(msg) => write!(f, "IndexedDB error: {}", msg),
+            DbErr::EncryptionError(msg) => write!(f, "encryption error: {}", msg),
+            DbErr::TaskPanicked => write!(f, "the operation of the async API panicked"),
+            DbErr::PinnedPagesExceeded(pages, limit) => write!(f, "{} pinned pages exceed the budget of {} pages of the page cache", pages, limit),
+            DbErr::DeserializeDocument(err) => write!(f, "{}", err),
+            DbErr::PageIdOutOfRange(page_id, count) => write!(f, "page {} is out of the {} pages of the database", page_id, count),
+            DbErr::AppendOnlyCollection(name) => write!(f, "the documents of the append-only collection \"{}\" can't be updated or deleted", name),
+            DbErr::AppendOutOfOrder(key) => write!(f, "the key {} is not greater than the keys of the append-only collection", key),
+            DbErr::MigrationOutOfOrder(version, latest) => write!(f, "the migration {} is older than the applied version {}", version, latest),
+            DbErr::ForeignKeyViolation(msg) => write!(f, "foreign key violation: {}", msg),
+            DbErr::OpfsError(msg) => write!(f, "OPFS error: {}", msg),
+            DbErr::PageSizeMismatch(expect, actual) => {
+                write!(f, "the page size of the database is mismatch. expect: {}, actual: {}", expect, actual)
+            },
         }
     }
 
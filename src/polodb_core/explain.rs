@@ -0,0 +1,217 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Describe how a query is executed.
+//!
+//! With [`ExplainVerbosity::ExecutionStats`], the query is run and the statistics
+//! of every stage are reported, the documents found are discarded.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{Document, doc};
+//! use polodb_core::explain::ExplainVerbosity;
+//!
+//! let db = Database::open_memory().unwrap();
+//! let books = db.collection::<Document>("books");
+//! for i in 0..10 {
+//!     books.insert_one(doc! { "_id": i, "year": 2000 + i % 2 }).unwrap();
+//! }
+//!
+//! let explain = books.explain(doc! { "year": 2001 }, ExplainVerbosity::ExecutionStats).unwrap();
+//! assert_eq!(explain.stages, vec!["COLLSCAN".to_string()]);
+//!
+//! let stats = explain.execution_stats.unwrap();
+//! assert_eq!(stats.n_returned, 5);
+//! assert_eq!(stats.total_docs_examined, 10);
+//! ```
+use std::time::Duration;
+use bson::{Bson, Document};
+use bson::spec::ElementType;
+use crate::metrics::MetricsData;
+use crate::clock::Clock;
+
+/// The stage reading the document by the primary key.
+pub const STAGE_IDHACK: &str = "IDHACK";
+
+/// The stage scanning the documents found by an index.
+pub const STAGE_IXSCAN: &str = "IXSCAN";
+
+/// The stage scanning all the documents of the collection.
+pub const STAGE_COLLSCAN: &str = "COLLSCAN";
+
+/// The stage sorting the documents found in memory,
+/// the order of `_id` is the order of the scan, which is not sorted again.
+pub const STAGE_SORT: &str = "SORT";
+
+/// The stage applying the projection on the documents found.
+pub const STAGE_PROJECTION: &str = "PROJECTION";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExplainVerbosity {
+    /// Describe the stages of the plan without running the query.
+    QueryPlanner,
+
+    /// Run the query and report the statistics of the execution.
+    ExecutionStats,
+}
+
+#[derive(Debug, Clone)]
+pub struct Explain {
+    /// The name of the collection.
+    pub namespace: String,
+
+    /// The stages of the plan, in the order they are executed.
+    pub stages: Vec<String>,
+
+    /// `None` unless the verbosity is [`ExplainVerbosity::ExecutionStats`].
+    pub execution_stats: Option<ExecutionStats>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionStats {
+    /// The number of the documents returned.
+    pub n_returned: u64,
+
+    /// Measured by the [`clock`](crate::clock) of the config.
+    pub execution_time: Duration,
+
+    /// The keys of the btree examined.
+    pub total_keys_examined: u64,
+
+    /// The documents read from the storage.
+    pub total_docs_examined: u64,
+
+    /// The pages fetched from the backend, read from the [`Metrics`] of the database.
+    /// `None` if the metrics are not enabled.
+    ///
+    /// [`Metrics`]: crate::Metrics
+    pub pages_read: Option<u64>,
+
+    /// The pages found in the page cache, `None` if the metrics are not enabled.
+    pub pages_hit_cache: Option<u64>,
+
+    pub stages: Vec<StageStats>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StageStats {
+    pub stage: String,
+
+    /// The number of the documents passed to the next stage.
+    pub n_returned: u64,
+
+    pub execution_time: Duration,
+}
+
+/// The stages to find the documents satisfying the query,
+/// `uses_index` if the planner found an index for the query.
+pub(crate) fn plan_stages(query: Option<&Document>, uses_index: bool, has_sort: bool, has_projection: bool) -> Vec<String> {
+    let has_pkey = query
+        .and_then(|query| query.get("_id"))
+        .map(|id| id.element_type() != ElementType::EmbeddedDocument)
+        .unwrap_or(false);
+    let first = if has_pkey {
+        STAGE_IDHACK
+    } else if uses_index {
+        STAGE_IXSCAN
+    } else {
+        STAGE_COLLSCAN
+    };
+    let mut stages = vec![first.to_string()];
+    if has_sort {
+        stages.push(STAGE_SORT.to_string());
+    }
+    if has_projection {
+        stages.push(STAGE_PROJECTION.to_string());
+    }
+    stages
+}
+
+/// Measure the execution time of a stage by the clock of the config.
+pub(crate) struct Stopwatch<'a> {
+    clock: &'a dyn Clock,
+    start: Duration,
+}
+
+impl<'a> Stopwatch<'a> {
+
+    pub(crate) fn start(clock: &'a dyn Clock) -> Stopwatch<'a> {
+        Stopwatch {
+            clock,
+            start: clock.now(),
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_sub(self.start)
+    }
+
+}
+
+/// Set the page counters by the metrics read before and after the execution.
+pub(crate) fn set_page_stats(stats: &mut ExecutionStats, before: &MetricsData, after: &MetricsData) {
+    stats.pages_read = Some(after.page_fetch_count.saturating_sub(before.page_fetch_count) as u64);
+    stats.pages_hit_cache = Some(after.page_hit_count.saturating_sub(before.page_hit_count) as u64);
+}
+
+impl Explain {
+
+    /// Convert to a document with the field names of MongoDB,
+    /// such as `executionStats.totalDocsExamined`.
+    pub fn to_document(&self) -> Document {
+        let mut doc = Document::new();
+        doc.insert("namespace", self.namespace.clone());
+        let stages: Vec<Bson> = self.stages.iter().map(|stage| Bson::String(stage.clone())).collect();
+        doc.insert("stages", stages);
+        if let Some(stats) = &self.execution_stats {
+            let mut stats_doc = Document::new();
+            stats_doc.insert("nReturned", stats.n_returned as i64);
+            stats_doc.insert("executionTimeMicros", stats.execution_time.as_micros() as i64);
+            stats_doc.insert("totalKeysExamined", stats.total_keys_examined as i64);
+            stats_doc.insert("totalDocsExamined", stats.total_docs_examined as i64);
+            if let Some(pages_read) = stats.pages_read {
+                stats_doc.insert("pagesRead", pages_read as i64);
+            }
+            if let Some(pages_hit_cache) = stats.pages_hit_cache {
+                stats_doc.insert("pagesHitCache", pages_hit_cache as i64);
+            }
+            let stages: Vec<Bson> = stats.stages.iter().map(|stage| {
+                let mut stage_doc = Document::new();
+                stage_doc.insert("stage", stage.stage.clone());
+                stage_doc.insert("nReturned", stage.n_returned as i64);
+                stage_doc.insert("executionTimeMicros", stage.execution_time.as_micros() as i64);
+                Bson::Document(stage_doc)
+            }).collect();
+            stats_doc.insert("stages", stages);
+            doc.insert("executionStats", stats_doc);
+        }
+        doc
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use super::{plan_stages, STAGE_COLLSCAN, STAGE_IDHACK, STAGE_IXSCAN, STAGE_PROJECTION, STAGE_SORT};
+
+    #[test]
+    fn test_plan_stages() {
+        assert_eq!(plan_stages(None, false, false, false), vec![STAGE_COLLSCAN.to_string()]);
+        assert_eq!(plan_stages(Some(&doc! { "_id": 1 }), false, false, true), vec![
+            STAGE_IDHACK.to_string(),
+            STAGE_PROJECTION.to_string(),
+        ]);
+        assert_eq!(plan_stages(Some(&doc! { "_id": { "$gt": 1 } }), false, false, false), vec![STAGE_COLLSCAN.to_string()]);
+        assert_eq!(plan_stages(Some(&doc! { "age": 18 }), true, false, false), vec![STAGE_IXSCAN.to_string()]);
+        assert_eq!(plan_stages(None, false, true, true), vec![
+            STAGE_COLLSCAN.to_string(),
+            STAGE_SORT.to_string(),
+            STAGE_PROJECTION.to_string(),
+        ]);
+    }
+
+}
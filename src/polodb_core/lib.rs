@@ -35,6 +35,25 @@
 //! let db = Database::open_memory().unwrap();
 //! ```
 //!
+//! ## Without a file system
+//!
+//! The file backend is enabled by the default feature `fs`. Without it, the query,
+//! aggregation and index layers are built for `wasm32-unknown-unknown`, such as
+//! Cloudflare Workers, and the database is opened in memory, on an [`ObjectStore`]
+//! implemented by the runtime, on IndexedDB in a browser with the feature `indexeddb`,
+//! or on the Origin Private File System in a Web Worker with the feature `opfs`.
+//!
+//! ```toml
+//! [dependencies]
+//! polodb_core = { version = "3", default-features = false }
+//! ```
+//!
+//! ## In an async runtime
+//!
+//! The operations of [`Database`] are blocking. An [`AsyncDatabase`] runs them on
+//! a pool of threads and returns the futures of them, which can be awaited in tokio
+//! or any other runtime.
+//!
 //! # Example
 //!
 //!  ```rust
@@ -108,11 +127,13 @@
 
 extern crate core;
 
+mod aggregation;
 mod btree;
 mod page;
 mod vm;
 mod error;
 mod cursor;
+mod server_cursor;
 mod session;
 
 mod db;
@@ -126,19 +147,54 @@ mod transaction;
 mod bson_utils;
 pub mod results;
 pub mod commands;
+pub mod middleware;
+pub mod pagination;
+pub mod subscription;
+pub mod explain;
+pub mod schema;
+pub mod migrations;
+pub mod vfs;
+#[cfg(all(feature = "maintenance", not(target_arch = "wasm32")))]
+pub mod maintenance;
+pub mod text;
+pub mod ejson;
+pub mod clock;
+#[cfg(feature = "debug")]
+pub mod debug;
 mod data_structures;
 mod collection_info;
+mod compression;
+mod options;
+mod projection;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "fs")]
 pub mod test_utils;
 mod metrics;
 
-pub use db::{Database, Collection, DbResult, IndexedDbContext};
-pub use config::Config;
+pub use db::{Database, Collection, KvNamespace, DbResult, HandleRequestResult};
+#[cfg(feature = "indexeddb")]
+pub use db::IndexedDbContext;
+#[cfg(feature = "opfs")]
+pub use db::OpfsContext;
+pub use config::{Config, GrowthStrategy, IndexedDbCompaction, JournalThrottle, OperatorMode, PageCachePolicy};
+pub use aggregation::{CustomAccumulator, ExprFunction};
+pub use compression::Compression;
+pub use options::{
+    CollectionOptions, CreateCollectionOptions, DeleteOptions, FindOptions, HandleOptions, IndexOptions,
+    InsertManyOptions, Priority, UpdateOptions,
+};
 pub use transaction::TransactionType;
+pub use collection_info::{ForeignKey, GeneratedField, OnDelete};
 pub use db::db_handle::DbHandle;
-pub use error::DbErr;
+pub use error::{CollectionArchivedError, DbErr, DeserializeDocumentError, DuplicateKeyError};
 pub use session::ClientSession;
-pub use metrics::Metrics;
+pub use metrics::{CollectionMetrics, Metrics};
+#[cfg(feature = "fs")]
+pub use backend::file::{RecoveryReport, RecoveryState};
+pub use backend::object_store::ObjectStore;
+#[cfg(feature = "fs")]
+pub use db::{DatabaseManager, DatabaseManagerConfig};
+#[cfg(not(target_arch = "wasm32"))]
+pub use db::{AsyncCollection, AsyncDatabase, AsyncTask};
 
 pub extern crate bson;
@@ -0,0 +1,256 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A background thread running the maintenance jobs of a database,
+//! enabled by the `maintenance` feature.
+//!
+//! The jobs of a round share a time budget. A job works in small steps,
+//! every step holds the lock of the database shortly and the thread pauses
+//! between the steps, so the foreground operations are not starved.
+//! The job interrupted by the deadline is resumed first in the next round.
+//! The jobs run through a handle of [`Priority::Background`], which lets the
+//! interactive operations waiting for the lock go first.
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//! use polodb_core::Database;
+//! use polodb_core::maintenance::{MaintenanceConfig, MaintenanceJob, MaintenanceScheduler};
+//!
+//! let db = Arc::new(Database::open_memory().unwrap());
+//! let scheduler = MaintenanceScheduler::start(db.clone(), vec![
+//!     MaintenanceJob::Checkpoint,
+//!     MaintenanceJob::ExpireTtl {
+//!         collection: "sessions".into(),
+//!         field: "updatedAt".into(),
+//!         expire_after: Duration::from_secs(3600),
+//!     },
+//! ], MaintenanceConfig::default());
+//! scheduler.run_now();
+//! ```
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use bson::{doc, DateTime, Document};
+use crate::{Database, DbErr, DbResult, HandleOptions, Priority};
+
+type CustomJob = Arc<dyn Fn(&Database, Instant) -> DbResult<Progress> + Send + Sync>;
+
+/// The state of a job when it returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Done,
+    /// The job stops at the deadline, it's resumed in the next round.
+    Interrupted,
+}
+
+#[derive(Clone)]
+pub enum MaintenanceJob {
+    /// Checkpoint the journal into the database file,
+    /// skipped if a transaction is running.
+    Checkpoint,
+
+    /// Count and persist the stats of the collections created by the older versions.
+    RefreshStats,
+
+    /// Delete the documents whose `field` is a date earlier than `expire_after` ago,
+    /// by the [`clock`](crate::clock) of the config.
+    ExpireTtl {
+        collection:   String,
+        field:        String,
+        expire_after: Duration,
+    },
+
+    /// Delete the documents whose `expireAt` field is a date earlier than now,
+    /// see [`Collection::delete_expired`](crate::Collection::delete_expired).
+    ExpireAt {
+        collection: String,
+    },
+
+    /// A job of the application, such as a compaction.
+    /// It's called with the deadline of the round and should return before it.
+    Custom {
+        name: String,
+        run:  CustomJob,
+    },
+}
+
+impl MaintenanceJob {
+
+    pub fn name(&self) -> &str {
+        match self {
+            MaintenanceJob::Checkpoint => "checkpoint",
+            MaintenanceJob::RefreshStats => "refreshStats",
+            MaintenanceJob::ExpireTtl { .. } => "expireTtl",
+            MaintenanceJob::ExpireAt { .. } => "expireAt",
+            MaintenanceJob::Custom { name, .. } => name.as_str(),
+        }
+    }
+
+}
+
+pub struct MaintenanceConfig {
+    /// How often a round of the jobs starts.
+    pub interval:   Duration,
+    /// The time a round can take.
+    pub budget:     Duration,
+    /// The documents deleted by a step of the TTL expiry.
+    pub batch_size: usize,
+    /// The pause between the steps, which leaves the database to the foreground.
+    pub pause:      Duration,
+}
+
+impl Default for MaintenanceConfig {
+
+    fn default() -> Self {
+        MaintenanceConfig {
+            interval:   Duration::from_secs(5),
+            budget:     Duration::from_millis(200),
+            batch_size: 100,
+            pause:      Duration::from_millis(1),
+        }
+    }
+
+}
+
+struct Scheduler {
+    db:     Arc<Database>,
+    jobs:   Vec<MaintenanceJob>,
+    config: MaintenanceConfig,
+    // the index of the job the next round starts with
+    next:   usize,
+}
+
+impl Scheduler {
+
+    fn run_round(&mut self) {
+        if self.jobs.is_empty() {
+            return;
+        }
+        let deadline = Instant::now() + self.config.budget;
+        for _ in 0..self.jobs.len() {
+            if Instant::now() >= deadline {
+                return;
+            }
+            let job = &self.jobs[self.next];
+            match self.run_job(job, deadline) {
+                Ok(Progress::Interrupted) => return,
+                Ok(Progress::Done) => (),
+                Err(err) => crate::polo_log!("maintenance job {} failed: {}", job.name(), err),
+            }
+            self.next = (self.next + 1) % self.jobs.len();
+        }
+    }
+
+    fn run_job(&self, job: &MaintenanceJob, deadline: Instant) -> DbResult<Progress> {
+        match job {
+            MaintenanceJob::Checkpoint => match self.db.checkpoint() {
+                Ok(()) | Err(DbErr::Busy) => Ok(Progress::Done),
+                Err(err) => Err(err),
+            },
+            MaintenanceJob::RefreshStats => {
+                for name in self.db.list_collection_names()? {
+                    if Instant::now() >= deadline {
+                        return Ok(Progress::Interrupted);
+                    }
+                    if self.db.refresh_stats(&name)? {
+                        thread::sleep(self.config.pause);
+                    }
+                }
+                Ok(Progress::Done)
+            }
+            MaintenanceJob::ExpireTtl { collection, field, expire_after } => {
+                let now = self.db.config()?.clock().now();
+                let expire_at = now.saturating_sub(*expire_after);
+                let query = doc! {
+                    field.as_str(): { "$lt": DateTime::from_millis(expire_at.as_millis() as i64) },
+                };
+                self.delete_in_batches(collection, query, deadline)
+            }
+            MaintenanceJob::ExpireAt { collection } => {
+                let query = self.db.expired_query()?;
+                self.delete_in_batches(collection, query, deadline)
+            }
+            MaintenanceJob::Custom { run, .. } => run(&self.db, deadline),
+        }
+    }
+
+    fn delete_in_batches(&self, collection: &str, query: Document, deadline: Instant) -> DbResult<Progress> {
+        let batch_size = self.config.batch_size.max(1);
+        loop {
+            let count = self.db.delete_batch(collection, query.clone(), batch_size)?;
+            if count < batch_size {
+                return Ok(Progress::Done);
+            }
+            if Instant::now() >= deadline {
+                return Ok(Progress::Interrupted);
+            }
+            thread::sleep(self.config.pause);
+        }
+    }
+
+}
+
+/// Run the maintenance jobs of a database on a background thread,
+/// the thread is stopped when the scheduler is dropped.
+pub struct MaintenanceScheduler {
+    scheduler:   Arc<Mutex<Scheduler>>,
+    stop_sender: Option<Sender<()>>,
+    worker:      Option<JoinHandle<()>>,
+}
+
+impl MaintenanceScheduler {
+
+    pub fn start(db: Arc<Database>, jobs: Vec<MaintenanceJob>, config: MaintenanceConfig) -> MaintenanceScheduler {
+        let interval = config.interval;
+        let db = Arc::new(db.with_options(HandleOptions {
+            priority: Priority::Background,
+            ..db.handle_options().clone()
+        }));
+        let scheduler = Arc::new(Mutex::new(Scheduler {
+            db,
+            jobs,
+            config,
+            next: 0,
+        }));
+
+        let (stop_sender, stop_receiver) = mpsc::channel::<()>();
+        let worker_scheduler = scheduler.clone();
+        let worker = thread::Builder::new()
+            .name("polodb-scheduler".into())
+            .spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stop_receiver.recv_timeout(interval) {
+                    worker_scheduler.lock().unwrap().run_round();
+                }
+            })
+            .expect("spawn maintenance thread failed");
+
+        MaintenanceScheduler {
+            scheduler,
+            stop_sender: Some(stop_sender),
+            worker: Some(worker),
+        }
+    }
+
+    /// Run a round of the jobs on the current thread immediately.
+    pub fn run_now(&self) {
+        self.scheduler.lock().unwrap().run_round();
+    }
+
+}
+
+impl Drop for MaintenanceScheduler {
+
+    fn drop(&mut self) {
+        // dropping the sender wakes up the worker
+        self.stop_sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+
+}
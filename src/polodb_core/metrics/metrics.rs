@@ -5,8 +5,10 @@
  */
 use std::sync::{Arc, Mutex};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use bson::oid::ObjectId;
 use hashbrown::HashMap;
+use serde::Serialize;
 
 #[derive(Clone)]
 pub struct Metrics {
@@ -29,11 +31,22 @@ impl Metrics {
         self.inner.enable()
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.inner.enable.load(Ordering::Relaxed)
+    }
+
     pub fn data(&self) -> MetricsData {
         let inner = self.inner.data.lock().unwrap();
         inner.data.clone()
     }
 
+    /// The metrics of the operations on the collection,
+    /// `None` if the collection is not operated since the metrics are enabled.
+    pub fn collection(&self, name: &str) -> Option<CollectionMetrics> {
+        let inner = self.inner.data.lock().unwrap();
+        inner.collections.get(name).cloned()
+    }
+
     /// trace the data page allocation
     #[inline]
     pub(crate) fn add_data_page(&self, remain_size: u32) {
@@ -65,6 +78,37 @@ impl Metrics {
         self.inner.page_hit_cache();
     }
 
+    /// The pages of the journal beyond `journal_full_size`.
+    #[inline]
+    pub(crate) fn set_journal_lag(&self, lag: usize) {
+        self.inner.set_journal_lag(lag);
+    }
+
+    /// The bytes of the documents read from the storage.
+    #[inline]
+    pub(crate) fn read_bytes(&self, size: usize) {
+        self.inner.read_bytes(size);
+    }
+
+    /// The bytes of the documents written to the storage.
+    #[inline]
+    pub(crate) fn write_bytes(&self, size: usize) {
+        self.inner.write_bytes(size);
+    }
+
+    /// Reset the bytes counted before the operation on a collection.
+    #[inline]
+    pub(crate) fn start_operation(&self) {
+        self.inner.start_operation();
+    }
+
+    /// Add the operation and the bytes counted since [`Metrics::start_operation`]
+    /// to the metrics of the collection.
+    #[inline]
+    pub(crate) fn finish_operation(&self, col_name: &str, is_write: bool, latency: Duration) {
+        self.inner.finish_operation(col_name, is_write, latency);
+    }
+
     pub(crate) fn commit(&self) {
         self.inner.commit(self.sid.as_ref());
     }
@@ -132,8 +176,9 @@ impl MetricsInner {
             Some(sid) => data_wrapper.session.get_mut(sid).unwrap(),
             None => &mut data_wrapper.data,
         };
-        data.data_page_count -= 1;
-        data.data_page_spaces -= remain_size as usize;
+        // the page may be used before the metrics are enabled
+        data.data_page_count = data.data_page_count.saturating_sub(1);
+        data.data_page_spaces = data.data_page_spaces.saturating_sub(remain_size as usize);
     }
 
     pub(crate) fn use_space_in_data_page(&self, sid: Option<&ObjectId>,used_size: u32) {
@@ -157,7 +202,7 @@ impl MetricsInner {
             Some(sid) => data_wrapper.session.get_mut(sid).unwrap(),
             None => &mut data_wrapper.data,
         };
-        data.data_page_used_bytes -= used_size as usize;
+        data.data_page_used_bytes = data.data_page_used_bytes.saturating_sub(used_size as usize);
     }
 
     pub(crate) fn commit(&self, sid: Option<&ObjectId>) {
@@ -193,6 +238,55 @@ impl MetricsInner {
         data_wrapper.data.page_hit_count += 1;
     }
 
+    pub(crate) fn set_journal_lag(&self, lag: usize) {
+        test_enable!(self);
+
+        let mut data_wrapper = self.data.lock().unwrap();
+        data_wrapper.data.journal_lag = lag;
+    }
+
+    pub(crate) fn read_bytes(&self, size: usize) {
+        test_enable!(self);
+
+        let mut data_wrapper = self.data.lock().unwrap();
+        data_wrapper.pending_bytes_read += size as u64;
+    }
+
+    pub(crate) fn write_bytes(&self, size: usize) {
+        test_enable!(self);
+
+        let mut data_wrapper = self.data.lock().unwrap();
+        data_wrapper.pending_bytes_written += size as u64;
+    }
+
+    pub(crate) fn start_operation(&self) {
+        test_enable!(self);
+
+        let mut data_wrapper = self.data.lock().unwrap();
+        data_wrapper.pending_bytes_read = 0;
+        data_wrapper.pending_bytes_written = 0;
+    }
+
+    pub(crate) fn finish_operation(&self, col_name: &str, is_write: bool, latency: Duration) {
+        test_enable!(self);
+
+        let mut data_wrapper = self.data.lock().unwrap();
+        let bytes_read = std::mem::take(&mut data_wrapper.pending_bytes_read);
+        let bytes_written = std::mem::take(&mut data_wrapper.pending_bytes_written);
+        if !data_wrapper.collections.contains_key(col_name) {
+            data_wrapper.collections.insert(col_name.to_string(), CollectionMetrics::default());
+        }
+        let collection = data_wrapper.collections.get_mut(col_name).unwrap();
+        if is_write {
+            collection.writes += 1;
+        } else {
+            collection.reads += 1;
+        }
+        collection.bytes_read += bytes_read;
+        collection.bytes_written += bytes_written;
+        collection.total_latency_micros += latency.as_micros() as u64;
+    }
+
 }
 
 #[derive(Clone)]
@@ -202,6 +296,9 @@ pub struct MetricsData {
     pub data_page_used_bytes: usize,
     pub page_fetch_count: usize,
     pub page_hit_count:   usize,
+    /// The pages of the journal beyond `journal_full_size`,
+    /// which are not checkpointed because the sessions are reading them.
+    pub journal_lag:      usize,
 }
 
 impl MetricsData {
@@ -230,13 +327,56 @@ impl Default for MetricsData {
             page_fetch_count: 0,
             data_page_spaces: 0,
             page_hit_count: 0,
+            journal_lag: 0,
+        }
+    }
+}
+
+/// The operations on a collection counted since the metrics are enabled.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionMetrics {
+    /// The finds and the counts.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub reads: u64,
+    /// The inserts, the updates and the deletes.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub writes: u64,
+    /// The bytes of the documents read from the storage, before decompression.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub bytes_read: u64,
+    /// The bytes of the documents written to the storage, after compression.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub bytes_written: u64,
+    /// The time spent by the operations, measured by the clock of the config.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub total_latency_micros: u64,
+}
+
+impl CollectionMetrics {
+
+    pub fn operation_count(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn average_latency(&self) -> Duration {
+        let count = self.operation_count();
+        if count == 0 {
+            return Duration::ZERO;
         }
+        Duration::from_micros(self.total_latency_micros / count)
     }
+
 }
 
 struct MetricsDataWrapper {
     data: MetricsData,
     session: HashMap<ObjectId, MetricsData>,
+    collections: HashMap<String, CollectionMetrics>,
+    // the bytes counted in the operation running,
+    // which are added to its collection when it's finished
+    pending_bytes_read: u64,
+    pending_bytes_written: u64,
 }
 
 impl MetricsDataWrapper {
@@ -245,6 +385,9 @@ impl MetricsDataWrapper {
         MetricsDataWrapper {
             data: MetricsData::default(),
             session: HashMap::new(),
+            collections: HashMap::new(),
+            pending_bytes_read: 0,
+            pending_bytes_written: 0,
         }
     }
 
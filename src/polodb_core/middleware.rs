@@ -0,0 +1,244 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The request routing layer in front of [`Database::handle_request_doc`].
+//!
+//! Every command decoded from a request passes through the middlewares
+//! registered by [`Database::use_middleware`] in the order they were added,
+//! and then reaches the executor.
+//! A middleware can inspect or rewrite the command, reject it with an error,
+//! or post-process the result returned by the rest of the chain.
+//!
+//! ```rust
+//! use polodb_core::{Database, DbErr, DbResult};
+//! use polodb_core::commands::CommandMessage;
+//! use polodb_core::middleware::{Middleware, Next};
+//! use polodb_core::HandleRequestResult;
+//!
+//! struct ReadOnly;
+//!
+//! impl Middleware for ReadOnly {
+//!     fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+//!         if command.is_write() {
+//!             return Err(DbErr::PermissionDenied(command.name().into()));
+//!         }
+//!         next.run(command)
+//!     }
+//! }
+//!
+//! let db = Database::open_memory().unwrap();
+//! db.use_middleware(ReadOnly);
+//! ```
+//!
+//! [`Database::handle_request_doc`]: crate::Database::handle_request_doc
+//! [`Database::use_middleware`]: crate::Database::use_middleware
+use std::collections::HashMap;
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+use crate::commands::CommandMessage;
+use crate::db::HandleRequestResult;
+use crate::{DbErr, DbResult};
+
+pub trait Middleware: Send + Sync {
+
+    /// Handle the command, call `next.run(command)` to pass it
+    /// to the rest of the chain.
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult>;
+
+}
+
+pub(crate) type Executor<'a> = dyn FnMut(CommandMessage) -> DbResult<HandleRequestResult> + 'a;
+
+/// The rest of the middleware chain, ended with the executor.
+pub struct Next<'a> {
+    chain: &'a [Arc<dyn Middleware>],
+    executor: &'a mut Executor<'a>,
+}
+
+impl<'a> Next<'a> {
+
+    pub(crate) fn new(chain: &'a [Arc<dyn Middleware>], executor: &'a mut Executor<'a>) -> Next<'a> {
+        Next {
+            chain,
+            executor,
+        }
+    }
+
+    pub fn run(self, command: CommandMessage) -> DbResult<HandleRequestResult> {
+        match self.chain.split_first() {
+            Some((head, rest)) => {
+                let next = Next {
+                    chain: rest,
+                    executor: self.executor,
+                };
+                head.handle(command, next)
+            }
+            None => (self.executor)(command),
+        }
+    }
+
+}
+
+/// Print every command and its outcome with the logger of the database.
+pub struct LoggingMiddleware;
+
+impl Middleware for LoggingMiddleware {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        let name = command.name();
+        let ns = command.ns().map(|ns| ns.to_string());
+        let result = next.run(command);
+        match &result {
+            Ok(_) => crate::polo_log!("command: {}, ns: {:?}, ok", name, ns),
+            Err(err) => crate::polo_log!("command: {}, ns: {:?}, error: {}", name, ns, err),
+        }
+        result
+    }
+
+}
+
+/// The commands a role is allowed to run on a collection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Role {
+    /// Only the commands which don't write, see [`CommandMessage::is_write`].
+    Read,
+    ReadWrite,
+}
+
+impl Role {
+
+    fn allows(&self, command: &CommandMessage) -> bool {
+        match self {
+            Role::Read => !command.is_write(),
+            Role::ReadWrite => true,
+        }
+    }
+
+}
+
+/// Check the commands against the roles granted on the collections,
+/// the others are rejected with [`DbErr::PermissionDenied`].
+///
+/// Nothing is granted by default. The commands without a collection,
+/// such as the sessions and the transactions, are checked against the
+/// role granted by [`AuthMiddleware::grant_all`], but the ones which
+/// don't write are always allowed.
+#[derive(Default)]
+pub struct AuthMiddleware {
+    default_role: Option<Role>,
+    roles: HashMap<String, Role>,
+}
+
+impl AuthMiddleware {
+
+    pub fn new() -> AuthMiddleware {
+        AuthMiddleware::default()
+    }
+
+    /// Grant the role on the collection, it overrides the role of [`AuthMiddleware::grant_all`].
+    pub fn grant(mut self, ns: impl Into<String>, role: Role) -> AuthMiddleware {
+        self.roles.insert(ns.into(), role);
+        self
+    }
+
+    /// Grant the role on all the collections.
+    pub fn grant_all(mut self, role: Role) -> AuthMiddleware {
+        self.default_role = Some(role);
+        self
+    }
+
+    fn role_of(&self, ns: &str) -> Option<Role> {
+        self.roles.get(ns).copied().or(self.default_role)
+    }
+
+}
+
+impl Middleware for AuthMiddleware {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        let allowed = match command.ns() {
+            Some(ns) => self.role_of(ns).map(|role| role.allows(&command)).unwrap_or(false),
+            None => !command.is_write() || self.default_role.map(|role| role.allows(&command)).unwrap_or(false),
+        };
+        if !allowed {
+            return Err(DbErr::PermissionDenied(command.name().into()));
+        }
+        next.run(command)
+    }
+
+}
+
+/// Reject the commands with a malformed namespace before they
+/// reach the executor.
+pub struct ValidationMiddleware;
+
+impl Middleware for ValidationMiddleware {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        if let Some(ns) = command.ns() {
+            if ns.is_empty() || ns.starts_with('$') || ns.contains('\0') {
+                return Err(DbErr::IllegalCollectionName(ns.to_string()));
+            }
+        }
+        next.run(command)
+    }
+
+}
+
+/// Allow at most `max_requests` commands in every `window`,
+/// the others are rejected with [`DbErr::Busy`].
+#[cfg(not(target_arch = "wasm32"))]
+pub struct RateLimitMiddleware {
+    max_requests: u32,
+    window: Duration,
+    state: Mutex<RateLimitState>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+struct RateLimitState {
+    window_start: Instant,
+    count: u32,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl RateLimitMiddleware {
+
+    pub fn new(max_requests: u32, window: Duration) -> RateLimitMiddleware {
+        RateLimitMiddleware {
+            max_requests,
+            window,
+            state: Mutex::new(RateLimitState {
+                window_start: Instant::now(),
+                count: 0,
+            }),
+        }
+    }
+
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Middleware for RateLimitMiddleware {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        {
+            let mut state = self.state.lock()?;
+            let now = Instant::now();
+            if now.duration_since(state.window_start) >= self.window {
+                state.window_start = now;
+                state.count = 0;
+            }
+            if state.count >= self.max_requests {
+                return Err(DbErr::Busy);
+            }
+            state.count += 1;
+        }
+        next.run(command)
+    }
+
+}
@@ -0,0 +1,180 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Versioned migrations of the data, applied by [`Database::migrate`].
+//!
+//! The migrations are registered by [`Database::register_migration`] with increasing versions.
+//! Every pending migration runs in its own write transaction, which also records its version
+//! in the database, so a migration is applied once, even if it fails in the middle.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{Document, doc};
+//! use polodb_core::migrations::Migration;
+//!
+//! let db = Database::open_memory().unwrap();
+//! db.collection::<Document>("users").insert_one(doc! { "_id": 1, "name": "Alice" }).unwrap();
+//!
+//! db.register_migration(Migration::new(1, "add the roles", |db, session| {
+//!     db.collection::<Document>("users")
+//!         .update_many_with_session(doc! {}, doc! { "$set": { "role": "user" } }, session)?;
+//!     Ok(())
+//! }));
+//! db.register_migration(Migration::pipeline(2, "rename the names", "users", vec![
+//!     doc! { "$project": { "fullName": "$name", "role": 1 } },
+//! ]));
+//!
+//! let result = db.migrate().unwrap();
+//! assert_eq!(result.applied, vec![1, 2]);
+//! assert_eq!(db.migrate().unwrap().applied, Vec::<u64>::new());
+//! ```
+use std::fmt;
+use std::sync::Arc;
+use bson::{Bson, Document, DateTime};
+use crate::{ClientSession, Database, DbErr, DbResult};
+use crate::results::MigrateResult;
+
+/// The namespace of the key-value API recording the applied versions.
+pub(crate) const MIGRATIONS_NAMESPACE: &str = "$migrations";
+
+/// A migration written in Rust, the operations should be done with the session
+/// to be in the transaction of the migration.
+pub type MigrationFn = Arc<dyn Fn(&Database, &mut ClientSession) -> DbResult<()> + Send + Sync>;
+
+#[derive(Clone)]
+enum MigrationKind {
+    Function(MigrationFn),
+    Pipeline {
+        collection: String,
+        pipeline:   Vec<Document>,
+    },
+}
+
+/// A migration of the data registered by [`Database::register_migration`].
+#[derive(Clone)]
+pub struct Migration {
+    version: u64,
+    name:    String,
+    kind:    MigrationKind,
+}
+
+impl Migration {
+
+    /// A migration calling `f` with the session of its transaction.
+    pub fn new<F>(version: u64, name: &str, f: F) -> Migration
+    where
+        F: Fn(&Database, &mut ClientSession) -> DbResult<()> + Send + Sync + 'static
+    {
+        Migration {
+            version,
+            name: name.to_string(),
+            kind: MigrationKind::Function(Arc::new(f)),
+        }
+    }
+
+    /// A migration replacing the documents of the collection by the results of the
+    /// aggregation pipeline. The results without an `_id` are inserted with new ones.
+    pub fn pipeline(version: u64, name: &str, collection: &str, pipeline: Vec<Document>) -> Migration {
+        Migration {
+            version,
+            name: name.to_string(),
+            kind: MigrationKind::Pipeline {
+                collection: collection.to_string(),
+                pipeline,
+            },
+        }
+    }
+
+    #[inline]
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, db: &Database, session: &mut ClientSession) -> DbResult<()> {
+        match &self.kind {
+            MigrationKind::Function(f) => f(db, session),
+            MigrationKind::Pipeline { collection, pipeline } => {
+                let collection = db.collection::<Document>(collection);
+                let docs = collection.aggregate_with_session(pipeline.clone(), session)?;
+                collection.truncate_with_session(session)?;
+                if !docs.is_empty() {
+                    collection.insert_many_with_session(docs, session)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+}
+
+impl fmt::Debug for Migration {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migration")
+            .field("version", &self.version)
+            .field("name", &self.name)
+            .finish()
+    }
+
+}
+
+// the keys are padded, so they are scanned in the order of the versions
+fn version_key(version: u64) -> String {
+    format!("{:020}", version)
+}
+
+/// The versions applied to the database, in the ascending order.
+pub(crate) fn applied_versions(db: &Database) -> DbResult<Vec<u64>> {
+    let records = db.kv(MIGRATIONS_NAMESPACE).scan_prefix("")?;
+    let versions = records
+        .iter()
+        .filter_map(|(key, _)| key.parse::<u64>().ok())
+        .collect();
+    Ok(versions)
+}
+
+/// Apply the migrations not recorded in the database, the migrations are in the order of the versions.
+pub(crate) fn migrate(db: &Database, migrations: &[Arc<Migration>]) -> DbResult<MigrateResult> {
+    let applied = applied_versions(db)?;
+    let latest = applied.last().copied();
+    let mut result = MigrateResult {
+        applied: Vec::new(),
+        version: latest,
+    };
+
+    for migration in migrations {
+        if applied.binary_search(&migration.version).is_ok() {
+            continue;
+        }
+        // a migration registered after the newer ones are applied may depend on the data before them
+        if let Some(latest) = latest {
+            if migration.version < latest {
+                return Err(DbErr::MigrationOutOfOrder(migration.version, latest));
+            }
+        }
+
+        let mut session = db.start_session()?;
+        session.with_transaction(|session| {
+            migration.apply(db, session)?;
+
+            let mut record = Document::new();
+            record.insert("name", migration.name.clone());
+            record.insert("appliedAt", DateTime::now());
+            db.kv(MIGRATIONS_NAMESPACE).set_with_session(&version_key(migration.version), Bson::Document(record), session)
+        })?;
+
+        crate::polo_log!("migration {} \"{}\" is applied", migration.version, migration.name);
+        result.applied.push(migration.version);
+        result.version = Some(migration.version);
+    }
+
+    Ok(result)
+}
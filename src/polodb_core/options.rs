@@ -0,0 +1,184 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use bson::Document;
+use crate::Compression;
+use crate::collection_info::{ForeignKey, GeneratedField};
+
+/// Options used to create a collection.
+#[derive(Debug, Clone, Default)]
+pub struct CreateCollectionOptions {
+    /// The codec compressing the documents of the collection.
+    pub compression: Compression,
+
+    /// The field increased on every update of a document,
+    /// which is checked by [`Collection::update_one_if`].
+    ///
+    /// [`Collection::update_one_if`]: crate::Collection::update_one_if
+    pub version_field: Option<String>,
+
+    /// Store the documents in the order of the insertion for the event logs,
+    /// the documents can't be updated or deleted but the collection can be truncated.
+    ///
+    /// The `_id` of a new document must be greater than the ones inserted,
+    /// or [`DbErr::AppendOutOfOrder`] is returned. The generated ones always are.
+    /// The pages are filled up instead of split in the middle,
+    /// so the inserts are faster and the scans by `_id` read the documents by the time of the insertion.
+    ///
+    /// [`DbErr::AppendOutOfOrder`]: crate::DbErr::AppendOutOfOrder
+    pub append_only: bool,
+
+    /// The fields referencing the `_id` of the documents of the other collections,
+    /// such as the `customer_id` of the orders referencing the customers.
+    ///
+    /// A document inserted or updated with a field referencing nothing fails with
+    /// [`DbErr::ForeignKeyViolation`], and the deletion of a referenced document
+    /// is handled by [`ForeignKey::on_delete`]. Dropping or truncating the referenced
+    /// collection is not checked.
+    ///
+    /// [`DbErr::ForeignKeyViolation`]: crate::DbErr::ForeignKeyViolation
+    pub foreign_keys: Vec<ForeignKey>,
+
+    /// The fields stored with the values of the expressions of the aggregation on the documents,
+    /// such as `{ "$toLower": "$email" }`, computed on every insert and update in the order of them.
+    /// The values set by the writes are replaced, and the fields can be indexed like the others.
+    ///
+    /// The functions registered by [`Database::register_function`] can't be called by the expressions.
+    ///
+    /// [`Database::register_function`]: crate::Database::register_function
+    pub generated_fields: Vec<GeneratedField>,
+}
+
+/// Options used to create an index, see [`Collection::create_index`].
+///
+/// [`Collection::create_index`]: crate::Collection::create_index
+#[derive(Debug, Clone, Default)]
+pub struct IndexOptions {
+    /// The name of the index, such as `"age_1_name_-1"` for the index
+    /// of `{ "age": 1, "name": -1 }` by default.
+    pub name: Option<String>,
+
+    /// Reject the documents with the same values of the fields with [`DbErr::DuplicateKey`].
+    /// A document without the fields has the same values as the others without them.
+    ///
+    /// [`DbErr::DuplicateKey`]: crate::DbErr::DuplicateKey
+    pub unique: Option<bool>,
+}
+
+/// Options used to insert documents.
+#[derive(Debug, Clone, Default)]
+pub struct InsertManyOptions {
+    /// Fail at the first document which can't be inserted, nothing is inserted then.
+    /// It's the default.
+    ///
+    /// If it's `false`, the documents with an existing `_id`, or failed to be serialized,
+    /// are skipped and reported by [`InsertManyResult::write_errors`],
+    /// and the other documents are inserted.
+    ///
+    /// [`InsertManyResult::write_errors`]: crate::results::InsertManyResult::write_errors
+    pub ordered: Option<bool>,
+}
+
+/// Options used to update documents.
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Insert a document if no document matches the query, and return its `_id`
+    /// by [`UpdateResult::upserted_id`].
+    ///
+    /// The document inserted is built from the equality conditions of the top-level
+    /// fields of the query, such as `{ "name": "Alice" }` or `{ "name": { "$eq": "Alice" } }`,
+    /// and then updated by the update.
+    ///
+    /// [`UpdateResult::upserted_id`]: crate::results::UpdateResult::upserted_id
+    pub upsert: Option<bool>,
+}
+
+/// Options used to delete documents.
+#[derive(Debug, Clone, Default)]
+pub struct DeleteOptions {
+    /// Delete the documents in the transactions of at most `batch_size` documents,
+    /// which are committed one by one, to bound the size of the journal.
+    ///
+    /// The deletion is not atomic with it, a failure leaves the committed batches deleted.
+    /// In a transaction started by the user, the batches are committed with the transaction.
+    pub batch_size: Option<usize>,
+}
+
+/// Options used to find documents.
+#[derive(Debug, Clone, Default)]
+pub struct FindOptions {
+    /// The fields of the documents to return, such as `{ "name": 1 }`.
+    ///
+    /// Only the fields needed by the filter and the projection are decoded
+    /// from the storage when it's an inclusion projection.
+    ///
+    /// A top-level array or binary can be sliced by `{ "messages": { "$slice": n } }`,
+    /// the first `n` items, or the last ones if `n` is negative,
+    /// or `{ "$slice": [skip, limit] }`. Only the items in the slice are decoded
+    /// if the field is not read by the filter.
+    pub projection: Option<Document>,
+
+    /// The order of the documents, such as `{ "age": -1, "name": 1 }`.
+    ///
+    /// The documents are scanned in the order of `_id`, so `{ "_id": 1 }` is free,
+    /// the others sort all the documents satisfying the filter
+    /// before they are skipped and limited.
+    pub sort: Option<Document>,
+
+    /// Skip the first documents satisfying the filter.
+    pub skip: Option<u64>,
+
+    /// Return at most the number of documents, `0` is the same as no limit.
+    ///
+    /// The scan ends as soon as the documents are found if they're not sorted.
+    pub limit: Option<u64>,
+}
+
+/// The default options of the operations through a handle of a collection,
+/// see [`Collection::with_options`].
+///
+/// The fields not given by the options of an operation are taken from them.
+///
+/// [`Collection::with_options`]: crate::Collection::with_options
+#[derive(Debug, Clone, Default)]
+pub struct CollectionOptions {
+    /// The default options of the finds, such as a projection
+    /// leaving out the large fields of the documents.
+    pub find: FindOptions,
+
+    /// The default options of the deletions.
+    pub delete: DeleteOptions,
+}
+
+/// The options of a handle of a database, see [`Database::with_options`].
+///
+/// [`Database::with_options`]: crate::Database::with_options
+#[derive(Debug, Clone, Default)]
+pub struct HandleOptions {
+    /// Reject the writes through the handle with [`DbErr::PermissionDenied`].
+    ///
+    /// [`DbErr::PermissionDenied`]: crate::DbErr::PermissionDenied
+    pub read_only: bool,
+
+    /// The [`DeleteOptions::batch_size`] of the deletions through the handle,
+    /// if it's not given by the options of the deletion.
+    pub delete_batch_size: Option<usize>,
+
+    /// The priority of the operations through the handle
+    /// when they wait for the lock of the database.
+    pub priority: Priority,
+}
+
+/// The priority of the operations of a handle, see [`HandleOptions::priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Priority {
+    /// The operations of the users, such as the queries of a UI.
+    #[default]
+    Interactive,
+    /// The operations nobody waits for, such as the TTL expiry or a bulk import.
+    /// They let the interactive operations waiting for the lock go first,
+    /// a bulk import should be split into batches so they can.
+    Background,
+}
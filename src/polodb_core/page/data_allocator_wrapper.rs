@@ -95,10 +95,23 @@ impl DataAllocatorWrapper {
         raw_page
     }
 
+    #[cfg(feature = "debug")]
+    #[inline]
+    pub(crate) fn free_pages(&self) -> &[(u32, u32)] {
+        &self.free_pages
+    }
+
     pub fn push(&mut self, pid: u32, remain_size: u32) {
         self.free_pages.push((pid, remain_size));
     }
 
+    /// Remove the page from the candidates, returns `false` if it's not listed.
+    pub fn remove(&mut self, pid: u32) -> bool {
+        let len = self.free_pages.len();
+        self.free_pages.retain(|(item_pid, _)| *item_pid != pid);
+        self.free_pages.len() != len
+    }
+
     fn sort(&mut self) {
         self.free_pages.sort_by(|a, b| {
             let (a_pid, a_remain_size) = a;
@@ -84,6 +84,10 @@ impl FreeListDataWrapper {
         pid
     }
 
+    pub(crate) fn page_ids(&self) -> Vec<u32> {
+        (0..self.size()).map(|index| self.get_pid_by_index(index)).collect()
+    }
+
     fn get_pid_by_index(&self, index: u32) -> u32 {
         let data_offset: u32 = DATA_FRAGMENT_OFFSET + index * 4;
         self.page.get_u32(data_offset)
@@ -3,8 +3,10 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
+use std::convert::TryInto;
 use std::num::NonZeroU32;
 use super::RawPage;
+use crate::{DbErr, DbResult};
 
 static HEADER_DESP: &str          = "PoloDB Format v3.1";
 const SECTOR_SIZE_OFFSET: u32     = 40;
@@ -13,11 +15,39 @@ const NULL_PAGE_BAR_OFFSET: u32   = 48;
 const META_PAGE_ID: u32           = 52;
 const DATA_ALLOCATOR_OFFSET: u32  = 56;
 // const META_ID_COUNTER_OFFSET: u32 = 60;
+const COMPAT_FEATURES_OFFSET: u32   = 64;
+const INCOMPAT_FEATURES_OFFSET: u32 = 72;
+const METADATA_SIZE_OFFSET: u32     = 80;
+const METADATA_OFFSET: u32          = 84;
+const METADATA_MAX_SIZE: u32        = 2048 - METADATA_OFFSET;
 pub const FREE_LIST_OFFSET: u32   = 2048;
 const FREE_LIST_PAGE_LINK_OFFSET: u32 = 2048 + 4;
 pub const HEADER_FREE_LIST_MAX_SIZE: usize = (2048 - 8) / 4;
 pub const DATABASE_VERSION: [u8; 4] = [0, 0, 3, 1];
 
+/// Some collections have foreign keys, the deletes only look for the references if it's set.
+pub(crate) const FEATURE_COMPAT_FOREIGN_KEYS: u64 = 1 << 0;
+/// The collections may be compressed, the readers without the support can't read them.
+pub(crate) const FEATURE_INCOMPAT_COMPRESSION: u64 = 1 << 0;
+/// Reserved for the encryption of the pages.
+#[allow(dead_code)]
+pub(crate) const FEATURE_INCOMPAT_ENCRYPTION: u64  = 1 << 1;
+/// The collections may have indexes, the writers without the support don't update them.
+pub(crate) const FEATURE_INCOMPAT_INDEXES: u64     = 1 << 3;
+/// The pages of the database file may be compressed, the readers without the support can't read them.
+pub(crate) const FEATURE_INCOMPAT_PAGE_COMPRESSION: u64 = 1 << 4;
+/// The incompatible features this version can read.
+const KNOWN_INCOMPAT_FEATURES: u64 = FEATURE_INCOMPAT_COMPRESSION
+    | FEATURE_INCOMPAT_INDEXES
+    | FEATURE_INCOMPAT_PAGE_COMPRESSION;
+
+/// The milliseconds since the epoch when the database is created, in i64.
+pub(crate) const METADATA_CREATED_AT: &str      = "createdAt";
+/// The journal frames checkpointed into the file since it's created, in u64.
+pub(crate) const METADATA_CHECKPOINT_LSN: &str  = "checkpointLsn";
+/// The milliseconds since the epoch of the last checkpoint, in i64.
+pub(crate) const METADATA_CHECKPOINT_AT: &str   = "checkpointAt";
+
 /**
  * Offset 0 (32 bytes) : "PoloDB Format v3.0";
  * Offset 32 (8 bytes) : Version 0.0.3.0;
@@ -27,6 +57,14 @@ pub const DATABASE_VERSION: [u8; 4] = [0, 0, 3, 1];
  * Offset 52 (4 bytes) : MetaPageId(usually 1);
  * Offset 56 (4 bytes) : DataAllocatorPageId(0 for none);
  * Offset 60 (4 bytes) : MetaIdCounter;
+ * Offset 64 (8 bytes) : CompatFeatures, the unknown bits are ignored;
+ * Offset 72 (8 bytes) : IncompatFeatures, a database with unknown bits can't be opened;
+ * Offset 80 (2 bytes) : MetadataSize;
+ * Offset 84           : Metadata;
+ *
+ * The metadata area holds the key-value entries, the unknown keys are kept:
+ * | 1b      | key | 2b        | value |
+ * | key len | key | value len | value |
  *
  * Free list offset: 2048;
  * | 4b   | 4b                  | 4b     | 4b    | ... |
@@ -45,6 +83,7 @@ impl HeaderPageWrapper {
         wrapper.set_page_size(page_size.get());
         wrapper.set_meta_page_id(1);
         wrapper.set_null_page_bar(2);
+        wrapper.set_created_at(bson::DateTime::now().timestamp_millis());
         wrapper
     }
 
@@ -99,7 +138,6 @@ impl HeaderPageWrapper {
     }
 
     #[inline]
-    #[allow(dead_code)]
     pub(crate) fn get_page_size(&mut self) -> u32 {
         self.0.get_u32(PAGE_SIZE_OFFSET)
     }
@@ -175,6 +213,189 @@ impl HeaderPageWrapper {
         self.0.get_u32(FREE_LIST_PAGE_LINK_OFFSET)
     }
 
+    #[inline]
+    pub(crate) fn get_compat_features(&self) -> u64 {
+        self.0.get_u64(COMPAT_FEATURES_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_compat_features(&mut self, features: u64) {
+        self.0.seek(COMPAT_FEATURES_OFFSET);
+        self.0.put_u64(features);
+    }
+
+    #[inline]
+    pub(crate) fn get_incompat_features(&self) -> u64 {
+        self.0.get_u64(INCOMPAT_FEATURES_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_incompat_features(&mut self, features: u64) {
+        self.0.seek(INCOMPAT_FEATURES_OFFSET);
+        self.0.put_u64(features);
+    }
+
+    /// Fail if the database uses the incompatible features unknown to this version.
+    pub(crate) fn check_features(&self) -> DbResult<()> {
+        let unknown = self.get_incompat_features() & !KNOWN_INCOMPAT_FEATURES;
+        if unknown != 0 {
+            return Err(DbErr::UnsupportedFeatures(unknown));
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn metadata_size(&self) -> u32 {
+        // the size is broken if it's out of the area
+        (self.0.get_u16(METADATA_SIZE_OFFSET) as u32).min(METADATA_MAX_SIZE)
+    }
+
+    /// The offsets of the entries and the values, and the lengths of the values.
+    /// A broken entry and the rest of the area are ignored.
+    fn metadata_entry_offsets(&self) -> Vec<(u32, u32, u32)> {
+        let end = METADATA_OFFSET + self.metadata_size();
+        let mut result = vec![];
+        let mut offset = METADATA_OFFSET;
+        while offset + 3 <= end {
+            let key_len = self.0.get_u8(offset) as u32;
+            let value_len_offset = offset + 1 + key_len;
+            if value_len_offset + 2 > end {
+                break;
+            }
+            let value_len = self.0.get_u16(value_len_offset) as u32;
+            let value_offset = value_len_offset + 2;
+            if value_offset + value_len > end {
+                break;
+            }
+            result.push((offset, value_offset, value_len));
+            offset = value_offset + value_len;
+        }
+        result
+    }
+
+    #[inline]
+    fn metadata_key(&self, offset: u32, value_offset: u32) -> &[u8] {
+        &self.0.data[((offset + 1) as usize)..((value_offset - 2) as usize)]
+    }
+
+    #[inline]
+    fn metadata_value(&self, value_offset: u32, value_len: u32) -> &[u8] {
+        &self.0.data[(value_offset as usize)..((value_offset + value_len) as usize)]
+    }
+
+    fn find_metadata(&self, key: &str) -> Option<(u32, u32, u32)> {
+        self.metadata_entry_offsets()
+            .into_iter()
+            .find(|(offset, value_offset, _)| self.metadata_key(*offset, *value_offset) == key.as_bytes())
+    }
+
+    pub(crate) fn get_metadata(&self, key: &str) -> Option<&[u8]> {
+        self.find_metadata(key)
+            .map(|(_, value_offset, value_len)| self.metadata_value(value_offset, value_len))
+    }
+
+    /// Insert or replace the entry of the key.
+    pub(crate) fn set_metadata(&mut self, key: &str, value: &[u8]) -> DbResult<()> {
+        if key.is_empty() || key.len() > u8::MAX as usize || value.len() > u16::MAX as usize {
+            return Err(DbErr::HeaderMetadataFull);
+        }
+
+        let old_entry = self.find_metadata(key);
+        if let Some((_, value_offset, value_len)) = old_entry {
+            if value_len as usize == value.len() {
+                self.0.seek(value_offset);
+                self.0.put(value);
+                return Ok(());
+            }
+        }
+
+        let entry_size = (1 + key.len() + 2 + value.len()) as u32;
+        let old_entry_size = old_entry
+            .map(|(offset, value_offset, value_len)| value_offset + value_len - offset)
+            .unwrap_or(0);
+        if self.metadata_size() - old_entry_size + entry_size > METADATA_MAX_SIZE {
+            return Err(DbErr::HeaderMetadataFull);
+        }
+
+        self.remove_metadata(key);
+
+        let size = self.metadata_size();
+        self.0.seek(METADATA_OFFSET + size);
+        self.0.put_u8(key.len() as u8);
+        self.0.put_str(key);
+        self.0.put_u16(value.len() as u16);
+        self.0.put(value);
+
+        self.0.seek(METADATA_SIZE_OFFSET);
+        self.0.put_u16((size + entry_size) as u16);
+
+        Ok(())
+    }
+
+    /// Remove the entry of the key, return false if it's not found.
+    pub(crate) fn remove_metadata(&mut self, key: &str) -> bool {
+        let (offset, value_offset, value_len) = match self.find_metadata(key) {
+            Some(entry) => entry,
+            None => return false,
+        };
+        let entry_end = (value_offset + value_len) as usize;
+        let end = (METADATA_OFFSET + self.metadata_size()) as usize;
+        let entry_size = entry_end - offset as usize;
+
+        self.0.data.copy_within(entry_end..end, offset as usize);
+        for byte in &mut self.0.data[(end - entry_size)..end] {
+            *byte = 0;
+        }
+
+        self.0.seek(METADATA_SIZE_OFFSET);
+        self.0.put_u16((end - entry_size - METADATA_OFFSET as usize) as u16);
+
+        true
+    }
+
+    /// All the entries of the metadata area, including the unknown ones.
+    pub(crate) fn metadata_entries(&self) -> Vec<(String, Vec<u8>)> {
+        self.metadata_entry_offsets()
+            .into_iter()
+            .map(|(offset, value_offset, value_len)| {
+                let key = String::from_utf8_lossy(self.metadata_key(offset, value_offset)).to_string();
+                (key, self.metadata_value(value_offset, value_len).to_vec())
+            })
+            .collect()
+    }
+
+    pub(crate) fn get_created_at(&self) -> Option<i64> {
+        let value = self.get_metadata(METADATA_CREATED_AT)?;
+        let bytes: [u8; 8] = value.try_into().ok()?;
+        Some(i64::from_be_bytes(bytes))
+    }
+
+    pub(crate) fn set_created_at(&mut self, millis: i64) {
+        // the area is empty when the page is initialized
+        self.set_metadata(METADATA_CREATED_AT, &millis.to_be_bytes())
+            .expect("the metadata area is full");
+    }
+
+    pub(crate) fn get_checkpoint_lsn(&self) -> Option<u64> {
+        let value = self.get_metadata(METADATA_CHECKPOINT_LSN)?;
+        let bytes: [u8; 8] = value.try_into().ok()?;
+        Some(u64::from_be_bytes(bytes))
+    }
+
+    pub(crate) fn set_checkpoint_lsn(&mut self, lsn: u64) -> DbResult<()> {
+        self.set_metadata(METADATA_CHECKPOINT_LSN, &lsn.to_be_bytes())
+    }
+
+    pub(crate) fn get_checkpoint_at(&self) -> Option<i64> {
+        let value = self.get_metadata(METADATA_CHECKPOINT_AT)?;
+        let bytes: [u8; 8] = value.try_into().ok()?;
+        Some(i64::from_be_bytes(bytes))
+    }
+
+    pub(crate) fn set_checkpoint_at(&mut self, millis: i64) -> DbResult<()> {
+        self.set_metadata(METADATA_CHECKPOINT_AT, &millis.to_be_bytes())
+    }
+
 }
 
 #[cfg(test)]
@@ -182,6 +403,7 @@ mod tests {
     // use crate::page::HeaderPage;
 
     use std::num::NonZeroU32;
+    use crate::DbErr;
     use crate::page::RawPage;
     use crate::page::header_page_wrapper::*;
 
@@ -204,4 +426,38 @@ mod tests {
         assert_eq!(wrapper.get_page_size(), test_page_size);
     }
 
+    #[test]
+    fn features_and_metadata() {
+        let mut wrapper = HeaderPageWrapper::init(0, NonZeroU32::new(4096).unwrap());
+        assert!(wrapper.get_created_at().is_some());
+        assert_eq!(wrapper.get_checkpoint_lsn(), None);
+        assert_eq!(wrapper.get_compat_features(), 0);
+        assert!(wrapper.check_features().is_ok());
+
+        wrapper.set_incompat_features(FEATURE_INCOMPAT_COMPRESSION);
+        assert!(wrapper.check_features().is_ok());
+        wrapper.set_incompat_features(FEATURE_INCOMPAT_COMPRESSION | (1 << 40));
+        assert!(matches!(wrapper.check_features(), Err(DbErr::UnsupportedFeatures(flags)) if flags == 1 << 40));
+
+        wrapper.set_checkpoint_lsn(10).unwrap();
+        wrapper.set_metadata("unknown", b"kept").unwrap();
+        wrapper.set_checkpoint_lsn(25).unwrap();
+        assert_eq!(wrapper.get_checkpoint_lsn(), Some(25));
+
+        // the value of another length moves the entry to the end
+        wrapper.set_metadata("unknown", b"kept longer").unwrap();
+        let keys: Vec<String> = wrapper.metadata_entries().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, vec![METADATA_CREATED_AT, METADATA_CHECKPOINT_LSN, "unknown"]);
+        assert_eq!(wrapper.get_metadata("unknown"), Some(&b"kept longer"[..]));
+
+        assert!(wrapper.remove_metadata(METADATA_CHECKPOINT_LSN));
+        assert!(!wrapper.remove_metadata(METADATA_CHECKPOINT_LSN));
+        assert_eq!(wrapper.get_checkpoint_lsn(), None);
+        assert_eq!(wrapper.get_metadata("unknown"), Some(&b"kept longer"[..]));
+
+        let large_value = vec![1u8; (METADATA_MAX_SIZE - 16) as usize];
+        assert!(matches!(wrapper.set_metadata("large", &large_value), Err(DbErr::HeaderMetadataFull)));
+        assert_eq!(wrapper.metadata_entries().len(), 2);
+    }
+
 }
@@ -12,10 +12,8 @@ pub(crate) mod data_allocator_wrapper;
 
 pub(crate) use free_list_data_wrapper::FreeListDataWrapper;
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "fs")]
 use std::io::{Seek, SeekFrom, Write, Read};
-#[cfg(not(target_arch = "wasm32"))]
-use std::fs::File;
 use std::num::NonZeroU32;
 
 #[repr(u8)]
@@ -46,6 +44,9 @@ impl PageType {
 
 }
 
+/// The integers of the pages are stored in big-endian by the accessors,
+/// so a database file written on one platform can be opened on another.
+/// The BSON documents in the pages are little-endian as the BSON spec.
 #[derive(Debug, Clone)]
 pub(crate) struct RawPage {
     pub page_id:    u32,
@@ -155,15 +156,15 @@ impl RawPage {
         u64::from_be_bytes(buffer)
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn sync_to_file(&self, file: &mut File, offset: u64) -> std::io::Result<()> {
+    #[cfg(feature = "fs")]
+    pub fn sync_to_file<F: Write + Seek + ?Sized>(&self, file: &mut F, offset: u64) -> std::io::Result<()> {
         file.seek(SeekFrom::Start(offset))?;
         file.write_all(self.data.as_slice())?;
         Ok(())
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn read_from_file(&mut self, file: &mut File, offset: u64) -> std::io::Result<()> {
+    #[cfg(feature = "fs")]
+    pub fn read_from_file<F: Read + Seek + ?Sized>(&mut self, file: &mut F, offset: u64) -> std::io::Result<()> {
         file.seek(SeekFrom::Start(offset))?;
         file.read_exact(self.data.as_mut_slice())?;
         Ok(())
@@ -0,0 +1,175 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Keyset pagination, which continues after the last seen document
+//! by a filter instead of skipping the documents of the previous pages.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{Document, doc};
+//!
+//! let db = Database::open_memory().unwrap();
+//! let posts = db.collection::<Document>("posts");
+//! for i in 0..5 {
+//!     posts.insert_one(doc! { "_id": i, "score": i % 2 }).unwrap();
+//! }
+//!
+//! let sort = doc! { "score": -1 };
+//! let first = posts.paginate(None, sort.clone(), None, 3).unwrap();
+//! assert_eq!(first.items.len(), 3);
+//!
+//! let token = first.next_token.unwrap();
+//! let second = posts.paginate(None, sort, Some(&token), 3).unwrap();
+//! assert_eq!(second.items.len(), 2);
+//! assert!(second.next_token.is_none());
+//! ```
+use bson::{Bson, Document, doc};
+use crate::{DbErr, DbResult};
+use crate::aggregation::{resolve_path, SortSpec};
+
+/// A page of the documents, with the token to get the next page
+/// if there are more documents.
+#[derive(Debug, Clone)]
+pub struct Page<T> {
+    pub items:      Vec<T>,
+    pub next_token: Option<String>,
+}
+
+// The sort fields with `_id` as the last one to break the ties.
+fn sort_keys(sort: &Document) -> DbResult<Vec<(String, bool)>> {
+    let mut keys = Vec::with_capacity(sort.len() + 1);
+    for (key, value) in sort.iter() {
+        let ascending = match value {
+            Bson::Int32(1) | Bson::Int64(1) => true,
+            Bson::Int32(-1) | Bson::Int64(-1) => false,
+            _ => {
+                return Err(DbErr::ValidationError(
+                    format!("paginate: the order of '{}' should be 1 or -1", key)
+                ));
+            }
+        };
+        keys.push((key.clone(), ascending));
+    }
+    if !keys.iter().any(|(key, _)| key == "_id") {
+        keys.push(("_id".to_string(), true));
+    }
+    Ok(keys)
+}
+
+/// Generate the filter of the documents after the last seen key in the sort order.
+///
+/// The last seen key has the values of the sort fields and `_id`,
+/// the filter of the sort `{ "a": 1, "b": -1 }` is:
+///
+/// ```json
+/// { "$or": [
+///     { "a": { "$gt": a0 } },
+///     { "a": a0, "b": { "$lt": b0 } },
+///     { "a": a0, "b": b0, "_id": { "$gt": id0 } }
+/// ] }
+/// ```
+pub fn paginate_after(sort: &Document, last_seen_key: &Document) -> DbResult<Document> {
+    let keys = sort_keys(sort)?;
+    let mut branches = bson::Array::with_capacity(keys.len());
+    for (index, (key, ascending)) in keys.iter().enumerate() {
+        let mut branch = Document::new();
+        for (prefix_key, _) in &keys[..index] {
+            branch.insert(prefix_key.clone(), key_value(last_seen_key, prefix_key)?);
+        }
+        let op = if *ascending { "$gt" } else { "$lt" };
+        branch.insert(key.clone(), doc! { op: key_value(last_seen_key, key)? });
+        branches.push(Bson::Document(branch));
+    }
+    Ok(doc! { "$or": branches })
+}
+
+fn key_value(key_doc: &Document, key: &str) -> DbResult<Bson> {
+    key_doc.get(key).cloned().ok_or_else(|| {
+        DbErr::ValidationError(format!("paginate: the last seen key has no '{}'", key))
+    })
+}
+
+fn last_seen_key_of(keys: &[(String, bool)], doc: &Document) -> Document {
+    let mut result = Document::new();
+    for (key, _) in keys {
+        result.insert(key.clone(), resolve_path(doc, key).unwrap_or(Bson::Null));
+    }
+    result
+}
+
+/// Encode the last seen key as an opaque token.
+pub(crate) fn encode_token(last_seen_key: &Document) -> DbResult<String> {
+    let bytes = bson::to_vec(last_seen_key)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+pub(crate) fn decode_token(token: &str) -> DbResult<Document> {
+    let invalid = || DbErr::ValidationError("invalid continuation token".into());
+    if token.len() % 2 != 0 || !token.is_ascii() {
+        return Err(invalid());
+    }
+    let mut bytes = Vec::with_capacity(token.len() / 2);
+    for index in (0..token.len()).step_by(2) {
+        let byte = u8::from_str_radix(&token[index..index + 2], 16).map_err(|_| invalid())?;
+        bytes.push(byte);
+    }
+    bson::from_slice(&bytes).map_err(|_| invalid())
+}
+
+/// Get the page of the documents after the token, sorted in memory.
+pub(crate) fn paginate_documents(
+    docs: Vec<Document>,
+    sort: &Document,
+    limit: usize,
+) -> DbResult<Page<Document>> {
+    let keys = sort_keys(sort)?;
+    let mut sort_doc = Document::new();
+    for (key, ascending) in &keys {
+        sort_doc.insert(key.clone(), if *ascending { 1 } else { -1 });
+    }
+    let sort_spec = SortSpec::parse("paginate", &sort_doc)?;
+
+    let mut docs = docs;
+    sort_spec.sort(&mut docs);
+
+    let has_more = docs.len() > limit;
+    docs.truncate(limit);
+    let next_token = match docs.last() {
+        Some(last) if has_more => Some(encode_token(&last_seen_key_of(&keys, last))?),
+        _ => None,
+    };
+
+    Ok(Page {
+        items: docs,
+        next_token,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use super::{decode_token, encode_token, paginate_after};
+
+    #[test]
+    fn test_paginate_after() {
+        let filter = paginate_after(&doc! { "score": -1 }, &doc! { "score": 5, "_id": 3 }).unwrap();
+        assert_eq!(filter, doc! {
+            "$or": [
+                { "score": { "$lt": 5 } },
+                { "score": 5, "_id": { "$gt": 3 } },
+            ],
+        });
+    }
+
+    #[test]
+    fn test_token() {
+        let key = doc! { "score": 5, "_id": 3 };
+        let token = encode_token(&key).unwrap();
+        assert_eq!(decode_token(&token).unwrap(), key);
+        assert!(decode_token("zz").is_err());
+    }
+
+}
@@ -0,0 +1,488 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::cmp::min;
+use std::ops::Range;
+use bson::{Binary, Bson, Document};
+use smallvec::SmallVec;
+use crate::{DbErr, DbResult};
+
+/// A parsed projection document, such as `{ "name": 1, "age": 1 }`.
+///
+/// A top-level array or binary field can be sliced, such as
+/// `{ "messages": { "$slice": [20, 10] } }`, the other fields are not
+/// excluded by the slices. The slices of the fields not read by the query
+/// are done while the documents are decoded from the storage, so the
+/// elements out of the slices are never deserialized.
+pub(crate) struct Projection {
+    paths:       Vec<String>,
+    exclude:     bool,
+    include_id:  bool,
+    /// The top-level fields read by the query, `None` if they are unknown.
+    query_fields: Option<Vec<String>>,
+    /// The slices done while decoding.
+    scan_slices:  Vec<FieldSlice>,
+    /// The slices done by [`Projection::apply`].
+    slices:       Vec<FieldSlice>,
+}
+
+/// A `$slice` of a top-level field, the elements of an array or the bytes of a binary.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FieldSlice {
+    pub(crate) field: String,
+    /// Counted from the end if it's negative.
+    skip:  i64,
+    limit: Option<u64>,
+}
+
+impl FieldSlice {
+
+    // `{ "$slice": n }` or `{ "$slice": [skip, limit] }`
+    fn parse(field: &str, value: &Bson) -> DbResult<FieldSlice> {
+        let (skip, limit) = match value {
+            Bson::Array(arr) if arr.len() == 2 => {
+                let skip = bson_to_i64(&arr[0]);
+                let limit = bson_to_i64(&arr[1]).filter(|limit| *limit > 0);
+                match (skip, limit) {
+                    (Some(skip), Some(limit)) => (skip, Some(limit as u64)),
+                    _ => {
+                        return Err(DbErr::ValidationError(format!(
+                            "$slice of '{}' should be [skip, limit] with a positive limit", field
+                        )));
+                    }
+                }
+            }
+            _ => match bson_to_i64(value) {
+                Some(n) if n >= 0 => (0, Some(n as u64)),
+                Some(n) => (n, None),
+                None => {
+                    return Err(DbErr::ValidationError(format!(
+                        "$slice of '{}' should be a number or an array", field
+                    )));
+                }
+            },
+        };
+        if field.contains('.') {
+            return Err(DbErr::ValidationError(format!(
+                "$slice of '{}' is only supported on the top-level fields", field
+            )));
+        }
+        Ok(FieldSlice {
+            field: field.to_string(),
+            skip,
+            limit,
+        })
+    }
+
+    /// The range of the slice in a sequence of `len` items.
+    pub(crate) fn range(&self, len: usize) -> Range<usize> {
+        let start = if self.skip >= 0 {
+            min(self.skip as u64, len as u64) as usize
+        } else {
+            len.saturating_sub(self.skip.unsigned_abs() as usize)
+        };
+        let end = match self.limit {
+            Some(limit) => min(start as u64 + limit, len as u64) as usize,
+            None => len,
+        };
+        start..end
+    }
+
+    /// Slice an array or a binary, the other values are kept.
+    pub(crate) fn apply(&self, value: Bson) -> Bson {
+        match value {
+            Bson::Array(mut arr) => {
+                let range = self.range(arr.len());
+                arr.truncate(range.end);
+                arr.drain(..range.start);
+                Bson::Array(arr)
+            }
+            Bson::Binary(Binary { subtype, bytes }) => {
+                let range = self.range(bytes.len());
+                Bson::Binary(Binary {
+                    subtype,
+                    bytes: bytes[range].to_vec(),
+                })
+            }
+            _ => value,
+        }
+    }
+
+}
+
+/// The parts of the stored documents decoded by a scan.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ScanFields {
+    /// The top-level fields, all of them if it's `None`.
+    pub(crate) fields: Option<Vec<String>>,
+    pub(crate) slices: Vec<FieldSlice>,
+}
+
+fn bson_to_i64(value: &Bson) -> Option<i64> {
+    match value {
+        Bson::Int32(i) => Some(*i as i64),
+        Bson::Int64(i) => Some(*i),
+        _ => None,
+    }
+}
+
+fn is_truthy(key: &str, value: &Bson) -> DbResult<bool> {
+    let result = match value {
+        Bson::Boolean(b) => *b,
+        Bson::Int32(i) => *i != 0,
+        Bson::Int64(i) => *i != 0,
+        Bson::Double(f) => *f != 0.0,
+        _ => {
+            return Err(DbErr::ValidationError(format!(
+                "projection value of '{}' should be a number or a boolean", key
+            )));
+        }
+    };
+    Ok(result)
+}
+
+// Collect the top-level fields the query reads,
+// return false if the query can't be analyzed.
+fn collect_query_fields(query: &Document, fields: &mut Vec<String>) -> bool {
+    for (key, value) in query.iter() {
+        match (key.as_str(), value) {
+            ("$and" | "$or", Bson::Array(arr)) => {
+                for item in arr {
+                    match item {
+                        Bson::Document(sub_query) => {
+                            if !collect_query_fields(sub_query, fields) {
+                                return false;
+                            }
+                        }
+                        _ => return false,
+                    }
+                }
+            }
+            ("$not", Bson::Document(sub_query)) => {
+                if !collect_query_fields(sub_query, fields) {
+                    return false;
+                }
+            }
+            _ if key.starts_with('$') => return false,
+            _ => {
+                let top_level = key.split('.').next().unwrap();
+                if !fields.iter().any(|f| f == top_level) {
+                    fields.push(top_level.to_string());
+                }
+            }
+        }
+    }
+    true
+}
+
+fn include_paths(doc: &Document, paths: &[&str]) -> Document {
+    let mut result = Document::new();
+
+    for (key, value) in doc.iter() {
+        if paths.contains(&key.as_str()) {
+            result.insert(key.clone(), value.clone());
+            continue;
+        }
+
+        let sub_paths: SmallVec<[&str; 8]> = paths
+            .iter()
+            .filter_map(|path| path.strip_prefix(key.as_str())?.strip_prefix('.'))
+            .collect();
+        if sub_paths.is_empty() {
+            continue;
+        }
+
+        if let Bson::Document(sub_doc) = value {
+            result.insert(key.clone(), include_paths(sub_doc, &sub_paths));
+        }
+    }
+
+    result
+}
+
+fn exclude_path(doc: &mut Document, path: &str) {
+    match path.split_once('.') {
+        Some((head, rest)) => {
+            if let Some(Bson::Document(sub_doc)) = doc.get_mut(head) {
+                exclude_path(sub_doc, rest);
+            }
+        }
+        None => {
+            doc.remove(path);
+        }
+    }
+}
+
+impl Projection {
+
+    /// Parse the projection of the results of the query.
+    pub(crate) fn parse(projection: &Document, query: Option<&Document>) -> DbResult<Projection> {
+        let mut paths = vec![];
+        let mut include_id = true;
+        let mut exclude: Option<bool> = None;
+        let mut slices = vec![];
+
+        for (key, value) in projection.iter() {
+            if let Some(slice) = value.as_document().and_then(|doc| doc.get("$slice")) {
+                slices.push(FieldSlice::parse(key, slice)?);
+                continue;
+            }
+            let truthy = is_truthy(key, value)?;
+            if key == "_id" {
+                include_id = truthy;
+                continue;
+            }
+
+            match exclude {
+                Some(exclude) if exclude == truthy => {
+                    return Err(DbErr::ValidationError(
+                        "cannot mix inclusion and exclusion in projection".into()
+                    ));
+                }
+                _ => exclude = Some(!truthy),
+            }
+            paths.push(key.clone());
+        }
+
+        let query_fields = match query {
+            Some(query) => {
+                let mut fields = vec![];
+                if collect_query_fields(query, &mut fields) {
+                    Some(fields)
+                } else {
+                    None
+                }
+            }
+            None => Some(vec![]),
+        };
+
+        // the fields read by the query are sliced after the query is matched
+        let (scan_slices, slices): (Vec<FieldSlice>, Vec<FieldSlice>) = slices.into_iter().partition(|slice| {
+            query_fields.as_ref()
+                .map(|fields| !fields.contains(&slice.field))
+                .unwrap_or(false)
+        });
+
+        Ok(Projection {
+            paths,
+            // `{ "_id": 0 }` is an exclusion, but `{ "_id": 1 }` is an inclusion
+            exclude: exclude.unwrap_or(!include_id || !projection.contains_key("_id")),
+            include_id,
+            query_fields,
+            scan_slices,
+            slices,
+        })
+    }
+
+    /// The parts of the documents needed to be decoded from the storage
+    /// to run the query and project the result.
+    ///
+    /// Return `None` if the whole document is needed.
+    pub(crate) fn scan_fields(&self) -> Option<ScanFields> {
+        let fields = match &self.query_fields {
+            Some(query_fields) if !self.exclude => {
+                let mut fields = vec!["_id".to_string()];
+                let paths = self.paths.iter()
+                    .chain(self.scan_slices.iter().chain(self.slices.iter()).map(|slice| &slice.field))
+                    .chain(query_fields.iter());
+                for path in paths {
+                    let top_level = path.split('.').next().unwrap();
+                    if !fields.iter().any(|f| f == top_level) {
+                        fields.push(top_level.to_string());
+                    }
+                }
+                Some(fields)
+            }
+            _ => None,
+        };
+
+        if fields.is_none() && self.scan_slices.is_empty() {
+            return None;
+        }
+        Some(ScanFields {
+            fields,
+            slices: self.scan_slices.clone(),
+        })
+    }
+
+    /// Decode the fields read besides the query, such as the fields to sort the documents,
+    /// they're sliced after the documents are read.
+    pub(crate) fn read_fields<'a>(&mut self, fields: impl Iterator<Item = &'a str>) {
+        for field in fields {
+            let top_level = field.split('.').next().unwrap();
+            if let Some(query_fields) = &mut self.query_fields {
+                if !query_fields.iter().any(|f| f == top_level) {
+                    query_fields.push(top_level.to_string());
+                }
+            }
+            let (read, scan): (Vec<FieldSlice>, Vec<FieldSlice>) = std::mem::take(&mut self.scan_slices)
+                .into_iter()
+                .partition(|slice| slice.field == top_level);
+            self.scan_slices = scan;
+            self.slices.extend(read);
+        }
+    }
+
+    /// Project the whole document read from the storage.
+    pub(crate) fn apply(&self, doc: Document) -> Document {
+        self.project(doc, &self.scan_slices)
+    }
+
+    /// Project the document decoded by the [`ScanFields`] of [`Projection::scan_fields`],
+    /// the fields of the slices done while decoding are not sliced again.
+    pub(crate) fn apply_scanned(&self, doc: Document) -> Document {
+        self.project(doc, &[])
+    }
+
+    fn project(&self, mut doc: Document, scan_slices: &[FieldSlice]) -> Document {
+        for slice in scan_slices.iter().chain(self.slices.iter()) {
+            if let Some(value) = doc.get_mut(&slice.field) {
+                *value = slice.apply(std::mem::replace(value, Bson::Null));
+            }
+        }
+
+        if self.exclude {
+            for path in &self.paths {
+                exclude_path(&mut doc, path);
+            }
+            if !self.include_id {
+                doc.remove("_id");
+            }
+            return doc;
+        }
+
+        let mut paths: SmallVec<[&str; 8]> = self.paths.iter().map(|p| p.as_str()).collect();
+        paths.extend(self.scan_slices.iter().chain(self.slices.iter()).map(|slice| slice.field.as_str()));
+        if self.include_id {
+            paths.push("_id");
+        }
+        include_paths(&doc, &paths)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{Binary, Bson, doc};
+    use bson::spec::BinarySubtype;
+    use super::Projection;
+
+    #[test]
+    fn test_inclusion() {
+        let query = doc! {
+            "$or": [{ "age": 1 }, { "name.first": "V" }],
+        };
+        let projection = Projection::parse(&doc! { "name": 1, "address.city": true }, Some(&query)).unwrap();
+        let doc = doc! {
+            "_id": 1,
+            "name": "Vincent",
+            "age": 30,
+            "address": { "city": "Guangzhou", "street": "Unknown" },
+        };
+        assert_eq!(projection.apply(doc), doc! {
+            "_id": 1,
+            "name": "Vincent",
+            "address": { "city": "Guangzhou" },
+        });
+
+        let scan = projection.scan_fields().unwrap();
+        assert_eq!(scan.fields.unwrap(), vec!["_id", "name", "address", "age"]);
+    }
+
+    #[test]
+    fn test_exclusion() {
+        let projection = Projection::parse(&doc! { "_id": 0, "address.street": 0 }, None).unwrap();
+        let doc = doc! {
+            "_id": 1,
+            "name": "Vincent",
+            "address": { "city": "Guangzhou", "street": "Unknown" },
+        };
+        assert_eq!(projection.apply(doc), doc! {
+            "name": "Vincent",
+            "address": { "city": "Guangzhou" },
+        });
+        assert!(projection.scan_fields().is_none());
+    }
+
+    #[test]
+    fn test_id_only() {
+        let doc = doc! { "_id": 1, "name": "Vincent" };
+        let projection = Projection::parse(&doc! { "_id": 1 }, None).unwrap();
+        assert_eq!(projection.apply(doc.clone()), doc! { "_id": 1 });
+        let projection = Projection::parse(&doc! { "_id": 0 }, None).unwrap();
+        assert_eq!(projection.apply(doc), doc! { "name": "Vincent" });
+    }
+
+    #[test]
+    fn test_mixed() {
+        assert!(Projection::parse(&doc! { "name": 1, "age": 0 }, None).is_err());
+    }
+
+    #[test]
+    fn test_slice() {
+        let doc = doc! {
+            "_id": 1,
+            "title": "chat",
+            "messages": [0, 1, 2, 3, 4, 5],
+            "avatar": Binary { subtype: BinarySubtype::Generic, bytes: vec![0, 1, 2, 3] },
+        };
+        let slice_of = |value: Bson| {
+            let projection = Projection::parse(&doc! { "messages": { "$slice": value } }, None).unwrap();
+            projection.apply(doc.clone()).get_array("messages").unwrap().clone()
+        };
+        assert_eq!(slice_of(Bson::Int32(2)), vec![Bson::Int32(0), Bson::Int32(1)]);
+        assert_eq!(slice_of(Bson::Int32(-2)), vec![Bson::Int32(4), Bson::Int32(5)]);
+        assert_eq!(slice_of(bson::bson!([4, 10])), vec![Bson::Int32(4), Bson::Int32(5)]);
+        assert_eq!(slice_of(bson::bson!([-3, 1])), vec![Bson::Int32(3)]);
+        assert!(slice_of(bson::bson!([10, 1])).is_empty());
+
+        let projection = Projection::parse(&doc! {
+            "title": 1,
+            "avatar": { "$slice": [1, 2] },
+        }, None).unwrap();
+        assert_eq!(projection.apply(doc.clone()), doc! {
+            "_id": 1,
+            "title": "chat",
+            "avatar": Binary { subtype: BinarySubtype::Generic, bytes: vec![1, 2] },
+        });
+
+        assert!(Projection::parse(&doc! { "messages": { "$slice": [1, 0] } }, None).is_err());
+        assert!(Projection::parse(&doc! { "chat.messages": { "$slice": 1 } }, None).is_err());
+    }
+
+    #[test]
+    fn test_slice_in_scan() {
+        let projection = Projection::parse(&doc! {
+            "messages": { "$slice": -10 },
+            "members": { "$slice": 2 },
+        }, Some(&doc! { "members": "Alice" })).unwrap();
+        let scan = projection.scan_fields().unwrap();
+        assert!(scan.fields.is_none());
+        assert_eq!(scan.slices.len(), 1);
+        assert_eq!(scan.slices[0].field, "messages");
+
+        // the slices are not known if the query can't be analyzed
+        let projection = Projection::parse(&doc! {
+            "messages": { "$slice": -10 },
+        }, Some(&doc! { "$where": "true" })).unwrap();
+        assert!(projection.scan_fields().is_none());
+    }
+
+    #[test]
+    fn test_read_fields() {
+        let mut projection = Projection::parse(&doc! {
+            "name": 1,
+            "messages": { "$slice": 2 },
+        }, None).unwrap();
+        projection.read_fields(["age", "messages"].iter().copied());
+        let scan = projection.scan_fields().unwrap();
+        assert_eq!(scan.fields.unwrap(), vec!["_id", "name", "messages", "age"]);
+        assert!(scan.slices.is_empty());
+
+        let doc = projection.apply(doc! { "_id": 1, "name": "Alice", "age": 20, "messages": [1, 2, 3] });
+        assert_eq!(doc, doc! { "_id": 1, "name": "Alice", "messages": [1, 2] });
+    }
+
+}
@@ -6,6 +6,7 @@
 
 use std::collections::{HashMap};
 use crate::bson::Bson;
+use crate::metrics::CollectionMetrics;
 use serde::{Serialize, Serializer};
 use serde::ser::SerializeMap;
 
@@ -19,9 +20,24 @@ pub struct InsertOneResult {
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct InsertManyResult {
-    /// The `_id` field of the documents inserted.
+    /// The `_id` field of the documents inserted, by the indexes of the documents.
     #[serde(serialize_with = "map_serialize")]
     pub inserted_ids: HashMap<usize, Bson>,
+    /// The documents failed to be inserted by an unordered insertion,
+    /// see [`InsertManyOptions::ordered`].
+    ///
+    /// [`InsertManyOptions::ordered`]: crate::InsertManyOptions::ordered
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub write_errors: Vec<WriteError>,
+}
+
+/// The error of a document failed to be written.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WriteError {
+    /// The index of the document in the documents to write.
+    pub index: usize,
+    pub message: String,
 }
 
 fn map_serialize<S>(data: &HashMap<usize, Bson>, serializer: S) -> Result<S::Ok, S::Error>
@@ -38,12 +54,38 @@ fn map_serialize<S>(data: &HashMap<usize, Bson>, serializer: S) -> Result<S::Ok,
     map.end()
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Default, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateResult {
-    /// The number of documents that were modified by the operation.
+    /// The number of documents matched by the query.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub matched_count: u64,
+    /// The number of documents that were modified by the operation,
+    /// the documents not changed by the update are not counted.
     #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
     pub modified_count: u64,
+    /// The `_id` field of the document inserted by an upsert,
+    /// see [`UpdateOptions::upsert`].
+    ///
+    /// [`UpdateOptions::upsert`]: crate::UpdateOptions::upsert
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upserted_id: Option<Bson>,
+}
+
+/// The result of [`Collection::update_one_if`].
+///
+/// [`Collection::update_one_if`]: crate::Collection::update_one_if
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateIfResult {
+    /// The version matches, and the document is updated.
+    Updated,
+    /// The document exists, but it has been updated by others.
+    VersionConflict {
+        /// The version of the document now, `Null` if it has no version.
+        current_version: Bson,
+    },
+    /// No document satisfies the filter without the version.
+    NotFound,
 }
 
 #[derive(Debug, Serialize)]
@@ -53,6 +95,73 @@ pub struct DeleteResult {
     #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
     pub deleted_count: u64,
 }
+
+/// The result of [`Collection::create_index`].
+///
+/// [`Collection::create_index`]: crate::Collection::create_index
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateIndexResult {
+    /// The name of the index, which is used to drop it.
+    pub index_name: String,
+}
+
+/// The result of [`Collection::find_many_by_ids`].
+///
+/// [`Collection::find_many_by_ids`]: crate::Collection::find_many_by_ids
+#[derive(Debug, Clone)]
+pub struct FindByIdsResult<T> {
+    /// The documents found, in the order of the `_id` fields.
+    pub found: Vec<T>,
+    /// The `_id` fields not found, in order and without duplicates.
+    pub missing: Vec<Bson>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CollectionStats {
+    /// The number of documents in the collection.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub count: u64,
+    /// The bytes of the stored documents, after the compression of the collection.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub data_size: u64,
+    /// The operations on the collection, if the metrics of the database are enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<CollectionMetrics>,
+}
+
+/// The size of the database before and after [`Database::compact`].
+///
+/// [`Database::compact`]: crate::Database::compact
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactResult {
+    /// The bytes of the database before the compaction.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub size_before: u64,
+    /// The bytes of the database after the compaction.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub size_after: u64,
+}
+
+/// The pages pinned in the page cache by [`Collection::pin`].
+///
+/// [`Collection::pin`]: crate::Collection::pin
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PinResult {
+    /// The pages of the collection and its indexes.
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub pages: u64,
+    /// The pages pinned of all the collections pinned in the database,
+    /// counted against the half of [`Config::page_cache_size`].
+    ///
+    /// [`Config::page_cache_size`]: crate::Config::page_cache_size
+    #[serde(serialize_with = "crate::bson::serde_helpers::serialize_u64_as_i64")]
+    pub total_pages: u64,
+}
+
 #[cfg(test)]
 mod tests {
     use bson::doc;
@@ -65,10 +174,23 @@ mod tests {
         let mut inserted_ids: HashMap<usize, Bson> = HashMap::new() ;
         inserted_ids.insert(0, doc! {}.into());
 
-        let result = InsertManyResult { inserted_ids };
+        let result = InsertManyResult {
+            inserted_ids,
+            write_errors: vec![],
+        };
         let _bson_doc = bson::to_document(&result).unwrap();
         let bson_str = format!("{:?}", _bson_doc);
         assert_eq!(r#"Document({"insertedIds": Document({"0": Document({})})})"#, bson_str);
     }
 
 }
+
+/// The result of [`Database::migrate`](crate::Database::migrate).
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MigrateResult {
+    /// The versions of the migrations applied by this call, in the order they are applied.
+    pub applied: Vec<u64>,
+    /// The latest version applied to the database, `None` if no migration is ever applied.
+    pub version: Option<u64>,
+}
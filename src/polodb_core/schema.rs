@@ -0,0 +1,286 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Discover the shapes of the documents of a collection by a sample of them.
+//!
+//! The fields of the sampled documents are reported with the ratio of the documents
+//! having them, the BSON types seen and the count of the distinct values,
+//! so a field drifted to another type or missing in the newer documents is found.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{Document, doc};
+//!
+//! let db = Database::open_memory().unwrap();
+//! let users = db.collection::<Document>("users");
+//! users.insert_one(doc! { "_id": 1, "age": 30, "name": "Alice" }).unwrap();
+//! users.insert_one(doc! { "_id": 2, "age": "31" }).unwrap();
+//!
+//! let analysis = users.analyze_schema(100).unwrap();
+//! assert_eq!(analysis.sampled, 2);
+//!
+//! let age = analysis.field("age").unwrap();
+//! assert_eq!(age.types, vec![("int".to_string(), 1), ("string".to_string(), 1)]);
+//! assert_eq!(analysis.field("name").unwrap().presence, 50.0);
+//! ```
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use bson::{Bson, Document};
+use bson::spec::ElementType;
+
+/// The documents sampled by the `AnalyzeSchema` command without a `sampleSize`.
+pub const DEFAULT_SAMPLE_SIZE: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct SchemaAnalysis {
+    /// The name of the collection.
+    pub namespace: String,
+
+    /// The number of the documents sampled.
+    pub sampled: u64,
+
+    /// The fields in the order they are first seen, the fields of the embedded
+    /// documents are in the dotted paths. The elements of the arrays are not examined.
+    pub fields: Vec<FieldSchema>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FieldSchema {
+    /// The dotted path of the field, such as `address.city`.
+    pub path: String,
+
+    /// The number of the sampled documents having the field.
+    pub count: u64,
+
+    /// The percentage of the sampled documents having the field, from 0 to 100.
+    pub presence: f64,
+
+    /// The names of the BSON types of the values with their counts, the most seen first.
+    /// The names are the aliases of `$type` of MongoDB, such as `int`, `string` and `objectId`.
+    pub types: Vec<(String, u64)>,
+
+    /// The estimated count of the distinct values in the sample,
+    /// the values are compared by the hashes.
+    pub distinct: u64,
+}
+
+impl SchemaAnalysis {
+
+    /// The field of the path, `None` if no sampled document has it.
+    pub fn field(&self, path: &str) -> Option<&FieldSchema> {
+        self.fields.iter().find(|field| field.path == path)
+    }
+
+    /// Convert to a document, such as
+    /// `{ namespace, sampled, fields: [{ path, count, presence, types: { int: 1 }, distinct }] }`.
+    pub fn to_document(&self) -> Document {
+        let mut doc = Document::new();
+        doc.insert("namespace", self.namespace.clone());
+        doc.insert("sampled", self.sampled as i64);
+        let fields: Vec<Bson> = self.fields.iter().map(|field| {
+            let mut types = Document::new();
+            for (name, count) in &field.types {
+                types.insert(name.clone(), *count as i64);
+            }
+            let mut field_doc = Document::new();
+            field_doc.insert("path", field.path.clone());
+            field_doc.insert("count", field.count as i64);
+            field_doc.insert("presence", field.presence);
+            field_doc.insert("types", types);
+            field_doc.insert("distinct", field.distinct as i64);
+            Bson::Document(field_doc)
+        }).collect();
+        doc.insert("fields", fields);
+        doc
+    }
+
+}
+
+#[derive(Default)]
+struct FieldStats {
+    count:  u64,
+    types:  Vec<(ElementType, u64)>,
+    hashes: HashSet<u64>,
+}
+
+impl FieldStats {
+
+    fn add(&mut self, value: &Bson) {
+        self.count += 1;
+        let ty = value.element_type();
+        match self.types.iter_mut().find(|(seen, _)| *seen == ty) {
+            Some((_, count)) => *count += 1,
+            None => self.types.push((ty, 1)),
+        }
+        self.hashes.insert(hash_value(value));
+    }
+
+}
+
+/// Accumulate the fields of the sampled documents.
+pub(crate) struct SchemaAnalyzer {
+    namespace: String,
+    sampled:   u64,
+    paths:     Vec<String>,
+    fields:    HashMap<String, FieldStats>,
+}
+
+impl SchemaAnalyzer {
+
+    pub(crate) fn new(namespace: &str) -> SchemaAnalyzer {
+        SchemaAnalyzer {
+            namespace: namespace.to_string(),
+            sampled: 0,
+            paths: Vec::new(),
+            fields: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn sampled(&self) -> u64 {
+        self.sampled
+    }
+
+    pub(crate) fn add(&mut self, doc: &Document) {
+        self.sampled += 1;
+        self.add_fields("", doc);
+    }
+
+    fn add_fields(&mut self, prefix: &str, doc: &Document) {
+        for (key, value) in doc {
+            let path = if prefix.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", prefix, key)
+            };
+            if !self.fields.contains_key(&path) {
+                self.paths.push(path.clone());
+            }
+            self.fields.entry(path.clone()).or_default().add(value);
+            if let Bson::Document(sub_doc) = value {
+                self.add_fields(&path, sub_doc);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self) -> SchemaAnalysis {
+        let SchemaAnalyzer { namespace, sampled, paths, mut fields } = self;
+        let fields = paths
+            .into_iter()
+            .map(|path| {
+                let stats = fields.remove(&path).unwrap();
+                let mut types: Vec<(String, u64)> = stats.types
+                    .into_iter()
+                    .map(|(ty, count)| (type_name(ty).to_string(), count))
+                    .collect();
+                // the sort is stable, the types of the same count are in the order seen
+                types.sort_by(|a, b| b.1.cmp(&a.1));
+                FieldSchema {
+                    path,
+                    count: stats.count,
+                    presence: stats.count as f64 * 100.0 / sampled as f64,
+                    types,
+                    distinct: stats.hashes.len() as u64,
+                }
+            })
+            .collect();
+        SchemaAnalysis {
+            namespace,
+            sampled,
+            fields,
+        }
+    }
+
+}
+
+// the values of different types are never equal, even if they are printed the same
+fn hash_value(value: &Bson) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    (value.element_type() as u8).hash(&mut hasher);
+    value.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The alias of the type used by `$type` of MongoDB.
+fn type_name(ty: ElementType) -> &'static str {
+    match ty {
+        ElementType::Double => "double",
+        ElementType::String => "string",
+        ElementType::EmbeddedDocument => "object",
+        ElementType::Array => "array",
+        ElementType::Binary => "binData",
+        ElementType::Undefined => "undefined",
+        ElementType::ObjectId => "objectId",
+        ElementType::Boolean => "bool",
+        ElementType::DateTime => "date",
+        ElementType::Null => "null",
+        ElementType::RegularExpression => "regex",
+        ElementType::DbPointer => "dbPointer",
+        ElementType::JavaScriptCode => "javascript",
+        ElementType::Symbol => "symbol",
+        ElementType::JavaScriptCodeWithScope => "javascriptWithScope",
+        ElementType::Int32 => "int",
+        ElementType::Timestamp => "timestamp",
+        ElementType::Int64 => "long",
+        ElementType::Decimal128 => "decimal",
+        ElementType::MaxKey => "maxKey",
+        ElementType::MinKey => "minKey",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson};
+    use super::SchemaAnalyzer;
+
+    #[test]
+    fn test_analyze_fields() {
+        let mut analyzer = SchemaAnalyzer::new("users");
+        analyzer.add(&doc! { "_id": 1, "age": 30, "address": { "city": "Paris" } });
+        analyzer.add(&doc! { "_id": 2, "age": 30, "address": { "city": "Rome", "zip": 100 } });
+        analyzer.add(&doc! { "_id": 3, "age": 30.5 });
+        analyzer.add(&doc! { "_id": 4, "age": Bson::Null, "tags": ["a"] });
+
+        let analysis = analyzer.finish();
+        assert_eq!(analysis.sampled, 4);
+        let paths: Vec<&str> = analysis.fields.iter().map(|field| field.path.as_str()).collect();
+        assert_eq!(paths, vec!["_id", "age", "address", "address.city", "address.zip", "tags"]);
+
+        let age = analysis.field("age").unwrap();
+        assert_eq!(age.count, 4);
+        assert_eq!(age.presence, 100.0);
+        assert_eq!(age.types, vec![
+            ("int".to_string(), 2),
+            ("double".to_string(), 1),
+            ("null".to_string(), 1),
+        ]);
+        assert_eq!(age.distinct, 3);
+
+        let city = analysis.field("address.city").unwrap();
+        assert_eq!(city.presence, 50.0);
+        assert_eq!(city.distinct, 2);
+        assert_eq!(analysis.field("tags").unwrap().types, vec![("array".to_string(), 1)]);
+        assert_eq!(analysis.field("_id").unwrap().distinct, 4);
+
+        let doc = analysis.to_document();
+        assert_eq!(doc.get_i64("sampled").unwrap(), 4);
+        let fields = doc.get_array("fields").unwrap();
+        assert_eq!(fields[1].as_document().unwrap().get_document("types").unwrap(), &doc! {
+            "int": 2_i64,
+            "double": 1_i64,
+            "null": 1_i64,
+        });
+    }
+
+    #[test]
+    fn test_analyze_nothing() {
+        let analysis = SchemaAnalyzer::new("users").finish();
+        assert_eq!(analysis.sampled, 0);
+        assert!(analysis.fields.is_empty());
+    }
+
+}
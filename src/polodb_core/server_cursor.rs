@@ -0,0 +1,221 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::collections::VecDeque;
+use std::time::Duration;
+use bson::{Bson, Document};
+use hashbrown::HashMap;
+use crate::{DbErr, DbResult};
+
+/// The documents of a batched find not returned yet,
+/// kept until they are fetched by `GetMore` or the cursor is killed.
+struct ServerCursor {
+    ns:          String,
+    query_shape: Document,
+    docs:        VecDeque<Document>,
+    last_access: Duration,
+}
+
+/// The open cursors of the batched finds of the wire protocol.
+///
+/// A cursor not accessed for the `cursor_timeout` of the config is reaped,
+/// the shape of its query is logged to find the clients which forget to exhaust
+/// or kill their cursors.
+pub(crate) struct ServerCursors {
+    cursors: HashMap<i64, ServerCursor>,
+    next_id: i64,
+}
+
+impl ServerCursors {
+
+    pub(crate) fn new() -> ServerCursors {
+        ServerCursors {
+            cursors: HashMap::new(),
+            next_id: 1,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.cursors.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.cursors.is_empty()
+    }
+
+    /// Return the first batch of the documents, and the id of the cursor
+    /// keeping the rest of them, which is 0 if nothing is left.
+    pub(crate) fn open(
+        &mut self,
+        ns: &str,
+        filter: Option<&Document>,
+        docs: Vec<Document>,
+        batch_size: usize,
+        now: Duration,
+    ) -> (i64, Vec<Document>) {
+        let mut docs: VecDeque<Document> = docs.into();
+        let batch = take_batch(&mut docs, batch_size);
+        if docs.is_empty() {
+            return (0, batch);
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cursors.insert(id, ServerCursor {
+            ns: ns.to_string(),
+            query_shape: filter.map(query_shape).unwrap_or_default(),
+            docs,
+            last_access: now,
+        });
+        (id, batch)
+    }
+
+    /// Return the next batch of the cursor, the whole rest if `batch_size` is `None`.
+    /// The cursor is closed when it's exhausted, and the id returned is 0.
+    pub(crate) fn get_more(
+        &mut self,
+        ns: &str,
+        id: i64,
+        batch_size: Option<usize>,
+        now: Duration,
+    ) -> DbResult<(i64, Vec<Document>)> {
+        let cursor = match self.cursors.get_mut(&id) {
+            Some(cursor) if cursor.ns == ns => cursor,
+            _ => return Err(DbErr::CursorNotFound(id)),
+        };
+        let batch_size = batch_size.unwrap_or(cursor.docs.len());
+        let batch = take_batch(&mut cursor.docs, batch_size);
+        cursor.last_access = now;
+        if cursor.docs.is_empty() {
+            self.cursors.remove(&id);
+            return Ok((0, batch));
+        }
+        Ok((id, batch))
+    }
+
+    /// Close the cursors of the collection, return the count of them closed.
+    pub(crate) fn kill(&mut self, ns: &str, ids: &[i64]) -> usize {
+        let mut count = 0;
+        for id in ids {
+            if self.cursors.get(id).map(|cursor| cursor.ns == ns).unwrap_or(false) {
+                self.cursors.remove(id);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Close the cursors idle for longer than `timeout`, which are leaked
+    /// by the clients probably, return the count of them.
+    pub(crate) fn reap(&mut self, now: Duration, timeout: Duration) -> usize {
+        let before = self.cursors.len();
+        self.cursors.retain(|id, cursor| {
+            let idle = now.saturating_sub(cursor.last_access);
+            if idle <= timeout {
+                return true;
+            }
+            crate::polo_log!(
+                "cursor {} of \"{}\" is reaped after idle for {:?}, {} documents left, query: {}",
+                id, cursor.ns, idle, cursor.docs.len(), cursor.query_shape,
+            );
+            false
+        });
+        before - self.cursors.len()
+    }
+
+}
+
+fn take_batch(docs: &mut VecDeque<Document>, batch_size: usize) -> Vec<Document> {
+    let count = batch_size.min(docs.len());
+    docs.drain(..count).collect()
+}
+
+/// The query with the values replaced by `"?"`, the same for the queries
+/// only different in the values, without logging the data of the users.
+pub(crate) fn query_shape(filter: &Document) -> Document {
+    let mut shape = Document::new();
+    for (key, value) in filter {
+        shape.insert(key.clone(), value_shape(key, value));
+    }
+    shape
+}
+
+fn value_shape(key: &str, value: &Bson) -> Bson {
+    match value {
+        Bson::Document(doc) if doc.keys().any(|field| field.starts_with('$')) => {
+            Bson::Document(query_shape(doc))
+        }
+        // the conditions of `$and`, `$or` and `$nor`
+        Bson::Array(arr) if key.starts_with('$') && arr.iter().all(|item| item.as_document().is_some()) => {
+            let conditions = arr
+                .iter()
+                .filter_map(|item| item.as_document())
+                .map(|doc| Bson::Document(query_shape(doc)))
+                .collect();
+            Bson::Array(conditions)
+        }
+        _ => Bson::String("?".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use bson::doc;
+    use crate::DbErr;
+    use super::{ServerCursors, query_shape};
+
+    #[test]
+    fn test_batches() {
+        let mut cursors = ServerCursors::new();
+        let docs = (0..5).map(|i| doc! { "_id": i }).collect();
+        let (id, batch) = cursors.open("test", None, docs, 2, Duration::ZERO);
+        assert_ne!(id, 0);
+        assert_eq!(batch.len(), 2);
+
+        let (next_id, batch) = cursors.get_more("test", id, Some(2), Duration::ZERO).unwrap();
+        assert_eq!(next_id, id);
+        assert_eq!(batch[0], doc! { "_id": 2 });
+        assert!(matches!(cursors.get_more("other", id, None, Duration::ZERO), Err(DbErr::CursorNotFound(_))));
+
+        let (next_id, batch) = cursors.get_more("test", id, None, Duration::ZERO).unwrap();
+        assert_eq!(next_id, 0);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(cursors.len(), 0);
+
+        let (id, _) = cursors.open("test", None, vec![doc! { "_id": 1 }], 1, Duration::ZERO);
+        assert_eq!(id, 0);
+    }
+
+    #[test]
+    fn test_reap() {
+        let mut cursors = ServerCursors::new();
+        let docs = || (0..3).map(|i| doc! { "_id": i }).collect();
+        let (first, _) = cursors.open("test", None, docs(), 1, Duration::from_secs(10));
+        let (second, _) = cursors.open("test", None, docs(), 1, Duration::from_secs(10));
+
+        cursors.get_more("test", second, Some(1), Duration::from_secs(50)).unwrap();
+        assert_eq!(cursors.reap(Duration::from_secs(80), Duration::from_secs(60)), 1);
+        assert!(cursors.get_more("test", first, None, Duration::from_secs(80)).is_err());
+        assert_eq!(cursors.kill("test", &[second]), 1);
+        assert_eq!(cursors.len(), 0);
+    }
+
+    #[test]
+    fn test_query_shape() {
+        let shape = query_shape(&doc! {
+            "name": "Alice",
+            "age": { "$gt": 18, "$lt": 30 },
+            "$or": [{ "city": "Paris" }, { "tags": { "$in": ["a", "b"] } }],
+            "address": { "city": "Paris" },
+        });
+        assert_eq!(shape, doc! {
+            "name": "?",
+            "age": { "$gt": "?", "$lt": "?" },
+            "$or": [{ "city": "?" }, { "tags": { "$in": "?" } }],
+            "address": "?",
+        });
+    }
+
+}
@@ -3,18 +3,24 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU64};
+#[cfg(feature = "fs")]
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use bson::Document;
 use bson::oid::ObjectId;
 use crate::backend::{AutoStartResult, Backend};
-use crate::{Config, DbErr, DbResult, Metrics, TransactionType};
+use crate::backend::object_store::PrefetchPlan;
+use crate::{Config, DbErr, DbResult, GrowthStrategy, Metrics, TransactionType};
 use crate::data_ticket::DataTicket;
+use crate::data_structures::buffer_arena::BufferArena;
 use crate::dump::JournalDump;
+#[cfg(feature = "debug")]
+use crate::dump::JournalFrameDump;
 use crate::page::header_page_wrapper::HeaderPageWrapper;
 use crate::page::RawPage;
 use crate::transaction::TransactionState;
-use super::session::{Session, SessionInner};
+use super::session::{Session, SessionInner, db_size_for_new_page};
 
 #[derive(Clone)]
 pub(crate) struct BaseSession {
@@ -70,6 +76,23 @@ impl BaseSession {
         session.remove_session(sid)
     }
 
+    pub fn park_session(&self, sid: &ObjectId, snapshot_version: usize) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.park_session(sid, snapshot_version)
+    }
+
+    pub fn unpark_session(&self, sid: &ObjectId) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.unpark_session(sid)
+    }
+
+    /// Replace the snapshot of the session by the latest version.
+    pub fn renew_session(&self, sid: &ObjectId) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.remove_session(sid)?;
+        session.backend.new_session(sid)
+    }
+
     pub fn pipeline_read_page(&self, page_id: u32, session_id: Option<&ObjectId>) -> DbResult<Arc<RawPage>> {
         let mut session = self.inner.as_ref().lock()?;
         session.pipeline_read_page(page_id, session_id)
@@ -91,9 +114,66 @@ impl BaseSession {
         session.db_size()
     }
 
-    pub fn init_block_count(&self) -> u64 {
+    pub fn growth_strategy(&self) -> GrowthStrategy {
         let session = self.inner.as_ref().lock().unwrap();
-        session.config.init_block_count.get()
+        session.config.growth_strategy.clone()
+    }
+
+    pub fn max_db_size(&self) -> Option<NonZeroU64> {
+        let session = self.inner.as_ref().lock().unwrap();
+        session.config.max_db_size
+    }
+
+    /// Checkpoint the backend, return [`DbErr::Busy`] if a transaction is running.
+    pub fn checkpoint(&self) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        if !session.transaction_state.is_no_trans() {
+            return Err(DbErr::Busy);
+        }
+        session.evict_parked_sessions()?;
+        session.backend.checkpoint()
+    }
+
+    pub fn prefetch_plan(&self, max_pages: usize) -> DbResult<Option<PrefetchPlan>> {
+        let session = self.inner.as_ref().lock()?;
+        Ok(session.backend.prefetch_plan(max_pages))
+    }
+
+    pub fn put_prefetched(&self, plan: &PrefetchPlan, pages: Vec<Arc<RawPage>>) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.put_prefetched(plan, pages);
+        Ok(())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn persist_to(&self, path: &Path) -> DbResult<()> {
+        let session = self.inner.as_ref().lock()?;
+        session.backend.persist_to(path)
+    }
+
+    /// Shrink the database in the transaction, see [`Backend::shrink_db_size`].
+    pub fn shrink_db_size(&self, db_size: u64) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.shrink_db_size(db_size)
+    }
+
+    #[cfg(feature = "debug")]
+    pub fn journal_frames(&self) -> DbResult<Vec<JournalFrameDump>> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.journal_frames()
+    }
+
+    /// See [`Backend::set_journal_full_size`].
+    pub fn set_journal_full_size(&self, size: u64) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.set_journal_full_size(size);
+        Ok(())
+    }
+
+    /// Pin the pages in the page cache, see [`Backend::pin_pages`].
+    pub fn pin_pages(&self, pages: &[u32]) -> DbResult<()> {
+        let mut session = self.inner.as_ref().lock()?;
+        session.backend.pin_pages(pages)
     }
 
     pub fn set_db_size(&self, db_size: u64) -> DbResult<()> {
@@ -122,11 +202,6 @@ impl Session for BaseSession {
         session.page_size
     }
 
-    fn store_doc(&self, doc: &Document) -> DbResult<DataTicket> {
-        let mut session = self.inner.as_ref().lock()?;
-        session.store_doc(doc)
-    }
-
     fn store_data_in_storage(&self, data: &[u8]) -> DbResult<DataTicket> {
         let mut session = self.inner.as_ref().lock()?;
         session.store_data_in_storage(data)
@@ -162,6 +237,16 @@ impl Session for BaseSession {
         session.auto_start_transaction(ty)
     }
 
+    fn take_buffer(&self) -> Vec<u8> {
+        let mut session = self.inner.as_ref().lock().unwrap();
+        session.arena.take()
+    }
+
+    fn give_back_buffer(&self, buffer: Vec<u8>) {
+        let mut session = self.inner.as_ref().lock().unwrap();
+        session.arena.give_back(buffer)
+    }
+
     fn auto_commit(&self) -> DbResult<()> {
         let mut session = self.inner.as_ref().lock()?;
         session.auto_commit()
@@ -200,6 +285,11 @@ struct BaseSessionInner {
 
     metrics:             Metrics,
 
+    arena:               BufferArena,
+
+    // the sessions in the pool, the snapshots of them are the latest
+    parked_sessions:     Vec<ObjectId>,
+
 }
 
 impl BaseSessionInner {
@@ -220,6 +310,10 @@ impl BaseSessionInner {
             config,
 
             metrics,
+
+            arena: BufferArena::new(),
+
+            parked_sessions: Vec::new(),
         })
     }
 
@@ -228,9 +322,36 @@ impl BaseSessionInner {
     }
 
     fn remove_session(&mut self, sid: &ObjectId) -> DbResult<()> {
+        self.parked_sessions.retain(|id| id != sid);
         self.backend.remove_session(sid)
     }
 
+    // The outdated snapshot is released at once.
+    fn park_session(&mut self, sid: &ObjectId, snapshot_version: usize) -> DbResult<()> {
+        if snapshot_version != self.version {
+            return self.backend.remove_session(sid);
+        }
+        self.parked_sessions.push(sid.clone());
+        Ok(())
+    }
+
+    // The parked snapshot is reused without a round trip to the backend.
+    fn unpark_session(&mut self, sid: &ObjectId) -> DbResult<()> {
+        if let Some(index) = self.parked_sessions.iter().position(|id| id == sid) {
+            self.parked_sessions.swap_remove(index);
+            return Ok(());
+        }
+        self.backend.new_session(sid)
+    }
+
+    // The parked snapshots are outdated by a commit, and they block the checkpoint.
+    fn evict_parked_sessions(&mut self) -> DbResult<()> {
+        for sid in std::mem::take(&mut self.parked_sessions) {
+            self.backend.remove_session(&sid)?;
+        }
+        Ok(())
+    }
+
     // for test
     #[allow(dead_code)]
     fn first_page_free_list_pid_and_size(&mut self) -> DbResult<(u32, u32)> {
@@ -277,7 +398,7 @@ impl BaseSessionInner {
     }
 
     fn dump_journal(&mut self) -> DbResult<Box<JournalDump>> {
-        Err(DbErr::Busy)
+        self.backend.dump_journal()
     }
 
     fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
@@ -286,6 +407,8 @@ impl BaseSessionInner {
     }
 
     fn commit(&mut self) -> DbResult<()> {
+        self.arena.reset();
+        self.evict_parked_sessions()?;
         self.backend.commit()?;
         self.version += 1;
         Ok(())
@@ -336,6 +459,7 @@ impl BaseSessionInner {
     // all the cache are wrong
     // cleat it
     fn rollback(&mut self) -> DbResult<()> {
+        self.arena.reset();
         self.backend.rollback()?;
         Ok(())
     }
@@ -398,13 +522,21 @@ impl SessionInner for BaseSessionInner {
         let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page.as_ref().clone());
 
         let null_page_bar = first_page_wrapper.get_null_page_bar();
-        first_page_wrapper.set_null_page_bar(null_page_bar + 1);
 
-        if (null_page_bar as u64) >= self.backend.db_size() {  // truncate file
-            let exceed_size = self.config.init_block_count.get() * (self.page_size.get() as u64);
-            self.backend.set_db_size(exceed_size)?;
+        let db_size = self.backend.db_size();
+        let next_size = db_size_for_new_page(
+            null_page_bar,
+            db_size,
+            self.page_size.get() as u64,
+            &self.config.growth_strategy,
+            self.config.max_db_size,
+        )?;
+        if next_size > db_size {  // grow file
+            self.backend.set_db_size(next_size)?;
         }
 
+        first_page_wrapper.set_null_page_bar(null_page_bar + 1);
+
         self.write_page(&first_page_wrapper.0)?;
 
         crate::polo_log!("alloc new page_id : {}", null_page_bar);
@@ -417,7 +549,7 @@ impl SessionInner for BaseSessionInner {
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "fs"))]
 mod test {
     use std::env;
     use std::collections::HashSet;
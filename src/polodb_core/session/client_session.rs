@@ -32,17 +32,70 @@ impl<'a> ClientSession<'a> {
     /// auto mode. The PoloDB will go into read mode firstly, once the users
     /// execute write operations(insert/update/delete), the DB will turn into
     /// write mode.
+    ///
+    /// The transaction reads the database committed when it's started,
+    /// and its writes are seen by the others only after it's committed.
+    /// Out of a transaction, every operation of the session is committed by itself.
     pub fn start_transaction(&mut self, ty: Option<TransactionType>) -> DbResult<()> {
         self.db.start_transaction(ty, Some(&self.id))
     }
 
+    /// Write the changes of the transaction to the database atomically,
+    /// even if they are in several collections.
+    ///
+    /// Return [`DbErr::SessionOutdated`] if the database is committed by another one
+    /// after the transaction is started, the transaction is aborted then.
+    ///
+    /// [`DbErr::SessionOutdated`]: crate::DbErr::SessionOutdated
     pub fn commit_transaction(&mut self) -> DbResult<()> {
         self.db.commit(Some(&self.id))
     }
 
+    /// Discard the changes of the transaction.
     pub fn abort_transaction(&mut self) -> DbResult<()> {
         self.db.rollback(Some(&self.id))
     }
+
+    /// Run the function in a write transaction, which is committed if it succeeds,
+    /// and aborted if it fails.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::bson::{Document, doc};
+    ///
+    /// let db = Database::open_memory().unwrap();
+    /// let accounts = db.collection::<Document>("accounts");
+    /// let transfers = db.collection::<Document>("transfers");
+    /// accounts.insert_many(vec![
+    ///     doc! { "_id": "alice", "balance": 100 },
+    ///     doc! { "_id": "bob", "balance": 0 },
+    /// ]).unwrap();
+    ///
+    /// let mut session = db.start_session().unwrap();
+    /// session.with_transaction(|session| {
+    ///     accounts.update_one_with_session(doc! { "_id": "alice" }, doc! { "$inc": { "balance": -30 } }, session)?;
+    ///     accounts.update_one_with_session(doc! { "_id": "bob" }, doc! { "$inc": { "balance": 30 } }, session)?;
+    ///     transfers.insert_one_with_session(doc! { "from": "alice", "to": "bob", "amount": 30 }, session)?;
+    ///     Ok(())
+    /// }).unwrap();
+    ///
+    /// assert_eq!(transfers.count_documents().unwrap(), 1);
+    /// ```
+    pub fn with_transaction<R>(&mut self, f: impl FnOnce(&mut ClientSession<'a>) -> DbResult<R>) -> DbResult<R> {
+        self.start_transaction(Some(TransactionType::Write))?;
+        match f(self) {
+            Ok(result) => {
+                self.commit_transaction()?;
+                Ok(result)
+            }
+            Err(err) => {
+                if let Err(abort_err) = self.abort_transaction() {
+                    crate::polo_log!("abort transaction error: {}", abort_err);
+                }
+                Err(err)
+            }
+        }
+    }
 }
 
 impl Drop for ClientSession<'_> {
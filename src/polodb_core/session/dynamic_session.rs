@@ -4,27 +4,35 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::collections::BTreeMap;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU64};
 use std::sync::{Mutex, Arc};
 use bson::Document;
 use bson::oid::ObjectId;
 use crate::data_ticket::DataTicket;
-use crate::{DbErr, DbResult, Metrics, TransactionType};
+use crate::data_structures::buffer_arena::BufferArena;
+use crate::{DbErr, DbResult, GrowthStrategy, Metrics, TransactionType};
 use crate::backend::AutoStartResult;
 use crate::page::header_page_wrapper::HeaderPageWrapper;
 use crate::page::RawPage;
 use crate::session::{BaseSession, Session};
-use crate::session::session::SessionInner;
+use crate::session::session::{SessionInner, db_size_for_new_page};
+use crate::transaction::TransactionState;
 
 struct DynamicSessionInner {
     id: ObjectId,
     version: usize,
+    // the version of the database when the snapshot of the backend is taken
+    snapshot_version: usize,
     base_session: BaseSession,
+    // the dirty pages of the transaction, `None` out of a transaction
     page_map: Option<BTreeMap<u32, Arc<RawPage>>>,
+    transaction_state: TransactionState,
     page_size: NonZeroU32,
     db_size: u64,
-    init_block_count: u64,
+    growth_strategy: GrowthStrategy,
+    max_db_size: Option<NonZeroU64>,
     metrics: Metrics,
+    arena: BufferArena,
 }
 
 impl DynamicSessionInner {
@@ -33,16 +41,21 @@ impl DynamicSessionInner {
         let page_size = base_session.page_size();
         let version = base_session.version();
         let db_size = base_session.db_size();
-        let init_block_count = base_session.init_block_count();
+        let growth_strategy = base_session.growth_strategy();
+        let max_db_size = base_session.max_db_size();
         DynamicSessionInner {
             id,
             version,
+            snapshot_version: version,
             base_session,
             page_map: None,
+            transaction_state: TransactionState::NoTrans,
             page_size,
             db_size,
-            init_block_count,
+            growth_strategy,
+            max_db_size,
             metrics,
+            arena: BufferArena::new(),
         }
     }
 
@@ -50,32 +63,66 @@ impl DynamicSessionInner {
         if self.page_map.is_some() {
             return Err(DbErr::StartTransactionInAnotherTransaction);
         }
+        self.begin()?;
+        self.transaction_state = TransactionState::User;
+        Ok(())
+    }
+
+    /// The transaction reads the latest version of the database when it's started,
+    /// and the writes are kept in the session until it's committed.
+    fn begin(&mut self) -> DbResult<()> {
+        self.refresh_snapshot()?;
         self.page_map = Some(BTreeMap::new());
         Ok(())
     }
 
+    /// Move the snapshot of the backend to the latest version, if the database
+    /// is committed since it's taken, by this session or the others.
+    fn refresh_snapshot(&mut self) -> DbResult<()> {
+        let version = self.base_session.version();
+        if version == self.snapshot_version {
+            return Ok(());
+        }
+        self.base_session.renew_session(&self.id)?;
+        self.version = version;
+        self.snapshot_version = version;
+        self.db_size = self.base_session.db_size();
+        Ok(())
+    }
+
     /// 1. Check version first.
-    ///    If the base_session is updated, this commit MUST fail
+    ///    If the base_session is updated, this commit MUST fail,
+    ///    and the transaction is aborted.
     /// 2. If the version is valid, flush all the pages to the base
+    ///    in one transaction, so the collections are written atomically.
     fn commit(&mut self) -> DbResult<()> {
-        let current_version = self.base_session.version();
-        if current_version != self.version {
-            return Err(DbErr::SessionOutdated);
-        }
-
-        if let Some(page_map) = &self.page_map {
-            self.base_session.start_transaction(TransactionType::Write)?;
-            self.base_session.set_db_size(self.db_size)?;
+        let page_map = self.page_map.as_ref().ok_or(DbErr::NoTransactionStarted)?;
 
-            for (_page_id, page) in page_map {
-                self.base_session.write_page(page)?;
+        // nothing is written, the version of the database is kept
+        if !page_map.is_empty() {
+            if self.base_session.version() != self.version {
+                self.discard();
+                return Err(DbErr::SessionOutdated);
             }
 
-            self.base_session.commit()?;
-            self.page_map = None;  // clear the map after commited
+            self.base_session.start_transaction(TransactionType::Write)?;
+            let write_result = self.base_session.set_db_size(self.db_size)
+                .and_then(|()| {
+                    page_map.values().try_for_each(|page| self.base_session.write_page(page))
+                })
+                .and_then(|()| self.base_session.commit());
+            if let Err(err) = write_result {
+                if let Err(rollback_err) = self.base_session.rollback() {
+                    return Err(err.add(rollback_err));
+                }
+                return Err(err);
+            }
             self.version = self.base_session.version();
         }
 
+        self.page_map = None;  // clear the map after commited
+        self.transaction_state = TransactionState::NoTrans;
+        self.arena.reset();
         self.metrics.commit();
 
         Ok(())
@@ -85,15 +132,64 @@ impl DynamicSessionInner {
         if self.page_map.is_none() {
             return Err(DbErr::NoTransactionStarted);
         }
-        self.page_map = Some(BTreeMap::new());
+        self.discard();
+        Ok(())
+    }
+
+    // the pages allocated by the transaction are given back with the size
+    fn discard(&mut self) {
+        self.arena.reset();
+        self.page_map = None;
+        self.transaction_state = TransactionState::NoTrans;
+        self.db_size = self.base_session.db_size();
+    }
+
+    /// The operation out of a transaction of the user runs in a transaction of its own,
+    /// which is committed when the operation is finished.
+    fn auto_start_transaction(&mut self) -> DbResult<AutoStartResult> {
+        let mut result = AutoStartResult { auto_start: false };
+        match self.transaction_state {
+            TransactionState::DbAuto(_) => {
+                self.transaction_state.acquire();
+            }
+
+            TransactionState::NoTrans => {
+                self.begin()?;
+                self.transaction_state = TransactionState::new_db_auto();
+                result.auto_start = true;
+            }
+
+            _ => (),
+        }
+        Ok(result)
+    }
+
+    fn auto_commit(&mut self) -> DbResult<()> {
+        if !self.transaction_state.release() {
+            return Ok(());
+        }
+        let is_read = self.page_map.as_ref().map(BTreeMap::is_empty).unwrap_or(true);
+        if is_read {
+            self.page_map = None;
+            self.transaction_state = TransactionState::NoTrans;
+            self.arena.reset();
+            return Ok(());
+        }
+        self.commit()
+    }
+
+    fn auto_rollback(&mut self) -> DbResult<()> {
+        if self.transaction_state.release() {
+            self.discard();
+        }
         Ok(())
     }
 }
 
 impl SessionInner for DynamicSessionInner {
     fn read_page(&mut self, page_id: u32) -> DbResult<Arc<RawPage>> {
-        let page_map = self.page_map.as_ref().ok_or(DbErr::NoTransactionStarted)?;
-        match page_map.get(&page_id) {
+        // out of a transaction the snapshot is read
+        match self.page_map.as_ref().and_then(|page_map| page_map.get(&page_id)) {
             Some(page) => Ok(page.clone()),
             None => {
                 self.base_session
@@ -121,12 +217,16 @@ impl SessionInner for DynamicSessionInner {
         let mut first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page.as_ref().clone());
 
         let null_page_bar = first_page_wrapper.get_null_page_bar();
-        first_page_wrapper.set_null_page_bar(null_page_bar + 1);
 
-        if (null_page_bar as u64) >= self.db_size {  // truncate file
-            let exceed_size = self.init_block_count * (self.page_size().get() as u64);
-            self.db_size = exceed_size;
-        }
+        self.db_size = db_size_for_new_page(
+            null_page_bar,
+            self.db_size,
+            self.page_size().get() as u64,
+            &self.growth_strategy,
+            self.max_db_size,
+        )?;
+
+        first_page_wrapper.set_null_page_bar(null_page_bar + 1);
 
         self.write_page(&first_page_wrapper.0)?;
 
@@ -157,6 +257,26 @@ impl DynamicSession {
         }
     }
 
+    /// Park the snapshot of the session in the backend when it's returned to the pool,
+    /// the snapshot is reused by [`DynamicSession::renew`] if nothing is committed since.
+    pub fn park(&self) -> DbResult<()> {
+        let mut inner = self.inner.lock()?;
+        inner.page_map = None;
+        inner.transaction_state = TransactionState::NoTrans;
+        inner.arena.reset();
+        inner.base_session.park_session(&inner.id, inner.snapshot_version)
+    }
+
+    /// Move the parked session to the latest version of the database.
+    pub fn renew(&self) -> DbResult<()> {
+        let mut inner = self.inner.lock()?;
+        inner.base_session.unpark_session(&inner.id)?;
+        inner.version = inner.base_session.version();
+        inner.snapshot_version = inner.version;
+        inner.db_size = inner.base_session.db_size();
+        Ok(())
+    }
+
 }
 
 impl Session for DynamicSession {
@@ -175,11 +295,6 @@ impl Session for DynamicSession {
         inner.page_size()
     }
 
-    fn store_doc(&self, doc: &Document) -> DbResult<DataTicket> {
-        let mut inner = self.inner.lock()?;
-        inner.store_doc(doc)
-    }
-
     fn store_data_in_storage(&self, data: &[u8]) -> DbResult<DataTicket> {
         let mut inner = self.inner.lock()?;
         inner.store_data_in_storage(data)
@@ -210,19 +325,29 @@ impl Session for DynamicSession {
         inner.get_data_from_storage(data_ticket)
     }
 
-    // dynamic session must start transaction manually
+    fn take_buffer(&self) -> Vec<u8> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.arena.take()
+    }
+
+    fn give_back_buffer(&self, buffer: Vec<u8>) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.arena.give_back(buffer)
+    }
+
     fn auto_start_transaction(&self, _ty: TransactionType) -> DbResult<AutoStartResult> {
-        Ok(AutoStartResult {
-            auto_start: false,
-        })
+        let mut inner = self.inner.lock()?;
+        inner.auto_start_transaction()
     }
 
     fn auto_commit(&self) -> DbResult<()> {
-        Ok(())
+        let mut inner = self.inner.lock()?;
+        inner.auto_commit()
     }
 
     fn auto_rollback(&self) -> DbResult<()> {
-        Ok(())
+        let mut inner = self.inner.lock()?;
+        inner.auto_rollback()
     }
 
     fn start_transaction(&self, ty: TransactionType) -> DbResult<()> {
@@ -6,10 +6,13 @@
 use std::cell::Cell;
 use std::cmp::min;
 use std::sync::Arc;
-use std::num::NonZeroU32;
+use std::num::{NonZeroU32, NonZeroU64};
 use bson::Document;
+use smallvec::SmallVec;
 use crate::data_ticket::DataTicket;
-use crate::{DbErr, DbResult, Metrics, TransactionType};
+use crate::compression;
+use crate::projection::ScanFields;
+use crate::{DbErr, DbResult, GrowthStrategy, Metrics, TransactionType};
 use crate::backend::AutoStartResult;
 use crate::page::data_page_wrapper::DataPageWrapper;
 use crate::page::header_page_wrapper::HeaderPageWrapper;
@@ -19,6 +22,39 @@ use crate::page::data_allocator_wrapper::DataAllocatorWrapper;
 
 const PRESERVE_WRAPPER_MIN_REMAIN_SIZE: u32 = 16;
 
+/// The page ids are 32-bit, the last one is left for the null page bar.
+const MAX_PAGE_COUNT: u64 = u32::MAX as u64;
+
+/// The size of the database when the page `page_id` is allocated,
+/// fail if the page is beyond the max size.
+pub(crate) fn db_size_for_new_page(
+    page_id: u32,
+    db_size: u64,
+    page_size: u64,
+    growth_strategy: &GrowthStrategy,
+    max_db_size: Option<NonZeroU64>,
+) -> DbResult<u64> {
+    // the quota of the config is capped by the limit of the page ids
+    let max_page_count = max_db_size
+        .map(|max_db_size| min(max_db_size.get() / page_size, MAX_PAGE_COUNT))
+        .unwrap_or(MAX_PAGE_COUNT);
+    let limit = max_page_count * page_size;
+
+    let required = (page_id as u64 + 1) * page_size;
+    if required > limit {
+        return Err(DbErr::DatabaseSizeLimitExceeded(limit));
+    }
+    if page_id as u64 == max_page_count / 10 * 9 {
+        crate::polo_log!("the database reaches 90% of the max size: {} bytes", limit);
+    }
+
+    if required <= db_size {
+        return Ok(db_size);
+    }
+    let next_size = growth_strategy.next_db_size(db_size, required, page_size);
+    Ok(min(next_size, limit))
+}
+
 pub(crate) trait Session {
     /// use Arc here because PoloDB will heavily rely on
     /// the page cache, use Arc to share the same memory
@@ -26,7 +62,6 @@ pub(crate) trait Session {
     fn read_page(&self, page_id: u32) -> DbResult<Arc<RawPage>>;
     fn write_page(&self, page: &RawPage) -> DbResult<()>;
     fn page_size(&self) -> NonZeroU32;
-    fn store_doc(&self, doc: &Document) -> DbResult<DataTicket>;
     fn store_data_in_storage(&self, data: &[u8]) -> DbResult<DataTicket>;
     fn alloc_page_id(&self) -> DbResult<u32>;
     fn free_pages(&self, pages: &[u32]) -> DbResult<()>;
@@ -35,7 +70,16 @@ pub(crate) trait Session {
     }
     fn free_data_ticket(&self, data_ticket: &DataTicket) -> DbResult<Vec<u8>>;
     fn get_doc_from_ticket(&self, data_ticket: &DataTicket) -> DbResult<Document>;
+    /// Decode only the parts of the document needed by the scan.
+    fn get_doc_fields_from_ticket(&self, data_ticket: &DataTicket, scan: &ScanFields) -> DbResult<Document> {
+        let bytes = self.get_data_from_storage(data_ticket)?;
+        compression::decode_doc_fields(&bytes, scan)
+    }
     fn get_data_from_storage(&self, data_ticket: &DataTicket) -> DbResult<Vec<u8>>;
+    /// Take a byte buffer from the arena of the transaction,
+    /// give it back by [`Session::give_back_buffer`] after using it.
+    fn take_buffer(&self) -> Vec<u8>;
+    fn give_back_buffer(&self, buffer: Vec<u8>);
     fn auto_start_transaction(&self, ty: TransactionType) -> DbResult<AutoStartResult>;
     fn auto_commit(&self) -> DbResult<()>;
     fn auto_rollback(&self) -> DbResult<()>;
@@ -153,12 +197,9 @@ pub(crate) trait SessionInner {
         Ok(page_id)
     }
 
-    fn store_doc(&mut self, doc: &Document) -> DbResult<DataTicket> where Self: Sized {
-        let bytes = bson::to_vec(doc)?;
-        self.store_data_in_storage(&bytes)
-    }
-
     fn store_data_in_storage(&mut self, data: &[u8]) -> DbResult<DataTicket> where Self: Sized {
+        self.metrics().write_bytes(data.len());
+
         if data.len() >= self.page_size().get() as usize / 2 {
             return store_large_data(self, data);
         }
@@ -216,6 +257,12 @@ pub(crate) trait SessionInner {
 
         if wrapper.is_empty() {
             metrics.free_data_page(wrapper.remain_size());
+            // the page MUST NOT be distributed again after it's freed
+            let mut data_allocator = self.get_data_allocator_wrapper()?;
+            if data_allocator.remove(data_ticket.pid) {
+                let data_allocator_page = data_allocator.generate_page();
+                self.write_page(&data_allocator_page)?;
+            }
             self.free_page(data_ticket.pid)?;
         }
         let page = wrapper.consume_page();
@@ -225,19 +272,19 @@ pub(crate) trait SessionInner {
 
     fn get_doc_from_ticket(&mut self, data_ticket: &DataTicket) -> DbResult<Document> {
         let bytes = self.get_data_from_storage(data_ticket)?;
-        let doc = bson::from_slice(&bytes)?;
-        return Ok(doc);
+        compression::decode_doc(&bytes)
     }
 
     fn get_data_from_storage(&mut self, data_ticket: &DataTicket) -> DbResult<Vec<u8>> {
-        if data_ticket.is_large_data() {
-            self.get_doc_from_large_page(data_ticket.pid)
+        let bytes = if data_ticket.is_large_data() {
+            self.get_doc_from_large_page(data_ticket.pid)?
         } else {
             let page = self.read_page(data_ticket.pid)?;
             let wrapper = DataPageWrapper::from_raw(page.as_ref().clone());
-            let bytes = wrapper.get(data_ticket.index as u32).unwrap().to_vec();
-            Ok(bytes)
-        }
+            wrapper.get(data_ticket.index as u32).unwrap().to_vec()
+        };
+        self.metrics().read_bytes(bytes.len());
+        Ok(bytes)
     }
 
     fn get_doc_from_large_page(&mut self, pid: u32) -> DbResult<Vec<u8>> {
@@ -340,7 +387,8 @@ pub(crate) trait SessionInner {
 
 fn free_large_data_page(session: &mut impl SessionInner, pid: u32) -> DbResult<Vec<u8>> {
     let mut result: Vec<u8> = Vec::with_capacity(session.page_size().get() as usize);
-    let mut free_pid: Vec<u32> = Vec::new();
+    // a document of a few pages usually
+    let mut free_pid: SmallVec<[u32; 8]> = SmallVec::new();
 
     let mut next_pid = pid;
     while next_pid != 0 {
@@ -429,3 +477,25 @@ fn store_large_data(session: &mut impl SessionInner, bytes: &[u8]) -> DbResult<D
 
     Ok(DataTicket::large_ticket(first_pid))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU64;
+    use crate::{DbErr, GrowthStrategy};
+    use super::db_size_for_new_page;
+
+    #[test]
+    fn test_size_limit_of_page_ids() {
+        let growth_strategy = GrowthStrategy::Fixed(NonZeroU64::new(16).unwrap());
+        let limit = u32::MAX as u64 * 4096;
+        let db_size = db_size_for_new_page(u32::MAX - 1, 0, 4096, &growth_strategy, None).unwrap();
+        assert_eq!(db_size, limit);
+
+        // a quota beyond the page ids is capped by them
+        for max_db_size in [None, NonZeroU64::new(u64::MAX)] {
+            let err = db_size_for_new_page(u32::MAX, 0, 4096, &growth_strategy, max_db_size).unwrap_err();
+            assert!(matches!(err, DbErr::DatabaseSizeLimitExceeded(size) if size == limit));
+        }
+    }
+
+}
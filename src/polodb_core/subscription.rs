@@ -0,0 +1,291 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The in-process subscriptions of the changes of a collection.
+//!
+//! [`Collection::subscribe`] returns a [`Subscription`] receiving the documents
+//! inserted, updated and deleted in the collection, filtered by a query.
+//! The events are sent after the changes are committed, the changes of a session
+//! are sent when the transaction is committed, and dropped when it's aborted.
+//! So a view of a GUI can be updated without polling the database.
+//!
+//! ```rust
+//! use polodb_core::Database;
+//! use polodb_core::bson::{Document, doc};
+//! use polodb_core::subscription::ChangeOperation;
+//!
+//! let db = Database::open_memory().unwrap();
+//! let tasks = db.collection::<Document>("tasks");
+//! let subscription = tasks.subscribe(doc! { "done": false }).unwrap();
+//!
+//! tasks.insert_one(doc! { "_id": 1, "title": "write docs", "done": false }).unwrap();
+//! tasks.insert_one(doc! { "_id": 2, "title": "release", "done": true }).unwrap();
+//!
+//! let event = subscription.try_recv().unwrap();
+//! assert_eq!(event.operation, ChangeOperation::Insert);
+//! assert_eq!(event.document_key, 1.into());
+//! assert!(subscription.try_recv().is_none());
+//! ```
+//!
+//! The changes of the commands handled by [`Database::handle_request_doc`] are sent too.
+//! Dropping a collection doesn't send the deletions of the documents.
+//!
+//! [`Collection::subscribe`]: crate::Collection::subscribe
+//! [`Database::handle_request_doc`]: crate::Database::handle_request_doc
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::time::Duration;
+use bson::{Bson, Document};
+use bson::oid::ObjectId;
+use hashbrown::HashMap;
+use crate::aggregation::DocumentFilter;
+
+/// The kind of a change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOperation {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// A change of a document.
+#[derive(Debug, Clone)]
+pub struct ChangeEvent {
+    pub operation: ChangeOperation,
+
+    /// The name of the collection.
+    pub collection: String,
+
+    /// The `_id` field of the document.
+    pub document_key: Bson,
+
+    /// The document after an insertion or an update,
+    /// or the document deleted by a deletion.
+    pub full_document: Document,
+}
+
+impl ChangeEvent {
+
+    pub(crate) fn new(operation: ChangeOperation, collection: &str, full_document: Document) -> ChangeEvent {
+        ChangeEvent {
+            operation,
+            collection: collection.to_string(),
+            document_key: full_document.get("_id").cloned().unwrap_or(Bson::Null),
+            full_document,
+        }
+    }
+
+}
+
+/// The receiver of the changes returned by [`Collection::subscribe`].
+///
+/// The subscription is cancelled when it's dropped. The events are
+/// buffered until they are received, so they should be received constantly.
+/// It's counted as an open cursor by [`Config::max_open_cursors`] until it's dropped.
+///
+/// [`Collection::subscribe`]: crate::Collection::subscribe
+/// [`Config::max_open_cursors`]: crate::Config::max_open_cursors
+pub struct Subscription {
+    receiver: Receiver<ChangeEvent>,
+    open_count: Arc<AtomicUsize>,
+}
+
+impl Subscription {
+
+    /// Wait for the next change, return `None` if the database is closed.
+    pub fn recv(&self) -> Option<ChangeEvent> {
+        self.receiver.recv().ok()
+    }
+
+    /// Return the next change if there is one, without blocking,
+    /// which can be called on every frame of a GUI.
+    pub fn try_recv(&self) -> Option<ChangeEvent> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Wait for the next change at most `timeout`.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<ChangeEvent> {
+        match self.receiver.recv_timeout(timeout) {
+            Ok(event) => Some(event),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => None,
+        }
+    }
+
+}
+
+impl Drop for Subscription {
+
+    fn drop(&mut self) {
+        self.open_count.fetch_sub(1, Ordering::SeqCst);
+    }
+
+}
+
+impl Iterator for Subscription {
+    type Item = ChangeEvent;
+
+    fn next(&mut self) -> Option<ChangeEvent> {
+        self.recv()
+    }
+}
+
+struct Subscriber {
+    collection: String,
+    filter:     Option<DocumentFilter>,
+    sender:     Sender<ChangeEvent>,
+}
+
+impl Subscriber {
+
+    fn matches(&self, event: &ChangeEvent) -> bool {
+        if self.collection != event.collection {
+            return false;
+        }
+        match &self.filter {
+            Some(filter) => match filter.matches(&event.full_document) {
+                Ok(result) => result,
+                Err(err) => {
+                    crate::polo_log!("match the change of \"{}\" failed: {}", event.collection, err);
+                    false
+                }
+            },
+            None => true,
+        }
+    }
+
+}
+
+/// The subscribers of a database, and the changes of the sessions not committed.
+#[derive(Default)]
+pub(crate) struct Subscriptions {
+    subscribers: Vec<Subscriber>,
+    pending:     HashMap<ObjectId, Vec<ChangeEvent>>,
+    // the subscriptions not dropped, the subscribers are removed lazily
+    open_count:  Arc<AtomicUsize>,
+}
+
+impl Subscriptions {
+
+    pub(crate) fn new() -> Subscriptions {
+        Subscriptions::default()
+    }
+
+    pub(crate) fn subscribe(&mut self, collection: &str, filter: Option<DocumentFilter>) -> Subscription {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(Subscriber {
+            collection: collection.to_string(),
+            filter,
+            sender,
+        });
+        self.open_count.fetch_add(1, Ordering::SeqCst);
+        Subscription {
+            receiver,
+            open_count: self.open_count.clone(),
+        }
+    }
+
+    /// The count of the subscriptions not dropped.
+    pub(crate) fn open_count(&self) -> usize {
+        self.open_count.load(Ordering::SeqCst)
+    }
+
+    /// The changes of the collection are collected only if it's subscribed.
+    pub(crate) fn is_subscribed(&self, collection: &str) -> bool {
+        self.subscribers.iter().any(|subscriber| subscriber.collection == collection)
+    }
+
+    /// Send the changes committed, or keep them until the session is committed.
+    pub(crate) fn publish(&mut self, events: Vec<ChangeEvent>, session_id: Option<&ObjectId>) {
+        if events.is_empty() {
+            return;
+        }
+        match session_id {
+            Some(session_id) => {
+                self.pending
+                    .entry(session_id.clone())
+                    .or_insert_with(Vec::new)
+                    .extend(events);
+            }
+            None => self.send(events),
+        }
+    }
+
+    pub(crate) fn commit(&mut self, session_id: &ObjectId) {
+        if let Some(events) = self.pending.remove(session_id) {
+            self.send(events);
+        }
+    }
+
+    pub(crate) fn discard(&mut self, session_id: &ObjectId) {
+        self.pending.remove(session_id);
+    }
+
+    // the subscriber is removed if the subscription is dropped
+    fn send(&mut self, events: Vec<ChangeEvent>) {
+        for event in events {
+            self.subscribers.retain(|subscriber| {
+                !subscriber.matches(&event) || subscriber.sender.send(event.clone()).is_ok()
+            });
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::doc;
+    use bson::oid::ObjectId;
+    use hashbrown::HashMap;
+    use crate::aggregation::DocumentFilter;
+    use crate::OperatorMode;
+    use super::{ChangeEvent, ChangeOperation, Subscriptions};
+
+    #[test]
+    fn test_publish_in_session() {
+        let mut subscriptions = Subscriptions::new();
+        let filter = DocumentFilter::new(doc! { "age": { "$gt": 18 } }, HashMap::new(), OperatorMode::Strict);
+        let subscription = subscriptions.subscribe("users", Some(filter));
+        assert!(subscriptions.is_subscribed("users"));
+        assert!(!subscriptions.is_subscribed("orders"));
+
+        let session_id = ObjectId::new();
+        subscriptions.publish(vec![
+            ChangeEvent::new(ChangeOperation::Insert, "users", doc! { "_id": 1, "age": 20 }),
+            ChangeEvent::new(ChangeOperation::Insert, "users", doc! { "_id": 2, "age": 10 }),
+        ], Some(&session_id));
+        assert!(subscription.try_recv().is_none());
+
+        subscriptions.commit(&session_id);
+        assert_eq!(subscription.try_recv().unwrap().document_key, 1.into());
+        assert!(subscription.try_recv().is_none());
+
+        subscriptions.publish(vec![
+            ChangeEvent::new(ChangeOperation::Delete, "users", doc! { "_id": 1, "age": 20 }),
+        ], Some(&session_id));
+        subscriptions.discard(&session_id);
+        subscriptions.commit(&session_id);
+        assert!(subscription.try_recv().is_none());
+
+        drop(subscription);
+        subscriptions.publish(vec![
+            ChangeEvent::new(ChangeOperation::Insert, "users", doc! { "_id": 3, "age": 30 }),
+        ], None);
+        assert!(!subscriptions.is_subscribed("users"));
+    }
+
+    #[test]
+    fn test_open_count() {
+        let mut subscriptions = Subscriptions::new();
+        let first = subscriptions.subscribe("users", None);
+        let _second = subscriptions.subscribe("orders", None);
+        assert_eq!(subscriptions.open_count(), 2);
+
+        drop(first);
+        assert_eq!(subscriptions.open_count(), 1);
+    }
+
+}
@@ -5,6 +5,10 @@
  */
 use std::path::PathBuf;
 use std::env;
+use std::future::Future;
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
 use crate::{Config, Database, DbResult};
 
 pub fn mk_db_path(db_name: &str) -> PathBuf {
@@ -36,4 +40,26 @@ pub fn prepare_db_with_config(db_name: &str, config: Config) -> DbResult<Databas
 
 pub fn prepare_db(db_name: &str) -> DbResult<Database> {
     prepare_db_with_config(db_name, Config::default())
-}
\ No newline at end of file
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run the future on the current thread until it's done,
+/// for the tests of the async API without a runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = Box::pin(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        thread::park();
+    }
+}
@@ -12,7 +12,8 @@ pub fn mk_db_path(db_name: &str) -> PathBuf {
     db_path
 }
 
-fn mk_journal_path(db_name: &str) -> PathBuf {
+#[allow(dead_code)]
+pub fn mk_journal_path(db_name: &str) -> PathBuf {
     let mut journal_path = env::temp_dir();
 
     let journal_filename = String::from(db_name) + ".db.journal";
@@ -0,0 +1,387 @@
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{doc, Document};
+
+mod common;
+
+use common::prepare_db;
+
+fn prepare_orders(db: &Database) {
+    db.collection::<Document>("orders").insert_many(vec![
+        doc! { "_id": 1, "item": "almonds", "price": 12, "quantity": 2 },
+        doc! { "_id": 2, "item": "pecans", "price": 20, "quantity": 1 },
+        doc! { "_id": 3, "item": "cookies", "price": 10, "quantity": 60 },
+    ]).unwrap();
+    db.collection::<Document>("inventory").insert_many(vec![
+        doc! { "_id": 1, "sku": "almonds", "instock": 120 },
+        doc! { "_id": 2, "sku": "bread", "instock": 80 },
+        doc! { "_id": 3, "sku": "cashews", "instock": 60 },
+        doc! { "_id": 4, "sku": "pecans", "instock": 70 },
+        doc! { "_id": 5, "sku": "cookies", "instock": 20 },
+    ]).unwrap();
+}
+
+#[test]
+fn test_lookup_equality() {
+    let db = prepare_db("test-lookup-equality").unwrap();
+    prepare_orders(&db);
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$match": { "price": { "$gt": 10 } } },
+        doc! {
+            "$lookup": {
+                "from": "inventory",
+                "localField": "item",
+                "foreignField": "sku",
+                "as": "inventory_docs",
+            },
+        },
+    ]).unwrap();
+
+    assert_eq!(result.len(), 2);
+    for order in &result {
+        let joined = order.get_array("inventory_docs").unwrap();
+        assert_eq!(joined.len(), 1);
+        let inventory = joined[0].as_document().unwrap();
+        assert_eq!(inventory.get_str("sku").unwrap(), order.get_str("item").unwrap());
+    }
+}
+
+#[test]
+fn test_lookup_by_primary_key() {
+    let db = prepare_db("test-lookup-primary-key").unwrap();
+    prepare_orders(&db);
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! {
+            "$lookup": {
+                "from": "inventory",
+                "localField": "_id",
+                "foreignField": "_id",
+                "as": "same_id",
+            },
+        },
+    ]).unwrap();
+
+    assert_eq!(result.len(), 3);
+    for order in &result {
+        let joined = order.get_array("same_id").unwrap();
+        assert_eq!(joined.len(), 1);
+        assert_eq!(joined[0].as_document().unwrap().get("_id"), order.get("_id"));
+    }
+}
+
+#[test]
+fn test_lookup_pipeline() {
+    let db = prepare_db("test-lookup-pipeline").unwrap();
+    prepare_orders(&db);
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! {
+            "$lookup": {
+                "from": "inventory",
+                "let": { "order_item": "$item", "order_qty": "$quantity" },
+                "pipeline": [
+                    { "$match": { "$expr": { "$eq": ["$sku", "$$order_item"] } } },
+                    { "$match": { "$expr": { "$gte": ["$instock", "$$order_qty"] } } },
+                ],
+                "as": "stockdata",
+            },
+        },
+    ]).unwrap();
+
+    let stock_count = |id: i32| {
+        let order = result.iter().find(|doc| doc.get_i32("_id").unwrap() == id).unwrap();
+        order.get_array("stockdata").unwrap().len()
+    };
+    assert_eq!(stock_count(1), 1);
+    assert_eq!(stock_count(2), 1);
+    // only 20 cookies in stock
+    assert_eq!(stock_count(3), 0);
+}
+
+#[test]
+fn test_lookup_hash_join() {
+    let db = prepare_db("test-lookup-hash-join").unwrap();
+
+    let mut users = vec![];
+    let mut posts = vec![];
+    for i in 0..200 {
+        users.push(doc! { "name": format!("user-{}", i), "group": i % 10 });
+        posts.push(doc! { "group": i % 10, "title": format!("post-{}", i) });
+    }
+    db.collection::<Document>("users").insert_many(users).unwrap();
+    db.collection::<Document>("posts").insert_many(posts).unwrap();
+
+    let result = db.collection::<Document>("users").aggregate(vec![
+        doc! {
+            "$lookup": {
+                "from": "posts",
+                "localField": "group",
+                "foreignField": "group",
+                "as": "posts",
+            },
+        },
+    ]).unwrap();
+
+    assert_eq!(result.len(), 200);
+    for user in &result {
+        assert_eq!(user.get_array("posts").unwrap().len(), 20);
+    }
+}
+
+#[test]
+fn test_unknown_stage() {
+    let db = prepare_db("test-aggregate-unknown-stage").unwrap();
+    prepare_orders(&db);
+
+    let err = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$bucketAuto": { "groupBy": "$price", "buckets": 2 } },
+    ]).unwrap_err();
+    assert!(matches!(err, DbErr::UnknownAggregationOperation(_)));
+}
+
+#[test]
+fn test_set_window_fields() {
+    let db = prepare_db("test-set-window-fields").unwrap();
+    db.collection::<Document>("scores").insert_many(vec![
+        doc! { "game": "a", "player": "p1", "score": 30, "day": 1 },
+        doc! { "game": "a", "player": "p2", "score": 50, "day": 2 },
+        doc! { "game": "a", "player": "p3", "score": 50, "day": 3 },
+        doc! { "game": "a", "player": "p4", "score": 10, "day": 4 },
+        doc! { "game": "b", "player": "p1", "score": 20, "day": 1 },
+    ]).unwrap();
+
+    let result = db.collection::<Document>("scores").aggregate(vec![
+        doc! {
+            "$setWindowFields": {
+                "partitionBy": "$game",
+                "sortBy": { "score": -1 },
+                "output": {
+                    "rank": { "$rank": {} },
+                    "denseRank": { "$denseRank": {} },
+                    "runningTotal": {
+                        "$sum": "$score",
+                        "window": { "documents": ["unbounded", "current"] },
+                    },
+                },
+            },
+        },
+    ]).unwrap();
+
+    let ranks: Vec<(i64, i64, i32)> = result
+        .iter()
+        .filter(|doc| doc.get_str("game").unwrap() == "a")
+        .map(|doc| (
+            doc.get_i64("rank").unwrap(),
+            doc.get_i64("denseRank").unwrap(),
+            doc.get_i32("runningTotal").unwrap(),
+        ))
+        .collect();
+    assert_eq!(ranks, vec![(1, 1, 50), (1, 1, 100), (3, 2, 130), (4, 3, 140)]);
+
+    let result = db.collection::<Document>("scores").aggregate(vec![
+        doc! { "$match": { "game": "a" } },
+        doc! {
+            "$setWindowFields": {
+                "sortBy": { "day": 1 },
+                "output": {
+                    "movingAvg": {
+                        "$avg": "$score",
+                        "window": { "range": [-1, 0] },
+                    },
+                },
+            },
+        },
+    ]).unwrap();
+    let averages: Vec<f64> = result.iter().map(|doc| doc.get_f64("movingAvg").unwrap()).collect();
+    assert_eq!(averages, vec![30.0, 40.0, 50.0, 30.0]);
+}
+
+#[test]
+fn test_densify_and_fill() {
+    let db = prepare_db("test-densify-fill").unwrap();
+    db.collection::<Document>("readings").insert_many(vec![
+        doc! { "sensor": "a", "hour": 0, "temp": 10.0 },
+        doc! { "sensor": "a", "hour": 3, "temp": 16.0 },
+        doc! { "sensor": "b", "hour": 1, "temp": 20.0 },
+        doc! { "sensor": "b", "hour": 2 },
+    ]).unwrap();
+
+    let result = db.collection::<Document>("readings").aggregate(vec![
+        doc! {
+            "$densify": {
+                "field": "hour",
+                "partitionByFields": ["sensor"],
+                "range": { "step": 1, "bounds": "partition" },
+            },
+        },
+        doc! {
+            "$fill": {
+                "partitionByFields": ["sensor"],
+                "sortBy": { "hour": 1 },
+                "output": {
+                    "temp": { "method": "linear" },
+                },
+            },
+        },
+        doc! {
+            "$fill": {
+                "partitionByFields": ["sensor"],
+                "sortBy": { "hour": 1 },
+                "output": {
+                    "temp": { "method": "locf" },
+                },
+            },
+        },
+    ]).unwrap();
+
+    let temps: Vec<(String, f64)> = result
+        .iter()
+        .map(|doc| (doc.get_str("sensor").unwrap().to_string(), doc.get_f64("temp").unwrap()))
+        .collect();
+    assert_eq!(temps, vec![
+        ("a".to_string(), 10.0),
+        ("a".to_string(), 12.0),
+        ("a".to_string(), 14.0),
+        ("a".to_string(), 16.0),
+        ("b".to_string(), 20.0),
+        ("b".to_string(), 20.0),
+    ]);
+}
+
+#[test]
+fn test_group() {
+    let db = prepare_db("test-aggregate-group").unwrap();
+    prepare_orders(&db);
+    db.collection::<Document>("orders").insert_one(doc! {
+        "_id": 4, "item": "almonds", "price": 12, "quantity": 8,
+    }).unwrap();
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! {
+            "$group": {
+                "_id": "$item",
+                "total": { "$sum": "$quantity" },
+                "count": { "$count": {} },
+                "maxPrice": { "$max": "$price" },
+            },
+        },
+    ]).unwrap();
+
+    assert_eq!(result.len(), 3);
+    let almonds = result.iter().find(|doc| doc.get_str("_id").unwrap() == "almonds").unwrap();
+    assert_eq!(almonds.get_i32("total").unwrap(), 10);
+    assert_eq!(almonds.get_i64("count").unwrap(), 2);
+    assert_eq!(almonds.get_i32("maxPrice").unwrap(), 12);
+}
+
+#[test]
+fn test_aggregate_in_session() {
+    let db = prepare_db("test-aggregate-in-session").unwrap();
+    prepare_orders(&db);
+
+    let mut session = db.start_session().unwrap();
+    let orders = db.collection::<Document>("orders");
+    let result = orders.aggregate_with_session(vec![
+        doc! { "$unionWith": "inventory" },
+    ], &mut session).unwrap();
+    assert_eq!(result.len(), 8);
+
+    session.start_transaction(None).unwrap();
+    orders.insert_one_with_session(doc! { "_id": 4, "item": "bread", "price": 3, "quantity": 1 }, &mut session).unwrap();
+    let result = orders.aggregate_with_session(vec![
+        doc! { "$unionWith": { "coll": "inventory" } },
+    ], &mut session).unwrap();
+    assert_eq!(result.len(), 9);
+    session.commit_transaction().unwrap();
+
+    assert_eq!(orders.aggregate(vec![]).unwrap().len(), 4);
+}
+
+#[test]
+fn test_union_with_pipeline() {
+    let db = prepare_db("test-union-with-pipeline").unwrap();
+    db.collection::<Document>("sales").insert_many(vec![
+        doc! { "year": 2023, "amount": 10 },
+        doc! { "year": 2023, "amount": 20 },
+    ]).unwrap();
+    db.collection::<Document>("sales_archive").insert_many(vec![
+        doc! { "year": 2021, "amount": 5 },
+        doc! { "year": 2022, "amount": 7 },
+    ]).unwrap();
+
+    let result = db.collection::<Document>("sales").aggregate(vec![
+        doc! {
+            "$unionWith": {
+                "coll": "sales_archive",
+                "pipeline": [{ "$match": { "year": 2022 } }],
+            },
+        },
+        doc! { "$group": { "_id": null, "total": { "$sum": "$amount" } } },
+    ]).unwrap();
+
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].get_i32("total").unwrap(), 37);
+}
+
+#[test]
+fn test_aggregate_recycled_sessions() {
+    let db = prepare_db("test-aggregate-recycled-sessions").unwrap();
+    let collection = db.collection::<Document>("test");
+    let pipeline = vec![doc! {
+        "$group": { "_id": null, "count": { "$count": {} } },
+    }];
+
+    for i in 0..20i64 {
+        collection.insert_one(doc! { "_id": i }).unwrap();
+        // the recycled session reads the latest commit
+        let result = collection.aggregate(pipeline.clone()).unwrap();
+        assert_eq!(result[0].get_i64("count").unwrap(), i + 1);
+        let result = collection.aggregate(pipeline.clone()).unwrap();
+        assert_eq!(result[0].get_i64("count").unwrap(), i + 1);
+    }
+
+    // the idle sessions don't block the checkpoint
+    db.checkpoint().unwrap();
+    assert_eq!(collection.count_documents().unwrap(), 20);
+}
+
+#[test]
+fn test_sort_project_skip_limit() {
+    let db = prepare_db("test-aggregate-sort-project").unwrap();
+    prepare_orders(&db);
+    db.collection::<Document>("orders").insert_one(doc! {
+        "_id": 4, "item": "almonds", "price": 12, "quantity": 8,
+    }).unwrap();
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$match": { "price": { "$gte": 12 } } },
+        doc! { "$group": { "_id": "$item", "quantity": { "$sum": "$quantity" }, "price": { "$max": "$price" } } },
+        doc! { "$project": { "_id": 0, "item": "$_id", "total": { "$multiply": ["$price", "$quantity"] } } },
+        doc! { "$sort": { "total": -1 } },
+    ]).unwrap();
+    assert_eq!(result, vec![
+        doc! { "item": "almonds", "total": 120 },
+        doc! { "item": "pecans", "total": 20 },
+    ]);
+
+    let result = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$sort": { "price": 1, "_id": -1 } },
+        doc! { "$skip": 1 },
+        doc! { "$limit": 2 },
+        doc! { "$project": { "price": 0, "quantity": 0 } },
+    ]).unwrap();
+    assert_eq!(result, vec![
+        doc! { "_id": 4, "item": "almonds" },
+        doc! { "_id": 1, "item": "almonds" },
+    ]);
+
+    let err = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$limit": -1 },
+    ]).unwrap_err();
+    assert!(matches!(err, DbErr::ValidationError(_)));
+    let err = db.collection::<Document>("orders").aggregate(vec![
+        doc! { "$project": { "price": 0, "total": "$price" } },
+    ]).unwrap_err();
+    assert!(matches!(err, DbErr::ValidationError(_)));
+}
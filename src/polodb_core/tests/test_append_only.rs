@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::time::Duration;
+use polodb_core::{Config, CreateCollectionOptions, Database, DbErr};
+use polodb_core::bson::{doc, Bson, Document};
+use polodb_core::clock::MockClock;
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn create_events(db: &Database) {
+    db.create_collection_with_options("events", CreateCollectionOptions {
+        append_only: true,
+        ..Default::default()
+    }).unwrap();
+}
+
+#[test]
+fn test_append_only_insert_order() {
+    let db_path = mk_db_path("test-append-only-order");
+    {
+        let db = prepare_db("test-append-only-order").unwrap();
+        create_events(&db);
+
+        let events = db.collection::<Document>("events");
+        for seq in 0..2000 {
+            events.insert_one(doc! { "seq": seq, "kind": "click" }).unwrap();
+        }
+    }
+
+    let db = Database::open_file(&db_path).unwrap();
+    let events = db.collection::<Document>("events");
+    assert_eq!(events.count_documents().unwrap(), 2000);
+
+    let seqs: Vec<i32> = events.find(None).unwrap()
+        .map(|doc| doc.unwrap().get_i32("seq").unwrap())
+        .collect();
+    assert_eq!(seqs, (0..2000).collect::<Vec<i32>>());
+
+    // the option is persisted
+    events.insert_one(doc! { "seq": 2000 }).unwrap();
+    let result = events.update_many(doc! { "seq": 2000 }, doc! { "$set": { "seq": 0 } });
+    assert!(matches!(result, Err(DbErr::AppendOnlyCollection(name)) if name == "events"));
+}
+
+#[test]
+fn test_append_only_keys() {
+    let db = Database::open_memory().unwrap();
+    create_events(&db);
+
+    let events = db.collection::<Document>("events");
+    events.insert_many((0..1000).map(|i| doc! { "_id": i, "value": i })).unwrap();
+
+    let result = events.insert_one(doc! { "_id": 500 });
+    assert!(matches!(result, Err(DbErr::AppendOutOfOrder(_))));
+    let result = events.insert_one(doc! { "_id": 999 });
+    assert!(matches!(result, Err(DbErr::AppendOutOfOrder(_))));
+    // an ObjectId can't be generated after the integers
+    let result = events.insert_one(doc! { "value": 1000 });
+    assert!(matches!(result, Err(DbErr::AppendOutOfOrder(_))));
+
+    events.insert_one(doc! { "_id": 1000, "value": 1000 }).unwrap();
+    assert_eq!(events.count_documents().unwrap(), 1001);
+
+    let range: Vec<Document> = events.find(doc! { "_id": { "$gte": 995 } }).unwrap()
+        .map(|doc| doc.unwrap())
+        .collect();
+    assert_eq!(range.len(), 6);
+    assert_eq!(range[0].get_i32("value").unwrap(), 995);
+}
+
+#[test]
+fn test_append_only_writes() {
+    let db = Database::open_memory().unwrap();
+    create_events(&db);
+
+    let events = db.collection::<Document>("events");
+    events.insert_many((0..10).map(|i| doc! { "_id": i })).unwrap();
+
+    let result = events.update_one(doc! { "_id": 1 }, doc! { "$set": { "value": 1 } });
+    assert!(matches!(result, Err(DbErr::AppendOnlyCollection(_))));
+    let result = events.delete_one(doc! { "_id": 1 });
+    assert!(matches!(result, Err(DbErr::AppendOnlyCollection(_))));
+    let result = events.delete_many(doc! { "_id": 100 });
+    assert!(matches!(result, Err(DbErr::AppendOnlyCollection(_))));
+    assert_eq!(events.count_documents().unwrap(), 10);
+
+    // the log can be truncated as a whole
+    assert_eq!(events.truncate().unwrap().deleted_count, 10);
+    assert_eq!(events.count_documents().unwrap(), 0);
+    events.insert_one(doc! { "_id": 0 }).unwrap();
+}
+
+#[test]
+fn test_append_only_clock_back() {
+    let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+    let db = Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        ..Default::default()
+    }).unwrap();
+    create_events(&db);
+
+    let events = db.collection::<Document>("events");
+    let first = events.insert_one(doc! { "seq": 0 }).unwrap().inserted_id;
+
+    // the generated id is still after the last one
+    clock.set(Duration::from_secs(1_600_000_000));
+    let second = events.insert_one(doc! { "seq": 1 }).unwrap().inserted_id;
+    match (&first, &second) {
+        (Bson::ObjectId(first), Bson::ObjectId(second)) => assert!(second > first),
+        _ => panic!("unexpected ids: {} {}", first, second),
+    }
+
+    let seqs: Vec<i32> = events.find(None).unwrap()
+        .map(|doc| doc.unwrap().get_i32("seq").unwrap())
+        .collect();
+    assert_eq!(seqs, vec![0, 1]);
+}
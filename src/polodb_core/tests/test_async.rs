@@ -0,0 +1,60 @@
+use std::num::NonZeroUsize;
+use polodb_core::{AsyncDatabase, DbErr};
+use polodb_core::bson::{Document, doc};
+use polodb_core::test_utils::block_on;
+
+mod common;
+
+use common::prepare_db;
+
+#[test]
+fn test_async_crud() {
+    let db = AsyncDatabase::new(prepare_db("test-async-crud").unwrap());
+    block_on(async move {
+        db.create_collection("books").await.unwrap();
+        let books = db.collection::<Document>("books");
+        books.insert_many(vec![
+            doc! { "_id": 1, "title": "1984", "year": 1949 },
+            doc! { "_id": 2, "title": "Animal Farm", "year": 1945 },
+        ]).await.unwrap();
+        books.insert_one(doc! { "_id": 3, "title": "Dune", "year": 1965 }).await.unwrap();
+        assert_eq!(books.count_documents().await.unwrap(), 3);
+
+        let result = books.update_one(doc! { "_id": 3 }, doc! { "$set": { "year": 1966 } }).await.unwrap();
+        assert_eq!(result.modified_count, 1);
+        let book = books.find_one(doc! { "_id": 3 }).await.unwrap().unwrap();
+        assert_eq!(book.get_i32("year").unwrap(), 1966);
+
+        let old = books.find_many(doc! { "year": { "$lt": 1950 } }).await.unwrap();
+        assert_eq!(old.len(), 2);
+        let result = books.delete_many(doc! { "year": { "$lt": 1950 } }).await.unwrap();
+        assert_eq!(result.deleted_count, 2);
+
+        assert_eq!(db.list_collection_names().await.unwrap(), vec!["books".to_string()]);
+        // the blocking handle sees the same data
+        assert_eq!(db.database().collection::<Document>("books").count_documents().unwrap(), 1);
+    });
+}
+
+#[test]
+fn test_async_concurrent_tasks() {
+    let db = AsyncDatabase::with_threads(prepare_db("test-async-concurrent").unwrap(), NonZeroUsize::new(2).unwrap());
+    let items = db.collection::<Document>("items");
+    let tasks: Vec<_> = (0..50)
+        .map(|i| items.insert_one(doc! { "_id": i }))
+        .collect();
+    block_on(async move {
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(items.count_documents().await.unwrap(), 50);
+
+        let count = db.run(|db| db.collection::<Document>("items").count_documents()).await.unwrap();
+        assert_eq!(count, 50);
+
+        let result = db.run(|_| -> polodb_core::DbResult<()> { panic!("boom") }).await;
+        assert!(matches!(result, Err(DbErr::TaskPanicked)));
+        // the threads survive the panic
+        assert_eq!(items.count_documents().await.unwrap(), 50);
+    });
+}
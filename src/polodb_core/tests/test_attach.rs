@@ -0,0 +1,107 @@
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{doc, Bson, Document};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+#[test]
+fn test_attach_and_query() {
+    let archive_path = mk_db_path("test-attach-archive");
+    let _ = std::fs::remove_file(&archive_path);
+    {
+        let archive = Database::open_file(&archive_path).unwrap();
+        archive.collection::<Document>("orders").insert_many(vec![
+            doc! { "order": 1, "year": 2021 },
+            doc! { "order": 2, "year": 2022 },
+        ]).unwrap();
+    }
+
+    let db = prepare_db("test-attach-main").unwrap();
+    db.collection::<Document>("orders").insert_one(doc! { "order": 3, "year": 2023 }).unwrap();
+
+    db.attach("archive", &archive_path).unwrap();
+    assert_eq!(db.list_attached(), vec!["archive".to_string()]);
+
+    let archived = db.collection::<Document>("archive.orders");
+    assert_eq!(archived.count_documents().unwrap(), 2);
+    assert_eq!(db.collection::<Document>("orders").count_documents().unwrap(), 1);
+
+    archived.insert_one(doc! { "order": 0, "year": 2020 }).unwrap();
+    let result = archived.find_many(doc! { "year": 2020 }).unwrap();
+    assert_eq!(result.len(), 1);
+
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "archive.orders",
+    })).unwrap();
+    assert_eq!(result.value, Bson::Int64(3));
+
+    let err = db.attach("archive", &archive_path).unwrap_err();
+    assert!(matches!(err, DbErr::AttachAliasAlreadyExists(_)));
+
+    db.detach("archive").unwrap();
+    assert!(matches!(db.detach("archive").unwrap_err(), DbErr::AttachAliasNotFound(_)));
+
+    // without the alias, the name is a normal collection of the main database
+    assert_eq!(db.collection::<Document>("archive.orders").count_documents().unwrap(), 0);
+}
+
+#[test]
+fn test_attach_illegal_alias() {
+    let db = Database::open_memory().unwrap();
+    let other = Database::open_memory().unwrap();
+    let err = db.attach_database("a.b", other).unwrap_err();
+    assert!(matches!(err, DbErr::IllegalAttachAlias(_)));
+    assert!(db.list_attached().is_empty());
+}
+
+#[test]
+fn test_archive_collection() {
+    let archive_path = mk_db_path("test-archive-cold");
+    let _ = std::fs::remove_file(&archive_path);
+
+    let db = prepare_db("test-archive-main").unwrap();
+    let orders = db.collection::<Document>("orders");
+    orders.insert_many((0..100).map(|i| doc! { "_id": i, "year": 2000 + i % 10 })).unwrap();
+
+    db.attach("cold", &archive_path).unwrap();
+    db.archive_collection("orders", "cold").unwrap();
+
+    // the stub is kept in the catalog, the operations are routed to the archive
+    assert_eq!(db.list_collection_names().unwrap(), vec!["orders".to_string()]);
+    assert_eq!(orders.count_documents().unwrap(), 100);
+    assert_eq!(orders.find_many(doc! { "year": 2003 }).unwrap().len(), 10);
+    orders.insert_one(doc! { "_id": 100, "year": 2010 }).unwrap();
+    assert_eq!(db.collection::<Document>("cold.orders").count_documents().unwrap(), 101);
+
+    db.detach("cold").unwrap();
+    let err = orders.count_documents().unwrap_err();
+    assert!(matches!(err, DbErr::CollectionArchived(_)));
+    let err = orders.insert_one(doc! { "_id": 101 }).unwrap_err();
+    assert!(matches!(err, DbErr::CollectionArchived(_)));
+
+    db.attach("cold", &archive_path).unwrap();
+    assert_eq!(orders.count_documents().unwrap(), 101);
+
+    orders.drop().unwrap();
+    assert!(db.list_collection_names().unwrap().is_empty());
+    assert_eq!(db.collection::<Document>("cold.orders").count_documents().unwrap(), 0);
+}
+
+#[test]
+fn test_archive_collection_errors() {
+    let db = Database::open_memory().unwrap();
+    db.collection::<Document>("logs").insert_one(doc! { "level": "info" }).unwrap();
+
+    let err = db.archive_collection("logs", "cold").unwrap_err();
+    assert!(matches!(err, DbErr::AttachAliasNotFound(_)));
+
+    db.attach_database("cold", Database::open_memory().unwrap()).unwrap();
+    let err = db.archive_collection("missing", "cold").unwrap_err();
+    assert!(matches!(err, DbErr::CollectionNotFound(_)));
+
+    db.archive_collection("logs", "cold").unwrap();
+    let err = db.archive_collection("logs", "cold").unwrap_err();
+    assert!(matches!(err, DbErr::CollectionArchived(_)));
+}
@@ -0,0 +1,60 @@
+use std::thread;
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{Document, doc};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+#[test]
+fn test_backup_to() {
+    let backup_path = mk_db_path("test-backup-to-copy");
+    let _ = std::fs::remove_file(&backup_path);
+
+    let db = prepare_db("test-backup-to").unwrap();
+    let collection = db.collection::<Document>("items");
+    for i in 0..2000 {
+        collection.insert_one(doc! { "_id": i, "name": format!("item-{}", i) }).unwrap();
+    }
+    db.checkpoint().unwrap();
+    collection.update_many(doc! { "_id": { "$lt": 100 } }, doc! { "$set": { "updated": true } }).unwrap();
+
+    // the writes during the backup are not in it
+    let writer = db.clone();
+    let handle = thread::spawn(move || {
+        let collection = writer.collection::<Document>("items");
+        for i in 2000..2500 {
+            collection.insert_one(doc! { "_id": i }).unwrap();
+        }
+    });
+    db.backup_to(&backup_path).unwrap();
+    handle.join().unwrap();
+    assert_eq!(collection.count_documents().unwrap(), 2500);
+
+    let backup = Database::open_file(&backup_path).unwrap();
+    let items = backup.collection::<Document>("items");
+    let count = items.count_documents().unwrap();
+    assert!((2000..=2500).contains(&count));
+    let doc = items.find_one(doc! { "_id": 50 }).unwrap().unwrap();
+    assert!(doc.get_bool("updated").unwrap());
+    let ids: Vec<i32> = items.find_many(doc! { "_id": { "$gte": 2000 } }).unwrap()
+        .iter()
+        .map(|doc| doc.get_i32("_id").unwrap())
+        .collect();
+    assert_eq!(ids, (2000..(count as i32)).collect::<Vec<i32>>());
+
+    // the checkpoint is not blocked after the backup
+    db.checkpoint().unwrap();
+}
+
+#[test]
+fn test_backup_to_existing_file() {
+    let backup_path = mk_db_path("test-backup-to-existing-copy");
+    std::fs::write(&backup_path, b"not a backup").unwrap();
+
+    let db = prepare_db("test-backup-to-existing").unwrap();
+    db.collection::<Document>("items").insert_one(doc! { "_id": 1 }).unwrap();
+    let result = db.backup_to(&backup_path);
+    assert!(matches!(result, Err(DbErr::IOErr(_))));
+    assert_eq!(std::fs::read(&backup_path).unwrap(), b"not a backup");
+}
@@ -1,9 +1,11 @@
+use std::num::NonZeroUsize;
 use polodb_core::bson::{Document, doc};
-use polodb_core::{Database, Collection};
+use polodb_core::{Config, Database, Collection, CollectionOptions, DbErr, DeleteOptions, FindOptions, IndexOptions, PageCachePolicy};
 mod common;
 
 use common::{
     prepare_db,
+    prepare_db_with_config,
     create_file_and_return_db_with_items,
     create_memory_and_return_db_with_items,
 };
@@ -114,3 +116,203 @@ fn test_create_collection_and_find_by_pkey() {
         assert_eq!(result.len(), 1);
     });
 }
+
+#[test]
+fn test_collection_stats() {
+    vec![
+        prepare_db("test-collection-stats").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        let stats = collection.stats().unwrap();
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.data_size, 0);
+
+        for i in 0..100 {
+            collection.insert_one(doc! {
+                "_id": i,
+                "content": i.to_string(),
+            }).unwrap();
+        }
+        let stats = collection.stats().unwrap();
+        assert_eq!(stats.count, 100);
+        assert_eq!(collection.count_documents().unwrap(), 100);
+        let inserted_size = stats.data_size;
+        assert!(inserted_size > 0);
+
+        collection.update_many(doc! {}, doc! {
+            "$set": { "content": "a longer content" },
+        }).unwrap();
+        let stats = collection.stats().unwrap();
+        assert_eq!(stats.count, 100);
+        assert!(stats.data_size > inserted_size);
+
+        collection.delete_many(doc! {
+            "_id": { "$lt": 40 },
+        }).unwrap();
+        assert_eq!(collection.count_documents().unwrap(), 60);
+
+        collection.delete_many(doc! {}).unwrap();
+        let stats = collection.stats().unwrap();
+        assert_eq!(stats.count, 0);
+        assert_eq!(stats.data_size, 0);
+    });
+}
+
+#[test]
+fn test_collection_stats_metrics() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_one(doc! { "_id": 0 }).unwrap();
+    assert!(collection.stats().unwrap().metrics.is_none());
+
+    db.metrics().enable();
+    for i in 1..=10 {
+        collection.insert_one(doc! {
+            "_id": i,
+            "content": "content",
+        }).unwrap();
+    }
+    collection.update_one(doc! { "_id": 1 }, doc! {
+        "$set": { "content": "a longer content" },
+    }).unwrap();
+    let docs = collection.find_many(doc! {}).unwrap();
+    assert_eq!(docs.len(), 11);
+    db.collection::<Document>("other").insert_one(doc! { "_id": 1 }).unwrap();
+
+    let metrics = collection.stats().unwrap().metrics.unwrap();
+    assert_eq!(metrics.writes, 11);
+    assert_eq!(metrics.reads, 1);
+    assert_eq!(metrics.operation_count(), 12);
+    assert!(metrics.bytes_written > 0);
+    assert!(metrics.bytes_read > 0);
+    assert_eq!(metrics.average_latency().as_micros() as u64, metrics.total_latency_micros / 12);
+
+    let other = db.collection::<Document>("other").stats().unwrap().metrics.unwrap();
+    assert_eq!(other.writes, 1);
+    assert_eq!(other.reads, 0);
+    assert!(db.collection::<Document>("none").stats().unwrap().metrics.is_none());
+}
+
+#[test]
+fn test_collection_stats_rollback() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_one(doc! { "_id": 0 }).unwrap();
+
+    let mut session = db.start_session().unwrap();
+    session.start_transaction(None).unwrap();
+    collection.insert_one_with_session(doc! { "_id": 1 }, &mut session).unwrap();
+    assert_eq!(collection.count_documents_with_session(&mut session).unwrap(), 2);
+    session.abort_transaction().unwrap();
+
+    assert_eq!(collection.count_documents().unwrap(), 1);
+}
+
+#[test]
+fn test_collection_stats_transaction() {
+    vec![
+        prepare_db("test-collection-stats-transaction").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+
+        let mut session = db.start_session().unwrap();
+        session.start_transaction(None).unwrap();
+        for i in 0..1000 {
+            collection.insert_one_with_session(doc! {
+                "_id": i,
+                "content": i.to_string(),
+            }, &mut session).unwrap();
+        }
+        collection.delete_many_with_session(doc! {
+            "_id": { "$lt": 100 },
+        }, &mut session).unwrap();
+        session.commit_transaction().unwrap();
+
+        assert_eq!(collection.count_documents().unwrap(), 900);
+        assert_eq!(collection.find_many(doc! {}).unwrap().len(), 900);
+    });
+}
+
+#[test]
+fn test_collection_options() {
+    let db = Database::open_memory().unwrap();
+    let posts = db.collection::<Document>("posts").with_options(CollectionOptions {
+        find: FindOptions {
+            projection: Some(doc! { "title": 1 }),
+            ..Default::default()
+        },
+        delete: DeleteOptions {
+            batch_size: Some(8),
+        },
+    });
+    assert_eq!(posts.options().delete.batch_size, Some(8));
+    posts.insert_many((0..20).map(|i| doc! { "_id": i, "title": format!("post {}", i), "body": "..." })).unwrap();
+
+    let post = posts.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    assert_eq!(post, doc! { "_id": 1, "title": "post 1" });
+
+    // the options of a find take precedence
+    let post = posts.find_one_with_options(doc! { "_id": 1 }, FindOptions {
+        projection: Some(doc! { "_id": 0, "body": 1 }),
+        ..Default::default()
+    }).unwrap().unwrap();
+    assert_eq!(post, doc! { "body": "..." });
+
+    // the other handles are not changed
+    let post = db.collection::<Document>("posts").find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    assert_eq!(post.len(), 3);
+
+    let result = posts.delete_many(doc! { "_id": { "$gte": 10 } }).unwrap();
+    assert_eq!(result.deleted_count, 10);
+    assert_eq!(posts.count_documents().unwrap(), 10);
+}
+
+#[test]
+fn test_collection_pin() {
+    let db = prepare_db_with_config("test-collection-pin", Config {
+        page_cache_size: NonZeroUsize::new(32).unwrap(),
+        page_cache_policy: PageCachePolicy::Lru,
+        ..Default::default()
+    }).unwrap();
+    let countries = db.collection::<Document>("countries");
+    countries.create_index(doc! { "name": 1 }, IndexOptions::default()).unwrap();
+    for i in 0..20 {
+        countries.insert_one(doc! { "_id": i, "name": format!("country-{}", i) }).unwrap();
+    }
+    let items = db.collection::<Document>("items");
+    for i in 0..5000 {
+        items.insert_one(doc! { "_id": i, "content": "x".repeat(100) }).unwrap();
+    }
+    db.checkpoint().unwrap();
+
+    assert!(matches!(items.pin(), Err(DbErr::PinnedPagesExceeded(_, 16))));
+    let result = countries.pin().unwrap();
+    // the btree and the data of the collection, and the btree of the index
+    assert!(result.pages >= 3);
+    assert_eq!(result.total_pages, result.pages);
+
+    // the pages missed by a find after a scan of the other collection
+    let metrics = db.metrics();
+    metrics.enable();
+    let missed_pages = || {
+        items.find_many(None).unwrap();
+        let before = metrics.data();
+        assert_eq!(countries.find_many(doc! { "name": "country-3" }).unwrap().len(), 1);
+        let after = metrics.data();
+        (after.page_fetch_count - before.page_fetch_count) - (after.page_hit_count - before.page_hit_count)
+    };
+    let pinned_missed = missed_pages();
+    countries.unpin().unwrap();
+    assert!(pinned_missed < missed_pages());
+
+    // the dropped collection is unpinned
+    countries.pin().unwrap();
+    countries.drop().unwrap();
+    let small = db.collection::<Document>("small");
+    assert!(matches!(small.pin(), Err(DbErr::CollectionNotFound(_))));
+    small.insert_one(doc! { "_id": 1 }).unwrap();
+    let result = small.pin().unwrap();
+    assert_eq!(result.total_pages, result.pages);
+}
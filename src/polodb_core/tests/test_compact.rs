@@ -0,0 +1,95 @@
+use polodb_core::{Database, DbErr, IndexOptions};
+use polodb_core::bson::{Bson, Document, doc};
+use polodb_core::explain::{ExplainVerbosity, STAGE_IXSCAN};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn insert_and_delete(db: &Database) {
+    let collection = db.collection::<Document>("items");
+    collection.create_index(doc! { "group": 1 }, IndexOptions {
+        name: Some("by_group".into()),
+        ..Default::default()
+    }).unwrap();
+    for i in 0..3000 {
+        collection.insert_one(doc! {
+            "_id": i,
+            "group": i % 10,
+            "content": "a content to take some space in the pages",
+        }).unwrap();
+    }
+    collection.delete_many(doc! { "_id": { "$gte": 100 } }).unwrap();
+}
+
+fn check_items(db: &Database) {
+    let collection = db.collection::<Document>("items");
+    assert_eq!(collection.count_documents().unwrap(), 100);
+    assert_eq!(collection.stats().unwrap().count, 100);
+    let doc = collection.find_one(doc! { "_id": 42 }).unwrap().unwrap();
+    assert_eq!(doc.get_i32("group").unwrap(), 2);
+
+    let explain = collection.explain(doc! { "group": 3 }, ExplainVerbosity::QueryPlanner).unwrap();
+    assert_eq!(explain.stages, vec![STAGE_IXSCAN.to_string()]);
+    assert_eq!(collection.find_many(doc! { "group": 3 }).unwrap().len(), 10);
+}
+
+#[test]
+fn test_compact_file() {
+    let db_path = mk_db_path("test-compact-file");
+    {
+        let db = prepare_db("test-compact-file").unwrap();
+        insert_and_delete(&db);
+        db.checkpoint().unwrap();
+        let file_size = std::fs::metadata(&db_path).unwrap().len();
+
+        let result = db.compact().unwrap();
+        assert!(result.size_after < result.size_before);
+        assert!(std::fs::metadata(&db_path).unwrap().len() < file_size);
+        check_items(&db);
+
+        // the pages are allocated after the compacted ones
+        db.collection::<Document>("items").insert_one(doc! { "_id": 5000, "group": 3 }).unwrap();
+        db.collection::<Document>("items").delete_one(doc! { "_id": 5000 }).unwrap();
+    }
+
+    let db = Database::open_file(&db_path).unwrap();
+    check_items(&db);
+}
+
+#[test]
+fn test_compact_memory() {
+    let db = Database::open_memory().unwrap();
+    insert_and_delete(&db);
+    db.create_collection("empty").unwrap();
+
+    let result = db.compact().unwrap();
+    assert!(result.size_after < result.size_before);
+    check_items(&db);
+    assert_eq!(db.list_collection_names().unwrap().len(), 2);
+}
+
+#[test]
+fn test_compact_busy() {
+    let db = Database::open_memory().unwrap();
+    insert_and_delete(&db);
+
+    let session = db.start_session().unwrap();
+    assert!(matches!(db.compact(), Err(DbErr::Busy)));
+    drop(session);
+    db.compact().unwrap();
+    check_items(&db);
+}
+
+#[test]
+fn test_compact_request() {
+    let db = prepare_db("test-compact-request").unwrap();
+    insert_and_delete(&db);
+
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "Compact",
+    })).unwrap();
+    let result = result.value.as_document().unwrap().clone();
+    assert!(result.get_i64("sizeAfter").unwrap() < result.get_i64("sizeBefore").unwrap());
+    check_items(&db);
+}
@@ -0,0 +1,133 @@
+use polodb_core::{Compression, Config, CreateCollectionOptions, Database};
+use polodb_core::bson::{doc, Bson, Document};
+
+mod common;
+
+use common::{mk_db_path, prepare_db, prepare_db_with_config};
+
+fn article(index: i32) -> Document {
+    doc! {
+        "_id": index,
+        "content": format!("article {} ", index).repeat(100),
+    }
+}
+
+fn test_codec(name: &str, compression: Compression) {
+    let db_path = mk_db_path(name);
+    {
+        let db = prepare_db(name).unwrap();
+        db.create_collection_with_options("articles", CreateCollectionOptions {
+            compression,
+            ..Default::default()
+        }).unwrap();
+
+        let collection = db.collection::<Document>("articles");
+        collection.insert_many((0..100).map(article).collect::<Vec<Document>>()).unwrap();
+
+        // collections without codec are not affected
+        db.collection::<Document>("hot").insert_one(doc! { "_id": 1, "value": 1 }).unwrap();
+    }
+
+    let db = Database::open_file(&db_path).unwrap();
+    let collection = db.collection::<Document>("articles");
+    assert_eq!(collection.count_documents().unwrap(), 100);
+
+    let result = collection.find_one(doc! { "_id": 50 }).unwrap().unwrap();
+    assert_eq!(result, article(50));
+
+    let hot = db.collection::<Document>("hot").find_one(None).unwrap().unwrap();
+    assert_eq!(hot.get_i32("value").unwrap(), 1);
+
+    collection.update_many(doc! { "_id": 10 }, doc! {
+        "$set": { "content": "updated ".repeat(100) },
+    }).unwrap();
+    let result = collection.find_one(doc! { "_id": 10 }).unwrap().unwrap();
+    assert_eq!(result.get_str("content").unwrap(), "updated ".repeat(100));
+
+    collection.delete_many(doc! { "_id": 20 }).unwrap();
+    assert!(collection.find_one(doc! { "_id": 20 }).unwrap().is_none());
+    assert_eq!(collection.count_documents().unwrap(), 99);
+}
+
+#[test]
+fn test_lz4_collection() {
+    test_codec("test-compression-lz4", Compression::Lz4);
+}
+
+#[test]
+fn test_zstd_collection() {
+    test_codec("test-compression-zstd", Compression::Zstd { level: 3 });
+}
+
+#[test]
+fn test_create_compressed_collection_by_command() {
+    let db = Database::open_memory().unwrap();
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CreateCollection",
+        "ns": "logs",
+        "options": {
+            "compression": { "codec": "zstd", "level": 1 },
+        },
+    })).unwrap();
+    assert_eq!(result.value, Bson::Boolean(true));
+
+    let collection = db.collection::<Document>("logs");
+    collection.insert_one(article(1)).unwrap();
+    assert_eq!(collection.find_one(None).unwrap().unwrap(), article(1));
+}
+
+
+#[test]
+fn test_page_compression() {
+    let config = Config {
+        compression: Compression::Lz4,
+        ..Default::default()
+    };
+    let db_path = mk_db_path("test-page-compression");
+    {
+        let db = prepare_db("test-page-compression").unwrap();
+        let collection = db.collection::<Document>("articles");
+        collection.insert_many((0..50).map(article).collect::<Vec<Document>>()).unwrap();
+        assert_eq!(db.dump().unwrap().incompat_features, 0);
+    }
+
+    // the pages written before are read as they are
+    {
+        let db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+        assert_ne!(db.dump().unwrap().incompat_features, 0);
+        let collection = db.collection::<Document>("articles");
+        assert_eq!(collection.find_one(doc! { "_id": 10 }).unwrap().unwrap(), article(10));
+        collection.insert_many((50..200).map(article).collect::<Vec<Document>>()).unwrap();
+        db.checkpoint().unwrap();
+    }
+
+    let bytes = std::fs::read(&db_path).unwrap();
+    assert!(bytes.chunks(4096).skip(1).any(|page| page[0] == 0xFE));
+
+    // the compressed pages are read without the config
+    let db = Database::open_file(&db_path).unwrap();
+    let collection = db.collection::<Document>("articles");
+    assert_eq!(collection.count_documents().unwrap(), 200);
+    assert_eq!(collection.find_one(doc! { "_id": 150 }).unwrap().unwrap(), article(150));
+    collection.delete_many(doc! { "_id": { "$lt": 100 } }).unwrap();
+    assert_eq!(collection.count_documents().unwrap(), 100);
+}
+
+#[test]
+fn test_page_compression_new_file() {
+    let config = Config {
+        compression: Compression::Zstd { level: 3 },
+        ..Default::default()
+    };
+    let db_path = mk_db_path("test-page-compression-new-file");
+    {
+        let db = prepare_db_with_config("test-page-compression-new-file", config.clone()).unwrap();
+        let collection = db.collection::<Document>("articles");
+        collection.insert_many((0..100).map(article).collect::<Vec<Document>>()).unwrap();
+    }
+
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let collection = db.collection::<Document>("articles");
+    assert_eq!(collection.count_documents().unwrap(), 100);
+    assert_eq!(collection.find_one(doc! { "_id": 99 }).unwrap().unwrap(), article(99));
+}
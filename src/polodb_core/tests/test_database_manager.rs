@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::num::{NonZeroU32, NonZeroUsize};
+use polodb_core::{Config, DatabaseManager, DatabaseManagerConfig, DbErr};
+use polodb_core::bson::{doc, DateTime, Document};
+
+mod common;
+
+use common::mk_db_path;
+
+#[test]
+fn test_manager_open_many() {
+    let manager = DatabaseManager::new(DatabaseManagerConfig {
+        page_cache_size: NonZeroUsize::new(64).unwrap(),
+        ..Default::default()
+    });
+
+    for i in 0..3 {
+        let name = format!("test-manager-{}", i);
+        let path = mk_db_path(&name);
+        let _ = std::fs::remove_file(&path);
+
+        let db = manager.open(&name, &path).unwrap();
+        let collection = db.collection::<Document>("test");
+        for j in 0..100 {
+            collection.insert_one(doc! {
+                "tenant": i,
+                "index": j,
+            }).unwrap();
+        }
+    }
+
+    let mut names = manager.database_names();
+    names.sort();
+    assert_eq!(names, vec!["test-manager-0", "test-manager-1", "test-manager-2"]);
+
+    for i in 0..3 {
+        let db = manager.get(&format!("test-manager-{}", i)).unwrap();
+        let result = db.collection::<Document>("test").find_many(doc! {
+            "tenant": i,
+        }).unwrap();
+        assert_eq!(result.len(), 100);
+    }
+
+    assert!(manager.close("test-manager-0"));
+    assert!(manager.get("test-manager-0").is_none());
+}
+
+#[test]
+fn test_manager_maintenance_task() {
+    let manager = DatabaseManager::new(Default::default());
+    let path = mk_db_path("test-manager-task");
+    let _ = std::fs::remove_file(&path);
+    manager.open("task", &path).unwrap();
+
+    let counter = Arc::new(AtomicUsize::new(0));
+    let task_counter = counter.clone();
+    manager.add_maintenance_task(move |name, _db| {
+        assert_eq!(name, "task");
+        task_counter.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    });
+
+    manager.run_maintenance();
+    assert_eq!(counter.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_manager_page_size() {
+    let path_4k = mk_db_path("test-manager-page-size-4k");
+    let path_8k = mk_db_path("test-manager-page-size-8k");
+    let _ = std::fs::remove_file(&path_4k);
+    let _ = std::fs::remove_file(&path_8k);
+    let config_8k = Config {
+        page_size: NonZeroU32::new(8192).unwrap(),
+        ..Default::default()
+    };
+
+    {
+        let manager = DatabaseManager::new(Default::default());
+        manager.open("4k", &path_4k).unwrap();
+        let err = manager.open_with_config("8k", &path_8k, config_8k.clone()).err().unwrap();
+        assert!(matches!(err, DbErr::PageSizeMismatch(4096, 8192)));
+    }
+
+    let manager = DatabaseManager::new(Default::default());
+    let db = manager.open_with_config("8k", &path_8k, config_8k.clone()).unwrap();
+    db.collection::<Document>("test").insert_one(doc! { "name": "8k" }).unwrap();
+
+    // the file created with the pages of 4 KiB
+    let err = manager.open_with_config("4k", &path_4k, config_8k).err().unwrap();
+    assert!(matches!(err, DbErr::PageSizeMismatch(8192, 4096)));
+}
+
+#[test]
+fn test_manager_expire_documents() {
+    let manager = DatabaseManager::new(DatabaseManagerConfig {
+        expire_documents: true,
+        ..Default::default()
+    });
+    let path = mk_db_path("test-manager-expire");
+    let _ = std::fs::remove_file(&path);
+    let db = manager.open("expire", &path).unwrap();
+
+    let sessions = db.collection::<Document>("sessions");
+    sessions.insert_one(doc! { "_id": 1, "expireAt": DateTime::from_millis(0) }).unwrap();
+    sessions.insert_one(doc! { "_id": 2, "expireAt": DateTime::MAX }).unwrap();
+    sessions.insert_one(doc! { "_id": 3 }).unwrap();
+    db.collection::<Document>("logs").insert_one(doc! { "expireAt": DateTime::from_millis(0) }).unwrap();
+
+    manager.run_maintenance();
+    assert_eq!(sessions.count_documents().unwrap(), 2);
+    assert_eq!(db.collection::<Document>("logs").count_documents().unwrap(), 0);
+}
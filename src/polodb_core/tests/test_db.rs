@@ -1,6 +1,13 @@
-use polodb_core::{Database, Config, DbErr};
-use polodb_core::bson::{doc, Document};
+use polodb_core::{
+    Database, Config, DbErr, GrowthStrategy, JournalThrottle, RecoveryState,
+    Compression, CreateCollectionOptions, HandleOptions, Priority,
+};
+use polodb_core::bson::{doc, Bson, Document};
+use polodb_core::clock::MockClock;
 use std::env;
+use std::sync::Arc;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::time::{Duration, Instant};
 
 mod common;
 
@@ -8,6 +15,7 @@ use common::{
     create_file_and_return_db_with_items,
     create_memory_and_return_db_with_items,
     mk_db_path,
+    mk_journal_path,
 };
 
 static TEST_SIZE: usize = 1000;
@@ -73,3 +81,528 @@ fn test_multi_threads() {
     assert_eq!(one.get("content").unwrap().as_str().unwrap(), "Hello");
 }
 
+#[test]
+fn test_clone_handles() {
+    use std::thread;
+
+    let db = Database::open_memory().unwrap();
+    let handles: Vec<_> = (0..4)
+        .map(|index| {
+            let db = db.clone();
+            thread::spawn(move || {
+                let collection = db.collection::<Document>("test");
+                for i in 0..100 {
+                    collection.insert_one(doc! { "thread": index, "i": i }).unwrap();
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 400);
+
+    let reader = db.with_options(HandleOptions {
+        read_only: true,
+        ..Default::default()
+    });
+    assert!(reader.handle_options().read_only);
+    assert!(!db.handle_options().read_only);
+    assert_eq!(reader.collection::<Document>("test").count_documents().unwrap(), 400);
+    let err = reader.collection::<Document>("test").delete_many(doc! {}).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+
+    // the database is kept open by the other handle
+    drop(db);
+    let err = reader.collection::<Document>("test").insert_one(doc! {}).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+    assert_eq!(reader.collection::<Document>("test").count_documents().unwrap(), 400);
+}
+
+#[test]
+fn test_background_priority() {
+    use std::thread;
+
+    let db = Database::open_memory().unwrap();
+    let importer = db.with_options(HandleOptions {
+        priority: Priority::Background,
+        ..Default::default()
+    });
+    assert_eq!(importer.handle_options().priority, Priority::Background);
+    assert_eq!(db.handle_options().priority, Priority::Interactive);
+
+    let import = thread::spawn(move || {
+        let collection = importer.collection::<Document>("logs");
+        for batch in 0..20 {
+            let docs: Vec<Document> = (0..50)
+                .map(|i| doc! { "batch": batch, "i": i })
+                .collect();
+            collection.insert_many(docs).unwrap();
+        }
+    });
+
+    let collection = db.collection::<Document>("users");
+    for i in 0..100 {
+        collection.insert_one(doc! { "_id": i }).unwrap();
+        assert_eq!(collection.count_documents().unwrap(), i as u64 + 1);
+    }
+    import.join().unwrap();
+    assert_eq!(db.collection::<Document>("logs").count_documents().unwrap(), 1000);
+}
+
+
+#[test]
+fn test_journal_throttle() {
+    let db_path = mk_db_path("test-journal-throttle");
+    let _ = std::fs::remove_file(&db_path);
+    let config = Config {
+        journal_full_size: 10,
+        journal_throttle: Some(JournalThrottle {
+            start_factor: 1,
+            max_factor: 2,
+            max_delay: Duration::from_millis(20),
+        }),
+        ..Default::default()
+    };
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let metrics = db.metrics();
+    metrics.enable();
+
+    // the session blocks the checkpoint
+    let session = db.start_session().unwrap();
+
+    let collection = db.collection::<Document>("test");
+    let start = Instant::now();
+    for i in 0..40 {
+        collection.insert_one(doc! { "_id": i }).unwrap();
+    }
+    assert!(metrics.data().journal_lag > 0);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    drop(session);
+    db.collection::<Document>("test").insert_one(doc! { "_id": 40 }).unwrap();
+    assert_eq!(metrics.data().journal_lag, 0);
+}
+
+#[test]
+fn test_set_journal_full_size() {
+    let db_path = mk_db_path("test-set-journal-full-size");
+    let journal_path = mk_journal_path("test-set-journal-full-size");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(&journal_path);
+    let config = Config {
+        journal_full_size: 10,
+        ..Default::default()
+    };
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let journal_len = || std::fs::metadata(&journal_path).unwrap().len();
+
+    // checkpoint only on demand
+    db.set_journal_full_size(u64::MAX).unwrap();
+    let collection = db.collection::<Document>("test");
+    for i in 0..40 {
+        collection.insert_one(doc! { "_id": i }).unwrap();
+    }
+    let full_len = journal_len();
+
+    db.checkpoint().unwrap();
+    assert!(journal_len() < full_len);
+
+    db.set_journal_full_size(10).unwrap();
+    for i in 40..80 {
+        collection.insert_one(doc! { "_id": i }).unwrap();
+    }
+    assert!(journal_len() < full_len);
+    assert_eq!(collection.count_documents().unwrap(), 80);
+}
+
+#[test]
+fn test_recovery_report() {
+    const DB_NAME: &str = "test-recovery-report";
+    let db_path = mk_db_path(DB_NAME);
+    let journal_path = mk_journal_path(DB_NAME);
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(&journal_path);
+
+    let stale_journal = {
+        let (db, report) = Database::open_file_with_report(&db_path, Config::default()).unwrap();
+        assert_eq!(report.state, RecoveryState::Clean);
+        let collection = db.collection::<Document>("test");
+        for i in 0..10 {
+            collection.insert_one(doc! { "_id": i }).unwrap();
+        }
+        // the journal which is not deleted after the checkpoint
+        std::fs::read(&journal_path).unwrap()
+    };
+    std::fs::write(&journal_path, &stale_journal).unwrap();
+
+    {
+        let (db, report) = Database::open_file_with_report(&db_path, Config::default()).unwrap();
+        assert_eq!(report.state, RecoveryState::Replayed);
+        assert!(report.replayed_frames > 0);
+        assert_eq!(report.discarded_frames, 0);
+        assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 10);
+    }
+
+    // the header is broken while it's rewritten after a checkpoint
+    std::fs::write(&journal_path, vec![0xffu8; 64]).unwrap();
+    {
+        let (db, report) = Database::open_file_with_report(&db_path, Config::default()).unwrap();
+        assert_eq!(report.state, RecoveryState::DiscardedBrokenHeader);
+        assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 10);
+    }
+}
+
+#[test]
+fn test_growth_strategy() {
+    let db_path = mk_db_path("test-growth-strategy");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-growth-strategy"));
+    let config = Config {
+        journal_full_size: 10,
+        growth_strategy: GrowthStrategy::Fixed(NonZeroU64::new(64).unwrap()),
+        ..Default::default()
+    };
+    {
+        let db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+        let collection = db.collection::<Document>("test");
+        for i in 0..500 {
+            collection.insert_one(doc! {
+                "_id": i,
+                "content": "x".repeat(200),
+            }).unwrap();
+        }
+    }
+
+    // the leftover of the journal is checkpointed on open
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 500);
+    let pages = std::fs::metadata(&db_path).unwrap().len() / 4096;
+    assert!(pages > 16);
+    assert_eq!((pages - 16) % 64, 0);
+}
+
+#[test]
+fn test_direct_io() {
+    let db_path = mk_db_path("test-direct-io");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-direct-io"));
+    let config = Config {
+        journal_full_size: 10,
+        direct_io: true,
+        ..Default::default()
+    };
+    {
+        let db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+        let collection = db.collection::<Document>("test");
+        for i in 0..100 {
+            collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+        }
+    }
+
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let collection = db.collection::<Document>("test");
+    assert_eq!(collection.count_documents().unwrap(), 100);
+    let one = collection.find_one(doc! { "_id": 42 }).unwrap().unwrap();
+    assert_eq!(one.get("content").unwrap().as_str().unwrap(), "Hello");
+}
+
+#[test]
+fn test_io_uring() {
+    let db_path = mk_db_path("test-io-uring");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-io-uring"));
+    let config = Config {
+        journal_full_size: 10,
+        io_uring: true,
+        ..Default::default()
+    };
+    {
+        let db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+        let collection = db.collection::<Document>("test");
+        let docs: Vec<Document> = (0..100).map(|i| doc! { "_id": i, "content": "Hello" }).collect();
+        collection.insert_many(docs).unwrap();
+        for i in 100..200 {
+            collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+        }
+        assert_eq!(collection.count_documents().unwrap(), 200);
+    }
+
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let collection = db.collection::<Document>("test");
+    assert_eq!(collection.count_documents().unwrap(), 200);
+    let one = collection.find_one(doc! { "_id": 142 }).unwrap().unwrap();
+    assert_eq!(one.get("content").unwrap().as_str().unwrap(), "Hello");
+}
+
+#[test]
+fn test_header_metadata() {
+    let db_path = mk_db_path("test-header-metadata");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-header-metadata"));
+    {
+        let db = Database::open_file(&db_path).unwrap();
+        let dump = db.dump().unwrap();
+        assert!(dump.created_at.is_some());
+        assert_eq!(dump.checkpoint_lsn, None);
+        assert_eq!(dump.incompat_features, 0);
+
+        let collection = db.collection::<Document>("test");
+        for i in 0..10 {
+            collection.insert_one(doc! { "_id": i }).unwrap();
+        }
+        db.checkpoint().unwrap();
+        let first_lsn = db.dump().unwrap().checkpoint_lsn.unwrap();
+        assert!(first_lsn > 0);
+
+        db.create_collection_with_options("compressed", CreateCollectionOptions {
+            compression: Compression::Lz4,
+            ..Default::default()
+        }).unwrap();
+        db.checkpoint().unwrap();
+        let dump = db.dump().unwrap();
+        assert!(dump.checkpoint_lsn.unwrap() > first_lsn);
+        assert_ne!(dump.incompat_features, 0);
+    }
+
+    // a feature unknown to this version
+    {
+        use std::io::{Read, Seek, SeekFrom, Write};
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&db_path).unwrap();
+        let mut features = [0u8; 8];
+        file.seek(SeekFrom::Start(72)).unwrap();
+        file.read_exact(&mut features).unwrap();
+        features[0] |= 0x80;
+        file.seek(SeekFrom::Start(72)).unwrap();
+        file.write_all(&features).unwrap();
+    }
+
+    assert!(matches!(Database::open_file(&db_path), Err(DbErr::UnsupportedFeatures(_))));
+}
+
+#[test]
+fn test_max_db_size() {
+    let db_path = mk_db_path("test-max-db-size");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-max-db-size"));
+    let config = Config {
+        max_db_size: NonZeroU64::new(64 * 4096),
+        ..Default::default()
+    };
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let collection = db.collection::<Document>("test");
+
+    let mut inserted = 0;
+    let err = loop {
+        let result = collection.insert_one(doc! {
+            "_id": inserted,
+            "content": "x".repeat(1000),
+        });
+        match result {
+            Ok(_) => inserted += 1,
+            Err(err) => break err,
+        }
+    };
+    assert!(matches!(err, DbErr::DatabaseSizeLimitExceeded(size) if size == 64 * 4096));
+    assert!(inserted > 0);
+
+    // the failed transaction is rolled back
+    assert_eq!(collection.count_documents().unwrap(), inserted as u64);
+
+    // the space of the deleted documents is reused under the limit
+    collection.delete_many(doc! { "_id": { "$lt": inserted / 2 } }).unwrap();
+    collection.insert_one(doc! { "_id": inserted, "content": "x".repeat(1000) }).unwrap();
+    drop(db);
+    assert!(std::fs::metadata(&db_path).unwrap().len() <= 64 * 4096);
+}
+
+fn journal_size_after_updates(name: &str, delta_journal: bool) -> u64 {
+    let db_path = mk_db_path(name);
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path(name));
+    let config = Config {
+        journal_full_size: 10000,
+        delta_journal,
+        ..Default::default()
+    };
+    let journal_size = {
+        let db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+        let collection = db.collection::<Document>("test");
+        for i in 0..20 {
+            collection.insert_one(doc! { "_id": i, "count": 0, "content": "x".repeat(100) }).unwrap();
+        }
+        for _ in 0..100 {
+            collection.update_many(doc! { "_id": 7 }, doc! { "$inc": { "count": 1 } }).unwrap();
+        }
+        std::fs::metadata(mk_journal_path(name)).unwrap().len()
+    };
+
+    let db = Database::open_file_with_config(&db_path, config).unwrap();
+    let collection = db.collection::<Document>("test");
+    let result = collection.find_one(doc! { "_id": 7 }).unwrap().unwrap();
+    assert_eq!(result.get_i32("count").unwrap(), 100);
+    assert_eq!(collection.count_documents().unwrap(), 20);
+
+    journal_size
+}
+
+#[test]
+fn test_delta_journal() {
+    let whole_pages = journal_size_after_updates("test-delta-journal-off", false);
+    let deltas = journal_size_after_updates("test-delta-journal-on", true);
+    assert!(deltas * 2 < whole_pages);
+}
+
+#[test]
+fn test_persist_memory_db() {
+    let db_path = mk_db_path("test-persist-memory-db");
+    let _ = std::fs::remove_file(&db_path);
+    let _ = std::fs::remove_file(mk_journal_path("test-persist-memory-db"));
+
+    let db = create_memory_and_return_db_with_items(TEST_SIZE);
+    db.persist_to(&db_path).unwrap();
+    drop(db);
+
+    {
+        let db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), TEST_SIZE as u64);
+    }
+
+    let db = Database::open_memory_from_file(&db_path).unwrap();
+    let collection = db.collection::<Document>("test");
+    assert_eq!(collection.count_documents().unwrap(), TEST_SIZE as u64);
+    collection.insert_one(doc! { "content": "saved" }).unwrap();
+    // the file is not changed until it's saved
+    {
+        let db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), TEST_SIZE as u64);
+    }
+    db.persist_to(&db_path).unwrap();
+
+    let db = Database::open_file(&db_path).unwrap();
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), TEST_SIZE as u64 + 1);
+
+    let result = db.persist_to(&db_path);
+    assert!(matches!(result, Err(DbErr::NotSupportedByBackend(_))));
+}
+
+#[test]
+fn test_resource_limits() {
+    let db = Database::open_memory_with_config(Config {
+        max_open_cursors: NonZeroUsize::new(2),
+        max_sessions: NonZeroUsize::new(2),
+        max_collections: NonZeroUsize::new(2),
+        ..Default::default()
+    }).unwrap();
+
+    db.create_collection("a").unwrap();
+    let b = db.collection::<Document>("b");
+    b.insert_one(doc! { "_id": 1 }).unwrap();
+    assert!(matches!(db.create_collection("c"), Err(DbErr::TooManyCollections(2))));
+    let c = db.collection::<Document>("c");
+    assert!(matches!(c.insert_one(doc! { "_id": 1 }), Err(DbErr::TooManyCollections(2))));
+    assert_eq!(db.list_collection_names().unwrap().len(), 2);
+
+    let first = db.start_session().unwrap();
+    let _second = db.start_session().unwrap();
+    assert!(matches!(db.start_session(), Err(DbErr::TooManySessions(2))));
+    // the reads without a session are not limited
+    assert_eq!(b.find_many(None).unwrap().len(), 1);
+    drop(first);
+    let _third = db.start_session().unwrap();
+
+    let first = b.subscribe(None).unwrap();
+    let _second = b.subscribe(doc! { "_id": 2 }).unwrap();
+    assert!(matches!(b.subscribe(None), Err(DbErr::TooManyOpenCursors(2))));
+    drop(first);
+    assert!(b.subscribe(None).is_ok());
+}
+
+#[test]
+fn test_cursor_batches_and_timeout() {
+    let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+    let db = Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        cursor_timeout: Some(Duration::from_secs(60)),
+        max_open_cursors: NonZeroUsize::new(1),
+        ..Default::default()
+    }).unwrap();
+    let items = db.collection::<Document>("items");
+    items.insert_many((0..5).map(|i| doc! { "_id": i })).unwrap();
+
+    let find = |batch_size: i32| Bson::Document(doc! {
+        "command": "Find",
+        "ns": "items",
+        "multi": true,
+        "filter": { "_id": { "$gte": 0 } },
+        "options": { "batchSize": batch_size },
+    });
+    let get_more = |cursor_id: i64| Bson::Document(doc! {
+        "command": "GetMore",
+        "ns": "items",
+        "cursorId": cursor_id,
+        "batchSize": 2,
+    });
+
+    let result = db.handle_request_doc(find(2)).unwrap().value;
+    let cursor_id = result.as_document().unwrap().get_i64("cursorId").unwrap();
+    assert_eq!(result.as_document().unwrap().get_array("batch").unwrap().len(), 2);
+    assert!(matches!(db.handle_request_doc(find(2)), Err(DbErr::TooManyOpenCursors(1))));
+
+    let result = db.handle_request_doc(get_more(cursor_id)).unwrap().value;
+    assert_eq!(result.as_document().unwrap().get_i64("cursorId").unwrap(), cursor_id);
+    let result = db.handle_request_doc(get_more(cursor_id)).unwrap().value;
+    assert_eq!(result.as_document().unwrap().get_i64("cursorId").unwrap(), 0);
+    assert_eq!(result.as_document().unwrap().get_array("batch").unwrap().len(), 1);
+
+    // the cursor idle for longer than the timeout is reaped
+    let result = db.handle_request_doc(find(1)).unwrap().value;
+    let cursor_id = result.as_document().unwrap().get_i64("cursorId").unwrap();
+    clock.advance(Duration::from_secs(61));
+    assert!(matches!(db.handle_request_doc(get_more(cursor_id)), Err(DbErr::CursorNotFound(_))));
+
+    let result = db.handle_request_doc(find(1)).unwrap().value;
+    let cursor_id = result.as_document().unwrap().get_i64("cursorId").unwrap();
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "KillCursors",
+        "ns": "items",
+        "cursorIds": [cursor_id],
+    })).unwrap();
+    assert_eq!(result.value, Bson::Int64(1));
+}
+
+#[test]
+fn test_find_scan_slices() {
+    let db = Database::open_memory().unwrap();
+    let items = db.collection::<Document>("items");
+    items.insert_many((0..25).map(|i| doc! { "_id": i, "even": i % 2 == 0 })).unwrap();
+
+    let mut resume_from = None;
+    let mut found = vec![];
+    let mut slices = 0;
+    loop {
+        let mut options = doc! { "scanLimit": 10 };
+        if let Some(key) = resume_from.take() {
+            options.insert("resumeFrom", key);
+        }
+        let result = db.handle_request_doc(Bson::Document(doc! {
+            "command": "Find",
+            "ns": "items",
+            "multi": true,
+            "filter": { "even": true },
+            "options": options,
+        })).unwrap().value;
+        let result = result.as_document().unwrap();
+        slices += 1;
+        for doc in result.get_array("batch").unwrap() {
+            found.push(doc.as_document().unwrap().get_i32("_id").unwrap());
+        }
+        match result.get("resumeFrom") {
+            Some(key) => resume_from = Some(key.clone()),
+            None => break,
+        }
+    }
+
+    assert_eq!(slices, 3);
+    assert_eq!(found, (0..25).filter(|i| i % 2 == 0).collect::<Vec<i32>>());
+}
@@ -0,0 +1,57 @@
+#![cfg(feature = "debug")]
+
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{Bson, Document, doc};
+use polodb_core::debug::PageDebug;
+
+mod common;
+
+use common::prepare_db;
+
+#[test]
+fn test_debug_pages() {
+    let db = prepare_db("test-debug-pages").unwrap();
+    let collection = db.collection::<Document>("books");
+    for i in 0..10 {
+        collection.insert_one(doc! { "_id": i, "title": "Dune" }).unwrap();
+    }
+
+    let header = db.debug_page(0).unwrap();
+    let meta_pid = match &header {
+        PageDebug::Header { identifier, meta_pid, .. } => {
+            assert!(!identifier.is_empty());
+            *meta_pid
+        }
+        other => panic!("unexpected page: {:?}", other),
+    };
+    assert!(header.to_string().contains("Meta Page Id"));
+
+    let meta_page = db.debug_page(meta_pid).unwrap();
+    match &meta_page {
+        PageDebug::BTree { items, .. } => {
+            assert!(items.iter().any(|item| item.key == Bson::String("books".into())));
+        }
+        other => panic!("unexpected page: {:?}", other),
+    }
+    assert!(meta_page.to_string().contains("books"));
+
+    let frames = db.debug_journal_frames().unwrap();
+    assert!(!frames.is_empty());
+    // the last frame commits the last transaction
+    assert!(frames.last().unwrap().db_size > 0);
+
+    db.checkpoint().unwrap();
+    assert!(db.debug_journal_frames().unwrap().is_empty());
+}
+
+#[test]
+fn test_debug_memory() {
+    let db = Database::open_memory().unwrap();
+    db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+
+    assert!(matches!(db.debug_page(0).unwrap(), PageDebug::Header { .. }));
+    assert!(matches!(db.debug_page(10_000), Err(DbErr::PageIdOutOfRange(10_000, _))));
+
+    let result = db.debug_journal_frames();
+    assert!(matches!(result, Err(DbErr::NotSupportedByBackend(_))));
+}
@@ -1,5 +1,8 @@
-use polodb_core::Database;
-use polodb_core::bson::{doc, Document};
+use std::sync::Arc;
+use std::time::Duration;
+use polodb_core::{Config, Database, DeleteOptions, IndexOptions};
+use polodb_core::bson::{doc, DateTime, Document};
+use polodb_core::clock::MockClock;
 
 mod common;
 
@@ -111,3 +114,162 @@ fn test_delete_all_items() {
         assert!(deleted_data_page_count < data_page_count);
     });
 }
+
+#[test]
+fn test_drain() {
+    vec![
+        prepare_db("test-drain").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("jobs");
+        for i in 0..10 {
+            collection.insert_one(doc! {
+                "_id": i,
+                "state": if i % 2 == 0 { "ready" } else { "waiting" },
+            }).unwrap();
+        }
+
+        let claimed = collection.drain(doc! { "state": "ready" }).unwrap();
+        assert_eq!(claimed.len(), 5);
+        assert!(claimed.iter().all(|job| job.get_str("state").unwrap() == "ready"));
+        assert_eq!(collection.count_documents().unwrap(), 5);
+
+        let claimed = collection.drain(doc! { "state": "ready" }).unwrap();
+        assert!(claimed.is_empty());
+
+        let mut session = db.start_session().unwrap();
+        session.start_transaction(None).unwrap();
+        let claimed = collection.drain_with_session(None, &mut session).unwrap();
+        assert_eq!(claimed.len(), 5);
+        session.abort_transaction().unwrap();
+        assert_eq!(collection.count_documents().unwrap(), 5);
+
+        let claimed = db.collection::<Document>("missing").drain(None).unwrap();
+        assert!(claimed.is_empty());
+    });
+}
+
+#[test]
+fn test_delete_key_range() {
+    vec![
+        prepare_db("test-delete-key-range").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        collection.insert_many((0..1000).map(|i| doc! {
+            "_id": i,
+            "content": i.to_string(),
+        })).unwrap();
+
+        let result = collection.delete_many(doc! {
+            "_id": { "$gt": 100, "$lte": 300 },
+        }).unwrap();
+        assert_eq!(result.deleted_count, 200);
+        assert!(collection.find_one(doc! { "_id": 100 }).unwrap().is_some());
+        assert!(collection.find_one(doc! { "_id": 101 }).unwrap().is_none());
+        assert!(collection.find_one(doc! { "_id": 300 }).unwrap().is_none());
+        assert!(collection.find_one(doc! { "_id": 301 }).unwrap().is_some());
+
+        let result = collection.delete_one(doc! {
+            "_id": { "$gte": 500 },
+        }).unwrap();
+        assert_eq!(result.deleted_count, 1);
+        assert!(collection.find_one(doc! { "_id": 500 }).unwrap().is_none());
+
+        let result = collection.delete_many(doc! {
+            "_id": { "$lt": 50 },
+        }).unwrap();
+        assert_eq!(result.deleted_count, 50);
+        assert_eq!(collection.count_documents().unwrap(), 749);
+
+        // the documents out of the ranges are kept
+        let ids: Vec<i32> = collection.find_many(None).unwrap()
+            .iter()
+            .map(|doc| doc.get_i32("_id").unwrap())
+            .collect();
+        let expected: Vec<i32> = (50..=100).chain(301..500).chain(501..1000).collect();
+        assert_eq!(ids, expected);
+    });
+}
+
+#[test]
+fn test_delete_in_batches() {
+    vec![
+        prepare_db("test-delete-in-batches").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        collection.insert_many((0..100).map(|i| doc! {
+            "_id": i,
+            "even": i % 2 == 0,
+        })).unwrap();
+
+        let result = collection.delete_many_with_options(doc! { "even": true }, DeleteOptions {
+            batch_size: Some(7),
+        }).unwrap();
+        assert_eq!(result.deleted_count, 50);
+
+        let result = collection.delete_many_with_options(doc! {}, DeleteOptions {
+            batch_size: Some(10),
+        }).unwrap();
+        assert_eq!(result.deleted_count, 50);
+        assert_eq!(collection.count_documents().unwrap(), 0);
+    });
+}
+
+#[test]
+fn test_truncate() {
+    vec![
+        prepare_db("test-truncate").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        collection.insert_many((0..1000).map(|i| doc! {
+            "_id": i,
+            "content": i.to_string(),
+        })).unwrap();
+
+        let result = collection.truncate().unwrap();
+        assert_eq!(result.deleted_count, 1000);
+        assert_eq!(collection.count_documents().unwrap(), 0);
+        assert!(collection.find_many(None).unwrap().is_empty());
+        assert_eq!(db.list_collection_names().unwrap(), vec!["test".to_string()]);
+
+        collection.insert_one(doc! { "_id": 1, "content": "again" }).unwrap();
+        let found = collection.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+        assert_eq!(found.get_str("content").unwrap(), "again");
+
+        let result = db.collection::<Document>("missing").truncate().unwrap();
+        assert_eq!(result.deleted_count, 0);
+    });
+}
+
+#[test]
+fn test_delete_expired() {
+    let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+    let db = Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        ..Default::default()
+    }).unwrap();
+    let sessions = db.collection::<Document>("sessions");
+    for i in 0..30 {
+        let expire_at = DateTime::from_millis(1_700_000_000_000 + (i % 3) * 60_000);
+        sessions.insert_one(doc! { "_id": i, "expireAt": expire_at }).unwrap();
+    }
+    // the documents without the field never expire
+    sessions.insert_one(doc! { "_id": 100 }).unwrap();
+
+    assert_eq!(sessions.delete_expired().unwrap().deleted_count, 0);
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(sessions.delete_expired().unwrap().deleted_count, 10);
+
+    // the same with an index
+    sessions.create_index(doc! { "expireAt": 1 }, IndexOptions::default()).unwrap();
+    clock.advance(Duration::from_secs(60));
+    assert_eq!(sessions.delete_expired().unwrap().deleted_count, 10);
+
+    clock.advance(Duration::from_secs(3600));
+    assert_eq!(sessions.delete_expired().unwrap().deleted_count, 10);
+    assert_eq!(sessions.count_documents().unwrap(), 1);
+}
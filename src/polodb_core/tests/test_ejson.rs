@@ -0,0 +1,72 @@
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{Bson, DateTime, Document, doc};
+use polodb_core::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Order {
+    item: String,
+    quantity: i32,
+}
+
+#[test]
+fn test_ejson_insert_and_find() {
+    let db = Database::open_memory().unwrap();
+    let orders = db.collection::<Document>("orders");
+    let result = orders.insert_many_ejson(r#"[
+        {"_id": {"$oid": "65a1b2c3d4e5f60718293a4b"}, "item": "pen", "quantity": 3, "at": {"$date": "2024-01-01T00:00:00Z"}},
+        {"item": "ink", "quantity": {"$numberLong": "10"}, "at": {"$date": {"$numberLong": "1709251200000"}}}
+    ]"#).unwrap();
+    assert_eq!(result.inserted_ids.len(), 2);
+    orders.insert_one_ejson(r#"{"item": "paper", "quantity": 500}"#).unwrap();
+
+    let oid = ObjectId::parse_str("65a1b2c3d4e5f60718293a4b").unwrap();
+    let pen = orders.find_one_ejson(r#"{"_id": {"$oid": "65a1b2c3d4e5f60718293a4b"}}"#).unwrap().unwrap();
+    assert_eq!(pen.get_object_id("_id").unwrap(), oid);
+    assert_eq!(pen.get("at"), Some(&Bson::DateTime(DateTime::from_millis(1704067200000))));
+
+    let result = orders.find_ejson(r#"{"quantity": {"$gt": 1, "$lt": 100}}"#).unwrap();
+    assert_eq!(result.len(), 2);
+    let result = orders.find_ejson(r#"{"at": {"$gte": {"$date": "2024-02-01T00:00:00Z"}}}"#).unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].get_str("item").unwrap(), "ink");
+    assert_eq!(result[0].get_i64("quantity").unwrap(), 10);
+
+    let typed = db.collection::<Order>("orders");
+    let result = typed.find_ejson(r#"{"item": "paper"}"#).unwrap();
+    assert_eq!(result, vec![Order { item: "paper".into(), quantity: 500 }]);
+}
+
+#[test]
+fn test_ejson_update() {
+    let db = Database::open_memory().unwrap();
+    let orders = db.collection::<Document>("orders");
+    orders.insert_many(vec![
+        doc! { "_id": 1, "item": "pen", "quantity": 3 },
+        doc! { "_id": 2, "item": "ink", "quantity": 10 },
+    ]).unwrap();
+
+    let result = orders.update_one_ejson(
+        r#"{"_id": 1}"#,
+        r#"{"$set": {"shipped": {"$date": "2024-01-01T00:00:00Z"}}}"#,
+    ).unwrap();
+    assert_eq!(result.modified_count, 1);
+    let result = orders.update_many_ejson(r#"{}"#, r#"{"$inc": {"quantity": 1}}"#).unwrap();
+    assert_eq!(result.modified_count, 2);
+
+    let pen = orders.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    assert_eq!(pen.get_i32("quantity").unwrap(), 4);
+    assert_eq!(pen.get_datetime("shipped").unwrap(), &DateTime::from_millis(1704067200000));
+}
+
+#[test]
+fn test_ejson_invalid() {
+    let db = Database::open_memory().unwrap();
+    let orders = db.collection::<Document>("orders");
+    assert!(matches!(orders.find_ejson(r#"{"a": "#), Err(DbErr::ParseError(_))));
+    assert!(matches!(orders.find_ejson("[1, 2]"), Err(DbErr::ParseError(_))));
+    assert!(matches!(orders.insert_one_ejson(r#"{"_id": {"$oid": "123"}}"#), Err(DbErr::ParseError(_))));
+    assert!(matches!(orders.insert_many_ejson(r#"[{"a": 1}, "b"]"#), Err(DbErr::ParseError(_))));
+    assert!(matches!(orders.update_one_ejson("{}", "null"), Err(DbErr::ParseError(_))));
+    assert_eq!(orders.count_documents().unwrap(), 0);
+}
@@ -0,0 +1,60 @@
+#![cfg(feature = "encryption")]
+
+use polodb_core::{Config, Database, DbErr};
+use polodb_core::bson::{Document, doc};
+
+mod common;
+
+use common::{mk_db_path, prepare_db_with_config};
+
+fn encrypted_config(key: [u8; 32]) -> Config {
+    Config {
+        encryption_key: Some(key),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_encryption_reopen() {
+    let db_path = mk_db_path("test-encryption-reopen");
+    {
+        let db = prepare_db_with_config("test-encryption-reopen", encrypted_config([1; 32])).unwrap();
+        let collection = db.collection::<Document>("secrets");
+        for i in 0..500 {
+            collection.insert_one(doc! { "_id": i, "text": "top secret text" }).unwrap();
+        }
+        collection.update_many(doc! {}, doc! { "$set": { "checked": true } }).unwrap();
+        db.checkpoint().unwrap();
+        collection.delete_many(doc! { "_id": { "$gte": 250 } }).unwrap();
+    }
+
+    // the plaintext is in neither the file nor the journal
+    let mut journal_path = db_path.clone();
+    journal_path.set_file_name("test-encryption-reopen.db.journal");
+    for path in [&db_path, &journal_path] {
+        let bytes = std::fs::read(path).unwrap();
+        assert!(!bytes.windows(15).any(|window| window == b"top secret text"));
+    }
+
+    let db = Database::open_file_with_config(&db_path, encrypted_config([1; 32])).unwrap();
+    let collection = db.collection::<Document>("secrets");
+    assert_eq!(collection.count_documents().unwrap(), 250);
+    let doc = collection.find_one(doc! { "_id": 100 }).unwrap().unwrap();
+    assert_eq!(doc.get_str("text").unwrap(), "top secret text");
+    assert!(doc.get_bool("checked").unwrap());
+}
+
+#[test]
+fn test_encryption_wrong_key() {
+    let db_path = mk_db_path("test-encryption-wrong-key");
+    {
+        let db = prepare_db_with_config("test-encryption-wrong-key", encrypted_config([1; 32])).unwrap();
+        db.collection::<Document>("secrets").insert_one(doc! { "text": "hello" }).unwrap();
+    }
+
+    let result = Database::open_file_with_config(&db_path, encrypted_config([2; 32]));
+    assert!(matches!(result, Err(DbErr::EncryptionError(_))));
+
+    let result = Database::open_file(&db_path);
+    assert!(result.is_err());
+}
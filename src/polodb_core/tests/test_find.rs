@@ -1,5 +1,8 @@
-use polodb_core::Database;
-use polodb_core::bson::{doc, Document};
+use polodb_core::{Config, Database, DbErr, FindOptions, OperatorMode};
+use polodb_core::bson::{doc, Binary, Bson, Document};
+use polodb_core::bson::spec::BinarySubtype;
+use polodb_core::explain::ExplainVerbosity;
+use serde::{Deserialize, Serialize};
 
 mod common;
 
@@ -103,3 +106,459 @@ fn test_find() {
         assert_eq!(one.get("content").unwrap().as_str().unwrap(), "3");
     });
 }
+
+#[test]
+fn test_find_with_projection() {
+    [
+        prepare_db("test-find-with-projection").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("people");
+        collection.insert_many(vec![
+            doc! { "_id": 1, "name": "Vincent", "age": 30, "bio": "x".repeat(500), "address": { "city": "Guangzhou", "street": "A" } },
+            doc! { "_id": 2, "name": "Alice", "age": 20, "bio": "y".repeat(500), "address": { "city": "Shanghai", "street": "B" } },
+        ]).unwrap();
+
+        let result = collection.find_many_with_options(doc! { "age": { "$gt": 25 } }, FindOptions {
+            projection: Some(doc! { "name": 1, "address.city": 1 }),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(result, vec![
+            doc! { "_id": 1, "name": "Vincent", "address": { "city": "Guangzhou" } },
+        ]);
+
+        let result = collection.find_one_with_options(doc! { "_id": 2 }, FindOptions {
+            projection: Some(doc! { "_id": 0, "bio": 0, "address": 0 }),
+            ..Default::default()
+        }).unwrap().unwrap();
+        assert_eq!(result, doc! { "name": "Alice", "age": 20 });
+
+        let err = collection.find_many_with_options(None, FindOptions {
+            projection: Some(doc! { "name": 1, "age": 0 }),
+            ..Default::default()
+        });
+        assert!(err.is_err());
+    });
+}
+
+#[test]
+fn test_find_with_slice() {
+    [
+        prepare_db("test-find-with-slice").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let chats = db.collection::<Document>("chats");
+        let messages: Vec<Document> = (0..500)
+            .map(|i| doc! { "seq": i, "text": format!("message {}", i) })
+            .collect();
+        chats.insert_one(doc! {
+            "_id": 1,
+            "title": "general",
+            "messages": messages,
+            "avatar": Binary { subtype: BinarySubtype::Generic, bytes: (0..=255u8).collect() },
+        }).unwrap();
+
+        // page through the messages from the newest ones
+        let page = chats.find_one_with_options(doc! { "_id": 1 }, FindOptions {
+            projection: Some(doc! { "messages": { "$slice": [-20, 10] } }),
+            ..Default::default()
+        }).unwrap().unwrap();
+        let seqs: Vec<i32> = page.get_array("messages").unwrap()
+            .iter()
+            .map(|message| message.as_document().unwrap().get_i32("seq").unwrap())
+            .collect();
+        assert_eq!(seqs, (480..490).collect::<Vec<i32>>());
+        assert_eq!(page.get_str("title").unwrap(), "general");
+
+        let result = chats.find_many_with_options(None, FindOptions {
+            projection: Some(doc! { "_id": 0, "title": 1, "avatar": { "$slice": [16, 4] } }),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(result, vec![doc! {
+            "title": "general",
+            "avatar": Binary { subtype: BinarySubtype::Generic, bytes: vec![16, 17, 18, 19] },
+        }]);
+
+        // the filter reads the whole array before it's sliced
+        let result = chats.find_many_with_options(doc! { "messages": { "$size": 500i64 } }, FindOptions {
+            projection: Some(doc! { "_id": 1, "messages": { "$slice": -1 } }),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].get_array("messages").unwrap().len(), 1);
+    });
+}
+
+#[test]
+fn test_find_sort_skip_limit() {
+    vec![
+        prepare_db("test-find-sort-skip-limit").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("people");
+        for i in 0..100 {
+            collection.insert_one(doc! { "_id": i, "age": i % 10, "bio": "x".repeat(100) }).unwrap();
+        }
+        let ids = |docs: Vec<Document>| -> Vec<i32> {
+            docs.iter().map(|doc| doc.get_i32("_id").unwrap()).collect()
+        };
+
+        // the pages in the order of `_id`
+        let page = collection.find_many_with_options(None, FindOptions {
+            skip: Some(20),
+            limit: Some(5),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(ids(page), vec![20, 21, 22, 23, 24]);
+
+        let page = collection.find_many_with_options(doc! { "age": 3 }, FindOptions {
+            sort: Some(doc! { "_id": 1 }),
+            skip: Some(8),
+            limit: Some(5),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(ids(page), vec![83, 93]);
+
+        let page = collection.find_many_with_options(None, FindOptions {
+            sort: Some(doc! { "age": -1, "_id": 1 }),
+            skip: Some(5),
+            limit: Some(12),
+            projection: Some(doc! { "age": 0, "bio": 0 }),
+        }).unwrap();
+        assert_eq!(page[0], doc! { "_id": 59 });
+        assert_eq!(ids(page), vec![59, 69, 79, 89, 99, 8, 18, 28, 38, 48, 58, 68]);
+
+        // the sort field is read while it's not in the projection
+        let doc = collection.find_one_with_options(doc! { "_id": { "$gte": 50 } }, FindOptions {
+            sort: Some(doc! { "age": 1, "_id": -1 }),
+            projection: Some(doc! { "_id": 1 }),
+            ..Default::default()
+        }).unwrap().unwrap();
+        assert_eq!(doc, doc! { "_id": 90 });
+
+        let doc = collection.find_one_with_options(None, FindOptions {
+            skip: Some(7),
+            ..Default::default()
+        }).unwrap().unwrap();
+        assert_eq!(doc.get_i32("_id").unwrap(), 7);
+
+        // a limit of 0 is no limit
+        let all = collection.find_many_with_options(None, FindOptions {
+            skip: Some(90),
+            limit: Some(0),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(all.len(), 10);
+
+        let page = collection.find_many_with_options(doc! { "$expr": { "$lt": ["$age", 2] } }, FindOptions {
+            sort: Some(doc! { "_id": -1 }),
+            limit: Some(3),
+            ..Default::default()
+        }).unwrap();
+        assert_eq!(ids(page), vec![91, 90, 81]);
+
+        let err = collection.find_many_with_options(None, FindOptions {
+            sort: Some(doc! { "age": "desc" }),
+            ..Default::default()
+        });
+        assert!(matches!(err, Err(DbErr::ValidationError(_))));
+
+        // the scan ends after the limit
+        let explain = collection.explain_with_options(None, FindOptions {
+            skip: Some(10),
+            limit: Some(5),
+            ..Default::default()
+        }, ExplainVerbosity::ExecutionStats).unwrap();
+        assert_eq!(explain.stages, vec!["COLLSCAN".to_string()]);
+        let stats = explain.execution_stats.unwrap();
+        assert_eq!(stats.n_returned, 5);
+        assert_eq!(stats.total_docs_examined, 15);
+
+        let explain = collection.explain_with_options(None, FindOptions {
+            sort: Some(doc! { "age": 1 }),
+            limit: Some(5),
+            ..Default::default()
+        }, ExplainVerbosity::ExecutionStats).unwrap();
+        assert_eq!(explain.stages, vec!["COLLSCAN".to_string(), "SORT".to_string()]);
+        let stats = explain.execution_stats.unwrap();
+        assert_eq!(stats.n_returned, 5);
+        assert_eq!(stats.total_docs_examined, 100);
+    });
+}
+
+#[test]
+fn test_find_sort_skip_limit_request() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("people");
+    for i in 0..10 {
+        collection.insert_one(doc! { "_id": i, "age": i % 3 }).unwrap();
+    }
+
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "Find",
+        "ns": "people",
+        "multi": true,
+        "filter": { "age": { "$gt": 0 } },
+        "options": { "sort": { "age": 1, "_id": -1 }, "skip": 1, "limit": 3 },
+    })).unwrap().value;
+    let ids: Vec<i32> = result.as_array().unwrap()
+        .iter()
+        .map(|doc| doc.as_document().unwrap().get_i32("_id").unwrap())
+        .collect();
+    assert_eq!(ids, vec![4, 1, 8]);
+}
+
+#[test]
+fn test_count_documents_request_with_filter() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("people");
+    for i in 0..10 {
+        collection.insert_one(doc! { "_id": i, "age": i % 3 }).unwrap();
+    }
+
+    let count = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "people",
+        "filter": { "age": 1 },
+    })).unwrap().value;
+    assert_eq!(count, Bson::Int64(3));
+
+    let count = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "missing",
+        "filter": { "age": 1 },
+    })).unwrap().value;
+    assert_eq!(count, Bson::Int64(0));
+}
+
+#[test]
+fn test_find_many_by_ids() {
+    [
+        prepare_db("test-find-many-by-ids").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("items");
+        // the btree has several levels
+        let docs: Vec<Document> = (0..2000)
+            .map(|i| doc! { "_id": i * 2, "content": "x".repeat(100) })
+            .collect();
+        collection.insert_many(&docs).unwrap();
+
+        let ids = vec![3999, 1000, 7, 0, 1000, 3998, 4000, 1, 1999, 2000];
+        let result = collection.find_many_by_ids(ids).unwrap();
+        let found: Vec<i32> = result.found.iter()
+            .map(|doc| doc.get_i32("_id").unwrap())
+            .collect();
+        assert_eq!(found, vec![0, 1000, 2000, 3998]);
+        let missing: Vec<i32> = result.missing.iter()
+            .map(|id| id.as_i32().unwrap())
+            .collect();
+        assert_eq!(missing, vec![1, 7, 1999, 3999, 4000]);
+
+        let result = collection.find_many_by_ids((0..2000).map(|i| i * 2)).unwrap();
+        assert_eq!(result.found, docs);
+        assert!(result.missing.is_empty());
+
+        let result = db.collection::<Document>("empty").find_many_by_ids(vec![1, 2]).unwrap();
+        assert!(result.found.is_empty());
+        assert_eq!(result.missing.len(), 2);
+    });
+}
+
+#[test]
+fn test_explain() {
+    vec![
+        prepare_db("test-explain").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("books");
+        for i in 0..20 {
+            collection.insert_one(doc! { "_id": i, "year": 2000 + i % 4 }).unwrap();
+        }
+
+        let explain = collection.explain(doc! { "_id": 3 }, ExplainVerbosity::QueryPlanner).unwrap();
+        assert_eq!(explain.stages, vec!["IDHACK".to_string()]);
+        assert!(explain.execution_stats.is_none());
+
+        let explain = collection.explain(doc! { "_id": 3 }, ExplainVerbosity::ExecutionStats).unwrap();
+        let stats = explain.execution_stats.unwrap();
+        assert_eq!(stats.n_returned, 1);
+        assert_eq!(stats.total_keys_examined, 1);
+        assert_eq!(stats.total_docs_examined, 1);
+        assert!(stats.pages_read.is_none());
+
+        db.metrics().enable();
+        let explain = collection.explain_with_options(doc! { "year": 2001 }, FindOptions {
+            projection: Some(doc! { "year": 1 }),
+            ..Default::default()
+        }, ExplainVerbosity::ExecutionStats).unwrap();
+        assert_eq!(explain.stages, vec!["COLLSCAN".to_string(), "PROJECTION".to_string()]);
+        let stats = explain.execution_stats.as_ref().unwrap();
+        assert_eq!(stats.n_returned, 5);
+        assert_eq!(stats.total_docs_examined, 20);
+        assert_eq!(stats.stages.len(), 2);
+        assert_eq!(stats.stages[0].n_returned, 5);
+        assert!(stats.pages_read.is_some());
+
+        let doc = explain.to_document();
+        assert_eq!(doc.get_document("executionStats").unwrap().get_i64("totalDocsExamined").unwrap(), 20);
+
+        let explain = db.collection::<Document>("missing")
+            .explain(None, ExplainVerbosity::ExecutionStats)
+            .unwrap();
+        assert_eq!(explain.execution_stats.unwrap().n_returned, 0);
+    });
+}
+
+#[test]
+fn test_find_unknown_operators_strict() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_one(doc! { "_id": 1, "age": 20 }).unwrap();
+
+    let assert_unknown = |result: Result<Vec<Document>, DbErr>| {
+        match result {
+            Err(DbErr::UnknownQueryOperator(op)) => assert_eq!(op, "$foo"),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    };
+    assert_unknown(collection.find_many(doc! { "age": { "$foo": 1 } }));
+    assert_unknown(collection.find_many(doc! { "$foo": 1 }));
+    assert_unknown(collection.find_many(doc! { "_id": 1, "age": { "$gt": 1, "$foo": 1 } }));
+    assert_unknown(collection.find_many(doc! { "$expr": true, "age": { "$foo": 1 } }));
+    assert_unknown(collection.aggregate(vec![
+        doc! { "$match": { "age": { "$exists": true, "$foo": 1 } } },
+    ]));
+    assert!(matches!(
+        collection.delete_many(doc! { "age": { "$foo": 1 } }),
+        Err(DbErr::UnknownQueryOperator(_)),
+    ));
+    assert!(matches!(
+        collection.update_many(doc! { "_id": 1 }, doc! { "$foo": { "age": 1 } }),
+        Err(DbErr::UnknownUpdateOperation(_)),
+    ));
+    assert_eq!(collection.count_documents().unwrap(), 1);
+}
+
+#[test]
+fn test_find_unknown_operators_lax() {
+    let db = Database::open_memory_with_config(Config {
+        operator_mode: OperatorMode::Lax,
+        ..Default::default()
+    }).unwrap();
+    let collection = db.collection::<Document>("test");
+    // the top-level fields starting with `$` are rejected by the inserts,
+    // but the ones of the embedded documents are kept
+    collection.insert_many(vec![
+        doc! { "_id": 1, "range": { "$min": 1, "$max": 5 } },
+        doc! { "_id": 2, "range": { "$min": 2, "$max": 5 } },
+    ]).unwrap();
+
+    let ids = |docs: Vec<Document>| -> Vec<i32> {
+        docs.iter().map(|doc| doc.get_i32("_id").unwrap()).collect()
+    };
+    let range = doc! { "$min": 1, "$max": 5 };
+    assert_eq!(ids(collection.find_many(doc! { "range": range.clone() }).unwrap()), vec![1]);
+    assert!(collection.find_many(doc! { "$tag": "a" }).unwrap().is_empty());
+    assert_eq!(ids(collection.find_many(doc! { "_id": 1, "range": range.clone() }).unwrap()), vec![1]);
+    assert_eq!(ids(collection.aggregate(vec![
+        doc! { "$match": { "range": range.clone(), "missing": { "$exists": false } } },
+    ]).unwrap()), vec![1]);
+    assert!(collection.aggregate(vec![
+        doc! { "$match": { "$tag": "a", "missing": { "$exists": false } } },
+    ]).unwrap().is_empty());
+
+    // the fields are written by the updates
+    let result = collection.update_one(doc! { "_id": 2 }, doc! { "$tag": "b" }).unwrap();
+    assert_eq!(result.modified_count, 1);
+    assert_eq!(ids(collection.find_many(doc! { "$tag": "b" }).unwrap()), vec![2]);
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Country {
+    #[serde(rename = "_id")]
+    id: i32,
+    name: String,
+    population: i64,
+}
+
+#[test]
+fn test_find_typed() {
+    vec![
+        prepare_db("test-find-typed").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let countries = db.collection::<Country>("countries");
+        countries.insert_many(vec![
+            Country { id: 1, name: "Iceland".into(), population: 372_000 },
+            Country { id: 3, name: "Malta".into(), population: 535_000 },
+        ]).unwrap();
+        db.collection::<Document>("countries").insert_one(doc! {
+            "_id": 2,
+            "name": "Monaco",
+        }).unwrap();
+
+        let found: Vec<Country> = countries
+            .find(doc! { "population": { "$gt": 0 } })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[1], Country { id: 3, name: "Malta".into(), population: 535_000 });
+
+        let mut iter = countries.find(None).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap().name, "Iceland");
+        match iter.next().unwrap() {
+            Err(DbErr::DeserializeDocument(err)) => {
+                assert_eq!(err.collection, "countries");
+                assert_eq!(err.id, Some(Bson::Int32(2)));
+            }
+            result => panic!("unexpected result: {:?}", result),
+        }
+        assert_eq!(iter.next().unwrap().unwrap().name, "Malta");
+        assert!(iter.next().is_none());
+
+        let err = countries.find_one(doc! { "_id": 2 }).unwrap_err();
+        assert!(matches!(err, DbErr::DeserializeDocument(_)));
+        assert!(err.to_string().contains("countries"));
+    });
+}
+
+#[test]
+fn test_analyze_schema() {
+    let db = Database::open_memory().unwrap();
+    let users = db.collection::<Document>("users");
+    users.insert_many((0..100).map(|i| {
+        if i < 80 {
+            doc! { "_id": i, "age": i % 10, "profile": { "city": "Paris" } }
+        } else {
+            // the newer documents have the age drifted to a string, and no profile
+            doc! { "_id": i, "age": format!("{}", i), "email": "a@example.com" }
+        }
+    })).unwrap();
+
+    let analysis = users.analyze_schema(1000).unwrap();
+    assert_eq!(analysis.sampled, 100);
+    let age = analysis.field("age").unwrap();
+    assert_eq!(age.presence, 100.0);
+    assert_eq!(age.types, vec![("int".to_string(), 80), ("string".to_string(), 20)]);
+    assert_eq!(age.distinct, 30);
+    assert_eq!(analysis.field("profile.city").unwrap().presence, 80.0);
+    assert_eq!(analysis.field("email").unwrap().count, 20);
+
+    // the sample spreads over the collection
+    let analysis = users.analyze_schema(10).unwrap();
+    assert_eq!(analysis.sampled, 10);
+    assert_eq!(analysis.field("email").unwrap().count, 2);
+
+    assert_eq!(db.collection::<Document>("missing").analyze_schema(10).unwrap().sampled, 0);
+
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "AnalyzeSchema",
+        "ns": "users",
+        "options": { "sampleSize": 50 },
+    })).unwrap();
+    let doc = result.value.as_document().unwrap();
+    assert_eq!(doc.get_i64("sampled").unwrap(), 50);
+    assert_eq!(doc.get_str("namespace").unwrap(), "users");
+}
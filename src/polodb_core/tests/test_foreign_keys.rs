@@ -0,0 +1,141 @@
+use polodb_core::{CreateCollectionOptions, Database, DbErr, ForeignKey, OnDelete};
+use polodb_core::bson::{doc, Bson, Document};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn create_shop(db: &Database, on_delete: OnDelete) {
+    db.create_collection("customers").unwrap();
+    db.create_collection_with_options("orders", CreateCollectionOptions {
+        foreign_keys: vec![ForeignKey::new("customer_id", "customers", on_delete)],
+        ..Default::default()
+    }).unwrap();
+    db.collection::<Document>("customers").insert_many(vec![
+        doc! { "_id": 1, "name": "Alice" },
+        doc! { "_id": 2, "name": "Bob" },
+    ]).unwrap();
+}
+
+#[test]
+fn test_foreign_key_insert_update() {
+    let db_path = mk_db_path("test-foreign-key-insert");
+    {
+        let db = prepare_db("test-foreign-key-insert").unwrap();
+        create_shop(&db, OnDelete::Restrict);
+    }
+
+    // the foreign keys are persisted
+    let db = Database::open_file(&db_path).unwrap();
+    let orders = db.collection::<Document>("orders");
+    orders.insert_one(doc! { "_id": 10, "customer_id": 1 }).unwrap();
+    // nothing is referenced
+    orders.insert_one(doc! { "_id": 11 }).unwrap();
+    orders.insert_one(doc! { "_id": 12, "customer_id": Bson::Null }).unwrap();
+
+    let result = orders.insert_one(doc! { "_id": 13, "customer_id": 3 });
+    assert!(matches!(result, Err(DbErr::ForeignKeyViolation(_))));
+    assert_eq!(orders.count_documents().unwrap(), 3);
+
+    let result = orders.insert_many(vec![
+        doc! { "_id": 14, "customer_id": 2 },
+        doc! { "_id": 15, "customer_id": 3 },
+    ]);
+    assert!(matches!(result, Err(DbErr::ForeignKeyViolation(_))));
+    assert_eq!(orders.count_documents().unwrap(), 3);
+
+    let result = orders.update_one(doc! { "_id": 10 }, doc! { "$set": { "customer_id": 3 } });
+    assert!(matches!(result, Err(DbErr::ForeignKeyViolation(_))));
+    assert_eq!(orders.find_one(doc! { "_id": 10 }).unwrap().unwrap().get_i32("customer_id").unwrap(), 1);
+    orders.update_one(doc! { "_id": 10 }, doc! { "$set": { "customer_id": 2 } }).unwrap();
+    // the other fields are updated without the reference changed
+    orders.update_one(doc! { "_id": 10 }, doc! { "$set": { "total": 100 } }).unwrap();
+    assert_eq!(orders.find_one(doc! { "_id": 10 }).unwrap().unwrap().get_i32("customer_id").unwrap(), 2);
+}
+
+#[test]
+fn test_foreign_key_restrict() {
+    let db = Database::open_memory().unwrap();
+    create_shop(&db, OnDelete::Restrict);
+    let customers = db.collection::<Document>("customers");
+    let orders = db.collection::<Document>("orders");
+    orders.insert_one(doc! { "_id": 10, "customer_id": 1 }).unwrap();
+
+    let result = customers.delete_one(doc! { "_id": 1 });
+    assert!(matches!(result, Err(DbErr::ForeignKeyViolation(_))));
+    assert_eq!(customers.count_documents().unwrap(), 2);
+    assert!(customers.find_one(doc! { "_id": 1 }).unwrap().is_some());
+
+    assert_eq!(customers.delete_one(doc! { "_id": 2 }).unwrap().deleted_count, 1);
+    orders.delete_one(doc! { "_id": 10 }).unwrap();
+    assert_eq!(customers.delete_one(doc! { "_id": 1 }).unwrap().deleted_count, 1);
+}
+
+#[test]
+fn test_foreign_key_restrict_self() {
+    let db = Database::open_memory().unwrap();
+    db.create_collection_with_options("staff", CreateCollectionOptions {
+        foreign_keys: vec![ForeignKey::new("manager_id", "staff", OnDelete::Restrict)],
+        ..Default::default()
+    }).unwrap();
+    let staff = db.collection::<Document>("staff");
+    staff.insert_many(vec![
+        doc! { "_id": 1, "team": "a" },
+        doc! { "_id": 2, "team": "a", "manager_id": 1 },
+        doc! { "_id": 3, "team": "b", "manager_id": 1 },
+    ]).unwrap();
+
+    let result = staff.delete_many(doc! { "team": "a" });
+    assert!(matches!(result, Err(DbErr::ForeignKeyViolation(_))));
+    assert_eq!(staff.count_documents().unwrap(), 3);
+
+    // the documents referencing each other are deleted together
+    staff.delete_one(doc! { "_id": 3 }).unwrap();
+    assert_eq!(staff.delete_many(doc! { "team": "a" }).unwrap().deleted_count, 2);
+}
+
+#[test]
+fn test_foreign_key_set_null() {
+    let db = Database::open_memory().unwrap();
+    create_shop(&db, OnDelete::SetNull);
+    let orders = db.collection::<Document>("orders");
+    orders.insert_many(vec![
+        doc! { "_id": 10, "customer_id": 1 },
+        doc! { "_id": 11, "customer_id": 2 },
+    ]).unwrap();
+
+    db.collection::<Document>("customers").delete_many(doc! { "name": "Alice" }).unwrap();
+    let order = orders.find_one(doc! { "_id": 10 }).unwrap().unwrap();
+    assert_eq!(order.get("customer_id"), Some(&Bson::Null));
+    assert_eq!(orders.find_one(doc! { "_id": 11 }).unwrap().unwrap().get_i32("customer_id").unwrap(), 2);
+}
+
+#[test]
+fn test_foreign_key_cascade() {
+    let db = Database::open_memory().unwrap();
+    create_shop(&db, OnDelete::Cascade);
+    db.create_collection_with_options("order_items", CreateCollectionOptions {
+        foreign_keys: vec![ForeignKey::new("order_id", "orders", OnDelete::Cascade)],
+        ..Default::default()
+    }).unwrap();
+
+    let orders = db.collection::<Document>("orders");
+    let items = db.collection::<Document>("order_items");
+    orders.insert_many(vec![
+        doc! { "_id": 10, "customer_id": 1 },
+        doc! { "_id": 11, "customer_id": 1 },
+        doc! { "_id": 12, "customer_id": 2 },
+    ]).unwrap();
+    items.insert_many(vec![
+        doc! { "order_id": 10, "sku": "a" },
+        doc! { "order_id": 11, "sku": "b" },
+        doc! { "order_id": 12, "sku": "c" },
+    ]).unwrap();
+
+    db.collection::<Document>("customers").delete_one(doc! { "_id": 1 }).unwrap();
+    assert_eq!(orders.count_documents().unwrap(), 1);
+    let skus: Vec<String> = items.find(None).unwrap()
+        .map(|doc| doc.unwrap().get_str("sku").unwrap().to_string())
+        .collect();
+    assert_eq!(skus, vec!["c".to_string()]);
+}
@@ -0,0 +1,108 @@
+use polodb_core::{Database, DbErr, IndexOptions};
+use polodb_core::bson::{doc, Document};
+
+mod common;
+
+use common::{mk_db_path, mk_journal_path, prepare_db};
+
+fn read_u32_be(bytes: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0u8; 4];
+    buffer.copy_from_slice(&bytes[offset..(offset + 4)]);
+    u32::from_be_bytes(buffer)
+}
+
+#[test]
+fn test_header_page_format() {
+    let db_path = mk_db_path("test-header-page-format");
+    {
+        let db = prepare_db("test-header-page-format").unwrap();
+        db.collection::<Document>("test").insert_one(doc! { "_id": 1 }).unwrap();
+    }
+
+    let bytes = std::fs::read(&db_path).unwrap();
+    assert_eq!(bytes.len() % 4096, 0);
+    assert_eq!(&bytes[0..18], b"PoloDB Format v3.1");
+    assert_eq!(&bytes[32..36], &[0, 0, 3, 1]);
+    // the integers are big-endian on all the platforms
+    assert_eq!(&bytes[44..48], &[0x00, 0x00, 0x10, 0x00]);
+    assert_eq!(read_u32_be(&bytes, 52), 1);
+    assert!(read_u32_be(&bytes, 48) >= 2);
+    assert_eq!(&bytes[72..80], &[0; 8]);
+    // the first metadata entry is the creation time
+    assert_eq!(&bytes[84..94], b"\x09createdAt");
+    assert_eq!(&bytes[94..96], &[0x00, 0x08]);
+}
+
+#[test]
+fn test_journal_header_format() {
+    let db_path = mk_db_path("test-journal-header-format");
+    let db = prepare_db("test-journal-header-format").unwrap();
+    db.collection::<Document>("test").insert_one(doc! { "_id": 1 }).unwrap();
+
+    let bytes = std::fs::read(mk_journal_path("test-journal-header-format")).unwrap();
+    assert_eq!(&bytes[0..19], b"PoloDB Journal v0.3");
+    assert_eq!(read_u32_be(&bytes, 36), 4096);
+
+    drop(db);
+    assert!(std::fs::metadata(&db_path).is_ok());
+}
+
+#[test]
+fn test_integer_keys_round_trip() {
+    let db_path = mk_db_path("test-integer-keys-round-trip");
+    let ids: Vec<i64> = (-300..300)
+        .chain([i64::MIN, i64::MAX, 1 << 59, (1 << 59) - 1, 0x0FFF_FFFF_FFFF_FFFF].iter().cloned())
+        .collect();
+    {
+        let db = prepare_db("test-integer-keys-round-trip").unwrap();
+        let collection = db.collection::<Document>("test");
+        for id in &ids {
+            collection.insert_one(doc! { "_id": *id, "content": id.to_string() }).unwrap();
+        }
+    }
+
+    let db = Database::open_file(&db_path).unwrap();
+    let collection = db.collection::<Document>("test");
+    assert_eq!(collection.count_documents().unwrap(), ids.len() as u64);
+    for id in &ids {
+        let result = collection.find_one(doc! { "_id": *id }).unwrap().unwrap();
+        assert_eq!(result.get_str("content").unwrap(), id.to_string());
+    }
+}
+
+#[test]
+fn test_inspect_header() {
+    let db_path = mk_db_path("test-inspect-header");
+    let db = prepare_db("test-inspect-header").unwrap();
+    let books = db.collection::<Document>("books");
+    books.insert_one(doc! { "_id": 1, "title": "Dune" }).unwrap();
+    books.create_index(doc! { "title": 1 }, IndexOptions::default()).unwrap();
+    db.collection::<Document>("authors").insert_one(doc! { "_id": 1 }).unwrap();
+    db.checkpoint().unwrap();
+
+    // the database opened is not locked against the inspection
+    let header = Database::inspect_header(&db_path).unwrap();
+    assert!(header.identifier.starts_with("PoloDB"));
+    assert_eq!(header.version, "0.0.3.1");
+    assert_eq!(header.page_size.get(), 4096);
+    assert_eq!(header.page_count, std::fs::metadata(&db_path).unwrap().len() / 4096);
+    assert!(header.created_at.is_some());
+    assert!(header.checkpoint_lsn.unwrap() > 0);
+    assert!(header.checkpoint_at.unwrap() >= header.created_at.unwrap());
+
+    let mut names: Vec<&str> = header.collections.iter().map(|col| col.name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, vec!["authors", "books"]);
+    let books_summary = header.collections.iter().find(|col| col.name == "books").unwrap();
+    assert_eq!(books_summary.indexes, vec!["title_1".to_string()]);
+
+    // the collection in the journal is not seen
+    db.collection::<Document>("pending").insert_one(doc! { "_id": 1 }).unwrap();
+    let header = Database::inspect_header(&db_path).unwrap();
+    assert_eq!(header.collections.len(), 2);
+    assert!(header.journal_size.unwrap() > 0);
+
+    let text_path = mk_db_path("test-inspect-header-text");
+    std::fs::write(&text_path, b"not a database").unwrap();
+    assert!(matches!(Database::inspect_header(&text_path), Err(DbErr::NotAValidDatabase)));
+}
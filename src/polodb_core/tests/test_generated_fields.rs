@@ -0,0 +1,92 @@
+use polodb_core::{CreateCollectionOptions, Database, DbErr, GeneratedField, IndexOptions};
+use polodb_core::bson::{doc, Document};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn create_users(db: &Database) {
+    db.create_collection_with_options("users", CreateCollectionOptions {
+        generated_fields: vec![
+            GeneratedField::new("email_lower", doc! {
+                "$toLower": { "$trim": { "input": "$email" } },
+            }),
+            GeneratedField::new("display", doc! {
+                "$concat": ["$name", " <", "$email_lower", ">"],
+            }),
+        ],
+        ..Default::default()
+    }).unwrap();
+}
+
+#[test]
+fn test_generated_fields_writes() {
+    let db_path = mk_db_path("test-generated-fields");
+    {
+        let db = prepare_db("test-generated-fields").unwrap();
+        create_users(&db);
+        let users = db.collection::<Document>("users");
+        // the value written is replaced
+        users.insert_one(doc! {
+            "_id": 1,
+            "name": "Alice",
+            "email": " Alice@Example.COM ",
+            "email_lower": "wrong",
+        }).unwrap();
+
+        let alice = users.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+        assert_eq!(alice.get_str("email_lower").unwrap(), "alice@example.com");
+        assert_eq!(alice.get_str("display").unwrap(), "Alice <alice@example.com>");
+    }
+
+    // the fields are persisted
+    let db = Database::open_file(&db_path).unwrap();
+    let users = db.collection::<Document>("users");
+    users.update_one(doc! { "_id": 1 }, doc! { "$set": { "email": "ALICE@test.org" } }).unwrap();
+    let alice = users.find_one(doc! { "email_lower": "alice@test.org" }).unwrap().unwrap();
+    assert_eq!(alice.get_str("display").unwrap(), "Alice <alice@test.org>");
+
+    users.update_one(doc! { "_id": 1 }, doc! { "$unset": { "email": "" } }).unwrap();
+    let alice = users.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    assert_eq!(alice.get_str("email_lower").unwrap(), "");
+}
+
+#[test]
+fn test_generated_fields_index() {
+    let db = Database::open_memory().unwrap();
+    create_users(&db);
+    let users = db.collection::<Document>("users");
+    users.create_index(doc! { "email_lower": 1 }, IndexOptions {
+        unique: Some(true),
+        ..Default::default()
+    }).unwrap();
+
+    users.insert_one(doc! { "_id": 1, "name": "Alice", "email": "alice@example.com" }).unwrap();
+    let result = users.insert_one(doc! { "_id": 2, "name": "Alice", "email": "ALICE@example.com" });
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+
+    users.insert_one(doc! { "_id": 2, "name": "Bob", "email": "bob@example.com" }).unwrap();
+    let result = users.update_one(doc! { "_id": 2 }, doc! { "$set": { "email": "Alice@Example.com" } });
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+    assert_eq!(users.find_one(doc! { "email_lower": "bob@example.com" }).unwrap().unwrap().get_i32("_id").unwrap(), 2);
+
+    // the documents updated together can't take the same value
+    let result = users.update_many(doc! {}, doc! { "$set": { "email": "carol@example.com" } });
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+    assert_eq!(users.find_one(doc! { "_id": 1 }).unwrap().unwrap().get_str("email").unwrap(), "alice@example.com");
+
+    // the value kept by the document itself is not a duplicate
+    users.update_one(doc! { "_id": 1 }, doc! { "$set": { "email": "ALICE@example.com" } }).unwrap();
+}
+
+#[test]
+fn test_generated_fields_invalid() {
+    let db = Database::open_memory().unwrap();
+    for field in ["", "_id", "a.b", "$a"] {
+        let result = db.create_collection_with_options("users", CreateCollectionOptions {
+            generated_fields: vec![GeneratedField::new(field, "$name")],
+            ..Default::default()
+        });
+        assert!(matches!(result, Err(DbErr::ValidationError(_))));
+    }
+}
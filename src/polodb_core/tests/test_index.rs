@@ -0,0 +1,138 @@
+use polodb_core::{Database, DbErr, IndexOptions};
+use polodb_core::bson::{Document, doc};
+use polodb_core::explain::{ExplainVerbosity, STAGE_COLLSCAN, STAGE_IXSCAN};
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn find_ids(db: &Database, query: Document) -> Vec<i32> {
+    let collection = db.collection::<Document>("users");
+    let mut ids: Vec<i32> = collection.find_many(query)
+        .unwrap()
+        .iter()
+        .map(|doc| doc.get_i32("_id").unwrap())
+        .collect();
+    ids.sort_unstable();
+    ids
+}
+
+#[test]
+fn test_index_find() {
+    vec![
+        prepare_db("test-index-find").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("users");
+        for i in 0..200 {
+            let mut doc = doc! { "_id": i, "name": format!("user-{}", i % 7) };
+            if i % 10 != 0 {
+                doc.insert("age", i % 50);
+            }
+            collection.insert_one(doc).unwrap();
+        }
+
+        let queries = vec![
+            doc! { "age": 18 },
+            doc! { "age": 18.0 },
+            doc! { "age": { "$gte": 20, "$lt": 25 } },
+            doc! { "age": { "$in": [3, 41, 100] } },
+            doc! { "age": 18, "name": "user-4" },
+            doc! { "age": null },
+        ];
+        let expected: Vec<Vec<i32>> = queries.iter().map(|query| find_ids(db, query.clone())).collect();
+
+        let result = collection.create_index(doc! { "age": 1 }, IndexOptions::default()).unwrap();
+        assert_eq!(result.index_name, "age_1");
+
+        for (query, expected) in queries.iter().zip(expected.iter()) {
+            assert_eq!(find_ids(db, query.clone()), *expected, "query: {}", query);
+        }
+
+        let explain = collection.explain(doc! { "age": 18 }, ExplainVerbosity::ExecutionStats).unwrap();
+        assert_eq!(explain.stages, vec![STAGE_IXSCAN.to_string()]);
+        let stats = explain.execution_stats.unwrap();
+        assert_eq!(stats.n_returned, 4);
+        assert_eq!(stats.total_docs_examined, 4);
+
+        collection.drop_index("age_1").unwrap();
+        let explain = collection.explain(doc! { "age": 18 }, ExplainVerbosity::QueryPlanner).unwrap();
+        assert_eq!(explain.stages, vec![STAGE_COLLSCAN.to_string()]);
+        assert!(matches!(collection.drop_index("age_1"), Err(DbErr::IndexNotFound(_))));
+    });
+}
+
+#[test]
+fn test_index_unique() {
+    let db = prepare_db("test-index-unique").unwrap();
+    let collection = db.collection::<Document>("users");
+    collection.insert_one(doc! { "_id": 1, "email": "a@example.com" }).unwrap();
+    collection.insert_one(doc! { "_id": 2, "email": "a@example.com" }).unwrap();
+
+    let options = IndexOptions {
+        unique: Some(true),
+        ..Default::default()
+    };
+    let result = collection.create_index(doc! { "email": 1 }, options.clone());
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+
+    collection.delete_one(doc! { "_id": 2 }).unwrap();
+    collection.create_index(doc! { "email": 1 }, options.clone()).unwrap();
+    // creating the same index again does nothing
+    collection.create_index(doc! { "email": 1 }, options).unwrap();
+
+    let result = collection.insert_one(doc! { "_id": 3, "email": "a@example.com" });
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+    assert_eq!(collection.count_documents().unwrap(), 1);
+
+    collection.insert_one(doc! { "_id": 3, "email": "b@example.com" }).unwrap();
+    let result = collection.update_one(doc! { "_id": 3 }, doc! { "$set": { "email": "a@example.com" } });
+    assert!(matches!(result, Err(DbErr::DuplicateKey(_))));
+    let doc = collection.find_one(doc! { "_id": 3 }).unwrap().unwrap();
+    assert_eq!(doc.get_str("email").unwrap(), "b@example.com");
+}
+
+#[test]
+fn test_index_update_delete() {
+    let db = prepare_db("test-index-update-delete").unwrap();
+    let collection = db.collection::<Document>("users");
+    collection.create_index(doc! { "age": 1 }, IndexOptions::default()).unwrap();
+    for i in 0..100 {
+        collection.insert_one(doc! { "_id": i, "age": i % 10 }).unwrap();
+    }
+
+    collection.update_many(doc! { "age": 3 }, doc! { "$set": { "age": 30 } }).unwrap();
+    assert!(find_ids(&db, doc! { "age": 3 }).is_empty());
+    assert_eq!(find_ids(&db, doc! { "age": 30 }).len(), 10);
+
+    collection.delete_many(doc! { "age": { "$gt": 5 } }).unwrap();
+    assert_eq!(find_ids(&db, doc! { "age": { "$gte": 0 } }).len(), 50);
+    assert!(find_ids(&db, doc! { "age": 30 }).is_empty());
+
+    collection.truncate().unwrap();
+    assert!(find_ids(&db, doc! { "age": 1 }).is_empty());
+    collection.insert_one(doc! { "_id": 1, "age": 1 }).unwrap();
+    assert_eq!(find_ids(&db, doc! { "age": 1 }), vec![1]);
+}
+
+#[test]
+fn test_index_reopen() {
+    let db_path = mk_db_path("test-index-reopen");
+    {
+        let db = prepare_db("test-index-reopen").unwrap();
+        let collection = db.collection::<Document>("users");
+        for i in 0..50 {
+            collection.insert_one(doc! { "_id": i, "age": i % 5 }).unwrap();
+        }
+        collection.create_index(doc! { "age": 1 }, IndexOptions {
+            name: Some("by_age".into()),
+            ..Default::default()
+        }).unwrap();
+    }
+
+    let db = Database::open_file(db_path).unwrap();
+    let collection = db.collection::<Document>("users");
+    let explain = collection.explain(doc! { "age": 2 }, ExplainVerbosity::QueryPlanner).unwrap();
+    assert_eq!(explain.stages, vec![STAGE_IXSCAN.to_string()]);
+    assert_eq!(find_ids(&db, doc! { "age": 2 }).len(), 10);
+}
@@ -4,7 +4,7 @@ use std::path::PathBuf;
 use bson::Document;
 use bson::spec::ElementType;
 use serde::{Deserialize, Serialize};
-use polodb_core::Database;
+use polodb_core::{Database, InsertManyOptions};
 use polodb_core::bson::{doc, Bson};
 
 mod common;
@@ -209,3 +209,44 @@ fn test_insert_different_types_as_key() {
     assert_eq!(result[0].get("_id").unwrap().element_type(), ElementType::String);
     assert_eq!(result[1].get("_id").unwrap().element_type(), ElementType::Int32);
 }
+
+#[test]
+fn test_insert_many_unordered() {
+    vec![
+        prepare_db("test-insert-many-unordered").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        collection.insert_one(doc! { "_id": 2, "name": "Bob" }).unwrap();
+        let subscription = collection.subscribe(None).unwrap();
+
+        let docs = vec![
+            doc! { "_id": 1, "name": "Alice" },
+            doc! { "_id": 2, "name": "Bob" },
+            doc! { "_id": 3, "name": "Carol" },
+            doc! { "_id": 3, "name": "Dave" },
+        ];
+
+        // nothing is inserted by default
+        assert!(collection.insert_many(&docs).is_err());
+        assert_eq!(collection.count_documents().unwrap(), 1);
+
+        let result = collection.insert_many_with_options(&docs, InsertManyOptions {
+            ordered: Some(false),
+        }).unwrap();
+        let mut inserted: Vec<usize> = result.inserted_ids.keys().cloned().collect();
+        inserted.sort();
+        assert_eq!(inserted, vec![0, 2]);
+        let failed: Vec<usize> = result.write_errors.iter().map(|err| err.index).collect();
+        assert_eq!(failed, vec![1, 3]);
+        assert_eq!(collection.count_documents().unwrap(), 3);
+
+        let carol = collection.find_one(doc! { "_id": 3 }).unwrap().unwrap();
+        assert_eq!(carol.get_str("name").unwrap(), "Carol");
+
+        let keys: Vec<Bson> = std::iter::from_fn(|| subscription.try_recv())
+            .map(|event| event.document_key)
+            .collect();
+        assert_eq!(keys, vec![Bson::Int32(1), Bson::Int32(3)]);
+    });
+}
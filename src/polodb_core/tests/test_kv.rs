@@ -0,0 +1,90 @@
+use polodb_core::{Database, HandleOptions};
+use polodb_core::bson::{Bson, Document, doc};
+
+mod common;
+
+use common::prepare_db;
+
+#[test]
+fn test_kv_set_get_delete() {
+    let db = Database::open_memory().unwrap();
+    let settings = db.kv("settings");
+    assert_eq!(settings.namespace(), "settings");
+    assert_eq!(settings.get("theme").unwrap(), None);
+    assert!(!settings.delete("theme").unwrap());
+
+    settings.set("theme", "dark").unwrap();
+    settings.set("font_size", 14).unwrap();
+    assert_eq!(settings.get("theme").unwrap(), Some(Bson::String("dark".into())));
+
+    settings.set("theme", "light").unwrap();
+    settings.set("theme", "light").unwrap();
+    settings.set("window", doc! { "width": 800, "height": 600 }).unwrap();
+    assert_eq!(settings.get("theme").unwrap(), Some(Bson::String("light".into())));
+    assert_eq!(settings.get("window").unwrap(), Some(Bson::Document(doc! { "width": 800, "height": 600 })));
+
+    assert!(settings.delete("theme").unwrap());
+    assert_eq!(settings.get("theme").unwrap(), None);
+    assert_eq!(settings.get("font_size").unwrap(), Some(Bson::Int32(14)));
+
+    // the namespaces are separated, and hidden from the collections
+    assert_eq!(db.kv("other").get("font_size").unwrap(), None);
+    db.collection::<Document>("settings").insert_one(doc! { "_id": "font_size" }).unwrap();
+    assert_eq!(db.list_collection_names().unwrap(), vec!["settings".to_string()]);
+}
+
+#[test]
+fn test_kv_scan_prefix() {
+    let db = Database::open_memory().unwrap();
+    let flags = db.kv("flags");
+    for key in ["sync.enabled", "ui.compact", "ui.dark_mode", "ui/legacy", "u"] {
+        flags.set(key, true).unwrap();
+    }
+
+    let keys: Vec<String> = flags.scan_prefix("ui.").unwrap()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect();
+    assert_eq!(keys, vec!["ui.compact", "ui.dark_mode"]);
+    assert_eq!(flags.scan_prefix("").unwrap().len(), 5);
+    assert!(flags.scan_prefix("web.").unwrap().is_empty());
+    assert!(db.kv("empty").scan_prefix("ui.").unwrap().is_empty());
+}
+
+#[test]
+fn test_kv_in_session() {
+    let db = prepare_db("test-kv-in-session").unwrap();
+    let settings = db.kv("settings");
+    let users = db.collection::<Document>("users");
+
+    let mut session = db.start_session().unwrap();
+    session.start_transaction(None).unwrap();
+    users.insert_one_with_session(doc! { "_id": 1, "name": "Alice" }, &mut session).unwrap();
+    settings.set_with_session("current_user", 1, &mut session).unwrap();
+    assert_eq!(settings.get_with_session("current_user", &mut session).unwrap(), Some(Bson::Int32(1)));
+    session.abort_transaction().unwrap();
+
+    assert_eq!(settings.get("current_user").unwrap(), None);
+    assert_eq!(users.count_documents().unwrap(), 0);
+
+    session.start_transaction(None).unwrap();
+    users.insert_one_with_session(doc! { "_id": 1, "name": "Alice" }, &mut session).unwrap();
+    settings.set_with_session("current_user", 1, &mut session).unwrap();
+    session.commit_transaction().unwrap();
+
+    assert_eq!(settings.get("current_user").unwrap(), Some(Bson::Int32(1)));
+    assert_eq!(users.count_documents().unwrap(), 1);
+}
+
+#[test]
+fn test_kv_read_only() {
+    let db = Database::open_memory().unwrap();
+    db.kv("settings").set("theme", "dark").unwrap();
+
+    let reader = db.with_options(HandleOptions {
+        read_only: true,
+        ..HandleOptions::default()
+    });
+    assert_eq!(reader.kv("settings").get("theme").unwrap(), Some(Bson::String("dark".into())));
+    assert!(reader.kv("settings").set("theme", "light").is_err());
+}
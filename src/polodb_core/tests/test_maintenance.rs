@@ -0,0 +1,142 @@
+#![cfg(feature = "maintenance")]
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use polodb_core::{Config, Database};
+use polodb_core::bson::{doc, DateTime, Document};
+use polodb_core::clock::MockClock;
+use polodb_core::maintenance::{MaintenanceConfig, MaintenanceJob, MaintenanceScheduler, Progress};
+
+mod common;
+
+use common::prepare_db;
+
+#[test]
+fn test_expire_ttl() {
+    let db = Arc::new(prepare_db("test-maintenance-ttl").unwrap());
+    let sessions = db.collection::<Document>("sessions");
+    let expired = DateTime::from_system_time(SystemTime::now() - Duration::from_secs(7200));
+    for i in 0..250 {
+        let updated_at = if i % 5 == 0 { DateTime::now() } else { expired };
+        sessions.insert_one(doc! { "_id": i, "updatedAt": updated_at }).unwrap();
+    }
+
+    let scheduler = MaintenanceScheduler::start(db.clone(), vec![
+        MaintenanceJob::ExpireTtl {
+            collection: "sessions".into(),
+            field: "updatedAt".into(),
+            expire_after: Duration::from_secs(3600),
+        },
+        MaintenanceJob::Checkpoint,
+        MaintenanceJob::RefreshStats,
+    ], MaintenanceConfig {
+        interval: Duration::from_secs(3600),
+        budget: Duration::from_secs(10),
+        batch_size: 30,
+        ..Default::default()
+    });
+    scheduler.run_now();
+
+    assert_eq!(sessions.count_documents().unwrap(), 50);
+}
+
+#[test]
+fn test_expire_ttl_by_clock() {
+    let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+    let db = Arc::new(Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        ..Default::default()
+    }).unwrap());
+    let sessions = db.collection::<Document>("sessions");
+    sessions.insert_one(doc! { "_id": 1, "updatedAt": DateTime::from_millis(1_700_000_000_000) }).unwrap();
+
+    let scheduler = MaintenanceScheduler::start(db.clone(), vec![
+        MaintenanceJob::ExpireTtl {
+            collection: "sessions".into(),
+            field: "updatedAt".into(),
+            expire_after: Duration::from_secs(3600),
+        },
+    ], MaintenanceConfig {
+        interval: Duration::from_secs(3600),
+        ..Default::default()
+    });
+
+    clock.advance(Duration::from_secs(1800));
+    scheduler.run_now();
+    assert_eq!(sessions.count_documents().unwrap(), 1);
+
+    clock.advance(Duration::from_secs(1801));
+    scheduler.run_now();
+    assert_eq!(sessions.count_documents().unwrap(), 0);
+}
+
+#[test]
+fn test_expire_at() {
+    let clock = Arc::new(MockClock::new(Duration::from_secs(1_700_000_000)));
+    let db = Arc::new(Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        ..Default::default()
+    }).unwrap());
+    let cache = db.collection::<Document>("cache");
+    for i in 0..100 {
+        let expire_at = DateTime::from_millis(1_700_000_000_000 + if i % 2 == 0 { 1000 } else { 3_600_000 });
+        cache.insert_one(doc! { "_id": i, "expireAt": expire_at }).unwrap();
+    }
+
+    let scheduler = MaintenanceScheduler::start(db.clone(), vec![
+        MaintenanceJob::ExpireAt {
+            collection: "cache".into(),
+        },
+    ], MaintenanceConfig {
+        interval: Duration::from_secs(3600),
+        budget: Duration::from_secs(10),
+        batch_size: 30,
+        ..Default::default()
+    });
+
+    scheduler.run_now();
+    assert_eq!(cache.count_documents().unwrap(), 100);
+
+    clock.advance(Duration::from_secs(2));
+    scheduler.run_now();
+    assert_eq!(cache.count_documents().unwrap(), 50);
+}
+
+#[test]
+fn test_interrupted_job_resumed() {
+    let db = Arc::new(Database::open_memory().unwrap());
+    let calls = Arc::new(AtomicUsize::new(0));
+    let slow_calls = calls.clone();
+    let fast_calls = Arc::new(AtomicUsize::new(0));
+    let fast_calls2 = fast_calls.clone();
+
+    let scheduler = MaintenanceScheduler::start(db, vec![
+        MaintenanceJob::Custom {
+            name: "slow".into(),
+            run: Arc::new(move |_db: &Database, _deadline: Instant| {
+                // interrupted in the first round, done in the second one
+                let call = slow_calls.fetch_add(1, Ordering::SeqCst);
+                Ok(if call == 0 { Progress::Interrupted } else { Progress::Done })
+            }),
+        },
+        MaintenanceJob::Custom {
+            name: "fast".into(),
+            run: Arc::new(move |_db: &Database, _deadline: Instant| {
+                fast_calls2.fetch_add(1, Ordering::SeqCst);
+                Ok(Progress::Done)
+            }),
+        },
+    ], MaintenanceConfig {
+        interval: Duration::from_secs(3600),
+        ..Default::default()
+    });
+
+    scheduler.run_now();
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(fast_calls.load(Ordering::SeqCst), 0);
+
+    scheduler.run_now();
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(fast_calls.load(Ordering::SeqCst), 1);
+}
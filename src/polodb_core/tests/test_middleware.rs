@@ -0,0 +1,108 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use polodb_core::{Database, DbErr, DbResult, HandleRequestResult};
+use polodb_core::bson::{self, doc, Bson};
+use polodb_core::commands::CommandMessage;
+use polodb_core::middleware::{AuthMiddleware, Middleware, Next, Role, ValidationMiddleware};
+
+struct CountingMiddleware {
+    count: Arc<AtomicUsize>,
+}
+
+impl Middleware for CountingMiddleware {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        next.run(command)
+    }
+
+}
+
+struct DenyWrite;
+
+impl Middleware for DenyWrite {
+
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        if command.is_write() {
+            return Err(DbErr::PermissionDenied(command.name().into()));
+        }
+        next.run(command)
+    }
+
+}
+
+fn insert_request(ns: &str) -> Bson {
+    Bson::Document(doc! {
+        "command": "Insert",
+        "ns": ns,
+        "documents": [
+            { "name": "Vincent" },
+        ],
+    })
+}
+
+#[test]
+fn test_middleware_chain() {
+    let db = Database::open_memory().unwrap();
+    let count = Arc::new(AtomicUsize::new(0));
+    db.use_middleware(CountingMiddleware {
+        count: count.clone(),
+    });
+
+    db.handle_request_doc(insert_request("test")).unwrap();
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "test",
+    })).unwrap();
+
+    assert_eq!(count.load(Ordering::SeqCst), 2);
+    assert_eq!(bson::from_bson::<i64>(result.value).unwrap(), 1);
+}
+
+#[test]
+fn test_middleware_reject() {
+    let db = Database::open_memory().unwrap();
+    db.use_middleware(ValidationMiddleware);
+    db.use_middleware(DenyWrite);
+
+    let err = db.handle_request_doc(insert_request("$bad")).unwrap_err();
+    assert!(matches!(err, DbErr::IllegalCollectionName(_)));
+
+    let err = db.handle_request_doc(insert_request("test")).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+
+    assert_eq!(db.collection::<bson::Document>("test").count_documents().unwrap(), 0);
+}
+
+#[test]
+fn test_middleware_auth() {
+    let db = Database::open_memory().unwrap();
+    db.handle_request_doc(insert_request("logs")).unwrap();
+    db.use_middleware(AuthMiddleware::new()
+        .grant_all(Role::Read)
+        .grant("test", Role::ReadWrite));
+
+    db.handle_request_doc(insert_request("test")).unwrap();
+
+    let err = db.handle_request_doc(insert_request("logs")).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+
+    let result = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "logs",
+    })).unwrap();
+    assert_eq!(bson::from_bson::<i64>(result.value).unwrap(), 1);
+
+    let err = db.handle_request_doc(Bson::Document(doc! {
+        "command": "Compact",
+    })).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+
+    let db = Database::open_memory().unwrap();
+    db.use_middleware(AuthMiddleware::new());
+    let err = db.handle_request_doc(Bson::Document(doc! {
+        "command": "CountDocuments",
+        "ns": "test",
+    })).unwrap_err();
+    assert!(matches!(err, DbErr::PermissionDenied(_)));
+}
@@ -0,0 +1,90 @@
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{doc, Document};
+use polodb_core::migrations::Migration;
+
+mod common;
+
+use common::{mk_db_path, prepare_db};
+
+fn register_user_migrations(db: &Database) {
+    db.register_migration(Migration::new(1, "create the users", |db, session| {
+        db.collection::<Document>("users").insert_many_with_session(vec![
+            doc! { "_id": 1, "name": "Alice" },
+            doc! { "_id": 2, "name": "Bob" },
+        ], session)?;
+        Ok(())
+    }));
+    db.register_migration(Migration::pipeline(2, "split the names", "users", vec![
+        doc! { "$project": { "first": "$name", "active": { "$literal": true } } },
+    ]));
+}
+
+#[test]
+fn test_migrate_once() {
+    let db_path = mk_db_path("test-migrate-once");
+    {
+        let db = prepare_db("test-migrate-once").unwrap();
+        register_user_migrations(&db);
+
+        let result = db.migrate().unwrap();
+        assert_eq!(result.applied, vec![1, 2]);
+        assert_eq!(result.version, Some(2));
+
+        let users = db.collection::<Document>("users");
+        assert_eq!(users.find_one(doc! { "_id": 1 }).unwrap().unwrap(), doc! {
+            "_id": 1,
+            "first": "Alice",
+            "active": true,
+        });
+        // the records are not collections
+        assert_eq!(db.list_collection_names().unwrap(), vec!["users".to_string()]);
+    }
+
+    let db = Database::open_file(&db_path).unwrap();
+    register_user_migrations(&db);
+    db.register_migration(Migration::new(3, "deactivate bob", |db, session| {
+        db.collection::<Document>("users")
+            .update_one_with_session(doc! { "_id": 2 }, doc! { "$set": { "active": false } }, session)?;
+        Ok(())
+    }));
+
+    let result = db.migrate().unwrap();
+    assert_eq!(result.applied, vec![3]);
+    assert_eq!(result.version, Some(3));
+    assert_eq!(db.applied_migrations().unwrap(), vec![1, 2, 3]);
+    assert_eq!(db.collection::<Document>("users").count_documents().unwrap(), 2);
+}
+
+#[test]
+fn test_migrate_failed() {
+    let db = Database::open_memory().unwrap();
+    db.register_migration(Migration::new(1, "create the items", |db, session| {
+        db.collection::<Document>("items").insert_one_with_session(doc! { "_id": 1 }, session)?;
+        Ok(())
+    }));
+    db.register_migration(Migration::new(2, "broken", |db, session| {
+        db.collection::<Document>("items").insert_one_with_session(doc! { "_id": 2 }, session)?;
+        Err(DbErr::ValidationError("broken".into()))
+    }));
+
+    let result = db.migrate();
+    assert!(matches!(result, Err(DbErr::ValidationError(_))));
+    // the failed migration is rolled back, the one before it is kept
+    assert_eq!(db.applied_migrations().unwrap(), vec![1]);
+    assert_eq!(db.collection::<Document>("items").count_documents().unwrap(), 1);
+
+    db.register_migration(Migration::new(2, "fixed", |_db, _session| Ok(())));
+    assert_eq!(db.migrate().unwrap().applied, vec![2]);
+}
+
+#[test]
+fn test_migrate_out_of_order() {
+    let db = Database::open_memory().unwrap();
+    db.register_migration(Migration::new(5, "five", |_db, _session| Ok(())));
+    assert_eq!(db.migrate().unwrap().applied, vec![5]);
+
+    db.register_migration(Migration::new(3, "three", |_db, _session| Ok(())));
+    let result = db.migrate();
+    assert!(matches!(result, Err(DbErr::MigrationOutOfOrder(3, 5))));
+    assert_eq!(db.applied_migrations().unwrap(), vec![5]);
+}
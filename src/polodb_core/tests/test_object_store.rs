@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use polodb_core::{Config, Database, DbErr, DbResult, ObjectStore};
+use polodb_core::bson::{doc, Document};
+
+#[derive(Default)]
+struct MemoryStore {
+    objects: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl ObjectStore for MemoryStore {
+
+    fn get(&self, key: &str) -> DbResult<Option<Vec<u8>>> {
+        Ok(self.objects.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> DbResult<()> {
+        self.objects.lock().unwrap().insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+}
+
+#[derive(Default)]
+struct CountingStore {
+    store: MemoryStore,
+    gets:  AtomicUsize,
+}
+
+impl CountingStore {
+
+    fn gets(&self) -> usize {
+        self.gets.load(Ordering::SeqCst)
+    }
+
+}
+
+impl ObjectStore for CountingStore {
+
+    fn get(&self, key: &str) -> DbResult<Option<Vec<u8>>> {
+        self.gets.fetch_add(1, Ordering::SeqCst);
+        self.store.get(key)
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> DbResult<()> {
+        self.store.put(key, data)
+    }
+
+}
+
+#[test]
+fn test_object_store_share() {
+    let store = Arc::new(MemoryStore::default());
+    let writer = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+    let collection = writer.collection::<Document>("test");
+    for i in 0..100 {
+        collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+    }
+    writer.checkpoint().unwrap();
+
+    // another machine opens the version uploaded
+    let reader = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+    assert_eq!(reader.collection::<Document>("test").count_documents().unwrap(), 100);
+
+    for i in 100..150 {
+        collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+    }
+    writer.checkpoint().unwrap();
+
+    // the old version is still readable
+    assert_eq!(reader.collection::<Document>("test").count_documents().unwrap(), 100);
+    let one = reader.collection::<Document>("test").find_one(doc! { "_id": 42 }).unwrap().unwrap();
+    assert_eq!(one.get("content").unwrap().as_str().unwrap(), "Hello");
+
+    let new_reader = Database::open_object_store(store, "test", Config::default()).unwrap();
+    assert_eq!(new_reader.collection::<Document>("test").count_documents().unwrap(), 150);
+}
+
+#[test]
+fn test_object_store_upload_on_close() {
+    let store = Arc::new(MemoryStore::default());
+    {
+        let db = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+        let collection = db.collection::<Document>("test");
+        collection.insert_one(doc! { "_id": 0 }).unwrap();
+
+        let session = db.start_session().unwrap();
+        assert!(matches!(db.checkpoint(), Err(DbErr::Busy)));
+        drop(session);
+    }
+
+    let db = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 1);
+
+    // another prefix is another database
+    let other = Database::open_object_store(store, "other", Config::default()).unwrap();
+    assert_eq!(other.collection::<Document>("test").count_documents().unwrap(), 0);
+}
+
+#[test]
+fn test_object_store_prefetch() {
+    let store = Arc::new(CountingStore::default());
+    {
+        let db = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+        let collection = db.collection::<Document>("test");
+        for i in 0..100 {
+            collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+        }
+    }
+
+    let db = Database::open_object_store(store.clone(), "test", Config::default()).unwrap();
+    let fetched = db.prefetch().unwrap();
+    assert!(fetched > 0);
+
+    // the pages are read from the page cache
+    let gets = store.gets();
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 100);
+    assert_eq!(db.collection::<Document>("test").find_many(None).unwrap().len(), 100);
+    assert_eq!(store.gets(), gets);
+
+    // nothing is left to fetch
+    assert_eq!(db.prefetch().unwrap(), 0);
+    assert_eq!(Database::open_memory().unwrap().prefetch().unwrap(), 0);
+}
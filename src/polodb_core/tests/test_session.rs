@@ -1,4 +1,4 @@
-use polodb_core::{Database, DbErr};
+use polodb_core::{Database, DbErr, TransactionType};
 use polodb_core::bson::{Document, doc};
 
 mod common;
@@ -115,3 +115,82 @@ fn test_rollback() {
         assert_eq!(collection.count_documents_with_session(&mut session).unwrap(), 0);
     });
 }
+
+#[test]
+fn test_transaction_multiple_collections() {
+    vec![
+        prepare_db("test-transaction-multiple-collections").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let orders = db.collection::<Document>("orders");
+        let stocks = db.collection::<Document>("stocks");
+        stocks.insert_one(doc! { "_id": "apple", "count": 10 }).unwrap();
+
+        // the session started before a commit reads the latest version in its transaction
+        let mut session = db.start_session().unwrap();
+        stocks.insert_one(doc! { "_id": "pear", "count": 5 }).unwrap();
+
+        session.start_transaction(Some(TransactionType::Write)).unwrap();
+        orders.insert_one_with_session(doc! { "_id": 1, "item": "pear" }, &mut session).unwrap();
+        stocks.update_one_with_session(
+            doc! { "_id": "pear" },
+            doc! { "$inc": { "count": -1 } },
+            &mut session,
+        ).unwrap();
+
+        // the writes are not seen out of the transaction before it's committed
+        assert_eq!(orders.count_documents().unwrap(), 0);
+        assert_eq!(orders.count_documents_with_session(&mut session).unwrap(), 1);
+        session.commit_transaction().unwrap();
+
+        assert_eq!(orders.count_documents().unwrap(), 1);
+        let pear = stocks.find_one(doc! { "_id": "pear" }).unwrap().unwrap();
+        assert_eq!(pear.get_i32("count").unwrap(), 4);
+
+        // the next transaction is based on the commit of the session
+        session.start_transaction(None).unwrap();
+        orders.insert_one_with_session(doc! { "_id": 2, "item": "apple" }, &mut session).unwrap();
+        stocks.delete_one_with_session(doc! { "_id": "apple" }, &mut session).unwrap();
+        session.abort_transaction().unwrap();
+
+        assert_eq!(orders.count_documents().unwrap(), 1);
+        assert_eq!(stocks.count_documents().unwrap(), 2);
+
+        // out of a transaction the operation of the session is committed at once
+        orders.insert_one_with_session(doc! { "_id": 3, "item": "apple" }, &mut session).unwrap();
+        assert_eq!(orders.count_documents().unwrap(), 2);
+        assert!(matches!(session.commit_transaction(), Err(DbErr::NoTransactionStarted)));
+    });
+}
+
+#[test]
+fn test_with_transaction() {
+    let db = prepare_db("test-with-transaction").unwrap();
+    let col = db.collection::<Document>("test");
+    let mut session = db.start_session().unwrap();
+
+    let result = session.with_transaction(|session| {
+        col.insert_one_with_session(doc! { "_id": 1 }, session)?;
+        col.insert_one_with_session(doc! { "_id": 1 }, session)?;
+        Ok(())
+    });
+    assert!(result.is_err());
+    assert_eq!(col.count_documents().unwrap(), 0);
+
+    let count = session.with_transaction(|session| {
+        col.insert_many_with_session(vec![doc! { "_id": 1 }, doc! { "_id": 2 }], session)?;
+        col.count_documents_with_session(session)
+    }).unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(col.count_documents().unwrap(), 2);
+
+    // the outdated transaction is aborted, the session can start another one
+    session.start_transaction(None).unwrap();
+    col.insert_one_with_session(doc! { "_id": 3 }, &mut session).unwrap();
+    col.insert_one(doc! { "_id": 4 }).unwrap();
+    assert!(matches!(session.commit_transaction(), Err(DbErr::SessionOutdated)));
+    session.start_transaction(None).unwrap();
+    col.insert_one_with_session(doc! { "_id": 3 }, &mut session).unwrap();
+    session.commit_transaction().unwrap();
+    assert_eq!(col.count_documents().unwrap(), 4);
+}
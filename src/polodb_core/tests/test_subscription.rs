@@ -0,0 +1,115 @@
+use std::thread;
+use std::time::Duration;
+use polodb_core::Database;
+use polodb_core::bson::{Document, doc};
+use polodb_core::subscription::ChangeOperation;
+
+mod common;
+
+use common::prepare_db;
+
+#[test]
+fn test_subscribe_changes() {
+    let db = Database::open_memory().unwrap();
+    let users = db.collection::<Document>("users");
+    let subscription = users.subscribe(doc! { "age": { "$gte": 18 } }).unwrap();
+    let all = users.subscribe(None).unwrap();
+
+    users.insert_many(vec![
+        doc! { "_id": 1, "name": "Alice", "age": 20 },
+        doc! { "_id": 2, "name": "Bob", "age": 10 },
+    ]).unwrap();
+    users.update_one(doc! { "_id": 2 }, doc! { "$set": { "age": 18 } }).unwrap();
+    // nothing is changed
+    users.update_many(doc! { "_id": 1 }, doc! { "$set": { "age": 20 } }).unwrap();
+    users.delete_one(doc! { "_id": 1 }).unwrap();
+    db.collection::<Document>("orders").insert_one(doc! { "age": 30 }).unwrap();
+
+    let events: Vec<(ChangeOperation, i32)> = std::iter::from_fn(|| subscription.try_recv())
+        .map(|event| (event.operation, event.document_key.as_i32().unwrap()))
+        .collect();
+    assert_eq!(events, vec![
+        (ChangeOperation::Insert, 1),
+        (ChangeOperation::Update, 2),
+        (ChangeOperation::Delete, 1),
+    ]);
+
+    let events: Vec<ChangeOperation> = std::iter::from_fn(|| all.try_recv())
+        .map(|event| event.operation)
+        .collect();
+    assert_eq!(events, vec![
+        ChangeOperation::Insert,
+        ChangeOperation::Insert,
+        ChangeOperation::Update,
+        ChangeOperation::Delete,
+    ]);
+}
+
+#[test]
+fn test_subscribe_in_session() {
+    let db = prepare_db("test-subscribe-in-session").unwrap();
+    let collection = db.collection::<Document>("test");
+    let subscription = collection.subscribe(None).unwrap();
+
+    let mut session = db.start_session().unwrap();
+    session.start_transaction(None).unwrap();
+    collection.insert_one_with_session(doc! { "_id": 1 }, &mut session).unwrap();
+    assert!(subscription.try_recv().is_none());
+    session.commit_transaction().unwrap();
+    let event = subscription.try_recv().unwrap();
+    assert_eq!(event.operation, ChangeOperation::Insert);
+    assert_eq!(event.collection, "test");
+
+    session.start_transaction(None).unwrap();
+    collection.insert_one_with_session(doc! { "_id": 2 }, &mut session).unwrap();
+    session.abort_transaction().unwrap();
+    assert!(subscription.try_recv().is_none());
+}
+
+#[test]
+fn test_watch() {
+    let db = prepare_db("test-watch").unwrap();
+    let collection = db.collection::<Document>("test");
+    let watch = collection.watch().unwrap();
+
+    collection.insert_one(doc! { "_id": 1, "done": false }).unwrap();
+    collection.update_one(doc! { "_id": 1 }, doc! { "$set": { "done": true } }).unwrap();
+    collection.delete_one(doc! { "_id": 1 }).unwrap();
+
+    let events: Vec<(ChangeOperation, Document)> = watch
+        .take(3)
+        .map(|event| {
+            assert_eq!(event.document_key, 1.into());
+            (event.operation, event.full_document)
+        })
+        .collect();
+    assert_eq!(events, vec![
+        (ChangeOperation::Insert, doc! { "_id": 1, "done": false }),
+        (ChangeOperation::Update, doc! { "_id": 1, "done": true }),
+        (ChangeOperation::Delete, doc! { "_id": 1, "done": true }),
+    ]);
+}
+
+#[test]
+fn test_subscribe_across_threads() {
+    let db = Database::open_memory().unwrap();
+    let subscription = db.collection::<Document>("logs").subscribe(None).unwrap();
+
+    let writer = db.clone();
+    let handle = thread::spawn(move || {
+        let logs = writer.collection::<Document>("logs");
+        for i in 0..10 {
+            logs.insert_one(doc! { "i": i }).unwrap();
+        }
+    });
+
+    for i in 0..10 {
+        let event = subscription.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(event.full_document.get_i32("i").unwrap(), i);
+    }
+    handle.join().unwrap();
+
+    // the subscription ends with the database
+    drop(db);
+    assert!(subscription.recv().is_none());
+}
@@ -1,5 +1,9 @@
-use polodb_core::{Collection, Database};
-use polodb_core::bson::{Document, doc};
+use std::sync::Arc;
+use std::time::Duration;
+use polodb_core::{Collection, Config, CreateCollectionOptions, Database, DbErr, UpdateOptions};
+use polodb_core::bson::{DateTime, Document, Timestamp, doc};
+use polodb_core::clock::MockClock;
+use polodb_core::results::UpdateIfResult;
 
 mod common;
 
@@ -195,3 +199,174 @@ fn test_update_max() {
 //     println!("{:?}", content);
 //     // assert_eq!(result.get("content").unwrap().as_array().unwrap().len(), 4);
 // }
+
+#[test]
+fn test_update_one_if() {
+    vec![
+        prepare_db("test-update-one-if").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        db.create_collection_with_options("accounts", CreateCollectionOptions {
+            version_field: Some("version".into()),
+            ..Default::default()
+        }).unwrap();
+        let accounts = db.collection::<Document>("accounts");
+        accounts.insert_many(vec![
+            doc! { "_id": 1, "balance": 100, "version": 0 },
+            doc! { "_id": 2, "balance": 100 },
+        ]).unwrap();
+
+        // every update increases the version
+        accounts.update_many(doc! {}, doc! {
+            "$inc": { "balance": 10 },
+        }).unwrap();
+        let first = accounts.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+        assert_eq!(first.get_i32("version").unwrap(), 1);
+        assert_eq!(first.get_i32("balance").unwrap(), 110);
+        let second = accounts.find_one(doc! { "_id": 2 }).unwrap().unwrap();
+        assert_eq!(second.get_i32("version").unwrap(), 1);
+
+        let result = accounts.update_one_if(
+            doc! { "_id": 1, "version": 1 },
+            doc! { "$set": { "balance": 0 } },
+        ).unwrap();
+        assert_eq!(result, UpdateIfResult::Updated);
+
+        let result = accounts.update_one_if(
+            doc! { "_id": 1, "version": 1 },
+            doc! { "$set": { "balance": 50 } },
+        ).unwrap();
+        assert_eq!(result, UpdateIfResult::VersionConflict { current_version: 2.into() });
+        let first = accounts.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+        assert_eq!(first.get_i32("balance").unwrap(), 0);
+
+        let result = accounts.update_one_if(
+            doc! { "_id": 3, "version": 0 },
+            doc! { "$set": { "balance": 50 } },
+        ).unwrap();
+        assert_eq!(result, UpdateIfResult::NotFound);
+
+        // the version set by the update is not increased
+        accounts.update_one(doc! { "_id": 2 }, doc! {
+            "$set": { "version": 10 },
+        }).unwrap();
+        let second = accounts.find_one(doc! { "_id": 2 }).unwrap().unwrap();
+        assert_eq!(second.get_i32("version").unwrap(), 10);
+    });
+}
+
+#[test]
+fn test_update_one_if_without_version_field() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_one(doc! { "_id": 1, "version": 0 }).unwrap();
+
+    let result = collection.update_one_if(
+        doc! { "_id": 1, "version": 0 },
+        doc! { "$set": { "name": "Vincent" } },
+    );
+    assert!(matches!(result, Err(DbErr::ValidationError(_))));
+}
+
+#[test]
+fn test_update_matched_and_modified_count() {
+    let db = Database::open_memory().unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_many(vec![
+        doc! { "_id": 1, "group": "a", "score": 10 },
+        doc! { "_id": 2, "group": "a", "score": 20 },
+        doc! { "_id": 3, "group": "b", "score": 10 },
+    ]).unwrap();
+
+    let result = collection.update_many(
+        doc! { "group": "a" },
+        doc! { "$set": { "score": 10 } },
+    ).unwrap();
+    assert_eq!(result.matched_count, 2);
+    assert_eq!(result.modified_count, 1);
+    assert_eq!(result.upserted_id, None);
+
+    let result = collection.update_one(
+        doc! { "_id": 1 },
+        doc! { "$set": { "score": 10 } },
+    ).unwrap();
+    assert_eq!((result.matched_count, result.modified_count), (1, 0));
+
+    let result = db.collection::<Document>("missing").update_many(
+        doc! {},
+        doc! { "$set": { "score": 10 } },
+    ).unwrap();
+    assert_eq!((result.matched_count, result.modified_count), (0, 0));
+}
+
+#[test]
+fn test_update_upsert() {
+    vec![
+        prepare_db("test-update-upsert").unwrap(),
+        Database::open_memory().unwrap(),
+    ].iter().for_each(|db| {
+        let collection = db.collection::<Document>("test");
+        let options = UpdateOptions {
+            upsert: Some(true),
+        };
+
+        let result = collection.update_one_with_options(
+            doc! { "name": "Alice", "age": { "$eq": 20 }, "score": { "$gt": 10 } },
+            doc! { "$set": { "score": 30 } },
+            options.clone(),
+        ).unwrap();
+        assert_eq!((result.matched_count, result.modified_count), (0, 0));
+        let id = result.upserted_id.unwrap();
+
+        let doc = collection.find_one(doc! { "_id": id.clone() }).unwrap().unwrap();
+        assert_eq!(doc, doc! { "_id": id.clone(), "name": "Alice", "age": 20, "score": 30 });
+
+        // the document matches now
+        let result = collection.update_many_with_options(
+            doc! { "name": "Alice" },
+            doc! { "$inc": { "score": 1 } },
+            options,
+        ).unwrap();
+        assert_eq!((result.matched_count, result.modified_count), (1, 1));
+        assert_eq!(result.upserted_id, None);
+
+        // not inserted without the option
+        let result = collection.update_one(
+            doc! { "name": "Bob" },
+            doc! { "$set": { "score": 1 } },
+        ).unwrap();
+        assert_eq!(result.matched_count, 0);
+        assert_eq!(result.upserted_id, None);
+        assert_eq!(collection.count_documents().unwrap(), 1);
+    });
+}
+
+#[test]
+fn test_update_current_date() {
+    let clock = Arc::new(MockClock::new(Duration::from_millis(1_700_000_000_250)));
+    let db = Database::open_memory_with_config(Config {
+        clock: Some(clock.clone()),
+        ..Default::default()
+    }).unwrap();
+    let collection = db.collection::<Document>("test");
+    collection.insert_one(doc! { "_id": 1 }).unwrap();
+
+    clock.advance(Duration::from_secs(10));
+    collection.update_one(doc! { "_id": 1 }, doc! {
+        "$currentDate": {
+            "updated_at": true,
+            "checked_at": { "$type": "date" },
+            "version": { "$type": "timestamp" },
+        },
+    }).unwrap();
+
+    let result = collection.find_one(doc! { "_id": 1 }).unwrap().unwrap();
+    assert_eq!(result.get_datetime("updated_at").unwrap(), &DateTime::from_millis(1_700_000_010_250));
+    assert_eq!(result.get_datetime("checked_at").unwrap(), &DateTime::from_millis(1_700_000_010_250));
+    assert_eq!(result.get_timestamp("version").unwrap(), Timestamp { time: 1_700_000_010, increment: 1 });
+
+    let result = collection.update_one(doc! { "_id": 1 }, doc! {
+        "$currentDate": { "updated_at": { "$type": "string" } },
+    });
+    assert!(matches!(result, Err(DbErr::FieldTypeUnexpected(_))));
+}
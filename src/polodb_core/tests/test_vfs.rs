@@ -0,0 +1,144 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Arc;
+use polodb_core::{Config, Database, DbErr};
+use polodb_core::bson::{doc, Document};
+use polodb_core::vfs::{FaultVfs, MemoryVfs, Vfs};
+
+fn config_with_vfs(vfs: Arc<dyn Vfs>) -> Config {
+    Config {
+        vfs: Some(vfs),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_memory_vfs() {
+    let vfs = Arc::new(MemoryVfs::new());
+    let config = config_with_vfs(vfs.clone());
+    {
+        let db = Database::open_file_with_config("/test-memory-vfs.db", config.clone()).unwrap();
+        let collection = db.collection::<Document>("test");
+        for i in 0..100 {
+            collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+        }
+
+        // the file is locked by the first handle
+        let result = Database::open_file_with_config("/test-memory-vfs.db", config.clone());
+        assert!(matches!(result, Err(DbErr::DatabaseOccupied)));
+    }
+
+    // the journal is merged and removed on close
+    assert!(!vfs.exists(Path::new("/test-memory-vfs.db.journal")));
+    assert!(vfs.read_file(Path::new("/test-memory-vfs.db")).unwrap().len() >= 4096);
+
+    let db = Database::open_file_with_config("/test-memory-vfs.db", config).unwrap();
+    assert_eq!(db.collection::<Document>("test").count_documents().unwrap(), 100);
+}
+
+#[test]
+fn test_crash_recovery() {
+    const COMMITTED: i32 = 10;
+
+    for crash_after in (0..60).step_by(3) {
+        let memory = Arc::new(MemoryVfs::new());
+        let fault = Arc::new(FaultVfs::new(memory.clone()));
+        {
+            let db = Database::open_file_with_config("/test-crash.db", config_with_vfs(fault.clone())).unwrap();
+            let collection = db.collection::<Document>("test");
+            for i in 0..COMMITTED {
+                collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+            }
+
+            fault.crash_after_writes(crash_after);
+            for i in COMMITTED..(COMMITTED + 10) {
+                if collection.insert_one(doc! { "_id": i, "content": "Hello" }).is_err() {
+                    break;
+                }
+            }
+        }
+        assert!(fault.is_crashed());
+
+        // restart on the files left by the crash
+        let db = Database::open_file_with_config("/test-crash.db", config_with_vfs(memory)).unwrap();
+        let collection = db.collection::<Document>("test");
+        let count = collection.count_documents().unwrap() as i32;
+        assert!(count >= COMMITTED, "crash after {} writes", crash_after);
+        for i in 0..count {
+            assert!(collection.find_one(doc! { "_id": i }).unwrap().is_some());
+        }
+    }
+}
+
+#[test]
+fn test_torn_page_at_checkpoint() {
+    const COUNT: i32 = 100;
+
+    for crash_after in 0.. {
+        let memory = Arc::new(MemoryVfs::new());
+        let fault = Arc::new(FaultVfs::new(memory.clone()));
+        {
+            let db = Database::open_file_with_config("/test-torn-page.db", config_with_vfs(fault.clone())).unwrap();
+            let collection = db.collection::<Document>("test");
+            for i in 0..COUNT {
+                collection.insert_one(doc! { "_id": i, "content": "Hello" }).unwrap();
+            }
+
+            // a page of the main file is torn while the journal is merged
+            fault.crash_after_writes(crash_after);
+            if db.checkpoint().is_ok() {
+                assert!(crash_after > 1);
+                break;
+            }
+        }
+        assert!(fault.is_crashed());
+        assert!(memory.exists(Path::new("/test-torn-page.db.journal")));
+
+        // the pages are rewritten from the journal
+        let db = Database::open_file_with_config("/test-torn-page.db", config_with_vfs(memory)).unwrap();
+        let collection = db.collection::<Document>("test");
+        assert_eq!(collection.count_documents().unwrap(), COUNT as u64);
+        for i in 0..COUNT {
+            let result = collection.find_one(doc! { "_id": i }).unwrap().unwrap();
+            assert_eq!(result.get_str("content").unwrap(), "Hello");
+        }
+    }
+}
+
+fn count_zero_pages(bytes: &[u8]) -> usize {
+    bytes.chunks(4096).filter(|page| page.iter().all(|byte| *byte == 0)).count()
+}
+
+#[test]
+fn test_hole_punching() {
+    let memory = Arc::new(MemoryVfs::new());
+    let config = Config {
+        hole_punch_min_pages: Some(NonZeroU32::new(4).unwrap()),
+        ..config_with_vfs(memory.clone())
+    };
+    let db = Database::open_file_with_config("/test-hole-punching.db", config).unwrap();
+    let collection = db.collection::<Document>("test");
+    // the large documents are stored in the runs of pages
+    let content = "x".repeat(20 * 1024);
+    for i in 0..20 {
+        collection.insert_one(doc! { "_id": i, "content": content.clone() }).unwrap();
+    }
+    db.checkpoint().unwrap();
+    let zero_pages = count_zero_pages(&memory.read_file(Path::new("/test-hole-punching.db")).unwrap());
+
+    collection.delete_many(doc! {}).unwrap();
+    db.checkpoint().unwrap();
+    let bytes = memory.read_file(Path::new("/test-hole-punching.db")).unwrap();
+    assert!(count_zero_pages(&bytes) >= zero_pages + 50);
+
+    // the free pages are reused
+    for i in 0..20 {
+        collection.insert_one(doc! { "_id": i, "content": content.clone() }).unwrap();
+    }
+    db.checkpoint().unwrap();
+    assert_eq!(collection.count_documents().unwrap(), 20);
+    for i in 0..20 {
+        let result = collection.find_one(doc! { "_id": i }).unwrap().unwrap();
+        assert_eq!(result.get_str("content").unwrap().len(), content.len());
+    }
+}
@@ -0,0 +1,155 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The analyzers splitting the text into the terms of a text index.
+//!
+//! A text index selects its analyzer by name with the `tokenizer` option,
+//! the built-in analyzers are:
+//!
+//! - `whitespace`: splits on the whitespaces, the terms are kept as they are
+//! - `unicode`: splits on the word boundaries of unicode text, the terms are lower-cased
+//! - `cjk`: like `unicode`, but the runs of CJK characters are indexed as overlapping bigrams
+//!
+//! ```rust
+//! use polodb_core::text::{tokenizer_by_name, Tokenizer};
+//!
+//! let tokenizer = tokenizer_by_name("cjk").unwrap();
+//! assert_eq!(tokenizer.tokenize("PoloDB 数据库"), vec!["polodb", "数据", "据库"]);
+//! ```
+use std::sync::Arc;
+
+/// Split the text into the terms to index and to search.
+pub trait Tokenizer: Send + Sync {
+
+    fn tokenize(&self, text: &str) -> Vec<String>;
+
+}
+
+/// Split on the whitespaces.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text.split_whitespace().map(|term| term.to_string()).collect()
+    }
+
+}
+
+/// Split on the characters which are neither letters nor digits,
+/// the terms are lower-cased.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnicodeTokenizer;
+
+impl Tokenizer for UnicodeTokenizer {
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+            .map(|term| term.to_lowercase())
+            .collect()
+    }
+
+}
+
+/// Index the runs of CJK characters as overlapping bigrams,
+/// because the words are not separated by spaces in these languages.
+///
+/// The other text is split as [`UnicodeTokenizer`] does.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CjkBigramTokenizer;
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF     // Hiragana, Katakana
+        | 0x3400..=0x4DBF   // CJK Extension A
+        | 0x4E00..=0x9FFF   // CJK Unified Ideographs
+        | 0xAC00..=0xD7AF   // Hangul Syllables
+        | 0xF900..=0xFAFF   // CJK Compatibility Ideographs
+        | 0x20000..=0x2FA1F // CJK Extension B to F and the supplement
+    )
+}
+
+impl CjkBigramTokenizer {
+
+    fn push_cjk_run(run: &[char], result: &mut Vec<String>) {
+        if run.len() == 1 {
+            result.push(run[0].to_string());
+            return;
+        }
+        for pair in run.windows(2) {
+            result.push(pair.iter().collect());
+        }
+    }
+
+}
+
+impl Tokenizer for CjkBigramTokenizer {
+
+    fn tokenize(&self, text: &str) -> Vec<String> {
+        let mut result = vec![];
+        let mut word = String::new();
+        let mut cjk_run: Vec<char> = vec![];
+
+        for c in text.chars() {
+            if is_cjk(c) {
+                if !word.is_empty() {
+                    result.push(std::mem::take(&mut word).to_lowercase());
+                }
+                cjk_run.push(c);
+                continue;
+            }
+            if !cjk_run.is_empty() {
+                CjkBigramTokenizer::push_cjk_run(&cjk_run, &mut result);
+                cjk_run.clear();
+            }
+            if c.is_alphanumeric() {
+                word.push(c);
+            } else if !word.is_empty() {
+                result.push(std::mem::take(&mut word).to_lowercase());
+            }
+        }
+
+        if !cjk_run.is_empty() {
+            CjkBigramTokenizer::push_cjk_run(&cjk_run, &mut result);
+        }
+        if !word.is_empty() {
+            result.push(word.to_lowercase());
+        }
+        result
+    }
+
+}
+
+/// Get a built-in analyzer by the name of the `tokenizer` option.
+pub fn tokenizer_by_name(name: &str) -> Option<Arc<dyn Tokenizer>> {
+    let result: Arc<dyn Tokenizer> = match name {
+        "whitespace" => Arc::new(WhitespaceTokenizer),
+        "unicode" => Arc::new(UnicodeTokenizer),
+        "cjk" => Arc::new(CjkBigramTokenizer),
+        _ => return None,
+    };
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CjkBigramTokenizer, Tokenizer, UnicodeTokenizer, WhitespaceTokenizer};
+
+    #[test]
+    fn test_tokenizers() {
+        assert_eq!(WhitespaceTokenizer.tokenize("Hello,  World"), vec!["Hello,", "World"]);
+        assert_eq!(UnicodeTokenizer.tokenize("Hello, Wörld!"), vec!["hello", "wörld"]);
+        assert_eq!(
+            CjkBigramTokenizer.tokenize("東京タワー tokyo"),
+            vec!["東京", "京タ", "タワ", "ワー", "tokyo"],
+        );
+        assert_eq!(CjkBigramTokenizer.tokenize("猫 cat"), vec!["猫", "cat"]);
+    }
+
+}
@@ -0,0 +1,208 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::DbResult;
+use super::{Vfs, VfsFile, VfsLock};
+
+#[derive(Default)]
+struct FaultState {
+    writes:     AtomicU64,
+    // the writes allowed before the crash, `None` if it's not planned
+    crash_at:   AtomicU64,
+    crash_plan: AtomicBool,
+    crashed:    AtomicBool,
+    fail_sync:  AtomicBool,
+}
+
+impl FaultState {
+
+    /// Return the bytes of the write allowed to reach the file.
+    fn before_write(&self, len: usize) -> std::io::Result<usize> {
+        if self.crashed.load(Ordering::SeqCst) {
+            return Err(injected_error());
+        }
+        let count = self.writes.fetch_add(1, Ordering::SeqCst);
+        if self.crash_plan.load(Ordering::SeqCst) && count >= self.crash_at.load(Ordering::SeqCst) {
+            self.crashed.store(true, Ordering::SeqCst);
+            // a torn write, the first half reaches the disk
+            return Ok(len / 2);
+        }
+        Ok(len)
+    }
+
+    fn check_crashed(&self) -> std::io::Result<()> {
+        if self.crashed.load(Ordering::SeqCst) {
+            return Err(injected_error());
+        }
+        Ok(())
+    }
+
+}
+
+fn injected_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, "injected fault")
+}
+
+/// A [`Vfs`] injecting the errors of the disk into another VFS,
+/// to test how the database survives a crash.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use polodb_core::vfs::{FaultVfs, MemoryVfs};
+///
+/// let vfs = FaultVfs::new(Arc::new(MemoryVfs::new()));
+/// // the 11th write is torn, and all the writes after it fail
+/// vfs.crash_after_writes(10);
+/// ```
+pub struct FaultVfs {
+    inner: Arc<dyn Vfs>,
+    state: Arc<FaultState>,
+}
+
+impl FaultVfs {
+
+    pub fn new(inner: Arc<dyn Vfs>) -> FaultVfs {
+        FaultVfs {
+            inner,
+            state: Arc::new(FaultState::default()),
+        }
+    }
+
+    /// Crash after `count` more writes, the next write is torn,
+    /// and all the writes and syncs after it fail, like the power is lost.
+    pub fn crash_after_writes(&self, count: u64) {
+        let writes = self.state.writes.load(Ordering::SeqCst);
+        self.state.crash_at.store(writes.saturating_add(count), Ordering::SeqCst);
+        self.state.crash_plan.store(true, Ordering::SeqCst);
+    }
+
+    /// Fail all the syncs.
+    pub fn fail_syncs(&self, fail: bool) {
+        self.state.fail_sync.store(fail, Ordering::SeqCst);
+    }
+
+    /// The count of the writes since the VFS is created.
+    pub fn write_count(&self) -> u64 {
+        self.state.writes.load(Ordering::SeqCst)
+    }
+
+    pub fn is_crashed(&self) -> bool {
+        self.state.crashed.load(Ordering::SeqCst)
+    }
+
+    /// Cancel the crash planned and recover from the crash.
+    pub fn reset(&self) {
+        self.state.crash_plan.store(false, Ordering::SeqCst);
+        self.state.crashed.store(false, Ordering::SeqCst);
+        self.state.fail_sync.store(false, Ordering::SeqCst);
+    }
+
+}
+
+impl Vfs for FaultVfs {
+
+    fn open(&self, path: &Path, direct_io: bool) -> DbResult<Box<dyn VfsFile>> {
+        self.state.check_crashed()?;
+        let inner = self.inner.open(path, direct_io)?;
+        Ok(Box::new(FaultFile {
+            inner,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(path)
+    }
+
+    fn remove(&self, path: &Path) -> DbResult<()> {
+        self.state.check_crashed()?;
+        self.inner.remove(path)
+    }
+
+}
+
+struct FaultFile {
+    inner: Box<dyn VfsFile>,
+    state: Arc<FaultState>,
+}
+
+impl Read for FaultFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.inner.read(buf)
+    }
+
+}
+
+impl Write for FaultFile {
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let allowed = self.state.before_write(buf.len())?;
+        if allowed < buf.len() {
+            self.inner.write_all(&buf[..allowed])?;
+            return Err(injected_error());
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.state.check_crashed()?;
+        self.inner.flush()
+    }
+
+}
+
+impl Seek for FaultFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+
+}
+
+impl VfsFile for FaultFile {
+
+    fn size(&self) -> std::io::Result<u64> {
+        self.inner.size()
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.state.check_crashed()?;
+        self.inner.set_len(len)
+    }
+
+    fn preallocate(&self, len: u64) -> std::io::Result<()> {
+        self.state.check_crashed()?;
+        self.inner.preallocate(len)
+    }
+
+    fn punch_hole(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        self.state.check_crashed()?;
+        self.inner.punch_hole(offset, len)
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        self.state.check_crashed()?;
+        if self.state.fail_sync.load(Ordering::SeqCst) {
+            return Err(injected_error());
+        }
+        self.inner.sync()
+    }
+
+    fn lock(&self, lock: VfsLock) -> DbResult<()> {
+        self.inner.lock(lock)
+    }
+
+    // the direct I/O and io_uring would bypass the faults
+    fn as_os_file(&self) -> Option<&File> {
+        None
+    }
+
+}
@@ -0,0 +1,187 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use hashbrown::{HashMap, HashSet};
+use crate::{DbErr, DbResult};
+use super::{Vfs, VfsFile, VfsLock};
+
+static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Default)]
+struct MemoryFileData {
+    bytes:     Vec<u8>,
+    // the handles holding the shared locks
+    shared:    HashSet<u64>,
+    exclusive: Option<u64>,
+}
+
+/// A [`Vfs`] keeping the files in memory, the files live as long as the VFS.
+#[derive(Default)]
+pub struct MemoryVfs {
+    files: Mutex<HashMap<PathBuf, Arc<Mutex<MemoryFileData>>>>,
+}
+
+impl MemoryVfs {
+
+    pub fn new() -> MemoryVfs {
+        MemoryVfs::default()
+    }
+
+    /// A copy of the content of the file.
+    pub fn read_file(&self, path: &Path) -> Option<Vec<u8>> {
+        let files = self.files.lock().unwrap();
+        files.get(path).map(|data| data.lock().unwrap().bytes.clone())
+    }
+
+}
+
+impl Vfs for MemoryVfs {
+
+    fn open(&self, path: &Path, _direct_io: bool) -> DbResult<Box<dyn VfsFile>> {
+        let mut files = self.files.lock()?;
+        let data = files.entry(path.to_path_buf()).or_default().clone();
+        Ok(Box::new(MemoryFile {
+            data,
+            pos: 0,
+            handle: HANDLE_COUNTER.fetch_add(1, Ordering::SeqCst),
+        }))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.lock().unwrap().contains_key(path)
+    }
+
+    fn remove(&self, path: &Path) -> DbResult<()> {
+        // the handles opened keep the data, the same as unlink
+        self.files.lock()?.remove(path);
+        Ok(())
+    }
+
+}
+
+struct MemoryFile {
+    data:   Arc<Mutex<MemoryFileData>>,
+    pos:    u64,
+    handle: u64,
+}
+
+impl Read for MemoryFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let data = self.data.lock().unwrap();
+        let start = (self.pos as usize).min(data.bytes.len());
+        let count = buf.len().min(data.bytes.len() - start);
+        buf[..count].copy_from_slice(&data.bytes[start..(start + count)]);
+        self.pos += count as u64;
+        Ok(count)
+    }
+
+}
+
+impl Write for MemoryFile {
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut data = self.data.lock().unwrap();
+        let start = self.pos as usize;
+        let end = start + buf.len();
+        if data.bytes.len() < end {
+            data.bytes.resize(end, 0);
+        }
+        data.bytes[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+}
+
+impl Seek for MemoryFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let len = self.data.lock().unwrap().bytes.len() as i64;
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => offset as i64,
+            SeekFrom::End(offset) => len + offset,
+            SeekFrom::Current(offset) => self.pos as i64 + offset,
+        };
+        if new_pos < 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before the start"));
+        }
+        self.pos = new_pos as u64;
+        Ok(self.pos)
+    }
+
+}
+
+impl VfsFile for MemoryFile {
+
+    fn size(&self) -> std::io::Result<u64> {
+        Ok(self.data.lock().unwrap().bytes.len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> std::io::Result<()> {
+        self.data.lock().unwrap().bytes.resize(len as usize, 0);
+        Ok(())
+    }
+
+    // the range is zeroed like a hole of the file system
+    fn punch_hole(&self, offset: u64, len: u64) -> std::io::Result<()> {
+        let mut data = self.data.lock().unwrap();
+        let file_len = data.bytes.len();
+        let start = (offset as usize).min(file_len);
+        let end = (offset.saturating_add(len) as usize).min(file_len);
+        data.bytes[start..end].fill(0);
+        Ok(())
+    }
+
+    fn sync(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn lock(&self, lock: VfsLock) -> DbResult<()> {
+        let mut data = self.data.lock()?;
+        let other_exclusive = data.exclusive.map(|handle| handle != self.handle).unwrap_or(false);
+        match lock {
+            VfsLock::Shared => {
+                if other_exclusive {
+                    return Err(DbErr::Busy);
+                }
+                data.exclusive = None;
+                data.shared.insert(self.handle);
+            }
+            VfsLock::Exclusive => {
+                let other_shared = data.shared.iter().any(|handle| *handle != self.handle);
+                if other_exclusive || other_shared {
+                    return Err(DbErr::Busy);
+                }
+                data.shared.remove(&self.handle);
+                data.exclusive = Some(self.handle);
+            }
+            VfsLock::Unlock => {
+                data.shared.remove(&self.handle);
+                if data.exclusive == Some(self.handle) {
+                    data.exclusive = None;
+                }
+            }
+        }
+        Ok(())
+    }
+
+}
+
+impl Drop for MemoryFile {
+
+    fn drop(&mut self) {
+        let _ = self.lock(VfsLock::Unlock);
+    }
+
+}
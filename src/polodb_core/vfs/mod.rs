@@ -0,0 +1,97 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The files of the file backend are opened by a [`Vfs`], which can be replaced
+//! by [`Config::vfs`] to store the database somewhere else, or to inject
+//! the errors of the disk in the tests.
+//!
+//! ```rust
+//! use std::sync::Arc;
+//! use polodb_core::{Config, Database};
+//! use polodb_core::bson::{doc, Document};
+//! use polodb_core::vfs::MemoryVfs;
+//!
+//! let vfs = Arc::new(MemoryVfs::new());
+//! let config = Config {
+//!     vfs: Some(vfs.clone()),
+//!     ..Default::default()
+//! };
+//! {
+//!     let db = Database::open_file_with_config("/books.db", config.clone()).unwrap();
+//!     db.collection::<Document>("books").insert_one(doc! { "title": "Dune" }).unwrap();
+//! }
+//!
+//! // the file is kept by the VFS
+//! let db = Database::open_file_with_config("/books.db", config).unwrap();
+//! assert_eq!(db.collection::<Document>("books").count_documents().unwrap(), 1);
+//! ```
+//!
+//! [`Config::vfs`]: crate::Config::vfs
+mod memory_vfs;
+mod fault_vfs;
+
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use crate::DbResult;
+
+pub use memory_vfs::MemoryVfs;
+pub use fault_vfs::FaultVfs;
+#[cfg(feature = "fs")]
+pub use crate::backend::file::OsVfs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VfsLock {
+    Shared,
+    Exclusive,
+    Unlock,
+}
+
+/// The file system the file backend works on.
+pub trait Vfs: Send + Sync {
+    /// Open the file for reading and writing, it's created if it doesn't exist.
+    /// `direct_io` is a hint to bypass the cache of the OS.
+    fn open(&self, path: &Path, direct_io: bool) -> DbResult<Box<dyn VfsFile>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn remove(&self, path: &Path) -> DbResult<()>;
+}
+
+/// A file opened by a [`Vfs`].
+pub trait VfsFile: Read + Write + Seek + Send {
+    fn size(&self) -> std::io::Result<u64>;
+
+    fn set_len(&self, len: u64) -> std::io::Result<()>;
+
+    /// Grow the file to `len` bytes and reserve the space.
+    fn preallocate(&self, len: u64) -> std::io::Result<()> {
+        self.set_len(len)
+    }
+
+    /// Release the space of the range to the file system, the size of the file
+    /// is not changed. The content of the range is undefined after it,
+    /// it does nothing by default.
+    fn punch_hole(&self, _offset: u64, _len: u64) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Flush the data to the storage.
+    fn sync(&self) -> std::io::Result<()>;
+
+    /// Take or release the lock of the file without blocking,
+    /// return [`DbErr::Busy`] if it's held by another handle.
+    ///
+    /// A shared lock can be converted to an exclusive one and the other way round.
+    ///
+    /// [`DbErr::Busy`]: crate::DbErr::Busy
+    fn lock(&self, lock: VfsLock) -> DbResult<()>;
+
+    /// The file of the OS, which is needed by the direct I/O and io_uring.
+    fn as_os_file(&self) -> Option<&File> {
+        None
+    }
+}
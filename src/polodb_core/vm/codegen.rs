@@ -3,12 +3,14 @@
  * License, v. 2.0. If a copy of the MPL was not distributed with this
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
-use bson::{Bson, Document, Array};
+use bson::{Bson, DateTime, Document, Array, Timestamp};
 use bson::spec::ElementType;
+use smallvec::SmallVec;
 use super::label::{Label, LabelSlot, JumpTableRecord};
 use crate::vm::SubProgram;
 use crate::vm::op::DbOp;
-use crate::{DbResult, DbErr};
+use crate::{DbResult, DbErr, OperatorMode};
+use crate::aggregation::{is_unknown_field_operator, is_unknown_logical_operator};
 use crate::error::{mk_field_name_type_unexpected, mk_invalid_query_field};
 
 const JUMP_TABLE_DEFAULT_SIZE: usize = 8;
@@ -77,6 +79,7 @@ pub(super) struct Codegen {
     program:               Box<SubProgram>,
     jump_table:            Vec<JumpTableRecord>,
     skip_annotation:       bool,
+    operator_mode:         OperatorMode,
     paths:                 Vec<String>,
 }
 
@@ -90,11 +93,12 @@ macro_rules! path_hint {
 
 impl Codegen {
 
-    pub(super) fn new(skip_annotation: bool) -> Codegen {
+    pub(super) fn new(skip_annotation: bool, operator_mode: OperatorMode) -> Codegen {
         Codegen {
             program: Box::new(SubProgram::new()),
             jump_table: Vec::with_capacity(JUMP_TABLE_DEFAULT_SIZE),
             skip_annotation,
+            operator_mode,
             paths: Vec::with_capacity(PATH_DEFAULT_SIZE),
         }
     }
@@ -170,6 +174,17 @@ impl Codegen {
             if key == "_id" {
                 continue;
             }
+            // the other fields are compared literally, so only the unknown operators are checked
+            if self.operator_mode == OperatorMode::Strict {
+                let unknown_op = match value {
+                    _ if is_unknown_logical_operator(key) => Some(key.as_str()),
+                    Bson::Document(doc) => doc.keys().map(String::as_str).find(|op| is_unknown_field_operator(op)),
+                    _ => None,
+                };
+                if let Some(op) = unknown_op {
+                    return Err(DbErr::UnknownQueryOperator(op.into()));
+                }
+            }
 
             let key_static_id = self.push_static(Bson::String(key.clone()));
             let value_static_id = self.push_static(value.clone());
@@ -410,6 +425,15 @@ impl Codegen {
                     );
                 }
 
+                _ if is_unknown_logical_operator(key) => match self.operator_mode {
+                    OperatorMode::Strict => return Err(DbErr::UnknownQueryOperator(key.into())),
+                    OperatorMode::Lax => self.emit_field_equal(
+                        key, value,
+                        get_field_failed_label,
+                        not_found_label,
+                    ),
+                },
+
                 _ => return Err(DbErr::InvalidField(mk_invalid_query_field(
                     self.last_key().into(), self.gen_path())
                 )),
@@ -429,27 +453,38 @@ impl Codegen {
                         self.last_key().into(), self.gen_path())
                     )),
 
-                _ => {
-                    let key_static_id = self.push_static(key.into());
-                    self.emit_goto2(DbOp::GetField, key_static_id, get_field_failed_label);
-
-                    let value_static_id = self.push_static(value.clone());
-                    self.emit_push_value(value_static_id);  // push a value2
-
-                    self.emit(DbOp::Equal);
-                    // if not equal，go to next
-                    self.emit_goto(DbOp::IfFalse, not_found_label);
-
-                    self.emit(DbOp::Pop); // pop a value2
-                    self.emit(DbOp::Pop); // pop a value1
-                }
+                _ => self.emit_field_equal(
+                    key, value,
+                    get_field_failed_label,
+                    not_found_label,
+                ),
             }
         }
         Ok(())
     }
 
+    fn emit_field_equal(&mut self,
+                        key: &str,
+                        value: &Bson,
+                        get_field_failed_label: Label,
+                        not_found_label: Label
+    ) {
+        let key_static_id = self.push_static(key.into());
+        self.emit_goto2(DbOp::GetField, key_static_id, get_field_failed_label);
+
+        let value_static_id = self.push_static(value.clone());
+        self.emit_push_value(value_static_id);  // push a value2
+
+        self.emit(DbOp::Equal);
+        // if not equal，go to next
+        self.emit_goto(DbOp::IfFalse, not_found_label);
+
+        self.emit(DbOp::Pop); // pop a value2
+        self.emit(DbOp::Pop); // pop a value1
+    }
+
     fn recursively_get_field(&mut self, key: &str, get_field_failed_label: Label) -> usize {
-        let slices: Vec<&str> = key.split('.').collect();
+        let slices: SmallVec<[&str; 4]> = key.split('.').collect();
         for slice in &slices {
             let str_ref: &str = slice;
             let current_stat_id = self.push_static(str_ref.into());
@@ -606,7 +641,8 @@ impl Codegen {
                 self.emit_goto(DbOp::IfFalse, not_found_label);
 
                 self.emit(DbOp::Pop2);
-                self.emit_u32((field_size + 1) as u32);
+                // the field, the size of it and the expected one
+                self.emit_u32((field_size + 2) as u32);
             }
 
             _ => return Err(DbErr::InvalidField(mk_invalid_query_field(
@@ -623,6 +659,16 @@ impl Codegen {
                                  get_field_failed_label: Label,
                                  not_found_label: Label
     ) -> DbResult<()> {
+        if let Some(op) = value.keys().find(|op| is_unknown_field_operator(op)) {
+            match self.operator_mode {
+                OperatorMode::Strict => return Err(DbErr::UnknownQueryOperator(op.clone())),
+                OperatorMode::Lax => {
+                    let literal = Bson::Document(value.clone());
+                    self.emit_field_equal(key, &literal, get_field_failed_label, not_found_label);
+                    return Ok(());
+                }
+            }
+        }
         for (sub_key, sub_value) in value.iter() {
             path_hint!(self, sub_key.clone(), {
                 self.emit_query_tuple_document_kv(
@@ -634,10 +680,11 @@ impl Codegen {
         Ok(())
     }
 
-    pub(super) fn emit_update_operation(&mut self, update: &Document) -> DbResult<()> {
+    /// `now` is the time set by `$currentDate`.
+    pub(super) fn emit_update_operation(&mut self, update: &Document, now: DateTime) -> DbResult<()> {
         for (key, value) in update.iter() {
             path_hint!(self, key.clone(), {
-                self.emit_update_operation_kv(key, value)?;
+                self.emit_update_operation_kv(key, value, now)?;
             });
         }
 
@@ -646,7 +693,7 @@ impl Codegen {
         Ok(())
     }
 
-    fn emit_update_operation_kv(&mut self, key: &str, value: &Bson) -> DbResult<()> {
+    fn emit_update_operation_kv(&mut self, key: &str, value: &Bson, now: DateTime) -> DbResult<()> {
         match key.as_ref() {
             "$inc" => {
                 let doc = crate::try_unwrap_document!("$inc", value);
@@ -660,6 +707,37 @@ impl Codegen {
                 self.iterate_add_op(DbOp::SetField, doc)?;
             }
 
+            "$currentDate" => {
+                let doc = crate::try_unwrap_document!("$currentDate", value);
+
+                let mut values = Document::new();
+                for (key, value) in doc.iter() {
+                    let current = match value {
+                        Bson::Boolean(true) => Bson::DateTime(now),
+                        Bson::Document(spec) => match spec.get("$type") {
+                            Some(Bson::String(ty)) if ty == "date" => Bson::DateTime(now),
+                            Some(Bson::String(ty)) if ty == "timestamp" => Bson::Timestamp(Timestamp {
+                                time: (now.timestamp_millis() / 1000) as u32,
+                                increment: 1,
+                            }),
+                            _ => return Err(mk_field_name_type_unexpected(
+                                key.into(),
+                                "{ $type: \"date\" | \"timestamp\" }".into(),
+                                format!("{}", value),
+                            )),
+                        },
+                        t => return Err(mk_field_name_type_unexpected(
+                            key.into(),
+                            "true or Document".into(),
+                            format!("{}", t),
+                        )),
+                    };
+                    values.insert(key.clone(), current);
+                }
+
+                self.iterate_add_op(DbOp::SetField, &values)?;
+            }
+
             "$max" => {
                 update_op::update_op_min_max(self, value, false)?;
             }
@@ -733,7 +811,20 @@ impl Codegen {
                 }
             }
 
-            _ => return Err(DbErr::UnknownUpdateOperation(key.into())),
+            _ => match self.operator_mode {
+                OperatorMode::Strict => return Err(DbErr::UnknownUpdateOperation(key.into())),
+                // set the field named by the operator
+                OperatorMode::Lax => {
+                    let value_id = self.push_static(value.clone());
+                    self.emit_push_value(value_id);
+
+                    let key_id = self.push_static(Bson::from(key));
+                    self.emit(DbOp::SetField);
+                    self.emit_u32(key_id);
+
+                    self.emit(DbOp::Pop);
+                }
+            },
 
         }
 
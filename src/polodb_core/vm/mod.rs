@@ -12,9 +12,11 @@ pub(crate) use subprogram::SubProgram;
 
 use std::vec::Vec;
 use std::cmp::Ordering;
-use bson::Bson;
+use bson::{Bson, Document};
 use op::DbOp;
 use crate::cursor::Cursor;
+use crate::aggregation;
+use crate::data_ticket::DataTicket;
 use crate::{TransactionType, DbResult, DbErr};
 use crate::error::{CannotApplyOperationForTypes, mk_field_name_type_unexpected, mk_unexpected_type_for_op};
 use std::cell::Cell;
@@ -50,13 +52,26 @@ pub struct VM<'a> {
     r1:                  Option<Cursor>,
     pub(crate) r2:       i64,  // usually the counter
     r3:                  usize,
+    pub(crate) size_delta: i64,  // the change of the stored bytes by the updates
+    pub(crate) modified_count: u64,  // the documents changed by the updates
+    pub(crate) keys_examined: u64,
+    pub(crate) docs_examined: u64,
+    pub(crate) resume_key: Option<Bson>,  // the key to continue the suspended scan
+    rows_matched:        u64,  // the rows reached, including the skipped ones
+    scan_key_index:      usize,  // the next of the scan keys to seek
+    pub(crate) updated_docs: Vec<(Document, Document)>,  // the documents before and after the updates
     session:             &'a dyn Session,
     stack:               Vec<Bson>,
     pub(crate) program:  SubProgram,
     rollback_on_drop:    bool,
+    joined_transactions: usize,  // the transactions of the caller joined by the opens
 }
 
 fn generic_cmp(op: DbOp, val1: &Bson, val2: &Bson) -> DbResult<bool> {
+    // the documents and the arrays are not ordered, but can be equal
+    if let (DbOp::Equal, Bson::Document(_), Bson::Document(_)) | (DbOp::Equal, Bson::Array(_), Bson::Array(_)) = (op, val1, val2) {
+        return Ok(val1 == val2);
+    }
     let ord = crate::bson_utils::value_cmp(val1, val2)?;
     let result = matches!((op, ord),
         (DbOp::Equal, Ordering::Equal) |
@@ -82,10 +97,19 @@ impl<'a> VM<'a> {
             r1: None,
             r2: 0,
             r3: 0,
+            size_delta: 0,
+            modified_count: 0,
+            keys_examined: 0,
+            docs_examined: 0,
+            resume_key: None,
+            rows_matched: 0,
+            scan_key_index: 0,
+            updated_docs: Vec::new(),
             session: page_handler,
             stack,
             program,
             rollback_on_drop: false,
+            joined_transactions: 0,
         }
     }
 
@@ -93,6 +117,21 @@ impl<'a> VM<'a> {
         let result = self.session.auto_start_transaction(ty)?;
         if result.auto_start {
             self.rollback_on_drop = true;
+        } else {
+            self.joined_transactions += 1;
+        }
+        Ok(())
+    }
+
+    /// Release the transactions joined by the VM, and commit the one started by it.
+    fn release_transactions(&mut self) -> DbResult<()> {
+        while self.joined_transactions > 0 {
+            self.session.auto_commit()?;
+            self.joined_transactions -= 1;
+        }
+        if self.rollback_on_drop {
+            self.session.auto_commit()?;
+            self.rollback_on_drop = false;
         }
         Ok(())
     }
@@ -109,12 +148,57 @@ impl<'a> VM<'a> {
         Ok(())
     }
 
-    fn reset_cursor(&mut self, is_empty: &Cell<bool>) -> DbResult<()> {
+    fn read_doc(&mut self, ticket: &DataTicket) -> DbResult<Document> {
+        self.keys_examined += 1;
+        self.docs_examined += 1;
+        match &self.program.scan_fields {
+            Some(scan) => self.session.get_doc_fields_from_ticket(ticket, scan),
+            None => self.session.get_doc_from_ticket(ticket),
+        }
+    }
+
+    /// Seek the next of the scan keys existing in the collection.
+    fn next_scan_key(&mut self) -> DbResult<Option<DataTicket>> {
+        let keys = self.program.scan_keys.as_ref().unwrap();
         let cursor = self.r1.as_mut().unwrap();
-        cursor.reset(self.session)?;
-        if cursor.has_next() {
-            let item = cursor.peek_data().unwrap();
-            let doc = self.session.get_doc_from_ticket(&item)?;
+        while self.scan_key_index < keys.len() {
+            let key = &keys[self.scan_key_index];
+            if self.scan_key_index == 0 {
+                cursor.seek(self.session, key)?;
+            } else {
+                cursor.seek_forward(self.session, key)?;
+            }
+            self.scan_key_index += 1;
+            if !cursor.has_next() {
+                // the rest of the keys are greater than all the keys of the collection
+                self.scan_key_index = keys.len();
+                return Ok(None);
+            }
+            if let Some(current) = cursor.peek_key()? {
+                if crate::bson_utils::value_cmp(&current, key)? == Ordering::Equal {
+                    return Ok(cursor.peek_data());
+                }
+            }
+            self.keys_examined += 1;
+        }
+        Ok(None)
+    }
+
+    fn reset_cursor(&mut self, is_empty: &Cell<bool>) -> DbResult<()> {
+        let item = if self.program.scan_keys.is_some() {
+            // the keys before `resume_from` are removed by the planner
+            self.scan_key_index = 0;
+            self.next_scan_key()?
+        } else {
+            let cursor = self.r1.as_mut().unwrap();
+            match &self.program.resume_from {
+                Some(key) => cursor.seek(self.session, key)?,
+                None => cursor.reset(self.session)?,
+            }
+            cursor.peek_data()
+        };
+        if let Some(item) = item {
+            let doc = self.read_doc(&item)?;
             self.stack.push(Bson::Document(doc));
             is_empty.set(false);
         } else {
@@ -131,21 +215,52 @@ impl<'a> VM<'a> {
 
         let result = cursor.reset_by_pkey(self.session, op)?;
         if !result {
+            self.keys_examined += 1;
             return Ok(false);
         }
 
         let ticket = cursor.peek_data().unwrap();
-        let doc = self.session.get_doc_from_ticket(&ticket)?;
+        let doc = self.read_doc(&ticket)?;
         self.stack.push(Bson::Document(doc));
         Ok(true)
     }
 
     fn next(&mut self) -> DbResult<()> {
-        let cursor = self.r1.as_mut().unwrap();
-        let _ = cursor.next(self.session)?;
-        match cursor.peek_data() {
+        // end the scan as if the cursor reaches the end
+        if let Some(limit) = self.program.limit {
+            if self.rows_matched >= self.program.skip + limit {
+                self.r0 = 0;
+                return Ok(());
+            }
+        }
+        if let Some(keys) = &self.program.scan_keys {
+            // suspend the scan before the next of the scan keys
+            if let Some(scan_limit) = self.program.scan_limit {
+                if self.docs_examined >= scan_limit && self.scan_key_index < keys.len() {
+                    self.resume_key = Some(keys[self.scan_key_index].clone());
+                    self.r0 = 0;
+                    return Ok(());
+                }
+            }
+        }
+        let ticket = if self.program.scan_keys.is_some() {
+            self.next_scan_key()?
+        } else {
+            let cursor = self.r1.as_mut().unwrap();
+            let _ = cursor.next(self.session)?;
+            // suspend the scan as if the cursor reaches the end
+            if let Some(scan_limit) = self.program.scan_limit {
+                if self.docs_examined >= scan_limit && cursor.has_next() {
+                    self.resume_key = cursor.peek_key()?;
+                    self.r0 = 0;
+                    return Ok(());
+                }
+            }
+            cursor.peek_data()
+        };
+        match ticket {
             Some(ticket) => {
-                let doc = self.session.get_doc_from_ticket(&ticket)?;
+                let doc = self.read_doc(&ticket)?;
                 self.stack.push(Bson::Document(doc));
 
                 debug_assert!(self.stack.len() <= 64, "stack too large: {}", self.stack.len());
@@ -540,8 +655,36 @@ impl<'a> VM<'a> {
                         let top_value = &self.stack[top_index];
 
                         let doc = top_value.as_document().unwrap();
+                        let generated_doc;
+                        let doc = if self.program.generated_fields.is_empty() {
+                            doc
+                        } else {
+                            let mut doc = doc.clone();
+                            aggregation::set_generated_fields(&mut doc, &self.program.generated_fields)?;
+                            generated_doc = doc;
+                            &generated_doc
+                        };
 
-                        self.r1.as_mut().unwrap().update_current(self.session, doc)?;
+                        let old_doc = if self.program.keep_updated_docs {
+                            let ticket = self.r1.as_mut().unwrap().peek_data().unwrap();
+                            Some(self.session.get_doc_from_ticket(&ticket)?)
+                        } else {
+                            None
+                        };
+
+                        let changed = if self.program.dry_run {
+                            old_doc.as_ref() != Some(doc)
+                        } else {
+                            let (size_delta, changed) = self.r1.as_mut().unwrap().update_current(self.session, doc, &self.program.compression)?;
+                            self.size_delta += size_delta;
+                            changed
+                        };
+                        if changed {
+                            self.modified_count += 1;
+                            if let Some(old_doc) = old_doc {
+                                self.updated_docs.push((old_doc, doc.clone()));
+                            }
+                        }
 
                         self.pc = self.pc.add(1);
                     }
@@ -615,16 +758,18 @@ impl<'a> VM<'a> {
 
                     DbOp::ResultRow => {
                         self.pc = self.pc.add(1);
+                        self.rows_matched += 1;
+                        // the program goes on to the next document
+                        if self.rows_matched <= self.program.skip {
+                            continue;
+                        }
                         self.state = VmState::HasRow;
                         return Ok(());
                     }
 
                     DbOp::Close => {
                         self.r1 = None;
-                        if self.rollback_on_drop {
-                            self.session.auto_commit()?;
-                            self.rollback_on_drop = false;
-                        }
+                        self.release_transactions()?;
 
                         self.pc = self.pc.add(1);
                     }
@@ -652,9 +797,7 @@ impl<'a> VM<'a> {
     }
 
     pub(crate) fn commit_and_close(mut self) -> DbResult<()> {
-        self.session.auto_commit()?;
-        self.rollback_on_drop = false;
-        Ok(())
+        self.release_transactions()
     }
 
     pub(crate) fn set_rollback_on_drop(&mut self, value: bool) {
@@ -672,6 +815,11 @@ impl<'a> Drop for VM<'a> {
             if let Err(err) = _result {
                 panic!("rollback fatal: {}", err);
             }
+        } else {
+            // the caller rolls back its transaction
+            for _ in 0..self.joined_transactions {
+                let _ = self.session.auto_rollback();
+            }
         }
     }
 
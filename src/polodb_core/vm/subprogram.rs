@@ -4,9 +4,10 @@
  * file, You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 use std::fmt;
-use bson::{Bson, Document};
-use crate::collection_info::CollectionSpecification;
-use crate::DbResult;
+use bson::{Bson, DateTime, Document};
+use crate::collection_info::{CollectionSpecification, GeneratedField};
+use crate::{Compression, DbResult, OperatorMode};
+use crate::projection::ScanFields;
 use super::op::DbOp;
 use super::label::LabelSlot;
 use crate::vm::codegen::Codegen;
@@ -15,6 +16,21 @@ pub(crate) struct SubProgram {
     pub(super) static_values:    Vec<Bson>,
     pub(super) instructions:     Vec<u8>,
     pub(super) label_slots:      Vec<LabelSlot>,
+    pub(super) compression:      Compression,
+    pub(super) scan_fields:      Option<ScanFields>,
+    // the scan starts from the key, and is suspended after the documents examined
+    pub(super) resume_from:      Option<Bson>,
+    pub(super) scan_limit:       Option<u64>,
+    // the sorted primary keys found by an index, only these documents are scanned
+    pub(super) scan_keys:        Option<Vec<Bson>>,
+    // the rows skipped and the most rows returned, the scan ends after the limit
+    pub(super) skip:             u64,
+    pub(super) limit:            Option<u64>,
+    pub(super) keep_updated_docs: bool,
+    // the updates are only kept, nothing is written
+    pub(super) dry_run:          bool,
+    // computed on the documents updated before they are written
+    pub(super) generated_fields: Vec<GeneratedField>,
 }
 
 impl SubProgram {
@@ -24,18 +40,65 @@ impl SubProgram {
             static_values: Vec::with_capacity(32),
             instructions: Vec::with_capacity(256),
             label_slots: Vec::with_capacity(32),
+            compression: Compression::None,
+            scan_fields: None,
+            resume_from: None,
+            scan_limit: None,
+            scan_keys: None,
+            skip: 0,
+            limit: None,
+            keep_updated_docs: false,
+            dry_run: false,
+            generated_fields: Vec::new(),
         }
     }
 
+    /// Only decode the parts of the documents needed while scanning.
+    pub(crate) fn set_scan_fields(&mut self, scan: ScanFields) {
+        self.scan_fields = Some(scan);
+    }
+
+    /// Scan the collection from the key `resume_from`, and suspend the scan after
+    /// `scan_limit` documents are examined. The key to continue is left in the VM.
+    pub(crate) fn set_scan_slice(&mut self, resume_from: Option<Bson>, scan_limit: u64) {
+        self.resume_from = resume_from;
+        self.scan_limit = Some(scan_limit);
+    }
+
+    /// Only scan the documents of the sorted primary keys, found by an index.
+    pub(crate) fn set_scan_keys(&mut self, keys: Vec<Bson>) {
+        self.scan_keys = Some(keys);
+    }
+
+    /// Skip the first `skip` rows, and end the scan after `limit` rows are returned.
+    pub(crate) fn set_result_window(&mut self, skip: u64, limit: Option<u64>) {
+        self.skip = skip;
+        self.limit = limit;
+    }
+
+    /// Keep the documents before and after the updates in the VM,
+    /// to update the entries of the indexes.
+    pub(crate) fn set_keep_updated_docs(&mut self) {
+        self.keep_updated_docs = true;
+    }
+
+    /// Keep the documents before and after the updates without writing them,
+    /// so the updates can be checked before they are done.
+    pub(crate) fn set_dry_run(&mut self) {
+        self.keep_updated_docs = true;
+        self.dry_run = true;
+    }
+
     pub(crate) fn compile_query(
         col_spec: &CollectionSpecification,
         query: &Document,
         skip_annotation: bool,
+        operator_mode: OperatorMode,
     ) -> DbResult<SubProgram> {
         // let _indexes = meta_doc.get(meta_doc_key::INDEXES);
         // let _tuples = doc_to_tuples(doc);
 
-        let mut codegen = Codegen::new(skip_annotation);
+        let mut codegen = Codegen::new(skip_annotation, operator_mode);
 
         codegen.emit_open_read(col_spec.info.root_pid);
 
@@ -57,15 +120,17 @@ impl SubProgram {
         query: Option<&Document>,
         update: &Document,
         skip_annotation: bool, is_many: bool,
+        operator_mode: OperatorMode,
+        now: DateTime,
     ) -> DbResult<SubProgram> {
-        let mut codegen = Codegen::new(skip_annotation);
+        let mut codegen = Codegen::new(skip_annotation, operator_mode);
 
         codegen.emit_open_write(col_spec.info.root_pid);
 
         codegen.emit_query_layout(
             query.unwrap(),
             |codegen| -> DbResult<()> {
-                codegen.emit_update_operation(update)?;
+                codegen.emit_update_operation(update, now)?;
                 codegen.emit(DbOp::Pop);
                 codegen.emit(DbOp::IncR2);
                 Ok(())
@@ -73,11 +138,15 @@ impl SubProgram {
             is_many
         )?;
 
-        Ok(codegen.take())
+        let mut program = codegen.take();
+        program.compression = col_spec.info.compression;
+        program.generated_fields = col_spec.info.generated_fields.clone();
+
+        Ok(program)
     }
 
     pub(crate) fn compile_query_all(col_spec: &CollectionSpecification, skip_annotation: bool) -> DbResult<SubProgram> {
-        let mut codegen = Codegen::new(skip_annotation);
+        let mut codegen = Codegen::new(skip_annotation, OperatorMode::default());
         let result_label = codegen.new_label();
         let next_label = codegen.new_label();
         let close_label = codegen.new_label();
@@ -334,6 +403,7 @@ mod tests {
     use polodb_line_diff::assert_eq;
     use crate::collection_info::{CollectionSpecification, CollectionSpecificationInfo, CollectionType};
     use crate::vm::SubProgram;
+    use crate::{Compression, OperatorMode};
 
     fn new_spec<T: Into<String>>(name: T, root_pid: u32) -> CollectionSpecification {
         CollectionSpecification {
@@ -343,6 +413,14 @@ mod tests {
                 uuid: None,
                 create_at: DateTime::now(),
                 root_pid,
+                compression: Compression::None,
+                version_field: None,
+                append_only: false,
+                foreign_keys: Vec::new(),
+                generated_fields: Vec::new(),
+                count: None,
+                data_size: None,
+                archived_in: None,
             },
             indexes: HashMap::new(),
         }
@@ -384,7 +462,7 @@ mod tests {
             "age": 32,
         };
         let col_spec = new_spec("test", 100);
-        let program = SubProgram::compile_query(&col_spec, &test_doc, false).unwrap();
+        let program = SubProgram::compile_query(&col_spec, &test_doc, false, OperatorMode::Strict).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
         let expect = r#"Program:
@@ -441,7 +519,7 @@ mod tests {
             "_id": 6,
             "age": 32,
         };
-        let program = SubProgram::compile_query(&col_spec, &test_doc, false).unwrap();
+        let program = SubProgram::compile_query(&col_spec, &test_doc, false, OperatorMode::Strict).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
 
@@ -484,7 +562,7 @@ mod tests {
                 },
             ],
         };
-        let program = SubProgram::compile_query(&col_spec, &test_doc, false).unwrap();
+        let program = SubProgram::compile_query(&col_spec, &test_doc, false, OperatorMode::Strict).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
         let expect = r#"Program:
@@ -547,7 +625,7 @@ mod tests {
                 },
             ],
         };
-        let program = SubProgram::compile_query(&col_spec, &test_doc, false).unwrap();
+        let program = SubProgram::compile_query(&col_spec, &test_doc, false, OperatorMode::Strict).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
         let expect = r#"Program:
@@ -618,7 +696,7 @@ mod tests {
                 "$in": [ 1, 2 ],
             },
         };
-        let program = SubProgram::compile_query(&col_spec, &test_doc, false).unwrap();
+        let program = SubProgram::compile_query(&col_spec, &test_doc, false, OperatorMode::Strict).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
         let expect = r#"Program:
@@ -699,7 +777,9 @@ mod tests {
             &col_spec,
             Some(&query_doc),
             &update_doc,
-            false, true
+            false, true,
+            OperatorMode::Strict,
+            DateTime::now(),
         ).unwrap();
         let actual = format!("Program:\n\n{}", program);
 
@@ -0,0 +1,425 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A small REST facade of PoloDB.
+//!
+//! The documents are encoded in [Extended JSON](https://www.mongodb.com/docs/manual/reference/mongodb-extended-json/),
+//! so the local processes which can not link PoloDB (Electron, scripts, etc.)
+//! can talk to a single instance over HTTP.
+//!
+//! | Method   | Path                            | Description                                   |
+//! |----------|---------------------------------|-----------------------------------------------|
+//! | `GET`    | `/collections`                  | list the names of the collections             |
+//! | `POST`   | `/collections`                  | create a collection: `{ "name": "books" }`    |
+//! | `DELETE` | `/collections/<ns>`             | drop the collection                           |
+//! | `GET`    | `/collections/<ns>/count`       | count the documents                           |
+//! | `GET`    | `/collections/<ns>/documents`   | find, with `filter`, `skip` and `limit`       |
+//! | `POST`   | `/collections/<ns>/documents`   | insert a document or an array of documents    |
+//! | `PATCH`  | `/collections/<ns>/documents`   | update: `{ "filter", "update", "multi" }`     |
+//! | `DELETE` | `/collections/<ns>/documents`   | delete, with `filter` and `multi`             |
+//!
+//! All the requests are dispatched by [`Database::handle_request_doc`],
+//! so the middlewares registered on the database apply.
+use std::net::ToSocketAddrs;
+use std::sync::Arc;
+use polodb_core::{Database, DbErr};
+use polodb_core::bson::{doc, Bson, Document};
+use polodb_core::ejson::{self, JsonOptions};
+use serde_json::{json, Value};
+
+const DEFAULT_PAGE_LIMIT: usize = 100;
+
+#[derive(Debug)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub body: Value,
+}
+
+impl HttpResponse {
+
+    fn ok(body: Value) -> HttpResponse {
+        HttpResponse {
+            status: 200,
+            body,
+        }
+    }
+
+    fn error(status: u16, msg: impl Into<String>) -> HttpResponse {
+        HttpResponse {
+            status,
+            body: json!({ "error": msg.into() }),
+        }
+    }
+
+}
+
+impl From<DbErr> for HttpResponse {
+
+    fn from(err: DbErr) -> Self {
+        let status = match &err {
+            DbErr::PermissionDenied(_) => 403,
+            DbErr::CollectionNotFound(_) | DbErr::CursorNotFound(_) => 404,
+            DbErr::Busy => 503,
+            DbErr::TooManyOpenCursors(_) | DbErr::TooManySessions(_) | DbErr::TooManyCollections(_) => 429,
+            DbErr::IOErr(_) | DbErr::LockError => 500,
+            _ => 400,
+        };
+        HttpResponse::error(status, err.to_string())
+    }
+
+}
+
+type HttpResult<T> = Result<T, HttpResponse>;
+
+pub struct HttpServer {
+    db: Arc<Database>,
+    server: tiny_http::Server,
+}
+
+impl HttpServer {
+
+    pub fn bind<A: ToSocketAddrs>(db: Arc<Database>, addr: A) -> std::io::Result<HttpServer> {
+        let server = tiny_http::Server::http(addr)
+            .map_err(|err| std::io::Error::other(err.to_string()))?;
+        Ok(HttpServer {
+            db,
+            server,
+        })
+    }
+
+    pub fn server_addr(&self) -> tiny_http::ListenAddr {
+        self.server.server_addr()
+    }
+
+    /// Serve the incoming requests until the server is dropped.
+    pub fn run(&self) {
+        for mut request in self.server.incoming_requests() {
+            let mut body = String::new();
+            let response = match request.as_reader().read_to_string(&mut body) {
+                Ok(_) => handle_http_request(
+                    &self.db,
+                    request.method().as_str(),
+                    request.url(),
+                    &body,
+                ),
+                Err(err) => HttpResponse::error(400, err.to_string()),
+            };
+
+            let content_type = tiny_http::Header::from_bytes(
+                &b"Content-Type"[..],
+                &b"application/json"[..],
+            ).unwrap();
+            let http_response = tiny_http::Response::from_string(response.body.to_string())
+                .with_status_code(response.status)
+                .with_header(content_type);
+
+            if let Err(err) = request.respond(http_response) {
+                eprintln!("write response failed: {}", err);
+            }
+        }
+    }
+
+}
+
+/// Handle a request without the transport,
+/// `url` is the path with the query string.
+pub fn handle_http_request(db: &Database, method: &str, url: &str, body: &str) -> HttpResponse {
+    match route(db, method, url, body) {
+        Ok(resp) => resp,
+        Err(resp) => resp,
+    }
+}
+
+fn route(db: &Database, method: &str, url: &str, body: &str) -> HttpResult<HttpResponse> {
+    let (path, query) = match url.find('?') {
+        Some(index) => (&url[..index], parse_query_string(&url[index + 1..])),
+        None => (url, vec![]),
+    };
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(|s| s.as_str()).collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["collections"]) => {
+            let names = db.list_collection_names()?;
+            Ok(HttpResponse::ok(json!(names)))
+        }
+        ("POST", ["collections"]) => {
+            let body = parse_body_document(body)?;
+            let name = body.get_str("name")
+                .map_err(|_| HttpResponse::error(400, "field 'name' is required"))?;
+            let created = execute(db, doc! {
+                "command": "CreateCollection",
+                "ns": name,
+            })?;
+            Ok(HttpResponse::ok(json!({ "created": created.as_bool().unwrap_or(false) })))
+        }
+        ("DELETE", ["collections", ns]) => {
+            execute(db, doc! {
+                "command": "DropCollection",
+                "ns": *ns,
+            })?;
+            Ok(HttpResponse::ok(json!({})))
+        }
+        ("GET", ["collections", ns, "count"]) => {
+            let count = execute(db, doc! {
+                "command": "CountDocuments",
+                "ns": *ns,
+            })?;
+            Ok(HttpResponse::ok(to_json(count)))
+        }
+        ("GET", ["collections", ns, "documents"]) => find_documents(db, ns, &query),
+        ("POST", ["collections", ns, "documents"]) => {
+            let documents = match parse_body(body)? {
+                Bson::Document(doc) => vec![Bson::Document(doc)],
+                Bson::Array(arr) => arr,
+                _ => return Err(HttpResponse::error(400, "a document or an array is expected")),
+            };
+            let result = execute(db, doc! {
+                "command": "Insert",
+                "ns": *ns,
+                "documents": documents,
+            })?;
+            Ok(HttpResponse::ok(to_json(result)))
+        }
+        ("PATCH", ["collections", ns, "documents"]) => {
+            let body = parse_body_document(body)?;
+            let filter = body.get_document("filter").cloned().unwrap_or_default();
+            let update = body.get_document("update")
+                .map_err(|_| HttpResponse::error(400, "field 'update' is required"))?
+                .clone();
+            let multi = body.get_bool("multi").unwrap_or(false);
+            let result = execute(db, doc! {
+                "command": "Update",
+                "ns": *ns,
+                "filter": filter,
+                "update": update,
+                "multi": multi,
+            })?;
+            Ok(HttpResponse::ok(to_json(result)))
+        }
+        ("DELETE", ["collections", ns, "documents"]) => {
+            let filter = query_document(&query, "filter")?.unwrap_or_default();
+            let multi = query_value(&query, "multi").map(|v| v != "false").unwrap_or(true);
+            let result = execute(db, doc! {
+                "command": "Delete",
+                "ns": *ns,
+                "filter": filter,
+                "multi": multi,
+            })?;
+            Ok(HttpResponse::ok(to_json(result)))
+        }
+        _ => Err(HttpResponse::error(404, format!("no route for {} {}", method, path))),
+    }
+}
+
+/// Return a page of the documents:
+/// ```json
+/// { "items": [...], "skip": 0, "limit": 100, "total": 1000 }
+/// ```
+fn find_documents(db: &Database, ns: &str, query: &[(String, String)]) -> HttpResult<HttpResponse> {
+    let filter = query_document(query, "filter")?;
+    let skip = query_usize(query, "skip")?.unwrap_or(0);
+    let limit = query_usize(query, "limit")?.unwrap_or(DEFAULT_PAGE_LIMIT);
+
+    let mut command = doc! {
+        "command": "Find",
+        "ns": ns,
+        "multi": true,
+        "options": {
+            "skip": skip as i64,
+            "limit": limit as i64,
+        },
+    };
+    if let Some(filter) = &filter {
+        command.insert("filter", filter.clone());
+    }
+    let page: Vec<Value> = match execute(db, command)? {
+        Bson::Array(arr) => arr.into_iter().map(to_json).collect(),
+        _ => vec![],
+    };
+    let total = count_documents(db, ns, filter)?;
+
+    Ok(HttpResponse::ok(json!({
+        "items": page,
+        "skip": skip,
+        "limit": limit,
+        "total": total,
+    })))
+}
+
+fn count_documents(db: &Database, ns: &str, filter: Option<Document>) -> HttpResult<u64> {
+    let mut command = doc! {
+        "command": "CountDocuments",
+        "ns": ns,
+    };
+    if let Some(filter) = filter {
+        command.insert("filter", filter);
+    }
+    let count = execute(db, command)?;
+    Ok(count_of(&count))
+}
+
+fn count_of(value: &Bson) -> u64 {
+    match value {
+        Bson::Int64(v) => *v as u64,
+        Bson::Int32(v) => *v as u64,
+        _ => 0,
+    }
+}
+
+fn execute(db: &Database, command: Document) -> HttpResult<Bson> {
+    let result = db.handle_request_doc(Bson::Document(command))?;
+    Ok(result.value)
+}
+
+/// The results are returned in relaxed Extended JSON.
+fn to_json(value: Bson) -> Value {
+    ejson::to_json(value, &JsonOptions::default())
+}
+
+fn parse_body(body: &str) -> HttpResult<Bson> {
+    let value: Value = serde_json::from_str(body)
+        .map_err(|err| HttpResponse::error(400, format!("invalid json: {}", err)))?;
+    Ok(ejson::from_json(value)?)
+}
+
+fn parse_body_document(body: &str) -> HttpResult<Document> {
+    match parse_body(body)? {
+        Bson::Document(doc) => Ok(doc),
+        _ => Err(HttpResponse::error(400, "a document is expected")),
+    }
+}
+
+fn query_value<'a>(query: &'a [(String, String)], key: &str) -> Option<&'a str> {
+    query.iter()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.as_str())
+}
+
+fn query_document(query: &[(String, String)], key: &str) -> HttpResult<Option<Document>> {
+    match query_value(query, key) {
+        Some(value) => parse_body_document(value).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn query_usize(query: &[(String, String)], key: &str) -> HttpResult<Option<usize>> {
+    match query_value(query, key) {
+        Some(value) => value.parse::<usize>()
+            .map(Some)
+            .map_err(|_| HttpResponse::error(400, format!("'{}' should be a non-negative integer", key))),
+        None => Ok(None),
+    }
+}
+
+fn parse_query_string(query: &str) -> Vec<(String, String)> {
+    query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.find('=') {
+            Some(index) => (percent_decode(&pair[..index]), percent_decode(&pair[index + 1..])),
+            None => (percent_decode(pair), String::new()),
+        })
+        .collect()
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut result: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                match (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        result.push(high << 4 | low);
+                        i += 3;
+                        continue;
+                    }
+                    _ => result.push(b'%'),
+                }
+            }
+            b'+' => result.push(b' '),
+            byte => result.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&result).into_owned()
+}
+
+fn hex_value(ch: u8) -> Option<u8> {
+    match ch {
+        b'0'..=b'9' => Some(ch - b'0'),
+        b'a'..=b'f' => Some(ch - b'a' + 10),
+        b'A'..=b'F' => Some(ch - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polodb_core::Database;
+    use crate::{handle_http_request, percent_decode};
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("%7B%22a%22%3A1%7D"), r#"{"a":1}"#);
+        assert_eq!(percent_decode("a+b%2"), "a b%2");
+    }
+
+    #[test]
+    fn test_rest_crud() {
+        let db = Database::open_memory().unwrap();
+
+        let resp = handle_http_request(&db, "POST", "/collections/books/documents", r#"[
+            { "title": "1984", "pages": 328 },
+            { "title": "Animal Farm", "pages": 112 },
+            { "title": "The Great Gatsby", "pages": 180 }
+        ]"#);
+        assert_eq!(resp.status, 200);
+
+        let resp = handle_http_request(&db, "GET", "/collections/books/documents?skip=1&limit=1", "");
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body["total"], 3);
+        assert_eq!(resp.body["items"].as_array().unwrap().len(), 1);
+        assert!(resp.body["items"][0]["_id"]["$oid"].is_string());
+
+        let resp = handle_http_request(
+            &db, "GET",
+            "/collections/books/documents?filter=%7B%22pages%22%3A%7B%22%24gt%22%3A150%7D%7D&limit=1",
+            "",
+        );
+        assert_eq!(resp.body["total"], 2);
+        assert_eq!(resp.body["items"][0]["title"], "1984");
+
+        let resp = handle_http_request(&db, "GET", "/collections/unknown/documents", "");
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body["total"], 0);
+
+        let resp = handle_http_request(&db, "PATCH", "/collections/books/documents", r#"{
+            "filter": { "title": "1984" },
+            "update": { "$set": { "pages": 330 } }
+        }"#);
+        assert_eq!(resp.body["modifiedCount"], 1);
+
+        let resp = handle_http_request(
+            &db, "DELETE",
+            "/collections/books/documents?filter=%7B%22title%22%3A%221984%22%7D",
+            "",
+        );
+        assert_eq!(resp.body["deletedCount"], 1);
+
+        let resp = handle_http_request(&db, "GET", "/collections/books/count", "");
+        assert_eq!(resp.body, 2);
+
+        let resp = handle_http_request(&db, "GET", "/unknown", "");
+        assert_eq!(resp.status, 404);
+    }
+
+}
@@ -0,0 +1,69 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+use std::process::exit;
+use std::sync::Arc;
+use clap::{App, Arg};
+use polodb_core::Database;
+use polodb_core::middleware::{LoggingMiddleware, ValidationMiddleware};
+use polodb_http::HttpServer;
+
+fn main() {
+    let version = Database::get_version();
+    let matches = App::new("PoloDB HTTP")
+        .version(version.as_str())
+        .about("Serve a PoloDB database over HTTP")
+        .author("Vincent Chan <okcdz@diverse.space>")
+        .arg(
+            Arg::with_name("path")
+                .short("p")
+                .long("path")
+                .value_name("PATH")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("listen")
+                .long("listen")
+                .value_name("ADDR")
+                .help("the address to listen on")
+                .default_value("127.0.0.1:9090")
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("log")
+                .help("print log")
+                .long("log")
+                .short("l")
+        )
+        .get_matches();
+
+    Database::set_log(matches.is_present("log"));
+
+    let db = match matches.value_of("path") {
+        Some(path) => Database::open_file(path),
+        None => Database::open_memory(),
+    };
+    let db = match db {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("open db failed: {}", err);
+            exit(6);
+        }
+    };
+    db.use_middleware(LoggingMiddleware);
+    db.use_middleware(ValidationMiddleware);
+
+    let listen = matches.value_of("listen").unwrap();
+    let server = match HttpServer::bind(Arc::new(db), listen) {
+        Ok(server) => server,
+        Err(err) => {
+            eprintln!("listen on {} failed: {}", listen, err);
+            exit(7);
+        }
+    };
+
+    eprintln!("listening on http://{}", server.server_addr());
+    server.run();
+}
@@ -0,0 +1,5 @@
+const COMMANDS: &[&str] = &["handle_message"];
+
+fn main() {
+    tauri_plugin::Builder::new(COMMANDS).build();
+}
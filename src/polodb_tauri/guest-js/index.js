@@ -0,0 +1,17 @@
+// The binding of the webview, the same as the `Database` of polodb_wasm,
+// except that `handleMessage` returns a promise.
+import { invoke } from '@tauri-apps/api/core';
+
+export class Database {
+
+  /**
+   * Send a BSON request to the database of the app.
+   * @param {Uint8Array} buf the bytes of the request
+   * @returns {Promise<Uint8Array>} the bytes of the result
+   */
+  async handleMessage(buf) {
+    const result = await invoke('plugin:polodb|handle_message', buf);
+    return new Uint8Array(result);
+  }
+
+}
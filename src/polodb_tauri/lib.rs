@@ -0,0 +1,207 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! A Tauri plugin giving the webview a local PoloDB database.
+//!
+//! The webview sends the same BSON requests as `polodb_wasm` through the IPC of Tauri,
+//! so the JavaScript API is shared, except that `handleMessage` returns a promise.
+//!
+//! ```rust,ignore
+//! fn main() {
+//!     tauri::Builder::default()
+//!         .plugin(tauri_plugin_polodb::init())
+//!         .run(tauri::generate_context!())
+//!         .expect("error while running tauri application");
+//! }
+//! ```
+//!
+//! The plugin is configured in `tauri.conf.json`:
+//!
+//! ```json
+//! {
+//!   "plugins": {
+//!     "polodb": {
+//!       "path": "todos.db",
+//!       "collections": {
+//!         "todos": "readWrite",
+//!         "settings": "read"
+//!       }
+//!     }
+//!   }
+//! }
+//! ```
+//!
+//! The path is relative to the data directory of the app, `polodb.db` by default.
+//! If `collections` is given, the webview can only access the collections in it,
+//! and only read the collections of `read`. The scope is a middleware of the database,
+//! so the [`Collection`](polodb_core::Collection) API of the Rust side is not scoped,
+//! which gets the database by [`PoloDbExt::polodb`].
+//!
+//! The capabilities of the window should include `polodb:default`.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use polodb_core::{bson, Database, DbErr, DbResult, HandleRequestResult};
+use polodb_core::commands::CommandMessage;
+use polodb_core::middleware::{Middleware, Next};
+use serde::{Deserialize, Serialize, Serializer};
+use tauri::ipc::{InvokeBody, Request, Response};
+use tauri::plugin::{Builder, TauriPlugin};
+use tauri::{Manager, Runtime, State};
+
+const DEFAULT_DB_NAME: &str = "polodb.db";
+
+/// The config of the plugin in `tauri.conf.json`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Config {
+    /// The database file, relative to the data directory of the app.
+    pub path: Option<PathBuf>,
+
+    /// Keep the database in memory instead of a file.
+    #[serde(default)]
+    pub memory: bool,
+
+    /// The collections the webview can access, all of them if it's empty.
+    #[serde(default)]
+    pub collections: HashMap<String, Access>,
+}
+
+/// What the webview can do with a collection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Access {
+    Read,
+    ReadWrite,
+}
+
+/// The error returned to the webview as a string.
+#[derive(Debug)]
+pub enum Error {
+    Db(DbErr),
+    Tauri(tauri::Error),
+    /// The request is not sent as the binary body.
+    ExpectedBinary,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Db(err) => write!(f, "{}", err),
+            Error::Tauri(err) => write!(f, "{}", err),
+            Error::ExpectedBinary => write!(f, "the request should be the bytes of a BSON document"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<DbErr> for Error {
+    fn from(err: DbErr) -> Self {
+        Error::Db(err)
+    }
+}
+
+impl From<bson::de::Error> for Error {
+    fn from(err: bson::de::Error) -> Self {
+        Error::Db(err.into())
+    }
+}
+
+impl From<bson::ser::Error> for Error {
+    fn from(err: bson::ser::Error) -> Self {
+        Error::Db(err.into())
+    }
+}
+
+impl From<tauri::Error> for Error {
+    fn from(err: tauri::Error) -> Self {
+        Error::Tauri(err)
+    }
+}
+
+impl Serialize for Error {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+/// The database managed by the app.
+pub struct PoloDb {
+    db: Database,
+}
+
+/// Get the database from the app, a window or a webview.
+pub trait PoloDbExt<R: Runtime> {
+    fn polodb(&self) -> &Database;
+}
+
+impl<R: Runtime, T: Manager<R>> PoloDbExt<R> for T {
+    fn polodb(&self) -> &Database {
+        &self.state::<PoloDb>().inner().db
+    }
+}
+
+/// Reject the commands on the collections out of the scope.
+struct CollectionScope {
+    collections: HashMap<String, Access>,
+}
+
+impl Middleware for CollectionScope {
+    fn handle(&self, command: CommandMessage, next: Next) -> DbResult<HandleRequestResult> {
+        if let Some(ns) = command.ns() {
+            match self.collections.get(ns) {
+                Some(Access::ReadWrite) => (),
+                Some(Access::Read) if !command.is_write() => (),
+                _ => return Err(DbErr::PermissionDenied(format!("{} on \"{}\"", command.name(), ns))),
+            }
+        }
+        next.run(command)
+    }
+}
+
+/// Handle a BSON request of the webview, the same as `handleMessage` of `polodb_wasm`.
+#[tauri::command]
+fn handle_message(request: Request<'_>, state: State<'_, PoloDb>) -> Result<Response, Error> {
+    let buf = match request.body() {
+        InvokeBody::Raw(buf) => buf,
+        _ => return Err(Error::ExpectedBinary),
+    };
+    let value = bson::from_slice(buf)?;
+    let result = state.db.handle_request_doc(value)?;
+    Ok(Response::new(bson::to_vec(&result.value)?))
+}
+
+fn open_database<R: Runtime>(app: &tauri::AppHandle<R>, config: &Config) -> Result<Database, Error> {
+    if config.memory {
+        return Ok(Database::open_memory()?);
+    }
+    let data_dir = app.path().app_data_dir()?;
+    std::fs::create_dir_all(&data_dir).map_err(|err| DbErr::IOErr(Box::new(err)))?;
+    let path = data_dir.join(config.path.as_deref().unwrap_or_else(|| Path::new(DEFAULT_DB_NAME)));
+    Ok(Database::open_file(path)?)
+}
+
+/// Create the plugin, the database is opened when the app is set up.
+pub fn init<R: Runtime>() -> TauriPlugin<R, Option<Config>> {
+    Builder::<R, Option<Config>>::new("polodb")
+        .invoke_handler(tauri::generate_handler![handle_message])
+        .setup(|app, api| {
+            let default_config = Config::default();
+            let config = api.config().as_ref().unwrap_or(&default_config);
+
+            let db = open_database(app, config)?;
+            if !config.collections.is_empty() {
+                db.use_middleware(CollectionScope {
+                    collections: config.collections.clone(),
+                });
+            }
+
+            app.manage(PoloDb { db });
+            Ok(())
+        })
+        .build()
+}
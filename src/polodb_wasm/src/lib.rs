@@ -3,19 +3,33 @@ use wasm_bindgen::JsCast;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Reflect;
 #[cfg(target_arch = "wasm32")]
-use web_sys::IdbDatabase;
+use web_sys::{IdbDatabase, IdbFactory};
 #[cfg(target_arch = "wasm32")]
 use polodb_core::IndexedDbContext;
 use std::rc::Rc;
 use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
-use polodb_core::{Database, bson};
+use serde::Serialize;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use web_sys::FileSystemSyncAccessHandle;
+use polodb_core::{Database, DbErr, DbResult, IndexOptions, OpfsContext, bson};
+use polodb_core::bson::{Bson, Document, doc};
+use polodb_core::subscription::{ChangeEvent, ChangeOperation, Subscription};
+
+mod sync;
+
+pub use sync::SyncClient;
+
+// the documents examined by each step of `handleMessageAsync`
+const SCAN_SLICE: i32 = 1000;
 
 #[wasm_bindgen(js_name = Database)]
 pub struct DatabaseWrapper {
     db:        Rc<RefCell<Option<Database>>>,
-    onsuccess: Option<js_sys::Function>,
-    onerror:   Option<js_sys::Function>,
+    watchers:  Rc<RefCell<Watchers>>,
+    onsuccess:  Option<js_sys::Function>,
+    onerror:    Option<js_sys::Function>,
+    onprogress: Option<js_sys::Function>,
 }
 
 #[wasm_bindgen(js_class = Database)]
@@ -25,20 +39,38 @@ impl DatabaseWrapper {
     pub fn new() -> DatabaseWrapper {
         DatabaseWrapper {
             db: Rc::new(RefCell::new(None)),
+            watchers: Rc::new(RefCell::new(Watchers::default())),
             onsuccess: None,
             onerror: None,
+            onprogress: None,
         }
     }
 
-    /// If a name is provided, the data will be synced to IndexedDB
+    /// If a name is provided, the data will be synced to IndexedDB.
+    ///
+    /// The failures of opening IndexedDB are passed to `onerror`, or thrown if it's not set.
     #[wasm_bindgen]
-    pub fn open(&mut self, name: Option<String>) -> Result<(), JsError> {
+    pub fn open(&mut self, name: Option<String>) -> Result<(), JsValue> {
         match name {
             Some(name) => {
-                self.open_indexeddb(name.as_str())?;
+                let result = open_indexeddb(
+                    &self.db,
+                    name.as_str(),
+                    self.onprogress.clone(),
+                    self.onsuccess.clone(),
+                    self.onerror.clone(),
+                );
+                if let Err(err) = result {
+                    match &self.onerror {
+                        Some(onerror) => {
+                            let _ = onerror.call1(&JsValue::UNDEFINED, &err);
+                        }
+                        None => return Err(err),
+                    }
+                }
             },
             None => {
-                let db = Database::open_memory()?;
+                let db = Database::open_memory().map_err(|err| typed_error(DATABASE_ERROR, &err.to_string(), None))?;
                 let mut db_ref = self.db.as_ref().borrow_mut();
                 *db_ref = Some(db);
             },
@@ -46,57 +78,226 @@ impl DatabaseWrapper {
         Ok(())
     }
 
-    #[cfg(not(target_arch = "wasm32"))]
-    fn open_indexeddb(&mut self, _name: &str) -> Result<(), JsError> {
-        unreachable!()
-    }
-
-    #[cfg(target_arch = "wasm32")]
-    fn open_indexeddb(&mut self, name: &str) -> Result<(), JsError> {
-        let window = web_sys::window().unwrap();
-        let factory = window.indexed_db().unwrap().expect("indexeddb not supported");
-
-        let open_request = factory.open(name).unwrap();
-
-        {
-            let db = self.db.clone();
-            let name = name.to_string();
-            let user_onsuccess = self.onsuccess().clone();
-            let onsuccess = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {
-                let db = db.clone();
-                let name = name.to_string();
-                let user_onsuccess = user_onsuccess.clone();
-                let target = Reflect::get(event.as_ref(), &"target".into()).unwrap();
-                let idb = Reflect::get(target.as_ref(), &"result".into()).unwrap().dyn_into::<IdbDatabase>().unwrap();
-                // val
-                let raw_db = Database::open_indexeddb(IndexedDbContext {
-                    name,
-                    idb,
-                }).unwrap();
-                let mut db_ref = db.as_ref().borrow_mut();
-                *db_ref = Some(raw_db);
-
-                if let Some(user_onsuccess) = user_onsuccess {
-                    user_onsuccess.call0(&JsValue::UNDEFINED).unwrap();
-                }
-            });
-            open_request.set_onsuccess(Some(onsuccess.as_ref().unchecked_ref()));
-            open_request.set_onerror(self.onerror.as_ref());
-        }
+    /// Open the database like `open`, the promise is resolved when it's opened,
+    /// and rejected with the error passed to `onerror` otherwise.
+    /// `onsuccess` and `onerror` are not called.
+    #[wasm_bindgen(js_name = openAsync)]
+    pub fn open_async(&self, name: Option<String>) -> js_sys::Promise {
+        let db = self.db.clone();
+        let onprogress = self.onprogress.clone();
+        js_sys::Promise::new(&mut |resolve, reject| {
+            let result = match &name {
+                Some(name) => open_indexeddb(&db, name, onprogress.clone(), Some(resolve.clone()), Some(reject.clone())),
+                None => Database::open_memory()
+                    .map(|raw_db| {
+                        *db.as_ref().borrow_mut() = Some(raw_db);
+                        let _ = resolve.call0(&JsValue::UNDEFINED);
+                    })
+                    .map_err(|err| typed_error(DATABASE_ERROR, &err.to_string(), None)),
+            };
+            if let Err(err) = result {
+                let _ = reject.call1(&JsValue::UNDEFINED, &err);
+            }
+        })
+    }
 
+    /// Open the database on the handles of the database file and the journal in
+    /// the Origin Private File System, created by `createSyncAccessHandle()` in a
+    /// dedicated worker. It's opened at once and the writes are persisted when
+    /// they are done. The handles are closed when the database is freed.
+    #[wasm_bindgen(js_name = openOpfs)]
+    pub fn open_opfs(&mut self, db_file: FileSystemSyncAccessHandle, journal_file: FileSystemSyncAccessHandle) -> Result<(), JsValue> {
+        let db = Database::open_opfs(OpfsContext { db_file, journal_file })
+            .map_err(|err| typed_error(DATABASE_ERROR, &err.to_string(), None))?;
+        *self.db.as_ref().borrow_mut() = Some(db);
         Ok(())
     }
 
     #[wasm_bindgen(js_name = handleMessage)]
     pub fn handle_message(&self, buf: &[u8]) -> Result<Vec<u8>, JsError> {
         let mut db_ref = self.db.as_ref().borrow_mut();
-        let db = db_ref.as_mut().unwrap();
+        let db = db_ref.as_mut().ok_or_else(|| JsError::new("the database is not opened"))?;
         let bson = bson::from_slice(buf)?;
-        let result = db.handle_request_doc(bson)?;
-        let result_vec = bson::to_vec(&result.value)?;
+        let result = db.handle_request_doc(bson);
+        drop(db_ref);
+        dispatch_changes(&self.watchers);
+        let result_vec = bson::to_vec(&result?.value)?;
         Ok(result_vec)
     }
 
+    /// Handle the message like `handleMessage`, but a multi find is run on the slices
+    /// of the collection, yielding to the event loop between them, so a large scan
+    /// doesn't freeze the page. The documents changed between the slices may be seen or not.
+    ///
+    /// The result is a `Uint8Array` with its own buffer, not a view of the memory
+    /// of wasm, so the buffer can be transferred to a worker by `postMessage`.
+    #[wasm_bindgen(js_name = handleMessageAsync)]
+    pub fn handle_message_async(&self, buf: &[u8]) -> js_sys::Promise {
+        let db = self.db.clone();
+        let watchers = self.watchers.clone();
+        let request = bson::from_slice::<Document>(buf);
+        future_to_promise(async move {
+            let request = request.map_err(to_js_error)?;
+            let result = handle_request_in_slices(&db, request).await;
+            dispatch_changes(&watchers);
+            let bytes = bson::to_vec(&result?).map_err(to_js_error)?;
+            Ok(js_sys::Uint8Array::from(bytes.as_slice()).into())
+        })
+    }
+
+    /// Insert a document in BSON, resolved with the result like `{ insertedId }` in BSON.
+    ///
+    /// The methods returning a promise take and return the documents in BSON like `handleMessage`.
+    /// The documents, the arrays and the results of the writes are resolved as `Uint8Array`,
+    /// a missing document as `null` and a count as a number.
+    #[wasm_bindgen(js_name = insertOne)]
+    pub fn insert_one(&self, collection: &str, doc: &[u8]) -> js_sys::Promise {
+        let request = parse_document(doc).map(|doc| insert_request(collection, vec![Bson::Document(doc)]));
+        self.request_promise(request)
+    }
+
+    /// Insert an array of the documents in BSON, resolved with the result like `{ insertedIds }` in BSON.
+    #[wasm_bindgen(js_name = insertMany)]
+    pub fn insert_many(&self, collection: &str, docs: js_sys::Array) -> js_sys::Promise {
+        let docs: Result<bson::Array, JsValue> = docs
+            .iter()
+            .map(|doc| {
+                let bytes = doc
+                    .dyn_into::<js_sys::Uint8Array>()
+                    .map_err(|_| to_js_error("a document should be a Uint8Array"))?;
+                parse_document(&bytes.to_vec()).map(Bson::Document)
+            })
+            .collect();
+        self.request_promise(docs.map(|docs| insert_request(collection, docs)))
+    }
+
+    /// Find the documents matching the filter, all of them if it's missing. The collection
+    /// is scanned in slices like `handleMessageAsync`, resolved with an array in BSON.
+    #[wasm_bindgen]
+    pub fn find(&self, collection: &str, filter: Option<Vec<u8>>) -> js_sys::Promise {
+        let request = parse_filter(filter).map(|filter| find_request(collection, filter, true));
+        self.request_promise(request)
+    }
+
+    /// Find a document matching the filter, resolved with the document in BSON or `null`.
+    #[wasm_bindgen(js_name = findOne)]
+    pub fn find_one(&self, collection: &str, filter: Option<Vec<u8>>) -> js_sys::Promise {
+        let request = parse_filter(filter).map(|filter| find_request(collection, filter, false));
+        self.request_promise(request)
+    }
+
+    /// Update a document matching the filter, resolved with the result like `{ matchedCount, modifiedCount }` in BSON.
+    #[wasm_bindgen(js_name = updateOne)]
+    pub fn update_one(&self, collection: &str, filter: &[u8], update: &[u8]) -> js_sys::Promise {
+        self.update(collection, filter, update, false)
+    }
+
+    #[wasm_bindgen(js_name = updateMany)]
+    pub fn update_many(&self, collection: &str, filter: &[u8], update: &[u8]) -> js_sys::Promise {
+        self.update(collection, filter, update, true)
+    }
+
+    /// Delete a document matching the filter, resolved with the result like `{ deletedCount }` in BSON.
+    #[wasm_bindgen(js_name = deleteOne)]
+    pub fn delete_one(&self, collection: &str, filter: &[u8]) -> js_sys::Promise {
+        self.delete(collection, filter, false)
+    }
+
+    #[wasm_bindgen(js_name = deleteMany)]
+    pub fn delete_many(&self, collection: &str, filter: &[u8]) -> js_sys::Promise {
+        self.delete(collection, filter, true)
+    }
+
+    /// Resolved with the count of the documents of the collection.
+    #[wasm_bindgen(js_name = countDocuments)]
+    pub fn count_documents(&self, collection: &str) -> js_sys::Promise {
+        self.request_promise(Ok(count_request(collection)))
+    }
+
+    /// The collection of the name, whose methods take and return the plain objects
+    /// of JavaScript instead of BSON.
+    #[wasm_bindgen]
+    pub fn collection(&self, name: &str) -> CollectionWrapper {
+        CollectionWrapper {
+            db: self.db.clone(),
+            watchers: self.watchers.clone(),
+            name: name.to_string(),
+        }
+    }
+
+    /// Create the collections and the indexes declared by the schema if they don't exist,
+    /// so it can be called on every startup. The schema is like
+    /// `{ collections: [{ name, indexes: [{ key: { age: 1 }, name, unique }] }] }`.
+    ///
+    /// A `validator` of a collection is rejected, the documents are not validated by PoloDB.
+    #[wasm_bindgen(js_name = ensureSchema)]
+    pub fn ensure_schema(&self, schema: JsValue) -> Result<(), JsError> {
+        let json = js_sys::JSON::stringify(&schema)
+            .map_err(|_| JsError::new("the schema can't be converted to JSON"))?;
+        let json: serde_json::Value = serde_json::from_str(&String::from(json))?;
+        let schema = match bson::to_bson(&json)? {
+            Bson::Document(schema) => schema,
+            _ => return Err(JsError::new("the schema should be an object")),
+        };
+
+        let db_ref = self.db.as_ref().borrow();
+        let db = db_ref.as_ref().ok_or_else(|| JsError::new("the database is not opened"))?;
+        let result = ensure_schema(db, &schema);
+        drop(db_ref);
+        dispatch_changes(&self.watchers);
+        Ok(result?)
+    }
+
+    /// Call `callback` with the changes of the collection, after the messages changing
+    /// them are handled and the transactions are committed. A change is a `Uint8Array`
+    /// of a BSON document like `{ operation: "insert", collection, documentKey, fullDocument }`,
+    /// the `fullDocument` of a deletion is the document deleted.
+    ///
+    /// Return the id of the watcher passed to `unwatch`.
+    #[wasm_bindgen]
+    pub fn watch(&self, collection: &str, callback: js_sys::Function) -> Result<u32, JsError> {
+        let db_ref = self.db.as_ref().borrow();
+        let db = db_ref.as_ref().ok_or_else(|| JsError::new("the database is not opened"))?;
+        let subscription = db.collection::<Document>(collection).watch()?;
+        Ok(self.watchers.borrow_mut().add(subscription, callback))
+    }
+
+    /// Delete the documents of the collection whose `expireAt` field is a date in the past,
+    /// return the count of them. There is no background thread in the browser,
+    /// so it's called by the application, on startup or by a timer.
+    #[wasm_bindgen(js_name = deleteExpired)]
+    pub fn delete_expired(&self, collection: &str) -> Result<f64, JsError> {
+        let db_ref = self.db.as_ref().borrow();
+        let db = db_ref.as_ref().ok_or_else(|| JsError::new("the database is not opened"))?;
+        let result = db.collection::<Document>(collection).delete_expired();
+        drop(db_ref);
+        dispatch_changes(&self.watchers);
+        Ok(result?.deleted_count as f64)
+    }
+
+    /// Stop the watcher returned by `watch`, return false if it's not found.
+    #[wasm_bindgen]
+    pub fn unwatch(&self, id: u32) -> bool {
+        self.watchers.borrow_mut().remove(id)
+    }
+
+    fn update(&self, collection: &str, filter: &[u8], update: &[u8], multi: bool) -> js_sys::Promise {
+        let request = parse_document(filter).and_then(|filter| {
+            let update = parse_document(update)?;
+            Ok(update_request(collection, filter, update, multi))
+        });
+        self.request_promise(request)
+    }
+
+    fn delete(&self, collection: &str, filter: &[u8], multi: bool) -> js_sys::Promise {
+        let request = parse_document(filter).map(|filter| delete_request(collection, filter, multi));
+        self.request_promise(request)
+    }
+
+    fn request_promise(&self, request: Result<Document, JsValue>) -> js_sys::Promise {
+        request_promise(&self.db, &self.watchers, request, bson_to_js)
+    }
+
     #[wasm_bindgen(getter)]
     pub fn onsuccess(&self) -> Option<js_sys::Function> {
         self.onsuccess.clone()
@@ -107,6 +308,10 @@ impl DatabaseWrapper {
         self.onsuccess = Some(fun);
     }
 
+    /// Called with an `Error` if the database fails to open. The `name` of the error
+    /// is `IndexedDbNotSupported`, `IndexedDbBlocked` if the storage is denied, such as
+    /// the third-party storage blocked or in private browsing, `IndexedDbOpenFailed`,
+    /// or `DatabaseError` if the data can't be loaded. The `cause` is the error of the browser.
     #[wasm_bindgen(getter)]
     pub fn onerror(&self) -> Option<js_sys::Function> {
         self.onerror.clone()
@@ -116,4 +321,530 @@ impl DatabaseWrapper {
     pub fn set_onerror(&mut self, fun: js_sys::Function) {
         self.onerror = Some(fun);
     }
+
+    /// Called with `{ framesLoaded, framesTotal, bytesLoaded, done }` while
+    /// the frames in IndexedDB are read on opening, set it before `open`.
+    #[wasm_bindgen(getter)]
+    pub fn onprogress(&self) -> Option<js_sys::Function> {
+        self.onprogress.clone()
+    }
+
+    #[wasm_bindgen(setter)]
+    pub fn set_onprogress(&mut self, fun: js_sys::Function) {
+        self.onprogress = Some(fun);
+    }
+}
+
+/// A collection returned by `Database.collection`. The documents, the filters and the updates
+/// are the objects of JavaScript, converted to BSON by their JSON types, so the integers
+/// are stored as `Int64`. The results are resolved as the objects, an `ObjectId` is like
+/// `{ $oid: "..." }` and a date is like `{ $date: ... }`.
+#[wasm_bindgen(js_name = Collection)]
+pub struct CollectionWrapper {
+    db:       Rc<RefCell<Option<Database>>>,
+    watchers: Rc<RefCell<Watchers>>,
+    name:     String,
+}
+
+#[wasm_bindgen(js_class = Collection)]
+impl CollectionWrapper {
+
+    #[wasm_bindgen(getter)]
+    pub fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    /// Resolved with `{ insertedId }`.
+    #[wasm_bindgen(js_name = insertOne)]
+    pub fn insert_one(&self, doc: JsValue) -> js_sys::Promise {
+        let request = js_to_document(doc).map(|doc| insert_request(&self.name, vec![Bson::Document(doc)]));
+        self.request_promise(request)
+    }
+
+    /// Resolved with `{ insertedIds }`, the ids by the indexes of the documents.
+    #[wasm_bindgen(js_name = insertMany)]
+    pub fn insert_many(&self, docs: js_sys::Array) -> js_sys::Promise {
+        let docs: Result<bson::Array, JsValue> = docs
+            .iter()
+            .map(|doc| js_to_document(doc).map(Bson::Document))
+            .collect();
+        self.request_promise(docs.map(|docs| insert_request(&self.name, docs)))
+    }
+
+    /// Resolved with an array of the documents matching the filter, all of them if it's missing.
+    #[wasm_bindgen]
+    pub fn find(&self, filter: JsValue) -> js_sys::Promise {
+        let request = js_to_filter(filter).map(|filter| find_request(&self.name, filter, true));
+        self.request_promise(request)
+    }
+
+    /// Resolved with a document matching the filter, or `null`.
+    #[wasm_bindgen(js_name = findOne)]
+    pub fn find_one(&self, filter: JsValue) -> js_sys::Promise {
+        let request = js_to_filter(filter).map(|filter| find_request(&self.name, filter, false));
+        self.request_promise(request)
+    }
+
+    /// Resolved with `{ matchedCount, modifiedCount }`.
+    #[wasm_bindgen(js_name = updateOne)]
+    pub fn update_one(&self, filter: JsValue, update: JsValue) -> js_sys::Promise {
+        self.update(filter, update, false)
+    }
+
+    #[wasm_bindgen(js_name = updateMany)]
+    pub fn update_many(&self, filter: JsValue, update: JsValue) -> js_sys::Promise {
+        self.update(filter, update, true)
+    }
+
+    /// Resolved with `{ deletedCount }`.
+    #[wasm_bindgen(js_name = deleteOne)]
+    pub fn delete_one(&self, filter: JsValue) -> js_sys::Promise {
+        self.delete(filter, false)
+    }
+
+    #[wasm_bindgen(js_name = deleteMany)]
+    pub fn delete_many(&self, filter: JsValue) -> js_sys::Promise {
+        self.delete(filter, true)
+    }
+
+    #[wasm_bindgen(js_name = countDocuments)]
+    pub fn count_documents(&self) -> js_sys::Promise {
+        self.request_promise(Ok(count_request(&self.name)))
+    }
+
+    fn update(&self, filter: JsValue, update: JsValue, multi: bool) -> js_sys::Promise {
+        let request = js_to_filter(filter).and_then(|filter| {
+            let update = js_to_document(update)?;
+            Ok(update_request(&self.name, filter, update, multi))
+        });
+        self.request_promise(request)
+    }
+
+    fn delete(&self, filter: JsValue, multi: bool) -> js_sys::Promise {
+        let request = js_to_filter(filter).map(|filter| delete_request(&self.name, filter, multi));
+        self.request_promise(request)
+    }
+
+    fn request_promise(&self, request: Result<Document, JsValue>) -> js_sys::Promise {
+        request_promise(&self.db, &self.watchers, request, bson_to_object)
+    }
+
+}
+
+const INDEXEDDB_NOT_SUPPORTED: &str = "IndexedDbNotSupported";
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const INDEXEDDB_BLOCKED: &str = "IndexedDbBlocked";
+#[cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+const INDEXEDDB_OPEN_FAILED: &str = "IndexedDbOpenFailed";
+const DATABASE_ERROR: &str = "DatabaseError";
+
+/// An `Error` of JavaScript with the `name` telling the kind of the failure.
+fn typed_error(name: &str, message: &str, cause: Option<&JsValue>) -> JsValue {
+    let error = js_sys::Error::new(message);
+    error.set_name(name);
+    if let Some(cause) = cause {
+        let _ = js_sys::Reflect::set(&error, &"cause".into(), cause);
+    }
+    error.into()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn open_indexeddb(
+    _db: &Rc<RefCell<Option<Database>>>,
+    _name: &str,
+    _onprogress: Option<js_sys::Function>,
+    _onsuccess: Option<js_sys::Function>,
+    _onerror: Option<js_sys::Function>,
+) -> Result<(), JsValue> {
+    Err(typed_error(INDEXEDDB_NOT_SUPPORTED, "IndexedDB is only supported in the browsers", None))
+}
+
+/// Open the IndexedDB database, `onsuccess` or `onerror` is called when it's done.
+/// The errors before the request is sent are returned.
+#[cfg(target_arch = "wasm32")]
+fn open_indexeddb(
+    db: &Rc<RefCell<Option<Database>>>,
+    name: &str,
+    onprogress: Option<js_sys::Function>,
+    onsuccess: Option<js_sys::Function>,
+    onerror: Option<js_sys::Function>,
+) -> Result<(), JsValue> {
+    // `indexedDB` is on the global object of the workers as well,
+    // reading it throws a `SecurityError` if the storage is denied
+    let factory = Reflect::get(&js_sys::global(), &"indexedDB".into())
+        .map_err(|err| typed_error(INDEXEDDB_BLOCKED, "the access to IndexedDB is denied", Some(&err)))?;
+    if factory.is_undefined() || factory.is_null() {
+        return Err(typed_error(INDEXEDDB_NOT_SUPPORTED, "IndexedDB is not supported", None));
+    }
+    let factory: IdbFactory = factory
+        .dyn_into()
+        .map_err(|err| typed_error(INDEXEDDB_NOT_SUPPORTED, "IndexedDB is not supported", Some(&err)))?;
+
+    let open_request = factory
+        .open_with_u32(name, 2)
+        .map_err(|err| typed_error(INDEXEDDB_BLOCKED, "the access to IndexedDB is denied", Some(&err)))?;
+
+    // the committed pages are put in the frame store, and the last commit in the meta store,
+    // the stores missing are created when the database of the older versions is upgraded
+    let onupgradeneeded = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {
+        // the upgrade is aborted if it fails, the request fails with `onerror`
+        let _ = create_stores(&event);
+    });
+    open_request.set_onupgradeneeded(Some(onupgradeneeded.as_ref().unchecked_ref()));
+    onupgradeneeded.forget();
+
+    {
+        let db = db.clone();
+        let name = name.to_string();
+        let onerror = onerror.clone();
+        let request_onsuccess = Closure::<dyn Fn(JsValue)>::new(move |event: JsValue| {
+            let fail = |onerror: &Option<js_sys::Function>, err: JsValue| {
+                if let Some(onerror) = onerror {
+                    let _ = onerror.call1(&JsValue::UNDEFINED, &err);
+                }
+            };
+            let idb = match request_result(&event) {
+                Ok(idb) => idb,
+                Err(err) => {
+                    return fail(&onerror, typed_error(INDEXEDDB_OPEN_FAILED, "failed to open IndexedDB", Some(&err)));
+                }
+            };
+
+            // the database is set after the frames are replayed
+            let loaded_db = db.clone();
+            let loaded_onsuccess = onsuccess.clone();
+            let loaded_onerror = onerror.clone();
+            let result = Database::open_indexeddb(IndexedDbContext {
+                name: name.clone(),
+                idb,
+                on_progress: onprogress.clone(),
+            }, move |raw_db| match raw_db {
+                Ok(raw_db) => {
+                    *loaded_db.as_ref().borrow_mut() = Some(raw_db);
+                    if let Some(onsuccess) = &loaded_onsuccess {
+                        let _ = onsuccess.call0(&JsValue::UNDEFINED);
+                    }
+                }
+                Err(err) => fail(&loaded_onerror, typed_error(DATABASE_ERROR, &err.to_string(), None)),
+            });
+            if let Err(err) = result {
+                fail(&onerror, typed_error(DATABASE_ERROR, &err.to_string(), None));
+            }
+        });
+        open_request.set_onsuccess(Some(request_onsuccess.as_ref().unchecked_ref()));
+        request_onsuccess.forget();
+    }
+
+    {
+        let error_request = open_request.clone();
+        let request_onerror = Closure::<dyn Fn(JsValue)>::new(move |_event: JsValue| {
+            let cause = Reflect::get(error_request.as_ref(), &"error".into()).unwrap_or(JsValue::NULL);
+            let err = typed_error(INDEXEDDB_OPEN_FAILED, "failed to open IndexedDB", Some(&cause));
+            if let Some(onerror) = &onerror {
+                let _ = onerror.call1(&JsValue::UNDEFINED, &err);
+            }
+        });
+        open_request.set_onerror(Some(request_onerror.as_ref().unchecked_ref()));
+        request_onerror.forget();
+    }
+
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn request_result(event: &JsValue) -> Result<IdbDatabase, JsValue> {
+    let target = Reflect::get(event, &"target".into())?;
+    Reflect::get(target.as_ref(), &"result".into())?.dyn_into::<IdbDatabase>()
+}
+
+#[cfg(target_arch = "wasm32")]
+fn create_stores(event: &JsValue) -> Result<(), JsValue> {
+    let idb = request_result(event)?;
+    for store in [IndexedDbContext::FRAME_STORE, IndexedDbContext::META_STORE] {
+        if !idb.object_store_names().contains(store) {
+            idb.create_object_store(store)?;
+        }
+    }
+    Ok(())
+}
+
+fn ensure_schema(db: &Database, schema: &Document) -> DbResult<()> {
+    let collections = match schema.get("collections") {
+        Some(Bson::Array(collections)) => collections,
+        None => return Ok(()),
+        Some(_) => return Err(DbErr::ValidationError("ensureSchema: collections should be an array".into())),
+    };
+    for item in collections {
+        let spec = item
+            .as_document()
+            .ok_or_else(|| DbErr::ValidationError("ensureSchema: a collection should be an object".into()))?;
+        let name = spec
+            .get_str("name")
+            .map_err(|_| DbErr::ValidationError("ensureSchema: the name of a collection is missing".into()))?;
+        match spec.get("validator") {
+            None | Some(Bson::Null) => (),
+            Some(Bson::Document(validator)) if validator.is_empty() => (),
+            Some(_) => return Err(DbErr::ValidationError(format!("ensureSchema: the validator of '{}' is not supported", name))),
+        }
+
+        match db.create_collection(name) {
+            Ok(()) | Err(DbErr::CollectionAlreadyExits(_)) => (),
+            Err(err) => return Err(err),
+        }
+
+        let indexes = match spec.get("indexes") {
+            Some(Bson::Array(indexes)) => indexes.as_slice(),
+            None => &[],
+            Some(_) => return Err(DbErr::ValidationError(format!("ensureSchema: the indexes of '{}' should be an array", name))),
+        };
+        let collection = db.collection::<Document>(name);
+        for index in indexes {
+            let index = index
+                .as_document()
+                .ok_or_else(|| DbErr::ValidationError("ensureSchema: an index should be an object".into()))?;
+            let keys = index
+                .get_document("key")
+                .map_err(|_| DbErr::ValidationError(format!("ensureSchema: the key of an index of '{}' is missing", name)))?;
+            let options = IndexOptions {
+                name: index.get_str("name").ok().map(String::from),
+                unique: index.get_bool("unique").ok(),
+            };
+            // an index existing with the same key and options is kept
+            collection.create_index(keys.clone(), options)?;
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Watchers {
+    next_id:  u32,
+    watchers: Vec<(u32, Subscription, js_sys::Function)>,
+}
+
+impl Watchers {
+
+    fn add(&mut self, subscription: Subscription, callback: js_sys::Function) -> u32 {
+        self.next_id += 1;
+        self.watchers.push((self.next_id, subscription, callback));
+        self.next_id
+    }
+
+    fn remove(&mut self, id: u32) -> bool {
+        let len = self.watchers.len();
+        self.watchers.retain(|(watcher_id, _, _)| *watcher_id != id);
+        self.watchers.len() != len
+    }
+
+}
+
+/// Pass the changes received to the callbacks of the watchers. The watchers are
+/// not borrowed when the callbacks are called, so a callback can watch and unwatch.
+fn dispatch_changes(watchers: &Rc<RefCell<Watchers>>) {
+    let mut changes = vec![];
+    for (_, subscription, callback) in &watchers.borrow().watchers {
+        while let Some(event) = subscription.try_recv() {
+            changes.push((callback.clone(), event));
+        }
+    }
+    for (callback, event) in changes {
+        let bytes = match bson::to_vec(&change_to_doc(event)) {
+            Ok(bytes) => bytes,
+            Err(_) => continue,
+        };
+        let change = js_sys::Uint8Array::from(bytes.as_slice());
+        let _ = callback.call1(&JsValue::UNDEFINED, &change);
+    }
+}
+
+fn change_to_doc(event: ChangeEvent) -> Document {
+    let operation = match event.operation {
+        ChangeOperation::Insert => "insert",
+        ChangeOperation::Update => "update",
+        ChangeOperation::Delete => "delete",
+    };
+    doc! {
+        "operation": operation,
+        "collection": event.collection,
+        "documentKey": event.document_key,
+        "fullDocument": event.full_document,
+    }
+}
+
+async fn handle_request_in_slices(db: &Rc<RefCell<Option<Database>>>, request: Document) -> Result<Bson, JsValue> {
+    if !is_sliced_find(&request) {
+        let result = with_db(db, |db| db.handle_request_doc(Bson::Document(request)))?;
+        return Ok(result.value);
+    }
+
+    let mut docs = bson::Array::new();
+    let mut resume_from = None;
+    loop {
+        let mut options = request.get_document("options").cloned().unwrap_or_default();
+        options.insert("scanLimit", SCAN_SLICE);
+        if let Some(key) = resume_from.take() {
+            options.insert("resumeFrom", key);
+        }
+        let mut slice = request.clone();
+        slice.insert("options", options);
+
+        let result = with_db(db, |db| db.handle_request_doc(Bson::Document(slice)))?;
+        let mut result = match result.value {
+            Bson::Document(result) => result,
+            _ => return Err(to_js_error("the result of the find is not a document")),
+        };
+        if let Ok(batch) = result.get_array_mut("batch") {
+            docs.append(batch);
+        }
+        match result.remove("resumeFrom") {
+            Some(key) => resume_from = Some(key),
+            None => break,
+        }
+        yield_to_event_loop().await?;
+    }
+
+    Ok(Bson::Array(docs))
+}
+
+/// Handle the request after the current task, the errors of building it reject the promise.
+fn request_promise(
+    db: &Rc<RefCell<Option<Database>>>,
+    watchers: &Rc<RefCell<Watchers>>,
+    request: Result<Document, JsValue>,
+    to_js: fn(Bson) -> Result<JsValue, JsValue>,
+) -> js_sys::Promise {
+    let db = db.clone();
+    let watchers = watchers.clone();
+    future_to_promise(async move {
+        let result = handle_request_in_slices(&db, request?).await;
+        dispatch_changes(&watchers);
+        to_js(result?)
+    })
+}
+
+fn insert_request(collection: &str, docs: bson::Array) -> Document {
+    doc! {
+        "command": "Insert",
+        "ns": collection,
+        "documents": docs,
+    }
+}
+
+fn find_request(collection: &str, filter: Document, multi: bool) -> Document {
+    doc! {
+        "command": "Find",
+        "ns": collection,
+        "multi": multi,
+        "filter": filter,
+    }
+}
+
+fn update_request(collection: &str, filter: Document, update: Document, multi: bool) -> Document {
+    doc! {
+        "command": "Update",
+        "ns": collection,
+        "filter": filter,
+        "update": update,
+        "multi": multi,
+    }
+}
+
+fn delete_request(collection: &str, filter: Document, multi: bool) -> Document {
+    doc! {
+        "command": "Delete",
+        "ns": collection,
+        "filter": filter,
+        "multi": multi,
+    }
+}
+
+fn count_request(collection: &str) -> Document {
+    doc! {
+        "command": "CountDocuments",
+        "ns": collection,
+    }
+}
+
+fn parse_document(buf: &[u8]) -> Result<Document, JsValue> {
+    bson::from_slice(buf).map_err(to_js_error)
+}
+
+fn parse_filter(filter: Option<Vec<u8>>) -> Result<Document, JsValue> {
+    match filter {
+        Some(filter) => parse_document(&filter),
+        None => Ok(Document::new()),
+    }
+}
+
+fn js_to_document(value: JsValue) -> Result<Document, JsValue> {
+    serde_wasm_bindgen::from_value(value).map_err(to_js_error)
+}
+
+// a missing filter matches all the documents
+fn js_to_filter(filter: JsValue) -> Result<Document, JsValue> {
+    if filter.is_undefined() || filter.is_null() {
+        return Ok(Document::new());
+    }
+    js_to_document(filter)
+}
+
+/// Convert the result to the objects of JavaScript, the documents are plain objects instead of `Map`.
+fn bson_to_object(value: Bson) -> Result<JsValue, JsValue> {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    value.serialize(&serializer).map_err(to_js_error)
+}
+
+/// The documents and the arrays are returned in BSON, the numbers are converted
+/// to the numbers of JavaScript, which may lose the precision of a large `Int64`.
+fn bson_to_js(value: Bson) -> Result<JsValue, JsValue> {
+    let value = match value {
+        Bson::Null => JsValue::NULL,
+        Bson::Boolean(b) => JsValue::from(b),
+        Bson::Int32(i) => JsValue::from(i),
+        Bson::Int64(i) => JsValue::from(i as f64),
+        Bson::Double(f) => JsValue::from(f),
+        Bson::String(s) => JsValue::from(s),
+        value => {
+            let bytes = bson::to_vec(&value).map_err(to_js_error)?;
+            js_sys::Uint8Array::from(bytes.as_slice()).into()
+        }
+    };
+    Ok(value)
+}
+
+// the multi finds without a cursor, the result of them is an array of the documents
+fn is_sliced_find(request: &Document) -> bool {
+    let is_multi_find = request.get_str("command").map(|command| command == "Find").unwrap_or(false)
+        && request.get_bool("multi").unwrap_or(false);
+    let has_cursor = request
+        .get_document("options")
+        .map(|options| options.contains_key("batchSize") || options.contains_key("scanLimit"))
+        .unwrap_or(false);
+    is_multi_find && !has_cursor
+}
+
+fn with_db<T>(db: &Rc<RefCell<Option<Database>>>, f: impl FnOnce(&Database) -> DbResult<T>) -> Result<T, JsValue> {
+    let db_ref = db.as_ref().borrow();
+    let db = db_ref.as_ref().ok_or_else(|| to_js_error("the database is not opened"))?;
+    f(db).map_err(to_js_error)
+}
+
+fn to_js_error(err: impl std::fmt::Display) -> JsValue {
+    JsError::new(&err.to_string()).into()
+}
+
+/// Resolve after a macrotask, so the browser can render and handle the events
+/// in the meantime, which is not the case for a resolved promise.
+async fn yield_to_event_loop() -> Result<(), JsValue> {
+    let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+        let global = js_sys::global();
+        let set_timeout = js_sys::Reflect::get(&global, &"setTimeout".into())
+            .ok()
+            .and_then(|set_timeout| set_timeout.dyn_into::<js_sys::Function>().ok());
+        let _ = match set_timeout {
+            Some(set_timeout) => set_timeout.call2(&global, &resolve, &JsValue::from(0)),
+            None => resolve.call0(&JsValue::UNDEFINED),
+        };
+    });
+    JsFuture::from(promise).await.map(|_| ())
 }
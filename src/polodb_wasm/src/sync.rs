@@ -0,0 +1,356 @@
+//! The sync client of the offline-first apps.
+//!
+//! A `SyncClient` subscribes the changes of the collections committed locally,
+//! and keeps them as change frames until they are pushed to the endpoint.
+//! The frames pulled from the endpoint are applied in one transaction,
+//! a conflict with a local change not pushed is resolved by the policy of the client:
+//!
+//! - `remoteWins`, the default, the remote change is applied and the local one is dropped;
+//! - `localWins`, the remote change is skipped, the local one is pushed later.
+//!
+//! A frame is `{ seq, op, collection, key, document }` in Extended JSON,
+//! `op` is `insert`, `update` or `delete`, `document` is missing for a deletion.
+//! `push` posts an array of frames to the url, `pull` gets an array of frames from it.
+//! The frames of a WebSocket are passed by `pendingChanges`, `acknowledge` and `apply`.
+use std::cell::RefCell;
+use std::rc::Rc;
+use serde_json::{json, Value};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::{future_to_promise, JsFuture};
+use polodb_core::{Database, DbErr, DbResult};
+use polodb_core::bson::{Bson, Document, doc};
+use polodb_core::ejson::{self, JsonOptions};
+use polodb_core::subscription::{ChangeEvent, ChangeOperation, Subscription};
+use crate::{to_js_error, typed_error, with_db, DatabaseWrapper};
+
+const SYNC_ERROR: &str = "SyncError";
+
+struct SyncOptions {
+    url:         Option<String>,
+    collections: Vec<String>,
+    policy:      ConflictPolicy,
+}
+
+impl SyncOptions {
+
+    fn parse(options: &JsValue) -> Result<SyncOptions, JsValue> {
+        let json = js_sys::JSON::stringify(options)
+            .map_err(|_| typed_error(SYNC_ERROR, "the options can't be converted to JSON", None))?;
+        let options: Value = serde_json::from_str(&String::from(json)).map_err(to_js_error)?;
+        let collections = options["collections"]
+            .as_array()
+            .and_then(|collections| collections
+                .iter()
+                .map(|name| name.as_str().map(String::from))
+                .collect::<Option<Vec<String>>>())
+            .ok_or_else(|| typed_error(SYNC_ERROR, "the collections should be an array of the names", None))?;
+        let policy = match options["policy"].as_str() {
+            None | Some("remoteWins") => ConflictPolicy::RemoteWins,
+            Some("localWins") => ConflictPolicy::LocalWins,
+            Some(policy) => return Err(typed_error(SYNC_ERROR, &format!("unknown policy: {}", policy), None)),
+        };
+        Ok(SyncOptions {
+            url: options["url"].as_str().map(String::from),
+            collections,
+            policy,
+        })
+    }
+
+}
+
+#[derive(Clone, Copy)]
+enum ConflictPolicy {
+    RemoteWins,
+    LocalWins,
+}
+
+struct PendingChange {
+    seq:   u64,
+    event: ChangeEvent,
+}
+
+struct SyncState {
+    subscriptions: Vec<Subscription>,
+    pending:       Vec<PendingChange>,
+    next_seq:      u64,
+    policy:        ConflictPolicy,
+}
+
+impl SyncState {
+
+    fn new(db: &Database, collections: &[String], policy: ConflictPolicy) -> DbResult<SyncState> {
+        let subscriptions = collections
+            .iter()
+            .map(|name| db.collection::<Document>(name).subscribe(None))
+            .collect::<DbResult<Vec<Subscription>>>()?;
+        Ok(SyncState {
+            subscriptions,
+            pending: Vec::new(),
+            next_seq: 1,
+            policy,
+        })
+    }
+
+    // the changes committed are sent to the subscriptions synchronously
+    fn collect(&mut self) {
+        for subscription in &self.subscriptions {
+            while let Some(event) = subscription.try_recv() {
+                self.pending.push(PendingChange {
+                    seq: self.next_seq,
+                    event,
+                });
+                self.next_seq += 1;
+            }
+        }
+    }
+
+    fn discard_received(&self) {
+        for subscription in &self.subscriptions {
+            while subscription.try_recv().is_some() {}
+        }
+    }
+
+    fn frames(&mut self) -> Vec<Value> {
+        self.collect();
+        self.pending.iter().map(to_frame).collect()
+    }
+
+    fn acknowledge(&mut self, seq: u64) {
+        self.pending.retain(|change| change.seq > seq);
+    }
+
+    fn has_pending(&self, collection: &str, key: &Bson) -> bool {
+        self.pending
+            .iter()
+            .any(|change| change.event.collection == collection && &change.event.document_key == key)
+    }
+
+    /// Apply the frames pulled, return the count of the frames applied.
+    fn apply(&mut self, db: &Database, frames: Vec<Value>) -> DbResult<usize> {
+        self.collect();
+
+        let mut changes = Vec::with_capacity(frames.len());
+        for frame in frames {
+            let change = RemoteChange::parse(frame)?;
+            if self.has_pending(&change.collection, &change.key) {
+                match self.policy {
+                    ConflictPolicy::LocalWins => continue,
+                    ConflictPolicy::RemoteWins => self.pending.retain(|pending| {
+                        pending.event.collection != change.collection || pending.event.document_key != change.key
+                    }),
+                }
+            }
+            changes.push(change);
+        }
+
+        let mut session = db.start_session()?;
+        session.start_transaction(None)?;
+        for change in &changes {
+            let collection = db.collection::<Document>(&change.collection);
+            collection.delete_one_with_session(doc! { "_id": change.key.clone() }, &mut session)?;
+            if let Some(document) = &change.document {
+                collection.insert_one_with_session(document, &mut session)?;
+            }
+        }
+        session.commit_transaction()?;
+
+        // the changes applied are not pushed back
+        self.discard_received();
+        Ok(changes.len())
+    }
+
+}
+
+struct RemoteChange {
+    collection: String,
+    key:        Bson,
+    // `None` for a deletion
+    document:   Option<Document>,
+}
+
+impl RemoteChange {
+
+    fn parse(frame: Value) -> DbResult<RemoteChange> {
+        let mut frame = match ejson::from_json(frame)? {
+            Bson::Document(frame) => frame,
+            _ => return Err(DbErr::ParseError("a change frame should be an object".into())),
+        };
+        let collection = frame
+            .get_str("collection")
+            .map_err(|_| DbErr::ParseError("the collection of a change frame is missing".into()))?
+            .to_string();
+        let key = frame
+            .remove("key")
+            .ok_or_else(|| DbErr::ParseError("the key of a change frame is missing".into()))?;
+        let document = match frame.get_str("op") {
+            Ok("insert") | Ok("update") => {
+                let fields = frame
+                    .get_document("document")
+                    .map_err(|_| DbErr::ParseError("the document of a change frame is missing".into()))?;
+                // the `_id` is the key of the frame
+                let mut document = doc! { "_id": key.clone() };
+                for (field, value) in fields {
+                    if field != "_id" {
+                        document.insert(field.clone(), value.clone());
+                    }
+                }
+                Some(document)
+            }
+            Ok("delete") => None,
+            _ => return Err(DbErr::ParseError("the op of a change frame should be insert, update or delete".into())),
+        };
+        Ok(RemoteChange {
+            collection,
+            key,
+            document,
+        })
+    }
+
+}
+
+fn to_frame(change: &PendingChange) -> Value {
+    let options = JsonOptions::default();
+    let event = &change.event;
+    let op = match event.operation {
+        ChangeOperation::Insert => "insert",
+        ChangeOperation::Update => "update",
+        ChangeOperation::Delete => "delete",
+    };
+    let mut frame = json!({
+        "seq": change.seq,
+        "op": op,
+        "collection": event.collection,
+        "key": ejson::to_json(event.document_key.clone(), &options),
+    });
+    if event.operation != ChangeOperation::Delete {
+        frame["document"] = ejson::document_to_json(event.full_document.clone(), &options);
+    }
+    frame
+}
+
+#[wasm_bindgen(js_name = SyncClient)]
+pub struct SyncClient {
+    db:    Rc<RefCell<Option<Database>>>,
+    url:   Option<String>,
+    state: Rc<RefCell<SyncState>>,
+}
+
+#[wasm_bindgen(js_class = SyncClient)]
+impl SyncClient {
+
+    /// Sync the collections of an opened database, the options are
+    /// `{ url, collections: ["todos"], policy: "remoteWins" | "localWins" }`.
+    /// The `url` is needed by `push` and `pull` only.
+    ///
+    /// The changes committed before the client is created are not pushed.
+    #[wasm_bindgen(constructor)]
+    pub fn new(db: &DatabaseWrapper, options: JsValue) -> Result<SyncClient, JsValue> {
+        let options = SyncOptions::parse(&options)?;
+        let state = with_db(&db.db, |raw_db| SyncState::new(raw_db, &options.collections, options.policy))?;
+        Ok(SyncClient {
+            db: db.db.clone(),
+            url: options.url,
+            state: Rc::new(RefCell::new(state)),
+        })
+    }
+
+    /// The local changes not acknowledged, as an array of the frames.
+    #[wasm_bindgen(js_name = pendingChanges)]
+    pub fn pending_changes(&self) -> Result<JsValue, JsValue> {
+        let frames = self.state.borrow_mut().frames();
+        js_sys::JSON::parse(&Value::Array(frames).to_string())
+    }
+
+    /// Drop the local changes up to the `seq` of a frame, after they are pushed.
+    #[wasm_bindgen]
+    pub fn acknowledge(&self, seq: f64) {
+        self.state.borrow_mut().acknowledge(seq as u64);
+    }
+
+    /// Apply an array of the frames received, return the count of the frames applied.
+    #[wasm_bindgen]
+    pub fn apply(&self, frames: JsValue) -> Result<usize, JsValue> {
+        let json = js_sys::JSON::stringify(&frames)
+            .map_err(|_| typed_error(SYNC_ERROR, "the frames can't be converted to JSON", None))?;
+        let frames = parse_frames(&String::from(json))?;
+        let state = self.state.clone();
+        with_db(&self.db, |db| state.borrow_mut().apply(db, frames))
+    }
+
+    /// Post the local changes to the url, the promise is resolved with the count of the frames pushed.
+    /// The changes are kept if it fails.
+    #[wasm_bindgen]
+    pub fn push(&self) -> js_sys::Promise {
+        let url = self.url.clone();
+        let state = self.state.clone();
+        future_to_promise(async move {
+            let url = url.ok_or_else(|| typed_error(SYNC_ERROR, "the url is not set", None))?;
+            let frames = state.borrow_mut().frames();
+            let last_seq = match frames.last() {
+                Some(frame) => frame["seq"].as_u64().unwrap_or(0),
+                None => return Ok(JsValue::from(0)),
+            };
+            let count = frames.len();
+            fetch_text(&url, "POST", Some(Value::Array(frames).to_string())).await?;
+            state.borrow_mut().acknowledge(last_seq);
+            Ok(JsValue::from(count as u32))
+        })
+    }
+
+    /// Get the remote changes from the url and apply them,
+    /// the promise is resolved with the count of the frames applied.
+    #[wasm_bindgen]
+    pub fn pull(&self) -> js_sys::Promise {
+        let url = self.url.clone();
+        let db = self.db.clone();
+        let state = self.state.clone();
+        future_to_promise(async move {
+            let url = url.ok_or_else(|| typed_error(SYNC_ERROR, "the url is not set", None))?;
+            let text = fetch_text(&url, "GET", None).await?;
+            let frames = parse_frames(&text)?;
+            let count = with_db(&db, |db| state.borrow_mut().apply(db, frames))?;
+            Ok(JsValue::from(count as u32))
+        })
+    }
+
+}
+
+fn parse_frames(text: &str) -> Result<Vec<Value>, JsValue> {
+    match serde_json::from_str(text) {
+        Ok(Value::Array(frames)) => Ok(frames),
+        _ => Err(typed_error(SYNC_ERROR, "the change frames should be an array", None)),
+    }
+}
+
+/// `fetch` is on the global object of the workers as well.
+async fn fetch_text(url: &str, method: &str, body: Option<String>) -> Result<String, JsValue> {
+    let global = js_sys::global();
+    let fetch = js_sys::Reflect::get(&global, &"fetch".into())?
+        .dyn_into::<js_sys::Function>()
+        .map_err(|_| typed_error(SYNC_ERROR, "fetch is not supported", None))?;
+
+    let init = js_sys::Object::new();
+    js_sys::Reflect::set(&init, &"method".into(), &method.into())?;
+    if let Some(body) = body {
+        let headers = js_sys::Object::new();
+        js_sys::Reflect::set(&headers, &"content-type".into(), &"application/json".into())?;
+        js_sys::Reflect::set(&init, &"headers".into(), &headers)?;
+        js_sys::Reflect::set(&init, &"body".into(), &body.into())?;
+    }
+
+    let response = fetch.call2(&global, &url.into(), &init)?;
+    let response = JsFuture::from(js_sys::Promise::from(response))
+        .await
+        .map_err(|err| typed_error(SYNC_ERROR, "failed to reach the endpoint", Some(&err)))?;
+    let ok = js_sys::Reflect::get(&response, &"ok".into())?.as_bool().unwrap_or(false);
+    if !ok {
+        let status = js_sys::Reflect::get(&response, &"status".into())?.as_f64().unwrap_or(0.0);
+        return Err(typed_error(SYNC_ERROR, &format!("the endpoint responded with the status {}", status), None));
+    }
+
+    let text = js_sys::Reflect::get(&response, &"text".into())?
+        .dyn_into::<js_sys::Function>()?
+        .call0(&response)?;
+    let text = JsFuture::from(js_sys::Promise::from(text)).await?;
+    Ok(text.as_string().unwrap_or_default())
+}